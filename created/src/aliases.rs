@@ -0,0 +1,160 @@
+//! Names for robots. `createctl alias set kitchen-roomba` names the robot
+//! connected by its USB adapter's serial number (or, for an adapter without
+//! one, its device or address), in `serial.aliases_path`, so it is the same
+//! robot whichever ttyUSB number the kernel gives it. The name tags the
+//! session's log lines, the `connected` event, and `status`, and the robot
+//! keeps its own battery history, bumper calibration, and heading gains,
+//! under `robots/<name>/` beside the files a robot without a name uses.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use serde_json::{json, Value};
+use tracing::{info, warn};
+
+use crate::config::SerialConfig;
+use crate::discovery;
+
+/// Longest name taken
+const MAX_NAME: usize = 64;
+
+struct Aliases {
+    path: PathBuf,
+    /// Name by adapter serial number (or device)
+    names: BTreeMap<String, String>,
+    /// The connected robot's key, while a session runs
+    current: Option<String>,
+}
+
+static STATE: Mutex<Option<Aliases>> = Mutex::new(None);
+
+fn state() -> std::sync::MutexGuard<'static, Option<Aliases>> {
+    STATE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Load the names given so far.
+pub fn init(cfg: &SerialConfig) {
+    let path = cfg.aliases_path();
+    let names = match fs::read_to_string(&path) {
+        Ok(text) => serde_json::from_str(&text).unwrap_or_else(|e| {
+            warn!("ignoring unreadable robot names {}: {e}", path.display());
+            BTreeMap::new()
+        }),
+        Err(_) => BTreeMap::new(),
+    };
+    *state() = Some(Aliases { path, names, current: None });
+}
+
+/// What a name is given to for the robot on `path`: its USB adapter's
+/// serial number, or else the device or address.
+pub fn key(path: &Path) -> String {
+    discovery::serial_number(path).unwrap_or_else(|| discovery::identity(path))
+}
+
+/// A session starting on the robot `key`: its name, if it has one.
+pub fn begin(key: &str) -> Option<String> {
+    let mut guard = state();
+    let a = guard.as_mut()?;
+    a.current = Some(key.to_string());
+    a.names.get(key).cloned()
+}
+
+pub fn end() {
+    if let Some(a) = state().as_mut() {
+        a.current = None;
+    }
+}
+
+/// The connected robot's name.
+pub fn current() -> Option<String> {
+    let guard = state();
+    let a = guard.as_ref()?;
+    a.names.get(a.current.as_ref()?).cloned()
+}
+
+/// Where the connected robot keeps the state file that is `base` for a
+/// robot without a name.
+pub fn path(base: &Path) -> PathBuf {
+    let Some(name) = current() else { return base.to_path_buf() };
+    let dir = base.parent().unwrap_or(Path::new("."));
+    match base.file_name() {
+        Some(file) => dir.join("robots").join(name).join(file),
+        None => base.to_path_buf(),
+    }
+}
+
+/// The connected robot's copy of the state file `base`, or `base` itself
+/// until it has one of its own, and where it was read from.
+pub fn read(base: &Path) -> Option<(PathBuf, String)> {
+    let own = path(base);
+    match fs::read_to_string(&own) {
+        Ok(text) => Some((own, text)),
+        Err(_) if own != base => fs::read_to_string(base).ok().map(|text| (base.to_path_buf(), text)),
+        Err(_) => None,
+    }
+}
+
+/// Names usable as a directory, in logs, and on the command line.
+fn valid(name: &str) -> Result<(), String> {
+    let ok = !name.is_empty() && name.len() <= MAX_NAME && !name.starts_with('.') && name.chars().all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c));
+    if ok { Ok(()) } else { Err(format!("bad name {name:?}: up to {MAX_NAME} letters, digits, '-', '_', and '.', not starting with '.'")) }
+}
+
+fn save(a: &Aliases) -> Result<(), String> {
+    let tmp = a.path.with_extension("json.tmp");
+    let text = serde_json::to_string_pretty(&a.names).unwrap_or_default();
+    a.path
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|()| fs::write(&tmp, text))
+        .and_then(|()| fs::rename(&tmp, &a.path))
+        .map_err(|e| format!("write {}: {e}", a.path.display()))
+}
+
+/// Name the connected robot `name`.
+pub fn set(name: &str) -> Result<Value, String> {
+    valid(name)?;
+    let mut guard = state();
+    let a = guard.as_mut().ok_or("robot names aren't loaded")?;
+    let key = a.current.clone().ok_or("no robot is connected")?;
+    if let Some((other, _)) = a.names.iter().find(|(k, n)| *n == name && **k != key) {
+        return Err(format!("{name:?} is the name of {other} already"));
+    }
+    let old = a.names.insert(key.clone(), name.to_string());
+    if let Err(e) = save(a) {
+        match &old {
+            Some(old) => a.names.insert(key.clone(), old.clone()),
+            None => a.names.remove(&key),
+        };
+        return Err(e);
+    }
+    match &old {
+        Some(old) => info!("robot {key} is renamed from {old:?} to {name:?}"),
+        None => info!("robot {key} is named {name:?}, with state files of its own from its next session"),
+    }
+    Ok(json!({ "key": key, "name": name, "was": old }))
+}
+
+/// Take the connected robot's name away.
+pub fn clear() -> Result<Value, String> {
+    let mut guard = state();
+    let a = guard.as_mut().ok_or("robot names aren't loaded")?;
+    let key = a.current.clone().ok_or("no robot is connected")?;
+    let Some(old) = a.names.remove(&key) else { return Ok(json!({ "key": key, "was": null })) };
+    if let Err(e) = save(a) {
+        a.names.insert(key, old);
+        return Err(e);
+    }
+    info!("robot {key} is no longer named {old:?}");
+    Ok(json!({ "key": key, "was": old }))
+}
+
+/// The connected robot's name and key, and every name given.
+pub fn status() -> Value {
+    let guard = state();
+    let Some(a) = guard.as_ref() else { return json!({ "name": null, "key": null, "names": {} }) };
+    let name = a.current.as_ref().and_then(|k| a.names.get(k));
+    json!({ "name": name, "key": a.current, "names": a.names, "path": a.path })
+}
@@ -0,0 +1,102 @@
+//! Safety audit log: every time the daemon stops, holds back, or clamps the
+//! robot for safety, or refuses what was asked of it, one JSON line is
+//! appended to `audit.path` with the time, the action, its source (who or
+//! what set it off), and the cause. Unlike the event journal it is on by
+//! default and kept apart from everything else, so it can be handed over
+//! as it is; `createctl audit` shows the latest entries.
+//!
+//! The file is written as each entry comes, and moved aside to `.1` (one
+//! earlier file kept) once it reaches `audit.max_size_mb`.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use chrono::{SecondsFormat, Utc};
+use serde_json::{json, Value};
+use tracing::warn;
+
+use crate::config::AuditConfig;
+
+struct Audit {
+    path: PathBuf,
+    max_bytes: u64,
+    file: Option<File>,
+    /// Warned that the file can't be written, until it can again
+    failing: bool,
+}
+
+static STATE: Mutex<Option<Audit>> = Mutex::new(None);
+
+fn state() -> std::sync::MutexGuard<'static, Option<Audit>> {
+    STATE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+pub fn init(cfg: AuditConfig) {
+    *state() = Some(Audit { path: cfg.path(), max_bytes: cfg.max_bytes(), file: None, failing: false }).filter(|_| cfg.enabled());
+}
+
+impl Audit {
+    fn append(&mut self, line: &str) -> Result<(), String> {
+        let size = fs::metadata(&self.path).map(|m| m.len()).unwrap_or(0);
+        if size >= self.max_bytes {
+            self.file = None;
+            let old = PathBuf::from(format!("{}.1", self.path.display()));
+            fs::rename(&self.path, &old).map_err(|e| format!("rotate to {}: {e}", old.display()))?;
+        }
+        let mut file = match self.file.take() {
+            Some(file) => file,
+            None => {
+                if let Some(dir) = self.path.parent() {
+                    fs::create_dir_all(dir).map_err(|e| format!("create {}: {e}", dir.display()))?;
+                }
+                OpenOptions::new().create(true).append(true).open(&self.path).map_err(|e| format!("open {}: {e}", self.path.display()))?
+            }
+        };
+        writeln!(file, "{line}").and_then(|()| file.flush()).map_err(|e| format!("write {}: {e}", self.path.display()))?;
+        self.file = Some(file);
+        Ok(())
+    }
+}
+
+/// Log a safety `action` (e.g. `emergency_stop`, `cliff_interlock`), set
+/// off by `source`, with its `cause`.
+pub fn record(action: &str, source: &str, cause: &str) {
+    let mut guard = state();
+    let Some(audit) = guard.as_mut() else { return };
+    let entry = json!({
+        "time": Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+        "action": action,
+        "source": source,
+        "cause": cause,
+    });
+    match audit.append(&entry.to_string()) {
+        Ok(()) => audit.failing = false,
+        Err(e) if !audit.failing => {
+            warn!("safety audit log: {e}");
+            audit.failing = true;
+        }
+        Err(_) => {}
+    }
+}
+
+/// The latest `count` entries, oldest first, from the current file; with
+/// `action`, only those.
+pub fn latest(count: usize, action: Option<&str>) -> Result<Value, String> {
+    let path = state().as_ref().map(|a| a.path.clone()).ok_or("the safety audit log is off (audit.enabled)")?;
+    let file = match File::open(&path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(json!({ "entries": [] })),
+        Err(e) => return Err(format!("open {}: {e}", path.display())),
+    };
+    let mut entries: Vec<Value> = BufReader::new(file)
+        .lines()
+        .map_while(Result::ok)
+        .filter_map(|line| serde_json::from_str::<Value>(&line).ok())
+        .filter(|entry| action.is_none_or(|a| entry["action"] == a))
+        .collect();
+    let skip = entries.len().saturating_sub(count);
+    entries.drain(..skip);
+    Ok(json!({ "entries": entries }))
+}
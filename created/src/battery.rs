@@ -0,0 +1,231 @@
+//! Battery history: capacity over time, charge count, and equivalent full
+//! cycles, persisted across restarts in a small JSON file. Health is the
+//! reported capacity as a share of the design capacity; a `battery_degraded`
+//! event is raised once when it falls below `battery.warn_health_percent`.
+
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::aliases;
+use crate::bus::Event;
+use crate::config::BatteryConfig;
+use crate::oi::SensorFrame;
+
+const CHARGING_STATE: u8 = 21;
+const BATTERY_CHARGE: u8 = 25;
+const BATTERY_CAPACITY: u8 = 26;
+const SAVE_EVERY: Duration = Duration::from_secs(600);
+/// Daily history entries kept (two years)
+const MAX_HISTORY: usize = 730;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BatteryHistory {
+    /// Configured, or else the highest capacity ever reported
+    pub design_capacity_mah: Option<u32>,
+    pub capacity_mah: Option<u32>,
+    /// Times charging started
+    pub charges: u64,
+    /// Total charge drawn, for equivalent full cycles
+    pub discharged_mah: f64,
+    /// Whether the degradation warning has been raised
+    pub warned: bool,
+    /// One entry per UTC day, oldest first
+    pub history: Vec<DaySample>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DaySample {
+    pub date: String,
+    pub capacity_mah: u32,
+    pub cycles: f64,
+}
+
+impl BatteryHistory {
+    pub fn cycles(&self) -> f64 {
+        match self.design_capacity_mah {
+            Some(design) if design > 0 => self.discharged_mah / design as f64,
+            _ => 0.0,
+        }
+    }
+
+    pub fn health_percent(&self) -> Option<f64> {
+        let (capacity, design) = (self.capacity_mah?, self.design_capacity_mah?);
+        (design > 0).then(|| (capacity as f64 * 100.0 / design as f64).min(100.0))
+    }
+}
+
+struct Tracker {
+    cfg: BatteryConfig,
+    /// Where the history is saved: the connected robot's own, once it has a name
+    path: PathBuf,
+    data: BatteryHistory,
+    last_charge: Option<i32>,
+    charging: Option<bool>,
+    dirty: bool,
+    saved: Instant,
+}
+
+static TRACKER: Mutex<Option<Tracker>> = Mutex::new(None);
+
+fn tracker() -> std::sync::MutexGuard<'static, Option<Tracker>> {
+    TRACKER.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Load the saved history and start tracking. Does nothing unless
+/// `battery.enabled` is set.
+pub fn init(cfg: BatteryConfig) {
+    if !cfg.enabled() {
+        return;
+    }
+    let path = cfg.path();
+    let data = load(&cfg);
+    info!("tracking battery history in {}", path.display());
+    *tracker() = Some(Tracker { cfg, path, data, last_charge: None, charging: None, dirty: false, saved: Instant::now() });
+}
+
+/// The connected robot's saved history, or the shared one, which a robot
+/// given a name starts from; empty if there is none.
+fn load(cfg: &BatteryConfig) -> BatteryHistory {
+    let mut data = match aliases::read(&cfg.path()) {
+        Some((from, text)) => serde_json::from_str(&text).unwrap_or_else(|e| {
+            warn!("ignoring unreadable battery history {}: {e}", from.display());
+            BatteryHistory::default()
+        }),
+        None => BatteryHistory::default(),
+    };
+    if cfg.design_capacity_mah.is_some() {
+        data.design_capacity_mah = cfg.design_capacity_mah;
+    }
+    data
+}
+
+/// A session starting: track the connected robot's own history if it has
+/// a name (`aliases`), keeping the last robot's.
+pub fn begin() {
+    let mut guard = tracker();
+    let Some(t) = guard.as_mut() else { return };
+    let path = aliases::path(&t.cfg.path());
+    if path == t.path {
+        return;
+    }
+    if t.dirty {
+        save_locked(t);
+    }
+    info!("tracking battery history in {}", path.display());
+    t.data = load(&t.cfg);
+    t.path = path;
+    t.last_charge = None;
+    t.charging = None;
+}
+
+/// Account for one sensor frame; returns a `battery_degraded` event the first
+/// time health drops below the threshold.
+pub fn update(frame: &SensorFrame) -> Option<Event> {
+    let mut guard = tracker();
+    let t = guard.as_mut()?;
+
+    if let Some(charge) = frame.get(BATTERY_CHARGE) {
+        if let Some(drop) = t.last_charge.map(|last| last - charge).filter(|d| *d > 0) {
+            t.data.discharged_mah += drop as f64;
+            t.dirty = true;
+        }
+        t.last_charge = Some(charge);
+    }
+    if let Some(state) = frame.get(CHARGING_STATE) {
+        // 1-3: reconditioning, full, and trickle charging
+        let charging = (1..=3).contains(&state);
+        if t.charging == Some(false) && charging {
+            t.data.charges += 1;
+            t.dirty = true;
+        }
+        t.charging = Some(charging);
+    }
+    let mut event = None;
+    if let Some(capacity) = frame.get(BATTERY_CAPACITY).filter(|c| *c > 0).map(|c| c as u32) {
+        if t.data.capacity_mah != Some(capacity) {
+            t.data.capacity_mah = Some(capacity);
+            t.dirty = true;
+        }
+        if t.cfg.design_capacity_mah.is_none() && t.data.design_capacity_mah.is_none_or(|d| capacity > d) {
+            t.data.design_capacity_mah = Some(capacity);
+        }
+        record_day(&mut t.data, frame.time, capacity);
+        if let Some(health) = t.data.health_percent() {
+            let threshold = t.cfg.warn_health_percent();
+            if health < threshold && !t.data.warned {
+                warn!("battery health {health:.0}% is below {threshold:.0}% ({capacity} mAh)");
+                t.data.warned = true;
+                t.dirty = true;
+                event = Some(Event::BatteryDegraded { health_percent: health, capacity_mah: capacity });
+            } else if health >= threshold && t.data.warned {
+                // A new battery
+                t.data.warned = false;
+                t.dirty = true;
+            }
+        }
+    }
+    if t.dirty && t.saved.elapsed() >= SAVE_EVERY {
+        save_locked(t);
+    }
+    event
+}
+
+fn record_day(data: &mut BatteryHistory, time: SystemTime, capacity: u32) {
+    let date = DateTime::<Utc>::from(time).format("%Y-%m-%d").to_string();
+    let cycles = data.cycles();
+    match data.history.last_mut() {
+        Some(last) if last.date == date => {
+            last.capacity_mah = capacity;
+            last.cycles = cycles;
+        }
+        _ => {
+            data.history.push(DaySample { date, capacity_mah: capacity, cycles });
+            if data.history.len() > MAX_HISTORY {
+                data.history.remove(0);
+            }
+        }
+    }
+}
+
+/// Write the history now if anything changed (called when a session ends).
+pub fn save() {
+    if let Some(t) = tracker().as_mut().filter(|t| t.dirty) {
+        save_locked(t);
+    }
+}
+
+fn save_locked(t: &mut Tracker) {
+    let path = t.path.clone();
+    let tmp = path.with_extension("json.tmp");
+    let text = serde_json::to_string_pretty(&t.data).unwrap_or_default();
+    let result = path
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|()| fs::write(&tmp, text))
+        .and_then(|()| fs::rename(&tmp, &path));
+    match result {
+        Ok(()) => t.dirty = false,
+        Err(e) => warn!("cannot save battery history {}: {e}", path.display()),
+    }
+    t.saved = Instant::now();
+}
+
+/// Current figures for the status API, None if tracking is disabled.
+pub fn snapshot() -> Option<serde_json::Value> {
+    let guard = tracker();
+    let t = guard.as_ref()?;
+    Some(serde_json::json!({
+        "health_percent": t.data.health_percent(),
+        "capacity_mah": t.data.capacity_mah,
+        "design_capacity_mah": t.data.design_capacity_mah,
+        "cycles": t.data.cycles(),
+        "charges": t.data.charges,
+        "history": t.data.history,
+    }))
+}
@@ -0,0 +1,153 @@
+//! Status beeps: short tunes for daemon events, so a robot with no
+//! network or display still tells what it is doing. A small vocabulary
+//! covers the usual ones (connected, docking, battery low, and errors),
+//! and any other event can be given its own by type. Each pattern is an
+//! RTTTL ringtone, or the name of a song (see `song`); beeps cut in after
+//! the chunk of a song playing now, and the same beep isn't repeated
+//! within `REPEAT`, so one mishap reported by several events beeps once.
+//!
+//! Fault codes tell the daemon's own troubles apart, from across the room:
+//! the serial link lost, the config file unreadable, a behavior crashing.
+//! They play even with the status beeps off (`beeps.faults` turns them
+//! off), since a broken config leaves the defaults in force. The robot
+//! can't hear while its link is down, or while no robot is connected, so
+//! those two play as the next session connects; a fault code takes the
+//! place of the `error` beep that comes with it.
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use tracing::{debug, warn};
+
+use crate::behavior::Outcome;
+use crate::bus::Event;
+use crate::config::BeepsConfig;
+use crate::quiet;
+use crate::song::{self, Note};
+
+const REPEAT: Duration = Duration::from_secs(2);
+
+struct Beeps {
+    /// Notes by vocabulary word or event type
+    patterns: BTreeMap<String, Vec<Note>>,
+    /// Event types with a pattern of their own
+    events: Vec<String>,
+    last: BTreeMap<String, Instant>,
+    /// Faults to play once a robot is connected
+    pending: Vec<&'static str>,
+}
+
+const FAULTS: [&str; 3] = ["serial_lost", "config_invalid", "behavior_crash"];
+
+static STATE: Mutex<Option<Beeps>> = Mutex::new(None);
+
+fn state() -> std::sync::MutexGuard<'static, Option<Beeps>> {
+    STATE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// An RTTTL ringtone (it has a `:`), else a song name.
+fn pattern(text: &str) -> Result<Vec<Note>, String> {
+    if text.contains(':') {
+        let (name, melody) = song::rtttl::parse(text)?;
+        return song::encoded(if name.is_empty() { "beep" } else { &name }, &melody);
+    }
+    song::load(text, None)
+}
+
+/// Read the patterns, after `song::init`. Does nothing unless
+/// `beeps.enabled` or `beeps.faults`.
+pub fn init(cfg: BeepsConfig) {
+    if !cfg.enabled() && !cfg.faults() {
+        return;
+    }
+    let mut patterns = BTreeMap::new();
+    let events = if cfg.enabled() { cfg.events.clone().unwrap_or_default() } else { BTreeMap::new() };
+    let words = if cfg.enabled() { cfg.vocabulary().to_vec() } else { Vec::new() };
+    let faults = if cfg.faults() { cfg.fault_codes().to_vec() } else { Vec::new() };
+    let words = words.into_iter().chain(faults).map(|(word, p)| (word.to_string(), p));
+    for (name, text) in words.chain(events.clone()) {
+        if text.is_empty() {
+            continue;
+        }
+        match pattern(&text) {
+            Ok(notes) if !notes.is_empty() => {
+                patterns.insert(name, notes);
+            }
+            Ok(_) => warn!("beeps.{name}: every note is too short to play"),
+            Err(e) => warn!("beeps.{name}: {e}"),
+        }
+    }
+    *state() = Some(Beeps { patterns, events: events.into_keys().collect(), last: BTreeMap::new(), pending: Vec::new() });
+}
+
+/// The config file couldn't be read: play its fault code on connecting.
+pub fn config_invalid() {
+    if let Some(b) = state().as_mut() {
+        b.pending.push("config_invalid");
+    }
+}
+
+/// The vocabulary word for `event`, if any.
+fn word(event: &Event) -> Option<&'static str> {
+    Some(match event {
+        Event::Connected { .. } => "connected",
+        Event::BehaviorStarted { name } if name == "dock" => "docking",
+        Event::BatteryLow { .. } | Event::BatteryCritical { .. } => "battery_low",
+        Event::DockingFailed { .. } | Event::Stuck { .. } | Event::BehaviorTimeout { .. } => "error",
+        Event::BehaviorEnded { outcome: Outcome::Failed, .. } => "error",
+        _ => return None,
+    })
+}
+
+/// Beep for `event`, if it has a pattern, and on connecting play the
+/// faults that couldn't be played before.
+pub fn react(event: &Event) {
+    let mut guard = state();
+    let Some(b) = guard.as_mut() else { return };
+    if let Event::Disconnected { reason, .. } = event {
+        // However many attempts it takes to get it back
+        if reason != "shutdown" && !b.pending.contains(&"serial_lost") {
+            b.pending.push("serial_lost");
+        }
+        return;
+    }
+    let kind = if b.events.is_empty() {
+        None
+    } else {
+        serde_json::to_value(event).ok().and_then(|v| v["type"].as_str().map(str::to_string)).filter(|t| b.events.contains(t))
+    };
+    let word = match event {
+        Event::BehaviorCrashed { .. } => Some("behavior_crash"),
+        _ => word(event),
+    };
+    let mut names: Vec<String> = kind.or_else(|| word.map(str::to_string)).into_iter().collect();
+    if matches!(event, Event::Connected { .. }) {
+        names.extend(b.pending.drain(..).map(str::to_string));
+    }
+    for name in names {
+        b.beep(&name);
+    }
+}
+
+impl Beeps {
+    fn beep(&mut self, name: &str) {
+        let Some(notes) = self.patterns.get(name) else { return };
+        let now = Instant::now();
+        if self.last.get(name).is_some_and(|t| now.duration_since(*t) < REPEAT) {
+            return;
+        }
+        self.last.insert(name.to_string(), now);
+        if FAULTS.contains(&name) {
+            self.last.insert("error".to_string(), now);
+        }
+        let queued = if quiet::critical_beep(name) {
+            song::queue_critical(&format!("beep {name}"), notes.clone())
+        } else {
+            song::queue(&format!("beep {name}"), notes.clone(), true)
+        };
+        if let Err(e) = queued {
+            debug!("beep {name}: {e}");
+        }
+    }
+}
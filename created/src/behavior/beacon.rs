@@ -0,0 +1,154 @@
+//! Follow the beacon: home on one IR code, so a person holding down a
+//! button on the IR remote (or carrying a virtual wall) leads the robot
+//! around. The Create 2's left and right IR receivers say which way the
+//! code is: seen by both, the robot drives toward it; by one, it curves
+//! that way; by the omnidirectional receiver alone, it is off to the side
+//! or behind, and the robot turns on the spot toward where it was last
+//! seen. Without the left and right receivers in the stream it can only
+//! drive while the code is seen and turn while it isn't. It never drives
+//! into what it bumps (or sees on the light bumps), and once the code has
+//! been gone for `search_timeout_s` it waits where it is.
+
+use std::time::Instant;
+
+use serde_json::{json, Value};
+
+use super::{Behavior, Context, Status};
+use crate::bus::Event;
+use crate::config::BeaconConfig;
+use crate::motion::Wheels;
+use crate::pose;
+
+const OMNI: u8 = 17;
+const LEFT: u8 = 52;
+const RIGHT: u8 = 53;
+const BUMPS_AND_WHEEL_DROPS: u8 = 7;
+const BUMPS: i32 = 0b11;
+const LIGHT_BUMPER: u8 = 45;
+/// Codes are intermittent: one counts as seen this long after it last was, s.
+const HOLD_S: f64 = 0.3;
+
+/// IR codes of the Roomba remote's buttons.
+const REMOTE: std::ops::RangeInclusive<i32> = 129..=143;
+const VIRTUAL_WALL: i32 = 162;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Code {
+    /// Any button of the IR remote
+    Remote,
+    Exact(i32),
+}
+
+impl Code {
+    /// `remote`, `virtual-wall`, or a code number.
+    fn parse(s: &str) -> Result<Code, String> {
+        match s {
+            "remote" => Ok(Code::Remote),
+            "virtual-wall" => Ok(Code::Exact(VIRTUAL_WALL)),
+            _ => s.parse::<u8>().map(|c| Code::Exact(c as i32)).map_err(|_| format!("bad IR code {s:?}: expected remote, virtual-wall, or 0-255")),
+        }
+    }
+
+    fn matches(self, code: i32) -> bool {
+        match self {
+            Code::Remote => REMOTE.contains(&code),
+            Code::Exact(c) => c == code,
+        }
+    }
+
+    fn name(self) -> String {
+        match self {
+            Code::Remote => "remote".into(),
+            Code::Exact(VIRTUAL_WALL) => "virtual-wall".into(),
+            Code::Exact(c) => c.to_string(),
+        }
+    }
+}
+
+pub struct Beacon {
+    cfg: BeaconConfig,
+    code: Code,
+    started: Option<Instant>,
+    /// Last time each receiver saw the code
+    omni: Option<Instant>,
+    left: Option<Instant>,
+    right: Option<Instant>,
+    /// Side the code was last seen on, for turning back toward it: 1 left, -1 right
+    side: f64,
+    /// What the robot did last, for `createctl behavior`
+    phase: &'static str,
+}
+
+impl Beacon {
+    /// `args`: optionally the code to follow (default `behavior.beacon.code`).
+    pub fn new(args: &[&str], cfg: BeaconConfig) -> Result<Self, String> {
+        let code = match args {
+            [] => Code::parse(&cfg.code())?,
+            [code] => Code::parse(code)?,
+            _ => return Err("usage: beacon [remote|virtual-wall|<code>]".into()),
+        };
+        Ok(Beacon { cfg, code, started: None, omni: None, left: None, right: None, side: 1.0, phase: "searching" })
+    }
+
+    fn recent(t: Option<Instant>, now: Instant) -> bool {
+        t.is_some_and(|t| now.duration_since(t).as_secs_f64() < HOLD_S)
+    }
+}
+
+impl Behavior for Beacon {
+    fn name(&self) -> &'static str {
+        "beacon"
+    }
+
+    fn step(&mut self, ctx: &mut Context) -> Status {
+        let started = *self.started.get_or_insert(ctx.now);
+        for event in ctx.events {
+            match event {
+                Event::Cliff { .. } => return Status::Failed("cliff".into()),
+                Event::WheelDrop { .. } => return Status::Failed("wheel drop".into()),
+                _ => {}
+            }
+        }
+        let directional = ctx.frame.get(LEFT).is_some() && ctx.frame.get(RIGHT).is_some();
+        for (id, last) in [(OMNI, &mut self.omni), (LEFT, &mut self.left), (RIGHT, &mut self.right)] {
+            if ctx.frame.get(id).is_some_and(|c| self.code.matches(c)) {
+                *last = Some(ctx.now);
+            }
+        }
+        let (omni, left, right) = (Self::recent(self.omni, ctx.now), Self::recent(self.left, ctx.now), Self::recent(self.right, ctx.now));
+        if left != right {
+            self.side = if left { 1.0 } else { -1.0 };
+        }
+        let blocked = ctx.frame.get(BUMPS_AND_WHEEL_DROPS).is_some_and(|b| b & BUMPS != 0)
+            || (self.cfg.light_bumps() && ctx.frame.get(LIGHT_BUMPER).unwrap_or(0) != 0);
+        let speed = if blocked { 0.0 } else { self.cfg.speed() };
+        let (v, omega, phase) = match (left, right) {
+            (true, true) => (speed, 0.0, "following"),
+            (true, false) | (false, true) => (speed / 2.0, self.side * self.cfg.turn_rate(), "following"),
+            // Only the omnidirectional receiver: off to the side or behind
+            _ if omni && directional => (0.0, self.side * self.cfg.turn_rate(), "turning"),
+            _ if omni => (speed, 0.0, "following"),
+            _ => {
+                let last = [self.omni, self.left, self.right].into_iter().flatten().max().unwrap_or(started);
+                if ctx.now.duration_since(last) > self.cfg.search_timeout() {
+                    (0.0, 0.0, "waiting")
+                } else {
+                    (0.0, self.side * self.cfg.search_turn_rate(), "searching")
+                }
+            }
+        };
+        self.phase = if blocked && omega == 0.0 && phase == "following" { "blocked" } else { phase };
+        Status::Running(Wheels::arc(v, omega, pose::wheel_base()))
+    }
+
+    fn progress(&self) -> Value {
+        let now = Instant::now();
+        json!({
+            "code": self.code.name(),
+            "phase": self.phase,
+            "omni": Self::recent(self.omni, now),
+            "left": Self::recent(self.left, now),
+            "right": Self::recent(self.right, now)
+        })
+    }
+}
@@ -0,0 +1,153 @@
+//! Boustrophedon (lawn-mower) coverage of a rectangle in the pose frame:
+//! rows parallel to x, `row_spacing_m` apart, driven in alternating
+//! directions. A bump or cliff truncates the current row: the robot backs
+//! up, shifts over to the next row where it is, and drives that row back
+//! the other way. Steering is the waypoint controller's.
+
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+
+use super::waypoints::steer;
+use super::{Behavior, Context, Status};
+use crate::bus::Event;
+use crate::config::{CoverageConfig, WaypointsConfig};
+use crate::motion::Wheels;
+use crate::pose;
+
+/// Backing up gives up after this, in case the wheels are blocked.
+const BACKUP_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[derive(Debug, Clone, Copy)]
+enum Leg {
+    /// To (x, y): a row's start, or the shift onto the next row
+    Approach { x: f64, y: f64 },
+    /// Along the current row toward x
+    Row { x: f64 },
+    /// Away from an obstacle, from (x, y)
+    Backing { x: f64, y: f64 },
+}
+
+pub struct Coverage {
+    cfg: CoverageConfig,
+    steering: WaypointsConfig,
+    /// x0, y0, x1, y1 with x0 < x1 and y0 < y1
+    area: [f64; 4],
+    rows: usize,
+    row: usize,
+    leg: Leg,
+    since: Option<Instant>,
+    truncated: u64,
+}
+
+impl Coverage {
+    /// `args`: optional corners `x0,y0 x1,y1` overriding `behavior.coverage.area`.
+    pub fn new(args: &[&str], cfg: CoverageConfig, steering: WaypointsConfig) -> Result<Self, String> {
+        let [x0, y0, x1, y1] = match args {
+            [] => cfg.area(),
+            [a, b] => {
+                let corner = |s: &str| -> Result<(f64, f64), String> {
+                    let bad = || format!("bad corner {s:?}: expected x,y");
+                    let (x, y) = s.split_once(',').ok_or_else(bad)?;
+                    Ok((x.trim().parse().map_err(|_| bad())?, y.trim().parse().map_err(|_| bad())?))
+                };
+                let ((x0, y0), (x1, y1)) = (corner(a)?, corner(b)?);
+                [x0, y0, x1, y1]
+            }
+            _ => return Err("usage: coverage [<x0,y0> <x1,y1>]".into()),
+        };
+        let area = [x0.min(x1), y0.min(y1), x0.max(x1), y0.max(y1)];
+        if !area.iter().all(|v| v.is_finite()) || area[2] - area[0] < cfg.row_spacing_m() {
+            return Err("the coverage area must be at least one row spacing long".into());
+        }
+        let rows = ((area[3] - area[1]) / cfg.row_spacing_m()).floor() as usize + 1;
+        let leg = Leg::Approach { x: area[0], y: area[1] };
+        Ok(Coverage { cfg, steering, area, rows, row: 0, leg, since: None, truncated: 0 })
+    }
+
+    fn row_y(&self, row: usize) -> f64 {
+        self.area[1] + row as f64 * self.cfg.row_spacing_m()
+    }
+
+    /// Even rows run toward +x, odd rows back.
+    fn row_end(&self, row: usize) -> f64 {
+        if row.is_multiple_of(2) {
+            self.area[2]
+        } else {
+            self.area[0]
+        }
+    }
+
+    fn next_leg(&mut self, leg: Leg) {
+        self.leg = leg;
+        self.since = None;
+    }
+
+    /// Go on to the next row from `x`; false when there is none.
+    fn next_row(&mut self, ctx: &mut Context, x: f64, truncated: bool) -> bool {
+        ctx.emit.push(Event::RowCovered { row: self.row, rows: self.rows, truncated });
+        self.row += 1;
+        if self.row >= self.rows {
+            return false;
+        }
+        let x = x.clamp(self.area[0], self.area[2]);
+        self.next_leg(Leg::Approach { x, y: self.row_y(self.row) });
+        true
+    }
+}
+
+impl Behavior for Coverage {
+    fn name(&self) -> &'static str {
+        "coverage"
+    }
+
+    fn step(&mut self, ctx: &mut Context) -> Status {
+        let Some(pose) = ctx.pose else { return Status::Failed("no pose estimate (pose.enabled is off)".into()) };
+        let mut obstacle = false;
+        for event in ctx.events {
+            match event {
+                Event::WheelDrop { .. } => return Status::Failed("wheel drop".into()),
+                Event::Bump { .. } | Event::Cliff { .. } => obstacle = true,
+                _ => {}
+            }
+        }
+        if obstacle && !matches!(self.leg, Leg::Backing { .. }) {
+            self.truncated += 1;
+            self.next_leg(Leg::Backing { x: pose.x, y: pose.y });
+        }
+        let speed = self.cfg.speed();
+        loop {
+            let since = *self.since.get_or_insert(ctx.now);
+            if ctx.now.duration_since(since) > self.steering.timeout() {
+                return Status::Failed(format!("row {} not finished within {:?}", self.row, self.steering.timeout()));
+            }
+            match self.leg {
+                Leg::Backing { x, y } => {
+                    let backed = (pose.x - x).hypot(pose.y - y) >= self.cfg.backup_m();
+                    if !backed && ctx.now.duration_since(since) < BACKUP_TIMEOUT {
+                        return Status::Running(Wheels::arc(-speed / 2.0, 0.0, pose::wheel_base()));
+                    }
+                    if !self.next_row(ctx, pose.x, true) {
+                        return Status::Done;
+                    }
+                }
+                Leg::Approach { x, y } => match steer(&pose, x, y, self.steering.tolerance_m(), speed, &self.steering) {
+                    Some((wheels, _)) => return Status::Running(wheels),
+                    None => self.next_leg(Leg::Row { x: self.row_end(self.row) }),
+                },
+                Leg::Row { x } => match steer(&pose, x, self.row_y(self.row), self.steering.tolerance_m(), speed, &self.steering) {
+                    Some((wheels, _)) => return Status::Running(wheels),
+                    None => {
+                        if !self.next_row(ctx, x, false) {
+                            return Status::Done;
+                        }
+                    }
+                },
+            }
+        }
+    }
+
+    fn progress(&self) -> Value {
+        json!({ "row": self.row, "rows": self.rows, "area": self.area, "truncated_rows": self.truncated })
+    }
+}
@@ -0,0 +1,255 @@
+//! Host-side docking on the home base's IR beams, as an alternative to the
+//! built-in Seek Dock. The base sends a red buoy to one side, a green buoy
+//! to the other, and a short-range force field; the robot turns on the spot
+//! until it sees a beam, then drives in, steering toward the line where
+//! both buoys overlap. A bump that doesn't end on the dock, or losing the
+//! beams for too long, backs it off for another try. Once the robot
+//! reports the home base as a charging source it is put back in Passive
+//! mode so it charges, and docking succeeds when the charging state says
+//! it does; contacts that touch without charging back it off for another
+//! try as well. Giving up publishes a `docking_failed` event.
+
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+
+use super::{Behavior, Context, Status};
+use crate::bus::Event;
+use crate::config::{DockConfig, Side};
+use crate::motion::{self, Wheels};
+use crate::{oi, pose};
+
+/// Omnidirectional IR receiver, and the Create 2's left and right ones.
+const IR_PACKETS: [u8; 3] = [17, 52, 53];
+const CHARGING_STATE: u8 = 21;
+/// Beams are intermittent: one counts as seen this long after its last code.
+const BEAM_HOLD: Duration = Duration::from_millis(500);
+/// Back to searching after no beam for this long.
+const LOST_AFTER: Duration = Duration::from_secs(2);
+/// Backing off gives up after this, in case the wheels are blocked.
+const BACKUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Whether the charging state (packet 21) says the battery is charging:
+/// reconditioning, full, or trickle charging. `None` when it isn't
+/// streamed.
+pub fn charging(frame: &crate::oi::SensorFrame) -> Option<bool> {
+    frame.get(CHARGING_STATE).map(|s| (1..=3).contains(&s))
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct Beams {
+    red: bool,
+    green: bool,
+    force_field: bool,
+}
+
+impl Beams {
+    /// Home base codes: 240-254 (even) on Create 1, 160-175 on Create 2.
+    fn decode(code: i32) -> Beams {
+        let (red, green, force_field) = match code {
+            240..=254 if code % 2 == 0 => (8, 4, 2),
+            160..=175 => (8, 4, 1),
+            _ => return Beams::default(),
+        };
+        Beams { red: code & red != 0, green: code & green != 0, force_field: code & force_field != 0 }
+    }
+
+    fn any(&self) -> bool {
+        self.red || self.green || self.force_field
+    }
+
+    fn merge(&mut self, other: Beams) {
+        self.red |= other.red;
+        self.green |= other.green;
+        self.force_field |= other.force_field;
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Phase {
+    Searching { since: Instant },
+    Approaching,
+    Backing { since: Instant, from: Option<(f64, f64)> },
+    /// On the dock since then, waiting for it to charge
+    Verifying { since: Instant },
+}
+
+pub struct Dock {
+    cfg: DockConfig,
+    phase: Phase,
+    started: Option<Instant>,
+    attempts: u32,
+    /// Last time each beam was seen
+    red: Option<Instant>,
+    green: Option<Instant>,
+    force_field: Option<Instant>,
+}
+
+impl Dock {
+    pub fn new(cfg: DockConfig) -> Self {
+        let phase = Phase::Searching { since: Instant::now() };
+        Dock { cfg, phase, started: None, attempts: 1, red: None, green: None, force_field: None }
+    }
+
+    /// Beams seen recently, as of `now`.
+    fn beams(&self, now: Instant) -> Beams {
+        let recent = |t: Option<Instant>| t.is_some_and(|t| now.duration_since(t) < BEAM_HOLD);
+        Beams { red: recent(self.red), green: recent(self.green), force_field: recent(self.force_field) }
+    }
+
+    fn last_beam(&self) -> Option<Instant> {
+        [self.red, self.green, self.force_field].into_iter().flatten().max()
+    }
+
+    /// Back off and search again, unless the retries are used up.
+    fn retry(&mut self, ctx: &Context, why: &str) -> Result<(), String> {
+        if self.attempts > self.cfg.retries() {
+            return Err(format!("{why}; giving up after {} attempts", self.attempts));
+        }
+        self.attempts += 1;
+        self.phase = Phase::Backing { since: ctx.now, from: ctx.pose.map(|p| (p.x, p.y)) };
+        Ok(())
+    }
+}
+
+/// Give up, with a `docking_failed` event.
+fn fail(ctx: &mut Context, reason: String) -> Status {
+    ctx.emit.push(Event::DockingFailed { reason: reason.clone() });
+    Status::Failed(reason)
+}
+
+impl Behavior for Dock {
+    fn name(&self) -> &'static str {
+        "dock"
+    }
+
+    fn step(&mut self, ctx: &mut Context) -> Status {
+        let started = *self.started.get_or_insert(ctx.now);
+        let mut bumped = false;
+        let mut undocked = false;
+        for event in ctx.events {
+            match event {
+                Event::Docked if !matches!(self.phase, Phase::Verifying { .. }) => {
+                    // Charging only happens in Passive mode
+                    motion::halt();
+                    motion::queue(vec![oi::START]);
+                    self.phase = Phase::Verifying { since: ctx.now };
+                }
+                Event::Undocked => undocked = true,
+                Event::WheelDrop { .. } => return fail(ctx, "wheel drop".into()),
+                Event::Cliff { .. } => return fail(ctx, "cliff".into()),
+                Event::Bump { .. } => bumped = true,
+                _ => {}
+            }
+        }
+        if ctx.now.duration_since(started) > self.cfg.timeout() {
+            return fail(ctx, format!("not docked within {:?}", self.cfg.timeout()));
+        }
+
+        let mut seen = Beams::default();
+        for id in IR_PACKETS {
+            if let Some(code) = ctx.frame.get(id) {
+                seen.merge(Beams::decode(code));
+            }
+        }
+        for (beam, last) in [(seen.red, &mut self.red), (seen.green, &mut self.green), (seen.force_field, &mut self.force_field)] {
+            if beam {
+                *last = Some(ctx.now);
+            }
+        }
+        let beams = self.beams(ctx.now);
+
+        if bumped && !matches!(self.phase, Phase::Backing { .. } | Phase::Verifying { .. }) {
+            if let Err(e) = self.retry(ctx, "bumped without docking") {
+                return fail(ctx, e);
+            }
+        }
+        let wheel_base = pose::wheel_base();
+        loop {
+            match self.phase {
+                Phase::Searching { since } => {
+                    if beams.any() {
+                        self.phase = Phase::Approaching;
+                        continue;
+                    }
+                    if ctx.now.duration_since(since) > self.cfg.search_timeout() {
+                        if let Err(e) = self.retry(ctx, "no home base beam found") {
+                            return fail(ctx, e);
+                        }
+                        continue;
+                    }
+                    return Status::Running(Wheels::arc(0.0, self.cfg.search_turn_rate(), wheel_base));
+                }
+                Phase::Approaching => {
+                    if self.last_beam().is_none_or(|t| ctx.now.duration_since(t) > LOST_AFTER) {
+                        self.phase = Phase::Searching { since: ctx.now };
+                        continue;
+                    }
+                    let speed = if beams.force_field { self.cfg.slow_speed() } else { self.cfg.approach_speed() };
+                    // With only one buoy in view the robot is off to that
+                    // side of the center line and turns back across it
+                    let toward_center = match (beams.red, beams.green) {
+                        (true, false) => Some(self.cfg.red_side()),
+                        (false, true) => Some(match self.cfg.red_side() {
+                            Side::Left => Side::Right,
+                            Side::Right => Side::Left,
+                        }),
+                        _ => None,
+                    };
+                    let omega = match toward_center {
+                        // Off to the left (as seen facing the base): turn right
+                        Some(Side::Left) => -self.cfg.steer_rate(),
+                        Some(Side::Right) => self.cfg.steer_rate(),
+                        None => 0.0,
+                    };
+                    return Status::Running(Wheels::arc(speed, omega, wheel_base));
+                }
+                Phase::Backing { since, from } => {
+                    let elapsed = ctx.now.duration_since(since);
+                    let done = match (from, ctx.pose) {
+                        (Some((x, y)), Some(p)) => (p.x - x).hypot(p.y - y) >= self.cfg.backup_m(),
+                        _ => elapsed.as_secs_f64() >= self.cfg.backup_m() * 1000.0 / self.cfg.slow_speed(),
+                    };
+                    if !done && elapsed < BACKUP_TIMEOUT {
+                        // Off contacts that didn't charge, as undocking
+                        crate::charging_lockout::undocking();
+                        return Status::Running(Wheels::arc(-self.cfg.slow_speed(), 0.0, wheel_base));
+                    }
+                    self.phase = Phase::Searching { since: ctx.now };
+                }
+                Phase::Verifying { since } => {
+                    // Without the charging state, being on the dock has to do
+                    if !undocked && charging(ctx.frame) != Some(false) {
+                        return Status::Done;
+                    }
+                    if !undocked && ctx.now.duration_since(since) < self.cfg.verify() {
+                        return Status::Running(Wheels::STOP);
+                    }
+                    let why = if undocked { "lost the dock contacts before charging" } else { "on the dock but not charging" };
+                    // Back to Safe mode to drive off
+                    motion::queue(vec![oi::SAFE]);
+                    if let Err(e) = self.retry(ctx, why) {
+                        return fail(ctx, e);
+                    }
+                }
+            }
+        }
+    }
+
+    fn progress(&self) -> Value {
+        let phase = match self.phase {
+            Phase::Searching { .. } => "searching",
+            Phase::Approaching => "approaching",
+            Phase::Backing { .. } => "backing",
+            Phase::Verifying { .. } => "verifying",
+        };
+        let beams = self.beams(Instant::now());
+        json!({
+            "phase": phase,
+            "attempt": self.attempts,
+            "red": beams.red,
+            "green": beams.green,
+            "force_field": beams.force_field
+        })
+    }
+}
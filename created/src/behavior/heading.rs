@@ -0,0 +1,195 @@
+//! Heading hold: a PID controller on the pose estimate's heading, so that
+//! driving "straight" stays straight even though the wheels never quite
+//! match. Gains come from `behavior.heading`, or else from the last
+//! calibration (`createctl behavior start heading-tune`), which finds them
+//! with a relay autotune: it drives forward while turning hard one way or
+//! the other depending on the heading error, measures the oscillation that
+//! results, and applies Ziegler-Nichols rules to it. Calibrated gains are
+//! saved in `behavior.heading.gains_path`.
+
+use std::f64::consts::PI;
+use std::fs;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tracing::{info, warn};
+
+use super::{Behavior, Context, Status};
+use crate::aliases;
+use crate::bus::Event;
+use crate::config::HeadingConfig;
+use crate::motion::Wheels;
+use crate::pose;
+
+/// Error band in which the relay doesn't switch, rad, so sensor noise
+/// doesn't make it chatter.
+const RELAY_HYSTERESIS: f64 = 0.01;
+/// Oscillation periods left to settle before measuring.
+const SETTLE_CYCLES: usize = 2;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Gains {
+    pub kp: f64,
+    pub ki: f64,
+    pub kd: f64,
+}
+
+/// From the last calibration.
+static TUNED: Mutex<Option<Gains>> = Mutex::new(None);
+
+/// Load the gains of an earlier calibration: the connected robot's, by
+/// its name (`aliases`), or the shared ones.
+pub fn init(cfg: &HeadingConfig) {
+    let tuned = aliases::read(&cfg.gains_path()).and_then(|(path, text)| {
+        serde_json::from_str::<Gains>(&text).map_err(|e| warn!("ignoring unreadable heading gains {}: {e}", path.display())).ok()
+    });
+    *TUNED.lock().unwrap_or_else(|e| e.into_inner()) = tuned;
+}
+
+/// Configured gains, falling back to calibrated ones, then the defaults.
+pub fn gains(cfg: &HeadingConfig) -> Gains {
+    let tuned = *TUNED.lock().unwrap_or_else(|e| e.into_inner());
+    let kp = cfg.kp.or(tuned.map(|g| g.kp)).unwrap_or(2.0);
+    let ki = cfg.ki.or(tuned.map(|g| g.ki)).unwrap_or(0.5);
+    let kd = cfg.kd.or(tuned.map(|g| g.kd)).unwrap_or(0.1);
+    Gains { kp, ki, kd }
+}
+
+/// Holds one heading: the turn rate that steers back onto it.
+pub struct HeadingHold {
+    target: f64,
+    gains: Gains,
+    /// Turn rate limit, rad/s
+    max: f64,
+    integral: f64,
+    last: Option<(Instant, f64)>,
+}
+
+impl HeadingHold {
+    pub fn new(target: f64, cfg: &HeadingConfig) -> Self {
+        HeadingHold { target, gains: gains(cfg), max: cfg.max_correction(), integral: 0.0, last: None }
+    }
+
+    /// Turn rate in rad/s, positive counter-clockwise, for heading `theta`.
+    pub fn omega(&mut self, theta: f64, now: Instant) -> f64 {
+        let Gains { kp, ki, kd } = self.gains;
+        let error = pose::normalize(self.target - theta);
+        let mut derivative = 0.0;
+        if let Some((then, last_error)) = self.last {
+            let dt = now.duration_since(then).as_secs_f64();
+            if dt > 0.0 {
+                derivative = (error - last_error) / dt;
+                self.integral += error * dt;
+                // No more windup than the output can use
+                if ki > 0.0 {
+                    self.integral = self.integral.clamp(-self.max / ki, self.max / ki);
+                }
+            }
+        }
+        self.last = Some((now, error));
+        (kp * error + ki * self.integral + kd * derivative).clamp(-self.max, self.max)
+    }
+}
+
+/// The relay autotune, as a behavior.
+pub struct Tune {
+    cfg: HeadingConfig,
+    target: Option<f64>,
+    /// Relay output: turning counter-clockwise
+    up: bool,
+    /// Times the relay switched up
+    switches: Vec<Instant>,
+    /// Error extremes of each half cycle
+    peak: f64,
+    peaks: Vec<f64>,
+    started: Option<Instant>,
+}
+
+impl Tune {
+    pub fn new(cfg: HeadingConfig) -> Self {
+        Tune { cfg, target: None, up: true, switches: Vec::new(), peak: 0.0, peaks: Vec::new(), started: None }
+    }
+
+    /// Ziegler-Nichols gains from what was measured, once enough cycles are.
+    fn result(&self) -> Option<Gains> {
+        let cycles = self.cfg.tune_cycles();
+        if self.switches.len() < SETTLE_CYCLES + cycles + 1 {
+            return None;
+        }
+        let measured = &self.switches[SETTLE_CYCLES..];
+        let period = measured.last()?.duration_since(*measured.first()?).as_secs_f64() / (measured.len() - 1) as f64;
+        // Two half-cycle peaks per period; skip the settling ones
+        let peaks = &self.peaks[(2 * SETTLE_CYCLES).min(self.peaks.len())..];
+        let amplitude = peaks.iter().map(|p| p.abs()).sum::<f64>() / peaks.len().max(1) as f64;
+        if period <= 0.0 || amplitude <= 0.0 {
+            return None;
+        }
+        let ultimate = 4.0 * self.cfg.tune_amplitude() / (PI * amplitude);
+        Some(Gains { kp: 0.6 * ultimate, ki: 1.2 * ultimate / period, kd: 0.075 * ultimate * period })
+    }
+
+    fn save(&self, gains: Gains) {
+        *TUNED.lock().unwrap_or_else(|e| e.into_inner()) = Some(gains);
+        let path = aliases::path(&self.cfg.gains_path());
+        let text = serde_json::to_string_pretty(&gains).unwrap_or_default();
+        let result = path.parent().map_or(Ok(()), fs::create_dir_all).and_then(|()| fs::write(&path, text));
+        if let Err(e) = result {
+            warn!("cannot save heading gains {}: {e}", path.display());
+        }
+    }
+}
+
+impl Behavior for Tune {
+    fn name(&self) -> &'static str {
+        "heading-tune"
+    }
+
+    fn step(&mut self, ctx: &mut Context) -> Status {
+        let Some(pose) = ctx.pose else { return Status::Failed("no pose estimate (pose.enabled is off)".into()) };
+        for event in ctx.events {
+            match event {
+                Event::Bump { .. } => return Status::Failed("bump".into()),
+                Event::Cliff { .. } => return Status::Failed("cliff".into()),
+                Event::WheelDrop { .. } => return Status::Failed("wheel drop".into()),
+                _ => {}
+            }
+        }
+        let started = *self.started.get_or_insert(ctx.now);
+        if ctx.now.duration_since(started) > self.cfg.tune_timeout() {
+            return Status::Failed(format!("no steady oscillation within {:?}", self.cfg.tune_timeout()));
+        }
+        let target = *self.target.get_or_insert(pose.theta);
+        let error = pose::normalize(target - pose.theta);
+        // Turning counter-clockwise drives the error negative, and back
+        if self.up {
+            self.peak = self.peak.min(error);
+            if error < -RELAY_HYSTERESIS {
+                self.up = false;
+                self.peaks.push(self.peak);
+                self.peak = error;
+            }
+        } else {
+            self.peak = self.peak.max(error);
+            if error > RELAY_HYSTERESIS {
+                self.up = true;
+                self.peaks.push(self.peak);
+                self.peak = error;
+                self.switches.push(ctx.now);
+            }
+        }
+        if let Some(gains) = self.result() {
+            info!("heading gains calibrated: kp {:.3}, ki {:.3}, kd {:.3}", gains.kp, gains.ki, gains.kd);
+            self.save(gains);
+            ctx.emit.push(Event::HeadingTuned { kp: gains.kp, ki: gains.ki, kd: gains.kd });
+            return Status::Done;
+        }
+        let omega = if self.up { self.cfg.tune_amplitude() } else { -self.cfg.tune_amplitude() };
+        Status::Running(Wheels::arc(self.cfg.tune_speed(), omega, pose::wheel_base()))
+    }
+
+    fn progress(&self) -> Value {
+        json!({ "cycles": self.switches.len().saturating_sub(1), "needed": SETTLE_CYCLES + self.cfg.tune_cycles() })
+    }
+}
@@ -0,0 +1,424 @@
+//! Missions: a routine written as a TOML or YAML file of steps (drive,
+//! turn, arc, stop, wait, song, leds, dock, or any behavior) run one after
+//! another. Each step's `on_error` policy says what a failure does to the
+//! mission: abort it, skip the step, or retry the step up to `retries`
+//! times first.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use super::{dock, primitive, Behavior, Context, Outcome, Status};
+use crate::bus::Event;
+use crate::config::{BehaviorConfig, OnTimeout};
+use crate::motion::{self, Wheels};
+use crate::{command, leds, oi};
+
+/// Songs hold at most this many notes.
+const MAX_NOTES: usize = 16;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OnError {
+    /// End the mission as failed
+    #[default]
+    Abort,
+    /// Go on with the next step
+    Skip,
+    /// Run the step again, up to `retries` times, then abort
+    Retry,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Action {
+    /// Straight ahead (negative: backward), m; speed in mm/s
+    Drive { distance_m: f64, speed: Option<f64> },
+    /// On the spot, °, positive counter-clockwise; rate in °/s
+    Turn { angle: f64, rate: Option<f64> },
+    /// Forward along a circle of `radius_m`, turning `angle` ° (positive left); speed in mm/s
+    Arc { radius_m: f64, angle: f64, speed: Option<f64> },
+    /// To (x, y) in the pose frame, m, ending on heading `theta` ° if given; speed in mm/s
+    GoTo { x: f64, y: f64, theta: Option<f64>, speed: Option<f64> },
+    /// Ramp down to a standstill
+    Stop,
+    Wait { seconds: f64 },
+    /// Play song `number`, first storing `notes` ([MIDI note, 1/64 s]) in it if given
+    Song { number: u8, notes: Option<Vec<[u8; 2]>> },
+    /// Start the LED pattern `pattern`, or stop the animations without one
+    Leds { pattern: Option<String> },
+    Dock,
+    Behavior { name: String, #[serde(default)] args: Vec<String> },
+    /// Send OI commands as they are (an on-robot script with Script, 152),
+    /// once they check out against the protocol model
+    Oi { bytes: Vec<i64> },
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Step {
+    #[serde(flatten)]
+    pub action: Action,
+    /// Default: the mission's
+    pub on_error: Option<OnError>,
+    pub retries: Option<u32>,
+    /// Fail the step after this, s
+    pub timeout_s: Option<f64>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MissionFile {
+    pub name: Option<String>,
+    /// Policy for steps without their own (default "abort")
+    #[serde(default)]
+    pub on_error: OnError,
+    /// Retries for steps without their own (default 1)
+    pub retries: Option<u32>,
+    /// Deadline for the whole mission, s, over `behavior.timeouts`
+    pub timeout_s: Option<f64>,
+    /// What missing it does, over `behavior.on_timeout`
+    pub on_timeout: Option<OnTimeout>,
+    pub steps: Vec<Step>,
+}
+
+/// `name` as given, or looked up in `dir` (with a `.toml`, `.yaml`, or
+/// `.yml` extension) if it is a bare name. Shared with behavior trees.
+pub(super) fn locate(name: &str, dir: &Path, what: &str) -> Result<PathBuf, String> {
+    let path = Path::new(name);
+    if path.components().count() > 1 || path.exists() {
+        return Ok(path.to_path_buf());
+    }
+    ["", ".toml", ".yaml", ".yml"]
+        .iter()
+        .map(|ext| dir.join(format!("{name}{ext}")))
+        .find(|p| p.is_file())
+        .ok_or_else(|| format!("no {what} {name:?} in {}", dir.display()))
+}
+
+/// A YAML file if it is named so, TOML otherwise.
+pub(super) fn read<T: DeserializeOwned>(path: &Path) -> Result<T, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("read {}: {e}", path.display()))?;
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("yaml" | "yml") => serde_yaml::from_str(&text).map_err(|e| format!("{}: {e}", path.display())),
+        _ => toml::from_str(&text).map_err(|e| format!("{}: {e}", path.display())),
+    }
+}
+
+impl Action {
+    pub(super) fn kind(&self) -> &'static str {
+        match self {
+            Action::Drive { .. } => "drive",
+            Action::Turn { .. } => "turn",
+            Action::Arc { .. } => "arc",
+            Action::GoTo { .. } => "go_to",
+            Action::Stop => "stop",
+            Action::Wait { .. } => "wait",
+            Action::Song { .. } => "song",
+            Action::Leds { .. } => "leds",
+            Action::Dock => "dock",
+            Action::Behavior { .. } => "behavior",
+            Action::Oi { .. } => "oi",
+        }
+    }
+
+    /// Catch what would only fail once the action runs: bad song numbers,
+    /// unknown LED patterns and behaviors, and their bad arguments, and OI
+    /// commands that don't check out. `outer` can't be nested.
+    pub(super) fn check(&self, outer: &str) -> Result<(), String> {
+        match self {
+            Action::Oi { bytes } => {
+                program(bytes, None).map_err(|problems| command::describe(&problems))?;
+            }
+            Action::Song { number, notes } => {
+                if *number > 15 {
+                    return Err(format!("song number {number} out of range (0-15)"));
+                }
+                if notes.as_ref().is_some_and(|n| n.is_empty() || n.len() > MAX_NOTES) {
+                    return Err(format!("a song has 1 to {MAX_NOTES} notes"));
+                }
+            }
+            Action::Leds { pattern: Some(name) } if !leds::known(name) => return Err(format!("no LED pattern {name:?}")),
+            Action::Arc { radius_m, .. } if !radius_m.is_finite() || *radius_m <= 0.0 => return Err(format!("arc radius {radius_m} m must be positive")),
+            Action::Behavior { name, .. } if name == outer => return Err(format!("a {outer} can't run another")),
+            // Checked as they start, so files that refer to each other don't recurse here
+            Action::Behavior { name, .. } if name == "mission" || name == "tree" => {}
+            Action::Behavior { name, args } => {
+                let args: Vec<&str> = args.iter().map(String::as_str).collect();
+                super::build(name, &args)?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// A fresh run of the action.
+    pub(super) fn build(&self, cfg: &BehaviorConfig) -> Result<Box<dyn Behavior>, String> {
+        Ok(match self {
+            Action::Drive { distance_m, speed } => Box::new(primitive::Drive::new(*distance_m, *speed, cfg)),
+            Action::Turn { angle, rate } => Box::new(primitive::Turn::new(angle.to_radians(), rate.map(f64::to_radians), cfg)),
+            Action::Arc { radius_m, angle, speed } => Box::new(primitive::Arc::new(*radius_m, angle.to_radians(), *speed, cfg)?),
+            Action::GoTo { x, y, theta, speed } => Box::new(primitive::GoTo::new(*x, *y, theta.map(f64::to_radians), *speed, cfg)?),
+            Action::Stop => Box::new(primitive::Stop::new(cfg)),
+            Action::Wait { seconds } => Box::new(Wait { duration: Duration::from_secs_f64(seconds.max(0.0)), since: None }),
+            Action::Song { number, notes } => Box::new(Song { number: *number, notes: notes.clone(), until: None }),
+            Action::Leds { pattern } => Box::new(Leds { pattern: pattern.clone() }),
+            Action::Dock => Box::new(dock::Dock::new(cfg.dock())),
+            Action::Behavior { name, args } => {
+                let args: Vec<&str> = args.iter().map(String::as_str).collect();
+                super::build(name, &args)?
+            }
+            Action::Oi { bytes } => Box::new(Oi { bytes: bytes.clone() }),
+        })
+    }
+}
+
+/// The commands of an `oi` step's `bytes`, sent with the robot in `mode`.
+fn program(bytes: &[i64], mode: Option<i32>) -> Result<Vec<Vec<u8>>, Vec<command::Invalid>> {
+    command::validate(&command::bytes(bytes)?, mode)
+}
+
+/// Every problem with the mission file `name`, each OI command's on its
+/// own, for `createctl validate`.
+pub fn problems(name: &str, cfg: &BehaviorConfig) -> Result<Value, String> {
+    let path = locate(name, &cfg.missions_dir(), "mission")?;
+    let mission: MissionFile = read(&path)?;
+    let mut errors = Vec::new();
+    if mission.steps.is_empty() {
+        errors.push(json!({ "error": "the mission has no steps" }));
+    }
+    for (i, step) in mission.steps.iter().enumerate() {
+        let found = match &step.action {
+            Action::Oi { bytes } => program(bytes, None).err().unwrap_or_default().into_iter().map(|p| json!(p)).collect(),
+            action => action.check("mission").err().map(|e| json!({ "error": e })).into_iter().collect::<Vec<_>>(),
+        };
+        errors.extend(found.into_iter().map(|mut e| {
+            e["step"] = json!(i);
+            e
+        }));
+    }
+    Ok(json!({ "mission": path, "valid": errors.is_empty(), "errors": errors }))
+}
+
+fn parse(path: &Path) -> Result<MissionFile, String> {
+    let mission: MissionFile = read(path)?;
+    if mission.steps.is_empty() {
+        return Err(format!("{}: the mission has no steps", path.display()));
+    }
+    for (i, step) in mission.steps.iter().enumerate() {
+        step.action.check("mission").map_err(|e| format!("step {i}: {e}"))?;
+    }
+    Ok(mission)
+}
+
+pub struct Mission {
+    name: String,
+    file: MissionFile,
+    cfg: BehaviorConfig,
+    index: usize,
+    /// Of the current step, from 1
+    attempt: u32,
+    current: Option<Box<dyn Behavior>>,
+    step_started: Option<Instant>,
+    skipped: usize,
+}
+
+impl Mission {
+    /// `args`: the mission file, or its name in `behavior.missions_dir`.
+    pub fn load(args: &[&str], cfg: BehaviorConfig) -> Result<Self, String> {
+        let [name] = args else { return Err("usage: mission <file>".into()) };
+        let path = locate(name, &cfg.missions_dir(), "mission")?;
+        let file = parse(&path)?;
+        let name = file.name.clone().unwrap_or_else(|| path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default());
+        Ok(Mission { name, file, cfg, index: 0, attempt: 1, current: None, step_started: None, skipped: 0 })
+    }
+
+    fn step_event(&self, outcome: Outcome, reason: Option<String>) -> Event {
+        let step = self.file.steps[self.index].action.kind().to_string();
+        Event::MissionStep { mission: self.name.clone(), index: self.index, step, outcome, reason }
+    }
+
+    fn next_step(&mut self) {
+        self.index += 1;
+        self.attempt = 1;
+        self.current = None;
+        self.step_started = None;
+    }
+}
+
+impl Behavior for Mission {
+    fn name(&self) -> &'static str {
+        "mission"
+    }
+
+    fn step(&mut self, ctx: &mut Context) -> Status {
+        loop {
+            let Some(step) = self.file.steps.get(self.index).cloned() else { return Status::Done };
+            let status = match self.current.as_mut() {
+                None => match step.action.build(&self.cfg) {
+                    Ok(b) => {
+                        self.current = Some(b);
+                        continue;
+                    }
+                    Err(e) => Status::Failed(e),
+                },
+                Some(current) => {
+                    let started = *self.step_started.get_or_insert(ctx.now);
+                    let timeout = step.timeout_s.filter(|t| *t > 0.0).map(Duration::from_secs_f64);
+                    match timeout {
+                        Some(t) if ctx.now.duration_since(started) > t => Status::Failed(format!("timed out after {t:?}")),
+                        _ => current.step(ctx),
+                    }
+                }
+            };
+            match status {
+                Status::Running(wheels) => return Status::Running(wheels),
+                Status::Done => {
+                    ctx.emit.push(self.step_event(Outcome::Done, None));
+                    self.next_step();
+                }
+                Status::Failed(reason) => {
+                    ctx.emit.push(self.step_event(Outcome::Failed, Some(reason.clone())));
+                    let retries = step.retries.or(self.file.retries).unwrap_or(1);
+                    match step.on_error.unwrap_or(self.file.on_error) {
+                        OnError::Retry if self.attempt <= retries => {
+                            self.attempt += 1;
+                            self.current = None;
+                            self.step_started = None;
+                        }
+                        OnError::Skip => {
+                            self.skipped += 1;
+                            self.next_step();
+                        }
+                        _ => return Status::Failed(format!("step {} ({}): {reason}", self.index, step.action.kind())),
+                    }
+                }
+            }
+        }
+    }
+
+    fn progress(&self) -> Value {
+        let step = self.file.steps.get(self.index).map(|s| s.action.kind());
+        let detail = self.current.as_ref().map(|b| json!({ "name": b.name(), "progress": b.progress() }));
+        json!({
+            "mission": self.name,
+            "step": self.index,
+            "steps": self.file.steps.len(),
+            "type": step,
+            "attempt": self.attempt,
+            "skipped": self.skipped,
+            "current": detail
+        })
+    }
+
+    fn deadline(&self) -> Option<Duration> {
+        self.file.timeout_s.filter(|t| *t > 0.0).map(Duration::from_secs_f64)
+    }
+
+    fn on_timeout(&self) -> Option<OnTimeout> {
+        self.file.on_timeout
+    }
+}
+
+struct Wait {
+    duration: Duration,
+    since: Option<Instant>,
+}
+
+impl Behavior for Wait {
+    fn name(&self) -> &'static str {
+        "wait"
+    }
+
+    fn step(&mut self, ctx: &mut Context) -> Status {
+        let since = *self.since.get_or_insert(ctx.now);
+        if ctx.now.duration_since(since) >= self.duration {
+            return Status::Done;
+        }
+        Status::Running(Wheels::STOP)
+    }
+}
+
+struct Song {
+    number: u8,
+    notes: Option<Vec<[u8; 2]>>,
+    /// Playing until then
+    until: Option<Instant>,
+}
+
+impl Behavior for Song {
+    fn name(&self) -> &'static str {
+        "song"
+    }
+
+    /// Waits for a song it stored to finish; a song already on the robot
+    /// has an unknown length, so that step is done once it starts.
+    fn step(&mut self, ctx: &mut Context) -> Status {
+        let until = match self.until {
+            Some(until) => until,
+            None => {
+                let mut length = Duration::ZERO;
+                if let Some(notes) = &self.notes {
+                    let mut command = vec![oi::SONG, self.number, notes.len() as u8];
+                    for [note, duration] in notes {
+                        command.extend([*note, *duration]);
+                        length += Duration::from_secs_f64(*duration as f64 / 64.0);
+                    }
+                    motion::queue(command);
+                }
+                motion::queue(vec![oi::PLAY, self.number]);
+                *self.until.insert(ctx.now + length)
+            }
+        };
+        if ctx.now >= until {
+            return Status::Done;
+        }
+        Status::Running(Wheels::STOP)
+    }
+}
+
+struct Leds {
+    pattern: Option<String>,
+}
+
+impl Behavior for Leds {
+    fn name(&self) -> &'static str {
+        "leds"
+    }
+
+    /// Done as soon as the pattern starts: it plays on by itself.
+    fn step(&mut self, _ctx: &mut Context) -> Status {
+        let started = match &self.pattern {
+            Some(name) => leds::play(name),
+            None => leds::stop(),
+        };
+        match started {
+            Ok(_) => Status::Done,
+            Err(e) => Status::Failed(e),
+        }
+    }
+}
+
+struct Oi {
+    bytes: Vec<i64>,
+}
+
+impl Behavior for Oi {
+    fn name(&self) -> &'static str {
+        "oi"
+    }
+
+    /// Checked again in the mode the robot is in now, then sent whole or
+    /// not at all.
+    fn step(&mut self, ctx: &mut Context) -> Status {
+        match program(&self.bytes, ctx.frame.get(oi::OI_MODE)) {
+            Ok(commands) => {
+                commands.into_iter().for_each(motion::queue);
+                Status::Done
+            }
+            Err(problems) => Status::Failed(command::describe(&problems)),
+        }
+    }
+}
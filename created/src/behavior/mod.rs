@@ -0,0 +1,343 @@
+//! Autonomous behaviors. At most one runs at a time; the session steps it
+//! after every sensor frame with the frame, the pose estimate, and the
+//! frame's events, and it answers with the wheel speeds it wants (sent
+//! through [`motion`], so ramping still applies) until it is done or fails.
+//! Starting one replaces the running one; a manual `drive` or `stop`
+//! cancels it. Every behavior publishes `behavior_started` and
+//! `behavior_ended` events, plus progress events of its own.
+//!
+//! A run may have a deadline, from `behavior.timeout_s` and
+//! `behavior.timeouts` or the behavior itself (a mission file's
+//! `timeout_s`). Missing it stops the wheels, ends the run as timed out
+//! with a `behavior_timeout` event, and with `on_timeout = "dock"` sends
+//! the robot home with the dock behavior.
+
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use serde_json::{json, Value};
+use tracing::{info, warn};
+
+use crate::bus::Event;
+use crate::config::{BehaviorConfig, OnTimeout};
+use crate::motion::{self, Layer, Wheels};
+use crate::oi::SensorFrame;
+use crate::pose::Pose;
+
+pub mod beacon;
+pub mod coverage;
+pub mod dock;
+pub mod heading;
+pub mod mission;
+pub mod patrol;
+pub mod primitive;
+pub mod route;
+pub mod script;
+pub mod selftest;
+pub mod spiral;
+pub mod tree;
+pub mod undock;
+pub mod wall_follow;
+pub mod wander;
+pub mod waypoints;
+
+/// What a behavior sees each step.
+pub struct Context<'a> {
+    pub frame: &'a SensorFrame,
+    pub pose: Option<Pose>,
+    /// Events detected in this frame
+    pub events: &'a [Event],
+    pub now: Instant,
+    /// Progress events to publish
+    pub emit: Vec<Event>,
+}
+
+pub enum Status {
+    Running(Wheels),
+    Done,
+    Failed(String),
+}
+
+pub trait Behavior: Send {
+    fn name(&self) -> &'static str;
+
+    fn step(&mut self, ctx: &mut Context) -> Status;
+
+    /// Shown by `createctl behavior`.
+    fn progress(&self) -> Value {
+        Value::Null
+    }
+
+    /// A deadline of its own, over the configured one.
+    fn deadline(&self) -> Option<Duration> {
+        None
+    }
+
+    /// What missing it does, over `behavior.on_timeout`.
+    fn on_timeout(&self) -> Option<OnTimeout> {
+        None
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Done,
+    Failed,
+    Cancelled,
+    /// Stopped at its deadline
+    TimedOut,
+}
+
+struct Runner {
+    /// A session is running, so behaviors can start
+    session: bool,
+    active: Option<Box<dyn Behavior>>,
+    /// When the active one started, and its deadline
+    since: Option<Instant>,
+    deadline: Option<(Duration, OnTimeout)>,
+    /// Started/ended events waiting for the next step
+    pending: Vec<Event>,
+    /// Started once the active one is done or fails: the autostart
+    /// behavior, after the self-test
+    next: Option<Box<dyn Behavior>>,
+}
+
+static RUNNER: Mutex<Runner> = Mutex::new(Runner { session: false, active: None, since: None, deadline: None, pending: Vec::new(), next: None });
+static CONFIG: OnceLock<BehaviorConfig> = OnceLock::new();
+
+fn runner() -> std::sync::MutexGuard<'static, Runner> {
+    RUNNER.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+pub fn init(cfg: BehaviorConfig) {
+    heading::init(&cfg.heading());
+    let _ = CONFIG.set(cfg);
+}
+
+/// Settings for the behaviors, `[behavior]` in the config.
+pub fn config() -> BehaviorConfig {
+    CONFIG.get().cloned().unwrap_or_default()
+}
+
+fn ended(name: &str, outcome: Outcome, reason: Option<String>) -> Event {
+    Event::BehaviorEnded { name: name.to_string(), outcome, reason }
+}
+
+/// Called by the session as it starts and ends: behaviors only run while a
+/// robot is connected, and none survives its session but one `resumed`
+/// after a lost link. `behavior.autostart` is started with every other
+/// session, after the self-test if that runs on connecting.
+pub fn begin(resumed: Option<Box<dyn Behavior>>) {
+    // The connected robot's own gains, if it has a name
+    heading::init(&config().heading());
+    {
+        let mut r = runner();
+        r.session = true;
+        r.pending.clear();
+    }
+    if let Some(behavior) = resumed {
+        let name = behavior.name();
+        match start(behavior) {
+            Ok(()) => info!("resuming behavior {name}"),
+            Err(e) => warn!("not resuming behavior {name}: {e}"),
+        }
+        return;
+    }
+    let cfg = config();
+    let self_test = cfg.self_test().on_connect();
+    if self_test {
+        if let Err(e) = start(Box::new(selftest::SelfTest::new(cfg.self_test()))) {
+            warn!("behavior.self_test.on_connect: {e}");
+        }
+    }
+    let Some(autostart) = cfg.autostart else { return };
+    let words: Vec<&str> = autostart.split_whitespace().collect();
+    let Some((name, args)) = words.split_first() else { return };
+    let built = match build(name, args) {
+        Ok(behavior) if self_test => {
+            info!("starting behavior {autostart:?} after the self-test");
+            runner().next = Some(behavior);
+            return;
+        }
+        Ok(behavior) => start(behavior),
+        Err(e) => Err(e),
+    };
+    match built {
+        Ok(()) => info!("starting behavior {autostart:?}"),
+        Err(e) => warn!("behavior.autostart: {e}"),
+    }
+}
+
+/// The behavior that was running, for resuming it.
+pub fn end() -> Option<Box<dyn Behavior>> {
+    let mut r = runner();
+    r.session = false;
+    r.next = None;
+    r.pending.clear();
+    route::end();
+    selftest::end();
+    r.active.take()
+}
+
+/// A behavior by name, as `createctl behavior start <name> [args]` and
+/// missions refer to them.
+pub fn build(name: &str, args: &[&str]) -> Result<Box<dyn Behavior>, String> {
+    let cfg = config();
+    match name {
+        "waypoints" => {
+            let points = args.iter().map(|s| waypoints::Waypoint::parse(s, &cfg.waypoints())).collect::<Result<Vec<_>, _>>()?;
+            Ok(Box::new(waypoints::Waypoints::new(points, cfg.waypoints())?))
+        }
+        "wall-follow" => Ok(Box::new(wall_follow::WallFollow::new(args.first().copied(), cfg.wall_follow())?)),
+        "go-to" => {
+            let [goal] = args else { return Err("usage: go-to <x,y[,theta]>".into()) };
+            let parts: Result<Vec<f64>, _> = goal.split(',').map(|p| p.trim().parse::<f64>()).collect();
+            match parts.as_deref() {
+                Ok([x, y]) => Ok(Box::new(primitive::GoTo::new(*x, *y, None, None, &cfg)?)),
+                Ok([x, y, theta]) => Ok(Box::new(primitive::GoTo::new(*x, *y, Some(theta.to_radians()), None, &cfg)?)),
+                _ => Err(format!("bad goal {goal:?}: expected x,y[,theta] (m, m, °)")),
+            }
+        }
+        "wander" => Ok(Box::new(wander::Wander::new(cfg.wander()))),
+        "coverage" => Ok(Box::new(coverage::Coverage::new(args, cfg.coverage(), cfg.waypoints())?)),
+        "spiral" => Ok(Box::new(spiral::Spiral::new(args, cfg.spiral())?)),
+        "dock" if args.is_empty() => Ok(Box::new(dock::Dock::new(cfg.dock()))),
+        "dock" => Err("usage: dock".into()),
+        "beacon" => Ok(Box::new(beacon::Beacon::new(args, cfg.beacon())?)),
+        "heading-tune" if args.is_empty() => Ok(Box::new(heading::Tune::new(cfg.heading()))),
+        "heading-tune" => Err("usage: heading-tune".into()),
+        "mission" => Ok(Box::new(mission::Mission::load(args, cfg)?)),
+        "patrol" => Ok(Box::new(patrol::Patrol::new(args, &crate::patrol::config(), cfg)?)),
+        "route" => Ok(Box::new(route::Route::load(args, cfg)?)),
+        "script" => Ok(Box::new(script::Script::load(args, &cfg)?)),
+        "self-test" if args.is_empty() => Ok(Box::new(selftest::SelfTest::new(cfg.self_test()))),
+        "self-test" => Err("usage: self-test".into()),
+        "tree" => Ok(Box::new(tree::Tree::load(args, cfg)?)),
+        "undock" => {
+            let distance = match args {
+                [] => None,
+                [m] => Some(m.parse::<f64>().map_err(|_| format!("bad distance {m:?}: expected metres"))?),
+                _ => return Err("usage: undock [m]".into()),
+            };
+            Ok(Box::new(undock::Undock::new(distance, &crate::charging_lockout::config(), &cfg)))
+        }
+        _ => Err(format!("unknown behavior {name:?} (waypoints, go-to, wall-follow, wander, coverage, spiral, dock, beacon, heading-tune, mission, patrol, route, script, self-test, tree, undock)")),
+    }
+}
+
+/// Run `behavior` from the next frame on, replacing any running one.
+pub fn start(behavior: Box<dyn Behavior>) -> Result<(), String> {
+    let mut r = runner();
+    if !r.session {
+        return Err("no robot is connected".into());
+    }
+    crate::estop::check()?;
+    crate::low_voltage::check()?;
+    r.run(behavior);
+    Ok(())
+}
+
+impl Runner {
+    fn run(&mut self, behavior: Box<dyn Behavior>) {
+        if let Some(old) = self.active.take() {
+            let reason = format!("replaced by {}", behavior.name());
+            self.pending.push(ended(old.name(), Outcome::Cancelled, Some(reason)));
+        }
+        let cfg = config();
+        let after = behavior.deadline().or_else(|| cfg.deadline(behavior.name()));
+        self.deadline = after.map(|a| (a, behavior.on_timeout().unwrap_or(cfg.on_timeout())));
+        self.since = Some(Instant::now());
+        self.pending.push(Event::BehaviorStarted { name: behavior.name().to_string() });
+        self.active = Some(behavior);
+        self.next = None;
+    }
+
+    /// Stop the active behavior if it is past its deadline; the events.
+    fn expire(&mut self, now: Instant) -> Vec<Event> {
+        let (Some(behavior), Some(since), Some((after, then))) = (self.active.as_ref(), self.since, self.deadline) else { return Vec::new() };
+        if now.duration_since(since) <= after {
+            return Vec::new();
+        }
+        let name = behavior.name();
+        let dock = then == OnTimeout::Dock && name != "dock";
+        warn!("behavior {name} timed out after {after:?}{}", if dock { ", docking" } else { "" });
+        motion::stop();
+        self.active = None;
+        self.next = None;
+        let then = if dock { "dock" } else { "stop" };
+        let out = vec![
+            Event::BehaviorTimeout { name: name.to_string(), after_s: after.as_secs_f64(), then: then.to_string() },
+            ended(name, Outcome::TimedOut, Some(format!("timed out after {after:?}"))),
+        ];
+        if dock {
+            self.run(Box::new(dock::Dock::new(config().dock())));
+        }
+        out
+    }
+}
+
+/// Stop the running behavior, if any, without touching the wheels. The
+/// name of the cancelled behavior.
+pub fn cancel(reason: &str) -> Option<&'static str> {
+    let mut r = runner();
+    let old = r.active.take()?;
+    r.next = None;
+    r.pending.push(ended(old.name(), Outcome::Cancelled, Some(reason.to_string())));
+    Some(old.name())
+}
+
+/// The running behavior and its progress.
+pub fn status() -> Option<Value> {
+    let r = runner();
+    let b = r.active.as_ref()?;
+    let elapsed = r.since.map(|s| s.elapsed().as_secs_f64());
+    let deadline = r.deadline.map(|(after, _)| after.as_secs_f64());
+    Some(json!({ "name": b.name(), "progress": b.progress(), "elapsed_s": elapsed, "deadline_s": deadline }))
+}
+
+/// Step the running behavior for one frame; events to publish.
+pub fn tick(frame: &SensorFrame, pose: Option<Pose>, events: &[Event]) -> Vec<Event> {
+    let mut r = runner();
+    let mut out = std::mem::take(&mut r.pending);
+    let now = Instant::now();
+    out.extend(route::update(pose, now));
+    out.extend(r.expire(now));
+    // Suspended while a reflex or a safety stop has the wheels
+    if motion::layer() > Layer::User {
+        return out;
+    }
+    let Some(behavior) = r.active.as_mut() else { return out };
+    let mut ctx = Context { frame, pose, events, now, emit: Vec::new() };
+    // A bug in one behavior ends it, not the session
+    let status = match panic::catch_unwind(AssertUnwindSafe(|| behavior.step(&mut ctx))) {
+        Ok(status) => status,
+        Err(payload) => {
+            let error = payload.downcast_ref::<&str>().map(|s| s.to_string()).or_else(|| payload.downcast_ref::<String>().cloned()).unwrap_or_else(|| "panic".into());
+            ctx.emit.push(Event::BehaviorCrashed { name: behavior.name().to_string(), error: error.clone() });
+            Status::Failed(format!("crashed: {error}"))
+        }
+    };
+    out.append(&mut ctx.emit);
+    match status {
+        Status::Running(wheels) => motion::lease(wheels),
+        Status::Done => {
+            motion::stop();
+            out.push(ended(behavior.name(), Outcome::Done, None));
+            r.active = None;
+        }
+        Status::Failed(reason) => {
+            motion::stop();
+            out.push(ended(behavior.name(), Outcome::Failed, Some(reason)));
+            r.active = None;
+        }
+    }
+    if r.active.is_none() {
+        if let Some(next) = r.next.take() {
+            r.run(next);
+        }
+    }
+    out
+}
@@ -0,0 +1,112 @@
+//! One patrol run: back off the dock if the robot starts on it, play a
+//! recorded route, and dock again (unless `patrol.dock` is off). A route
+//! that fails still ends with docking, so the robot is home for the next
+//! run; either failing fails the patrol. Every phase publishes a
+//! `patrol_phase` event as it ends, for the patrol report.
+
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+
+use serde_json::{json, Value};
+
+use super::dock::Dock;
+use super::primitive::Turn;
+use super::route::Route;
+use super::undock::Undock;
+use super::{Behavior, Context, Outcome, Status};
+use crate::bus::Event;
+use crate::charging_lockout;
+use crate::config::{BehaviorConfig, PatrolConfig, RouteMode};
+
+pub struct Patrol {
+    route: String,
+    cfg: BehaviorConfig,
+    undock_m: f64,
+    /// Moves left, each with the phase it belongs to; undocking is added
+    /// on the first frame, if the robot is on the dock
+    moves: VecDeque<(&'static str, Box<dyn Behavior>)>,
+    started: bool,
+    failures: Vec<String>,
+}
+
+impl Patrol {
+    /// `args`: the route's name (default `patrol.route`), then optionally
+    /// `velocity` or `poses`.
+    pub fn new(args: &[&str], patrol: &PatrolConfig, cfg: BehaviorConfig) -> Result<Self, String> {
+        let mode = patrol.mode.map(|m| match m {
+            RouteMode::Velocity => "velocity",
+            RouteMode::Poses => "poses",
+        });
+        let route_args: Vec<&str> = match args {
+            [] => {
+                let name = patrol.route.as_deref().ok_or("no route given, and patrol.route isn't set")?;
+                std::iter::once(name).chain(mode).collect()
+            }
+            [name] => std::iter::once(*name).chain(mode).collect(),
+            [_, _] => args.to_vec(),
+            _ => return Err("usage: patrol [route] [velocity|poses]".into()),
+        };
+        let route = Route::load(&route_args, cfg.clone())?;
+        let mut moves: VecDeque<(&'static str, Box<dyn Behavior>)> = VecDeque::new();
+        moves.push_back(("route", Box::new(route)));
+        if patrol.dock() {
+            moves.push_back(("dock", Box::new(Dock::new(cfg.dock()))));
+        }
+        Ok(Patrol { route: route_args[0].to_string(), cfg, undock_m: patrol.undock_m(), moves, started: false, failures: Vec::new() })
+    }
+
+    fn phase_ended(&self, ctx: &mut Context, phase: &str, outcome: Outcome, reason: Option<String>) {
+        ctx.emit.push(Event::PatrolPhase { route: self.route.clone(), phase: phase.to_string(), outcome, reason });
+    }
+}
+
+impl Behavior for Patrol {
+    fn name(&self) -> &'static str {
+        "patrol"
+    }
+
+    fn step(&mut self, ctx: &mut Context) -> Status {
+        if !self.started {
+            self.started = true;
+            if charging_lockout::on_dock(ctx.frame) && self.undock_m > 0.0 {
+                // Off the contacts backward, then around to face the room
+                let undock = Undock::new(Some(self.undock_m), &charging_lockout::config(), &self.cfg);
+                self.moves.push_front(("undock", Box::new(Turn::new(PI, None, &self.cfg))));
+                self.moves.push_front(("undock", Box::new(undock)));
+            }
+        }
+        while let Some((phase, m)) = self.moves.front_mut() {
+            let phase = *phase;
+            match m.step(ctx) {
+                Status::Running(wheels) => return Status::Running(wheels),
+                Status::Done => {
+                    self.moves.pop_front();
+                    if self.moves.front().is_none_or(|(next, _)| *next != phase) {
+                        self.phase_ended(ctx, phase, Outcome::Done, None);
+                    }
+                }
+                Status::Failed(reason) => {
+                    self.phase_ended(ctx, phase, Outcome::Failed, Some(reason.clone()));
+                    self.failures.push(format!("{phase}: {reason}"));
+                    // Whatever went wrong out there, go home
+                    self.moves.retain(|(p, _)| *p != phase && *p != "route");
+                }
+            }
+        }
+        if self.failures.is_empty() {
+            Status::Done
+        } else {
+            Status::Failed(self.failures.join("; "))
+        }
+    }
+
+    fn progress(&self) -> Value {
+        let current = self.moves.front();
+        json!({
+            "route": self.route,
+            "phase": current.map(|(phase, _)| *phase),
+            "step": current.map(|(_, m)| json!({ "name": m.name(), "progress": m.progress() })),
+            "failures": self.failures
+        })
+    }
+}
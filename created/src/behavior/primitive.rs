@@ -0,0 +1,415 @@
+//! Motion primitives: drive a distance, turn through an angle, follow an
+//! arc, go to a pose, and stop, measured with the pose estimate and given
+//! up on after a timeout. The building blocks of mission and tree steps and
+//! of scripts.
+//!
+//! Each slows down as it closes in on its goal, so the velocity ramp
+//! doesn't carry it far past, and times out after `timeout_factor` times
+//! the time it should take (plus a couple of seconds for the ramps).
+//! Without a pose estimate they drive open loop, for as long as the move
+//! should take.
+
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+
+use super::heading::HeadingHold;
+use super::{Behavior, Context, Status};
+use crate::bus::Event;
+use crate::config::{BehaviorConfig, GoToMode, HeadingConfig, PrimitivesConfig};
+use crate::motion::{self, Wheels};
+use crate::pose;
+
+/// Drives slow down over their last this many metres,
+const SLOWDOWN_M: f64 = 0.1;
+/// turns and arcs over their last this many radians (20°),
+const SLOWDOWN_ANGLE: f64 = 0.35;
+/// to no less than this fraction of their speed.
+const MIN_FRACTION: f64 = 0.25;
+/// Added to every timeout, for ramping up and down.
+const TIMEOUT_SLACK: Duration = Duration::from_secs(2);
+/// Pose changes below these, m and rad, count as standing still.
+const STILL_M: f64 = 0.001;
+const STILL_ANGLE: f64 = 0.005;
+/// Frames standing still before a stop is done.
+const STILL_FRAMES: u32 = 3;
+/// Go-to steps approach no slower than this, mm/s: below it the wheels
+/// barely turn.
+const MIN_SPEED: f64 = 40.0;
+/// Without a pose estimate, a stop is done after this.
+const STOP_SETTLE: Duration = Duration::from_millis(500);
+
+/// Fails a primitive on contact. The bumpers and cliff sensors are at the
+/// front, so backing up or turning away from what set them off only stops
+/// for a wheel drop.
+pub(super) fn obstacle(ctx: &Context, forward: bool) -> Option<String> {
+    ctx.events.iter().find_map(|e| match e {
+        Event::Bump { .. } if forward => Some("bump".to_string()),
+        Event::Cliff { .. } if forward => Some("cliff".to_string()),
+        Event::WheelDrop { .. } => Some("wheel drop".to_string()),
+        _ => None,
+    })
+}
+
+/// `full` scaled down as `remaining` falls below `over`.
+fn slowed(full: f64, remaining: f64, over: f64) -> f64 {
+    full * (remaining / over).clamp(MIN_FRACTION, 1.0)
+}
+
+/// When a primitive that should take `expected` seconds started, and how
+/// long it is allowed.
+struct Deadline {
+    since: Option<Instant>,
+    limit: Duration,
+}
+
+impl Deadline {
+    fn new(expected: f64, cfg: &BehaviorConfig) -> Self {
+        Deadline::scaled(expected, cfg.primitives().timeout_factor())
+    }
+
+    fn scaled(expected: f64, factor: f64) -> Self {
+        Deadline { since: None, limit: Duration::from_secs_f64(expected * factor) + TIMEOUT_SLACK }
+    }
+
+    /// Time since the start, or why the primitive failed.
+    fn check(&mut self, now: Instant) -> Result<Duration, String> {
+        let elapsed = now.duration_since(*self.since.get_or_insert(now));
+        if elapsed > self.limit {
+            return Err(format!("timed out after {:?}", self.limit));
+        }
+        Ok(elapsed)
+    }
+}
+
+/// Heading turned so far, summed frame by frame so turns past 180° count.
+#[derive(Default)]
+struct Turned {
+    total: f64,
+    last: Option<f64>,
+}
+
+impl Turned {
+    fn update(&mut self, theta: f64) -> f64 {
+        if let Some(last) = self.last {
+            self.total += pose::normalize(theta - last);
+        }
+        self.last = Some(theta);
+        self.total
+    }
+}
+
+/// Straight ahead (negative: backward), holding the heading it started on.
+pub struct Drive {
+    distance: f64,
+    speed: f64,
+    heading: HeadingConfig,
+    hold: Option<HeadingHold>,
+    from: Option<(f64, f64)>,
+    travelled: f64,
+    deadline: Deadline,
+}
+
+impl Drive {
+    /// `distance` in m; `speed` in mm/s, or `behavior.primitives.speed`.
+    pub fn new(distance: f64, speed: Option<f64>, cfg: &BehaviorConfig) -> Self {
+        let speed = speed.unwrap_or(cfg.primitives().speed()).abs().clamp(1.0, motion::MAX_SPEED as f64);
+        let deadline = Deadline::new(distance.abs() * 1000.0 / speed, cfg);
+        Drive { distance, speed, heading: cfg.heading(), hold: None, from: None, travelled: 0.0, deadline }
+    }
+}
+
+impl Behavior for Drive {
+    fn name(&self) -> &'static str {
+        "drive"
+    }
+
+    fn step(&mut self, ctx: &mut Context) -> Status {
+        if let Some(reason) = obstacle(ctx, self.distance > 0.0) {
+            return Status::Failed(reason);
+        }
+        let elapsed = match self.deadline.check(ctx.now) {
+            Ok(elapsed) => elapsed,
+            Err(e) => return Status::Failed(e),
+        };
+        let (speed, omega) = match ctx.pose {
+            Some(p) => {
+                let (x, y) = *self.from.get_or_insert((p.x, p.y));
+                self.travelled = (p.x - x).hypot(p.y - y);
+                let remaining = self.distance.abs() - self.travelled;
+                if remaining <= 0.0 {
+                    return Status::Done;
+                }
+                let hold = self.hold.get_or_insert_with(|| HeadingHold::new(p.theta, &self.heading));
+                (slowed(self.speed, remaining, SLOWDOWN_M), hold.omega(p.theta, ctx.now))
+            }
+            None => {
+                self.travelled = (elapsed.as_secs_f64() * self.speed / 1000.0).min(self.distance.abs());
+                if self.travelled >= self.distance.abs() {
+                    return Status::Done;
+                }
+                (self.speed, 0.0)
+            }
+        };
+        Status::Running(Wheels::arc(speed.copysign(self.distance), omega, pose::wheel_base()))
+    }
+
+    fn progress(&self) -> Value {
+        json!({ "distance_m": self.distance, "travelled_m": self.travelled })
+    }
+}
+
+/// On the spot, positive counter-clockwise.
+pub struct Turn {
+    angle: f64,
+    rate: f64,
+    turned: Turned,
+    deadline: Deadline,
+}
+
+impl Turn {
+    /// `angle` in rad; `rate` in rad/s, or `behavior.primitives.turn_rate`.
+    pub fn new(angle: f64, rate: Option<f64>, cfg: &BehaviorConfig) -> Self {
+        let rate = rate.unwrap_or(cfg.primitives().turn_rate()).abs().max(0.01);
+        Turn { angle, rate, turned: Turned::default(), deadline: Deadline::new(angle.abs() / rate, cfg) }
+    }
+}
+
+impl Behavior for Turn {
+    fn name(&self) -> &'static str {
+        "turn"
+    }
+
+    fn step(&mut self, ctx: &mut Context) -> Status {
+        if let Some(reason) = obstacle(ctx, false) {
+            return Status::Failed(reason);
+        }
+        let elapsed = match self.deadline.check(ctx.now) {
+            Ok(elapsed) => elapsed,
+            Err(e) => return Status::Failed(e),
+        };
+        let turned = match ctx.pose {
+            Some(p) => self.turned.update(p.theta).abs(),
+            None => elapsed.as_secs_f64() * self.rate,
+        };
+        let remaining = self.angle.abs() - turned;
+        if remaining <= 0.0 {
+            return Status::Done;
+        }
+        let rate = if ctx.pose.is_some() { slowed(self.rate, remaining, SLOWDOWN_ANGLE) } else { self.rate };
+        Status::Running(Wheels::arc(0.0, rate.copysign(self.angle), pose::wheel_base()))
+    }
+
+    fn progress(&self) -> Value {
+        json!({ "angle": self.angle.to_degrees(), "turned": self.turned.total.abs().to_degrees() })
+    }
+}
+
+/// Forward along a circle, turning through an angle: positive turns left
+/// (counter-clockwise), negative right.
+pub struct Arc {
+    radius: f64,
+    angle: f64,
+    speed: f64,
+    turned: Turned,
+    deadline: Deadline,
+}
+
+impl Arc {
+    /// `radius` in m, `angle` in rad; `speed` along the arc in mm/s, or
+    /// `behavior.primitives.speed`.
+    pub fn new(radius: f64, angle: f64, speed: Option<f64>, cfg: &BehaviorConfig) -> Result<Self, String> {
+        if !radius.is_finite() || radius <= 0.0 {
+            return Err(format!("arc radius {radius} m must be positive (turn on the spot with a turn)"));
+        }
+        let speed = speed.unwrap_or(cfg.primitives().speed()).abs().clamp(1.0, motion::MAX_SPEED as f64);
+        let deadline = Deadline::new(radius * angle.abs() * 1000.0 / speed, cfg);
+        Ok(Arc { radius, angle, speed, turned: Turned::default(), deadline })
+    }
+}
+
+impl Behavior for Arc {
+    fn name(&self) -> &'static str {
+        "arc"
+    }
+
+    fn step(&mut self, ctx: &mut Context) -> Status {
+        if let Some(reason) = obstacle(ctx, true) {
+            return Status::Failed(reason);
+        }
+        let elapsed = match self.deadline.check(ctx.now) {
+            Ok(elapsed) => elapsed,
+            Err(e) => return Status::Failed(e),
+        };
+        let turned = match ctx.pose {
+            Some(p) => self.turned.update(p.theta).abs(),
+            None => elapsed.as_secs_f64() * self.speed / 1000.0 / self.radius,
+        };
+        let remaining = self.angle.abs() - turned;
+        if remaining <= 0.0 {
+            return Status::Done;
+        }
+        // Slowing both keeps the radius
+        let speed = if ctx.pose.is_some() { slowed(self.speed, remaining, SLOWDOWN_ANGLE) } else { self.speed };
+        let omega = (speed / 1000.0 / self.radius).copysign(self.angle);
+        Status::Running(Wheels::arc(speed, omega, pose::wheel_base()))
+    }
+
+    fn progress(&self) -> Value {
+        json!({ "radius_m": self.radius, "angle": self.angle.to_degrees(), "turned": self.turned.total.abs().to_degrees() })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Leg {
+    /// Turning toward the goal (rotate mode only)
+    Face,
+    Approach,
+    /// There, turning to the heading
+    Align,
+}
+
+/// To (x, y) in the pose frame, ending on heading `theta` if given. The
+/// smooth mode steers along one curve with the polar unicycle law (Astolfi):
+/// with ρ the distance to go, α the goal's bearing off the heading, and β
+/// what is left of the final heading past that bearing, v = k_ρ·ρ and
+/// ω = k_α·α + k_β·β, forward only, turning on the spot while the goal is
+/// behind. The rotate mode turns to face the goal, drives there holding
+/// the bearing, and turns to the heading. Both finish with a turn on the
+/// spot if the heading is still off. Needs the pose estimate.
+pub struct GoTo {
+    x: f64,
+    y: f64,
+    theta: Option<f64>,
+    speed: f64,
+    cfg: PrimitivesConfig,
+    leg: Leg,
+    deadline: Option<Deadline>,
+    distance: Option<f64>,
+}
+
+impl GoTo {
+    /// `x`, `y` in m, `theta` in rad; `speed` in mm/s, or
+    /// `behavior.primitives.speed`.
+    pub fn new(x: f64, y: f64, theta: Option<f64>, speed: Option<f64>, cfg: &BehaviorConfig) -> Result<Self, String> {
+        if !(x.is_finite() && y.is_finite() && theta.is_none_or(f64::is_finite)) {
+            return Err(format!("bad goal ({x}, {y})"));
+        }
+        let primitives = cfg.primitives();
+        let speed = speed.unwrap_or(primitives.speed()).abs().clamp(1.0, motion::MAX_SPEED as f64);
+        let leg = if primitives.go_to_mode() == GoToMode::Rotate { Leg::Face } else { Leg::Approach };
+        Ok(GoTo { x, y, theta, speed, cfg: primitives, leg, deadline: None, distance: None })
+    }
+
+    /// Turn rate toward heading `error` to go, slowing down at the end.
+    fn turn(&self, error: f64) -> f64 {
+        slowed(self.cfg.turn_rate(), error.abs(), SLOWDOWN_ANGLE).copysign(error)
+    }
+}
+
+impl Behavior for GoTo {
+    fn name(&self) -> &'static str {
+        "go-to"
+    }
+
+    fn step(&mut self, ctx: &mut Context) -> Status {
+        let Some(p) = ctx.pose else { return Status::Failed("no pose estimate (pose.enabled is off)".into()) };
+        if let Some(reason) = obstacle(ctx, self.leg == Leg::Approach) {
+            return Status::Failed(reason);
+        }
+        let (dx, dy) = (self.x - p.x, self.y - p.y);
+        let rho = dx.hypot(dy);
+        let bearing = dy.atan2(dx);
+        let alpha = pose::normalize(bearing - p.theta);
+        self.distance = Some(rho);
+        if self.deadline.is_none() {
+            // Turning to the goal, driving there, and turning to the heading
+            let turns = alpha.abs() + self.theta.map_or(0.0, |t| pose::normalize(t - bearing).abs());
+            let expected = rho * 1000.0 / self.speed + turns / self.cfg.turn_rate();
+            self.deadline = Some(Deadline::scaled(expected, self.cfg.timeout_factor()));
+        }
+        if let Some(Err(e)) = self.deadline.as_mut().map(|d| d.check(ctx.now)) {
+            return Status::Failed(e);
+        }
+        let turn_rate = self.cfg.turn_rate();
+        if self.leg != Leg::Align && rho <= self.cfg.tolerance_m() {
+            self.leg = Leg::Align;
+        }
+        let (v, omega) = match self.leg {
+            Leg::Face if alpha.abs() <= self.cfg.angle_tolerance() => {
+                self.leg = Leg::Approach;
+                return self.step(ctx);
+            }
+            Leg::Face => (0.0, self.turn(alpha)),
+            Leg::Approach if self.cfg.go_to_mode() == GoToMode::Rotate => {
+                let (_, k_alpha, _) = self.cfg.gains();
+                let v = slowed(self.speed, rho, SLOWDOWN_M).max(MIN_SPEED.min(self.speed)) * alpha.cos().max(0.0);
+                (v, (k_alpha * alpha).clamp(-turn_rate, turn_rate))
+            }
+            Leg::Approach => {
+                let (k_rho, k_alpha, k_beta) = self.cfg.gains();
+                let beta = self.theta.map_or(0.0, |t| pose::normalize(t - bearing));
+                let v = if alpha.abs() < std::f64::consts::FRAC_PI_2 { (k_rho * rho * 1000.0).clamp(MIN_SPEED.min(self.speed), self.speed) * alpha.cos() } else { 0.0 };
+                (v, (k_alpha * alpha + k_beta * beta).clamp(-turn_rate, turn_rate))
+            }
+            Leg::Align => {
+                let error = self.theta.map_or(0.0, |t| pose::normalize(t - p.theta));
+                if error.abs() <= self.cfg.angle_tolerance() {
+                    return Status::Done;
+                }
+                (0.0, self.turn(error))
+            }
+        };
+        Status::Running(Wheels::arc(v, omega, pose::wheel_base()))
+    }
+
+    fn progress(&self) -> Value {
+        let leg = match self.leg {
+            Leg::Face => "face",
+            Leg::Approach => "approach",
+            Leg::Align => "align",
+        };
+        json!({ "goal": [self.x, self.y, self.theta.map(f64::to_degrees)], "leg": leg, "distance_m": self.distance })
+    }
+}
+
+/// Ramps the wheels down and waits until the robot stands still.
+pub struct Stop {
+    last: Option<pose::Pose>,
+    still: u32,
+    deadline: Deadline,
+}
+
+impl Stop {
+    pub fn new(cfg: &BehaviorConfig) -> Self {
+        Stop { last: None, still: 0, deadline: Deadline::new(STOP_SETTLE.as_secs_f64(), cfg) }
+    }
+}
+
+impl Behavior for Stop {
+    fn name(&self) -> &'static str {
+        "stop"
+    }
+
+    fn step(&mut self, ctx: &mut Context) -> Status {
+        let elapsed = match self.deadline.check(ctx.now) {
+            Ok(elapsed) => elapsed,
+            Err(_) => return Status::Failed("still moving".into()),
+        };
+        match ctx.pose {
+            Some(p) => {
+                if let Some(last) = self.last.replace(p) {
+                    let moved = (p.x - last.x).hypot(p.y - last.y);
+                    let turned = pose::normalize(p.theta - last.theta).abs();
+                    self.still = if moved < STILL_M && turned < STILL_ANGLE { self.still + 1 } else { 0 };
+                }
+                if self.still >= STILL_FRAMES {
+                    return Status::Done;
+                }
+            }
+            None if elapsed >= STOP_SETTLE => return Status::Done,
+            None => {}
+        }
+        Status::Running(Wheels::STOP)
+    }
+}
@@ -0,0 +1,314 @@
+//! Teach and repeat: record a route while the robot is driven by hand
+//! (`createctl route record <name>` ... `createctl route stop`), then play
+//! it back on demand with the `route` behavior. A route keeps the wheel
+//! speeds requested, each time they change, and the pose every
+//! `behavior.routes.spacing_m` along the way. Playback either repeats the
+//! wheel speeds on the recorded timing (`velocity`), or drives through the
+//! recorded poses with the waypoint controller (`poses`), which corrects
+//! for the drift that timing alone accumulates. Poses are replayed
+//! relative to where the robot stands when playback starts, as they were
+//! recorded relative to where it stood then.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tracing::{info, warn};
+
+use super::waypoints::{Waypoint, Waypoints};
+use super::{primitive, Behavior, Context, Status};
+use crate::bus::Event;
+use crate::config::{BehaviorConfig, RouteMode};
+use crate::motion::{self, Wheels};
+use crate::pose::{self, Pose};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Sample {
+    /// s since the recording started
+    pub t: f64,
+    /// Requested wheel speeds from then on, mm/s
+    pub left: i16,
+    pub right: i16,
+    /// Pose relative to the start (x forward, m; theta rad), if tracked
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pose: Option<[f64; 3]>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RouteFile {
+    pub name: String,
+    /// When it was recorded, RFC 3339
+    pub recorded: String,
+    pub samples: Vec<Sample>,
+}
+
+struct Recording {
+    name: String,
+    path: PathBuf,
+    spacing: f64,
+    since: Instant,
+    origin: Option<Pose>,
+    last_wheels: Option<Wheels>,
+    last_pose: Option<[f64; 3]>,
+    samples: Vec<Sample>,
+}
+
+static RECORDING: Mutex<Option<Recording>> = Mutex::new(None);
+/// Events from `stop`, published with the next frame.
+static PENDING: Mutex<Vec<Event>> = Mutex::new(Vec::new());
+
+fn recording() -> std::sync::MutexGuard<'static, Option<Recording>> {
+    RECORDING.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// `<name>.json` in `behavior.routes.dir`; names are kept to plain file
+/// names.
+fn path(name: &str, cfg: &BehaviorConfig) -> Result<PathBuf, String> {
+    if name.is_empty() || name.contains(['/', '\\']) || name.starts_with('.') {
+        return Err(format!("bad route name {name:?}"));
+    }
+    Ok(cfg.routes().dir().join(format!("{name}.json")))
+}
+
+/// `pose` in the frame of `origin`: x ahead of it, y to its left.
+fn relative(pose: &Pose, origin: &Pose) -> [f64; 3] {
+    let (dx, dy) = (pose.x - origin.x, pose.y - origin.y);
+    let (sin, cos) = origin.theta.sin_cos();
+    [dx * cos + dy * sin, -dx * sin + dy * cos, pose::normalize(pose.theta - origin.theta)]
+}
+
+/// Start recording a route, replacing one in progress.
+pub fn record(name: &str, cfg: &BehaviorConfig) -> Result<(), String> {
+    let path = path(name, cfg)?;
+    let mut r = recording();
+    if let Some(old) = r.take() {
+        warn!("route {} discarded for {name}", old.name);
+    }
+    *r = Some(Recording {
+        name: name.to_string(),
+        path,
+        spacing: cfg.routes().spacing_m(),
+        since: Instant::now(),
+        origin: None,
+        last_wheels: None,
+        last_pose: None,
+        samples: Vec::new(),
+    });
+    info!("recording route {name}");
+    Ok(())
+}
+
+/// The route being recorded, its samples, and seconds so far.
+pub fn recording_status() -> Option<Value> {
+    let r = recording();
+    let rec = r.as_ref()?;
+    Some(json!({ "name": rec.name, "samples": rec.samples.len(), "duration_s": rec.since.elapsed().as_secs_f64() }))
+}
+
+impl Recording {
+    fn sample(&mut self, pose: Option<Pose>, now: Instant) {
+        let wheels = motion::current();
+        let pose = pose.map(|p| relative(&p, self.origin.get_or_insert(p)));
+        let moved = match (pose, self.last_pose) {
+            (Some([x, y, _]), Some([lx, ly, _])) => (x - lx).hypot(y - ly) >= self.spacing,
+            (Some(_), None) => true,
+            _ => false,
+        };
+        if self.last_wheels != Some(wheels) || moved {
+            let t = now.duration_since(self.since).as_secs_f64();
+            self.samples.push(Sample { t, left: wheels.left, right: wheels.right, pose });
+            self.last_wheels = Some(wheels);
+            if pose.is_some() {
+                self.last_pose = pose;
+            }
+        }
+    }
+
+    /// Write the route, ending on the pose it stopped at.
+    fn save(mut self, pose: Option<Pose>) -> Result<Event, String> {
+        let t = self.since.elapsed().as_secs_f64();
+        let end = pose.zip(self.origin).map(|(p, o)| relative(&p, &o));
+        self.samples.push(Sample { t, left: 0, right: 0, pose: end.or(self.last_pose) });
+        let file = RouteFile { name: self.name.clone(), recorded: chrono::Local::now().to_rfc3339(), samples: self.samples };
+        let distance = length(&file.samples);
+        let text = serde_json::to_string_pretty(&file).map_err(|e| e.to_string())?;
+        let written = self.path.parent().map_or(Ok(()), fs::create_dir_all).and_then(|()| fs::write(&self.path, text));
+        written.map_err(|e| format!("write {}: {e}", self.path.display()))?;
+        info!("route {} saved to {}: {} samples, {t:.1} s, {distance:.2} m", self.name, self.path.display(), file.samples.len());
+        Ok(Event::RouteRecorded { name: self.name, samples: file.samples.len(), duration_s: t, distance_m: distance })
+    }
+}
+
+/// Distance along the recorded poses.
+fn length(samples: &[Sample]) -> f64 {
+    let poses: Vec<[f64; 3]> = samples.iter().filter_map(|s| s.pose).collect();
+    poses.windows(2).map(|w| (w[1][0] - w[0][0]).hypot(w[1][1] - w[0][1])).sum()
+}
+
+/// Stop recording and save the route.
+pub fn stop() -> Result<Value, String> {
+    let rec = recording().take().ok_or("no route is being recorded")?;
+    let path = rec.path.clone();
+    let event = rec.save(pose::current())?;
+    PENDING.lock().unwrap_or_else(|e| e.into_inner()).push(event.clone());
+    Ok(json!({ "route": event, "path": path }))
+}
+
+/// Routes in `behavior.routes.dir`.
+pub fn list(cfg: &BehaviorConfig) -> Vec<String> {
+    let Ok(entries) = fs::read_dir(cfg.routes().dir()) else { return Vec::new() };
+    let mut names: Vec<String> = entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.extension().is_some_and(|x| x == "json"))
+        .filter_map(|p| p.file_stem().map(|s| s.to_string_lossy().into_owned()))
+        .collect();
+    names.sort();
+    names
+}
+
+/// Sample the frame into the route being recorded; events to publish.
+pub(super) fn update(pose: Option<Pose>, now: Instant) -> Vec<Event> {
+    if let Some(rec) = recording().as_mut() {
+        rec.sample(pose, now);
+    }
+    std::mem::take(&mut *PENDING.lock().unwrap_or_else(|e| e.into_inner()))
+}
+
+/// A recording in progress is saved when the session ends.
+pub(super) fn end() {
+    let Some(rec) = recording().take() else { return };
+    if let Err(e) = rec.save(pose::current()) {
+        warn!("route not saved: {e}");
+    }
+}
+
+fn load(path: &Path) -> Result<RouteFile, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("read {}: {e}", path.display()))?;
+    let file: RouteFile = serde_json::from_str(&text).map_err(|e| format!("{}: {e}", path.display()))?;
+    if file.samples.is_empty() {
+        return Err(format!("{}: the route is empty", path.display()));
+    }
+    Ok(file)
+}
+
+enum Playback {
+    /// Next sample to apply
+    Velocity { index: usize, since: Option<Instant> },
+    /// Built on the first frame, from where the robot is
+    Poses { follow: Option<Waypoints> },
+}
+
+pub struct Route {
+    file: RouteFile,
+    cfg: BehaviorConfig,
+    playback: Playback,
+    wheels: Wheels,
+}
+
+impl Route {
+    /// `args`: the route's name, then optionally `velocity` or `poses`.
+    pub fn load(args: &[&str], cfg: BehaviorConfig) -> Result<Self, String> {
+        let (name, mode) = match args {
+            [name] => (name, cfg.routes().mode()),
+            [name, "velocity"] => (name, RouteMode::Velocity),
+            [name, "poses"] => (name, RouteMode::Poses),
+            _ => return Err("usage: route <name> [velocity|poses]".into()),
+        };
+        let file = load(&path(name, &cfg)?)?;
+        let poses = file.samples.iter().filter(|s| s.pose.is_some()).count();
+        let playback = match mode {
+            RouteMode::Poses if poses >= 2 => Playback::Poses { follow: None },
+            RouteMode::Poses => return Err(format!("route {name} has no poses to follow (recorded without pose.enabled); play it with velocity")),
+            RouteMode::Velocity => Playback::Velocity { index: 0, since: None },
+        };
+        Ok(Route { file, cfg, playback, wheels: Wheels::STOP })
+    }
+
+    /// The recorded poses as waypoints from `start`; only the last has to
+    /// be reached precisely, the others are passed through.
+    fn waypoints(&self, start: &Pose) -> Result<Waypoints, String> {
+        let wcfg = self.cfg.waypoints();
+        let spacing = self.cfg.routes().spacing_m();
+        let (sin, cos) = start.theta.sin_cos();
+        let poses: Vec<[f64; 3]> = self.file.samples.iter().filter_map(|s| s.pose).collect();
+        let mut points: Vec<Waypoint> = poses
+            .iter()
+            .skip(1)
+            .map(|[x, y, _]| Waypoint {
+                x: start.x + x * cos - y * sin,
+                y: start.y + x * sin + y * cos,
+                tolerance: (spacing / 2.0).max(wcfg.tolerance_m()),
+                timeout: wcfg.timeout(),
+            })
+            .collect();
+        if let Some(last) = points.last_mut() {
+            last.tolerance = wcfg.tolerance_m();
+        }
+        Waypoints::new(points, wcfg)
+    }
+}
+
+impl Behavior for Route {
+    fn name(&self) -> &'static str {
+        "route"
+    }
+
+    fn step(&mut self, ctx: &mut Context) -> Status {
+        let start = ctx.pose;
+        let built = match &self.playback {
+            Playback::Poses { follow: None } => {
+                let Some(start) = start else { return Status::Failed("no pose estimate (pose.enabled is off)".into()) };
+                Some(self.waypoints(&start))
+            }
+            _ => None,
+        };
+        match &mut self.playback {
+            Playback::Velocity { index, since } => {
+                let forward = self.wheels.left as i32 + self.wheels.right as i32 > 0;
+                if let Some(reason) = primitive::obstacle(ctx, forward) {
+                    return Status::Failed(reason);
+                }
+                let t = ctx.now.duration_since(*since.get_or_insert(ctx.now)).as_secs_f64();
+                while let Some(s) = self.file.samples.get(*index).filter(|s| s.t <= t) {
+                    self.wheels = Wheels::new(s.left as i32, s.right as i32);
+                    *index += 1;
+                }
+                if *index >= self.file.samples.len() {
+                    return Status::Done;
+                }
+                Status::Running(self.wheels)
+            }
+            Playback::Poses { follow } => {
+                if let Some(built) = built {
+                    match built {
+                        Ok(w) => *follow = Some(w),
+                        Err(e) => return Status::Failed(e),
+                    }
+                }
+                match follow.as_mut() {
+                    Some(w) => w.step(ctx),
+                    None => Status::Done,
+                }
+            }
+        }
+    }
+
+    fn progress(&self) -> Value {
+        let detail = match &self.playback {
+            Playback::Velocity { index, since } => json!({
+                "mode": "velocity",
+                "sample": index,
+                "of": self.file.samples.len(),
+                "elapsed_s": since.map(|s| s.elapsed().as_secs_f64()),
+                "duration_s": self.file.samples.last().map(|s| s.t)
+            }),
+            Playback::Poses { follow } => json!({ "mode": "poses", "follow": follow.as_ref().map(|w| w.progress()) }),
+        };
+        json!({ "route": self.file.name, "playback": detail })
+    }
+}
+
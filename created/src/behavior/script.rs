@@ -0,0 +1,362 @@
+//! Behaviors written in [Rhai](https://rhai.rs) scripts, loaded from
+//! `behavior.scripts_dir` and reloaded whenever the file changes, without
+//! restarting the daemon. A script defines `fn step(s)`, called after every
+//! sensor frame with the sensor state, and answers through command
+//! functions: `drive`, `arc`, `stop`, `song`, `leds`, `oi`, `done`, and `fail`, or the
+//! motion primitives `drive_distance`, `turn`, `arc_angle`, and `go_to`, which run
+//! over the following frames while `step` keeps being called. Its `this`
+//! is a map that keeps its value from step to step, and across reloads.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use rhai::{Array, CallFnOptions, Dynamic, Engine, EvalAltResult, Map, Scope, AST};
+use serde_json::{json, Value};
+use tracing::{info, warn};
+
+use super::{primitive, Behavior, Context, Status};
+use crate::bus::Event;
+use crate::config::BehaviorConfig;
+use crate::motion::{self, Wheels};
+use crate::{command, leds, oi, pose, safety};
+
+/// How often the file is checked for changes.
+const RELOAD_CHECK: Duration = Duration::from_secs(1);
+/// Operations allowed per call, so a runaway loop can't stall the session.
+const MAX_OPERATIONS: u64 = 200_000;
+
+/// What the command functions asked for during one step.
+#[derive(Default)]
+struct Output {
+    wheels: Option<Wheels>,
+    primitive: Option<Box<dyn Behavior>>,
+    end: Option<Result<(), String>>,
+    /// The OI mode at this step, for checking `oi` commands
+    mode: Option<i32>,
+}
+
+/// The last motion primitive a script started.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Motion {
+    Idle,
+    Running,
+    Done,
+    Failed,
+}
+
+impl Motion {
+    fn as_str(self) -> &'static str {
+        match self {
+            Motion::Idle => "idle",
+            Motion::Running => "running",
+            Motion::Done => "done",
+            Motion::Failed => "failed",
+        }
+    }
+}
+
+pub struct Script {
+    name: String,
+    path: PathBuf,
+    engine: Engine,
+    ast: AST,
+    modified: Option<SystemTime>,
+    checked: Instant,
+    this: Dynamic,
+    out: Arc<Mutex<Output>>,
+    /// Requested speeds, held until the script changes them
+    wheels: Wheels,
+    primitive: Option<Box<dyn Behavior>>,
+    motion: Motion,
+    /// Why the last primitive failed
+    motion_error: Option<String>,
+    started: Option<Instant>,
+    reloads: u64,
+    /// Why the last reload failed; the previous version keeps running
+    reload_error: Option<String>,
+}
+
+fn number(value: &Dynamic) -> Result<f64, Box<EvalAltResult>> {
+    value.as_float().or_else(|_| value.as_int().map(|i| i as f64)).map_err(|t| format!("expected a number, got {t}").into())
+}
+
+/// The engine with the command functions, writing to `out`.
+fn engine(name: &str, out: &Arc<Mutex<Output>>, cfg: &BehaviorConfig) -> Engine {
+    let mut engine = Engine::new();
+    engine.set_max_operations(MAX_OPERATIONS);
+    let o = out.clone();
+    engine.register_fn("drive", move |left: Dynamic, right: Dynamic| -> Result<(), Box<EvalAltResult>> {
+        let wheels = Wheels::new(number(&left)? as i32, number(&right)? as i32);
+        o.lock().unwrap_or_else(|e| e.into_inner()).wheels = Some(wheels);
+        Ok(())
+    });
+    let o = out.clone();
+    engine.register_fn("arc", move |speed: Dynamic, turn_rate: Dynamic| -> Result<(), Box<EvalAltResult>> {
+        let wheels = Wheels::arc(number(&speed)?, number(&turn_rate)?.to_radians(), pose::wheel_base());
+        o.lock().unwrap_or_else(|e| e.into_inner()).wheels = Some(wheels);
+        Ok(())
+    });
+    let o = out.clone();
+    engine.register_fn("stop", move || {
+        o.lock().unwrap_or_else(|e| e.into_inner()).wheels = Some(Wheels::STOP);
+    });
+    let (o, c) = (out.clone(), cfg.clone());
+    let drive_distance = move |distance: f64, speed: Option<f64>| {
+        o.lock().unwrap_or_else(|e| e.into_inner()).primitive = Some(Box::new(primitive::Drive::new(distance, speed, &c)));
+    };
+    let f = drive_distance.clone();
+    engine.register_fn("drive_distance", move |distance: Dynamic| -> Result<(), Box<EvalAltResult>> {
+        f(number(&distance)?, None);
+        Ok(())
+    });
+    engine.register_fn("drive_distance", move |distance: Dynamic, speed: Dynamic| -> Result<(), Box<EvalAltResult>> {
+        drive_distance(number(&distance)?, Some(number(&speed)?));
+        Ok(())
+    });
+    let (o, c) = (out.clone(), cfg.clone());
+    let turn = move |angle: f64, rate: Option<f64>| {
+        let turn = primitive::Turn::new(angle.to_radians(), rate.map(f64::to_radians), &c);
+        o.lock().unwrap_or_else(|e| e.into_inner()).primitive = Some(Box::new(turn));
+    };
+    let f = turn.clone();
+    engine.register_fn("turn", move |angle: Dynamic| -> Result<(), Box<EvalAltResult>> {
+        f(number(&angle)?, None);
+        Ok(())
+    });
+    engine.register_fn("turn", move |angle: Dynamic, rate: Dynamic| -> Result<(), Box<EvalAltResult>> {
+        turn(number(&angle)?, Some(number(&rate)?));
+        Ok(())
+    });
+    let (o, c) = (out.clone(), cfg.clone());
+    let arc_angle = move |radius: f64, angle: f64, speed: Option<f64>| -> Result<(), Box<EvalAltResult>> {
+        let arc = primitive::Arc::new(radius, angle.to_radians(), speed, &c)?;
+        o.lock().unwrap_or_else(|e| e.into_inner()).primitive = Some(Box::new(arc));
+        Ok(())
+    };
+    let f = arc_angle.clone();
+    engine.register_fn("arc_angle", move |radius: Dynamic, angle: Dynamic| f(number(&radius)?, number(&angle)?, None));
+    engine.register_fn("arc_angle", move |radius: Dynamic, angle: Dynamic, speed: Dynamic| {
+        arc_angle(number(&radius)?, number(&angle)?, Some(number(&speed)?))
+    });
+    let (o, c) = (out.clone(), cfg.clone());
+    let go_to = move |x: f64, y: f64, theta: Option<f64>| -> Result<(), Box<EvalAltResult>> {
+        let go = primitive::GoTo::new(x, y, theta.map(f64::to_radians), None, &c)?;
+        o.lock().unwrap_or_else(|e| e.into_inner()).primitive = Some(Box::new(go));
+        Ok(())
+    };
+    let f = go_to.clone();
+    engine.register_fn("go_to", move |x: Dynamic, y: Dynamic| f(number(&x)?, number(&y)?, None));
+    engine.register_fn("go_to", move |x: Dynamic, y: Dynamic, theta: Dynamic| go_to(number(&x)?, number(&y)?, Some(number(&theta)?)));
+    let o = out.clone();
+    engine.register_fn("done", move || {
+        o.lock().unwrap_or_else(|e| e.into_inner()).end = Some(Ok(()));
+    });
+    let o = out.clone();
+    engine.register_fn("fail", move |reason: &str| {
+        o.lock().unwrap_or_else(|e| e.into_inner()).end = Some(Err(reason.to_string()));
+    });
+    engine.register_fn("song", |number: i64| -> Result<(), Box<EvalAltResult>> {
+        let number = u8::try_from(number).ok().filter(|n| *n <= 15).ok_or("song numbers are 0-15")?;
+        motion::queue(vec![oi::PLAY, number]);
+        Ok(())
+    });
+    let o = out.clone();
+    engine.register_fn("oi", move |bytes: Array| -> Result<(), Box<EvalAltResult>> {
+        let values = bytes.iter().map(|b| b.as_int().map_err(|t| format!("oi: expected bytes, got {t}"))).collect::<Result<Vec<i64>, _>>()?;
+        let mode = o.lock().unwrap_or_else(|e| e.into_inner()).mode;
+        let commands = command::bytes(&values).and_then(|program| command::validate(&program, mode));
+        commands.map_err(|problems| format!("oi: {}", command::describe(&problems)))?.into_iter().for_each(motion::queue);
+        Ok(())
+    });
+    engine.register_fn("mode", |mode: &str| -> Result<(), Box<EvalAltResult>> {
+        safety::set_mode(mode)?;
+        Ok(())
+    });
+    engine.register_fn("leds", |pattern: &str| -> Result<(), Box<EvalAltResult>> {
+        leds::play(pattern)?;
+        Ok(())
+    });
+    engine.register_fn("leds_stop", || {
+        let _ = leds::stop();
+    });
+    let script = name.to_string();
+    engine.on_print(move |text| info!(script, "{text}"));
+    let script = name.to_string();
+    engine.on_debug(move |text, _, _| info!(script, "{text}"));
+    engine
+}
+
+/// `name` as given, or `<name>.rhai` in `behavior.scripts_dir` if it is a
+/// bare name.
+fn locate(name: &str, dir: &Path) -> Result<PathBuf, String> {
+    let path = Path::new(name);
+    if path.components().count() > 1 || path.exists() {
+        return Ok(path.to_path_buf());
+    }
+    [format!("{name}.rhai"), name.to_string()]
+        .iter()
+        .map(|file| dir.join(file))
+        .find(|p| p.is_file())
+        .ok_or_else(|| format!("no script {name:?} in {}", dir.display()))
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+impl Script {
+    /// `args`: the script file, or its name in `behavior.scripts_dir`.
+    pub fn load(args: &[&str], cfg: &BehaviorConfig) -> Result<Self, String> {
+        let [name] = args else { return Err("usage: script <name>".into()) };
+        let path = locate(name, &cfg.scripts_dir())?;
+        let name = path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+        let out = Arc::new(Mutex::new(Output::default()));
+        let engine = engine(&name, &out, cfg);
+        let modified = modified(&path);
+        let ast = compile(&engine, &path)?;
+        Ok(Script {
+            name,
+            path,
+            engine,
+            ast,
+            modified,
+            checked: Instant::now(),
+            this: Dynamic::from_map(Map::new()),
+            out,
+            wheels: Wheels::STOP,
+            primitive: None,
+            motion: Motion::Idle,
+            motion_error: None,
+            started: None,
+            reloads: 0,
+            reload_error: None,
+        })
+    }
+
+    /// Pick up changes to the file; a version that doesn't compile is
+    /// reported and the running one kept.
+    fn reload(&mut self, now: Instant) {
+        if now.duration_since(self.checked) < RELOAD_CHECK {
+            return;
+        }
+        self.checked = now;
+        let modified = modified(&self.path);
+        if modified == self.modified {
+            return;
+        }
+        self.modified = modified;
+        match compile(&self.engine, &self.path) {
+            Ok(ast) => {
+                info!("reloaded script {}", self.path.display());
+                self.ast = ast;
+                self.reloads += 1;
+                self.reload_error = None;
+            }
+            Err(e) => {
+                warn!("keeping the running version of script {}: {e}", self.name);
+                self.reload_error = Some(e);
+            }
+        }
+    }
+}
+
+/// Compile and run the top level of the script, which must define `step(s)`.
+fn compile(engine: &Engine, path: &Path) -> Result<AST, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("read {}: {e}", path.display()))?;
+    let ast = engine.compile(&text).map_err(|e| format!("{}: {e}", path.display()))?;
+    if !ast.iter_functions().any(|f| f.name == "step" && f.params.len() == 1) {
+        return Err(format!("{}: no fn step(s)", path.display()));
+    }
+    engine.run_ast_with_scope(&mut Scope::new(), &ast).map_err(|e| format!("{}: {e}", path.display()))?;
+    Ok(ast)
+}
+
+/// The argument to `step`: packets by name, plus `pose` (x, y in m, theta
+/// in rad, and confidence 0-1, or `()`), `events` (maps with a `type`), `time` (s since the
+/// script started), `motion` (the last primitive: "idle", "running",
+/// "done", or "failed"), and `motion_error` (why it failed, or `()`).
+fn sensors(ctx: &Context, elapsed: Duration, motion: Motion, motion_error: Option<&str>) -> Map {
+    let mut s = Map::new();
+    for (packet, value) in ctx.frame.iter() {
+        s.insert(packet.name.into(), Dynamic::from_int(value as i64));
+    }
+    let pose = ctx.pose.map(|p| {
+        let mut m = Map::new();
+        m.insert("x".into(), p.x.into());
+        m.insert("y".into(), p.y.into());
+        m.insert("theta".into(), p.theta.into());
+        m.insert("confidence".into(), p.confidence.into());
+        Dynamic::from_map(m)
+    });
+    s.insert("pose".into(), pose.unwrap_or(Dynamic::UNIT));
+    let events: Array = ctx.events.iter().filter_map(|e| rhai::serde::to_dynamic(e).ok()).collect();
+    s.insert("events".into(), events.into());
+    s.insert("time".into(), elapsed.as_secs_f64().into());
+    s.insert("motion".into(), motion.as_str().into());
+    s.insert("motion_error".into(), motion_error.map_or(Dynamic::UNIT, |e| e.into()));
+    s
+}
+
+impl Behavior for Script {
+    fn name(&self) -> &'static str {
+        "script"
+    }
+
+    fn step(&mut self, ctx: &mut Context) -> Status {
+        self.reload(ctx.now);
+        let started = *self.started.get_or_insert(ctx.now);
+        let s = sensors(ctx, ctx.now.duration_since(started), self.motion, self.motion_error.as_deref());
+        *self.out.lock().unwrap_or_else(|e| e.into_inner()) = Output { mode: ctx.frame.get(oi::OI_MODE), ..Output::default() };
+        let options = CallFnOptions::new().eval_ast(false).bind_this_ptr(&mut self.this);
+        if let Err(e) = self.engine.call_fn_with_options::<Dynamic>(options, &mut Scope::new(), &self.ast, "step", (s,)) {
+            ctx.emit.push(Event::BehaviorCrashed { name: format!("script {}", self.name), error: e.to_string() });
+            return Status::Failed(format!("script {}: {e}", self.name));
+        }
+        let out = std::mem::take(&mut *self.out.lock().unwrap_or_else(|e| e.into_inner()));
+        match out.end {
+            Some(Ok(())) => return Status::Done,
+            Some(Err(reason)) => return Status::Failed(reason),
+            None => {}
+        }
+        // Wheel speeds replace a running primitive, and a new one replaces both
+        if let Some(wheels) = out.wheels {
+            self.wheels = wheels;
+            self.primitive = None;
+            if self.motion == Motion::Running {
+                self.motion = Motion::Idle;
+            }
+        }
+        if let Some(p) = out.primitive {
+            self.primitive = Some(p);
+            self.motion = Motion::Running;
+            self.motion_error = None;
+        }
+        if let Some(p) = self.primitive.as_mut() {
+            match p.step(ctx) {
+                Status::Running(wheels) => return Status::Running(wheels),
+                Status::Done => self.motion = Motion::Done,
+                Status::Failed(reason) => {
+                    self.motion = Motion::Failed;
+                    self.motion_error = Some(reason);
+                }
+            }
+            self.primitive = None;
+            self.wheels = Wheels::STOP;
+        }
+        Status::Running(self.wheels)
+    }
+
+    fn progress(&self) -> Value {
+        let state: Value = rhai::serde::from_dynamic(&self.this).unwrap_or(Value::Null);
+        json!({
+            "script": self.name,
+            "path": self.path,
+            "reloads": self.reloads,
+            "reload_error": self.reload_error,
+            "motion": self.primitive.as_ref().map(|p| json!({ "name": p.name(), "progress": p.progress() })),
+            "state": state
+        })
+    }
+}
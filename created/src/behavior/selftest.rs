@@ -0,0 +1,305 @@
+//! The self-test: a short check that the robot answers and works, run on
+//! connecting with `behavior.self_test.on_connect` or with `createctl
+//! self-test run`. One check after the other, each passes, fails, or is
+//! skipped when it can't be done (quiet mode, a packet not streamed, the
+//! robot on the dock):
+//!
+//! - firmware: the OI answered a query for its mode before the stream
+//!   started, and whether it knows the Create 2's packets (only on connect)
+//! - stream: frames arrive at the streaming rate and pass their checksums
+//! - leds: the power LED blinks (not something the sensors can confirm)
+//! - chirp: a short chirp plays, as the song-playing packet shows
+//! - left_wheel, right_wheel: each wheel alone turns a little forward, and
+//!   its encoder counts it; without packets 43 and 44 in the stream, the
+//!   session queries them. Skipped while the charging lockout holds the
+//!   robot on the dock.
+//!
+//! The report is published as a `self_test` event and kept for `createctl
+//! self-test`; a run with a failed check ends as failed.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use serde_json::{json, Value};
+use tracing::{info, warn};
+
+use super::{Behavior, Context, Status};
+use crate::bus::Event;
+use crate::config::{LedPatternConfig, SelfTestConfig};
+use crate::motion::Wheels;
+use crate::{charging_lockout, leds, pose, song};
+
+const SONG_PLAYING: u8 = 37;
+const ENCODERS: [u8; 2] = [43, 44];
+/// The robot's streaming period
+const STREAM_PERIOD: Duration = Duration::from_millis(15);
+/// Rising fifth, 1/64 s units
+const CHIRP: [song::Note; 2] = [[84, 6], [91, 10]];
+const CHIRP_WAIT: Duration = Duration::from_secs(2);
+/// Standing still between the wheels, so one's coasting isn't the other's
+const SETTLE: Duration = Duration::from_millis(300);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Verdict {
+    Pass,
+    Fail,
+    Skip,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Check {
+    pub name: String,
+    pub result: Verdict,
+    pub detail: String,
+}
+
+/// What the OI answered before the stream started, for this session.
+static FIRMWARE: Mutex<Option<Result<String, String>>> = Mutex::new(None);
+/// Stream frames that failed their checksum, all sessions
+static DISCARDED: AtomicU64 = AtomicU64::new(0);
+static REPORT: Mutex<Option<Value>> = Mutex::new(None);
+/// While a wheel check wants the encoders queried: the counts the last
+/// query answered, if it was answered.
+static QUERIED: Mutex<Option<Option<[i32; 2]>>> = Mutex::new(None);
+
+/// The session's answer to the queries before the stream, from the session.
+pub fn identified(firmware: Result<String, String>) {
+    *FIRMWARE.lock().unwrap_or_else(|e| e.into_inner()) = Some(firmware);
+}
+
+pub(super) fn end() {
+    *FIRMWARE.lock().unwrap_or_else(|e| e.into_inner()) = None;
+}
+
+/// A stream frame failed its checksum.
+pub fn discarded() {
+    DISCARDED.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Whether the session should query the encoder counts, for a wheel check.
+pub fn wants_encoders() -> bool {
+    QUERIED.lock().unwrap_or_else(|e| e.into_inner()).is_some()
+}
+
+/// The session's answer to a query for the encoder counts.
+pub fn queried(counts: Option<[i32; 2]>) {
+    let mut guard = QUERIED.lock().unwrap_or_else(|e| e.into_inner());
+    if guard.is_some() {
+        *guard = Some(counts);
+    }
+}
+
+fn querying(on: bool) {
+    *QUERIED.lock().unwrap_or_else(|e| e.into_inner()) = on.then_some(None);
+}
+
+/// The last run's report, for `createctl self-test`.
+pub fn report() -> Option<Value> {
+    REPORT.lock().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+enum Phase {
+    Stream { since: Instant, frames: u32, discarded: u64 },
+    Chirp { since: Instant },
+    /// `from` the count it started at, once an encoder has been read
+    Wheel { index: usize, since: Instant, from: Option<i32> },
+    Settle { index: usize, until: Instant },
+}
+
+pub struct SelfTest {
+    cfg: SelfTestConfig,
+    phase: Option<Phase>,
+    checks: Vec<Check>,
+}
+
+impl SelfTest {
+    pub fn new(cfg: SelfTestConfig) -> Self {
+        SelfTest { cfg, phase: None, checks: Vec::new() }
+    }
+
+    fn check(&mut self, name: &str, result: Verdict, detail: String) {
+        match result {
+            Verdict::Fail => warn!("self-test {name}: failed, {detail}"),
+            _ => info!("self-test {name}: {}, {detail}", if result == Verdict::Pass { "passed" } else { "skipped" }),
+        }
+        self.checks.push(Check { name: name.to_string(), result, detail });
+    }
+
+    fn firmware(&mut self) {
+        let firmware = FIRMWARE.lock().unwrap_or_else(|e| e.into_inner()).clone();
+        match firmware {
+            Some(Ok(detail)) => self.check("firmware", Verdict::Pass, detail),
+            Some(Err(e)) => self.check("firmware", Verdict::Fail, e),
+            None => self.check("firmware", Verdict::Skip, "only queried on connecting (behavior.self_test.on_connect)".into()),
+        }
+    }
+
+    fn leds(&mut self) {
+        let blink = LedPatternConfig { leds: Some(vec!["power".into()]), color: Some(0), ..Default::default() };
+        let spans = (0..6).map(|i| (i % 2 == 0, Duration::from_millis(150))).collect();
+        match leds::flash("self-test", &blink, spans) {
+            Ok(_) => self.check("leds", Verdict::Pass, "blinked the power LED three times".into()),
+            Err(e) => self.check("leds", Verdict::Skip, e),
+        }
+    }
+
+    /// Start the chirp; false if it can't play.
+    fn chirp(&mut self) -> bool {
+        match song::queue("self-test", CHIRP.to_vec(), true) {
+            Ok(_) => true,
+            Err(e) => {
+                self.check("chirp", Verdict::Skip, e);
+                false
+            }
+        }
+    }
+
+    /// The encoder counts that make `wheel_mm` at the rim.
+    fn counts(&self) -> i32 {
+        (self.cfg.wheel_mm() / 1000.0 / pose::metres_per_count()).round().max(1.0) as i32
+    }
+
+    /// The next wheel to turn, from `index`, or the end of the test.
+    fn wheel(&mut self, index: usize, ctx: &Context) -> Option<Phase> {
+        let name = ["left_wheel", "right_wheel"].get(index)?;
+        if charging_lockout::docked() {
+            self.check(name, Verdict::Skip, "on the dock, where the charging lockout holds the wheels".into());
+            return self.wheel(index + 1, ctx);
+        }
+        let from = ctx.frame.get(ENCODERS[index]);
+        querying(from.is_none());
+        Some(Phase::Wheel { index, since: ctx.now, from })
+    }
+
+    /// The encoder count of wheel `index`, streamed or queried.
+    fn encoder(index: usize, ctx: &Context) -> Option<i32> {
+        ctx.frame.get(ENCODERS[index]).or_else(|| QUERIED.lock().unwrap_or_else(|e| e.into_inner()).flatten().map(|c| c[index]))
+    }
+
+    fn finish(&mut self, ctx: &mut Context) -> Status {
+        querying(false);
+        let failed: Vec<&str> = self.checks.iter().filter(|c| c.result == Verdict::Fail).map(|c| c.name.as_str()).collect();
+        let passed = failed.is_empty();
+        if passed {
+            info!("self-test passed");
+        } else {
+            warn!("self-test failed: {}", failed.join(", "));
+        }
+        let status = if passed { Status::Done } else { Status::Failed(format!("failed {}", failed.join(", "))) };
+        *REPORT.lock().unwrap_or_else(|e| e.into_inner()) = Some(json!({ "passed": passed, "checks": self.checks }));
+        ctx.emit.push(Event::SelfTest { passed, checks: std::mem::take(&mut self.checks) });
+        status
+    }
+}
+
+impl Behavior for SelfTest {
+    fn name(&self) -> &'static str {
+        "self-test"
+    }
+
+    fn step(&mut self, ctx: &mut Context) -> Status {
+        let phase = match self.phase.take() {
+            None => {
+                self.firmware();
+                Phase::Stream { since: ctx.now, frames: 0, discarded: DISCARDED.load(Ordering::Relaxed) }
+            }
+            Some(Phase::Stream { since, frames, discarded }) if ctx.now.duration_since(since) < self.cfg.stream_time() => {
+                Phase::Stream { since, frames: frames + 1, discarded }
+            }
+            Some(Phase::Stream { since, frames, discarded }) => {
+                let elapsed = ctx.now.duration_since(since);
+                let bad = DISCARDED.load(Ordering::Relaxed) - discarded;
+                // Within half a period of the streaming rate, on average
+                let expected = (elapsed.as_secs_f64() / (STREAM_PERIOD.as_secs_f64() * 1.5)) as u32;
+                let result = if bad == 0 && frames >= expected { Verdict::Pass } else { Verdict::Fail };
+                self.check("stream", result, format!("{frames} frames in {:.1} s, {bad} failed their checksum", elapsed.as_secs_f64()));
+                self.leds();
+                if self.chirp() {
+                    Phase::Chirp { since: ctx.now }
+                } else {
+                    match self.wheel(0, ctx) {
+                        Some(phase) => phase,
+                        None => return self.finish(ctx),
+                    }
+                }
+            }
+            Some(Phase::Chirp { since }) => {
+                let verdict = match ctx.frame.get(SONG_PLAYING) {
+                    None => Some((Verdict::Skip, format!("queued; packet {SONG_PLAYING} isn't in sensors.stream to see it play"))),
+                    Some(1) => Some((Verdict::Pass, "played".to_string())),
+                    Some(_) if ctx.now.duration_since(since) > CHIRP_WAIT => Some((Verdict::Fail, format!("not seen playing within {:.0} s", CHIRP_WAIT.as_secs_f64()))),
+                    Some(_) => None,
+                };
+                match verdict {
+                    None => Phase::Chirp { since },
+                    Some((result, detail)) => {
+                        self.check("chirp", result, detail);
+                        match self.wheel(0, ctx) {
+                            Some(phase) => phase,
+                            None => return self.finish(ctx),
+                        }
+                    }
+                }
+            }
+            Some(Phase::Wheel { index, since, from: None }) => {
+                let name = ["left_wheel", "right_wheel"][index];
+                match Self::encoder(index, ctx) {
+                    // Timed from the first count, so the waiting isn't counted against the wheel
+                    Some(from) => Phase::Wheel { index, since: ctx.now, from: Some(from) },
+                    None if ctx.now.duration_since(since) < self.cfg.wheel_timeout() => Phase::Wheel { index, since, from: None },
+                    None => {
+                        let id = ENCODERS[index];
+                        self.check(name, Verdict::Fail, format!("packet {id} isn't in sensors.stream and a query for it wasn't answered"));
+                        Phase::Settle { index, until: ctx.now }
+                    }
+                }
+            }
+            Some(Phase::Wheel { index, since, from: Some(from) }) => {
+                let name = ["left_wheel", "right_wheel"][index];
+                let counted = Self::encoder(index, ctx).map_or(0, |now| (now.wrapping_sub(from) as u16 as i16) as i32);
+                let elapsed = ctx.now.duration_since(since);
+                let target = self.counts();
+                if counted < target && elapsed < self.cfg.wheel_timeout() {
+                    self.phase = Some(Phase::Wheel { index, since, from: Some(from) });
+                    let speed = self.cfg.wheel_speed();
+                    return Status::Running(if index == 0 { Wheels::new(speed, 0) } else { Wheels::new(0, speed) });
+                }
+                if counted >= target {
+                    self.check(name, Verdict::Pass, format!("{counted} encoder counts in {:.2} s", elapsed.as_secs_f64()));
+                } else {
+                    self.check(name, Verdict::Fail, format!("{counted} of {target} encoder counts in {:.1} s", elapsed.as_secs_f64()));
+                }
+                Phase::Settle { index, until: ctx.now + SETTLE }
+            }
+            Some(Phase::Settle { index, until }) if ctx.now < until => Phase::Settle { index, until },
+            Some(Phase::Settle { index, .. }) => match self.wheel(index + 1, ctx) {
+                Some(phase) => phase,
+                None => return self.finish(ctx),
+            },
+        };
+        self.phase = Some(phase);
+        Status::Running(Wheels::STOP)
+    }
+
+    fn progress(&self) -> Value {
+        let phase = match self.phase {
+            None | Some(Phase::Stream { .. }) => "stream",
+            Some(Phase::Chirp { .. }) => "chirp",
+            Some(Phase::Wheel { index: 0, .. }) | Some(Phase::Settle { index: 0, .. }) => "left_wheel",
+            Some(_) => "right_wheel",
+        };
+        json!({ "checking": phase, "checks": self.checks })
+    }
+}
+
+impl Drop for SelfTest {
+    fn drop(&mut self) {
+        // Stopped while checking a wheel, it wants no more queries
+        if matches!(self.phase, Some(Phase::Wheel { .. })) {
+            querying(false);
+        }
+    }
+}
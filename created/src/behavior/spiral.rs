@@ -0,0 +1,93 @@
+//! Outward spiral, the way Roombas clean an open spot: start on a tight
+//! circle and widen it by `growth_m` every turn, at a steady speed, until
+//! the spiral has covered the target area or something is in the way.
+
+use std::f64::consts::PI;
+use std::time::Instant;
+
+use serde_json::{json, Value};
+
+use super::{Behavior, Context, Status};
+use crate::bus::Event;
+use crate::config::SpiralConfig;
+use crate::motion::Wheels;
+use crate::pose;
+
+const LIGHT_BUMPER: u8 = 45;
+
+pub struct Spiral {
+    cfg: SpiralConfig,
+    area: f64,
+    /// Heading turned so far, rad
+    turned: f64,
+    last_theta: Option<f64>,
+    last: Option<Instant>,
+}
+
+impl Spiral {
+    /// `args`: optionally the area to cover, m² (default `behavior.spiral.area_m2`).
+    pub fn new(args: &[&str], cfg: SpiralConfig) -> Result<Self, String> {
+        let area = match args {
+            [] => cfg.area_m2(),
+            [area] => area.parse::<f64>().ok().filter(|a| *a > 0.0).ok_or_else(|| format!("bad area {area:?}: expected m²"))?,
+            _ => return Err("usage: spiral [area_m2]".into()),
+        };
+        Ok(Spiral { cfg, area, turned: 0.0, last_theta: None, last: None })
+    }
+
+    /// Radius of the circle the robot is on now, m.
+    fn radius(&self) -> f64 {
+        self.cfg.initial_radius_m() + self.cfg.growth_m() * self.turned / (2.0 * PI)
+    }
+
+    /// Area inside the spiral so far, m².
+    fn covered(&self) -> f64 {
+        PI * self.radius().powi(2)
+    }
+}
+
+impl Behavior for Spiral {
+    fn name(&self) -> &'static str {
+        "spiral"
+    }
+
+    fn step(&mut self, ctx: &mut Context) -> Status {
+        for event in ctx.events {
+            match event {
+                // The open spot ends here
+                Event::Bump { .. } => return Status::Done,
+                Event::Cliff { .. } => return Status::Failed("cliff".into()),
+                Event::WheelDrop { .. } => return Status::Failed("wheel drop".into()),
+                _ => {}
+            }
+        }
+        if self.cfg.light_bumps() && ctx.frame.get(LIGHT_BUMPER).unwrap_or(0) != 0 {
+            return Status::Done;
+        }
+        // Measured with the pose estimate, else integrated from the turn rate asked for
+        let speed = self.cfg.speed();
+        match ctx.pose {
+            Some(p) => {
+                if let Some(last) = self.last_theta {
+                    self.turned += pose::normalize(p.theta - last).abs();
+                }
+                self.last_theta = Some(p.theta);
+            }
+            None => {
+                if let Some(last) = self.last {
+                    self.turned += ctx.now.duration_since(last).as_secs_f64() * speed / 1000.0 / self.radius();
+                }
+            }
+        }
+        self.last = Some(ctx.now);
+        if self.covered() >= self.area {
+            return Status::Done;
+        }
+        let omega = speed / 1000.0 / self.radius();
+        Status::Running(Wheels::arc(speed, omega.copysign(self.cfg.direction()), pose::wheel_base()))
+    }
+
+    fn progress(&self) -> Value {
+        json!({ "radius_m": self.radius(), "turns": self.turned / (2.0 * PI), "covered_m2": self.covered(), "area_m2": self.area })
+    }
+}
@@ -0,0 +1,367 @@
+//! Behavior trees, described in TOML or YAML files and built from the
+//! mission actions (drive, turn, arc, stop, wait, song, leds, dock, or any
+//! behavior), conditions on the sensor state, and the usual composites and
+//! decorators. The tree is ticked after every sensor frame: a node
+//! succeeds, fails, or keeps running with the wheel speeds it wants.
+//!
+//! `sequence` and `selector` resume at the child that was running;
+//! `reactive_sequence` and `reactive_selector` start from the first child on
+//! every tick, so a condition ahead of a running action is checked each
+//! frame, and halt the running child when an earlier one decides the tick.
+
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use super::mission::{self, Action};
+use super::{Behavior, Context, Status};
+use crate::config::BehaviorConfig;
+use crate::motion::Wheels;
+use crate::oi;
+
+const BUMPS_WHEELDROPS: u8 = 7;
+const CLIFFS: [u8; 4] = [9, 10, 11, 12];
+const CHARGING_SOURCES: u8 = 34;
+const HOME_BASE: i32 = 0b10;
+const BATTERY_CHARGE: u8 = 25;
+const BATTERY_CAPACITY: u8 = 26;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Spec {
+    /// Children in order until one fails
+    Sequence { children: Vec<Spec> },
+    /// Children in order until one succeeds
+    Selector { children: Vec<Spec> },
+    ReactiveSequence { children: Vec<Spec> },
+    ReactiveSelector { children: Vec<Spec> },
+    /// Swap success and failure
+    Invert { child: Box<Spec> },
+    /// Succeed even if the child fails
+    Succeed { child: Box<Spec> },
+    /// Run the child again after each success, `times` in all (default: forever)
+    Repeat { child: Box<Spec>, times: Option<u32> },
+    /// Run the child again after a failure, up to `times` more times
+    Retry { child: Box<Spec>, times: u32 },
+    /// Fail the child after `seconds`
+    Timeout { child: Box<Spec>, seconds: f64 },
+    /// A bumper is pressed
+    Bumped,
+    /// A cliff sensor sees a drop
+    Cliff,
+    /// A wheel is dropped
+    WheelDrop,
+    /// On the home base
+    Docked,
+    BatteryBelow { percent: f64 },
+    /// Packet `name` is above and/or below the given values
+    Sensor { name: String, above: Option<f64>, below: Option<f64> },
+    /// Stand still; never finishes
+    Idle,
+    #[serde(untagged)]
+    Action(Action),
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TreeFile {
+    pub name: Option<String>,
+    pub root: Spec,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Condition {
+    Bumped,
+    Cliff,
+    WheelDrop,
+    Docked,
+    BatteryBelow(f64),
+    Sensor { id: u8, above: Option<f64>, below: Option<f64> },
+}
+
+impl Condition {
+    fn holds(&self, ctx: &Context) -> bool {
+        let frame = ctx.frame;
+        let bits = |mask: i32| frame.get(BUMPS_WHEELDROPS).is_some_and(|b| b & mask != 0);
+        match *self {
+            Condition::Bumped => bits(0b11),
+            Condition::WheelDrop => bits(0b1100),
+            Condition::Cliff => CLIFFS.iter().any(|id| frame.get(*id).is_some_and(|v| v != 0)),
+            Condition::Docked => frame.get(CHARGING_SOURCES).is_some_and(|s| s & HOME_BASE != 0),
+            Condition::BatteryBelow(percent) => match (frame.get(BATTERY_CHARGE), frame.get(BATTERY_CAPACITY)) {
+                (Some(charge), Some(capacity)) if capacity > 0 => (charge as f64 * 100.0 / capacity as f64) < percent,
+                _ => false,
+            },
+            Condition::Sensor { id, above, below } => frame.get(id).is_some_and(|v| {
+                let v = v as f64;
+                above.is_none_or(|a| v > a) && below.is_none_or(|b| v < b)
+            }),
+        }
+    }
+}
+
+enum Tick {
+    Success,
+    Failure(String),
+    Running(Wheels),
+}
+
+enum Node {
+    Sequence { children: Vec<Node>, current: usize, reactive: bool },
+    Selector { children: Vec<Node>, current: usize, reactive: bool },
+    Invert(Box<Node>),
+    Succeed(Box<Node>),
+    Repeat { child: Box<Node>, times: Option<u32>, count: u32 },
+    Retry { child: Box<Node>, times: u32, failures: u32 },
+    Timeout { child: Box<Node>, limit: Duration, since: Option<Instant> },
+    Condition(Condition),
+    Idle,
+    Action { action: Action, running: Option<Box<dyn Behavior>> },
+}
+
+impl Node {
+    /// Check the spec and build its nodes.
+    fn new(spec: &Spec) -> Result<Node, String> {
+        let children = |specs: &[Spec], kind: &str| -> Result<Vec<Node>, String> {
+            if specs.is_empty() {
+                return Err(format!("a {kind} needs children"));
+            }
+            specs.iter().map(Node::new).collect()
+        };
+        let child = |spec: &Spec| Node::new(spec).map(Box::new);
+        Ok(match spec {
+            Spec::Sequence { children: c } => Node::Sequence { children: children(c, "sequence")?, current: 0, reactive: false },
+            Spec::Selector { children: c } => Node::Selector { children: children(c, "selector")?, current: 0, reactive: false },
+            Spec::ReactiveSequence { children: c } => Node::Sequence { children: children(c, "reactive_sequence")?, current: 0, reactive: true },
+            Spec::ReactiveSelector { children: c } => Node::Selector { children: children(c, "reactive_selector")?, current: 0, reactive: true },
+            Spec::Invert { child: c } => Node::Invert(child(c)?),
+            Spec::Succeed { child: c } => Node::Succeed(child(c)?),
+            Spec::Repeat { child: c, times } => Node::Repeat { child: child(c)?, times: *times, count: 0 },
+            Spec::Retry { child: c, times } => Node::Retry { child: child(c)?, times: *times, failures: 0 },
+            Spec::Timeout { child: c, seconds } => {
+                Node::Timeout { child: child(c)?, limit: Duration::from_secs_f64(seconds.max(0.0)), since: None }
+            }
+            Spec::Bumped => Node::Condition(Condition::Bumped),
+            Spec::Cliff => Node::Condition(Condition::Cliff),
+            Spec::WheelDrop => Node::Condition(Condition::WheelDrop),
+            Spec::Docked => Node::Condition(Condition::Docked),
+            Spec::BatteryBelow { percent } => Node::Condition(Condition::BatteryBelow(*percent)),
+            Spec::Sensor { name, above, below } => {
+                let id = oi::packet_by_name(name).ok_or_else(|| format!("unknown sensor {name:?}"))?.id;
+                Node::Condition(Condition::Sensor { id, above: *above, below: *below })
+            }
+            Spec::Idle => Node::Idle,
+            Spec::Action(action) => {
+                action.check("tree")?;
+                Node::Action { action: action.clone(), running: None }
+            }
+        })
+    }
+
+    /// Back to the state it was built in, stopping running actions.
+    fn halt(&mut self) {
+        match self {
+            Node::Sequence { children, current, .. } | Node::Selector { children, current, .. } => {
+                children.iter_mut().for_each(Node::halt);
+                *current = 0;
+            }
+            Node::Invert(child) | Node::Succeed(child) => child.halt(),
+            Node::Repeat { child, count, .. } => {
+                child.halt();
+                *count = 0;
+            }
+            Node::Retry { child, failures, .. } => {
+                child.halt();
+                *failures = 0;
+            }
+            Node::Timeout { child, since, .. } => {
+                child.halt();
+                *since = None;
+            }
+            Node::Action { running, .. } => *running = None,
+            Node::Condition(_) | Node::Idle => {}
+        }
+    }
+
+    fn tick(&mut self, ctx: &mut Context, cfg: &BehaviorConfig) -> Tick {
+        match self {
+            Node::Sequence { children, current, reactive } => composite(children, current, *reactive, true, ctx, cfg),
+            Node::Selector { children, current, reactive } => composite(children, current, *reactive, false, ctx, cfg),
+            Node::Invert(child) => match child.tick(ctx, cfg) {
+                Tick::Success => Tick::Failure("inverted success".into()),
+                Tick::Failure(_) => Tick::Success,
+                running => running,
+            },
+            Node::Succeed(child) => match child.tick(ctx, cfg) {
+                Tick::Failure(_) => Tick::Success,
+                other => other,
+            },
+            Node::Repeat { child, times, count } => {
+                // At most one new run per frame, so a child that succeeds at
+                // once can't spin
+                for _ in 0..2 {
+                    match child.tick(ctx, cfg) {
+                        Tick::Success => {
+                            *count += 1;
+                            child.halt();
+                            if times.is_some_and(|n| *count >= n) {
+                                *count = 0;
+                                return Tick::Success;
+                            }
+                        }
+                        Tick::Failure(e) => {
+                            *count = 0;
+                            return Tick::Failure(e);
+                        }
+                        running => return running,
+                    }
+                }
+                Tick::Running(Wheels::STOP)
+            }
+            Node::Retry { child, times, failures } => {
+                for _ in 0..2 {
+                    match child.tick(ctx, cfg) {
+                        Tick::Failure(e) => {
+                            *failures += 1;
+                            child.halt();
+                            if *failures > *times {
+                                *failures = 0;
+                                return Tick::Failure(e);
+                            }
+                        }
+                        Tick::Success => {
+                            *failures = 0;
+                            return Tick::Success;
+                        }
+                        running => return running,
+                    }
+                }
+                Tick::Running(Wheels::STOP)
+            }
+            Node::Timeout { child, limit, since } => {
+                let started = *since.get_or_insert(ctx.now);
+                if ctx.now.duration_since(started) > *limit {
+                    child.halt();
+                    *since = None;
+                    return Tick::Failure(format!("timed out after {limit:?}"));
+                }
+                let tick = child.tick(ctx, cfg);
+                if !matches!(tick, Tick::Running(_)) {
+                    *since = None;
+                }
+                tick
+            }
+            Node::Condition(condition) => match condition.holds(ctx) {
+                true => Tick::Success,
+                false => Tick::Failure(format!("{condition:?} is false")),
+            },
+            Node::Idle => Tick::Running(Wheels::STOP),
+            Node::Action { action, running } => {
+                let behavior = match running {
+                    Some(b) => b,
+                    None => match action.build(cfg) {
+                        Ok(b) => running.insert(b),
+                        Err(e) => return Tick::Failure(e),
+                    },
+                };
+                match behavior.step(ctx) {
+                    Status::Running(wheels) => Tick::Running(wheels),
+                    Status::Done => {
+                        *running = None;
+                        Tick::Success
+                    }
+                    Status::Failed(e) => {
+                        *running = None;
+                        Tick::Failure(format!("{}: {e}", action.kind()))
+                    }
+                }
+            }
+        }
+    }
+
+    /// Names of the actions running under this node.
+    fn active(&self, out: &mut Vec<&'static str>) {
+        match self {
+            Node::Sequence { children, .. } | Node::Selector { children, .. } => children.iter().for_each(|c| c.active(out)),
+            Node::Invert(child) | Node::Succeed(child) => child.active(out),
+            Node::Repeat { child, .. } | Node::Retry { child, .. } | Node::Timeout { child, .. } => child.active(out),
+            Node::Action { running: Some(b), .. } => out.push(b.name()),
+            _ => {}
+        }
+    }
+}
+
+/// Tick a sequence (`sequence`: go on while children succeed) or a selector
+/// (go on while they fail).
+fn composite(children: &mut [Node], current: &mut usize, reactive: bool, sequence: bool, ctx: &mut Context, cfg: &BehaviorConfig) -> Tick {
+    let from = if reactive { 0 } else { *current };
+    let mut reasons = Vec::new();
+    for i in from..children.len() {
+        let tick = children[i].tick(ctx, cfg);
+        let decided = match tick {
+            Tick::Running(_) => true,
+            Tick::Success => !sequence,
+            Tick::Failure(_) => sequence,
+        };
+        if !decided {
+            if let Tick::Failure(e) = tick {
+                reasons.push(e);
+            }
+            continue;
+        }
+        // Whatever ran further along last time is no longer wanted
+        for later in children.iter_mut().skip(i + 1) {
+            later.halt();
+        }
+        if let Tick::Running(_) = tick {
+            *current = i;
+        } else {
+            *current = 0;
+        }
+        return tick;
+    }
+    *current = 0;
+    if sequence {
+        Tick::Success
+    } else {
+        Tick::Failure(reasons.pop().unwrap_or_else(|| "every child failed".into()))
+    }
+}
+
+pub struct Tree {
+    name: String,
+    root: Node,
+    cfg: BehaviorConfig,
+}
+
+impl Tree {
+    /// `args`: the tree file, or its name in `behavior.trees_dir`.
+    pub fn load(args: &[&str], cfg: BehaviorConfig) -> Result<Self, String> {
+        let [name] = args else { return Err("usage: tree <file>".into()) };
+        let path = mission::locate(name, &cfg.trees_dir(), "tree")?;
+        let file: TreeFile = mission::read(&path)?;
+        let root = Node::new(&file.root).map_err(|e| format!("{}: {e}", path.display()))?;
+        let name = file.name.unwrap_or_else(|| path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default());
+        Ok(Tree { name, root, cfg })
+    }
+}
+
+impl Behavior for Tree {
+    fn name(&self) -> &'static str {
+        "tree"
+    }
+
+    fn step(&mut self, ctx: &mut Context) -> Status {
+        match self.root.tick(ctx, &self.cfg) {
+            Tick::Running(wheels) => Status::Running(wheels),
+            Tick::Success => Status::Done,
+            Tick::Failure(e) => Status::Failed(e),
+        }
+    }
+
+    fn progress(&self) -> Value {
+        let mut active = Vec::new();
+        self.root.active(&mut active);
+        json!({ "tree": self.name, "running": active })
+    }
+}
@@ -0,0 +1,47 @@
+//! Undocking: straight back off the dock's contacts, slowly, the only
+//! driving the charging lockout lets through on the dock. Done at once if
+//! the robot isn't on the dock.
+
+use serde_json::{json, Value};
+use tracing::info;
+
+use super::primitive::Drive;
+use super::{Behavior, Context, Status};
+use crate::charging_lockout;
+use crate::config::{BehaviorConfig, ChargingLockoutConfig};
+
+pub struct Undock {
+    drive: Drive,
+    distance: f64,
+    started: bool,
+}
+
+impl Undock {
+    /// `distance` in m, or `charging_lockout.undock_m`.
+    pub fn new(distance: Option<f64>, lockout: &ChargingLockoutConfig, cfg: &BehaviorConfig) -> Self {
+        let distance = distance.unwrap_or(lockout.undock_m()).abs();
+        Undock { drive: Drive::new(-distance, Some(lockout.undock_speed()), cfg), distance, started: false }
+    }
+}
+
+impl Behavior for Undock {
+    fn name(&self) -> &'static str {
+        "undock"
+    }
+
+    fn step(&mut self, ctx: &mut Context) -> Status {
+        if !self.started {
+            self.started = true;
+            if !charging_lockout::on_dock(ctx.frame) {
+                info!("undock: not on the dock");
+                return Status::Done;
+            }
+        }
+        charging_lockout::undocking();
+        self.drive.step(ctx)
+    }
+
+    fn progress(&self) -> Value {
+        json!({ "distance_m": self.distance, "step": self.drive.progress() })
+    }
+}
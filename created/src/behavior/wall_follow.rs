@@ -0,0 +1,103 @@
+//! Wall following: keep a wall on one side at a constant distance with a
+//! proportional controller on the side sensor's signal strength. On the
+//! right that is the wall signal (packet 27), or the right light bump on a
+//! Create 2 that doesn't stream it; on the left, the left light bump (Create
+//! 2 only). A bump, or a wall straight ahead on the center light bumps,
+//! turns the robot away from the wall for a moment; losing the wall makes
+//! it arc back toward it. Runs until cancelled, or a cliff or wheel drop.
+
+use std::time::Instant;
+
+use serde_json::{json, Value};
+
+use super::{Behavior, Context, Status};
+use crate::bus::Event;
+use crate::config::{Side, WallFollowConfig};
+use crate::motion::Wheels;
+use crate::pose;
+
+const WALL_SIGNAL: u8 = 27;
+const LIGHT_BUMP_LEFT: u8 = 46;
+const LIGHT_BUMP_CENTER_LEFT: u8 = 48;
+const LIGHT_BUMP_CENTER_RIGHT: u8 = 49;
+const LIGHT_BUMP_RIGHT: u8 = 51;
+
+pub struct WallFollow {
+    cfg: WallFollowConfig,
+    side: Side,
+    /// Turning away from an obstacle until then
+    turning_until: Option<Instant>,
+    signal: Option<i32>,
+}
+
+impl WallFollow {
+    /// `side` overrides `behavior.wall_follow.side`.
+    pub fn new(side: Option<&str>, cfg: WallFollowConfig) -> Result<Self, String> {
+        let side = match side {
+            None => cfg.side(),
+            Some("left") => Side::Left,
+            Some("right") => Side::Right,
+            Some(other) => return Err(format!("bad wall side {other:?}: expected left or right")),
+        };
+        Ok(WallFollow { cfg, side, turning_until: None, signal: None })
+    }
+
+    /// +1 when turning toward the wall is counter-clockwise.
+    fn toward_wall(&self) -> f64 {
+        match self.side {
+            Side::Left => 1.0,
+            Side::Right => -1.0,
+        }
+    }
+}
+
+impl Behavior for WallFollow {
+    fn name(&self) -> &'static str {
+        "wall-follow"
+    }
+
+    fn step(&mut self, ctx: &mut Context) -> Status {
+        for event in ctx.events {
+            match event {
+                Event::Cliff { .. } => return Status::Failed("cliff".into()),
+                Event::WheelDrop { .. } => return Status::Failed("wheel drop".into()),
+                Event::Bump { .. } => self.turning_until = Some(ctx.now + self.cfg.turn_away()),
+                _ => {}
+            }
+        }
+        let frame = ctx.frame;
+        let signal = match self.side {
+            Side::Right => frame.get(WALL_SIGNAL).or_else(|| frame.get(LIGHT_BUMP_RIGHT)),
+            Side::Left => frame.get(LIGHT_BUMP_LEFT),
+        };
+        let Some(signal) = signal else {
+            return Status::Failed(match self.side {
+                Side::Right => "no wall_signal or light_bump_right_signal in the sensor stream".into(),
+                Side::Left => "no light_bump_left_signal in the sensor stream (Create 2 only)".into(),
+            });
+        };
+        self.signal = Some(signal);
+        let ahead = [LIGHT_BUMP_CENTER_LEFT, LIGHT_BUMP_CENTER_RIGHT].iter().filter_map(|&id| frame.get(id)).max();
+        if ahead.is_some_and(|s| s as f64 >= self.cfg.front_signal()) {
+            self.turning_until = Some(ctx.now + self.cfg.turn_away());
+        }
+
+        let toward = self.toward_wall();
+        let (v, omega) = if self.turning_until.is_some_and(|until| ctx.now < until) {
+            (0.0, -toward * self.cfg.max_turn_rate())
+        } else if (signal as f64) < self.cfg.lost_signal() {
+            (self.cfg.speed() / 2.0, toward * self.cfg.search_turn_rate())
+        } else {
+            // Positive error: too far from the wall
+            let error = (self.cfg.target_signal() - signal as f64) / self.cfg.target_signal();
+            let omega = (toward * self.cfg.gain() * error).clamp(-self.cfg.max_turn_rate(), self.cfg.max_turn_rate());
+            (self.cfg.speed(), omega)
+        };
+        Status::Running(Wheels::arc(v, omega, pose::wheel_base()))
+    }
+
+    fn progress(&self) -> Value {
+        let turning = self.turning_until.is_some_and(|until| Instant::now() < until);
+        json!({ "side": self.side, "signal": self.signal, "turning_away": turning })
+    }
+}
@@ -0,0 +1,177 @@
+//! Bump-and-turn wander: drive forward until something is in the way (a
+//! bump, a light bump on Create 2, or a cliff), back up, turn away from it
+//! by a random angle, and carry on. Runs until cancelled or a wheel drops.
+
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde_json::{json, Value};
+
+use super::{Behavior, Context, Status};
+use crate::bus::Event;
+use crate::config::WanderConfig;
+use crate::motion::Wheels;
+use crate::pose::{self, Pose};
+
+const LIGHT_BUMPER: u8 = 45;
+/// Light bumper bits 0-2 are on the left half, 3-5 on the right.
+const LIGHT_BUMPER_LEFT: i32 = 0b000111;
+const LIGHT_BUMPER_RIGHT: i32 = 0b111000;
+/// Backing up or turning never takes longer than this, in case the pose
+/// estimate doesn't move (e.g. the wheels are blocked).
+const MANEUVER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Small xorshift generator; the angles only need to look random.
+struct Rng(u64);
+
+impl Rng {
+    fn seeded() -> Self {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or(1);
+        Rng(nanos | 1)
+    }
+
+    /// Uniform in 0..1.
+    fn next(&mut self) -> f64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Phase {
+    Forward,
+    Backing { since: Instant, from: Option<(f64, f64)> },
+    /// `turn` radians, positive counter-clockwise; `turned` so far, summed
+    /// frame by frame from the heading last seen
+    Turning { since: Instant, turn: f64, turned: f64, last: Option<f64> },
+}
+
+/// Which way to turn away from an obstacle.
+#[derive(Debug, Clone, Copy)]
+enum Away {
+    Left,
+    Right,
+    Either,
+}
+
+pub struct Wander {
+    cfg: WanderConfig,
+    rng: Rng,
+    phase: Phase,
+    away: Away,
+    obstacles: u64,
+}
+
+impl Wander {
+    pub fn new(cfg: WanderConfig) -> Self {
+        Wander { cfg, rng: Rng::seeded(), phase: Phase::Forward, away: Away::Either, obstacles: 0 }
+    }
+
+    /// The side of an obstacle in this frame, if there is one.
+    fn obstacle(&self, ctx: &Context) -> Option<Away> {
+        for event in ctx.events {
+            match event {
+                Event::Bump { left: true, right: true } => return Some(Away::Either),
+                // Turn away from the side that hit
+                Event::Bump { left: true, .. } => return Some(Away::Right),
+                Event::Bump { right: true, .. } => return Some(Away::Left),
+                Event::Cliff { sensors } => {
+                    let left = sensors.iter().any(|s| s.contains("left"));
+                    let right = sensors.iter().any(|s| s.contains("right"));
+                    return Some(match (left, right) {
+                        (true, false) => Away::Right,
+                        (false, true) => Away::Left,
+                        _ => Away::Either,
+                    });
+                }
+                _ => {}
+            }
+        }
+        let light = ctx.frame.get(LIGHT_BUMPER).filter(|_| self.cfg.light_bumps())?;
+        match (light & LIGHT_BUMPER_LEFT != 0, light & LIGHT_BUMPER_RIGHT != 0) {
+            (true, true) => Some(Away::Either),
+            (true, false) => Some(Away::Right),
+            (false, true) => Some(Away::Left),
+            (false, false) => None,
+        }
+    }
+
+    fn turn_angle(&mut self) -> f64 {
+        let (min, max) = (self.cfg.min_turn(), self.cfg.max_turn());
+        let angle = min + (max - min) * self.rng.next();
+        let left = match self.away {
+            Away::Left => true,
+            Away::Right => false,
+            Away::Either => self.rng.next() < 0.5,
+        };
+        if left {
+            angle
+        } else {
+            -angle
+        }
+    }
+}
+
+impl Behavior for Wander {
+    fn name(&self) -> &'static str {
+        "wander"
+    }
+
+    fn step(&mut self, ctx: &mut Context) -> Status {
+        if ctx.events.iter().any(|e| matches!(e, Event::WheelDrop { .. })) {
+            return Status::Failed("wheel drop".into());
+        }
+        let pose: Option<Pose> = ctx.pose;
+        if self.phase == Phase::Forward {
+            if let Some(away) = self.obstacle(ctx) {
+                self.obstacles += 1;
+                self.away = away;
+                self.phase = Phase::Backing { since: ctx.now, from: pose.map(|p| (p.x, p.y)) };
+            }
+        }
+        loop {
+            match self.phase {
+                Phase::Forward => return Status::Running(Wheels::arc(self.cfg.speed(), 0.0, pose::wheel_base())),
+                Phase::Backing { since, from } => {
+                    let elapsed = ctx.now.duration_since(since);
+                    let done = match (from, pose) {
+                        (Some((x, y)), Some(p)) => (p.x - x).hypot(p.y - y) >= self.cfg.backup_m(),
+                        // Without a pose estimate, back up for as long as it should take
+                        _ => elapsed.as_secs_f64() >= self.cfg.backup_m() * 1000.0 / self.cfg.backup_speed(),
+                    };
+                    if !done && elapsed < MANEUVER_TIMEOUT {
+                        return Status::Running(Wheels::arc(-self.cfg.backup_speed(), 0.0, pose::wheel_base()));
+                    }
+                    let turn = self.turn_angle();
+                    self.phase = Phase::Turning { since: ctx.now, turn, turned: 0.0, last: pose.map(|p| p.theta) };
+                }
+                Phase::Turning { since, turn, mut turned, last } => {
+                    let elapsed = ctx.now.duration_since(since);
+                    let done = match (last, pose) {
+                        (Some(theta), Some(p)) => {
+                            turned += pose::normalize(p.theta - theta);
+                            self.phase = Phase::Turning { since, turn, turned, last: Some(p.theta) };
+                            turned.abs() >= turn.abs()
+                        }
+                        _ => elapsed.as_secs_f64() >= turn.abs() / self.cfg.turn_rate(),
+                    };
+                    if !done && elapsed < MANEUVER_TIMEOUT {
+                        let omega = self.cfg.turn_rate().copysign(turn);
+                        return Status::Running(Wheels::arc(0.0, omega, pose::wheel_base()));
+                    }
+                    self.phase = Phase::Forward;
+                }
+            }
+        }
+    }
+
+    fn progress(&self) -> Value {
+        let phase = match self.phase {
+            Phase::Forward => "forward",
+            Phase::Backing { .. } => "backing",
+            Phase::Turning { .. } => "turning",
+        };
+        json!({ "phase": phase, "obstacles": self.obstacles })
+    }
+}
@@ -0,0 +1,132 @@
+//! Waypoint navigation: drive through a list of (x, y) targets in the pose
+//! frame. The robot turns in place toward a waypoint that is well off its
+//! heading, otherwise drives with a proportional heading correction and
+//! slows down as it closes in. Each waypoint has a tolerance and a timeout;
+//! a bump, cliff, or wheel drop on the way ends the run.
+
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+
+use super::{Behavior, Context, Status};
+use crate::bus::Event;
+use crate::config::WaypointsConfig;
+use crate::motion::Wheels;
+use crate::pose;
+
+/// Slowest approach speed, mm/s: below this the wheels barely turn.
+const MIN_SPEED: f64 = 40.0;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Waypoint {
+    /// metres
+    pub x: f64,
+    pub y: f64,
+    pub tolerance: f64,
+    pub timeout: Duration,
+}
+
+impl Waypoint {
+    /// `x,y[,tolerance_m[,timeout_s]]`, with the defaults from `cfg`.
+    pub fn parse(spec: &str, cfg: &WaypointsConfig) -> Result<Self, String> {
+        let parts: Result<Vec<f64>, _> = spec.split(',').map(|p| p.trim().parse::<f64>()).collect();
+        let bad = || format!("bad waypoint {spec:?}: expected x,y[,tolerance_m[,timeout_s]]");
+        let parts = parts.map_err(|_| bad())?;
+        let (x, y, tolerance, timeout) = match parts.as_slice() {
+            [x, y] => (*x, *y, cfg.tolerance_m(), cfg.timeout()),
+            [x, y, t] => (*x, *y, *t, cfg.timeout()),
+            [x, y, t, s] if *s > 0.0 => (*x, *y, *t, Duration::from_secs_f64(*s)),
+            _ => return Err(bad()),
+        };
+        if !(x.is_finite() && y.is_finite() && tolerance > 0.0) {
+            return Err(bad());
+        }
+        Ok(Waypoint { x, y, tolerance, timeout })
+    }
+}
+
+/// The wheel speeds for cruising at up to `speed` mm/s toward (x, y), and
+/// the distance left, or None once within `tolerance`.
+pub(super) fn steer(pose: &pose::Pose, x: f64, y: f64, tolerance: f64, speed: f64, cfg: &WaypointsConfig) -> Option<(Wheels, f64)> {
+    let (dx, dy) = (x - pose.x, y - pose.y);
+    let distance = dx.hypot(dy);
+    if distance <= tolerance {
+        return None;
+    }
+    let error = pose::normalize(dy.atan2(dx) - pose.theta);
+    let omega = (cfg.heading_gain() * error).clamp(-cfg.max_turn_rate(), cfg.max_turn_rate());
+    let v = if error.abs() > cfg.rotate_in_place() {
+        0.0
+    } else {
+        let approach = speed * (distance / cfg.slowdown_m()).min(1.0);
+        approach.max(MIN_SPEED.min(speed)) * error.cos()
+    };
+    Some((Wheels::arc(v, omega, pose::wheel_base()), distance))
+}
+
+fn blocked(event: &Event) -> Option<&'static str> {
+    match event {
+        Event::Bump { .. } => Some("bump"),
+        Event::Cliff { .. } => Some("cliff"),
+        Event::WheelDrop { .. } => Some("wheel drop"),
+        _ => None,
+    }
+}
+
+pub struct Waypoints {
+    cfg: WaypointsConfig,
+    points: Vec<Waypoint>,
+    index: usize,
+    /// When the current waypoint became the target
+    since: Option<Instant>,
+    distance: Option<f64>,
+}
+
+impl Waypoints {
+    pub fn new(points: Vec<Waypoint>, cfg: WaypointsConfig) -> Result<Self, String> {
+        if points.is_empty() {
+            return Err("no waypoints given".into());
+        }
+        Ok(Waypoints { cfg, points, index: 0, since: None, distance: None })
+    }
+}
+
+impl Behavior for Waypoints {
+    fn name(&self) -> &'static str {
+        "waypoints"
+    }
+
+    fn step(&mut self, ctx: &mut Context) -> Status {
+        let Some(pose) = ctx.pose else { return Status::Failed("no pose estimate (pose.enabled is off)".into()) };
+        if let Some(reason) = ctx.events.iter().find_map(blocked) {
+            return Status::Failed(format!("{reason} on the way to waypoint {}", self.index));
+        }
+        while let Some(wp) = self.points.get(self.index).copied() {
+            let since = *self.since.get_or_insert(ctx.now);
+            if ctx.now.duration_since(since) > wp.timeout {
+                return Status::Failed(format!(
+                    "waypoint {} ({}, {}) not reached within {:?}",
+                    self.index, wp.x, wp.y, wp.timeout
+                ));
+            }
+            match steer(&pose, wp.x, wp.y, wp.tolerance, self.cfg.speed(), &self.cfg) {
+                Some((wheels, distance)) => {
+                    self.distance = Some(distance);
+                    return Status::Running(wheels);
+                }
+                None => {
+                    ctx.emit.push(Event::WaypointReached { index: self.index, x: wp.x, y: wp.y });
+                    self.index += 1;
+                    self.since = None;
+                    self.distance = None;
+                }
+            }
+        }
+        Status::Done
+    }
+
+    fn progress(&self) -> Value {
+        let target = self.points.get(self.index).map(|wp| [wp.x, wp.y]);
+        json!({ "waypoint": self.index, "of": self.points.len(), "target": target, "distance_m": self.distance })
+    }
+}
@@ -0,0 +1,125 @@
+//! Command-line client for the created control socket: sends its arguments
+//! as one command line and prints the JSON reply.
+
+use std::env;
+use std::fs;
+#[cfg(unix)]
+use std::io::{BufRead, BufReader, Write};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::process;
+
+const DEFAULT_SOCKET: &str = "/run/created/control.sock";
+
+fn usage() -> String {
+    format!(
+        "Usage: createctl [--socket <path>] <command> [args...]\n\
+         Commands:\n  \
+           status              Connection state and session statistics\n  \
+           alias [set <name>|clear]\n                      Show robot names, or name the connected robot (by its USB\n                      adapter's serial number) or take its name away\n  \
+           release [<seconds>|status]\n                      Close the port and leave it alone for another program, until\n                      reclaimed or for that long; the session picks up after\n  \
+           reclaim             Take the released port back\n  \
+           sensors             The latest sensor packets, by name, and the OI mode\n  \
+           events [<n>]        The last 200 events, or those numbered above n\n  \
+           timing              Control-loop timing of the last 10 s\n  \
+           power               Power profile, idle slowing, and wakeups per second\n  \
+           battery             Battery health and history\n  \
+           pose [reset]        Dead-reckoning pose, or make the current position the origin\n  \
+           drive <left> <right> Set wheel speeds in mm/s (-500..500), until changed or for\n                      motion.heartbeat_ms unless renewed\n  \
+           heartbeat           Renew the last drive request\n  \
+           stop                Stop the wheels\n  \
+           mode passive|safe|full\n                      Put the robot in an OI mode (full with safety.allow_full_mode)\n  \
+           estop [status|clear]\n                      Emergency stop: zero the wheels, cancel everything, Passive\n                      mode, and latch until cleared; or show or clear it\n  \
+           behavior [stop]     Show or stop the running behavior\n  \
+           behavior start <name> [args...]\n                      Start a behavior: waypoints <x,y>..., go-to <x,y[,theta]>,\n                      wall-follow [left|right], wander, coverage [<x0,y0> <x1,y1>],\n                      spiral [area_m2], dock, beacon [remote|virtual-wall|<code>],\n                      heading-tune, self-test, undock [m],\n                      mission <file>, patrol [route], route <name>\n                      [velocity|poses], script <name>, tree <name>\n  \
+           self-test [run]     Show the last self-test's report, or run it now\n  \
+           undock              Back off the dock's contacts, so the robot may drive again\n  \
+           mission run <file>  Run a mission file (TOML or YAML), or a mission by name from the\n                      daemon's missions directory\n  \
+           validate mission|script <file>\n                      Check a mission or script without running it, listing every\n                      problem\n  \
+           validate oi <byte>...\n                      Check OI command bytes against the protocol model\n  \
+           song [stop]         Show the song playing and the queue, or empty it\n  \
+           song play <file> [track]\n                      Queue a MIDI or ABC file (from the daemon's songs directory, or\n                      a path) or a song from its config; track from 0,\n                      default the first with notes\n  \
+           song play-rtttl <ringtone>\n                      Queue an RTTTL ringtone, e.g. \"tune:d=4,o=5,b=120:c,e,g\"\n  \
+           leds [stop]         Show the LED patterns and those playing, or stop them\n  \
+           leds play <pattern> Play an LED pattern: breathe, alert, busy, or one from the config\n  \
+           morse beep|leds <text>\n                      Send text in Morse code as beeps, or flashes of the LEDs\n  \
+           quiet [on|off|toggle|auto]\n                      Show or set quiet mode: no songs, beeps, greeting, or LED\n                      animations; auto follows the quiet hours again\n  \
+           overcurrent [ack]   Show motors cut for overcurrent, or resume them once cleared\n  \
+           audit [action] [count]\n                      Latest safety audit log entries (default 20), or those of one\n                      action\n  \
+           bumper              Light bump signals and the virtual bumper's thresholds\n  \
+           bumper calibrate open|stop|save\n                      Sample with nothing ahead, or an obstacle at the stopping\n                      distance, then save thresholds worked out from both\n  \
+           patrol              Show the patrol schedule, the run in progress, and the last report\n  \
+           patrol run [route] [velocity|poses]\n                      Patrol a route now: undock, play it back, and dock\n  \
+           route [list]        Show the route being recorded, or list the recorded routes\n  \
+           route record <name> Record the route driven, until route stop saves it\n  \
+           route stop          Stop recording and save the route\n  \
+           route play <name> [velocity|poses]\n                      Play a recorded route back\n  \
+           waypoints <x,y>...  Drive through waypoints (m, pose frame); each x,y[,tolerance_m[,timeout_s]]\n  \
+           sniff [on|off]      Show or toggle the raw serial sniffer\n\
+         The socket defaults to $CREATED_SOCKET or {DEFAULT_SOCKET}."
+    )
+}
+
+fn main() {
+    let mut socket = env::var("CREATED_SOCKET").unwrap_or_else(|_| DEFAULT_SOCKET.to_string());
+    let mut words = Vec::new();
+    let mut it = env::args().skip(1);
+    while let Some(arg) = it.next() {
+        match arg.as_str() {
+            "--socket" => match it.next() {
+                Some(p) => socket = p,
+                None => {
+                    eprintln!("--socket requires a path\n{}", usage());
+                    process::exit(2);
+                }
+            },
+            "-h" | "--help" => {
+                println!("{}", usage());
+                process::exit(0);
+            }
+            _ => words.push(arg),
+        }
+    }
+    if words.is_empty() {
+        eprintln!("{}", usage());
+        process::exit(2);
+    }
+    // The daemon resolves paths from its own working directory
+    if let [cmd, run, file, ..] = words.as_mut_slice() {
+        if matches!((cmd.as_str(), run.as_str()), ("mission", "run") | ("song", "play") | ("validate", "mission" | "script")) && Path::new(file).exists() {
+            if let Ok(path) = fs::canonicalize(&*file) {
+                *file = path.to_string_lossy().into_owned();
+            }
+        }
+    }
+
+    let reply = match request(&socket, &words.join(" ")) {
+        Ok(reply) => reply,
+        Err(e) => {
+            eprintln!("{e}");
+            process::exit(1);
+        }
+    };
+    let reply = reply.trim();
+    println!("{reply}");
+    // Exit non-zero when the daemon reports an error, for use in scripts
+    if !reply.contains("\"ok\":true") {
+        process::exit(1);
+    }
+}
+
+/// The daemon's reply to `command`.
+#[cfg(unix)]
+fn request(socket: &str, command: &str) -> Result<String, String> {
+    let mut stream = UnixStream::connect(socket).map_err(|e| format!("cannot connect to {socket}: {e}"))?;
+    writeln!(stream, "{command}").map_err(|e| format!("send: {e}"))?;
+    let mut reply = String::new();
+    BufReader::new(&stream).read_line(&mut reply).map_err(|e| format!("receive: {e}"))?;
+    Ok(reply)
+}
+
+#[cfg(not(unix))]
+fn request(socket: &str, _command: &str) -> Result<String, String> {
+    Err(format!("cannot connect to {socket}: the control socket is a Unix socket, not available here"))
+}
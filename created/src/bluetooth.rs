@@ -0,0 +1,213 @@
+//! Bluetooth serial adapters (the Create's BAM, HC-05 style modules)
+//! without binding a `/dev/rfcomm*` device first: `serial.path =
+//! "bt://00:11:22:33:44:55"`, or `bt://00:11:22:33:44:55/2` for another
+//! RFCOMM channel than 1, opens an RFCOMM socket to the adapter. The link
+//! behaves like a port: reads time out after `TIMEOUT` with nothing to read,
+//! and a link dropped fails the next read or write, ending the session for
+//! the worker to connect again. RFCOMM sockets are Linux's; elsewhere bind
+//! the adapter as a serial port and name that instead.
+
+use std::io::{self, Read, Write};
+#[cfg(target_os = "linux")]
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+#[cfg(target_os = "linux")]
+use std::time::Duration;
+
+use crate::transport::Transport;
+
+/// The scheme of a `serial.path` naming a Bluetooth adapter
+pub const SCHEME: &str = "bt://";
+/// How long reads and writes wait, as for serial ports
+#[cfg(target_os = "linux")]
+const TIMEOUT: Duration = Duration::from_millis(500);
+/// How long paging the adapter may take: one switched off or out of range
+/// takes the whole of it
+#[cfg(target_os = "linux")]
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+#[cfg(target_os = "linux")]
+const BTPROTO_RFCOMM: libc::c_int = 3;
+
+/// `struct sockaddr_rc` of the kernel's Bluetooth headers
+#[cfg(target_os = "linux")]
+#[repr(C)]
+struct SockaddrRc {
+    rc_family: libc::sa_family_t,
+    /// The address, least significant byte first
+    rc_bdaddr: [u8; 6],
+    rc_channel: u8,
+}
+
+/// An RFCOMM link to an adapter.
+#[cfg(target_os = "linux")]
+pub struct Rfcomm {
+    fd: OwnedFd,
+}
+
+/// Never had: there are no RFCOMM sockets to connect.
+#[cfg(not(target_os = "linux"))]
+pub enum Rfcomm {}
+
+#[cfg(not(target_os = "linux"))]
+impl Rfcomm {
+    pub fn connect(path: &str) -> Result<Rfcomm, String> {
+        parse(path)?;
+        Err(format!("{path}: RFCOMM sockets need Linux; bind the adapter as a serial port and use that"))
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+impl Read for Rfcomm {
+    fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+        match *self {}
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+impl Write for Rfcomm {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        match *self {}
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {}
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+impl Transport for Rfcomm {
+    fn kind(&self) -> &'static str {
+        match *self {}
+    }
+
+    fn set_baud_rate(&mut self, _baud: u32) -> Result<(), String> {
+        match *self {}
+    }
+}
+
+/// The adapter's address and channel from `bt://<address>[/<channel>]`.
+pub fn parse(path: &str) -> Result<([u8; 6], u8), String> {
+    let rest = path.strip_prefix(SCHEME).ok_or_else(|| format!("{path}: not a {SCHEME} address"))?;
+    let (address, channel) = match rest.split_once('/') {
+        Some((address, channel)) => (address, channel.parse::<u8>().ok().filter(|c| (1..=30).contains(c)).ok_or_else(|| format!("{path}: bad RFCOMM channel {channel:?} (1-30)"))?),
+        None => (rest, 1),
+    };
+    let octets: Vec<u8> = address.split(':').map(|o| u8::from_str_radix(o, 16)).collect::<Result<_, _>>().map_err(|_| format!("{path}: bad Bluetooth address {address:?}"))?;
+    let Ok(mut bdaddr) = <[u8; 6]>::try_from(octets) else {
+        return Err(format!("{path}: bad Bluetooth address {address:?}, expected six octets (00:11:22:33:44:55)"));
+    };
+    bdaddr.reverse();
+    Ok((bdaddr, channel))
+}
+
+/// Wait until `fd` is ready for `events`; false on timing out.
+#[cfg(target_os = "linux")]
+fn ready(fd: &OwnedFd, events: libc::c_short, timeout: Duration) -> io::Result<bool> {
+    let mut pfd = libc::pollfd { fd: fd.as_raw_fd(), events, revents: 0 };
+    let ms = timeout.as_millis().min(i32::MAX as u128) as i32;
+    loop {
+        match unsafe { libc::poll(&mut pfd, 1, ms) } {
+            n if n < 0 => {
+                let e = io::Error::last_os_error();
+                if e.kind() != io::ErrorKind::Interrupted {
+                    return Err(e);
+                }
+            }
+            0 => return Ok(false),
+            _ => return Ok(true),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Rfcomm {
+    /// Connect to the adapter at `path`, a `bt://` address.
+    pub fn connect(path: &str) -> Result<Rfcomm, String> {
+        let (bdaddr, channel) = parse(path)?;
+        // SAFETY: plain syscalls; the descriptor is owned from here on
+        let fd = unsafe { libc::socket(libc::AF_BLUETOOTH, libc::SOCK_STREAM | libc::SOCK_CLOEXEC | libc::SOCK_NONBLOCK, BTPROTO_RFCOMM) };
+        if fd < 0 {
+            return Err(format!("RFCOMM socket: {}", io::Error::last_os_error()));
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        let addr = SockaddrRc { rc_family: libc::AF_BLUETOOTH as libc::sa_family_t, rc_bdaddr: bdaddr, rc_channel: channel };
+        let len = std::mem::size_of::<SockaddrRc>() as libc::socklen_t;
+        if unsafe { libc::connect(fd.as_raw_fd(), &addr as *const SockaddrRc as *const libc::sockaddr, len) } < 0 {
+            let e = io::Error::last_os_error();
+            if e.raw_os_error() != Some(libc::EINPROGRESS) {
+                return Err(format!("connect: {e}"));
+            }
+            if !ready(&fd, libc::POLLOUT, CONNECT_TIMEOUT).map_err(|e| format!("connect: {e}"))? {
+                return Err(format!("connect: no answer in {} s (the adapter may be off or out of range)", CONNECT_TIMEOUT.as_secs()));
+            }
+            let mut error: libc::c_int = 0;
+            let mut size = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+            unsafe { libc::getsockopt(fd.as_raw_fd(), libc::SOL_SOCKET, libc::SO_ERROR, &mut error as *mut libc::c_int as *mut libc::c_void, &mut size) };
+            if error != 0 {
+                return Err(format!("connect: {}", io::Error::from_raw_os_error(error)));
+            }
+        }
+        Ok(Rfcomm { fd })
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Read for Rfcomm {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if !ready(&self.fd, libc::POLLIN, TIMEOUT)? {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "RFCOMM read timed out"));
+        }
+        let n = unsafe { libc::read(self.fd.as_raw_fd(), buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+        match n {
+            n if n < 0 => Err(io::Error::last_os_error()),
+            // Ready with nothing to read: the adapter hung up
+            0 if !buf.is_empty() => Err(io::Error::new(io::ErrorKind::BrokenPipe, "RFCOMM link closed")),
+            n => Ok(n as usize),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Write for Rfcomm {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        loop {
+            let n = unsafe { libc::write(self.fd.as_raw_fd(), buf.as_ptr() as *const libc::c_void, buf.len()) };
+            if n >= 0 {
+                return Ok(n as usize);
+            }
+            let e = io::Error::last_os_error();
+            if e.kind() != io::ErrorKind::WouldBlock {
+                return Err(e);
+            }
+            if !ready(&self.fd, libc::POLLOUT, TIMEOUT)? {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "RFCOMM write timed out"));
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Transport for Rfcomm {
+    fn kind(&self) -> &'static str {
+        "bluetooth"
+    }
+
+    fn set_baud_rate(&mut self, _baud: u32) -> Result<(), String> {
+        Err("the adapter's baud rate to the robot is set on the adapter".into())
+    }
+
+    /// Hang up, so the adapter takes the next connection at once.
+    fn close(self: Box<Self>) -> Result<(), String> {
+        // SAFETY: a plain syscall on the descriptor owned
+        if unsafe { libc::shutdown(self.fd.as_raw_fd(), libc::SHUT_RDWR) } < 0 {
+            let e = io::Error::last_os_error();
+            if e.raw_os_error() != Some(libc::ENOTCONN) {
+                return Err(format!("shutdown: {e}"));
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,297 @@
+//! Virtual bumper: the Create 2's light bump sensors see obstacles a few
+//! centimetres ahead of the bumper, so forward motion can slow down as
+//! one comes closer and stop short of touching it. Each sensor has its
+//! own thresholds: from `slow` the forward speed is cut down in
+//! proportion to how far the signal is on its way to `stop`, where it is
+//! stopped. Turning on the spot and backing up are always allowed. Like
+//! the geofence, it applies to everything that drives the wheels.
+//!
+//! The signals depend on the room's light and the obstacles' colour, so
+//! thresholds can be calibrated on the robot: `bumper calibrate open`
+//! samples the signals with nothing ahead, `bumper calibrate stop` with
+//! an obstacle where the robot should stop, and `bumper calibrate save`
+//! keeps thresholds worked out from both, used unless configured.
+
+use std::fs;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tracing::{info, warn};
+
+use crate::aliases;
+use crate::bus::Event;
+use crate::config::{VirtualBumperConfig, LIGHT_BUMPS};
+use crate::motion::Wheels;
+use crate::oi::SensorFrame;
+
+const LIGHT_BUMP_SIGNALS: [u8; 6] = [46, 47, 48, 49, 50, 51];
+/// Slowest forward speed short of the stop, mm/s.
+const CRAWL: f64 = 40.0;
+/// Defaults: the center sensors look straight ahead, the side ones past
+/// the robot's path.
+const SLOW: [u16; 6] = [400, 200, 150, 150, 200, 400];
+const STOP: [u16; 6] = [1500, 800, 600, 600, 800, 1500];
+/// A calibrated slow threshold is at least this far above the open
+/// signal, and a stop one this far above the slow one.
+const MARGIN: u16 = 20;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Thresholds {
+    pub slow: [u16; 6],
+    pub stop: [u16; 6],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Sampling {
+    /// Nothing ahead: the highest signals seen
+    Open,
+    /// An obstacle at the stopping distance: the average signals
+    Stop,
+}
+
+struct Calibration {
+    kind: Sampling,
+    until: Instant,
+    max: [u16; 6],
+    sum: [f64; 6],
+    frames: u32,
+}
+
+struct Bumper {
+    enabled: bool,
+    thresholds: Thresholds,
+    readings: Option<[u16; 6]>,
+    /// The fraction of the forward speed allowed, from the last frame
+    allowed: f64,
+    /// Sensors at their stop threshold
+    stopped: Vec<&'static str>,
+    calibration: Option<Calibration>,
+}
+
+/// Results of the calibration so far.
+struct Calibrated {
+    open: Option<[u16; 6]>,
+    stop: Option<[u16; 6]>,
+}
+
+static STATE: Mutex<Option<Bumper>> = Mutex::new(None);
+static CONFIG: Mutex<Option<VirtualBumperConfig>> = Mutex::new(None);
+/// Kept thresholds from `calibration_path`.
+static SAVED: Mutex<Option<Thresholds>> = Mutex::new(None);
+static CALIBRATED: Mutex<Calibrated> = Mutex::new(Calibrated { open: None, stop: None });
+
+fn state() -> std::sync::MutexGuard<'static, Option<Bumper>> {
+    STATE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn config() -> VirtualBumperConfig {
+    CONFIG.lock().unwrap_or_else(|e| e.into_inner()).clone().unwrap_or_default()
+}
+
+/// Calibration works every session; slowing and stopping only with
+/// `virtual_bumper.enabled`.
+pub fn init(cfg: VirtualBumperConfig) {
+    load(&cfg);
+    if let Some(e) = cfg.thresholds_error() {
+        warn!("{e}; using the defaults");
+    }
+    *CONFIG.lock().unwrap_or_else(|e| e.into_inner()) = Some(cfg);
+}
+
+/// Keep the calibrated thresholds of the connected robot, by its name
+/// (`aliases`), or the shared ones.
+fn load(cfg: &VirtualBumperConfig) {
+    let saved = aliases::read(&cfg.calibration_path()).and_then(|(path, text)| {
+        serde_json::from_str::<Thresholds>(&text).map_err(|e| warn!("ignoring unreadable bumper calibration {}: {e}", path.display())).ok()
+    });
+    *SAVED.lock().unwrap_or_else(|e| e.into_inner()) = saved;
+}
+
+/// Configured thresholds, falling back to calibrated ones, then the
+/// defaults.
+fn thresholds(cfg: &VirtualBumperConfig) -> Thresholds {
+    let saved = *SAVED.lock().unwrap_or_else(|e| e.into_inner());
+    let valid = cfg.thresholds_error().is_none();
+    let per_sensor = |v: &Option<Vec<u16>>| -> Option<[u16; 6]> {
+        let v = v.as_ref().filter(|_| valid)?;
+        Some(std::array::from_fn(|i| if v.len() == 1 { v[0] } else { v[i] }))
+    };
+    let slow = per_sensor(&cfg.slow).or(saved.map(|t| t.slow)).unwrap_or(SLOW);
+    let stop = per_sensor(&cfg.stop).or(saved.map(|t| t.stop)).unwrap_or(STOP);
+    Thresholds { slow, stop }
+}
+
+pub fn begin() {
+    let cfg = config();
+    load(&cfg);
+    *state() = Some(Bumper { enabled: cfg.enabled(), thresholds: thresholds(&cfg), readings: None, allowed: 1.0, stopped: Vec::new(), calibration: None });
+}
+
+pub fn end() {
+    *state() = None;
+}
+
+/// Check the light bump signals; events to publish.
+pub fn update(frame: &SensorFrame) -> Vec<Event> {
+    let mut guard = state();
+    let Some(b) = guard.as_mut() else { return Vec::new() };
+    let signals: Option<Vec<u16>> = LIGHT_BUMP_SIGNALS.iter().map(|id| frame.get(*id).map(|v| v.clamp(0, u16::MAX as i32) as u16)).collect();
+    let Some(signals) = signals else {
+        b.readings = None;
+        b.allowed = 1.0;
+        return Vec::new();
+    };
+    let signals: [u16; 6] = std::array::from_fn(|i| signals[i]);
+    b.readings = Some(signals);
+    if let Some(c) = b.calibration.as_mut() {
+        for (i, s) in signals.iter().enumerate() {
+            c.max[i] = c.max[i].max(*s);
+            c.sum[i] += *s as f64;
+        }
+        c.frames += 1;
+    }
+    if !b.enabled {
+        return Vec::new();
+    }
+    let t = b.thresholds;
+    b.allowed = (0..6)
+        .map(|i| {
+            let (slow, stop, s) = (t.slow[i] as f64, t.stop[i] as f64, signals[i] as f64);
+            if s >= stop {
+                0.0
+            } else if s <= slow {
+                1.0
+            } else {
+                (stop - s) / (stop - slow)
+            }
+        })
+        .fold(1.0, f64::min);
+    let stopped: Vec<&'static str> = (0..6).filter(|i| signals[*i] >= t.stop[*i]).map(|i| LIGHT_BUMPS[i]).collect();
+    // Published as a sensor first reaches its stop threshold
+    let new = stopped.iter().any(|s| !b.stopped.contains(s));
+    b.stopped = stopped;
+    if !new {
+        return Vec::new();
+    }
+    info!("virtual bumper: stopping for {}", b.stopped.join(", "));
+    vec![Event::VirtualBump { sensors: b.stopped.iter().map(|s| s.to_string()).collect() }]
+}
+
+/// `wheels` with their forward speed cut down to what the light bumpers
+/// allow.
+pub fn limit(wheels: Wheels) -> Wheels {
+    let guard = state();
+    let Some(b) = guard.as_ref().filter(|b| b.enabled) else { return wheels };
+    let v = (wheels.left as f64 + wheels.right as f64) / 2.0;
+    let d = (wheels.right as f64 - wheels.left as f64) / 2.0;
+    if v <= 0.0 || b.allowed >= 1.0 {
+        return wheels;
+    }
+    let v = match b.allowed {
+        a if a > 0.0 => (v * a).max(CRAWL.min(v)),
+        _ => 0.0,
+    };
+    Wheels::new((v - d).round() as i32, (v + d).round() as i32)
+}
+
+/// Per-sensor values by sensor name.
+fn named(values: &[u16; 6]) -> Value {
+    LIGHT_BUMPS.iter().zip(values).map(|(n, v)| (n.to_string(), json!(v))).collect::<serde_json::Map<_, _>>().into()
+}
+
+/// The signals, the thresholds in use, and the calibration so far.
+pub fn status() -> Result<Value, String> {
+    let guard = state();
+    let b = guard.as_ref().ok_or("no robot is connected")?;
+    let calibrated = CALIBRATED.lock().unwrap_or_else(|e| e.into_inner());
+    Ok(json!({
+        "enabled": b.enabled,
+        "signals": b.readings.as_ref().map(named),
+        "allowed": b.allowed,
+        "stopped": b.stopped,
+        "slow": named(&b.thresholds.slow),
+        "stop": named(&b.thresholds.stop),
+        "calibration": { "open": calibrated.open.as_ref().map(named), "stop": calibrated.stop.as_ref().map(named) }
+    }))
+}
+
+/// Sample the signals for `virtual_bumper.calibrate_s`: `open` with nothing
+/// ahead, or `stop` with an obstacle where the robot should stop. Waits
+/// for the sampling to end.
+pub fn calibrate(kind: &str) -> Result<Value, String> {
+    let kind = match kind {
+        "open" => Sampling::Open,
+        "stop" => Sampling::Stop,
+        _ => return Err("usage: bumper calibrate open|stop|save".into()),
+    };
+    let duration = config().calibrate();
+    {
+        let mut guard = state();
+        let b = guard.as_mut().ok_or("no robot is connected")?;
+        if b.calibration.is_some() {
+            return Err("already calibrating".into());
+        }
+        b.calibration = Some(Calibration { kind, until: Instant::now() + duration, max: [0; 6], sum: [0.0; 6], frames: 0 });
+    }
+    let c = loop {
+        thread::sleep(Duration::from_millis(50));
+        let mut guard = state();
+        let b = guard.as_mut().ok_or("the robot disconnected")?;
+        if b.calibration.as_ref().is_some_and(|c| Instant::now() >= c.until) {
+            break b.calibration.take().ok_or("calibration lost")?;
+        }
+    };
+    if c.frames == 0 {
+        return Err("no light bump signals (stream packets 46-51, e.g. group 106)".into());
+    }
+    let values: [u16; 6] = match c.kind {
+        Sampling::Open => c.max,
+        Sampling::Stop => std::array::from_fn(|i| (c.sum[i] / c.frames as f64).round() as u16),
+    };
+    let mut calibrated = CALIBRATED.lock().unwrap_or_else(|e| e.into_inner());
+    match c.kind {
+        Sampling::Open => calibrated.open = Some(values),
+        Sampling::Stop => calibrated.stop = Some(values),
+    }
+    Ok(json!({ "sampled": if c.kind == Sampling::Open { "open" } else { "stop" }, "frames": c.frames, "signals": named(&values) }))
+}
+
+/// Work out thresholds from both samples, keep them in
+/// `virtual_bumper.calibration_path`, and use them from now on (where not
+/// configured). A sensor that didn't see the obstacle keeps its
+/// thresholds.
+pub fn save() -> Result<Value, String> {
+    let (open, stop) = {
+        let c = CALIBRATED.lock().unwrap_or_else(|e| e.into_inner());
+        (c.open.ok_or("calibrate open first")?, c.stop.ok_or("calibrate stop first")?)
+    };
+    let cfg = config();
+    let mut t = thresholds(&cfg);
+    let mut unseen = Vec::new();
+    for i in 0..6 {
+        let floor = open[i].saturating_add(MARGIN);
+        if stop[i] <= floor.saturating_add(MARGIN) {
+            unseen.push(LIGHT_BUMPS[i]);
+            continue;
+        }
+        t.stop[i] = stop[i];
+        // About twice the stopping distance, the signal falling off with its square
+        t.slow[i] = (stop[i] / 4).max(floor).min(stop[i] - MARGIN);
+    }
+    let path = aliases::path(&cfg.calibration_path());
+    let text = serde_json::to_string_pretty(&t).unwrap_or_default();
+    let written = path.parent().map_or(Ok(()), fs::create_dir_all).and_then(|()| fs::write(&path, text));
+    written.map_err(|e| format!("write {}: {e}", path.display()))?;
+    *SAVED.lock().unwrap_or_else(|e| e.into_inner()) = Some(t);
+    if let Some(b) = state().as_mut() {
+        b.thresholds = thresholds(&cfg);
+    }
+    if !unseen.is_empty() {
+        warn!("bumper calibration: {} didn't see the obstacle, keeping their thresholds", unseen.join(", "));
+    }
+    info!("bumper calibration saved to {}", path.display());
+    Ok(json!({ "slow": named(&t.slow), "stop": named(&t.stop), "unchanged": unseen, "path": path }))
+}
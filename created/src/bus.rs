@@ -0,0 +1,413 @@
+//! Fan-out of everything a robot session produces (sensor frames, commands
+//! sent, daemon events, pose estimates) to recorders and telemetry sinks.
+//! Each subscriber gets sensor frames and poses through a lock-free ring
+//! (`ring`) that drops the oldest when full, so publishing them never takes
+//! a lock a sink holds or waits on a sink, and commands and events through
+//! a channel; its `Receiver` hands them on in the order they were
+//! published. Publishing isn't free of locks or allocation, though: the
+//! flight recorder (`flight`) keeps each message under its own mutex, which
+//! only its dump shares, and each push to a ring boxes the frame's slot.
+
+use std::cell::Cell;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvError, RecvTimeoutError, SyncSender, TryRecvError, TrySendError};
+use std::sync::{Arc, OnceLock};
+use std::thread::{self, Thread};
+use std::time::{Duration, Instant, SystemTime};
+
+use serde::Serialize;
+use tracing::warn;
+
+use crate::behavior::Outcome;
+use crate::flight;
+use crate::oi::{self, SensorFrame};
+use crate::pose::Pose;
+use crate::ring::Ring;
+use crate::stats::SessionStats;
+
+#[derive(Debug, Clone)]
+pub enum Message {
+    /// Shared rather than copied: every subscriber gets the same frame
+    Sensors(Arc<SensorFrame>),
+    /// Raw bytes written to the robot; the first byte is the opcode
+    Command { time: SystemTime, bytes: Vec<u8> },
+    Event { time: SystemTime, event: Event },
+    /// Dead-reckoning estimate, after each sensor frame
+    Pose(Pose),
+}
+
+/// Discrete daemon events.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    /// `robot` is the robot's name, if it has been given one (`createctl alias set`)
+    Connected { device: String, robot: Option<String>, baud: u32 },
+    Disconnected { device: String, reason: String },
+    /// Connected again to the robot a link was lost to, within `serial.resume_s`, and picking up where it left off
+    Resumed { device: String, after_s: f64, mode: Option<String>, behavior: Option<String> },
+    StreamStarted { packets: Vec<u8> },
+    ModeChanged { from: Option<String>, to: String },
+    Bump { left: bool, right: bool },
+    WheelDrop { left: bool, right: bool },
+    Cliff { sensors: Vec<String> },
+    Docked,
+    Undocked,
+    /// A command received on the control socket
+    #[cfg_attr(not(unix), allow(dead_code))]
+    Control { command: String },
+    /// A robot button was pressed, or held for `buttons.long_press_s`; `action` the command it ran
+    Button { button: String, long: bool, action: Option<String> },
+    BatteryDegraded { health_percent: f64, capacity_mah: u32 },
+    /// The battery supervisor is sending the robot to its dock
+    BatteryLow { percent: Option<f64>, voltage_mv: Option<i32> },
+    /// Docking failed and the battery is nearly empty: the robot is stopped
+    BatteryCritical { percent: Option<f64>, voltage_mv: Option<i32> },
+    /// The battery voltage stayed below `low_voltage.cutoff_mv`: the robot is stopped and put to a low-power state
+    LowVoltageCutoff { voltage_mv: i32, cutoff_mv: i32 },
+    /// The voltage is back to `low_voltage.resume_mv`: the robot may move again
+    LowVoltageRecovered { voltage_mv: i32 },
+    DockingFailed { reason: String },
+    /// The pose entered a geofence zone: inside, near, boundary, or outside
+    Geofence { zone: String, distance_m: f64 },
+    /// The stasis sensor and the encoders disagree: `kind` wheels (slipping) or carried
+    SlipStarted { kind: String },
+    /// They agree again; `confidence` is what the pose estimate is still worth, 0 to 1
+    SlipEnded { kind: String, duration_s: f64, confidence: f64 },
+    /// The governor's top speed is held down by other conditions: `by` voltage, surface, proximity
+    SpeedLimit { by: Vec<String>, max_speed: f64 },
+    /// The wheels were driven without getting anywhere, or stalled
+    Stuck { reason: String, for_s: f64 },
+    /// Both wheels have been down for `wheel_drop.debounce_ms` after a wheel drop: the robot may move again
+    WheelsDown,
+    /// A cliff sensor sees a drop: no forward motion until none has for `cliff.clear_ms`
+    CliffInterlock { sensors: Vec<String> },
+    /// Forward motion is allowed again
+    CliffCleared,
+    /// The battery temperature reached a level: `normal`, `warning` (slowed down, charging paused), or `critical` (stopped)
+    BatteryTemperature { level: String, celsius: i32 },
+    /// Motors flagged for overcurrent for `overcurrent.persist_ms`, cut until acknowledged: `left_wheel`, `right_wheel`, `main_brush`, `side_brush`
+    Overcurrent { motors: Vec<String>, for_s: f64 },
+    /// A drive request wasn't renewed within `motion.heartbeat_ms`, so the wheels were stopped
+    HeartbeatLost { after_s: f64 },
+    /// The emergency stop was engaged: `source` SIGUSR1, control, or http and the client's address
+    EmergencyStop { source: String },
+    /// It was cleared; the robot stays in Passive mode until asked to move
+    EmergencyStopCleared { source: String },
+    /// Light bump sensors reached their stop threshold (virtual bumper)
+    VirtualBump { sensors: Vec<String> },
+    /// A reflex took the wheels: `trigger` cliff, wheel_drop, or bump
+    ReflexStarted { trigger: String, action: String },
+    /// It handed them back, having cancelled the running behavior or not
+    ReflexEnded { trigger: String, cancelled: bool, reason: Option<String> },
+    BehaviorStarted { name: String },
+    BehaviorEnded { name: String, outcome: Outcome, reason: Option<String> },
+    /// A behavior panicked, or its script raised an error; it ends as failed
+    BehaviorCrashed { name: String, error: String },
+    /// A behavior missed its deadline and was stopped; `then` stop or dock
+    BehaviorTimeout { name: String, after_s: f64, then: String },
+    /// Navigation reached waypoint `index` (from 0)
+    WaypointReached { index: usize, x: f64, y: f64 },
+    /// Coverage finished row `row` of `rows`, or cut it short at an obstacle
+    RowCovered { row: usize, rows: usize, truncated: bool },
+    /// A mission step `index` (from 0) ended
+    MissionStep { mission: String, index: usize, step: String, outcome: Outcome, reason: Option<String> },
+    /// A route was recorded and saved
+    RouteRecorded { name: String, samples: usize, duration_s: f64, distance_m: f64 },
+    /// A phase of a patrol run ended: undock, route, or dock
+    PatrolPhase { route: String, phase: String, outcome: Outcome, reason: Option<String> },
+    /// A patrol run ended, and its report was written to `path`
+    PatrolReport { route: String, outcome: Outcome, reason: Option<String>, duration_s: f64, distance_m: f64, docked: bool, path: Option<String> },
+    /// The heading-tune calibration found these gains
+    HeadingTuned { kp: f64, ki: f64, kd: f64 },
+    /// The self-test ran: each check passed, failed, or was skipped
+    SelfTest { passed: bool, checks: Vec<crate::behavior::selftest::Check> },
+    /// Totals for a session that just ended
+    SessionSummary(SessionStats),
+    /// Totals for a session's share of one local day
+    Activity(SessionStats),
+}
+
+/// Which sensor frames a subscriber receives: at most one per `period`, and
+/// only the packets in `fields`. Poses are sampled at the same rate; commands
+/// and events are always delivered.
+#[derive(Debug, Clone, Default)]
+pub struct Sampling {
+    pub period: Option<Duration>,
+    pub fields: Option<Vec<u8>>,
+    /// No sensor frames (or poses) at all
+    pub skip_sensors: bool,
+}
+
+impl Sampling {
+    /// From a sink's `rate_hz` and `fields` settings; unknown packet names are
+    /// warned about and skipped.
+    pub fn new(sink: &str, rate_hz: Option<f64>, fields: Option<&[String]>) -> Self {
+        let period = rate_hz.filter(|hz| *hz > 0.0).map(|hz| Duration::from_secs_f64(1.0 / hz));
+        let fields = fields.map(|names| {
+            names
+                .iter()
+                .filter_map(|name| match oi::packet_by_name(name) {
+                    Some(p) => Some(p.id),
+                    None => {
+                        warn!("{sink}: unknown sensor field {name:?}");
+                        None
+                    }
+                })
+                .collect()
+        });
+        Sampling { period, fields, skip_sensors: false }
+    }
+
+    /// For subscribers that only want commands and events.
+    pub fn no_sensors() -> Self {
+        Sampling { skip_sensors: true, ..Sampling::default() }
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.period.is_none() && self.fields.is_none() && !self.skip_sensors
+    }
+}
+
+/// A message with its place in the order messages were published
+type Stamped = (u64, Message);
+
+/// What a subscriber's publishers and its receiver share.
+struct Queue {
+    /// Sensor frames and poses, from the session thread alone
+    frames: Ring<Stamped>,
+    /// Stamps every message queued, frames and events alike
+    order: AtomicU64,
+    /// The receiving thread, once it has waited
+    reader: OnceLock<Thread>,
+}
+
+impl Queue {
+    fn wake(&self) {
+        if let Some(reader) = self.reader.get() {
+            reader.unpark();
+        }
+    }
+}
+
+/// A clone's reference to the queue. Dropping it wakes the receiver, to
+/// find out whether that was the last publisher; it comes after the sender
+/// in `Subscriber`, so that is already dropped.
+struct Handle(Arc<Queue>);
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        self.0.wake();
+    }
+}
+
+struct Subscriber {
+    name: &'static str,
+    /// Commands and events, which any thread may publish
+    tx: SyncSender<Stamped>,
+    warned_full: Arc<AtomicBool>,
+    sampling: Arc<Sampling>,
+    /// Capture time at which the next sampled frame is due, as microseconds
+    /// since the epoch (0 before the first); only the session thread moves it
+    next_due: Arc<AtomicU64>,
+    next_pose_due: Arc<AtomicU64>,
+    queue: Handle,
+}
+
+impl Clone for Subscriber {
+    fn clone(&self) -> Self {
+        Subscriber {
+            name: self.name,
+            tx: self.tx.clone(),
+            warned_full: self.warned_full.clone(),
+            sampling: self.sampling.clone(),
+            next_due: self.next_due.clone(),
+            next_pose_due: self.next_pose_due.clone(),
+            queue: Handle(self.queue.0.clone()),
+        }
+    }
+}
+
+impl Subscriber {
+    /// The message as this subscriber should see it, or None if a sensor
+    /// frame is skipped by its sampling.
+    fn sample(&self, msg: &Message) -> Option<Message> {
+        if self.sampling.is_full() {
+            return Some(msg.clone());
+        }
+        let frame = match msg {
+            Message::Sensors(frame) => frame,
+            Message::Pose(pose) => {
+                let due = !self.sampling.skip_sensors && self.due(&self.next_pose_due, pose.time);
+                return due.then(|| msg.clone());
+            }
+            _ => return Some(msg.clone()),
+        };
+        if self.sampling.skip_sensors || !self.due(&self.next_due, frame.time) {
+            return None;
+        }
+        match &self.sampling.fields {
+            None => Some(msg.clone()),
+            Some(ids) => {
+                let mut subset = SensorFrame::new(frame.time);
+                for &id in ids {
+                    if let Some(v) = frame.get(id) {
+                        subset.set(id, v);
+                    }
+                }
+                Some(Message::Sensors(Arc::new(subset)))
+            }
+        }
+    }
+
+    /// Whether something captured at `time` is due under the sampling
+    /// period, moving the `next_due` mark if it is.
+    fn due(&self, next_due: &AtomicU64, time: SystemTime) -> bool {
+        let Some(period) = self.sampling.period else { return true };
+        let micros = |t: SystemTime| t.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_micros() as u64;
+        let (now, period) = (micros(time).max(1), period.as_micros() as u64);
+        let due = next_due.load(Ordering::Relaxed);
+        if due != 0 && now < due {
+            return false;
+        }
+        // Stay on the period grid unless frames stopped for a while
+        let next = Some(due + period).filter(|next| due != 0 && *next > now);
+        next_due.store(next.unwrap_or(now + period), Ordering::Relaxed);
+        true
+    }
+}
+
+/// Cheap to clone; every clone publishes to the same subscribers. Messages are
+/// queued without waiting, so a slow subscriber loses messages instead of
+/// stalling the serial reader: its oldest sensor frames and poses, or, with
+/// its channel full, the newest commands and events. Sensor frames and poses
+/// are published from the session thread alone, the one pushing to the rings.
+#[derive(Clone, Default)]
+pub struct Bus {
+    subscribers: Vec<Subscriber>,
+}
+
+impl Bus {
+    /// Register a subscriber with room for `capacity` queued sensor frames
+    /// and poses, and as many commands and events. Sensor frames are
+    /// downsampled and trimmed before they are queued, so a slow sink never
+    /// holds full-rate frames.
+    pub fn subscribe(&mut self, name: &'static str, capacity: usize, sampling: Sampling) -> Receiver {
+        let (tx, rx) = mpsc::sync_channel(capacity);
+        let queue = Arc::new(Queue { frames: Ring::new(capacity), order: AtomicU64::new(0), reader: OnceLock::new() });
+        self.subscribers.push(Subscriber {
+            name,
+            tx,
+            warned_full: Arc::new(AtomicBool::new(false)),
+            sampling: Arc::new(sampling),
+            next_due: Arc::new(AtomicU64::new(0)),
+            next_pose_due: Arc::new(AtomicU64::new(0)),
+            queue: Handle(queue.clone()),
+        });
+        Receiver { rx, queue, next: Cell::new(0), event: Cell::new(None), frame: Cell::new(None), gone: Cell::new(false) }
+    }
+
+    pub fn publish(&self, msg: Message) {
+        flight::message(&msg);
+        for sub in &self.subscribers {
+            let Some(msg) = sub.sample(&msg) else { continue };
+            let queue = &sub.queue.0;
+            let stamped = (queue.order.fetch_add(1, Ordering::Relaxed), msg);
+            let dropped = match stamped.1 {
+                Message::Sensors(_) | Message::Pose(_) => queue.frames.push(stamped),
+                _ => matches!(sub.tx.try_send(stamped), Err(TrySendError::Full(_))),
+            };
+            queue.wake();
+            if dropped && !sub.warned_full.swap(true, Ordering::Relaxed) {
+                warn!("{} is falling behind; dropping messages", sub.name);
+            }
+        }
+    }
+
+    pub fn event(&self, event: Event) {
+        self.publish(Message::Event { time: SystemTime::now(), event });
+    }
+}
+
+/// A subscriber's end: its sensor frames and poses, commands, and events, in
+/// the order they were published. Used from one thread, as a channel's
+/// receiver is.
+pub struct Receiver {
+    rx: mpsc::Receiver<Stamped>,
+    queue: Arc<Queue>,
+    /// The place in the ring
+    next: Cell<u64>,
+    /// Taken from the channel or the ring, but not handed on yet, the
+    /// other's being older
+    event: Cell<Option<Stamped>>,
+    frame: Cell<Option<Stamped>>,
+    /// Every publisher is gone
+    gone: Cell<bool>,
+}
+
+impl Receiver {
+    /// The oldest message waiting, if there is one.
+    fn take(&self) -> Option<Message> {
+        let mut event = self.event.take().or_else(|| match self.rx.try_recv() {
+            Ok(stamped) => Some(stamped),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => {
+                self.gone.set(true);
+                None
+            }
+        });
+        let mut frame = self.frame.take().or_else(|| {
+            let mut next = self.next.get();
+            let frame = self.queue.frames.pop(&mut next);
+            self.next.set(next);
+            frame
+        });
+        let first = match (&event, &frame) {
+            (Some(e), Some(f)) if f.0 < e.0 => frame.take(),
+            (Some(_), _) => event.take(),
+            (None, _) => frame.take(),
+        };
+        self.event.set(event);
+        self.frame.set(frame);
+        first.map(|(_, msg)| msg)
+    }
+
+    /// Wait for a message until `deadline` (None: for as long as it takes),
+    /// parked until a publisher wakes the thread.
+    fn wait(&self, deadline: Option<Instant>) -> Result<Message, RecvTimeoutError> {
+        self.queue.reader.get_or_init(thread::current);
+        loop {
+            if let Some(msg) = self.take() {
+                return Ok(msg);
+            }
+            if self.gone.get() {
+                return Err(RecvTimeoutError::Disconnected);
+            }
+            match deadline {
+                None => thread::park(),
+                Some(deadline) => {
+                    let left = deadline.saturating_duration_since(Instant::now());
+                    if left.is_zero() {
+                        return Err(RecvTimeoutError::Timeout);
+                    }
+                    thread::park_timeout(left);
+                }
+            }
+        }
+    }
+
+    pub fn recv(&self) -> Result<Message, RecvError> {
+        self.wait(None).map_err(|_| RecvError)
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<Message, RecvTimeoutError> {
+        self.wait(Instant::now().checked_add(timeout))
+    }
+}
+
+impl Iterator for Receiver {
+    type Item = Message;
+
+    fn next(&mut self) -> Option<Message> {
+        self.recv().ok()
+    }
+}
@@ -0,0 +1,144 @@
+//! Buttons: the robot's own buttons (packet 18) as a way to tell the
+//! daemon what to do. Each press, and each long press (held for
+//! `buttons.long_press_s`), can run a control command, as `createctl`
+//! would send it: `behavior start wander`, `behavior start dock`,
+//! `quiet toggle`. A button without a long press command runs its press
+//! command as it goes down; one with both decides when it is released or
+//! held long enough. Every press is published as a `button` event.
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use tracing::{info, warn};
+
+use crate::bus::Event;
+use crate::config::ButtonsConfig;
+use crate::control;
+use crate::oi::SensorFrame;
+
+const BUTTONS: u8 = 18;
+/// Create 2's names for the bits of packet 18, from bit 0
+const NAMES: [&str; 8] = ["clean", "spot", "dock", "minute", "hour", "day", "schedule", "clock"];
+
+/// The bit of the button `name`; Create 1 calls bits 0 and 2 play and advance.
+fn bit(name: &str) -> Option<usize> {
+    match name {
+        "play" => Some(0),
+        "advance" => Some(2),
+        _ => NAMES.iter().position(|n| *n == name),
+    }
+}
+
+/// Commands by button bit.
+fn commands(map: Option<&BTreeMap<String, String>>, key: &str) -> [Option<String>; 8] {
+    let mut commands: [Option<String>; 8] = Default::default();
+    for (name, command) in map.into_iter().flatten() {
+        match bit(name) {
+            Some(i) => commands[i] = Some(command.clone()).filter(|c| !c.trim().is_empty()),
+            None => warn!("buttons.{key}.{name}: no such button"),
+        }
+    }
+    commands
+}
+
+struct Held {
+    since: Instant,
+    /// Its press or long press has been handled
+    handled: bool,
+}
+
+#[derive(Clone)]
+struct Commands {
+    long_press: Duration,
+    press: [Option<String>; 8],
+    long: [Option<String>; 8],
+}
+
+struct Buttons {
+    commands: Commands,
+    held: [Option<Held>; 8],
+}
+
+static CONFIG: Mutex<Option<Commands>> = Mutex::new(None);
+static STATE: Mutex<Option<Buttons>> = Mutex::new(None);
+
+fn state() -> std::sync::MutexGuard<'static, Option<Buttons>> {
+    STATE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Read the commands. Does nothing unless `buttons.enabled`.
+pub fn init(cfg: ButtonsConfig) {
+    if !cfg.enabled() {
+        return;
+    }
+    let commands = Commands { long_press: cfg.long_press(), press: commands(cfg.press.as_ref(), "press"), long: commands(cfg.long_press.as_ref(), "long_press") };
+    *CONFIG.lock().unwrap_or_else(|e| e.into_inner()) = Some(commands);
+}
+
+pub fn begin() {
+    let commands = CONFIG.lock().unwrap_or_else(|e| e.into_inner()).clone();
+    *state() = commands.map(|commands| Buttons { commands, held: Default::default() });
+}
+
+pub fn end() {
+    *state() = None;
+}
+
+impl Buttons {
+    /// Presses and long presses in this frame: button bit and whether long.
+    fn presses(&mut self, bits: i32) -> Vec<(usize, bool)> {
+        let now = Instant::now();
+        let mut presses = Vec::new();
+        for (i, held) in self.held.iter_mut().enumerate() {
+            let down = bits & (1 << i) != 0;
+            let has_long = self.commands.long[i].is_some();
+            match (held.as_mut(), down) {
+                (None, true) => {
+                    // Nothing to wait for
+                    if !has_long {
+                        presses.push((i, false));
+                    }
+                    *held = Some(Held { since: now, handled: !has_long });
+                }
+                (Some(h), true) if !h.handled && now.duration_since(h.since) >= self.commands.long_press => {
+                    presses.push((i, true));
+                    h.handled = true;
+                }
+                (Some(h), false) => {
+                    if !h.handled {
+                        presses.push((i, false));
+                    }
+                    *held = None;
+                }
+                _ => {}
+            }
+        }
+        presses
+    }
+}
+
+/// Check one frame's buttons, running the commands of any pressed; events
+/// to publish.
+pub fn update(frame: &SensorFrame) -> Vec<Event> {
+    let Some(bits) = frame.get(BUTTONS) else { return Vec::new() };
+    let (presses, commands) = {
+        let mut guard = state();
+        let Some(b) = guard.as_mut() else { return Vec::new() };
+        (b.presses(bits), b.commands.clone())
+    };
+    let mut events = Vec::new();
+    for (i, long) in presses {
+        let action = if long { &commands.long[i] } else { &commands.press[i] };
+        let button = NAMES[i];
+        if let Some(command) = action {
+            let press = if long { "long press" } else { "press" };
+            match control::handle(command) {
+                Ok(_) => info!("{button} button {press}: {command}"),
+                Err(e) => warn!("{button} button {press}: {command}: {e}"),
+            }
+        }
+        events.push(Event::Button { button: button.to_string(), long, action: action.clone() });
+    }
+    events
+}
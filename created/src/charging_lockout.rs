@@ -0,0 +1,229 @@
+//! Charging lockout: while the robot is on the dock (the home base is a
+//! charging source, or without packet 34 the battery is charging), it
+//! isn't driven. Driving off the contacts while they carry current arcs
+//! them, and nudging the robot on the dock confuses the charge cycle, so
+//! `drive` is refused, raw commands that would move it (drive commands,
+//! Spot, Clean, Max, Seek Dock, Play Script) are dropped, and whatever a
+//! behavior or reflex asks for is held at a stop.
+//!
+//! The way off is `undock` (`createctl undock`, patrol's first phase):
+//! straight back off the contacts, slowly, for `charging_lockout.undock_m`.
+//! Only backing up is let through, and only while it runs; the docking
+//! behavior and the battery supervisor backing off contacts that don't
+//! charge count as undocking too.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+use tracing::{info, warn};
+
+use crate::audit;
+use crate::config::ChargingLockoutConfig;
+use crate::motion::Wheels;
+use crate::oi::{self, SensorFrame};
+
+const CHARGING_STATE: u8 = 21;
+const CHARGING_SOURCES: u8 = 34;
+const HOME_BASE: i32 = 0b10;
+/// Undocking lets backing up through for this long after it last asked
+const PERMIT: Duration = Duration::from_millis(250);
+
+struct Lockout {
+    docked: bool,
+    /// When undocking last asked to back up
+    undocking: Option<Instant>,
+    /// Holding back what was asked, logged once until it stops asking
+    holding: bool,
+}
+
+static CONFIG: Mutex<Option<ChargingLockoutConfig>> = Mutex::new(None);
+static STATE: Mutex<Option<Lockout>> = Mutex::new(None);
+
+fn state() -> std::sync::MutexGuard<'static, Option<Lockout>> {
+    STATE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+pub fn init(cfg: ChargingLockoutConfig) {
+    *CONFIG.lock().unwrap_or_else(|e| e.into_inner()) = Some(cfg);
+}
+
+pub fn config() -> ChargingLockoutConfig {
+    CONFIG.lock().unwrap_or_else(|e| e.into_inner()).clone().unwrap_or_default()
+}
+
+pub fn begin() {
+    *state() = Some(Lockout { docked: false, undocking: None, holding: false }).filter(|_| config().enabled());
+}
+
+pub fn end() {
+    *state() = None;
+}
+
+/// Whether `frame` says the robot is on the dock.
+pub fn on_dock(frame: &SensorFrame) -> bool {
+    match frame.get(CHARGING_SOURCES) {
+        Some(sources) => sources & HOME_BASE != 0,
+        None => matches!(frame.get(CHARGING_STATE), Some(1..=3)),
+    }
+}
+
+pub fn update(frame: &SensorFrame) {
+    let mut guard = state();
+    let Some(l) = guard.as_mut() else { return };
+    let docked = on_dock(frame);
+    if docked == l.docked {
+        return;
+    }
+    l.docked = docked;
+    l.holding = false;
+    if docked {
+        info!("on the dock: holding the wheels until `undock`");
+    } else {
+        info!("off the dock, driving allowed");
+    }
+}
+
+/// From the undocking behavior, every step it backs up.
+pub fn undocking() {
+    if let Some(l) = state().as_mut() {
+        l.undocking = Some(Instant::now());
+    }
+}
+
+/// Whether the lockout holds the robot, on the dock.
+pub fn docked() -> bool {
+    state().as_ref().is_some_and(|l| l.docked)
+}
+
+/// Refuse driving on the dock, for `drive`.
+pub fn check() -> Result<(), String> {
+    if !docked() {
+        return Ok(());
+    }
+    audit::record("drive_refused", "charging lockout", "drive asked for on the dock");
+    Err("the robot is on the dock: undock first (createctl undock)".into())
+}
+
+/// `wheels`, or a stop on the dock unless it is undocking straight back.
+pub fn limit(wheels: Wheels) -> Wheels {
+    let mut guard = state();
+    let Some(l) = guard.as_mut() else { return wheels };
+    let backing = wheels.left <= 0 && wheels.right <= 0 && l.undocking.is_some_and(|t| t.elapsed() < PERMIT);
+    if !l.docked || wheels == Wheels::STOP || backing {
+        l.holding = false;
+        return wheels;
+    }
+    if !l.holding {
+        l.holding = true;
+        warn!("charging lockout: holding the wheels on the dock (undock first)");
+        audit::record("drive_held", "charging lockout", "driving asked for on the dock, held at a stop");
+    }
+    Wheels::STOP
+}
+
+/// Whether the raw OI command `bytes` may be sent now: nothing that sets
+/// the robot moving on the dock, but Seek Dock (or another command having
+/// it drive itself) while undocking, which seeks the dock again from
+/// contacts that didn't charge.
+pub fn allows(bytes: &[u8]) -> bool {
+    let guard = state();
+    let Some(l) = guard.as_ref().filter(|l| l.docked) else { return true };
+    let undocking = !oi::is_drive(bytes) && l.undocking.is_some_and(|t| t.elapsed() < PERMIT);
+    !oi::moves(bytes) || undocking
+}
+
+pub fn status() -> Value {
+    let guard = state();
+    let Some(l) = guard.as_ref() else { return json!({ "enabled": config().enabled(), "locked": false }) };
+    json!({ "enabled": true, "locked": l.docked, "undocking": l.undocking.is_some_and(|t| t.elapsed() < PERMIT) })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::SystemTime;
+
+    use super::*;
+
+    const FORWARD: [u8; 5] = [oi::DRIVE_DIRECT, 0, 200, 0, 200];
+    const BACK: [u8; 5] = [oi::DRIVE_DIRECT, 0xff, 0x9c, 0xff, 0x9c];
+
+    fn frame(packets: &[(u8, i32)]) -> SensorFrame {
+        let mut frame = SensorFrame::new(SystemTime::now());
+        for (id, v) in packets {
+            frame.set(*id, *v);
+        }
+        frame
+    }
+
+    fn session(enabled: bool) -> std::sync::MutexGuard<'static, ()> {
+        let serial = crate::robot::serial();
+        init(ChargingLockoutConfig { enabled: Some(enabled), ..Default::default() });
+        begin();
+        serial
+    }
+
+    #[test]
+    fn tells_the_dock_by_the_home_base_or_the_charging_state() {
+        let cases: &[(&[(u8, i32)], bool)] = &[
+            (&[(CHARGING_SOURCES, 0b10)], true),
+            (&[(CHARGING_SOURCES, 0b11), (CHARGING_STATE, 0)], true),
+            // Charging on the internal charger isn't the dock
+            (&[(CHARGING_SOURCES, 0b01), (CHARGING_STATE, 2)], false),
+            (&[(CHARGING_STATE, 2)], true),
+            (&[(CHARGING_STATE, 4)], false),
+            (&[], false),
+        ];
+        for (packets, docked) in cases {
+            assert_eq!(on_dock(&frame(packets)), *docked, "{packets:?}");
+        }
+    }
+
+    #[test]
+    fn holds_the_robot_on_the_dock() {
+        let _serial = session(true);
+        update(&frame(&[(CHARGING_SOURCES, 0b10)]));
+        assert!(docked() && check().is_err());
+        assert_eq!(limit(Wheels::new(200, 200)), Wheels::STOP);
+        assert_eq!(limit(Wheels::new(-100, -100)), Wheels::STOP);
+        for refused in [&FORWARD[..], &BACK, &[oi::DRIVE, 0, 100, 0, 1], &[oi::SEEK_DOCK], &[oi::CLEAN], &[oi::PLAY_SCRIPT]] {
+            assert!(!allows(refused), "{refused:?}");
+        }
+        for allowed in [&[oi::DRIVE_DIRECT, 0, 0, 0, 0][..], &[oi::LEDS, 0, 0, 0], &[oi::SAFE], &[oi::PLAY, 0]] {
+            assert!(allows(allowed), "{allowed:?}");
+        }
+        update(&frame(&[(CHARGING_SOURCES, 0)]));
+        assert!(!docked() && check().is_ok());
+        assert_eq!(limit(Wheels::new(200, 200)), Wheels::new(200, 200));
+        assert!(allows(&FORWARD));
+        end();
+    }
+
+    #[test]
+    fn undocking_backs_straight_off_for_a_moment() {
+        let _serial = session(true);
+        update(&frame(&[(CHARGING_SOURCES, 0b10)]));
+        undocking();
+        assert_eq!(limit(Wheels::new(-100, -100)), Wheels::new(-100, -100));
+        assert_eq!(limit(Wheels::new(-100, 0)), Wheels::new(-100, 0));
+        assert_eq!(limit(Wheels::new(100, 100)), Wheels::STOP);
+        assert_eq!(limit(Wheels::new(-100, 100)), Wheels::STOP);
+        // Seeking the dock again, but no raw drive command
+        assert!(allows(&[oi::SEEK_DOCK]));
+        assert!(!allows(&BACK));
+        thread::sleep(PERMIT + Duration::from_millis(20));
+        assert_eq!(limit(Wheels::new(-100, -100)), Wheels::STOP);
+        assert!(!allows(&[oi::SEEK_DOCK]));
+        end();
+    }
+
+    #[test]
+    fn disabled_it_holds_nothing() {
+        let _serial = session(false);
+        update(&frame(&[(CHARGING_SOURCES, 0b10)]));
+        assert!(!docked() && check().is_ok());
+        assert!(allows(&FORWARD));
+        assert_eq!(limit(Wheels::new(200, 200)), Wheels::new(200, 200));
+    }
+}
@@ -0,0 +1,217 @@
+//! Cliff interlock: while a cliff sensor sees a drop, and for
+//! `cliff.clear_ms` after the last one stops seeing it, the wheels may not
+//! move the robot forward. Every cliff sensor sits in the front half of
+//! the round robot, so any forward motion carries it further over the edge,
+//! while turning on the spot keeps the robot's footprint where it is and
+//! backing up takes it away. The forward part of whatever is sent is cut,
+//! keeping the turn, without ramping down.
+//!
+//! It is applied last, to the speeds the session sends and to raw drive
+//! commands queued by missions and scripts, so no layer, behavior, or mode
+//! (Full mode included, where the robot no longer stops itself) gets past
+//! it, and it isn't configurable off.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+use tracing::{info, warn};
+
+use crate::audit;
+use crate::bus::Event;
+use crate::config::CliffConfig;
+use crate::motion::Wheels;
+use crate::oi::{self, SensorFrame};
+
+const CLIFFS: [(u8, &str); 4] = [(9, "left"), (10, "front_left"), (11, "front_right"), (12, "right")];
+
+struct Interlock {
+    clear: Duration,
+    /// Sensors seeing a drop in the last frame
+    seen: Vec<&'static str>,
+    /// Inhibiting: since when no sensor has seen one, if none does
+    engaged: Option<Option<Instant>>,
+    /// The frames carry the cliff packets
+    streamed: bool,
+}
+
+static CLEAR: Mutex<Duration> = Mutex::new(Duration::ZERO);
+static STATE: Mutex<Option<Interlock>> = Mutex::new(None);
+
+fn state() -> std::sync::MutexGuard<'static, Option<Interlock>> {
+    STATE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+pub fn init(cfg: CliffConfig) {
+    *CLEAR.lock().unwrap_or_else(|e| e.into_inner()) = cfg.clear();
+}
+
+pub fn begin() {
+    let clear = *CLEAR.lock().unwrap_or_else(|e| e.into_inner());
+    *state() = Some(Interlock { clear, seen: Vec::new(), engaged: None, streamed: true });
+}
+
+pub fn end() {
+    *state() = None;
+}
+
+/// Check the cliff sensors; events to publish.
+pub fn update(frame: &SensorFrame) -> Vec<Event> {
+    let mut guard = state();
+    let Some(c) = guard.as_mut() else { return Vec::new() };
+    let readings: Option<Vec<i32>> = CLIFFS.iter().map(|(id, _)| frame.get(*id)).collect();
+    let Some(readings) = readings else {
+        if c.streamed {
+            warn!("the sensor stream has no cliff packets (9-12); the cliff interlock can't see drops");
+            c.streamed = false;
+        }
+        return Vec::new();
+    };
+    c.streamed = true;
+    let seen: Vec<&'static str> = CLIFFS.iter().zip(readings).filter(|(_, v)| *v != 0).map(|((_, name), _)| *name).collect();
+    let new = seen.iter().any(|s| !c.seen.contains(s));
+    c.seen = seen;
+    if !c.seen.is_empty() {
+        let was = c.engaged.replace(None);
+        if was.is_none() || new {
+            warn!("cliff interlock: no forward motion ({})", c.seen.join(", "));
+            audit::record("cliff_interlock", "cliff sensors", &format!("cliff on {}: no forward motion", c.seen.join(", ")));
+            return vec![Event::CliffInterlock { sensors: c.seen.iter().map(|s| s.to_string()).collect() }];
+        }
+        return Vec::new();
+    }
+    let Some(since) = c.engaged.as_mut() else { return Vec::new() };
+    let since = *since.get_or_insert_with(Instant::now);
+    if since.elapsed() < c.clear {
+        return Vec::new();
+    }
+    c.engaged = None;
+    info!("cliff interlock cleared");
+    audit::record("cliff_cleared", "cliff sensors", &format!("no cliff for {:?}", c.clear));
+    vec![Event::CliffCleared]
+}
+
+fn engaged() -> bool {
+    state().as_ref().is_some_and(|c| c.engaged.is_some())
+}
+
+/// `wheels` without their forward speed while engaged.
+pub fn limit(wheels: Wheels) -> Wheels {
+    let v = (wheels.left as i32 + wheels.right as i32) / 2;
+    if v <= 0 || !engaged() {
+        return wheels;
+    }
+    Wheels::new(wheels.left as i32 - v, wheels.right as i32 - v)
+}
+
+/// Whether the raw OI command `bytes` may be sent now: not a drive command
+/// going forward, nor one having the robot drive itself, while engaged.
+pub fn allows(bytes: &[u8]) -> bool {
+    let word = |i: usize| bytes.get(i..i + 2).map(|b| i16::from_be_bytes([b[0], b[1]]) as i32);
+    let forward = match bytes.first() {
+        // Velocity, then radius, where ±1 turns on the spot
+        Some(&oi::DRIVE) => word(1).is_some_and(|v| v > 0) && !matches!(word(3), Some(1 | -1)),
+        // Drive Direct and Drive PWM: right, then left
+        Some(&oi::DRIVE_DIRECT) | Some(&oi::DRIVE_PWM) => word(1).zip(word(3)).is_some_and(|(r, l)| r + l > 0),
+        // Cleaning and seeking the dock set off forward
+        Some(op) => oi::SELF_DRIVING.contains(op),
+        None => false,
+    };
+    !forward || !engaged()
+}
+
+pub fn status() -> Value {
+    let guard = state();
+    match guard.as_ref() {
+        Some(c) => json!({ "engaged": c.engaged.is_some(), "sensors": c.seen }),
+        None => json!({ "engaged": false, "sensors": [] }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::SystemTime;
+
+    use super::*;
+
+    const FORWARD: [u8; 5] = [oi::DRIVE_DIRECT, 0, 200, 0, 200];
+    const BACK: [u8; 5] = [oi::DRIVE_DIRECT, 0xff, 0x38, 0xff, 0x38];
+    const SPIN: [u8; 5] = [oi::DRIVE, 0, 200, 0, 1];
+
+    fn cliffs(on: [i32; 4]) -> SensorFrame {
+        let mut frame = SensorFrame::new(SystemTime::now());
+        for ((id, _), v) in CLIFFS.iter().zip(on) {
+            frame.set(*id, v);
+        }
+        frame
+    }
+
+    fn session(clear_ms: u64) -> std::sync::MutexGuard<'static, ()> {
+        let serial = crate::robot::serial();
+        init(CliffConfig { clear_ms: Some(clear_ms) });
+        begin();
+        serial
+    }
+
+    #[test]
+    fn engages_on_a_drop_and_clears_after_the_delay() {
+        let _serial = session(30);
+        assert!(update(&cliffs([0; 4])).is_empty());
+        assert!(allows(&FORWARD));
+        assert!(matches!(&update(&cliffs([0, 1, 0, 0]))[..], [Event::CliffInterlock { sensors }] if sensors == &["front_left"]));
+        assert!(!allows(&FORWARD));
+        // Still seeing it: nothing new to report
+        assert!(update(&cliffs([0, 1, 0, 0])).is_empty());
+        assert!(matches!(&update(&cliffs([0, 1, 1, 0]))[..], [Event::CliffInterlock { sensors }] if sensors.len() == 2));
+        // Gone, but held for `clear_ms`
+        assert!(update(&cliffs([0; 4])).is_empty());
+        assert!(!allows(&FORWARD));
+        thread::sleep(Duration::from_millis(40));
+        assert!(matches!(&update(&cliffs([0; 4]))[..], [Event::CliffCleared]));
+        assert!(allows(&FORWARD));
+        end();
+    }
+
+    #[test]
+    fn a_drop_seen_again_restarts_the_delay() {
+        let _serial = session(30);
+        update(&cliffs([1, 0, 0, 0]));
+        update(&cliffs([0; 4]));
+        thread::sleep(Duration::from_millis(20));
+        update(&cliffs([1, 0, 0, 0]));
+        update(&cliffs([0; 4]));
+        thread::sleep(Duration::from_millis(20));
+        assert!(update(&cliffs([0; 4])).is_empty());
+        assert!(!allows(&FORWARD));
+        end();
+    }
+
+    #[test]
+    fn cuts_only_forward_motion() {
+        let _serial = session(1000);
+        update(&cliffs([0, 0, 0, 1]));
+        assert_eq!(limit(Wheels::new(200, 200)), Wheels::STOP);
+        assert_eq!(limit(Wheels::new(100, 300)), Wheels::new(-100, 100));
+        assert_eq!(limit(Wheels::new(-200, -200)), Wheels::new(-200, -200));
+        assert_eq!(limit(Wheels::new(-150, 150)), Wheels::new(-150, 150));
+        for allowed in [&BACK[..], &SPIN, &[oi::DRIVE, 0, 200, 0xff, 0xff], &[oi::LEDS, 0, 0, 0], &[oi::DRIVE_PWM, 0, 0, 0, 0]] {
+            assert!(allows(allowed), "{allowed:?}");
+        }
+        for refused in [&FORWARD[..], &[oi::DRIVE, 0, 200, 0x7f, 0xff], &[oi::DRIVE_PWM, 0, 50, 0, 50], &[oi::SEEK_DOCK], &[oi::CLEAN]] {
+            assert!(!allows(refused), "{refused:?}");
+        }
+        end();
+        // No session, nothing to hold back
+        assert_eq!(limit(Wheels::new(200, 200)), Wheels::new(200, 200));
+        assert!(allows(&FORWARD));
+    }
+
+    #[test]
+    fn frames_without_the_cliff_packets_change_nothing() {
+        let _serial = session(1000);
+        assert!(update(&SensorFrame::new(SystemTime::now())).is_empty());
+        assert!(allows(&FORWARD));
+        end();
+    }
+}
@@ -0,0 +1,431 @@
+//! The OI's commands as a model, for checking command programs (the bytes
+//! of mission `oi` steps, scripts' `oi` calls, and on-robot OI scripts)
+//! before any of them is sent: every opcode has to exist, come with all
+//! its data bytes, each in range, and be sent in a mode that takes it.
+//! The robot ignores a command in the wrong mode, and reads a bad opcode
+//! or a short command as the start of something else, so a program that
+//! fails is refused as a whole, with a structured error for each problem.
+//!
+//! Opcodes are those of the Create 1 and Create 2 OIs together; the
+//! script and wait commands, digital outputs, and Send IR are Create 1's.
+
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::oi;
+
+/// A program's problem: where (the offset of the byte), in which command.
+#[derive(Debug, Clone, Serialize)]
+pub struct Invalid {
+    pub offset: usize,
+    pub opcode: Option<u8>,
+    pub command: Option<&'static str>,
+    pub error: String,
+}
+
+impl fmt::Display for Invalid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.command, self.opcode) {
+            (Some(name), Some(op)) => write!(f, "byte {}: {name} ({op}): {}", self.offset, self.error),
+            _ => write!(f, "byte {}: {}", self.offset, self.error),
+        }
+    }
+}
+
+/// All the problems, one after the other.
+pub fn describe(problems: &[Invalid]) -> String {
+    problems.iter().map(Invalid::to_string).collect::<Vec<_>>().join("; ")
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Unsigned,
+    Signed,
+    /// Two bytes, big-endian, signed
+    Word,
+    /// Drive's radius: a word in range, or straight (32767, -32768)
+    Radius,
+}
+
+#[derive(Clone, Copy)]
+struct Field {
+    name: &'static str,
+    kind: Kind,
+    min: i32,
+    max: i32,
+}
+
+const fn f(name: &'static str, kind: Kind, min: i32, max: i32) -> Field {
+    Field { name, kind, min, max }
+}
+
+#[derive(Clone, Copy)]
+enum Args {
+    Fixed(&'static [Field]),
+    /// Number, length, then that many notes and durations
+    Song,
+    /// Count, then that many packet ids
+    Packets,
+    /// Count, then that many bytes of a program the robot keeps
+    Script,
+}
+
+/// What mode a command has to be sent in.
+#[derive(Clone, Copy, PartialEq)]
+enum Needs {
+    Any,
+    /// Any once Start was sent
+    Started,
+    /// Safe or Full
+    Control,
+}
+
+#[derive(Clone, Copy)]
+struct Opcode {
+    code: u8,
+    name: &'static str,
+    args: Args,
+    needs: Needs,
+    /// The mode it leaves the robot in
+    then: Option<i32>,
+}
+
+const OFF: i32 = 0;
+const PASSIVE: Option<i32> = Some(oi::MODE_PASSIVE);
+const NONE: &[Field] = &[];
+const BYTE: Field = f("value", Kind::Unsigned, 0, 255);
+const SPEED: i32 = crate::motion::MAX_SPEED as i32;
+
+const fn op(code: u8, name: &'static str, args: Args, needs: Needs, then: Option<i32>) -> Opcode {
+    Opcode { code, name, args, needs, then }
+}
+
+const OPCODES: [Opcode; 36] = [
+    op(oi::START, "start", Args::Fixed(NONE), Needs::Any, PASSIVE),
+    op(129, "baud", Args::Fixed(&[f("baud code", Kind::Unsigned, 0, 11)]), Needs::Started, None),
+    op(130, "control", Args::Fixed(NONE), Needs::Started, Some(oi::MODE_SAFE)),
+    op(oi::SAFE, "safe", Args::Fixed(NONE), Needs::Started, Some(oi::MODE_SAFE)),
+    op(oi::FULL, "full", Args::Fixed(NONE), Needs::Started, Some(oi::MODE_FULL)),
+    op(oi::POWER, "power", Args::Fixed(NONE), Needs::Started, Some(OFF)),
+    op(oi::SPOT, "spot", Args::Fixed(NONE), Needs::Started, PASSIVE),
+    op(oi::CLEAN, "clean", Args::Fixed(NONE), Needs::Started, PASSIVE),
+    op(oi::MAX, "max", Args::Fixed(NONE), Needs::Started, PASSIVE),
+    op(oi::DRIVE, "drive", Args::Fixed(&[f("velocity", Kind::Word, -SPEED, SPEED), f("radius", Kind::Radius, -2000, 2000)]), Needs::Control, None),
+    op(138, "motors", Args::Fixed(&[f("motor bits", Kind::Unsigned, 0, 31)]), Needs::Control, None),
+    op(oi::LEDS, "leds", Args::Fixed(&[f("led bits", Kind::Unsigned, 0, 15), f("power color", Kind::Unsigned, 0, 255), f("power intensity", Kind::Unsigned, 0, 255)]), Needs::Control, None),
+    op(oi::SONG, "song", Args::Song, Needs::Started, None),
+    op(oi::PLAY, "play", Args::Fixed(&[f("song number", Kind::Unsigned, 0, 15)]), Needs::Control, None),
+    op(oi::SENSORS, "sensors", Args::Fixed(&[f("packet id", Kind::Unsigned, 0, 255)]), Needs::Started, None),
+    op(oi::SEEK_DOCK, "seek dock", Args::Fixed(NONE), Needs::Started, PASSIVE),
+    op(144, "pwm motors", Args::Fixed(&[f("main brush", Kind::Signed, -127, 127), f("side brush", Kind::Signed, -127, 127), f("vacuum", Kind::Unsigned, 0, 127)]), Needs::Control, None),
+    op(oi::DRIVE_DIRECT, "drive direct", Args::Fixed(&[f("right velocity", Kind::Word, -SPEED, SPEED), f("left velocity", Kind::Word, -SPEED, SPEED)]), Needs::Control, None),
+    op(oi::DRIVE_PWM, "drive pwm", Args::Fixed(&[f("right pwm", Kind::Word, -255, 255), f("left pwm", Kind::Word, -255, 255)]), Needs::Control, None),
+    op(147, "digital outputs", Args::Fixed(&[f("output bits", Kind::Unsigned, 0, 7)]), Needs::Control, None),
+    op(oi::STREAM, "stream", Args::Packets, Needs::Started, None),
+    op(149, "query list", Args::Packets, Needs::Started, None),
+    op(oi::PAUSE_RESUME_STREAM, "pause/resume stream", Args::Fixed(&[f("state", Kind::Unsigned, 0, 1)]), Needs::Started, None),
+    op(151, "send ir", Args::Fixed(&[BYTE]), Needs::Control, None),
+    op(oi::SCRIPT, "script", Args::Script, Needs::Started, None),
+    op(oi::PLAY_SCRIPT, "play script", Args::Fixed(NONE), Needs::Started, None),
+    op(154, "show script", Args::Fixed(NONE), Needs::Started, None),
+    op(155, "wait time", Args::Fixed(&[f("tenths of a second", Kind::Unsigned, 0, 255)]), Needs::Started, None),
+    op(156, "wait distance", Args::Fixed(&[f("distance", Kind::Word, i16::MIN as i32, i16::MAX as i32)]), Needs::Started, None),
+    op(157, "wait angle", Args::Fixed(&[f("angle", Kind::Word, i16::MIN as i32, i16::MAX as i32)]), Needs::Started, None),
+    op(158, "wait event", Args::Fixed(&[f("event", Kind::Signed, -20, 20)]), Needs::Started, None),
+    op(162, "scheduling leds", Args::Fixed(&[f("weekday bits", Kind::Unsigned, 0, 127), f("scheduling bits", Kind::Unsigned, 0, 31)]), Needs::Control, None),
+    op(163, "digit leds raw", Args::Fixed(&[f("digit 3", Kind::Unsigned, 0, 127), f("digit 2", Kind::Unsigned, 0, 127), f("digit 1", Kind::Unsigned, 0, 127), f("digit 0", Kind::Unsigned, 0, 127)]), Needs::Control, None),
+    op(164, "digit leds ascii", Args::Fixed(&[f("digit 3", Kind::Unsigned, 32, 126), f("digit 2", Kind::Unsigned, 32, 126), f("digit 1", Kind::Unsigned, 32, 126), f("digit 0", Kind::Unsigned, 32, 126)]), Needs::Control, None),
+    op(168, "set day/time", Args::Fixed(&[f("day", Kind::Unsigned, 0, 6), f("hour", Kind::Unsigned, 0, 23), f("minute", Kind::Unsigned, 0, 59)]), Needs::Started, None),
+    op(173, "stop", Args::Fixed(NONE), Needs::Started, Some(OFF)),
+];
+
+/// Songs hold at most this many notes, scripts this many bytes.
+const MAX_NOTES: i32 = 16;
+const MAX_SCRIPT: i32 = 100;
+
+fn opcode(code: u8) -> Option<&'static Opcode> {
+    OPCODES.iter().find(|o| o.code == code)
+}
+
+/// Whether `id` is a sensor packet or group
+fn packet(id: u8) -> bool {
+    id <= 6 || (oi::FIRST_PACKET..=oi::LAST_PACKET).contains(&id) || matches!(id, 100 | 101 | 106 | 107)
+}
+
+/// Program bytes as given in a file or script: each has to be 0-255.
+pub fn bytes(values: &[i64]) -> Result<Vec<u8>, Vec<Invalid>> {
+    let problems: Vec<Invalid> = values
+        .iter()
+        .enumerate()
+        .filter(|(_, v)| u8::try_from(**v).is_err())
+        .map(|(offset, v)| Invalid { offset, opcode: None, command: None, error: format!("{v} isn't a byte (0-255)") })
+        .collect();
+    if problems.is_empty() {
+        Ok(values.iter().map(|v| *v as u8).collect())
+    } else {
+        Err(problems)
+    }
+}
+
+/// Check `program`, sent with the robot in `mode` (None: not known, and
+/// then only the modes the program itself sets are checked); its
+/// commands, one by one.
+pub fn validate(program: &[u8], mode: Option<i32>) -> Result<Vec<Vec<u8>>, Vec<Invalid>> {
+    Checker { program, problems: Vec::new() }.run(mode)
+}
+
+/// Check the program an OI Script command stores, played with the robot
+/// in `mode`: the commands it can hold, no more than the robot keeps.
+/// The robot runs a script past the daemon's safeties, which only see
+/// Play Script go by, so none may drive the wheels, have the robot drive
+/// itself, or enter Full mode.
+fn validate_script(program: &[u8], mode: Option<i32>) -> Result<Vec<Vec<u8>>, Vec<Invalid>> {
+    let mut problems = Vec::new();
+    if program.is_empty() || program.len() > MAX_SCRIPT as usize {
+        problems.push(Invalid { offset: 0, opcode: None, command: None, error: format!("a script has 1 to {MAX_SCRIPT} bytes, not {}", program.len()) });
+    }
+    for (i, error) in script_opcodes(program) {
+        problems.push(Invalid { offset: i, opcode: Some(program[i]), command: opcode(program[i]).map(|o| o.name), error: error.into() });
+    }
+    Checker { program, problems }.run(mode)
+}
+
+/// Where a script's program has a command no script may hold, and why,
+/// as far as its commands can be told apart.
+fn script_opcodes(program: &[u8]) -> Vec<(usize, &'static str)> {
+    let mut found = Vec::new();
+    let mut i = 0;
+    while i < program.len() {
+        let code = program[i];
+        let error = match code {
+            oi::FULL => Some("a script can't enter Full mode"),
+            oi::SCRIPT | oi::PLAY_SCRIPT => Some("a script can't store or play another"),
+            _ if oi::DRIVES.contains(&code) => Some("a script can't drive the wheels"),
+            _ if oi::SELF_DRIVING.contains(&code) => Some("a script can't have the robot clean or seek the dock"),
+            _ => None,
+        };
+        found.extend(error.map(|e| (i, e)));
+        let Some(len) = opcode(code).and_then(|o| length(o, &program[i + 1..])) else { break };
+        i += 1 + len;
+    }
+    found
+}
+
+/// The data bytes following `op`, as far as `rest` tells.
+fn length(op: &Opcode, rest: &[u8]) -> Option<usize> {
+    match op.args {
+        Args::Fixed(fields) => Some(fields.iter().map(|f| if matches!(f.kind, Kind::Word | Kind::Radius) { 2 } else { 1 }).sum()),
+        Args::Song => rest.get(1).map(|n| 2 + 2 * *n as usize),
+        Args::Packets | Args::Script => rest.first().map(|n| 1 + *n as usize),
+    }
+}
+
+/// Whether the command `code` switches the OI's mode (or its baud rate).
+pub fn switches_mode(code: u8) -> bool {
+    code == 129 || opcode(code).is_some_and(|o| o.then.is_some())
+}
+
+/// Whether `code` is an OI opcode.
+pub fn is_opcode(code: u8) -> bool {
+    opcode(code).is_some()
+}
+
+/// How long the command at the start of `bytes` is, opcode and data; None
+/// while it hasn't all come yet, or if it doesn't start with an opcode.
+pub fn command_length(bytes: &[u8]) -> Option<usize> {
+    let (&code, rest) = bytes.split_first()?;
+    let n = 1 + length(opcode(code)?, rest)?;
+    (n <= bytes.len()).then_some(n)
+}
+
+struct Checker<'a> {
+    program: &'a [u8],
+    problems: Vec<Invalid>,
+}
+
+impl Checker<'_> {
+    fn problem(&mut self, offset: usize, op: Option<&Opcode>, error: String) {
+        self.problems.push(Invalid { offset, opcode: op.map(|o| o.code), command: op.map(|o| o.name), error });
+    }
+
+    fn run(mut self, mut mode: Option<i32>) -> Result<Vec<Vec<u8>>, Vec<Invalid>> {
+        let mut commands = Vec::new();
+        let mut i = 0;
+        while i < self.program.len() {
+            let code = self.program[i];
+            let Some(op) = opcode(code) else {
+                // Nothing after it can be told apart from data
+                self.problem(i, None, format!("{code} isn't an OI opcode"));
+                break;
+            };
+            let rest = &self.program[i + 1..];
+            let Some(len) = length(op, rest).filter(|len| *len <= rest.len()) else {
+                let wanted = length(op, rest).map_or("more".to_string(), |n| n.to_string());
+                self.problem(i, Some(op), format!("needs {wanted} data bytes, {} left", rest.len()));
+                break;
+            };
+            match (op.needs, mode) {
+                (Needs::Started, Some(OFF)) | (Needs::Control, Some(OFF)) => self.problem(i, Some(op), "the OI isn't started (send start first)".into()),
+                (Needs::Control, Some(m)) if m == oi::MODE_PASSIVE => self.problem(i, Some(op), "needs Safe or Full mode; the robot would be in Passive".into()),
+                _ => {}
+            }
+            self.data(i, op, &rest[..len], mode);
+            mode = op.then.or(mode);
+            commands.push(self.program[i..=i + len].to_vec());
+            i += 1 + len;
+        }
+        if self.problems.is_empty() {
+            Ok(commands)
+        } else {
+            Err(self.problems)
+        }
+    }
+
+    /// Check the data bytes of `op` at `at`.
+    fn data(&mut self, at: usize, op: &Opcode, data: &[u8], mode: Option<i32>) {
+        match op.args {
+            Args::Fixed(fields) => {
+                let mut j = 0;
+                for field in fields {
+                    let value = match field.kind {
+                        Kind::Unsigned => data[j] as i32,
+                        Kind::Signed => data[j] as i8 as i32,
+                        Kind::Word | Kind::Radius => i16::from_be_bytes([data[j], data[j + 1]]) as i32,
+                    };
+                    let straight = field.kind == Kind::Radius && (value == i16::MAX as i32 || value == i16::MIN as i32);
+                    if !straight && !(field.min..=field.max).contains(&value) {
+                        let error = format!("{} {value} out of range ({}-{})", field.name, field.min, field.max);
+                        self.problem(at + 1 + j, Some(op), error);
+                    }
+                    if field.name == "packet id" && !packet(data[j]) {
+                        self.problem(at + 1 + j, Some(op), format!("no sensor packet {}", data[j]));
+                    }
+                    j += if matches!(field.kind, Kind::Word | Kind::Radius) { 2 } else { 1 };
+                }
+            }
+            Args::Song => {
+                if data[0] > 15 {
+                    self.problem(at + 1, Some(op), format!("song number {} out of range (0-15)", data[0]));
+                }
+                if data[1] == 0 || data[1] as i32 > MAX_NOTES {
+                    self.problem(at + 2, Some(op), format!("a song has 1 to {MAX_NOTES} notes, not {}", data[1]));
+                }
+            }
+            Args::Packets => {
+                for (j, id) in data[1..].iter().enumerate() {
+                    if !packet(*id) {
+                        self.problem(at + 2 + j, Some(op), format!("no sensor packet {id}"));
+                    }
+                }
+            }
+            Args::Script => {
+                if let Err(problems) = validate_script(&data[1..], mode) {
+                    self.problems.extend(problems.into_iter().map(|p| Invalid { offset: at + 2 + p.offset, ..p }));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The offsets and errors `program` is refused with.
+    fn refusal(program: &[u8], mode: Option<i32>) -> Vec<(usize, String)> {
+        match validate(program, mode) {
+            Ok(commands) => panic!("{program:?} passed as {commands:?}"),
+            Err(problems) => problems.into_iter().map(|p| (p.offset, p.error)).collect(),
+        }
+    }
+
+    #[test]
+    fn accepts_safe_mode_programs() {
+        let cases: &[(&[u8], Option<i32>, usize)] = &[
+            (&[128, 131, 145, 0, 100, 0, 100], Some(OFF), 3),
+            (&[137, 0, 200, 0x7f, 0xff], Some(oi::MODE_SAFE), 1),
+            (&[137, 0xff, 0x38, 0x80, 0x00], Some(oi::MODE_SAFE), 1),
+            (&[139, 10, 128, 255, 141, 3], Some(oi::MODE_SAFE), 2),
+            (&[140, 0, 2, 60, 32, 62, 32], Some(oi::MODE_PASSIVE), 1),
+            (&[148, 3, 7, 100, 58, 150, 0], Some(oi::MODE_PASSIVE), 2),
+            (&[128, 131, 152, 4, 139, 10, 128, 255, 153], Some(OFF), 4),
+            (&[128, 131, 152, 6, oi::PLAY, 0, 155, 10, oi::PLAY, 1, 153], Some(OFF), 4),
+            (&[131, 145, 0, 0, 0, 0, 128], None, 3),
+            (&[], None, 0),
+        ];
+        for (program, mode, count) in cases {
+            match validate(program, *mode) {
+                Ok(commands) => {
+                    assert_eq!(commands.len(), *count, "{program:?}");
+                    assert_eq!(commands.concat(), *program);
+                }
+                Err(problems) => panic!("{program:?} refused: {}", describe(&problems)),
+            }
+        }
+    }
+
+    #[test]
+    fn refuses_scripts_that_enter_full_mode_or_nest() {
+        let cases: &[(&[u8], usize, &str)] = &[
+            (&[128, 152, 1, oi::FULL], 3, "a script can't enter Full mode"),
+            (&[128, 131, 152, 6, 145, 0, 100, 0, 100, oi::FULL], 9, "a script can't enter Full mode"),
+            (&[128, 152, 3, 152, 1, 153], 3, "a script can't store or play another"),
+            (&[128, 152, 1, 153], 3, "a script can't store or play another"),
+            (&[128, 131, 152, 7, 145, 0, 100, 0, 100, 155, 10, 153], 4, "a script can't drive the wheels"),
+            (&[128, 131, 152, 5, 137, 0, 100, 0x80, 0, 153], 4, "a script can't drive the wheels"),
+            (&[128, 131, 152, 5, 146, 0, 100, 0, 100, 153], 4, "a script can't drive the wheels"),
+            (&[128, 131, 152, 1, 135, 153], 4, "a script can't have the robot clean or seek the dock"),
+            (&[128, 131, 152, 3, 155, 10, 143, 153], 6, "a script can't have the robot clean or seek the dock"),
+            (&[128, 152, 2, 134, 136], 4, "a script can't have the robot clean or seek the dock"),
+        ];
+        for (program, offset, error) in cases {
+            let problems = refusal(program, Some(OFF));
+            assert!(problems.contains(&(*offset, error.to_string())), "{program:?}: {problems:?}");
+        }
+        assert_eq!(refusal(&[128, 152, 0], Some(OFF)), [(3, "a script has 1 to 100 bytes, not 0".to_string())]);
+    }
+
+    #[test]
+    fn refuses_truncated_commands_and_unknown_opcodes() {
+        let cases: &[(&[u8], usize, &str)] = &[
+            (&[128, 131, 145, 0, 100], 2, "needs 4 data bytes, 2 left"),
+            (&[137], 0, "needs 4 data bytes, 0 left"),
+            (&[140, 0], 0, "needs more data bytes, 1 left"),
+            (&[140, 0, 2, 60, 32], 0, "needs 6 data bytes, 4 left"),
+            (&[148, 3, 7], 0, "needs 4 data bytes, 2 left"),
+            (&[128, 152, 4, 145, 0, 100], 1, "needs 5 data bytes, 4 left"),
+            (&[128, 131, 200, 145, 0, 0, 0, 0], 2, "200 isn't an OI opcode"),
+            (&[0], 0, "0 isn't an OI opcode"),
+            (&[128, 152, 2, 156, 0], 3, "needs 2 data bytes, 1 left"),
+        ];
+        for (program, offset, error) in cases {
+            assert_eq!(refusal(program, Some(oi::MODE_SAFE)), [(*offset, error.to_string())], "{program:?}");
+        }
+    }
+
+    #[test]
+    fn refuses_commands_in_the_wrong_mode_or_out_of_range() {
+        let cases: &[(&[u8], Option<i32>, usize, &str)] = &[
+            (&[145, 0, 100, 0, 100], Some(OFF), 0, "the OI isn't started (send start first)"),
+            (&[128, 145, 0, 100, 0, 100], Some(OFF), 1, "needs Safe or Full mode; the robot would be in Passive"),
+            (&[131, 135, 137, 0, 100, 0, 0], None, 2, "needs Safe or Full mode; the robot would be in Passive"),
+            (&[145, 0x02, 0x58, 0, 0], Some(oi::MODE_SAFE), 1, "right velocity 600 out of range (-500-500)"),
+            (&[137, 0, 0, 0x09, 0x00], Some(oi::MODE_SAFE), 3, "radius 2304 out of range (-2000-2000)"),
+            (&[142, 99], Some(oi::MODE_PASSIVE), 1, "no sensor packet 99"),
+            (&[148, 2, 7, 99], Some(oi::MODE_PASSIVE), 3, "no sensor packet 99"),
+            (&[140, 16, 1, 60, 32], Some(oi::MODE_PASSIVE), 1, "song number 16 out of range (0-15)"),
+            (&[128, 152, 4, 139, 0, 0, 0], Some(OFF), 3, "needs Safe or Full mode; the robot would be in Passive"),
+        ];
+        for (program, mode, offset, error) in cases {
+            assert_eq!(refusal(program, *mode), [(*offset, error.to_string())], "{program:?}");
+        }
+    }
+
+    #[test]
+    fn refuses_values_that_arent_bytes() {
+        assert_eq!(bytes(&[128, 131, 255, 0]).unwrap(), [128, 131, 255, 0]);
+        let problems = bytes(&[128, 256, -1]).unwrap_err();
+        assert_eq!(problems.iter().map(|p| p.offset).collect::<Vec<_>>(), [1, 2]);
+    }
+}
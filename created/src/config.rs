@@ -0,0 +1,2529 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use tracing::Level;
+use serde::{Deserialize, Serialize};
+
+/// Environment variable selecting a named profile when `--profile` is not given.
+pub const PROFILE_ENV: &str = "CREATED_PROFILE";
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct SerialConfig {
+    /// Serial device path (e.g. /dev/ttyUSB0). If not set, autodetects.
+    pub path: Option<String>,
+    /// Baud rate (default 57600 for Create 1), tried first
+    pub baud: Option<u32>,
+    /// Try the other OI baud rates when the robot doesn't answer at `baud` (default true)
+    pub auto_baud: Option<bool>,
+    /// Watch udev/kernel hotplug events for a port instead of rescanning every `power.rescan_s` (default true)
+    pub hotplug: Option<bool>,
+    /// Only autodetect USB adapters with one of these vendor IDs or vendor:product pairs, in hex (e.g. "0403:6001")
+    pub usb_ids: Option<Vec<String>>,
+    /// Only autodetect USB adapters with one of these serial numbers
+    pub usb_serials: Option<Vec<String>>,
+    /// Make sure a port answers as a robot before claiming it (default true)
+    pub probe: Option<bool>,
+    /// Lock the port with a UUCP lock file, honoring other programs' (default true)
+    pub lock: Option<bool>,
+    /// Where lock files go (default /var/lock)
+    pub lock_dir: Option<String>,
+    /// How long after a lost link the same robot connecting again picks up where it left off, in seconds (default 30; 0 never)
+    pub resume_s: Option<f64>,
+    /// Pulse the Create 2's BRC pin through this modem line to wake it when a port doesn't answer: rts, dtr, both, or off (default)
+    pub wake: Option<WakeLine>,
+    /// Set an FTDI adapter's latency timer to this many ms while the port is claimed (1-255; default: leave it, usually 16)
+    pub latency_timer_ms: Option<u8>,
+    /// Robot names by adapter serial number, written by `createctl alias set` (default /var/lib/created/aliases.json)
+    pub aliases_path: Option<String>,
+}
+
+/// The modem line wired to the Create 2's BRC (baud rate change) pin, which
+/// wakes it from sleep when pulsed low.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum WakeLine {
+    #[default]
+    Off,
+    Rts,
+    Dtr,
+    Both,
+}
+
+/// The baud rates the OI runs at: the Create's default, the Create 2's,
+/// and the slower rate either can be switched to (Play held at power on,
+/// the baud rate change pin)
+const OI_BAUDS: [u32; 3] = [57_600, 115_200, 19_200];
+
+impl SerialConfig {
+    pub fn probe(&self) -> bool {
+        self.probe.unwrap_or(true)
+    }
+
+    /// Baud rates to connect at, `baud` first; the others only with probing
+    /// to tell which one answers.
+    pub fn bauds(&self) -> Vec<u32> {
+        let first = self.baud.unwrap_or(OI_BAUDS[0]);
+        let mut bauds = vec![first];
+        if self.auto_baud.unwrap_or(true) && self.probe() {
+            bauds.extend(OI_BAUDS.into_iter().filter(|b| *b != first));
+        }
+        bauds
+    }
+
+    pub fn resume(&self) -> Duration {
+        Duration::from_secs_f64(self.resume_s.unwrap_or(30.0).max(0.0))
+    }
+
+    pub fn aliases_path(&self) -> PathBuf {
+        PathBuf::from(self.aliases_path.as_deref().unwrap_or("/var/lib/created/aliases.json"))
+    }
+
+    /// Where to lock ports, unless `lock = false` or on Windows, which
+    /// opens a COM port for one program at a time anyway.
+    pub fn lock_dir(&self) -> Option<PathBuf> {
+        (cfg!(unix) && self.lock.unwrap_or(true)).then(|| PathBuf::from(self.lock_dir.as_deref().unwrap_or("/var/lock")))
+    }
+}
+
+#[derive(Debug, Deserialize, Default)]
+pub struct Config {
+    /// Log interval in milliseconds
+    pub interval_ms: Option<u64>,
+    /// Optional message to log instead of default
+    pub message: Option<String>,
+    /// Serial configuration for iRobot Create
+    pub serial: Option<SerialConfig>,
+    /// Log line format: "text" (default) or "json"
+    pub log_format: Option<LogFormat>,
+    /// Identifier included in structured logs (defaults to the hostname)
+    pub robot_id: Option<String>,
+    /// Logging to a rotating file in addition to (or instead of) stderr
+    pub log_file: Option<LogFileConfig>,
+    /// SQLite journal of daemon events
+    pub journal: Option<JournalConfig>,
+    /// Daily activity reports built from the journal
+    pub report: Option<ReportConfig>,
+    /// Log natively to journald instead of stderr (default: when started by systemd)
+    pub journald: Option<bool>,
+    /// Sensor streaming from the robot
+    pub sensors: Option<SensorsConfig>,
+    /// Recording of sensor data to disk
+    pub record: Option<RecordConfig>,
+    /// In-memory history dumped on panic or session failure
+    pub flight_recorder: Option<FlightRecorderConfig>,
+    /// Hexdump of all serial traffic
+    pub sniffer: Option<SnifferConfig>,
+    /// Control socket used by createctl
+    pub control: Option<ControlConfig>,
+    /// InfluxDB line protocol sink
+    pub influx: Option<InfluxConfig>,
+    /// Live telemetry streams over TCP and UDP
+    pub telemetry: Option<TelemetryConfig>,
+    /// Battery capacity and cycle history
+    pub battery: Option<BatteryConfig>,
+    /// Returning to the dock on a low battery
+    pub recharge: Option<RechargeConfig>,
+    /// Escape maneuvers on cliffs, wheel drops, and bumps
+    pub reflexes: Option<ReflexesConfig>,
+    /// Keep-in area for the pose estimate
+    pub geofence: Option<GeofenceConfig>,
+    /// Route runs at set times, docking in between
+    pub patrol: Option<PatrolConfig>,
+    /// Slowing and stopping on the light bumpers, short of contact
+    pub virtual_bumper: Option<VirtualBumperConfig>,
+    /// Noticing wheels that are driven but don't get anywhere
+    pub stuck: Option<StuckConfig>,
+    /// Lowering the top speed as the battery sags, the wheels slip, or obstacles come near
+    pub governor: Option<GovernorConfig>,
+    /// Driving with a locally attached gamepad
+    pub gamepad: Option<GamepadConfig>,
+    /// Limits applied to requested wheel motion
+    pub motion: Option<MotionConfig>,
+    /// Dead-reckoning pose estimate
+    pub pose: Option<PoseConfig>,
+    /// Autonomous behaviors
+    pub behavior: Option<BehaviorConfig>,
+    /// Melodies converted from files and played on the robot
+    pub songs: Option<SongsConfig>,
+    /// Short tunes announcing daemon events
+    pub beeps: Option<BeepsConfig>,
+    /// What the robot's LEDs show
+    pub leds: Option<LedsConfig>,
+    /// Sending text in Morse code
+    pub morse: Option<MorseConfig>,
+    /// Control commands run by the robot's buttons
+    pub buttons: Option<ButtonsConfig>,
+    /// Quiet hours
+    pub quiet: Option<QuietConfig>,
+    /// Software emergency stop
+    pub estop: Option<EstopConfig>,
+    /// Cliff interlock
+    pub cliff: Option<CliffConfig>,
+    /// Cutting motors that draw too much current
+    pub overcurrent: Option<OvercurrentConfig>,
+    /// Slowing down, stopping, and pausing charging on a hot battery
+    pub battery_temperature: Option<BatteryTemperatureConfig>,
+    /// What the daemon may do to the robot at all
+    pub safety: Option<SafetyConfig>,
+    /// Stopping on a wheel drop
+    pub wheel_drop: Option<WheelDropConfig>,
+    /// Log of safety interventions
+    pub audit: Option<AuditConfig>,
+    /// Holding the wheels while the robot is on the dock
+    pub charging_lockout: Option<ChargingLockoutConfig>,
+    /// Stopping for good at a critical battery voltage
+    pub low_voltage: Option<LowVoltageConfig>,
+    /// Running on a battery-powered host
+    pub power: Option<PowerConfig>,
+}
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct SensorsConfig {
+    /// Packet or group ids to stream (default [6]: packets 7-42, valid on Create 1 and 2)
+    pub stream: Option<Vec<u8>>,
+}
+
+impl SensorsConfig {
+    pub fn stream(&self) -> Vec<u8> {
+        self.stream.clone().unwrap_or_else(|| vec![6])
+    }
+}
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct RecordConfig {
+    /// CSV recorder
+    pub csv: Option<CsvConfig>,
+    /// MCAP recorder (sensor frames, commands, and events)
+    pub mcap: Option<McapConfig>,
+}
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct CsvConfig {
+    /// Enable the recorder (default false)
+    pub enabled: Option<bool>,
+    /// Output directory (default /var/lib/created/recordings)
+    pub dir: Option<String>,
+    /// Start a new file after this many minutes (default 60)
+    pub rotate_minutes: Option<u64>,
+    /// Start a new file once the current one reaches this size (default 16 MiB)
+    pub max_file_mb: Option<u64>,
+    /// Oldest files beyond this count are deleted (default 48)
+    pub max_files: Option<usize>,
+    /// Record at most this many frames per second (default: every frame)
+    pub rate_hz: Option<f64>,
+    /// Packet names to record (default: everything streamed)
+    pub fields: Option<Vec<String>>,
+}
+
+impl CsvConfig {
+    pub fn enabled(&self) -> bool {
+        self.enabled.unwrap_or(false)
+    }
+
+    pub fn dir(&self) -> PathBuf {
+        PathBuf::from(self.dir.as_deref().unwrap_or("/var/lib/created/recordings"))
+    }
+
+    pub fn rotate_after(&self) -> Duration {
+        Duration::from_secs(self.rotate_minutes.unwrap_or(60).max(1) * 60)
+    }
+
+    pub fn max_file_bytes(&self) -> u64 {
+        self.max_file_mb.unwrap_or(16).max(1) * 1024 * 1024
+    }
+
+    pub fn max_files(&self) -> usize {
+        self.max_files.unwrap_or(48).max(1)
+    }
+}
+
+#[derive(Debug, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human-readable env_logger lines
+    #[default]
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
+impl Config {
+    pub fn interval(&self) -> Duration {
+        Duration::from_millis(self.interval_ms.unwrap_or(5_000))
+    }
+
+    pub fn message(&self) -> &str {
+        self.message.as_deref().unwrap_or("hello world")
+    }
+
+    pub fn log_format(&self) -> LogFormat {
+        self.log_format.unwrap_or_default()
+    }
+
+    pub fn robot_id(&self) -> String {
+        match &self.robot_id {
+            Some(id) => id.clone(),
+            None => hostname().unwrap_or_else(|| "created".to_string()),
+        }
+    }
+}
+
+/// Messages produced while loading the config. The config is read before the
+/// logger exists (it decides the log format), so these are logged afterwards.
+pub type Diagnostics = Vec<(Level, String)>;
+
+/// Load the config file, applying the named profile (if any) on top of the
+/// top-level settings.
+pub fn load_config(profile: Option<&str>) -> (Config, Diagnostics) {
+    let mut diags = Diagnostics::new();
+    let Some(path) = find_config_file() else {
+        diags.push((Level::WARN, "no config file found; using defaults".into()));
+        if let Some(name) = profile {
+            diags.push((Level::WARN, format!("profile \"{name}\" requested but there is no config file to select it from")));
+        }
+        return (Config::default(), diags);
+    };
+    let cfg = match read_toml::<toml::Table>(&path).and_then(|table| resolve_profile(table, profile, &mut diags)) {
+        Ok(cfg) => {
+            diags.push((Level::INFO, format!("loaded config from {}", path.display())));
+            cfg
+        }
+        Err(e) => {
+            diags.push((Level::ERROR, format!("failed to parse config at {}: {e}", path.display())));
+            Config::default()
+        }
+    };
+    (cfg, diags)
+}
+
+/// Merge `[profile.<name>]` over the top-level table and deserialize the result.
+///
+/// Profiles only need to list the keys they change; nested tables such as
+/// `[profile.demo.serial]` are merged key by key.
+fn resolve_profile(mut table: toml::Table, profile: Option<&str>, diags: &mut Diagnostics) -> Result<Config, String> {
+    let mut profiles = match table.remove("profile") {
+        Some(toml::Value::Table(t)) => t,
+        Some(_) => return Err("`profile` must be a table of named profiles".into()),
+        None => toml::Table::new(),
+    };
+    if let Some(name) = profile {
+        match profiles.remove(name) {
+            Some(toml::Value::Table(overlay)) => {
+                diags.push((Level::INFO, format!("using config profile \"{name}\"")));
+                merge_tables(&mut table, overlay);
+            }
+            Some(_) => return Err(format!("profile \"{name}\" must be a table")),
+            None => {
+                let mut known: Vec<&String> = profiles.keys().collect();
+                known.sort();
+                diags.push((Level::WARN, format!("profile \"{name}\" not found (known: {known:?}); using top-level settings")));
+            }
+        }
+    }
+    Config::deserialize(toml::Value::Table(table)).map_err(|e| format!("toml: {e}"))
+}
+
+fn merge_tables(base: &mut toml::Table, overlay: toml::Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(b)), toml::Value::Table(o)) => merge_tables(b, o),
+            (_, v) => {
+                base.insert(key, v);
+            }
+        }
+    }
+}
+
+pub fn read_toml<T: for<'de> serde::Deserialize<'de>>(path: &Path) -> Result<T, String> {
+    let mut f = fs::File::open(path).map_err(|e| format!("open: {e}"))?;
+    let mut s = String::new();
+    f.read_to_string(&mut s).map_err(|e| format!("read: {e}"))?;
+    toml::from_str(&s).map_err(|e| format!("toml: {e}"))
+}
+
+fn find_config_file() -> Option<PathBuf> {
+    // 1) Explicit path via env var
+    if let Ok(p) = env::var("CREATED_CONFIG") {
+        let pb = PathBuf::from(p);
+        if pb.is_file() {
+            return Some(pb);
+        }
+    }
+
+    // 2) XDG config home
+    if let Ok(xdg) = env::var("XDG_CONFIG_HOME") {
+        let p = Path::new(&xdg).join("created").join("config.toml");
+        if p.is_file() {
+            return Some(p);
+        }
+    }
+
+    // 3) ~/.config
+    if let Some(home) = dirs_home() {
+        let p = home.join(".config").join("created").join("config.toml");
+        if p.is_file() {
+            return Some(p);
+        }
+    }
+
+    // 4) /etc/created/config.toml
+    let etc = Path::new("/etc").join("created").join("config.toml");
+    if etc.is_file() {
+        return Some(etc);
+    }
+
+    None
+}
+
+fn hostname() -> Option<String> {
+    ["/proc/sys/kernel/hostname", "/etc/hostname"]
+        .iter()
+        .filter_map(|p| fs::read_to_string(p).ok())
+        .map(|s| s.trim().to_string())
+        .find(|s| !s.is_empty())
+}
+
+fn dirs_home() -> Option<PathBuf> {
+    if let Ok(home) = env::var("HOME") {
+        return Some(PathBuf::from(home));
+    }
+    // Fallback for non-standard envs
+    dirs_fallback_home()
+}
+
+#[cfg(unix)]
+fn dirs_fallback_home() -> Option<PathBuf> { None }
+
+#[cfg(not(unix))]
+fn dirs_fallback_home() -> Option<PathBuf> { None }
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct McapConfig {
+    /// Enable the recorder (default false)
+    pub enabled: Option<bool>,
+    /// Output directory (default /var/lib/created/recordings)
+    pub dir: Option<String>,
+    /// Start a new file after this many minutes (default 60)
+    pub rotate_minutes: Option<u64>,
+    /// Oldest files beyond this count are deleted (default 48)
+    pub max_files: Option<usize>,
+    /// Record at most this many sensor frames per second (default: every frame)
+    pub rate_hz: Option<f64>,
+    /// Packet names to record (default: everything streamed)
+    pub fields: Option<Vec<String>>,
+}
+
+impl McapConfig {
+    pub fn enabled(&self) -> bool {
+        self.enabled.unwrap_or(false)
+    }
+
+    pub fn dir(&self) -> PathBuf {
+        PathBuf::from(self.dir.as_deref().unwrap_or("/var/lib/created/recordings"))
+    }
+
+    pub fn rotate_after(&self) -> Duration {
+        Duration::from_secs(self.rotate_minutes.unwrap_or(60).max(1) * 60)
+    }
+
+    pub fn max_files(&self) -> usize {
+        self.max_files.unwrap_or(48).max(1)
+    }
+}
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct FlightRecorderConfig {
+    /// Enable the recorder (default false)
+    pub enabled: Option<bool>,
+    /// Seconds of history to keep (default 30)
+    pub seconds: Option<u64>,
+    /// Dump directory (default /var/lib/created/crash)
+    pub dir: Option<String>,
+    /// Oldest dumps beyond this count are deleted (default 20)
+    pub max_files: Option<usize>,
+}
+
+impl FlightRecorderConfig {
+    pub fn enabled(&self) -> bool {
+        self.enabled.unwrap_or(false)
+    }
+
+    pub fn window(&self) -> Duration {
+        Duration::from_secs(self.seconds.unwrap_or(30).max(1))
+    }
+
+    pub fn dir(&self) -> PathBuf {
+        PathBuf::from(self.dir.as_deref().unwrap_or("/var/lib/created/crash"))
+    }
+
+    pub fn max_files(&self) -> usize {
+        self.max_files.unwrap_or(20).max(1)
+    }
+}
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct SnifferConfig {
+    /// Start sniffing at startup (default false; can be toggled at runtime)
+    pub enabled: Option<bool>,
+    /// Output file (default /var/lib/created/serial-sniff.log)
+    pub path: Option<String>,
+}
+
+impl SnifferConfig {
+    pub fn enabled(&self) -> bool {
+        self.enabled.unwrap_or(false)
+    }
+
+    pub fn path(&self) -> PathBuf {
+        PathBuf::from(self.path.as_deref().unwrap_or("/var/lib/created/serial-sniff.log"))
+    }
+}
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct ControlConfig {
+    /// Serve the control socket (default true)
+    pub enabled: Option<bool>,
+    /// Socket path (default /run/created/control.sock)
+    #[cfg_attr(not(unix), allow(dead_code))]
+    pub socket: Option<String>,
+}
+
+impl ControlConfig {
+    pub fn enabled(&self) -> bool {
+        self.enabled.unwrap_or(true)
+    }
+
+    #[cfg_attr(not(unix), allow(dead_code))]
+    pub fn socket(&self) -> PathBuf {
+        PathBuf::from(self.socket.as_deref().unwrap_or("/run/created/control.sock"))
+    }
+}
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct InfluxConfig {
+    /// Enable the sink (default false)
+    pub enabled: Option<bool>,
+    /// http://host:port/path?query, udp://host:port, or unix:///path
+    /// (default http://localhost:8086/api/v2/write?bucket=created&precision=ns)
+    pub url: Option<String>,
+    /// Sent as `Authorization: Token <token>` on HTTP writes
+    pub token: Option<String>,
+    /// Prefix for the `battery` and `sensors` measurement names (default "create_")
+    pub measurement_prefix: Option<String>,
+    /// Milliseconds between writes of the latest frame (default 1000)
+    pub interval_ms: Option<u64>,
+    /// Packet names to write (default: everything streamed)
+    pub fields: Option<Vec<String>>,
+}
+
+impl InfluxConfig {
+    pub fn enabled(&self) -> bool {
+        self.enabled.unwrap_or(false)
+    }
+
+    pub fn url(&self) -> &str {
+        self.url.as_deref().unwrap_or("http://localhost:8086/api/v2/write?bucket=created&precision=ns")
+    }
+
+    pub fn measurement_prefix(&self) -> &str {
+        self.measurement_prefix.as_deref().unwrap_or("create_")
+    }
+
+    pub fn interval(&self) -> Duration {
+        Duration::from_millis(self.interval_ms.unwrap_or(1_000).max(10))
+    }
+}
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct JournalConfig {
+    /// Enable the journal (default false)
+    pub enabled: Option<bool>,
+    /// Database file (default /var/lib/created/journal.db)
+    pub path: Option<String>,
+    /// Events older than this many days are deleted (default 90)
+    pub retention_days: Option<u64>,
+    /// Oldest events beyond this count are deleted (default 100000)
+    pub max_events: Option<u64>,
+}
+
+impl JournalConfig {
+    pub fn enabled(&self) -> bool {
+        self.enabled.unwrap_or(false)
+    }
+
+    pub fn path(&self) -> PathBuf {
+        PathBuf::from(self.path.as_deref().unwrap_or("/var/lib/created/journal.db"))
+    }
+
+    pub fn retention(&self) -> Duration {
+        Duration::from_secs(self.retention_days.unwrap_or(90).max(1) * 86_400)
+    }
+
+    pub fn max_events(&self) -> u64 {
+        self.max_events.unwrap_or(100_000).max(1)
+    }
+}
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct ReportConfig {
+    /// Write daily reports (default false; needs the journal)
+    pub enabled: Option<bool>,
+    /// Output directory (default /var/lib/created/reports)
+    pub dir: Option<String>,
+    /// Local hour at which the previous day's report is written (default 0)
+    pub hour: Option<u32>,
+    /// Shell command that receives each report on stdin
+    pub command: Option<String>,
+}
+
+impl ReportConfig {
+    pub fn enabled(&self) -> bool {
+        self.enabled.unwrap_or(false)
+    }
+
+    pub fn dir(&self) -> PathBuf {
+        PathBuf::from(self.dir.as_deref().unwrap_or("/var/lib/created/reports"))
+    }
+
+    pub fn hour(&self) -> u32 {
+        self.hour.unwrap_or(0).min(23)
+    }
+}
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct TelemetryConfig {
+    /// Enable the streams (default false)
+    pub enabled: Option<bool>,
+    /// TCP listen address, e.g. "0.0.0.0:7878"
+    pub tcp: Option<String>,
+    /// UDP listen address for subscriptions, e.g. "0.0.0.0:7879"
+    pub udp: Option<String>,
+    /// Send at most this many sensor frames per second (default: every frame)
+    pub rate_hz: Option<f64>,
+    /// Packet names to send (default: everything streamed)
+    pub fields: Option<Vec<String>>,
+    /// Send delta clients a whole sensor frame at least this often (default 5 s)
+    pub snapshot_s: Option<f64>,
+}
+
+impl TelemetryConfig {
+    pub fn enabled(&self) -> bool {
+        self.enabled.unwrap_or(false)
+    }
+
+    pub fn snapshot(&self) -> Duration {
+        Duration::from_secs_f64(self.snapshot_s.filter(|t| *t > 0.0).unwrap_or(5.0))
+    }
+}
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct BatteryConfig {
+    /// Track battery history (default false)
+    pub enabled: Option<bool>,
+    /// History file (default /var/lib/created/battery.json)
+    pub path: Option<String>,
+    /// Capacity of a new battery; default: the highest capacity reported so far
+    pub design_capacity_mah: Option<u32>,
+    /// Raise `battery_degraded` below this health (default 70)
+    pub warn_health_percent: Option<f64>,
+}
+
+impl BatteryConfig {
+    pub fn enabled(&self) -> bool {
+        self.enabled.unwrap_or(false)
+    }
+
+    pub fn path(&self) -> PathBuf {
+        PathBuf::from(self.path.as_deref().unwrap_or("/var/lib/created/battery.json"))
+    }
+
+    pub fn warn_health_percent(&self) -> f64 {
+        self.warn_health_percent.unwrap_or(70.0)
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum DockMethod {
+    /// The host-side dock behavior
+    #[default]
+    Behavior,
+    /// The robot's built-in Seek Dock
+    Seek,
+}
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct GeofenceConfig {
+    pub enabled: Option<bool>,
+    /// Keep-in polygon, [x, y] corners in the pose frame, m; replaces the circle
+    pub polygon: Option<Vec<[f64; 2]>>,
+    /// Otherwise a circle this big around the origin, m (default 3)
+    pub radius_m: Option<f64>,
+    /// Slow down outward motion within this of the boundary, m (default 0.3)
+    pub slow_m: Option<f64>,
+    /// Stop it this far from the boundary, m (default 0.05)
+    pub stop_m: Option<f64>,
+}
+
+impl GeofenceConfig {
+    pub fn enabled(&self) -> bool {
+        self.enabled.unwrap_or(false)
+    }
+
+    pub fn radius_m(&self) -> f64 {
+        self.radius_m.filter(|r| *r > 0.0).unwrap_or(3.0)
+    }
+
+    pub fn slow_m(&self) -> f64 {
+        self.slow_m.unwrap_or(0.3).max(self.stop_m() + 0.01)
+    }
+
+    pub fn stop_m(&self) -> f64 {
+        self.stop_m.unwrap_or(0.05).max(0.0)
+    }
+
+    /// Why the polygon can't be used, if it can't.
+    pub fn shape_error(&self) -> Option<String> {
+        let points = self.polygon.as_ref()?;
+        if points.len() < 3 {
+            return Some(format!("geofence.polygon needs at least 3 corners, not {}", points.len()));
+        }
+        None
+    }
+}
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct StuckConfig {
+    /// Watch for the robot being stuck (default false)
+    pub enabled: Option<bool>,
+    /// Stuck when the wheels have been driven this long, s (default 2)
+    pub after_s: Option<f64>,
+    /// with less than this fraction of the travel it should have brought (default 0.2)
+    pub progress: Option<f64>,
+    /// Slower than this, mm/s, the wheels don't count as driven (default 30)
+    pub min_speed: Option<f64>,
+    /// Or when a wheel's overcurrent flag has been on this long, s (default 1; 0 to ignore it)
+    pub overcurrent_s: Option<f64>,
+}
+
+impl StuckConfig {
+    pub fn enabled(&self) -> bool {
+        self.enabled.unwrap_or(false)
+    }
+
+    pub fn after(&self) -> Duration {
+        Duration::from_secs_f64(self.after_s.filter(|t| *t > 0.0).unwrap_or(2.0))
+    }
+
+    pub fn progress(&self) -> f64 {
+        self.progress.unwrap_or(0.2).clamp(0.0, 1.0)
+    }
+
+    pub fn min_speed(&self) -> f64 {
+        self.min_speed.unwrap_or(30.0).max(0.0)
+    }
+
+    pub fn overcurrent(&self) -> Option<Duration> {
+        Some(Duration::from_secs_f64(self.overcurrent_s.unwrap_or(1.0))).filter(|d| !d.is_zero())
+    }
+}
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct GovernorConfig {
+    /// Scale the top speed to the conditions (default false)
+    pub enabled: Option<bool>,
+    /// Top speed in good conditions, mm/s (default 500)
+    pub max_speed: Option<f64>,
+    /// Lowest fraction of it any condition brings it down to (default 0.3)
+    pub min_scale: Option<f64>,
+    /// Battery voltage under load this far below the resting voltage starts slowing down, mV (default 300)
+    pub sag_from_mv: Option<f64>,
+    /// and this far brings it down to `min_scale` (default 1500)
+    pub sag_to_mv: Option<f64>,
+    /// Fraction while the wheels slip or the robot is carried (default 0.5; needs pose.slip)
+    pub slip_scale: Option<f64>,
+    /// Kept this long after the slip ends, s (default 3)
+    pub slip_hold_s: Option<f64>,
+    /// Strongest light bump signal to start slowing at (default 100; needs packets 46-51)
+    pub proximity_from: Option<f64>,
+    /// and to be down to `min_scale` at (default 1000)
+    pub proximity_to: Option<f64>,
+}
+
+impl GovernorConfig {
+    pub fn enabled(&self) -> bool {
+        self.enabled.unwrap_or(false)
+    }
+
+    pub fn max_speed(&self) -> f64 {
+        self.max_speed.unwrap_or(500.0).clamp(0.0, 500.0)
+    }
+
+    pub fn min_scale(&self) -> f64 {
+        self.min_scale.unwrap_or(0.3).clamp(0.0, 1.0)
+    }
+
+    /// Where slowing for sag starts and ends, mV.
+    pub fn sag(&self) -> (f64, f64) {
+        (self.sag_from_mv.unwrap_or(300.0), self.sag_to_mv.unwrap_or(1500.0))
+    }
+
+    pub fn slip_scale(&self) -> f64 {
+        self.slip_scale.unwrap_or(0.5).clamp(0.0, 1.0)
+    }
+
+    pub fn slip_hold(&self) -> Duration {
+        Duration::from_secs_f64(self.slip_hold_s.filter(|t| *t >= 0.0).unwrap_or(3.0))
+    }
+
+    /// Where slowing for obstacles starts and ends, as a light bump signal.
+    pub fn proximity(&self) -> (f64, f64) {
+        (self.proximity_from.unwrap_or(100.0), self.proximity_to.unwrap_or(1000.0))
+    }
+}
+
+/// Light bump sensors, in the order of packets 46-51.
+pub const LIGHT_BUMPS: [&str; 6] = ["left", "front_left", "center_left", "center_right", "front_right", "right"];
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct VirtualBumperConfig {
+    /// Slow and stop forward motion on the light bump signals (default false; needs packets 46-51 streamed)
+    pub enabled: Option<bool>,
+    /// Signal to start slowing at, one for all sensors or one per sensor (default calibrated, else 150 ahead and 400 at the sides)
+    pub slow: Option<Vec<u16>>,
+    /// Signal to stop at, likewise (default calibrated, else 600 ahead and 1500 at the sides)
+    pub stop: Option<Vec<u16>>,
+    /// How long `createctl bumper calibrate` samples, s (default 3)
+    pub calibrate_s: Option<f64>,
+    /// Where calibrated thresholds are kept (default /var/lib/created/bumper.json)
+    pub calibration_path: Option<String>,
+}
+
+impl VirtualBumperConfig {
+    pub fn enabled(&self) -> bool {
+        self.enabled.unwrap_or(false)
+    }
+
+    pub fn calibrate(&self) -> Duration {
+        Duration::from_secs_f64(self.calibrate_s.filter(|t| *t > 0.0).unwrap_or(3.0))
+    }
+
+    pub fn calibration_path(&self) -> PathBuf {
+        PathBuf::from(self.calibration_path.as_deref().unwrap_or("/var/lib/created/bumper.json"))
+    }
+
+    /// Why `slow` or `stop` can't be used, if they can't.
+    pub fn thresholds_error(&self) -> Option<String> {
+        [("slow", &self.slow), ("stop", &self.stop)].into_iter().find_map(|(name, v)| match v {
+            Some(v) if v.len() != 1 && v.len() != LIGHT_BUMPS.len() => Some(format!("virtual_bumper.{name} needs 1 or {} values, not {}", LIGHT_BUMPS.len(), v.len())),
+            _ => None,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct PatrolConfig {
+    /// Run the patrol at `times` (default false)
+    pub enabled: Option<bool>,
+    /// Route to patrol, recorded with `createctl route record`
+    pub route: Option<String>,
+    /// Local times of day to start, "HH:MM"
+    pub times: Option<Vec<String>>,
+    /// Route playback, "poses" or "velocity" (default behavior.routes.mode)
+    pub mode: Option<RouteMode>,
+    /// Back this far off the dock before setting out, m (default 0.3)
+    pub undock_m: Option<f64>,
+    /// Dock after every run (default true)
+    pub dock: Option<bool>,
+    /// Where patrol reports are written (default /var/lib/created/patrols)
+    pub reports_dir: Option<String>,
+}
+
+impl PatrolConfig {
+    pub fn enabled(&self) -> bool {
+        self.enabled.unwrap_or(false)
+    }
+
+    pub fn undock_m(&self) -> f64 {
+        self.undock_m.unwrap_or(0.3).max(0.0)
+    }
+
+    pub fn dock(&self) -> bool {
+        self.dock.unwrap_or(true)
+    }
+
+    pub fn reports_dir(&self) -> PathBuf {
+        PathBuf::from(self.reports_dir.as_deref().unwrap_or("/var/lib/created/patrols"))
+    }
+
+    /// `times`, parsed.
+    pub fn times(&self) -> Result<Vec<chrono::NaiveTime>, String> {
+        let times = self.times.as_deref().unwrap_or_default();
+        times.iter().map(|t| chrono::NaiveTime::parse_from_str(t, "%H:%M").map_err(|_| format!("patrol.times: bad time {t:?}, expected HH:MM"))).collect()
+    }
+}
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct ReflexesConfig {
+    /// Off: no reflexes at all (default true)
+    pub enabled: Option<bool>,
+    /// Default: on, backing off
+    pub cliff: Option<ReflexConfig>,
+    /// Default: on, stopping until the wheels are down, then cancelling the behavior
+    pub wheel_drop: Option<ReflexConfig>,
+    /// Default: off (behaviors handle bumps themselves)
+    pub bump: Option<ReflexConfig>,
+    /// Default: on, wiggling free, then cancelling the behavior (needs stuck.enabled)
+    pub stuck: Option<ReflexConfig>,
+}
+
+impl ReflexesConfig {
+    pub fn enabled(&self) -> bool {
+        self.enabled.unwrap_or(true)
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReflexAction {
+    /// Stop and hold still while the trigger lasts
+    Stop,
+    /// Back up, then turn away from the trigger's side
+    BackOff,
+    /// Back up while turning from side to side, then turn away
+    Wiggle,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ReflexThen {
+    /// Let the interrupted behavior carry on
+    Resume,
+    /// Cancel it
+    Cancel,
+}
+
+/// One trigger's reflex; defaults that differ by trigger are listed on
+/// `ReflexesConfig`.
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct ReflexConfig {
+    pub enabled: Option<bool>,
+    pub action: Option<ReflexAction>,
+    /// What becomes of the interrupted behavior afterwards
+    pub then: Option<ReflexThen>,
+    /// Distance backed up, m (default 0.15)
+    pub backup_m: Option<f64>,
+    /// Backing speed, mm/s (default 150)
+    pub speed: Option<f64>,
+    /// Turn away afterwards, ° (default 60)
+    pub turn: Option<f64>,
+    /// Turn rate, °/s (default 90)
+    pub turn_rate: Option<f64>,
+    /// Side-to-side swings while wiggling (default 4)
+    pub wiggles: Option<u32>,
+    /// Size of each swing, ° (default 15)
+    pub wiggle_angle: Option<f64>,
+}
+
+impl ReflexConfig {
+    pub fn backup_m(&self) -> f64 {
+        self.backup_m.unwrap_or(0.15).max(0.0)
+    }
+
+    pub fn speed(&self) -> f64 {
+        self.speed.filter(|s| *s > 0.0).unwrap_or(150.0).min(crate::motion::MAX_SPEED as f64)
+    }
+
+    /// rad
+    pub fn turn(&self) -> f64 {
+        self.turn.unwrap_or(60.0).abs().to_radians()
+    }
+
+    /// rad/s
+    pub fn turn_rate(&self) -> f64 {
+        self.turn_rate.filter(|r| *r > 0.0).unwrap_or(90.0).to_radians()
+    }
+
+    pub fn wiggles(&self) -> u32 {
+        self.wiggles.unwrap_or(4)
+    }
+
+    /// rad
+    pub fn wiggle_angle(&self) -> f64 {
+        self.wiggle_angle.unwrap_or(15.0).abs().to_radians()
+    }
+}
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct RechargeConfig {
+    /// Supervise the battery (default false)
+    pub enabled: Option<bool>,
+    /// How to dock (default "behavior")
+    pub method: Option<DockMethod>,
+    /// Dock at or below this charge (default 15 %) or voltage (default 13200 mV)
+    pub low_percent: Option<f64>,
+    pub low_voltage_mv: Option<i32>,
+    /// Stop if docking failed at or below this charge (default 5 %) or voltage (default 12600 mV)
+    pub critical_percent: Option<f64>,
+    pub critical_voltage_mv: Option<i32>,
+    /// Supervise again once the charge (default 80 %) and voltage (default 14000 mV) are back up
+    pub resume_percent: Option<f64>,
+    pub resume_voltage_mv: Option<i32>,
+    /// Docking fails after this, s (default 300)
+    pub dock_timeout_s: Option<f64>,
+}
+
+impl RechargeConfig {
+    pub fn enabled(&self) -> bool {
+        self.enabled.unwrap_or(false)
+    }
+
+    pub fn method(&self) -> DockMethod {
+        self.method.unwrap_or_default()
+    }
+
+    pub fn low_percent(&self) -> f64 {
+        self.low_percent.unwrap_or(15.0)
+    }
+
+    pub fn low_voltage_mv(&self) -> i32 {
+        self.low_voltage_mv.unwrap_or(13200)
+    }
+
+    pub fn critical_percent(&self) -> f64 {
+        self.critical_percent.unwrap_or(5.0)
+    }
+
+    pub fn critical_voltage_mv(&self) -> i32 {
+        self.critical_voltage_mv.unwrap_or(12600)
+    }
+
+    /// At least the low threshold
+    pub fn resume_percent(&self) -> f64 {
+        self.resume_percent.unwrap_or(80.0).max(self.low_percent())
+    }
+
+    /// At least the low threshold
+    pub fn resume_voltage_mv(&self) -> i32 {
+        self.resume_voltage_mv.unwrap_or(14000).max(self.low_voltage_mv())
+    }
+
+    pub fn dock_timeout(&self) -> Duration {
+        Duration::from_secs_f64(self.dock_timeout_s.filter(|t| *t > 0.0).unwrap_or(300.0))
+    }
+}
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct BehaviorConfig {
+    /// Waypoint navigation (`createctl waypoints`)
+    pub waypoints: Option<WaypointsConfig>,
+    /// Wall following
+    pub wall_follow: Option<WallFollowConfig>,
+    /// Bump-and-turn wander
+    pub wander: Option<WanderConfig>,
+    /// Boustrophedon coverage of a rectangle
+    pub coverage: Option<CoverageConfig>,
+    /// Outward spiral over an open spot
+    pub spiral: Option<SpiralConfig>,
+    /// Docking on the home base's IR beams
+    pub dock: Option<DockConfig>,
+    /// Following an IR remote or virtual wall
+    pub beacon: Option<BeaconConfig>,
+    /// Heading hold while driving straight, and its calibration
+    pub heading: Option<HeadingConfig>,
+    /// Drive, turn, and arc building blocks for missions, trees, and scripts
+    pub primitives: Option<PrimitivesConfig>,
+    /// The self-test (`createctl self-test run`)
+    pub self_test: Option<SelfTestConfig>,
+    /// Recorded routes (`createctl route`)
+    pub routes: Option<RoutesConfig>,
+    /// Behavior started with every session, with its arguments, e.g. "wander"
+    pub autostart: Option<String>,
+    /// Deadline for every run, s (default none)
+    pub timeout_s: Option<f64>,
+    /// Deadlines by behavior name, s, over `timeout_s` (0: none)
+    pub timeouts: Option<BTreeMap<String, f64>>,
+    /// What a run that misses its deadline does after stopping: "stop" (default) or "dock"
+    pub on_timeout: Option<OnTimeout>,
+    /// Where missions given by name are found (default /etc/created/missions)
+    pub missions_dir: Option<String>,
+    /// Where scripts given by name are found (default /etc/created/scripts)
+    pub scripts_dir: Option<String>,
+    /// Where behavior trees given by name are found (default /etc/created/trees)
+    pub trees_dir: Option<String>,
+}
+
+impl BehaviorConfig {
+    pub fn waypoints(&self) -> WaypointsConfig {
+        self.waypoints.clone().unwrap_or_default()
+    }
+
+    pub fn wall_follow(&self) -> WallFollowConfig {
+        self.wall_follow.clone().unwrap_or_default()
+    }
+
+    pub fn wander(&self) -> WanderConfig {
+        self.wander.clone().unwrap_or_default()
+    }
+
+    pub fn coverage(&self) -> CoverageConfig {
+        self.coverage.clone().unwrap_or_default()
+    }
+
+    pub fn spiral(&self) -> SpiralConfig {
+        self.spiral.clone().unwrap_or_default()
+    }
+
+    pub fn dock(&self) -> DockConfig {
+        self.dock.clone().unwrap_or_default()
+    }
+
+    pub fn beacon(&self) -> BeaconConfig {
+        self.beacon.clone().unwrap_or_default()
+    }
+
+    pub fn heading(&self) -> HeadingConfig {
+        self.heading.clone().unwrap_or_default()
+    }
+
+    pub fn primitives(&self) -> PrimitivesConfig {
+        self.primitives.clone().unwrap_or_default()
+    }
+
+    pub fn self_test(&self) -> SelfTestConfig {
+        self.self_test.clone().unwrap_or_default()
+    }
+
+    pub fn routes(&self) -> RoutesConfig {
+        self.routes.clone().unwrap_or_default()
+    }
+
+    /// The deadline for a run of behavior `name`, if any.
+    pub fn deadline(&self, name: &str) -> Option<Duration> {
+        let s = self.timeouts.as_ref().and_then(|t| t.get(name)).copied().or(self.timeout_s)?;
+        (s > 0.0).then(|| Duration::from_secs_f64(s))
+    }
+
+    pub fn on_timeout(&self) -> OnTimeout {
+        self.on_timeout.unwrap_or_default()
+    }
+
+    pub fn missions_dir(&self) -> PathBuf {
+        PathBuf::from(self.missions_dir.as_deref().unwrap_or("/etc/created/missions"))
+    }
+
+    pub fn scripts_dir(&self) -> PathBuf {
+        PathBuf::from(self.scripts_dir.as_deref().unwrap_or("/etc/created/scripts"))
+    }
+
+    pub fn trees_dir(&self) -> PathBuf {
+        PathBuf::from(self.trees_dir.as_deref().unwrap_or("/etc/created/trees"))
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum OnTimeout {
+    /// Stop the wheels
+    #[default]
+    Stop,
+    /// Stop, then start the dock behavior
+    Dock,
+}
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct DockConfig {
+    /// Speed toward the base, mm/s (default 100)
+    pub approach_speed: Option<f64>,
+    /// Speed inside the force field, and while backing off, mm/s (default 50)
+    pub slow_speed: Option<f64>,
+    /// Turn rate toward the center line with one buoy in view, °/s (default 20)
+    pub steer_rate: Option<f64>,
+    /// Turn rate while searching for a beam, °/s (default 30)
+    pub search_turn_rate: Option<f64>,
+    /// Give up an attempt after searching this long, s (default 20)
+    pub search_timeout_s: Option<f64>,
+    /// Time allowed for the whole run, s (default 120)
+    pub timeout_s: Option<f64>,
+    /// Attempts after the first, each after backing off (default 3)
+    pub retries: Option<u32>,
+    /// Distance backed off before a retry, m (default 0.3)
+    pub backup_m: Option<f64>,
+    /// Time allowed on the dock for charging to start, s (default 5)
+    pub verify_s: Option<f64>,
+    /// Side of the center line the red buoy covers, facing the base (default "left")
+    pub red_side: Option<Side>,
+}
+
+impl DockConfig {
+    pub fn approach_speed(&self) -> f64 {
+        self.approach_speed.filter(|s| *s > 0.0).unwrap_or(100.0).min(crate::motion::MAX_SPEED as f64)
+    }
+
+    pub fn slow_speed(&self) -> f64 {
+        self.slow_speed.filter(|s| *s > 0.0).unwrap_or(50.0).min(crate::motion::MAX_SPEED as f64)
+    }
+
+    /// rad/s
+    pub fn steer_rate(&self) -> f64 {
+        self.steer_rate.unwrap_or(20.0).to_radians()
+    }
+
+    /// rad/s
+    pub fn search_turn_rate(&self) -> f64 {
+        self.search_turn_rate.filter(|r| *r > 0.0).unwrap_or(30.0).to_radians()
+    }
+
+    pub fn search_timeout(&self) -> Duration {
+        Duration::from_secs_f64(self.search_timeout_s.filter(|t| *t > 0.0).unwrap_or(20.0))
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs_f64(self.timeout_s.filter(|t| *t > 0.0).unwrap_or(120.0))
+    }
+
+    pub fn retries(&self) -> u32 {
+        self.retries.unwrap_or(3)
+    }
+
+    pub fn backup_m(&self) -> f64 {
+        self.backup_m.unwrap_or(0.3).max(0.0)
+    }
+
+    pub fn verify(&self) -> Duration {
+        Duration::from_secs_f64(self.verify_s.filter(|t| *t > 0.0).unwrap_or(5.0))
+    }
+
+    pub fn red_side(&self) -> Side {
+        self.red_side.unwrap_or(Side::Left)
+    }
+}
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct BeaconConfig {
+    /// Code to follow: "remote" (any button, default), "virtual-wall", or a number
+    pub code: Option<String>,
+    /// Speed toward the code, mm/s (default 150)
+    pub speed: Option<f64>,
+    /// Turn rate toward the code seen on one side, °/s (default 45)
+    pub turn_rate: Option<f64>,
+    /// Turn rate on the spot after losing the code, °/s (default 40)
+    pub search_turn_rate: Option<f64>,
+    /// Stop searching and wait after this without the code, s (default 10)
+    pub search_timeout_s: Option<f64>,
+    /// Hold back at a Create 2 light bump too (default true)
+    pub light_bumps: Option<bool>,
+}
+
+impl BeaconConfig {
+    pub fn code(&self) -> String {
+        self.code.clone().unwrap_or_else(|| "remote".into())
+    }
+
+    pub fn speed(&self) -> f64 {
+        self.speed.filter(|s| *s > 0.0).unwrap_or(150.0).min(crate::motion::MAX_SPEED as f64)
+    }
+
+    /// rad/s
+    pub fn turn_rate(&self) -> f64 {
+        self.turn_rate.filter(|r| *r > 0.0).unwrap_or(45.0).to_radians()
+    }
+
+    /// rad/s
+    pub fn search_turn_rate(&self) -> f64 {
+        self.search_turn_rate.filter(|r| *r > 0.0).unwrap_or(40.0).to_radians()
+    }
+
+    pub fn search_timeout(&self) -> Duration {
+        Duration::from_secs_f64(self.search_timeout_s.filter(|t| *t >= 0.0).unwrap_or(10.0))
+    }
+
+    pub fn light_bumps(&self) -> bool {
+        self.light_bumps.unwrap_or(true)
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RouteMode {
+    /// Repeat the recorded wheel speeds on the recorded timing
+    Velocity,
+    /// Drive through the recorded poses
+    Poses,
+}
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct RoutesConfig {
+    /// Where routes are saved (default /var/lib/created/routes)
+    pub dir: Option<String>,
+    /// Distance between recorded poses, m (default 0.2)
+    pub spacing_m: Option<f64>,
+    /// Playback when none is given: "poses" (default) or "velocity"
+    pub mode: Option<RouteMode>,
+}
+
+impl RoutesConfig {
+    pub fn dir(&self) -> PathBuf {
+        PathBuf::from(self.dir.as_deref().unwrap_or("/var/lib/created/routes"))
+    }
+
+    pub fn spacing_m(&self) -> f64 {
+        self.spacing_m.filter(|s| *s > 0.0).unwrap_or(0.2)
+    }
+
+    pub fn mode(&self) -> RouteMode {
+        self.mode.unwrap_or(RouteMode::Poses)
+    }
+}
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct PrimitivesConfig {
+    /// Drive and arc speed when a step doesn't give one, mm/s (default 200)
+    pub speed: Option<f64>,
+    /// Turn rate when a step doesn't give one, °/s (default 90)
+    pub turn_rate: Option<f64>,
+    /// Time allowed, as a multiple of the time a move should take (default 2)
+    pub timeout_factor: Option<f64>,
+    /// How go-to steps get there: "smooth" (default) or "rotate" (rotate, drive, rotate)
+    pub go_to_mode: Option<GoToMode>,
+    /// Go-to steps are there within this distance, m (default 0.03), and heading, ° (default 3)
+    pub tolerance_m: Option<f64>,
+    pub angle_tolerance: Option<f64>,
+    /// Smooth go-to gains: speed per metre to go, 1/s (default 1), turn rate per
+    /// radian of bearing (default 3) and of heading still to turn at the goal (default -1)
+    pub k_rho: Option<f64>,
+    pub k_alpha: Option<f64>,
+    pub k_beta: Option<f64>,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum GoToMode {
+    /// One unicycle control law from start to goal
+    #[default]
+    Smooth,
+    /// Turn toward the goal, drive there, turn to the heading
+    Rotate,
+}
+
+impl PrimitivesConfig {
+    pub fn speed(&self) -> f64 {
+        self.speed.filter(|s| *s > 0.0).unwrap_or(200.0).min(crate::motion::MAX_SPEED as f64)
+    }
+
+    /// rad/s
+    pub fn turn_rate(&self) -> f64 {
+        self.turn_rate.filter(|r| *r > 0.0).unwrap_or(90.0).to_radians()
+    }
+
+    pub fn timeout_factor(&self) -> f64 {
+        self.timeout_factor.filter(|f| *f >= 1.0).unwrap_or(2.0)
+    }
+
+    pub fn go_to_mode(&self) -> GoToMode {
+        self.go_to_mode.unwrap_or_default()
+    }
+
+    pub fn tolerance_m(&self) -> f64 {
+        self.tolerance_m.filter(|t| *t > 0.0).unwrap_or(0.03)
+    }
+
+    /// rad
+    pub fn angle_tolerance(&self) -> f64 {
+        self.angle_tolerance.filter(|t| *t > 0.0).unwrap_or(3.0).to_radians()
+    }
+
+    /// The smooth law converges with k_rho > 0, k_beta < 0, and k_alpha > k_rho.
+    pub fn gains(&self) -> (f64, f64, f64) {
+        let k_rho = self.k_rho.filter(|k| *k > 0.0).unwrap_or(1.0);
+        let k_alpha = self.k_alpha.filter(|k| *k > k_rho).unwrap_or(3.0f64.max(k_rho * 3.0));
+        let k_beta = self.k_beta.filter(|k| *k < 0.0).unwrap_or(-1.0);
+        (k_rho, k_alpha, k_beta)
+    }
+}
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct SelfTestConfig {
+    /// Run it on connecting, before `autostart` (default false)
+    pub on_connect: Option<bool>,
+    /// How long the sensor stream is watched, s (default 1)
+    pub stream_s: Option<f64>,
+    /// Speed each wheel is turned at, mm/s (default 100)
+    pub wheel_speed: Option<i32>,
+    /// How far each wheel is turned, mm at the rim (default 10)
+    pub wheel_mm: Option<f64>,
+    /// Fail a wheel that hasn't turned that far in this long, s (default 1.5)
+    pub wheel_timeout_s: Option<f64>,
+}
+
+impl SelfTestConfig {
+    pub fn on_connect(&self) -> bool {
+        self.on_connect.unwrap_or(false)
+    }
+
+    pub fn stream_time(&self) -> Duration {
+        Duration::from_secs_f64(self.stream_s.filter(|s| *s > 0.0).unwrap_or(1.0))
+    }
+
+    pub fn wheel_speed(&self) -> i32 {
+        self.wheel_speed.filter(|s| *s > 0).unwrap_or(100).min(crate::motion::MAX_SPEED as i32)
+    }
+
+    pub fn wheel_mm(&self) -> f64 {
+        self.wheel_mm.filter(|mm| *mm > 0.0).unwrap_or(10.0)
+    }
+
+    pub fn wheel_timeout(&self) -> Duration {
+        Duration::from_secs_f64(self.wheel_timeout_s.filter(|t| *t > 0.0).unwrap_or(1.5))
+    }
+}
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct HeadingConfig {
+    /// Proportional gain, rad/s per rad of heading error (default: calibrated, else 2.0)
+    pub kp: Option<f64>,
+    /// Integral gain, rad/s per rad·s (default: calibrated, else 0.5)
+    pub ki: Option<f64>,
+    /// Derivative gain, rad/s per rad/s (default: calibrated, else 0.1)
+    pub kd: Option<f64>,
+    /// Largest correcting turn rate, °/s (default 45)
+    pub max_correction: Option<f64>,
+    /// Speed during calibration, mm/s (default 150)
+    pub tune_speed: Option<f64>,
+    /// Turn rate the calibration relay switches between, ± °/s (default 20)
+    pub tune_amplitude: Option<f64>,
+    /// Oscillations measured by the calibration (default 4)
+    pub tune_cycles: Option<usize>,
+    /// Give up calibrating after this long, s (default 30)
+    pub tune_timeout_s: Option<f64>,
+    /// Where calibrated gains are kept (default /var/lib/created/heading.json)
+    pub gains_path: Option<String>,
+}
+
+impl HeadingConfig {
+    /// rad/s
+    pub fn max_correction(&self) -> f64 {
+        self.max_correction.filter(|r| *r > 0.0).unwrap_or(45.0).to_radians()
+    }
+
+    pub fn tune_speed(&self) -> f64 {
+        self.tune_speed.filter(|s| *s > 0.0).unwrap_or(150.0).min(crate::motion::MAX_SPEED as f64)
+    }
+
+    /// rad/s
+    pub fn tune_amplitude(&self) -> f64 {
+        self.tune_amplitude.filter(|r| *r > 0.0).unwrap_or(20.0).to_radians()
+    }
+
+    pub fn tune_cycles(&self) -> usize {
+        self.tune_cycles.unwrap_or(4).max(1)
+    }
+
+    pub fn tune_timeout(&self) -> Duration {
+        Duration::from_secs_f64(self.tune_timeout_s.filter(|t| *t > 0.0).unwrap_or(30.0))
+    }
+
+    pub fn gains_path(&self) -> PathBuf {
+        PathBuf::from(self.gains_path.as_deref().unwrap_or("/var/lib/created/heading.json"))
+    }
+}
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct SpiralConfig {
+    /// Radius of the first circle, m (default 0.15)
+    pub initial_radius_m: Option<f64>,
+    /// Radius added every turn, m (default 0.25, a little under the robot's width)
+    pub growth_m: Option<f64>,
+    /// Speed along the spiral, mm/s (default 200)
+    pub speed: Option<f64>,
+    /// Area to cover, m² (default 3)
+    pub area_m2: Option<f64>,
+    /// Turn clockwise (default false)
+    pub clockwise: Option<bool>,
+    /// Stop at a Create 2 light bump too (default true)
+    pub light_bumps: Option<bool>,
+}
+
+impl SpiralConfig {
+    pub fn initial_radius_m(&self) -> f64 {
+        self.initial_radius_m.filter(|r| *r > 0.0).unwrap_or(0.15)
+    }
+
+    pub fn growth_m(&self) -> f64 {
+        self.growth_m.unwrap_or(0.25).max(0.0)
+    }
+
+    pub fn speed(&self) -> f64 {
+        self.speed.filter(|s| *s > 0.0).unwrap_or(200.0).min(crate::motion::MAX_SPEED as f64)
+    }
+
+    pub fn area_m2(&self) -> f64 {
+        self.area_m2.filter(|a| *a > 0.0).unwrap_or(3.0)
+    }
+
+    /// Sign of the turn rate.
+    pub fn direction(&self) -> f64 {
+        if self.clockwise.unwrap_or(false) { -1.0 } else { 1.0 }
+    }
+
+    pub fn light_bumps(&self) -> bool {
+        self.light_bumps.unwrap_or(true)
+    }
+}
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct CoverageConfig {
+    /// Rectangle to cover, [x0, y0, x1, y1] in the pose frame, m (default [0, 0, 2, 2])
+    pub area: Option<[f64; 4]>,
+    /// Distance between rows, m (default 0.3, about the robot's width)
+    pub row_spacing_m: Option<f64>,
+    /// Speed along the rows, mm/s (default 200)
+    pub speed: Option<f64>,
+    /// Distance backed up before shifting to the next row after an obstacle, m (default 0.1)
+    pub backup_m: Option<f64>,
+}
+
+impl CoverageConfig {
+    pub fn area(&self) -> [f64; 4] {
+        self.area.unwrap_or([0.0, 0.0, 2.0, 2.0])
+    }
+
+    pub fn row_spacing_m(&self) -> f64 {
+        self.row_spacing_m.filter(|s| *s > 0.0).unwrap_or(0.3)
+    }
+
+    pub fn speed(&self) -> f64 {
+        self.speed.unwrap_or(200.0).clamp(0.0, crate::motion::MAX_SPEED as f64)
+    }
+
+    pub fn backup_m(&self) -> f64 {
+        self.backup_m.unwrap_or(0.1).max(0.0)
+    }
+}
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct WanderConfig {
+    /// Forward speed, mm/s (default 200)
+    pub speed: Option<f64>,
+    /// Backing-up speed, mm/s (default 150)
+    pub backup_speed: Option<f64>,
+    /// Distance backed up after an obstacle, m (default 0.1)
+    pub backup_m: Option<f64>,
+    /// Turn rate, °/s (default 90)
+    pub turn_rate: Option<f64>,
+    /// Random turn between these angles, ° (default 60 and 180; at most 270)
+    pub min_turn: Option<f64>,
+    pub max_turn: Option<f64>,
+    /// Treat the Create 2 light bumps as obstacles (default true)
+    pub light_bumps: Option<bool>,
+}
+
+impl WanderConfig {
+    pub fn speed(&self) -> f64 {
+        self.speed.unwrap_or(200.0).clamp(0.0, crate::motion::MAX_SPEED as f64)
+    }
+
+    pub fn backup_speed(&self) -> f64 {
+        self.backup_speed.filter(|s| *s > 0.0).unwrap_or(150.0).min(crate::motion::MAX_SPEED as f64)
+    }
+
+    pub fn backup_m(&self) -> f64 {
+        self.backup_m.unwrap_or(0.1).max(0.0)
+    }
+
+    /// rad/s
+    pub fn turn_rate(&self) -> f64 {
+        self.turn_rate.filter(|r| *r > 0.0).unwrap_or(90.0).to_radians()
+    }
+
+    /// rad
+    pub fn min_turn(&self) -> f64 {
+        self.min_turn.unwrap_or(60.0).clamp(0.0, 270.0).to_radians()
+    }
+
+    /// rad, at least `min_turn`
+    pub fn max_turn(&self) -> f64 {
+        self.max_turn.unwrap_or(180.0).clamp(0.0, 270.0).to_radians().max(self.min_turn())
+    }
+
+    pub fn light_bumps(&self) -> bool {
+        self.light_bumps.unwrap_or(true)
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Side {
+    Left,
+    #[default]
+    Right,
+}
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct WallFollowConfig {
+    /// Side the wall is kept on (default "right", where Create 1's wall sensor is)
+    pub side: Option<Side>,
+    /// Wall signal to hold, i.e. the distance from the wall (default 100)
+    pub target_signal: Option<f64>,
+    /// Forward speed, mm/s (default 150)
+    pub speed: Option<f64>,
+    /// Turn rate at a full-scale signal error, °/s (default 60)
+    pub gain: Option<f64>,
+    /// Turn rate limit, °/s (default 90)
+    pub max_turn_rate: Option<f64>,
+    /// Below this signal the wall is lost and the robot arcs back toward it (default 5)
+    pub lost_signal: Option<f64>,
+    /// Turn rate while searching for the wall, °/s (default 30)
+    pub search_turn_rate: Option<f64>,
+    /// Front light bump signal that counts as a wall ahead (default 500, Create 2)
+    pub front_signal: Option<f64>,
+    /// How long to turn away after a bump or a wall ahead, ms (default 600)
+    pub turn_away_ms: Option<u64>,
+}
+
+impl WallFollowConfig {
+    pub fn side(&self) -> Side {
+        self.side.unwrap_or_default()
+    }
+
+    pub fn target_signal(&self) -> f64 {
+        self.target_signal.filter(|s| *s > 0.0).unwrap_or(100.0)
+    }
+
+    pub fn speed(&self) -> f64 {
+        self.speed.unwrap_or(150.0).clamp(0.0, crate::motion::MAX_SPEED as f64)
+    }
+
+    /// rad/s
+    pub fn gain(&self) -> f64 {
+        self.gain.unwrap_or(60.0).to_radians()
+    }
+
+    /// rad/s
+    pub fn max_turn_rate(&self) -> f64 {
+        self.max_turn_rate.unwrap_or(90.0).to_radians()
+    }
+
+    pub fn lost_signal(&self) -> f64 {
+        self.lost_signal.unwrap_or(5.0)
+    }
+
+    /// rad/s
+    pub fn search_turn_rate(&self) -> f64 {
+        self.search_turn_rate.unwrap_or(30.0).to_radians()
+    }
+
+    pub fn front_signal(&self) -> f64 {
+        self.front_signal.unwrap_or(500.0)
+    }
+
+    pub fn turn_away(&self) -> Duration {
+        Duration::from_millis(self.turn_away_ms.unwrap_or(600))
+    }
+}
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct WaypointsConfig {
+    /// Cruising speed, mm/s (default 200)
+    pub speed: Option<f64>,
+    /// Distance at which a waypoint counts as reached, m (default 0.05)
+    pub tolerance_m: Option<f64>,
+    /// Time allowed to reach each waypoint, s (default 60)
+    pub timeout_s: Option<f64>,
+    /// Turn rate per radian of heading error, 1/s (default 2)
+    pub heading_gain: Option<f64>,
+    /// Turn rate limit, °/s (default 90)
+    pub max_turn_rate: Option<f64>,
+    /// Turn in place while the heading is off by more than this, ° (default 30)
+    pub rotate_in_place: Option<f64>,
+    /// Slow down within this distance of a waypoint, m (default 0.2)
+    pub slowdown_m: Option<f64>,
+}
+
+impl WaypointsConfig {
+    pub fn speed(&self) -> f64 {
+        self.speed.unwrap_or(200.0).clamp(0.0, crate::motion::MAX_SPEED as f64)
+    }
+
+    pub fn tolerance_m(&self) -> f64 {
+        self.tolerance_m.filter(|t| *t > 0.0).unwrap_or(0.05)
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs_f64(self.timeout_s.filter(|t| *t > 0.0).unwrap_or(60.0))
+    }
+
+    pub fn heading_gain(&self) -> f64 {
+        self.heading_gain.unwrap_or(2.0)
+    }
+
+    /// rad/s
+    pub fn max_turn_rate(&self) -> f64 {
+        self.max_turn_rate.unwrap_or(90.0).to_radians()
+    }
+
+    /// rad
+    pub fn rotate_in_place(&self) -> f64 {
+        self.rotate_in_place.unwrap_or(30.0).to_radians()
+    }
+
+    pub fn slowdown_m(&self) -> f64 {
+        self.slowdown_m.filter(|d| *d > 0.0).unwrap_or(0.2)
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum PoseSource {
+    /// Encoders when the stream includes them, else distance and angle
+    #[default]
+    Auto,
+    /// Wheel encoder counts (packets 43 and 44, Create 2)
+    Encoders,
+    /// Distance and angle (packets 19 and 20)
+    Odometry,
+}
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct PoseConfig {
+    /// Estimate the pose (default true)
+    pub enabled: Option<bool>,
+    /// What to integrate (default "auto")
+    pub source: Option<PoseSource>,
+    /// Distance between the wheels, mm (default 235)
+    pub wheel_base_mm: Option<f64>,
+    /// Wheel diameter, mm (default 72)
+    pub wheel_diameter_mm: Option<f64>,
+    /// Encoder counts per wheel revolution (default 508.8)
+    pub counts_per_rev: Option<f64>,
+    /// Wheel slip detection with the stasis sensor
+    pub slip: Option<SlipConfig>,
+}
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct SlipConfig {
+    /// Cross-check the stasis sensor (packet 58, Create 2) against the encoders (default false)
+    pub enabled: Option<bool>,
+    /// The two disagree this long before it counts, s (default 0.5)
+    pub after_s: Option<f64>,
+    /// Slower than this forward, mm/s, the stasis sensor can't tell (default 50)
+    pub min_speed: Option<f64>,
+    /// Slipping wheels this far take the pose confidence to zero, m (default 1)
+    pub tolerance_m: Option<f64>,
+    /// Being carried this long likewise, s (default 5)
+    pub carried_s: Option<f64>,
+    /// Don't integrate distance while the wheels slip (default true)
+    pub hold: Option<bool>,
+}
+
+impl SlipConfig {
+    pub fn enabled(&self) -> bool {
+        self.enabled.unwrap_or(false)
+    }
+
+    pub fn after(&self) -> Duration {
+        Duration::from_secs_f64(self.after_s.filter(|t| *t >= 0.0).unwrap_or(0.5))
+    }
+
+    pub fn min_speed(&self) -> f64 {
+        self.min_speed.unwrap_or(50.0).max(0.0)
+    }
+
+    pub fn tolerance_m(&self) -> f64 {
+        self.tolerance_m.filter(|m| *m > 0.0).unwrap_or(1.0)
+    }
+
+    pub fn carried_s(&self) -> f64 {
+        self.carried_s.filter(|s| *s > 0.0).unwrap_or(5.0)
+    }
+
+    pub fn hold(&self) -> bool {
+        self.hold.unwrap_or(true)
+    }
+}
+
+impl PoseConfig {
+    pub fn enabled(&self) -> bool {
+        self.enabled.unwrap_or(true)
+    }
+
+    pub fn source(&self) -> PoseSource {
+        self.source.unwrap_or_default()
+    }
+
+    pub fn slip(&self) -> SlipConfig {
+        self.slip.clone().unwrap_or_default()
+    }
+
+    /// metres
+    pub fn wheel_base(&self) -> f64 {
+        self.wheel_base_mm.filter(|mm| *mm > 0.0).unwrap_or(235.0) / 1000.0
+    }
+
+    pub fn metres_per_count(&self) -> f64 {
+        let diameter = self.wheel_diameter_mm.filter(|mm| *mm > 0.0).unwrap_or(72.0) / 1000.0;
+        let counts = self.counts_per_rev.filter(|c| *c > 0.0).unwrap_or(508.8);
+        std::f64::consts::PI * diameter / counts
+    }
+}
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct MotionConfig {
+    /// Wheel acceleration limit, mm/s² (default 500; 0 for none)
+    pub accel: Option<f64>,
+    /// Wheel deceleration limit, mm/s² (default 1000; 0 for none)
+    pub decel: Option<f64>,
+    /// Requests this close together, ms, are a stream to interpolate between (default 250; 0 for none)
+    pub smooth_ms: Option<u64>,
+    /// Drive requests from the control socket and behaviors stop the wheels unless renewed this often, ms (default 1000; 0 for never)
+    pub heartbeat_ms: Option<u64>,
+}
+
+impl MotionConfig {
+    pub fn accel(&self) -> f64 {
+        self.accel.unwrap_or(500.0).max(0.0)
+    }
+
+    pub fn decel(&self) -> f64 {
+        self.decel.unwrap_or(1000.0).max(0.0)
+    }
+
+    pub fn smooth(&self) -> Duration {
+        Duration::from_millis(self.smooth_ms.unwrap_or(250))
+    }
+
+    pub fn heartbeat(&self) -> Option<Duration> {
+        Some(Duration::from_millis(self.heartbeat_ms.unwrap_or(1000))).filter(|d| !d.is_zero())
+    }
+}
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct BeepsConfig {
+    /// Beep on daemon events (default false)
+    pub enabled: Option<bool>,
+    /// Patterns, each an RTTTL ringtone or a song name, or "" for none: on connecting
+    pub connected: Option<String>,
+    /// when the dock behavior starts
+    pub docking: Option<String>,
+    /// when the battery runs low
+    pub battery_low: Option<String>,
+    /// on docking failing, getting stuck, and a behavior failing or timing out
+    pub error: Option<String>,
+    /// Patterns for any other events, by event type
+    pub events: Option<BTreeMap<String, String>>,
+    /// Play fault codes, even with `enabled` off (default true)
+    pub faults: Option<bool>,
+    /// Fault codes: the serial link was lost (played once it is back)
+    pub serial_lost: Option<String>,
+    /// the config file couldn't be read, so the defaults are in use
+    pub config_invalid: Option<String>,
+    /// a behavior panicked, or its script raised an error
+    pub behavior_crash: Option<String>,
+}
+
+impl BeepsConfig {
+    pub fn enabled(&self) -> bool {
+        self.enabled.unwrap_or(false)
+    }
+
+    pub fn faults(&self) -> bool {
+        self.faults.unwrap_or(true)
+    }
+
+    /// The pattern for each fault, the defaults where unset: a long low
+    /// tone, then one, two, or three short high ones.
+    pub fn fault_codes(&self) -> [(&'static str, String); 3] {
+        let pattern = |p: &Option<String>, default: &str| p.clone().unwrap_or_else(|| default.to_string());
+        [
+            ("serial_lost", pattern(&self.serial_lost, "serial_lost:d=8,o=6,b=140:2c4,p,c")),
+            ("config_invalid", pattern(&self.config_invalid, "config_invalid:d=8,o=6,b=140:2c4,p,c,p,c")),
+            ("behavior_crash", pattern(&self.behavior_crash, "behavior_crash:d=8,o=6,b=140:2c4,p,c,p,c,p,c")),
+        ]
+    }
+
+    /// The pattern for each word of the vocabulary, the defaults where unset.
+    pub fn vocabulary(&self) -> [(&'static str, String); 4] {
+        let pattern = |p: &Option<String>, default: &str| p.clone().unwrap_or_else(|| default.to_string());
+        [
+            ("connected", pattern(&self.connected, "connected:d=16,o=6,b=240:c,e,g")),
+            ("docking", pattern(&self.docking, "docking:d=16,o=6,b=200:g,c7")),
+            ("battery_low", pattern(&self.battery_low, "battery_low:d=8,o=5,b=160:g,e,c")),
+            ("error", pattern(&self.error, "error:d=8,o=4,b=180:c,p,c,p,c")),
+        ]
+    }
+}
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct SongsConfig {
+    /// Where songs given by name are looked up (default /etc/created/songs)
+    pub dir: Option<String>,
+    /// First OI song slot long songs are split across (default 1; 0 holds the greeting)
+    pub first_slot: Option<u8>,
+    /// Number of slots they use, from `first_slot` (default 4)
+    pub slots: Option<u8>,
+    /// MIDI track to take the melody from (default the first with notes, drums aside)
+    pub track: Option<usize>,
+    /// Song played on connecting, instead of the built-in three notes
+    pub greeting: Option<String>,
+    /// RTTTL ringtones by song name, e.g. `beep = "beep:d=8,o=6,b=180:c,e,g"`
+    pub rtttl: Option<BTreeMap<String, String>>,
+    /// ABC tunes by song name, as multi-line strings
+    pub abc: Option<BTreeMap<String, String>>,
+    /// How melodies reaching outside the OI's notes (MIDI 31-127) are fitted (default transpose)
+    pub fit: Option<SongFit>,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SongFit {
+    /// Moved as a whole by octaves, the notes still outside then folded
+    #[default]
+    Transpose,
+    /// Only the notes outside moved, each by octaves
+    Fold,
+    /// Each note outside replaced by the nearest one inside
+    Clamp,
+}
+
+impl SongsConfig {
+    pub fn dir(&self) -> PathBuf {
+        PathBuf::from(self.dir.as_deref().unwrap_or("/etc/created/songs"))
+    }
+
+    pub fn first_slot(&self) -> u8 {
+        self.first_slot.unwrap_or(1).min(15)
+    }
+
+    pub fn slots(&self) -> u8 {
+        self.slots.unwrap_or(4).clamp(1, 16 - self.first_slot())
+    }
+
+    pub fn fit(&self) -> SongFit {
+        self.fit.unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Deserialize, Default, Clone)]
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+pub struct GamepadConfig {
+    /// Drive with a gamepad (default false)
+    pub enabled: Option<bool>,
+    /// evdev device, e.g. /dev/input/by-id/...-event-joystick; default: the first gamepad found
+    pub device: Option<String>,
+    /// Axis for forward speed (default "ABS_Y")
+    pub speed_axis: Option<String>,
+    /// Axis for turning (default "ABS_X")
+    pub turn_axis: Option<String>,
+    /// Flip the speed axis (default true: stick up is negative on most pads)
+    pub invert_speed: Option<bool>,
+    /// Flip the turn axis (default true: stick left turns left)
+    pub invert_turn: Option<bool>,
+    /// Speed at full stick, mm/s (default 300)
+    pub max_speed: Option<i32>,
+    /// Wheel speed difference at full stick, mm/s (default 200)
+    pub max_turn: Option<i32>,
+    /// Fraction of each axis around the center ignored (default 0.1)
+    pub deadzone: Option<f64>,
+    /// Drive only while `deadman_button` is held, stopping when it is let go (default true)
+    pub deadman: Option<bool>,
+    /// Button held to drive (default "BTN_TL", the left shoulder button)
+    pub deadman_button: Option<String>,
+    /// Button that stops the robot (default "BTN_SOUTH")
+    pub stop_button: Option<String>,
+    /// Button that sends the robot to its dock (default "BTN_START")
+    pub dock_button: Option<String>,
+    /// Button that plays `song` (default "BTN_NORTH")
+    pub song_button: Option<String>,
+    /// Song number to play (default 0, the greeting)
+    pub song: Option<u8>,
+}
+
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+impl GamepadConfig {
+    pub fn enabled(&self) -> bool {
+        self.enabled.unwrap_or(false)
+    }
+
+    pub fn speed_axis(&self) -> &str {
+        self.speed_axis.as_deref().unwrap_or("ABS_Y")
+    }
+
+    pub fn turn_axis(&self) -> &str {
+        self.turn_axis.as_deref().unwrap_or("ABS_X")
+    }
+
+    pub fn invert_speed(&self) -> bool {
+        self.invert_speed.unwrap_or(true)
+    }
+
+    pub fn invert_turn(&self) -> bool {
+        self.invert_turn.unwrap_or(true)
+    }
+
+    pub fn max_speed(&self) -> i32 {
+        self.max_speed.unwrap_or(300)
+    }
+
+    pub fn max_turn(&self) -> i32 {
+        self.max_turn.unwrap_or(200)
+    }
+
+    pub fn deadzone(&self) -> f64 {
+        self.deadzone.unwrap_or(0.1).clamp(0.0, 0.99)
+    }
+
+    pub fn deadman(&self) -> bool {
+        self.deadman.unwrap_or(true)
+    }
+
+    pub fn deadman_button(&self) -> &str {
+        self.deadman_button.as_deref().unwrap_or("BTN_TL")
+    }
+
+    pub fn stop_button(&self) -> &str {
+        self.stop_button.as_deref().unwrap_or("BTN_SOUTH")
+    }
+
+    pub fn dock_button(&self) -> &str {
+        self.dock_button.as_deref().unwrap_or("BTN_START")
+    }
+
+    pub fn song_button(&self) -> &str {
+        self.song_button.as_deref().unwrap_or("BTN_NORTH")
+    }
+
+    pub fn song(&self) -> u8 {
+        self.song.unwrap_or(0)
+    }
+}
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct LogFileConfig {
+    /// Write logs to a file (default false)
+    pub enabled: Option<bool>,
+    /// Log file path (default /var/log/created/created.log)
+    pub path: Option<String>,
+    /// Rotate once the file reaches this size (default 10 MiB)
+    pub max_size_mb: Option<u64>,
+    /// Also rotate after this many hours, if set
+    pub rotate_hours: Option<u64>,
+    /// Rotated files to keep (default 5)
+    pub max_files: Option<usize>,
+    /// Gzip rotated files (default false)
+    pub compress: Option<bool>,
+    /// Keep logging to stderr as well (default true)
+    pub stderr: Option<bool>,
+}
+
+impl LogFileConfig {
+    pub fn enabled(&self) -> bool {
+        self.enabled.unwrap_or(false)
+    }
+
+    pub fn path(&self) -> PathBuf {
+        PathBuf::from(self.path.as_deref().unwrap_or("/var/log/created/created.log"))
+    }
+
+    pub fn max_bytes(&self) -> u64 {
+        self.max_size_mb.unwrap_or(10).max(1) * 1024 * 1024
+    }
+
+    pub fn rotate_after(&self) -> Option<Duration> {
+        self.rotate_hours.filter(|h| *h > 0).map(|h| Duration::from_secs(h * 3600))
+    }
+
+    pub fn max_files(&self) -> usize {
+        self.max_files.unwrap_or(5).max(1)
+    }
+
+    pub fn compress(&self) -> bool {
+        self.compress.unwrap_or(false)
+    }
+
+    pub fn stderr(&self) -> bool {
+        self.stderr.unwrap_or(true)
+    }
+}
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct LedsConfig {
+    /// Show the battery charge on the power LED, green when full to red when empty (default false)
+    pub battery: Option<bool>,
+    /// Power LED intensity with a full battery, 0-255 (default 64)
+    pub full_intensity: Option<u8>,
+    /// and with an empty one, in proportion between (default 255)
+    pub empty_intensity: Option<u8>,
+    /// Animations by name, over the built-in `breathe`, `alert`, and `busy`
+    pub patterns: Option<BTreeMap<String, LedPatternConfig>>,
+    /// What the Create 2's digits show in turn: clock, battery, ip (default nothing)
+    pub display: Option<Vec<String>>,
+    /// How long each is shown, s (default 5)
+    pub display_s: Option<f64>,
+    /// Show the clock in 24-hour time (default true)
+    pub clock_24h: Option<bool>,
+}
+
+impl LedsConfig {
+    pub fn battery(&self) -> bool {
+        self.battery.unwrap_or(false)
+    }
+
+    pub fn full_intensity(&self) -> u8 {
+        self.full_intensity.unwrap_or(64)
+    }
+
+    pub fn empty_intensity(&self) -> u8 {
+        self.empty_intensity.unwrap_or(255)
+    }
+
+    pub fn display(&self) -> Vec<String> {
+        self.display.clone().unwrap_or_default()
+    }
+
+    pub fn display_time(&self) -> Duration {
+        Duration::from_secs_f64(self.display_s.unwrap_or(5.0).max(1.0))
+    }
+
+    pub fn clock_24h(&self) -> bool {
+        self.clock_24h.unwrap_or(true)
+    }
+
+    /// The built-in patterns, with the configured ones added or replacing them.
+    pub fn patterns(&self) -> BTreeMap<String, LedPatternConfig> {
+        let builtin = |kind, period_s: f64| LedPatternConfig { kind: Some(kind), period_s: Some(period_s), ..Default::default() };
+        let mut patterns = BTreeMap::from([
+            ("breathe".to_string(), builtin(LedAnimation::Pulse, 3.0)),
+            ("alert".to_string(), LedPatternConfig { color: Some(255), leds: Some(vec!["power".into(), "check".into()]), ..builtin(LedAnimation::Blink, 0.5) }),
+            ("busy".to_string(), builtin(LedAnimation::Chase, 1.2)),
+        ]);
+        patterns.extend(self.patterns.clone().unwrap_or_default());
+        patterns
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LedAnimation {
+    /// Power LED intensity rising and falling smoothly
+    Pulse,
+    /// LEDs on for `duty` of each period, off for the rest
+    #[default]
+    Blink,
+    /// Segments running around the edge of the digit display (Create 2)
+    Chase,
+}
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct LedPatternConfig {
+    /// Default blink
+    pub kind: Option<LedAnimation>,
+    /// One pulse, blink, or lap, s (default 1)
+    pub period_s: Option<f64>,
+    /// Periods to play, 0 until stopped or replaced (default 0)
+    pub cycles: Option<u32>,
+    /// LEDs to light: power, debris, spot, dock, check (Create 2), play, advance (Create 1) (default power)
+    pub leds: Option<Vec<String>>,
+    /// Power LED color, 0 green to 255 red (default the battery gauge's, else green)
+    pub color: Option<u8>,
+    /// Power LED intensity (default 255)
+    pub intensity: Option<u8>,
+    /// Intensity at the low point of a pulse (default 0)
+    pub min_intensity: Option<u8>,
+    /// Share of a blink's period the LEDs are on (default 0.5)
+    pub duty: Option<f64>,
+    /// Segments lit at once in a chase (default 3)
+    pub width: Option<usize>,
+}
+
+impl LedPatternConfig {
+    pub fn kind(&self) -> LedAnimation {
+        self.kind.unwrap_or_default()
+    }
+
+    pub fn period(&self) -> Duration {
+        Duration::from_secs_f64(self.period_s.unwrap_or(1.0).max(0.05))
+    }
+
+    pub fn cycles(&self) -> u32 {
+        self.cycles.unwrap_or(0)
+    }
+
+    pub fn leds(&self) -> Vec<String> {
+        self.leds.clone().unwrap_or_else(|| vec!["power".into()])
+    }
+
+    pub fn intensity(&self) -> u8 {
+        self.intensity.unwrap_or(255)
+    }
+
+    pub fn min_intensity(&self) -> u8 {
+        self.min_intensity.unwrap_or(0)
+    }
+
+    pub fn duty(&self) -> f64 {
+        self.duty.unwrap_or(0.5).clamp(0.0, 1.0)
+    }
+
+    pub fn width(&self) -> usize {
+        self.width.unwrap_or(3).max(1)
+    }
+}
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct MorseConfig {
+    /// Sending speed, words per minute (default 12)
+    pub wpm: Option<f64>,
+    /// Pitch of the beeps, MIDI note (default 84, about 1 kHz)
+    pub pitch: Option<u8>,
+    /// LEDs to flash, as in `leds.patterns` (default power)
+    pub leds: Option<Vec<String>>,
+    /// Power LED color, 0 green to 255 red (default the battery gauge's, else green)
+    pub color: Option<u8>,
+}
+
+impl MorseConfig {
+    /// One dot, by the 50 dots of PARIS to a word.
+    pub fn dot(&self) -> Duration {
+        Duration::from_secs_f64(1.2 / self.wpm.unwrap_or(12.0).clamp(1.0, 40.0))
+    }
+
+    pub fn pitch(&self) -> u8 {
+        self.pitch.unwrap_or(84)
+    }
+}
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct ButtonsConfig {
+    /// Run commands on button presses (default false)
+    pub enabled: Option<bool>,
+    /// Held this long, a press is a long press, s (default 1)
+    pub long_press_s: Option<f64>,
+    /// Control commands by button (clean, spot, dock, minute, hour, day, schedule, clock; play, advance on Create 1)
+    pub press: Option<BTreeMap<String, String>>,
+    /// and for long presses
+    pub long_press: Option<BTreeMap<String, String>>,
+}
+
+impl ButtonsConfig {
+    pub fn enabled(&self) -> bool {
+        self.enabled.unwrap_or(false)
+    }
+
+    pub fn long_press(&self) -> Duration {
+        Duration::from_secs_f64(self.long_press_s.unwrap_or(1.0).max(0.1))
+    }
+}
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct QuietConfig {
+    /// Local times quiet mode is on, as HH:MM-HH:MM ranges (e.g. "22:00-07:00"; default none)
+    pub hours: Option<Vec<String>>,
+    /// Beeps played even in quiet mode (default ["battery_low"])
+    pub critical_beeps: Option<Vec<String>>,
+    /// LED patterns played even in quiet mode (default ["alert"])
+    pub critical_leds: Option<Vec<String>>,
+}
+
+impl QuietConfig {
+    /// `hours`, parsed: start and end of each range.
+    pub fn hours(&self) -> Result<Vec<(chrono::NaiveTime, chrono::NaiveTime)>, String> {
+        let time = |t: &str| chrono::NaiveTime::parse_from_str(t.trim(), "%H:%M");
+        let hours = self.hours.as_deref().unwrap_or_default();
+        hours
+            .iter()
+            .map(|range| match range.split_once('-').map(|(start, end)| (time(start), time(end))) {
+                Some((Ok(start), Ok(end))) => Ok((start, end)),
+                _ => Err(format!("quiet.hours: bad range {range:?}, expected HH:MM-HH:MM")),
+            })
+            .collect()
+    }
+
+    pub fn critical_beeps(&self) -> Vec<String> {
+        self.critical_beeps.clone().unwrap_or_else(|| vec!["battery_low".into()])
+    }
+
+    pub fn critical_leds(&self) -> Vec<String> {
+        self.critical_leds.clone().unwrap_or_else(|| vec!["alert".into()])
+    }
+}
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct AuditConfig {
+    /// Log every safety intervention (default true)
+    pub enabled: Option<bool>,
+    /// JSON lines file (default /var/lib/created/audit.jsonl)
+    pub path: Option<String>,
+    /// Move the file aside to `.1` once it reaches this size (default 10 MiB)
+    pub max_size_mb: Option<u64>,
+}
+
+impl AuditConfig {
+    pub fn enabled(&self) -> bool {
+        self.enabled.unwrap_or(true)
+    }
+
+    pub fn path(&self) -> PathBuf {
+        PathBuf::from(self.path.as_deref().unwrap_or("/var/lib/created/audit.jsonl"))
+    }
+
+    pub fn max_bytes(&self) -> u64 {
+        self.max_size_mb.unwrap_or(10).max(1) * 1024 * 1024
+    }
+}
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct EstopConfig {
+    /// Engage on SIGUSR1 (default true)
+    pub signal: Option<bool>,
+    /// Address to serve the HTTP endpoint on, e.g. "0.0.0.0:8710" (default none)
+    pub http: Option<String>,
+    /// Allow clearing it over HTTP too (default false: only the control socket)
+    pub http_clear: Option<bool>,
+    /// Kept while engaged, so a restart doesn't clear it (default /var/lib/created/estop.json)
+    pub latch_file: Option<String>,
+}
+
+impl EstopConfig {
+    pub fn signal(&self) -> bool {
+        self.signal.unwrap_or(true)
+    }
+
+    pub fn http_clear(&self) -> bool {
+        self.http_clear.unwrap_or(false)
+    }
+
+    pub fn latch_file(&self) -> PathBuf {
+        PathBuf::from(self.latch_file.as_deref().unwrap_or("/var/lib/created/estop.json"))
+    }
+}
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct CliffConfig {
+    /// How long no cliff sensor has to see a drop before forward motion is allowed again, ms (default 500)
+    pub clear_ms: Option<u64>,
+}
+
+impl CliffConfig {
+    pub fn clear(&self) -> Duration {
+        Duration::from_millis(self.clear_ms.unwrap_or(500))
+    }
+}
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct OvercurrentConfig {
+    /// Cut wheels and brushes flagged for overcurrent (default true)
+    pub enabled: Option<bool>,
+    /// How long a motor's flag has to stay up before it is cut, ms (default 1000)
+    pub persist_ms: Option<u64>,
+}
+
+impl OvercurrentConfig {
+    pub fn enabled(&self) -> bool {
+        self.enabled.unwrap_or(true)
+    }
+
+    pub fn persist(&self) -> Duration {
+        Duration::from_millis(self.persist_ms.unwrap_or(1000))
+    }
+}
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct BatteryTemperatureConfig {
+    /// Act on the battery temperature (default true)
+    pub enabled: Option<bool>,
+    /// From here the speed is held down and charging paused, °C (default 45)
+    pub warning_c: Option<f64>,
+    /// From here the wheels stop, °C (default 55)
+    pub critical_c: Option<f64>,
+    /// How far below a threshold the temperature has to fall to leave its level, °C (default 3)
+    pub hysteresis_c: Option<f64>,
+    /// Top wheel speed from `warning_c`, mm/s (default 200)
+    pub warning_speed: Option<f64>,
+    /// Pause charging from `warning_c` (default true)
+    pub pause_charging: Option<bool>,
+}
+
+impl BatteryTemperatureConfig {
+    pub fn enabled(&self) -> bool {
+        self.enabled.unwrap_or(true)
+    }
+
+    pub fn warning_c(&self) -> f64 {
+        self.warning_c.unwrap_or(45.0)
+    }
+
+    pub fn critical_c(&self) -> f64 {
+        self.critical_c.unwrap_or(55.0)
+    }
+
+    pub fn hysteresis_c(&self) -> f64 {
+        self.hysteresis_c.unwrap_or(3.0).max(0.0)
+    }
+
+    pub fn warning_speed(&self) -> f64 {
+        self.warning_speed.unwrap_or(200.0).clamp(0.0, crate::motion::MAX_SPEED as f64)
+    }
+
+    pub fn pause_charging(&self) -> bool {
+        self.pause_charging.unwrap_or(true)
+    }
+}
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct SafetyConfig {
+    /// Let the control socket and scripts put the robot in Full mode, without its cliff and wheel drop safeties (default false)
+    pub allow_full_mode: Option<bool>,
+    /// Cap on the forward speed sent, either way, mm/s (default none)
+    pub max_speed: Option<f64>,
+    /// Cap on the turn rate sent, °/s (default none)
+    pub max_turn_rate: Option<f64>,
+    /// Cap on how fast the wheels speed up, mm/s² (default none)
+    pub max_accel: Option<f64>,
+}
+
+impl SafetyConfig {
+    pub fn allow_full_mode(&self) -> bool {
+        self.allow_full_mode.unwrap_or(false)
+    }
+
+    pub fn max_speed(&self) -> Option<f64> {
+        self.max_speed.filter(|v| *v >= 0.0)
+    }
+
+    pub fn max_turn_rate(&self) -> Option<f64> {
+        self.max_turn_rate.filter(|v| *v >= 0.0)
+    }
+
+    pub fn max_accel(&self) -> Option<f64> {
+        self.max_accel.filter(|v| *v > 0.0)
+    }
+}
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct WheelDropConfig {
+    /// How long both wheels have to be down again before the robot may move, ms (default 1000)
+    pub debounce_ms: Option<u64>,
+}
+
+impl WheelDropConfig {
+    pub fn debounce(&self) -> Duration {
+        Duration::from_millis(self.debounce_ms.unwrap_or(1000))
+    }
+}
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct ChargingLockoutConfig {
+    /// Refuse to drive while on the dock, other than to undock (default true)
+    pub enabled: Option<bool>,
+    /// How far `undock` backs off the contacts, m (default 0.3)
+    pub undock_m: Option<f64>,
+    /// How fast it backs off, mm/s (default 100)
+    pub undock_speed: Option<f64>,
+}
+
+impl ChargingLockoutConfig {
+    pub fn enabled(&self) -> bool {
+        self.enabled.unwrap_or(true)
+    }
+
+    pub fn undock_m(&self) -> f64 {
+        self.undock_m.unwrap_or(0.3).max(0.0)
+    }
+
+    pub fn undock_speed(&self) -> f64 {
+        self.undock_speed.unwrap_or(100.0)
+    }
+}
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct LowVoltageConfig {
+    /// Cut off below the critical voltage (default true)
+    pub enabled: Option<bool>,
+    /// Critical voltage, mV (default 12000)
+    pub cutoff_mv: Option<i32>,
+    /// How long the voltage has to stay below it, ms (default 3000)
+    pub debounce_ms: Option<u64>,
+    /// Motion allowed again once the voltage is back to this, mV (default 13500)
+    pub resume_mv: Option<i32>,
+    /// Power the robot down after the warning, ending the session (default false: Passive mode)
+    pub power_off: Option<bool>,
+}
+
+impl LowVoltageConfig {
+    pub fn enabled(&self) -> bool {
+        self.enabled.unwrap_or(true)
+    }
+
+    pub fn cutoff_mv(&self) -> i32 {
+        self.cutoff_mv.unwrap_or(12000)
+    }
+
+    pub fn debounce(&self) -> Duration {
+        Duration::from_millis(self.debounce_ms.unwrap_or(3000))
+    }
+
+    pub fn resume_mv(&self) -> i32 {
+        self.resume_mv.unwrap_or(13500).max(self.cutoff_mv())
+    }
+
+    pub fn power_off(&self) -> bool {
+        self.power_off.unwrap_or(false)
+    }
+}
+
+/// How hard the daemon works at keeping its host awake.
+#[derive(Debug, Deserialize, Serialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PowerProfile {
+    /// Full-rate streaming, quick discovery
+    #[default]
+    Normal,
+    /// For a battery-powered host: fewer wakeups, and slower to notice things
+    Low,
+}
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct PowerConfig {
+    /// normal (default) or low; the settings below default by it
+    pub profile: Option<PowerProfile>,
+    /// How often to look for a port when nothing says one came, seconds (default 2; low 10)
+    pub rescan_s: Option<u64>,
+    /// While the robot is idle, pause its stream and read a frame this often instead, ms (default 0, never; low 500)
+    pub idle_poll_ms: Option<u64>,
+    /// How long with no behavior, wheels, commands, or events counts as idle, seconds (default 10)
+    pub idle_after_s: Option<u64>,
+    /// Let the kernel fire the daemon's timers up to this late, to wake the host for several at once, ms (default: the kernel's 0.05; low 50)
+    pub timer_slack_ms: Option<u64>,
+}
+
+impl PowerConfig {
+    pub fn profile(&self) -> PowerProfile {
+        self.profile.unwrap_or_default()
+    }
+
+    fn low(&self) -> bool {
+        self.profile() == PowerProfile::Low
+    }
+
+    pub fn rescan(&self) -> Duration {
+        Duration::from_secs(self.rescan_s.unwrap_or(if self.low() { 10 } else { 2 }).max(1))
+    }
+
+    pub fn idle_poll(&self) -> Option<Duration> {
+        let ms = self.idle_poll_ms.unwrap_or(if self.low() { 500 } else { 0 });
+        Some(Duration::from_millis(ms)).filter(|d| !d.is_zero())
+    }
+
+    pub fn idle_after(&self) -> Duration {
+        Duration::from_secs(self.idle_after_s.unwrap_or(10))
+    }
+
+    pub fn timer_slack(&self) -> Option<Duration> {
+        let ms = self.timer_slack_ms.or(self.low().then_some(50))?;
+        Some(Duration::from_millis(ms))
+    }
+}
@@ -0,0 +1,334 @@
+//! Control socket: a Unix stream socket accepting one command per line and
+//! answering each with one line of JSON (`{"ok": true, ...}` or
+//! `{"ok": false, "error": "..."}`). `createctl` is a thin client for it.
+
+#[cfg(unix)]
+use std::fs;
+#[cfg(unix)]
+use std::io::{BufRead, BufReader, Write};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::path::Path;
+use std::sync::Mutex;
+#[cfg(unix)]
+use std::thread;
+use std::time::Duration;
+
+use serde_json::{json, Value};
+#[cfg(unix)]
+use tracing::{debug, warn};
+use tracing::info;
+
+use crate::behavior;
+use crate::bus::Bus;
+#[cfg(unix)]
+use crate::bus::Event;
+use crate::config::ControlConfig;
+use crate::motion::{self, Wheels};
+use crate::{aliases, audit, battery, bumper, charging_lockout, cliff, command, estop, governor, leds, low_voltage, monitor, morse, overcurrent, patrol, pose, power, quiet, release, safety, sniffer, song, stats, thermal, timing, wheel_drop};
+
+/// Where received commands are published. Held outside the server threads so
+/// the daemon can let go of it at shutdown and the sinks can finish.
+static BUS: Mutex<Option<Bus>> = Mutex::new(None);
+
+/// Publish received commands as `control` events on `bus` (None stops).
+pub fn attach(bus: Option<Bus>) {
+    *BUS.lock().unwrap_or_else(|e| e.into_inner()) = bus;
+}
+
+/// Bind the socket and serve it on a background thread. Failure to bind is
+/// logged, not fatal: the daemon works without it.
+#[cfg(unix)]
+pub fn spawn(cfg: &ControlConfig) {
+    if !cfg.enabled() {
+        return;
+    }
+    let path = cfg.socket();
+    let listener = match bind(&path) {
+        Ok(l) => l,
+        Err(e) => {
+            warn!("control socket disabled: {e}");
+            return;
+        }
+    };
+    info!("control socket listening on {}", path.display());
+    thread::spawn(move || {
+        for conn in listener.incoming() {
+            match conn {
+                Ok(stream) => {
+                    thread::spawn(move || serve(stream));
+                }
+                Err(e) => warn!("control socket accept: {e}"),
+            }
+        }
+    });
+}
+
+/// The button mappings' commands still run; there is no socket to send them.
+#[cfg(not(unix))]
+pub fn spawn(cfg: &ControlConfig) {
+    if cfg.enabled() {
+        info!("no control socket: Unix sockets only");
+    }
+}
+
+#[cfg(unix)]
+fn bind(path: &Path) -> Result<UnixListener, String> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| format!("create {}: {e}", dir.display()))?;
+    }
+    // A stale socket from a previous run would make bind fail
+    if path.exists() && UnixStream::connect(path).is_err() {
+        let _ = fs::remove_file(path);
+    }
+    UnixListener::bind(path).map_err(|e| format!("bind {}: {e}", path.display()))
+}
+
+#[cfg(unix)]
+fn serve(stream: UnixStream) {
+    let Ok(mut out) = stream.try_clone() else { return };
+    let mut teleop = false;
+    for line in BufReader::new(stream).lines() {
+        let Ok(line) = line else { break };
+        if line.trim().is_empty() {
+            continue;
+        }
+        debug!("control: {line}");
+        if !is_query(&line) {
+            if let Some(bus) = BUS.lock().unwrap_or_else(|e| e.into_inner()).as_ref() {
+                bus.event(Event::Control { command: line.trim().to_string() });
+            }
+            // A stream slowed for an idle robot picks up at once
+            power::nudge();
+        }
+        // Once the port is free, so the other program can be started as
+        // `createctl release` returns: waited for here, as a button's
+        // release runs on the session's own thread
+        let result = handle(&line).and_then(|v| if is_release(&line) { release::wait() } else { Ok(v) });
+        let reply = match result {
+            Ok(Value::Object(fields)) => {
+                let mut map = serde_json::Map::new();
+                map.insert("ok".into(), true.into());
+                map.extend(fields);
+                Value::Object(map)
+            }
+            Ok(other) => json!({ "ok": true, "result": other }),
+            Err(e) => json!({ "ok": false, "error": e }),
+        };
+        // Connection state rather than a daemon command, so handled here
+        if line.trim() == "teleop" {
+            teleop = true;
+            if writeln!(out, "{}", json!({ "ok": true, "teleop": true })).is_err() {
+                break;
+            }
+            continue;
+        }
+        if writeln!(out, "{reply}").is_err() {
+            break;
+        }
+    }
+    // An interactive driver that goes away (teleop quit, SSH dropped) must
+    // not leave the robot moving
+    if teleop {
+        motion::stop();
+        audit::record("teleop_stop", "control socket", "teleop connection closed");
+    }
+}
+
+/// Safety audit log entries shown when no count is given
+const AUDIT_ENTRIES: usize = 20;
+
+/// Commands that only show something, and heartbeats. Clients such as
+/// `created tui` poll them, so they aren't published as events.
+#[cfg(unix)]
+const QUERIES: [&str; 17] = ["status", "alias", "sensors", "timing", "power", "battery", "pose", "behavior", "self-test", "song", "leds", "quiet", "bumper", "overcurrent", "patrol", "route", "sniff"];
+
+#[cfg(unix)]
+fn is_query(line: &str) -> bool {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    match words.as_slice() {
+        ["events", ..] | ["audit", ..] | ["validate", ..] | ["estop", "status"] | ["release", "status"] | ["heartbeat"] => true,
+        [word] => QUERIES.contains(word),
+        _ => false,
+    }
+}
+
+/// `release`, or `release <seconds>`
+#[cfg(unix)]
+fn is_release(line: &str) -> bool {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    matches!(words.as_slice(), ["release"]) || matches!(words.as_slice(), ["release", word] if *word != "status")
+}
+
+/// Run one command, as received on the socket or configured for a button.
+pub fn handle(line: &str) -> Result<Value, String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    match words.as_slice() {
+        ["drive", left, right] => {
+            let (left, right) = match (left.parse::<i32>(), right.parse::<i32>()) {
+                (Ok(l), Ok(r)) => (l, r),
+                _ => return Err("usage: drive <left mm/s> <right mm/s>".into()),
+            };
+            estop::check()?;
+            let wheels = Wheels::new(left, right);
+            if wheels != Wheels::STOP {
+                low_voltage::check()?;
+                charging_lockout::check()?;
+            }
+            behavior::cancel("manual drive");
+            motion::lease(wheels);
+            Ok(json!({ "left": wheels.left, "right": wheels.right }))
+        }
+        ["heartbeat"] => Ok(json!({ "renewed": motion::renew() })),
+        ["stop"] => {
+            behavior::cancel("manual stop");
+            motion::stop();
+            Ok(json!({ "left": 0, "right": 0 }))
+        }
+        ["estop"] => Ok(estop::engage("control")),
+        ["estop", "status"] => Ok(estop::status()),
+        ["estop", "clear"] => estop::clear("control"),
+        ["mode", mode] => safety::set_mode(mode),
+        ["status"] => {
+            let (session, last_session) = stats::snapshot();
+            Ok(json!({
+                "connected": session.is_some(),
+                "robot": aliases::current(),
+                "released": release::status(),
+                "drive": motion::current(),
+                "layer": motion::layer(),
+                "governor": governor::status(),
+                "cliff": cliff::status(),
+                "wheel_drop": wheel_drop::status(),
+                "charging_lockout": charging_lockout::status(),
+                "low_voltage": low_voltage::status(),
+                "battery_temperature": thermal::status(),
+                "session": session,
+                "last_session": last_session
+            }))
+        }
+        ["release"] => Ok(release::release(None)),
+        ["release", "status"] => Ok(release::status()),
+        ["release", seconds] => {
+            let seconds = seconds.parse::<f64>().ok().filter(|s| s.is_finite() && *s > 0.0).ok_or("usage: release [seconds | status]")?;
+            Ok(release::release(Some(Duration::from_secs_f64(seconds))))
+        }
+        ["reclaim"] => release::reclaim(),
+        ["alias"] => Ok(aliases::status()),
+        ["alias", "set", name] => aliases::set(name),
+        ["alias", "clear"] => aliases::clear(),
+        ["sensors"] => monitor::sensors(),
+        ["events"] => Ok(monitor::events(0)),
+        ["events", after] => Ok(monitor::events(after.parse().map_err(|_| format!("bad event number {after:?}"))?)),
+        ["timing"] => match timing::report() {
+            Some(report) => serde_json::to_value(report).map_err(|e| e.to_string()),
+            None => Err("no complete timing window yet".to_string()),
+        },
+        ["power"] => Ok(power::status()),
+        ["battery"] => battery::snapshot().ok_or_else(|| "battery tracking is disabled".to_string()),
+        ["pose"] => match pose::current() {
+            Some(p) => serde_json::to_value(p).map_err(|e| e.to_string()),
+            None => Err("pose tracking is disabled or no robot is connected".to_string()),
+        },
+        ["pose", "reset"] => pose::reset().map(|()| json!({ "x": 0.0, "y": 0.0, "theta": 0.0 })),
+        ["behavior"] => Ok(json!({ "behavior": behavior::status() })),
+        ["behavior", "stop"] => {
+            let cancelled = behavior::cancel("stopped from the control socket");
+            motion::stop();
+            Ok(json!({ "cancelled": cancelled }))
+        }
+        ["behavior", "start", name, args @ ..] => {
+            behavior::start(behavior::build(name, args)?)?;
+            Ok(json!({ "behavior": name }))
+        }
+        ["self-test"] => Ok(json!({ "self_test": behavior::selftest::report() })),
+        ["self-test", "run"] => {
+            behavior::start(behavior::build("self-test", &[])?)?;
+            Ok(json!({ "behavior": "self-test" }))
+        }
+        ["undock"] => {
+            behavior::start(behavior::build("undock", &[])?)?;
+            Ok(json!({ "behavior": "undock" }))
+        }
+        ["validate", "mission", file] => behavior::mission::problems(file, &behavior::config()),
+        ["validate", "script", file] => Ok(match behavior::script::Script::load(&[file], &behavior::config()) {
+            Ok(_) => json!({ "valid": true, "errors": [] }),
+            Err(e) => json!({ "valid": false, "errors": [{ "error": e }] }),
+        }),
+        ["validate", "oi", bytes @ ..] if !bytes.is_empty() => {
+            let values = bytes.iter().map(|b| b.parse::<i64>().map_err(|_| format!("bad byte {b:?}"))).collect::<Result<Vec<_>, _>>()?;
+            let checked = command::bytes(&values).and_then(|program| command::validate(&program, None));
+            Ok(match checked {
+                Ok(commands) => json!({ "valid": true, "commands": commands, "errors": [] }),
+                Err(problems) => json!({ "valid": false, "errors": problems }),
+            })
+        }
+        ["mission", "run", file] => {
+            behavior::start(behavior::build("mission", &[file])?)?;
+            Ok(json!({ "behavior": "mission", "mission": file }))
+        }
+        ["song"] => Ok(song::status()),
+        ["song", "play", name] => song::play(name, None),
+        ["song", "play", name, track] => song::play(name, Some(track.parse().map_err(|_| format!("bad track {track:?}"))?)),
+        ["song", "stop"] => song::stop(),
+        ["song", "play-rtttl", text @ ..] if !text.is_empty() => song::play_rtttl(&text.join(" ")),
+        ["leds"] => Ok(leds::status()),
+        ["leds", "play", name] => leds::play(name),
+        ["leds", "stop"] => leds::stop(),
+        ["morse", "beep", text @ ..] if !text.is_empty() => morse::beep(&text.join(" ")),
+        ["morse", "leds", text @ ..] if !text.is_empty() => morse::flash(&text.join(" ")),
+        ["quiet"] => Ok(quiet::status()),
+        ["quiet", "on"] => Ok(quiet::set(true)),
+        ["quiet", "off"] => Ok(quiet::set(false)),
+        ["quiet", "toggle"] => Ok(quiet::toggle()),
+        ["quiet", "auto"] => Ok(quiet::auto()),
+        ["audit", args @ ..] => {
+            let (action, count) = match args {
+                [] => (None, AUDIT_ENTRIES),
+                [word] => match word.parse() {
+                    Ok(count) => (None, count),
+                    Err(_) => (Some(*word), AUDIT_ENTRIES),
+                },
+                [action, count] => (Some(*action), count.parse().map_err(|_| format!("bad count {count:?}"))?),
+                _ => return Err("usage: audit [action] [count]".into()),
+            };
+            audit::latest(count, action)
+        }
+        ["overcurrent"] => Ok(overcurrent::status()),
+        ["overcurrent", "ack"] => overcurrent::acknowledge(),
+        ["bumper"] => bumper::status(),
+        ["bumper", "calibrate", "save"] => bumper::save(),
+        ["bumper", "calibrate", kind] => bumper::calibrate(kind),
+        ["patrol"] => Ok(patrol::status()),
+        ["patrol", "run", args @ ..] => {
+            behavior::start(behavior::build("patrol", args)?)?;
+            Ok(json!({ "behavior": "patrol", "route": args.first().map(|r| r.to_string()).or(patrol::config().route) }))
+        }
+        ["route"] => Ok(json!({ "recording": behavior::route::recording_status() })),
+        ["route", "list"] => Ok(json!({ "routes": behavior::route::list(&behavior::config()) })),
+        ["route", "record", name] => {
+            behavior::route::record(name, &behavior::config())?;
+            Ok(json!({ "recording": name }))
+        }
+        ["route", "stop"] => behavior::route::stop(),
+        ["route", "play", args @ ..] => {
+            behavior::start(behavior::build("route", args)?)?;
+            Ok(json!({ "behavior": "route", "route": args.first() }))
+        }
+        ["waypoints", specs @ ..] => {
+            behavior::start(behavior::build("waypoints", specs)?)?;
+            Ok(json!({ "behavior": "waypoints", "waypoints": specs.len() }))
+        }
+        ["sniff"] => Ok(json!({ "sniffing": sniffer::enabled() })),
+        ["sniff", "on"] => {
+            let path = sniffer::set_enabled(true)?;
+            Ok(json!({ "sniffing": true, "path": path }))
+        }
+        ["sniff", "off"] => {
+            sniffer::set_enabled(false)?;
+            Ok(json!({ "sniffing": false }))
+        }
+        _ => Err(format!("unknown command: {line}")),
+    }
+}
@@ -0,0 +1,61 @@
+//! Discrete events derived from the sensor stream: OI mode changes and the
+//! rising edges of bumps, wheel drops, cliffs, and docking.
+
+use crate::bus::Event;
+use crate::oi::{self, SensorFrame};
+
+const BUMPS_WHEELDROPS: u8 = 7;
+const CLIFFS: [(u8, &str); 4] = [(9, "left"), (10, "front_left"), (11, "front_right"), (12, "right")];
+const CHARGING_SOURCES: u8 = 34;
+/// Bit of packet 34 set while the robot sits on its home base
+const HOME_BASE: i32 = 0b10;
+
+/// Compares each frame with the previous one. Packets missing from a frame
+/// (not streamed) never produce events.
+#[derive(Default)]
+pub struct EventDetector {
+    prev: Option<SensorFrame>,
+}
+
+impl EventDetector {
+    pub fn update(&mut self, frame: &SensorFrame) -> Vec<Event> {
+        let mut events = Vec::new();
+        let prev = self.prev.as_ref();
+        let was = |id: u8| prev.and_then(|p| p.get(id));
+
+        if let Some(mode) = frame.get(oi::OI_MODE).filter(|m| Some(*m) != was(oi::OI_MODE)) {
+            events.push(Event::ModeChanged {
+                from: was(oi::OI_MODE).map(|m| oi::mode_name(m).to_string()),
+                to: oi::mode_name(mode).to_string(),
+            });
+        }
+        if let Some(bits) = frame.get(BUMPS_WHEELDROPS) {
+            let rising = bits & !was(BUMPS_WHEELDROPS).unwrap_or(0);
+            if rising & 0b11 != 0 {
+                events.push(Event::Bump { left: bits & 0b10 != 0, right: bits & 0b01 != 0 });
+            }
+            if rising & 0b1100 != 0 {
+                events.push(Event::WheelDrop { left: bits & 0b1000 != 0, right: bits & 0b0100 != 0 });
+            }
+        }
+        let cliffs: Vec<String> = CLIFFS
+            .iter()
+            .filter(|(id, _)| frame.get(*id).unwrap_or(0) != 0 && was(*id).unwrap_or(0) == 0)
+            .map(|(_, name)| name.to_string())
+            .collect();
+        if !cliffs.is_empty() {
+            events.push(Event::Cliff { sensors: cliffs });
+        }
+        if let Some(sources) = frame.get(CHARGING_SOURCES) {
+            let docked = sources & HOME_BASE != 0;
+            match was(CHARGING_SOURCES).map(|s| s & HOME_BASE != 0) {
+                Some(was_docked) if was_docked == docked => {}
+                // The first frame only reports a dock already in place
+                None if !docked => {}
+                _ => events.push(if docked { Event::Docked } else { Event::Undocked }),
+            }
+        }
+        self.prev = Some(*frame);
+        events
+    }
+}
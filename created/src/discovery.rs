@@ -0,0 +1,261 @@
+//! Finding the robot's serial port. `serial.path` if it is set and exists;
+//! otherwise the udev symlinks, `/dev/serial/by-id`, `ttyUSB*`/`ttyACM*` (on
+//! macOS, `cu.usbserial*`/`cu.usbmodem*`), and Bluetooth adapters bound with
+//! `rfcomm bind`, `rfcomm*`, that pass the USB filters, each device once, in
+//! that order, for the worker to probe. A `bt://`, `tcp://`,
+//! `rfc2217://`, or `mock://` path is taken as it is: there is nothing
+//! under /dev to look for. With `serial.usb_ids` or `serial.usb_serials` set, only USB adapters
+//! with one of those vendor (and product) IDs or serial numbers are taken,
+//! so a GPS or an Arduino plugged in next to the robot's cable isn't
+//! grabbed for it; their IDs come from sysfs on Linux, elsewhere as the
+//! system lists its ports. On Windows, with no /dev, the COM ports it lists
+//! are taken instead, USB adapters first. A port that answered the worker's
+//! probe with something other than the OI is passed over too, until it is
+//! plugged in again or `REJECTED` has passed.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use tracing::{debug, warn};
+
+use crate::bluetooth;
+use crate::config::SerialConfig;
+use crate::{mock, tcp};
+
+/// How long a port that isn't a robot is passed over
+const REJECTED: Duration = Duration::from_secs(60);
+
+/// What sysfs says about a USB serial adapter.
+struct Usb {
+    vendor: u16,
+    product: u16,
+    serial: Option<String>,
+}
+
+/// The USB adapter behind the tty `path` (or a symlink to it), if it is one.
+#[cfg(target_os = "linux")]
+fn usb(path: &Path) -> Option<Usb> {
+    let dev = fs::canonicalize(path).ok()?;
+    let name = dev.file_name()?.to_str()?;
+    let device = fs::canonicalize(Path::new("/sys/class/tty").join(name).join("device")).ok()?;
+    // The interface, then the device with the IDs above it
+    let usb = device.ancestors().find(|d| d.join("idVendor").exists())?;
+    let read = |file: &str| fs::read_to_string(usb.join(file)).ok().map(|s| s.trim().to_string());
+    Some(Usb {
+        vendor: u16::from_str_radix(&read("idVendor")?, 16).ok()?,
+        product: u16::from_str_radix(&read("idProduct")?, 16).ok()?,
+        serial: read("serial"),
+    })
+}
+
+/// The USB adapter behind the port `path`, as the system lists it: there's
+/// no sysfs to read but on Linux.
+#[cfg(not(target_os = "linux"))]
+fn usb(path: &Path) -> Option<Usb> {
+    let name = port_name(path)?;
+    let port = serialport::available_ports().ok()?.into_iter().find(|p| p.port_name.eq_ignore_ascii_case(name))?;
+    match port.port_type {
+        serialport::SerialPortType::UsbPort(info) => Some(Usb { vendor: info.vid, product: info.pid, serial: info.serial_number }),
+        _ => None,
+    }
+}
+
+/// The port's name as listed: `COM3` of `COM3` or `\\.\COM3`, a path as it is.
+#[cfg(not(target_os = "linux"))]
+fn port_name(path: &Path) -> Option<&str> {
+    path.to_str().map(|p| p.trim_start_matches(r"\\.\"))
+}
+
+/// What tells the robot on `path` from others, whatever tty it comes up
+/// as: its adapter's USB IDs and serial number, or else the device.
+pub fn identity(path: &Path) -> String {
+    match usb(path) {
+        Some(Usb { vendor, product, serial: Some(serial) }) => format!("usb {vendor:04x}:{product:04x} {serial}"),
+        _ => fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf()).display().to_string(),
+    }
+}
+
+/// The serial number of the USB adapter behind `path`, if it has one.
+pub fn serial_number(path: &Path) -> Option<String> {
+    usb(path)?.serial
+}
+
+/// Which ports autodetection may take.
+pub struct Filter {
+    /// Vendor, and product if given; None for an entry that can't be read, which matches nothing
+    ids: Vec<Option<(u16, Option<u16>)>>,
+    serials: Vec<String>,
+    /// Ports found not to be a robot, by device, and when
+    rejected: HashMap<PathBuf, Instant>,
+}
+
+impl Filter {
+    pub fn new(cfg: &SerialConfig) -> Filter {
+        let ids = cfg.usb_ids.clone().unwrap_or_default().into_iter().map(|id| {
+            let parsed = parse_id(&id);
+            if parsed.is_none() {
+                warn!("serial.usb_ids: can't read {id:?}, expected a hex vendor ID or vendor:product (e.g. \"0403:6001\")");
+            }
+            parsed
+        }).collect();
+        Filter { ids, serials: cfg.usb_serials.clone().unwrap_or_default(), rejected: HashMap::new() }
+    }
+
+    /// Pass over the port at `path`, which isn't a robot.
+    pub fn reject(&mut self, path: &Path) {
+        self.rejected.insert(fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf()), Instant::now());
+    }
+
+    /// The device at `path` was plugged in (again): it may be a robot now.
+    pub fn plugged(&mut self, path: &Path) {
+        self.rejected.remove(path);
+    }
+
+    fn rejected(&self, path: &Path) -> bool {
+        let dev = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        self.rejected.get(&dev).is_some_and(|at| at.elapsed() < REJECTED)
+    }
+
+    /// Whether the port at `path` may be taken.
+    fn allows(&self, path: &Path) -> bool {
+        if self.rejected(path) {
+            debug!("skipping {}: it isn't a robot", path.display());
+            return false;
+        }
+        if self.ids.is_empty() && self.serials.is_empty() {
+            return true;
+        }
+        let Some(usb) = usb(path) else {
+            debug!("skipping {}: not a USB adapter, and serial.usb_ids/usb_serials are set", path.display());
+            return false;
+        };
+        let id = self.ids.is_empty() || self.ids.iter().flatten().any(|(vendor, product)| {
+            *vendor == usb.vendor && product.is_none_or(|p| p == usb.product)
+        });
+        let serial = self.serials.is_empty() || usb.serial.as_ref().is_some_and(|s| self.serials.contains(s));
+        if !(id && serial) {
+            debug!("skipping {}: USB {:04x}:{:04x}, serial {}, not in serial.usb_ids/usb_serials", path.display(), usb.vendor, usb.product, usb.serial.as_deref().unwrap_or("none"));
+        }
+        id && serial
+    }
+}
+
+/// `vendor` or `vendor:product`, in hex.
+fn parse_id(id: &str) -> Option<(u16, Option<u16>)> {
+    let hex = |s: &str| u16::from_str_radix(s.trim().trim_start_matches("0x"), 16).ok();
+    match id.split_once(':') {
+        Some((vendor, product)) => Some((hex(vendor)?, Some(hex(product)?))),
+        None => Some((hex(id)?, None)),
+    }
+}
+
+/// The ports that may be the robot's, best first: `serial.path` alone if
+/// it is there, or every device autodetection finds once, under the first
+/// name it is found by.
+pub fn candidates(cfg: &SerialConfig, filter: &Filter) -> Vec<PathBuf> {
+    // 1) Configured path, Bluetooth adapter, networked port, or simulated robot
+    if let Some(ref p) = cfg.path {
+        let pb = callout(PathBuf::from(p));
+        if (present(&pb) || p.starts_with(bluetooth::SCHEME) || tcp::is_network(p) || p.starts_with(mock::SCHEME)) && !filter.rejected(&pb) { return vec![pb]; }
+    }
+    let mut found = ports();
+    found.retain(|p| filter.allows(p));
+    found
+}
+
+/// The macOS call-out device `/dev/cu.*` for a dial-in device
+/// `/dev/tty.*`, whose open waits for a carrier a USB adapter never
+/// raises; any other path as it is.
+fn callout(path: PathBuf) -> PathBuf {
+    let Some(name) = path.to_str().and_then(|p| p.strip_prefix("/dev/tty.")) else { return path };
+    let cu = PathBuf::from(format!("/dev/cu.{name}"));
+    if !cu.exists() {
+        return path;
+    }
+    debug!("using {} rather than {}", cu.display(), path.display());
+    cu
+}
+
+#[cfg(unix)]
+fn present(path: &Path) -> bool {
+    path.exists()
+}
+
+/// COM ports aren't files: one is there if Windows lists it.
+#[cfg(not(unix))]
+fn present(path: &Path) -> bool {
+    port_name(path).is_some_and(|name| ports().iter().any(|p| p.to_str().is_some_and(|p| p.eq_ignore_ascii_case(name))))
+}
+
+/// Every serial device under /dev that may be a robot's, once each.
+#[cfg(unix)]
+fn ports() -> Vec<PathBuf> {
+    let mut found: Vec<PathBuf> = Vec::new();
+    // 2) Our udev-provided symlinks
+    if let Ok(entries) = fs::read_dir("/dev/serial") {
+        let mut links: Vec<PathBuf> = entries.flatten().map(|e| e.path()).filter(|p| {
+            p.file_name().and_then(|s| s.to_str()).is_some_and(|name| name.starts_with("by-irobot-"))
+        }).collect();
+        links.sort();
+        found.extend(links);
+    }
+    // 3) /dev/serial/by-id/* is the most stable symlink location
+    if let Ok(entries) = fs::read_dir("/dev/serial/by-id") {
+        let mut links: Vec<PathBuf> = entries.flatten().map(|e| e.path()).collect();
+        links.sort();
+        found.extend(links);
+    }
+    // 4) Fallback to ttyUSB* and ttyACM*, or on macOS the call-out devices
+    //    of USB adapters (not their tty.* twins), then Bluetooth adapters
+    //    bound with `rfcomm bind`
+    let mut ttys: Vec<PathBuf> = Vec::new();
+    let mut rfcomms: Vec<PathBuf> = Vec::new();
+    if let Ok(entries) = fs::read_dir("/dev") {
+        for e in entries.flatten() {
+            let name = e.file_name();
+            if let Some(s) = name.to_str() {
+                if ["ttyUSB", "ttyACM", "cu.usbserial", "cu.usbmodem"].iter().any(|prefix| s.starts_with(prefix)) {
+                    ttys.push(e.path());
+                } else if s.starts_with("rfcomm") {
+                    rfcomms.push(e.path());
+                }
+            }
+        }
+    }
+    ttys.sort();
+    rfcomms.sort();
+    found.extend(ttys);
+    found.extend(rfcomms);
+    let mut devices = Vec::new();
+    found.retain(|p| {
+        let Ok(dev) = fs::canonicalize(p) else { return false };
+        if devices.contains(&dev) {
+            return false;
+        }
+        devices.push(dev);
+        true
+    });
+    found
+}
+
+/// Every COM port Windows lists, there being no /dev to look in: USB
+/// adapters first, then the rest (built-in ports, Bluetooth serial ports),
+/// each in COM number order.
+#[cfg(not(unix))]
+fn ports() -> Vec<PathBuf> {
+    let mut ports = match serialport::available_ports() {
+        Ok(ports) => ports,
+        Err(e) => {
+            warn!("can't list the COM ports: {e}");
+            return Vec::new();
+        }
+    };
+    ports.sort_by_key(|p| {
+        let usb = matches!(p.port_type, serialport::SerialPortType::UsbPort(_));
+        let number = p.port_name.trim_start_matches("COM").parse::<u32>().unwrap_or(u32::MAX);
+        (!usb, number, p.port_name.clone())
+    });
+    ports.into_iter().map(|p| PathBuf::from(p.port_name)).collect()
+}
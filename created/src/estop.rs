@@ -0,0 +1,298 @@
+//! Software emergency stop: engaged by SIGUSR1, the control socket's
+//! `estop`, or a POST to the HTTP endpoint (`estop.http`), from anywhere
+//! that can reach one of them. It stops the wheels at once without ramping
+//! down, cancels the running behavior, drops the OI commands waiting to be
+//! sent, and puts the robot in Passive mode. Then it latches: nothing
+//! drives the wheels, no behavior starts, and only commands that can't
+//! move the robot are sent (songs, LEDs, sensor streams) until it is
+//! cleared by hand, `estop clear` on the control socket (or a POST to
+//! `/estop/clear` with `estop.http_clear`). The latch is kept in
+//! `estop.latch_file`, so restarting the daemon doesn't clear it.
+
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use chrono::{SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+#[cfg(unix)]
+use signal_hook::consts::SIGUSR1;
+#[cfg(unix)]
+use signal_hook::iterator::Signals;
+#[cfg(not(unix))]
+use tracing::debug;
+use tracing::{error, info, warn};
+
+use crate::{audit, behavior};
+use crate::bus::{Bus, Event};
+use crate::config::EstopConfig;
+use crate::motion;
+use crate::oi;
+
+const HTTP_TIMEOUT: Duration = Duration::from_secs(5);
+/// OI opcodes sent while latched: none of them moves the robot or leaves
+/// Passive mode.
+const HARMLESS: [u8; 11] = [oi::START, oi::LEDS, oi::SONG, oi::PLAY, 142, oi::STREAM, oi::PAUSE_RESUME_STREAM, 149, 162, 163, 164];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Latch {
+    /// What engaged it: signal, control, or http and the client's address
+    source: String,
+    since: String,
+}
+
+struct Estop {
+    latch: Option<Latch>,
+    /// The session has stopped the robot for this latch
+    handled: bool,
+    file: PathBuf,
+}
+
+static STATE: Mutex<Option<Estop>> = Mutex::new(None);
+/// Where engaging and clearing are published (None stops).
+static BUS: Mutex<Option<Bus>> = Mutex::new(None);
+
+fn state() -> std::sync::MutexGuard<'static, Option<Estop>> {
+    STATE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+pub fn attach(bus: Option<Bus>) {
+    *BUS.lock().unwrap_or_else(|e| e.into_inner()) = bus;
+}
+
+fn publish(event: Event) {
+    if let Some(bus) = BUS.lock().unwrap_or_else(|e| e.into_inner()).as_ref() {
+        bus.event(event);
+    }
+}
+
+#[cfg(unix)]
+fn listen_for_signal() {
+    match Signals::new([SIGUSR1]) {
+        Ok(mut signals) => {
+            thread::spawn(move || {
+                for _ in signals.forever() {
+                    engage("SIGUSR1");
+                }
+            });
+        }
+        Err(e) => warn!("emergency stop on SIGUSR1 disabled: {e}"),
+    }
+}
+
+#[cfg(not(unix))]
+fn listen_for_signal() {
+    debug!("no SIGUSR1 here to engage the emergency stop: use estop.http");
+}
+
+/// Pick up a latch left by the last run, and listen for SIGUSR1 and on
+/// `estop.http`.
+pub fn init(cfg: EstopConfig) {
+    let file = cfg.latch_file();
+    let latch = fs::read_to_string(&file).ok().map(|text| {
+        serde_json::from_str(&text).unwrap_or_else(|_| Latch { source: "latch file".into(), since: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true) })
+    });
+    if let Some(latch) = &latch {
+        warn!("emergency stop still engaged (by {} at {}); clear it with `createctl estop clear`", latch.source, latch.since);
+    }
+    *state() = Some(Estop { latch, handled: false, file });
+    if cfg.signal() {
+        listen_for_signal();
+    }
+    if let Some(addr) = cfg.http.clone() {
+        match TcpListener::bind(&addr) {
+            Ok(listener) => {
+                info!("emergency stop HTTP endpoint on http://{addr}/estop");
+                let clear = cfg.http_clear();
+                thread::spawn(move || {
+                    for conn in listener.incoming().flatten() {
+                        thread::spawn(move || serve(conn, clear));
+                    }
+                });
+            }
+            Err(e) => warn!("emergency stop HTTP endpoint disabled: bind {addr}: {e}"),
+        }
+    }
+}
+
+/// Whether it is engaged.
+pub fn engaged() -> bool {
+    state().as_ref().is_some_and(|e| e.latch.is_some())
+}
+
+/// Err while engaged, for whatever would set the robot moving.
+pub fn check() -> Result<(), String> {
+    if engaged() {
+        return Err("the emergency stop is engaged; clear it with `estop clear`".into());
+    }
+    Ok(())
+}
+
+/// Stop the robot and latch; `source` is what engaged it.
+pub fn engage(source: &str) -> Value {
+    {
+        let mut guard = state();
+        let e = guard.get_or_insert_with(|| Estop { latch: None, handled: false, file: EstopConfig::default().latch_file() });
+        if e.latch.is_none() {
+            let latch = Latch { source: source.to_string(), since: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true) };
+            error!("EMERGENCY STOP engaged by {source}");
+            audit::record("emergency_stop", source, "engaged: wheels stopped, behavior cancelled, latched until cleared");
+            if let Err(err) = fs::write(&e.file, serde_json::to_string(&latch).unwrap_or_default()) {
+                warn!("emergency stop: cannot keep the latch in {}: {err}", e.file.display());
+            }
+            e.latch = Some(latch);
+            e.handled = false;
+            publish(Event::EmergencyStop { source: source.to_string() });
+        }
+    }
+    motion::halt();
+    motion::discard();
+    behavior::cancel("emergency stop");
+    status()
+}
+
+/// Release the latch; the wheels stay stopped until something asks again.
+pub fn clear(source: &str) -> Result<Value, String> {
+    {
+        let mut guard = state();
+        let e = guard.as_mut().ok_or("the emergency stop is not engaged")?;
+        let latch = e.latch.take().ok_or("the emergency stop is not engaged")?;
+        if e.file.exists() {
+            if let Err(err) = fs::remove_file(&e.file) {
+                e.latch = Some(latch);
+                return Err(format!("cannot remove {}: {err}", e.file.display()));
+            }
+        }
+        info!("emergency stop cleared by {source} (engaged by {} at {})", latch.source, latch.since);
+        audit::record("emergency_stop_cleared", source, &format!("engaged by {} at {}", latch.source, latch.since));
+        publish(Event::EmergencyStopCleared { source: source.to_string() });
+    }
+    // Whatever was asked for before it was engaged doesn't come back
+    motion::halt();
+    Ok(status())
+}
+
+pub fn status() -> Value {
+    let guard = state();
+    match guard.as_ref().and_then(|e| e.latch.as_ref()) {
+        Some(latch) => json!({ "engaged": true, "source": latch.source, "since": latch.since }),
+        None => json!({ "engaged": false }),
+    }
+}
+
+/// A new session: the robot is to be stopped again if engaged.
+pub fn begin() {
+    if let Some(e) = state().as_mut() {
+        e.handled = false;
+    }
+}
+
+/// True once for each latch and session: the session should zero the
+/// wheels and put the robot in Passive mode now.
+pub fn stop_now() -> bool {
+    let mut guard = state();
+    let Some(e) = guard.as_mut() else { return false };
+    let due = e.latch.is_some() && !e.handled;
+    e.handled |= due;
+    due
+}
+
+/// Whether the OI command `bytes` may be sent now.
+pub fn allows(bytes: &[u8]) -> bool {
+    !engaged() || bytes.first().is_some_and(|op| HARMLESS.contains(op))
+}
+
+/// One HTTP request: GET /estop shows it, POST /estop engages it, and
+/// POST /estop/clear clears it if allowed.
+fn serve(stream: TcpStream, clear_allowed: bool) {
+    let peer = stream.peer_addr().map(|a| a.to_string()).unwrap_or_else(|_| "?".into());
+    let _ = stream.set_read_timeout(Some(HTTP_TIMEOUT));
+    let Ok(mut out) = stream.try_clone() else { return };
+    let mut reader = BufReader::new(stream);
+    let mut request = String::new();
+    if reader.read_line(&mut request).is_err() {
+        return;
+    }
+    // The headers and any body are of no interest
+    let mut header = String::new();
+    while reader.read_line(&mut header).is_ok_and(|n| n > 0 && header.trim() != "") {
+        header.clear();
+    }
+    let mut words = request.split_whitespace();
+    let (method, path) = (words.next().unwrap_or(""), words.next().unwrap_or(""));
+    let source = format!("http {peer}");
+    let (status, body) = match (method, path.trim_end_matches('/')) {
+        ("GET", "/estop") => ("200 OK", status()),
+        ("POST", "/estop") => ("200 OK", engage(&source)),
+        ("POST", "/estop/clear") if clear_allowed => match clear(&source) {
+            Ok(v) => ("200 OK", v),
+            Err(e) => ("409 Conflict", json!({ "error": e })),
+        },
+        ("POST", "/estop/clear") => ("403 Forbidden", json!({ "error": "clearing over HTTP is disabled (estop.http_clear)" })),
+        (_, "/estop" | "/estop/clear") => ("405 Method Not Allowed", json!({ "error": "use GET or POST" })),
+        _ => ("404 Not Found", json!({ "error": "not found" })),
+    };
+    let body = body.to_string();
+    let _ = write!(out, "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}", body.len());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FORWARD: [u8; 5] = [oi::DRIVE_DIRECT, 0, 200, 0, 200];
+
+    fn running(name: &str) -> (std::sync::MutexGuard<'static, ()>, PathBuf) {
+        let serial = crate::robot::serial();
+        let file = std::env::temp_dir().join(format!("created-estop-{}-{name}.json", std::process::id()));
+        let _ = fs::remove_file(&file);
+        init(EstopConfig { signal: Some(false), latch_file: Some(file.display().to_string()), ..Default::default() });
+        (serial, file)
+    }
+
+    #[test]
+    fn latches_until_cleared() {
+        let (_serial, file) = running("latch");
+        assert!(!engaged() && check().is_ok() && allows(&FORWARD));
+        assert!(!stop_now());
+        assert!(clear("test").is_err());
+        assert_eq!(engage("test")["source"], "test");
+        assert!(engaged() && check().is_err());
+        assert!(file.exists());
+        // Once per latch and session
+        assert!(stop_now());
+        assert!(!stop_now());
+        begin();
+        assert!(stop_now());
+        // Engaging again keeps the first latch
+        engage("again");
+        assert_eq!(status()["source"], "test");
+        for refused in [&FORWARD[..], &[oi::SAFE], &[oi::FULL], &[oi::SEEK_DOCK], &[oi::DRIVE, 0, 0, 0, 0], &[]] {
+            assert!(!allows(refused), "{refused:?}");
+        }
+        for allowed in [&[oi::START][..], &[oi::LEDS, 0, 0, 0], &[oi::PLAY, 0], &[oi::STREAM, 1, 7], &[oi::PAUSE_RESUME_STREAM, 0]] {
+            assert!(allows(allowed), "{allowed:?}");
+        }
+        assert_eq!(clear("test").unwrap()["engaged"], false);
+        assert!(!engaged() && allows(&FORWARD) && !file.exists());
+        assert!(clear("test").is_err());
+    }
+
+    #[test]
+    fn a_latch_outlives_a_restart() {
+        let (_serial, file) = running("restart");
+        engage("before");
+        // Started again, as the daemon would be
+        init(EstopConfig { signal: Some(false), latch_file: Some(file.display().to_string()), ..Default::default() });
+        assert!(engaged());
+        assert_eq!(status()["source"], "before");
+        clear("test").unwrap();
+        init(EstopConfig { signal: Some(false), latch_file: Some(file.display().to_string()), ..Default::default() });
+        assert!(!engaged());
+    }
+}
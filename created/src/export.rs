@@ -0,0 +1,126 @@
+//! `created export`: convert CSV/MCAP recordings into Parquet files
+//! partitioned by UTC day (`<out>/date=YYYY-MM-DD/<recording>.parquet`), the
+//! Hive layout DataFusion, Polars, and DuckDB read as one table with a `date`
+//! column.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use parquet::basic::{Compression, LogicalType, Repetition, TimeUnit, Type as PhysicalType};
+use parquet::data_type::{Int32Type, Int64Type};
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::SerializedFileWriter;
+use parquet::schema::types::Type;
+use tracing::{info, warn};
+
+use crate::oi::{SensorFrame, PACKETS};
+use crate::replay;
+
+pub const DEFAULT_OUT: &str = "/var/lib/created/parquet";
+const ROW_GROUP_ROWS: usize = 128 * 1024;
+
+/// Export every recording in `inputs` (files, or directories of .csv/.mcap
+/// files). Returns the number of Parquet files written.
+pub fn run(inputs: &[PathBuf], out: &Path) -> Result<usize, String> {
+    let mut written = 0;
+    for path in recordings(inputs)? {
+        let frames = match replay::load(&path) {
+            Ok(frames) => frames,
+            Err(e) => {
+                warn!("skipping {}: {e}", path.display());
+                continue;
+            }
+        };
+        let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("recording");
+        for (day, frames) in by_day(&frames) {
+            let dir = out.join(format!("date={day}"));
+            let file = dir.join(format!("{stem}.parquet"));
+            fs::create_dir_all(&dir).map_err(|e| format!("create {}: {e}", dir.display()))?;
+            write_parquet(&file, &frames)?;
+            info!("{} -> {} ({} rows)", path.display(), file.display(), frames.len());
+            written += 1;
+        }
+    }
+    Ok(written)
+}
+
+fn recordings(inputs: &[PathBuf]) -> Result<Vec<PathBuf>, String> {
+    let mut files = Vec::new();
+    for input in inputs {
+        if input.is_dir() {
+            let entries = fs::read_dir(input).map_err(|e| format!("read {}: {e}", input.display()))?;
+            let mut found: Vec<PathBuf> = entries
+                .filter_map(|e| e.ok().map(|e| e.path()))
+                .filter(|p| matches!(p.extension().and_then(|e| e.to_str()), Some("csv" | "mcap")))
+                .collect();
+            found.sort();
+            files.extend(found);
+        } else {
+            files.push(input.clone());
+        }
+    }
+    Ok(files)
+}
+
+/// A recording that runs past midnight contributes to two partitions.
+fn by_day(frames: &[SensorFrame]) -> BTreeMap<NaiveDate, Vec<&SensorFrame>> {
+    let mut days: BTreeMap<NaiveDate, Vec<&SensorFrame>> = BTreeMap::new();
+    for frame in frames {
+        days.entry(DateTime::<Utc>::from(frame.time).date_naive()).or_default().push(frame);
+    }
+    days
+}
+
+/// One `timestamp` column (UTC, milliseconds) followed by a nullable INT32
+/// column for every packet present in any of the frames, written to a
+/// temporary file and renamed into place.
+fn write_parquet(path: &Path, frames: &[&SensorFrame]) -> Result<(), String> {
+    let packets: Vec<_> = PACKETS.iter().filter(|p| frames.iter().any(|f| f.get(p.id).is_some())).collect();
+    let err = |e: parquet::errors::ParquetError| format!("{}: {e}", path.display());
+
+    let mut fields = vec![Arc::new(
+        Type::primitive_type_builder("timestamp", PhysicalType::INT64)
+            .with_repetition(Repetition::REQUIRED)
+            .with_logical_type(Some(LogicalType::timestamp(true, TimeUnit::MILLIS)))
+            .build()
+            .map_err(err)?,
+    )];
+    for p in &packets {
+        fields.push(Arc::new(
+            Type::primitive_type_builder(p.name, PhysicalType::INT32)
+                .with_repetition(Repetition::OPTIONAL)
+                .build()
+                .map_err(err)?,
+        ));
+    }
+    let schema = Arc::new(Type::group_type_builder("sensors").with_fields(fields).build().map_err(err)?);
+    let props = Arc::new(WriterProperties::builder().set_compression(Compression::SNAPPY).build());
+
+    let tmp = path.with_extension("parquet.tmp");
+    let file = File::create(&tmp).map_err(|e| format!("create {}: {e}", tmp.display()))?;
+    let mut writer = SerializedFileWriter::new(file, schema, props).map_err(err)?;
+    for chunk in frames.chunks(ROW_GROUP_ROWS) {
+        let mut group = writer.next_row_group().map_err(err)?;
+        let times: Vec<i64> = chunk
+            .iter()
+            .map(|f| f.time.duration_since(UNIX_EPOCH).map(|d| d.as_millis() as i64).unwrap_or(0))
+            .collect();
+        let mut col = group.next_column().map_err(err)?.ok_or("missing timestamp column")?;
+        col.typed::<Int64Type>().write_batch(&times, None, None).map_err(err)?;
+        col.close().map_err(err)?;
+        for p in &packets {
+            let values: Vec<i32> = chunk.iter().filter_map(|f| f.get(p.id)).collect();
+            let levels: Vec<i16> = chunk.iter().map(|f| f.get(p.id).is_some() as i16).collect();
+            let mut col = group.next_column().map_err(err)?.ok_or("missing packet column")?;
+            col.typed::<Int32Type>().write_batch(&values, Some(&levels), None).map_err(err)?;
+            col.close().map_err(err)?;
+        }
+        group.close().map_err(err)?;
+    }
+    writer.close().map_err(err)?;
+    fs::rename(&tmp, path).map_err(|e| format!("rename {}: {e}", tmp.display()))
+}
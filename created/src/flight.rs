@@ -0,0 +1,140 @@
+//! Crash flight recorder: the last few seconds of raw serial input, decoded
+//! frames, commands, and events, kept in memory and dumped to disk when the
+//! daemon panics or a robot session fails.
+
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::fs;
+use std::panic;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use tracing::{info, warn};
+
+use crate::bus::Message;
+use crate::config::FlightRecorderConfig;
+use crate::recorder;
+
+/// Hard cap on buffered entries regardless of the time window.
+const MAX_ENTRIES: usize = 100_000;
+const FILE_PREFIX: &str = "flight-";
+
+static RECORDER: OnceLock<FlightRecorder> = OnceLock::new();
+
+enum Entry {
+    Rx(Vec<u8>),
+    Message(Message),
+}
+
+struct Ring {
+    entries: VecDeque<(SystemTime, Entry)>,
+    /// Set when something was recorded since the last dump
+    dirty: bool,
+}
+
+struct FlightRecorder {
+    cfg: FlightRecorderConfig,
+    ring: Mutex<Ring>,
+}
+
+/// Enable the recorder and install a panic hook that dumps it. Does nothing
+/// unless `flight_recorder.enabled` is set.
+pub fn install(cfg: FlightRecorderConfig) {
+    if !cfg.enabled() {
+        return;
+    }
+    info!("flight recorder keeping the last {:?}; dumps go to {}", cfg.window(), cfg.dir().display());
+    let recorder = FlightRecorder { cfg, ring: Mutex::new(Ring { entries: VecDeque::new(), dirty: false }) };
+    if RECORDER.set(recorder).is_err() {
+        return;
+    }
+    let previous = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        previous(info);
+        dump(&format!("panic: {info}"));
+    }));
+}
+
+/// Record bytes read from the robot.
+pub fn rx(data: &[u8]) {
+    push(Entry::Rx(data.to_vec()));
+}
+
+/// Record a bus message (sensor frame, command, event, or pose).
+pub fn message(msg: &Message) {
+    push(Entry::Message(msg.clone()));
+}
+
+fn push(entry: Entry) {
+    let Some(rec) = RECORDER.get() else { return };
+    let now = SystemTime::now();
+    let mut ring = rec.ring.lock().unwrap_or_else(|e| e.into_inner());
+    ring.entries.push_back((now, entry));
+    ring.dirty = true;
+    let window = rec.cfg.window();
+    while let Some((t, _)) = ring.entries.front() {
+        let expired = now.duration_since(*t).unwrap_or_default() > window;
+        if expired || ring.entries.len() > MAX_ENTRIES {
+            ring.entries.pop_front();
+        } else {
+            break;
+        }
+    }
+}
+
+/// Write the buffered history to `flight-<UTC time>.log`, unless nothing was
+/// recorded since the previous dump.
+pub fn dump(reason: &str) {
+    let Some(rec) = RECORDER.get() else { return };
+    // try_lock: a panic while the ring is locked must not deadlock the hook
+    let Ok(mut ring) = rec.ring.try_lock() else {
+        eprintln!("flight recorder busy; not dumping ({reason})");
+        return;
+    };
+    if !ring.dirty {
+        return;
+    }
+    ring.dirty = false;
+    let text = render(reason, &ring.entries);
+    drop(ring);
+
+    let dir = rec.cfg.dir();
+    let stamp = Utc::now().format("%Y%m%dT%H%M%S%.3fZ");
+    let path = dir.join(format!("{FILE_PREFIX}{stamp}.log"));
+    match fs::create_dir_all(&dir).and_then(|()| fs::write(&path, text)) {
+        Ok(()) => {
+            info!("flight recorder dumped to {}", path.display());
+            recorder::prune(&dir, FILE_PREFIX, ".log", rec.cfg.max_files());
+        }
+        Err(e) => warn!("flight recorder: failed to write {}: {e}", path.display()),
+    }
+}
+
+fn render(reason: &str, entries: &VecDeque<(SystemTime, Entry)>) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "# created flight recorder dump");
+    let _ = writeln!(out, "# reason: {reason}");
+    let Some((first, _)) = entries.front() else { return out };
+    let _ = writeln!(out, "# start: {}", DateTime::<Utc>::from(*first).to_rfc3339_opts(SecondsFormat::Micros, true));
+    for (t, entry) in entries {
+        let rel = t.duration_since(*first).unwrap_or(Duration::ZERO);
+        let _ = write!(out, "+{:>10.3}ms ", rel.as_secs_f64() * 1000.0);
+        let _ = match entry {
+            Entry::Rx(bytes) => writeln!(out, "RX    {}", hex(bytes)),
+            Entry::Message(Message::Command { bytes, .. }) => writeln!(out, "TX    {}", hex(bytes)),
+            Entry::Message(Message::Sensors(frame)) => {
+                writeln!(out, "FRAME {}", serde_json::to_string(&**frame).unwrap_or_default())
+            }
+            Entry::Message(Message::Event { event, .. }) => {
+                writeln!(out, "EVENT {}", serde_json::to_string(event).unwrap_or_default())
+            }
+            Entry::Message(Message::Pose(pose)) => writeln!(out, "POSE  {}", serde_json::to_string(pose).unwrap_or_default()),
+        };
+    }
+    out
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect::<Vec<_>>().join(" ")
+}
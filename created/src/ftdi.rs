@@ -0,0 +1,55 @@
+//! The latency timer of FTDI USB serial adapters, the Create's own cable
+//! among them: the chip holds received bytes back for up to this long, 16 ms
+//! unless set, before sending a short USB packet, delaying every sensor
+//! frame and reply by as much. With `serial.latency_timer_ms` it is set
+//! lower for the claimed port through the driver's sysfs attribute, and put
+//! back when the port is closed.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use tracing::{debug, info, warn};
+
+/// A latency timer set, until dropped.
+pub struct Latency {
+    path: PathBuf,
+    was: u8,
+}
+
+/// The `latency_timer` attribute of the tty `device`, if its driver has one.
+fn attribute(device: &Path) -> Option<PathBuf> {
+    let dev = fs::canonicalize(device).ok()?;
+    let name = dev.file_name()?.to_str()?;
+    let path = Path::new("/sys/class/tty").join(name).join("device/latency_timer");
+    path.exists().then_some(path)
+}
+
+/// Set the latency timer of `device` to `ms`: None if it isn't an FTDI
+/// adapter, the timer is that already, or it can't be written.
+pub fn set(device: &Path, ms: u8) -> Option<Latency> {
+    let Some(path) = attribute(device) else {
+        debug!("{}: no latency timer to set (not an FTDI adapter)", device.display());
+        return None;
+    };
+    let was = fs::read_to_string(&path).ok()?.trim().parse::<u8>().ok()?;
+    if was == ms {
+        return None;
+    }
+    if let Err(e) = fs::write(&path, format!("{ms}\n")) {
+        warn!("can't set the latency timer of {} to {ms} ms ({e}); it stays at {was} ms (see the udev rule making {} writable)", device.display(), path.display());
+        return None;
+    }
+    info!("latency timer of {} set to {ms} ms, from {was} ms", device.display());
+    Some(Latency { path, was })
+}
+
+impl Drop for Latency {
+    fn drop(&mut self) {
+        // Gone with the adapter unplugged: nothing to put back
+        if self.path.exists() {
+            if let Err(e) = fs::write(&self.path, format!("{}\n", self.was)) {
+                warn!("can't put back the latency timer {}: {e}", self.path.display());
+            }
+        }
+    }
+}
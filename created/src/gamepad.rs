@@ -0,0 +1,231 @@
+//! Driving with a locally attached gamepad or joystick (evdev). One stick
+//! axis sets the speed, another the turn rate; buttons stop, seek the dock,
+//! or play a song. By default a deadman button must be held to drive, and
+//! letting it go stops the robot. The pad can be plugged in and out while
+//! the daemon runs: unplugging it stops the robot, and the input devices are
+//! rescanned until one shows up again.
+//!
+//! Like teleop, the pad leases the user layer and renews the lease every
+//! `teleop::REPEAT` while it drives, so if this thread stalls the heartbeat
+//! watchdog stops the wheels.
+
+use std::io;
+use std::os::fd::AsRawFd;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use evdev::{AbsoluteAxisType, Device, InputEventKind, Key};
+use tracing::{info, warn};
+
+use crate::config::GamepadConfig;
+use crate::motion::{self, Wheels};
+use crate::{behavior, oi, teleop};
+
+const RESCAN: Duration = Duration::from_secs(2);
+
+struct Mapping {
+    speed_axis: AbsoluteAxisType,
+    turn_axis: AbsoluteAxisType,
+    deadman: Option<Key>,
+    stop: Key,
+    dock: Key,
+    song: Key,
+}
+
+impl Mapping {
+    fn new(cfg: &GamepadConfig) -> Result<Self, String> {
+        let axis = |name: &str| AbsoluteAxisType::from_str(name).map_err(|_| format!("unknown gamepad axis {name:?}"));
+        let button = |name: &str| Key::from_str(name).map_err(|_| format!("unknown gamepad button {name:?}"));
+        Ok(Mapping {
+            speed_axis: axis(cfg.speed_axis())?,
+            turn_axis: axis(cfg.turn_axis())?,
+            deadman: if cfg.deadman() { Some(button(cfg.deadman_button())?) } else { None },
+            stop: button(cfg.stop_button())?,
+            dock: button(cfg.dock_button())?,
+            song: button(cfg.song_button())?,
+        })
+    }
+}
+
+/// Raw axis range, from the device's absinfo.
+#[derive(Clone, Copy)]
+struct Range {
+    min: i32,
+    max: i32,
+}
+
+impl Range {
+    fn of(device: &Device, axis: AbsoluteAxisType) -> Range {
+        match device.get_abs_state() {
+            Ok(state) => {
+                let info = state[axis.0 as usize];
+                Range { min: info.minimum, max: info.maximum }
+            }
+            Err(_) => Range { min: -32768, max: 32767 },
+        }
+    }
+
+    /// -1.0..=1.0 around the center, with the dead zone cut out.
+    fn normalize(&self, value: i32, deadzone: f64) -> f64 {
+        if self.max <= self.min {
+            return 0.0;
+        }
+        let mid = (self.max as f64 + self.min as f64) / 2.0;
+        let half = (self.max as f64 - self.min as f64) / 2.0;
+        let x = ((value as f64 - mid) / half).clamp(-1.0, 1.0);
+        if x.abs() < deadzone {
+            0.0
+        } else {
+            x.signum() * (x.abs() - deadzone) / (1.0 - deadzone)
+        }
+    }
+}
+
+pub fn spawn(cfg: GamepadConfig) -> Result<(), String> {
+    let mapping = Mapping::new(&cfg)?;
+    thread::spawn(move || run(cfg, mapping));
+    Ok(())
+}
+
+fn run(cfg: GamepadConfig, mapping: Mapping) {
+    let mut missing_logged = false;
+    loop {
+        match find(&cfg, &mapping) {
+            Some((path, device)) => {
+                missing_logged = false;
+                info!("gamepad connected: {} ({})", device.name().unwrap_or("unnamed"), path.display());
+                let e = drive(&cfg, &mapping, device);
+                warn!("gamepad disconnected: {e}");
+            }
+            None if !missing_logged => {
+                info!("no gamepad found; waiting for one to be plugged in");
+                missing_logged = true;
+            }
+            None => {}
+        }
+        thread::sleep(RESCAN);
+    }
+}
+
+fn find(cfg: &GamepadConfig, mapping: &Mapping) -> Option<(PathBuf, Device)> {
+    if let Some(path) = &cfg.device {
+        let path = PathBuf::from(path);
+        return Device::open(&path).ok().map(|d| (path, d));
+    }
+    evdev::enumerate().find(|(_, device)| {
+        let has_axis = device.supported_absolute_axes().is_some_and(|a| a.contains(mapping.speed_axis));
+        let has_buttons = device
+            .supported_keys()
+            .is_some_and(|k| k.contains(Key::BTN_SOUTH) || k.contains(Key::BTN_TRIGGER) || k.contains(mapping.stop));
+        has_axis && has_buttons
+    })
+}
+
+/// Follow the pad until it goes away. Returns why.
+fn drive(cfg: &GamepadConfig, mapping: &Mapping, mut device: Device) -> String {
+    let speed_range = Range::of(&device, mapping.speed_axis);
+    let turn_range = Range::of(&device, mapping.turn_axis);
+    let (mut speed, mut turn) = (0.0, 0.0);
+    let mut held = mapping.deadman.is_some_and(|key| device.get_key_state().is_ok_and(|keys| keys.contains(key)));
+    // Nothing is requested until the pad is used, so plugging it in doesn't
+    // override another driver
+    let mut last: Option<Wheels> = None;
+    let mut leased = Instant::now();
+    loop {
+        // Woken in time for the next renewal while driving
+        let driving = last.is_some_and(|w| w != Wheels::STOP);
+        let timeout = if driving { teleop::REPEAT.saturating_sub(leased.elapsed()) } else { teleop::REPEAT };
+        let ready = match readable(&device, timeout) {
+            Ok(ready) => ready,
+            Err(e) => {
+                if driving {
+                    motion::stop();
+                }
+                return e.to_string();
+            }
+        };
+        if ready {
+            let events = match device.fetch_events() {
+                Ok(events) => events.collect::<Vec<_>>(),
+                Err(e) => {
+                    if last.is_some_and(|w| w != Wheels::STOP) {
+                        motion::stop();
+                    }
+                    return e.to_string();
+                }
+            };
+            for event in events {
+                match event.kind() {
+                    InputEventKind::AbsAxis(axis) if axis == mapping.speed_axis => {
+                        speed = speed_range.normalize(event.value(), cfg.deadzone());
+                        if cfg.invert_speed() {
+                            speed = -speed;
+                        }
+                    }
+                    InputEventKind::AbsAxis(axis) if axis == mapping.turn_axis => {
+                        turn = turn_range.normalize(event.value(), cfg.deadzone());
+                        if cfg.invert_turn() {
+                            turn = -turn;
+                        }
+                    }
+                    // Down (1) or autorepeat (2), or up (0)
+                    InputEventKind::Key(key) if Some(key) == mapping.deadman => held = event.value() != 0,
+                    // Presses only, not releases or autorepeat
+                    InputEventKind::Key(key) if event.value() == 1 => {
+                        if key == mapping.stop {
+                            behavior::cancel("gamepad");
+                            motion::stop();
+                            last = Some(Wheels::STOP);
+                        } else if key == mapping.dock {
+                            info!("gamepad: seeking dock");
+                            behavior::cancel("gamepad");
+                            motion::halt();
+                            last = Some(Wheels::STOP);
+                            motion::queue(vec![oi::SEEK_DOCK]);
+                        } else if key == mapping.song {
+                            motion::queue(vec![oi::PLAY, cfg.song()]);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            // Positive turn is counter-clockwise: the right wheel runs faster
+            let s = speed * cfg.max_speed() as f64;
+            let t = turn * cfg.max_turn() as f64;
+            let mut wheels = Wheels::new((s - t).round() as i32, (s + t).round() as i32);
+            if mapping.deadman.is_some() && !held {
+                // Let go, the robot stops whatever the sticks say
+                wheels = Wheels::STOP;
+            }
+            if (last.is_some() || wheels != Wheels::STOP) && last != Some(wheels) {
+                behavior::cancel("gamepad");
+                motion::lease(wheels);
+                leased = Instant::now();
+                last = Some(wheels);
+            }
+        }
+        // Renewed while driving, whether the pad reports anything or not:
+        // a stick held steady sends nothing, and one at full deflection or
+        // an unmapped axis may stream without changing the wheels
+        if let Some(wheels) = last.filter(|w| *w != Wheels::STOP && leased.elapsed() >= teleop::REPEAT) {
+            motion::lease(wheels);
+            leased = Instant::now();
+        }
+    }
+}
+
+/// Whether the pad has events to read within `timeout`.
+fn readable(device: &Device, timeout: Duration) -> io::Result<bool> {
+    let mut pfd = libc::pollfd { fd: device.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+    // SAFETY: a plain syscall on a descriptor `device` holds open
+    match unsafe { libc::poll(&mut pfd, 1, timeout.as_millis() as libc::c_int) } {
+        n if n < 0 => {
+            let e = io::Error::last_os_error();
+            if e.kind() == io::ErrorKind::Interrupted { Ok(false) } else { Err(e) }
+        }
+        0 => Ok(false),
+        _ => Ok(true),
+    }
+}
@@ -0,0 +1,192 @@
+//! Geofence: a keep-in area in the pose frame, a polygon or a circle around
+//! the origin (where the session started). As the pose estimate nears the
+//! boundary, wheel requests that would carry the robot further out are
+//! slowed, and stopped at `stop_m` from it; turning, and driving back in,
+//! are always allowed. Applies to everything that drives the wheels, and
+//! publishes a `geofence` event whenever the robot enters another zone.
+
+use std::sync::Mutex;
+
+use tracing::{info, warn};
+
+use crate::bus::Event;
+use crate::config::GeofenceConfig;
+use crate::motion::Wheels;
+use crate::pose::Pose;
+
+/// Slowest outward speed short of the stop, mm/s.
+const CRAWL: f64 = 40.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Zone {
+    Inside,
+    /// Within `slow_m` of the boundary
+    Near,
+    /// Within `stop_m`: no further out
+    Boundary,
+    Outside,
+}
+
+impl Zone {
+    fn as_str(self) -> &'static str {
+        match self {
+            Zone::Inside => "inside",
+            Zone::Near => "near",
+            Zone::Boundary => "boundary",
+            Zone::Outside => "outside",
+        }
+    }
+}
+
+enum Shape {
+    Circle(f64),
+    Polygon(Vec<[f64; 2]>),
+}
+
+struct Fence {
+    shape: Shape,
+    slow: f64,
+    stop: f64,
+    zone: Option<Zone>,
+    /// The heading's share of the outward direction (-1..1), and the
+    /// fraction of the speed allowed moving outward, from the last pose
+    outward: f64,
+    allowed: f64,
+}
+
+static FENCE: Mutex<Option<Fence>> = Mutex::new(None);
+static CONFIG: Mutex<Option<GeofenceConfig>> = Mutex::new(None);
+
+fn fence() -> std::sync::MutexGuard<'static, Option<Fence>> {
+    FENCE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Fence every session. Does nothing unless `geofence.enabled`.
+pub fn init(cfg: GeofenceConfig) {
+    if !cfg.enabled() {
+        return;
+    }
+    match cfg.shape_error() {
+        Some(e) => warn!("geofence disabled: {e}"),
+        None => *CONFIG.lock().unwrap_or_else(|e| e.into_inner()) = Some(cfg),
+    }
+}
+
+pub fn begin() {
+    let cfg = CONFIG.lock().unwrap_or_else(|e| e.into_inner()).clone();
+    *fence() = cfg.map(|cfg| {
+        let shape = match cfg.polygon.clone() {
+            Some(points) => Shape::Polygon(points),
+            None => Shape::Circle(cfg.radius_m()),
+        };
+        Fence { shape, slow: cfg.slow_m(), stop: cfg.stop_m(), zone: None, outward: 0.0, allowed: 1.0 }
+    });
+}
+
+pub fn end() {
+    *fence() = None;
+}
+
+/// Nearest point to `p` on the segment `a`-`b`.
+fn nearest_on(p: [f64; 2], a: [f64; 2], b: [f64; 2]) -> [f64; 2] {
+    let (dx, dy) = (b[0] - a[0], b[1] - a[1]);
+    let length = dx * dx + dy * dy;
+    let t = if length > 0.0 { (((p[0] - a[0]) * dx + (p[1] - a[1]) * dy) / length).clamp(0.0, 1.0) } else { 0.0 };
+    [a[0] + t * dx, a[1] + t * dy]
+}
+
+/// Even-odd rule.
+fn contains(points: &[[f64; 2]], p: [f64; 2]) -> bool {
+    let mut inside = false;
+    for (i, a) in points.iter().enumerate() {
+        let b = points[(i + 1) % points.len()];
+        if (a[1] > p[1]) != (b[1] > p[1]) && p[0] < a[0] + (p[1] - a[1]) * (b[0] - a[0]) / (b[1] - a[1]) {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+impl Shape {
+    /// Distance to the boundary, negative outside, and the outward
+    /// direction there.
+    fn distance(&self, p: [f64; 2]) -> (f64, [f64; 2]) {
+        match self {
+            Shape::Circle(radius) => {
+                let r = p[0].hypot(p[1]);
+                let out = if r > 0.0 { [p[0] / r, p[1] / r] } else { [1.0, 0.0] };
+                (radius - r, out)
+            }
+            Shape::Polygon(points) => {
+                let nearest = (0..points.len())
+                    .map(|i| nearest_on(p, points[i], points[(i + 1) % points.len()]))
+                    .min_by(|a, b| {
+                        let da = (a[0] - p[0]).hypot(a[1] - p[1]);
+                        let db = (b[0] - p[0]).hypot(b[1] - p[1]);
+                        da.total_cmp(&db)
+                    })
+                    .unwrap_or(p);
+                let (dx, dy) = (nearest[0] - p[0], nearest[1] - p[1]);
+                let d = dx.hypot(dy);
+                let inside = contains(points, p);
+                // Toward the boundary from inside, away from it outside
+                let toward = if d > 0.0 { [dx / d, dy / d] } else { [1.0, 0.0] };
+                if inside {
+                    (d, toward)
+                } else {
+                    (-d, [-toward[0], -toward[1]])
+                }
+            }
+        }
+    }
+}
+
+/// Check the pose against the fence; events to publish.
+pub fn update(pose: Option<Pose>) -> Vec<Event> {
+    let mut guard = fence();
+    let Some(f) = guard.as_mut() else { return Vec::new() };
+    let Some(pose) = pose else { return Vec::new() };
+    let (distance, out) = f.shape.distance([pose.x, pose.y]);
+    let (sin, cos) = pose.theta.sin_cos();
+    f.outward = cos * out[0] + sin * out[1];
+    f.allowed = ((distance - f.stop) / (f.slow - f.stop)).clamp(0.0, 1.0);
+    let zone = if distance < 0.0 {
+        Zone::Outside
+    } else if distance <= f.stop {
+        Zone::Boundary
+    } else if distance <= f.slow {
+        Zone::Near
+    } else {
+        Zone::Inside
+    };
+    if f.zone == Some(zone) {
+        return Vec::new();
+    }
+    // The first check only reports a start outside the fence or near it
+    let first = f.zone.is_none();
+    f.zone = Some(zone);
+    if first && zone == Zone::Inside {
+        return Vec::new();
+    }
+    if zone != Zone::Inside {
+        info!("geofence: {} ({distance:.2} m from the boundary)", zone.as_str());
+    }
+    vec![Event::Geofence { zone: zone.as_str().to_string(), distance_m: distance }]
+}
+
+/// `wheels` with their outward speed cut down to what the fence allows.
+pub fn limit(wheels: Wheels) -> Wheels {
+    let guard = fence();
+    let Some(f) = guard.as_ref() else { return wheels };
+    let v = (wheels.left as f64 + wheels.right as f64) / 2.0;
+    let d = (wheels.right as f64 - wheels.left as f64) / 2.0;
+    if v * f.outward <= 0.0 || f.allowed >= 1.0 {
+        return wheels;
+    }
+    // Crawling the last of the way, rather than creeping up on the stop
+    let v = match f.allowed {
+        a if a > 0.0 => v.signum() * (v.abs() * a).max(CRAWL.min(v.abs())),
+        _ => 0.0,
+    };
+    Wheels::new((v - d).round() as i32, (v + d).round() as i32)
+}
@@ -0,0 +1,155 @@
+//! Speed governor: the top speed follows the conditions the robot drives
+//! in, instead of one fixed limit. Three things bring it down, each from
+//! 1 to `min_scale` of `max_speed`, and the lowest wins:
+//!
+//! - voltage: the battery sagging under load, measured against the voltage
+//!   it rests at while the wheels stand still, as a weak or cold battery
+//!   does long before it reads empty;
+//! - surface: the wheels slipping, or the robot being carried (the slip
+//!   detector's stasis check), and for `slip_hold_s` after;
+//! - proximity: the strongest light bump signal, in every direction, so
+//!   the robot turns and backs up near obstacles carefully too.
+//!
+//! Both wheels are scaled together, keeping the curvature, and a
+//! `speed_limit` event is published whenever the conditions holding the
+//! speed down change. Like the geofence, it applies to everything that
+//! drives the wheels.
+
+use std::sync::Mutex;
+use std::time::Instant;
+
+use serde_json::{json, Value};
+use tracing::info;
+
+use crate::bus::Event;
+use crate::config::GovernorConfig;
+use crate::motion::{self, Wheels};
+use crate::oi::SensorFrame;
+use crate::slip;
+
+const VOLTAGE: u8 = 22;
+const LIGHT_BUMP_SIGNALS: [u8; 6] = [46, 47, 48, 49, 50, 51];
+/// Weight of each frame in the smoothed voltage: single readings jump by
+/// tens of mV.
+const VOLTAGE_WEIGHT: f64 = 0.05;
+/// A scale this close to 1 doesn't count as holding the speed down.
+const NEGLIGIBLE: f64 = 0.02;
+
+struct Governor {
+    cfg: GovernorConfig,
+    /// Smoothed voltage, mV
+    voltage: Option<f64>,
+    /// Smoothed voltage the last time the wheels stood still, mV
+    resting: Option<f64>,
+    /// The last frame the slip detector reported slipping
+    slipped: Option<Instant>,
+    /// Scale for voltage, surface, and proximity, from the last frame
+    scales: [f64; 3],
+    /// Conditions holding the speed down
+    by: Vec<&'static str>,
+}
+
+const CONDITIONS: [&str; 3] = ["voltage", "surface", "proximity"];
+
+static STATE: Mutex<Option<Governor>> = Mutex::new(None);
+static CONFIG: Mutex<Option<GovernorConfig>> = Mutex::new(None);
+
+fn state() -> std::sync::MutexGuard<'static, Option<Governor>> {
+    STATE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Govern every session. Does nothing unless `governor.enabled`.
+pub fn init(cfg: GovernorConfig) {
+    if cfg.enabled() {
+        *CONFIG.lock().unwrap_or_else(|e| e.into_inner()) = Some(cfg);
+    }
+}
+
+pub fn begin() {
+    let cfg = CONFIG.lock().unwrap_or_else(|e| e.into_inner()).clone();
+    *state() = cfg.map(|cfg| Governor { cfg, voltage: None, resting: None, slipped: None, scales: [1.0; 3], by: Vec::new() });
+}
+
+pub fn end() {
+    *state() = None;
+}
+
+impl Governor {
+    /// 1 up to `from`, `min_scale` from `to` on, in proportion between.
+    fn ramp(&self, x: f64, (from, to): (f64, f64)) -> f64 {
+        let t = if to > from { ((x - from) / (to - from)).clamp(0.0, 1.0) } else if x >= from { 1.0 } else { 0.0 };
+        1.0 - (1.0 - self.cfg.min_scale()) * t
+    }
+
+    fn scale(&self) -> f64 {
+        self.scales.into_iter().fold(1.0, f64::min)
+    }
+
+    fn update(&mut self, frame: &SensorFrame) -> Option<Event> {
+        let now = Instant::now();
+        if let Some(mv) = frame.get(VOLTAGE) {
+            let mv = mv as f64;
+            let v = self.voltage.map_or(mv, |v| v + (mv - v) * VOLTAGE_WEIGHT);
+            self.voltage = Some(v);
+            if motion::sent() == Wheels::STOP {
+                self.resting = Some(v);
+            }
+        }
+        let sag = self.voltage.zip(self.resting).map_or(0.0, |(v, r)| r - v);
+        if slip::slipping() {
+            self.slipped = Some(now);
+        }
+        let slipping = self.slipped.is_some_and(|t| now.duration_since(t) <= self.cfg.slip_hold());
+        let signal = LIGHT_BUMP_SIGNALS.iter().filter_map(|id| frame.get(*id)).max().unwrap_or(0) as f64;
+        self.scales = [
+            self.ramp(sag, self.cfg.sag()),
+            if slipping { self.cfg.slip_scale() } else { 1.0 },
+            self.ramp(signal, self.cfg.proximity()),
+        ];
+        let by: Vec<&'static str> = CONDITIONS.into_iter().zip(self.scales).filter(|(_, s)| *s < 1.0 - NEGLIGIBLE).map(|(c, _)| c).collect();
+        if by == self.by {
+            return None;
+        }
+        self.by = by;
+        let max_speed = self.cfg.max_speed() * self.scale();
+        if self.by.is_empty() {
+            info!("speed governor: back to {:.0} mm/s", self.cfg.max_speed());
+        } else {
+            info!("speed governor: down to {max_speed:.0} mm/s for {}", self.by.join(", "));
+        }
+        Some(Event::SpeedLimit { by: self.by.iter().map(|c| c.to_string()).collect(), max_speed })
+    }
+}
+
+/// Check one frame; events to publish.
+pub fn update(frame: &SensorFrame) -> Vec<Event> {
+    state().as_mut().and_then(|g| g.update(frame)).into_iter().collect()
+}
+
+/// `wheels` within the top speed the conditions allow.
+pub fn limit(wheels: Wheels) -> Wheels {
+    let guard = state();
+    let Some(g) = guard.as_ref() else { return wheels };
+    let max_speed = g.cfg.max_speed() * g.scale();
+    let fastest = (wheels.left as f64).abs().max((wheels.right as f64).abs());
+    if fastest <= max_speed {
+        return wheels;
+    }
+    let k = max_speed / fastest;
+    Wheels::new((wheels.left as f64 * k).round() as i32, (wheels.right as f64 * k).round() as i32)
+}
+
+/// The top speed and what holds it down, for `createctl status`; null
+/// without the governor.
+pub fn status() -> Value {
+    let guard = state();
+    let Some(g) = guard.as_ref() else { return Value::Null };
+    json!({
+        "max_speed": g.cfg.max_speed() * g.scale(),
+        "by": g.by,
+        "voltage": g.scales[0],
+        "surface": g.scales[1],
+        "proximity": g.scales[2],
+        "sag_mv": g.voltage.zip(g.resting).map(|(v, r)| r - v)
+    })
+}
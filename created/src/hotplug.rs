@@ -0,0 +1,172 @@
+//! Serial hotplug: device events from the kernel's uevent netlink socket, so
+//! the robot worker hears of a serial adapter plugged in or pulled out
+//! within milliseconds instead of rescanning `/dev` every 2 s. With udev
+//! running its events are taken, which come once its rules have run and
+//! the `/dev/serial/by-irobot-*` and `by-id` links exist; without it, the
+//! kernel's own, which come as the device node appears. Other systems have
+//! no such socket: `Monitor::open` fails there, and the worker rescans.
+
+#[cfg(target_os = "linux")]
+use std::io;
+#[cfg(unix)]
+use std::os::fd::{AsRawFd, RawFd};
+#[cfg(target_os = "linux")]
+use std::os::fd::{FromRawFd, OwnedFd};
+#[cfg(target_os = "linux")]
+use std::path::Path;
+
+/// Multicast groups of NETLINK_KOBJECT_UEVENT
+#[cfg(target_os = "linux")]
+const KERNEL: u32 = 1;
+#[cfg(target_os = "linux")]
+const UDEV: u32 = 2;
+/// Where udevd listens while it runs
+#[cfg(target_os = "linux")]
+const UDEV_CONTROL: &str = "/run/udev/control";
+/// Prefix and magic of udev's messages, ahead of the properties
+#[cfg(target_os = "linux")]
+const UDEV_PREFIX: &[u8] = b"libudev\0";
+#[cfg(target_os = "linux")]
+const UDEV_MAGIC: u32 = 0xfeed_cafe;
+
+/// A serial device coming or going.
+#[derive(Debug)]
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+pub enum Change {
+    Added(String),
+    Removed(String),
+    /// Events were dropped, the socket buffer having filled: anything may have changed
+    Lost,
+}
+
+#[cfg(target_os = "linux")]
+pub struct Monitor {
+    fd: OwnedFd,
+    udev: bool,
+}
+
+/// Never had: there are no events to monitor.
+#[cfg(not(target_os = "linux"))]
+pub enum Monitor {}
+
+#[cfg(not(target_os = "linux"))]
+impl Monitor {
+    pub fn open() -> Result<Monitor, String> {
+        Err("device events need Linux's uevent socket".into())
+    }
+
+    pub fn source(&self) -> &'static str {
+        match *self {}
+    }
+
+    pub fn changes(&self) -> Result<Vec<Change>, String> {
+        match *self {}
+    }
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+impl AsRawFd for Monitor {
+    fn as_raw_fd(&self) -> RawFd {
+        match *self {}
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Monitor {
+    pub fn open() -> Result<Monitor, String> {
+        let udev = Path::new(UDEV_CONTROL).exists();
+        // SAFETY: plain syscalls; the descriptor is owned from here on
+        let fd = unsafe { libc::socket(libc::AF_NETLINK, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC | libc::SOCK_NONBLOCK, libc::NETLINK_KOBJECT_UEVENT) };
+        if fd < 0 {
+            return Err(format!("netlink socket: {}", io::Error::last_os_error()));
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        let mut addr: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+        addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        addr.nl_groups = if udev { UDEV } else { KERNEL };
+        let len = std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t;
+        if unsafe { libc::bind(fd.as_raw_fd(), &addr as *const libc::sockaddr_nl as *const libc::sockaddr, len) } < 0 {
+            return Err(format!("netlink bind: {}", io::Error::last_os_error()));
+        }
+        Ok(Monitor { fd, udev })
+    }
+
+    /// Whose events these are, for the log.
+    pub fn source(&self) -> &'static str {
+        if self.udev { "udev" } else { "kernel" }
+    }
+
+    /// The serial devices that came or went since last asked; empty if
+    /// none did. The socket is `wakeup::wait`ed on for them.
+    pub fn changes(&self) -> Result<Vec<Change>, String> {
+        let mut changes = Vec::new();
+        let mut buf = [0u8; 8192];
+        loop {
+            let n = unsafe { libc::recv(self.fd.as_raw_fd(), buf.as_mut_ptr() as *mut libc::c_void, buf.len(), 0) };
+            if n < 0 {
+                let e = io::Error::last_os_error();
+                match e.raw_os_error() {
+                    Some(libc::EAGAIN) | Some(libc::EINTR) => return Ok(changes),
+                    Some(libc::ENOBUFS) => changes.push(Change::Lost),
+                    _ => return Err(format!("netlink recv: {e}")),
+                }
+                continue;
+            }
+            changes.extend(parse(&buf[..n as usize]));
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl AsRawFd for Monitor {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+/// The NUL-separated `KEY=value` properties of a message, from udev or
+/// from the kernel.
+#[cfg(target_os = "linux")]
+fn properties(msg: &[u8]) -> Option<&[u8]> {
+    if let Some(header) = msg.strip_prefix(UDEV_PREFIX) {
+        let word = |at: usize| header.get(at..at + 4).map(|b| [b[0], b[1], b[2], b[3]]);
+        if u32::from_be_bytes(word(0)?) != UDEV_MAGIC {
+            return None;
+        }
+        let offset = u32::from_ne_bytes(word(8)?) as usize;
+        let len = u32::from_ne_bytes(word(12)?) as usize;
+        return msg.get(offset..offset.checked_add(len)?);
+    }
+    // "action@devpath", then the properties
+    let first = msg.iter().position(|b| *b == 0)?;
+    if !msg[..first].contains(&b'@') {
+        return None;
+    }
+    Some(&msg[first + 1..])
+}
+
+/// A tty coming or going, from one message.
+#[cfg(target_os = "linux")]
+fn parse(msg: &[u8]) -> Option<Change> {
+    let (mut action, mut subsystem, mut devname) = (None, None, None);
+    for property in properties(msg)?.split(|b| *b == 0) {
+        let Ok(property) = std::str::from_utf8(property) else { continue };
+        match property.split_once('=') {
+            Some(("ACTION", v)) => action = Some(v),
+            Some(("SUBSYSTEM", v)) => subsystem = Some(v),
+            Some(("DEVNAME", v)) => devname = Some(v),
+            _ => {}
+        }
+    }
+    if subsystem != Some("tty") {
+        return None;
+    }
+    // udev gives the path, the kernel the name under /dev
+    let devname = devname?;
+    let path = if devname.starts_with('/') { devname.to_string() } else { format!("/dev/{devname}") };
+    match action? {
+        "add" => Some(Change::Added(path)),
+        "remove" => Some(Change::Removed(path)),
+        _ => None,
+    }
+}
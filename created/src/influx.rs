@@ -0,0 +1,229 @@
+//! InfluxDB line protocol sink: battery and sensor measurements from the
+//! latest sensor frame, plus the latest pose, written every `interval_ms` to an Influx HTTP write
+//! endpoint, a UDP listener, or a Unix socket (e.g. Telegraf's
+//! `socket_listener`).
+
+use std::fmt::Write as _;
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs, UdpSocket};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+use tracing::{info, warn};
+
+use crate::bus::{Bus, Message, Receiver, Sampling};
+use crate::config::InfluxConfig;
+use crate::oi::SensorFrame;
+use crate::pose::Pose;
+use crate::power;
+use crate::timing;
+
+const QUEUE_MESSAGES: usize = 256;
+const IO_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Packets reported in the `<prefix>battery` measurement; everything else in
+/// the frame goes to `<prefix>sensors`.
+const BATTERY_FIELDS: [&str; 6] = ["voltage", "current", "temperature", "battery_charge", "battery_capacity", "charging_state"];
+
+pub fn spawn(cfg: InfluxConfig, robot_id: String, bus: &mut Bus) -> JoinHandle<()> {
+    // Only one frame per interval is written, so don't queue the rest
+    let rate_hz = 1.0 / cfg.interval().as_secs_f64();
+    let sampling = Sampling::new("influx sink", Some(rate_hz), cfg.fields.as_deref());
+    let rx = bus.subscribe("influx sink", QUEUE_MESSAGES, sampling);
+    thread::spawn(move || run(cfg, robot_id, rx))
+}
+
+fn run(cfg: InfluxConfig, robot_id: String, rx: Receiver) {
+    let target = match Target::parse(cfg.url()) {
+        Ok(t) => t,
+        Err(e) => {
+            warn!("influx sink disabled: {e}");
+            return;
+        }
+    };
+    info!("writing Influx line protocol to {} every {:?}", cfg.url(), cfg.interval());
+    let mut latest: Option<Arc<SensorFrame>> = None;
+    let mut pose: Option<Pose> = None;
+    let mut next = Instant::now() + cfg.interval();
+    let mut failing = false;
+    loop {
+        match rx.recv_timeout(next.saturating_duration_since(Instant::now())) {
+            Ok(Message::Sensors(frame)) => latest = Some(frame),
+            Ok(Message::Pose(p)) => pose = Some(p),
+            Ok(_) => {}
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+        if Instant::now() < next {
+            continue;
+        }
+        next += cfg.interval();
+        let Some(frame) = latest.take() else { continue };
+        let mut body = lines(&frame, cfg.measurement_prefix(), &robot_id);
+        if let Some(pose) = pose.take() {
+            body.push_str(&pose_line(&pose, cfg.measurement_prefix(), &robot_id));
+        }
+        if let Some(report) = timing::report() {
+            body.push_str(&timing_line(&report, cfg.measurement_prefix(), &robot_id, &frame));
+        }
+        if let Some(report) = power::report() {
+            body.push_str(&power_line(&report, cfg.measurement_prefix(), &robot_id, &frame));
+        }
+        match target.send(&body, cfg.token.as_deref()) {
+            Ok(()) if failing => {
+                info!("influx sink: writes succeeding again");
+                failing = false;
+            }
+            Ok(()) => {}
+            Err(e) if !failing => {
+                warn!("influx sink: {e}");
+                failing = true;
+            }
+            Err(_) => {}
+        }
+    }
+}
+
+/// Line protocol for one frame: `<prefix>battery` and `<prefix>sensors`,
+/// tagged with the robot id, integer fields, nanosecond timestamp.
+fn lines(frame: &SensorFrame, prefix: &str, robot_id: &str) -> String {
+    let ns = frame.time.duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0);
+    let tag = escape_tag(robot_id);
+    let mut battery = String::new();
+    let mut sensors = String::new();
+    for (p, v) in frame.iter() {
+        let out = if BATTERY_FIELDS.contains(&p.name) { &mut battery } else { &mut sensors };
+        if !out.is_empty() {
+            out.push(',');
+        }
+        let _ = write!(out, "{}={v}i", p.name);
+    }
+    let mut body = String::new();
+    for (name, fields) in [("battery", battery), ("sensors", sensors)] {
+        if !fields.is_empty() {
+            let _ = writeln!(body, "{prefix}{name},robot_id={tag} {fields} {ns}");
+        }
+    }
+    body
+}
+
+/// `<prefix>timing`: the last complete control-loop timing window, in ms.
+fn timing_line(report: &timing::Report, prefix: &str, robot_id: &str, frame: &SensorFrame) -> String {
+    let ns = frame.time.duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0);
+    let f = &report.frame_interval;
+    let w = &report.write_latency;
+    let b = &report.loop_busy;
+    format!(
+        "{prefix}timing,robot_id={} frame_interval_mean={},frame_interval_max={},frame_jitter={},late_frames={}i,\
+         write_latency_mean={},write_latency_max={},loop_busy_mean={},loop_busy_max={} {ns}\n",
+        escape_tag(robot_id),
+        f.mean_ms,
+        f.max_ms,
+        f.jitter_ms,
+        report.late_frames,
+        w.mean_ms,
+        w.max_ms,
+        b.mean_ms,
+        b.max_ms
+    )
+}
+
+/// `<prefix>power`: the daemon's wakeups per second over the last window,
+/// and whether the stream is slowed for an idle robot.
+fn power_line(report: &power::Report, prefix: &str, robot_id: &str, frame: &SensorFrame) -> String {
+    let ns = frame.time.duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0);
+    format!("{prefix}power,robot_id={} wakeups_per_s={},slowed={} {ns}\n", escape_tag(robot_id), report.wakeups_per_s, power::slowed())
+}
+
+/// `<prefix>pose`: x and y in metres, theta in radians, and confidence.
+fn pose_line(pose: &Pose, prefix: &str, robot_id: &str) -> String {
+    let ns = pose.time.duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0);
+    format!("{prefix}pose,robot_id={} x={},y={},theta={},confidence={} {ns}\n", escape_tag(robot_id), pose.x, pose.y, pose.theta, pose.confidence)
+}
+
+fn escape_tag(s: &str) -> String {
+    s.replace(',', "\\,").replace('=', "\\=").replace(' ', "\\ ")
+}
+
+enum Target {
+    Http { host: String, addr: String, path: String },
+    Udp(String),
+    #[cfg(unix)]
+    Unix(String),
+}
+
+#[cfg(unix)]
+fn unix(path: &str) -> Result<Target, String> {
+    Ok(Target::Unix(path.to_string()))
+}
+
+#[cfg(not(unix))]
+fn unix(path: &str) -> Result<Target, String> {
+    Err(format!("unix://{path}: Unix sockets need a Unix host; use http:// or udp://"))
+}
+
+impl Target {
+    fn parse(url: &str) -> Result<Self, String> {
+        if let Some(rest) = url.strip_prefix("http://") {
+            let (authority, path) = match rest.find('/') {
+                Some(i) => (&rest[..i], &rest[i..]),
+                None => (rest, "/api/v2/write"),
+            };
+            let addr = if authority.contains(':') { authority.to_string() } else { format!("{authority}:80") };
+            Ok(Target::Http { host: authority.to_string(), addr, path: path.to_string() })
+        } else if let Some(addr) = url.strip_prefix("udp://") {
+            Ok(Target::Udp(addr.to_string()))
+        } else if let Some(path) = url.strip_prefix("unix://") {
+            unix(path)
+        } else {
+            Err(format!("unsupported url {url:?} (expected http://, udp://, or unix://)"))
+        }
+    }
+
+    fn send(&self, body: &str, token: Option<&str>) -> Result<(), String> {
+        match self {
+            Target::Http { host, addr, path } => {
+                let sock = addr
+                    .to_socket_addrs()
+                    .map_err(|e| format!("resolve {addr}: {e}"))?
+                    .next()
+                    .ok_or_else(|| format!("resolve {addr}: no address"))?;
+                let mut stream = TcpStream::connect_timeout(&sock, IO_TIMEOUT).map_err(|e| format!("connect {addr}: {e}"))?;
+                let _ = stream.set_read_timeout(Some(IO_TIMEOUT));
+                let _ = stream.set_write_timeout(Some(IO_TIMEOUT));
+                let mut req = format!(
+                    "POST {path} HTTP/1.1\r\nHost: {host}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n",
+                    body.len()
+                );
+                if let Some(token) = token {
+                    let _ = write!(req, "Authorization: Token {token}\r\n");
+                }
+                req.push_str("\r\n");
+                req.push_str(body);
+                stream.write_all(req.as_bytes()).map_err(|e| format!("write {addr}: {e}"))?;
+                let mut status = [0u8; 12];
+                stream.read_exact(&mut status).map_err(|e| format!("read {addr}: {e}"))?;
+                let status = String::from_utf8_lossy(&status[9..12]).into_owned();
+                if status.starts_with('2') {
+                    Ok(())
+                } else {
+                    Err(format!("{addr} answered HTTP {status}"))
+                }
+            }
+            Target::Udp(addr) => {
+                let sock = UdpSocket::bind("0.0.0.0:0").map_err(|e| format!("udp bind: {e}"))?;
+                sock.send_to(body.as_bytes(), addr.as_str()).map_err(|e| format!("send {addr}: {e}"))?;
+                Ok(())
+            }
+            #[cfg(unix)]
+            Target::Unix(path) => {
+                let mut stream = UnixStream::connect(path).map_err(|e| format!("connect {path}: {e}"))?;
+                stream.write_all(body.as_bytes()).map_err(|e| format!("write {path}: {e}"))
+            }
+        }
+    }
+}
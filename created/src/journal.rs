@@ -0,0 +1,137 @@
+//! SQLite event journal: every daemon event (connects, mode changes, bumps,
+//! cliffs, docking, session errors, control commands) becomes one row of the
+//! `events` table, pruned by age and count.
+//!
+//! ```sql
+//! SELECT time, type, data FROM events WHERE type = 'bump' AND time > '2026-01-01';
+//! ```
+
+use std::path::Path;
+use std::sync::mpsc::RecvTimeoutError;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use rusqlite::{params, Connection, OpenFlags};
+use tracing::{debug, info, warn};
+
+use crate::bus::{Bus, Event, Message, Receiver, Sampling};
+use crate::config::JournalConfig;
+
+const QUEUE_MESSAGES: usize = 256;
+const PRUNE_EVERY: Duration = Duration::from_secs(3600);
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS events (
+    id INTEGER PRIMARY KEY,
+    time TEXT NOT NULL,
+    robot_id TEXT NOT NULL,
+    type TEXT NOT NULL,
+    data TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS events_time ON events (time);
+CREATE INDEX IF NOT EXISTS events_type ON events (type, time);
+";
+
+pub fn spawn(cfg: JournalConfig, robot_id: String, bus: &mut Bus) -> JoinHandle<()> {
+    let rx = bus.subscribe("event journal", QUEUE_MESSAGES, Sampling::no_sensors());
+    thread::spawn(move || run(cfg, robot_id, rx))
+}
+
+fn run(cfg: JournalConfig, robot_id: String, rx: Receiver) {
+    let db = match open(&cfg.path()) {
+        Ok(db) => db,
+        Err(e) => {
+            warn!("event journal disabled: {e}");
+            return;
+        }
+    };
+    info!("journaling events to {}", cfg.path().display());
+    let mut last_prune: Option<Instant> = None;
+    loop {
+        if last_prune.is_none_or(|t| t.elapsed() >= PRUNE_EVERY) {
+            prune(&db, &cfg);
+            last_prune = Some(Instant::now());
+        }
+        match rx.recv_timeout(Duration::from_secs(60)) {
+            Ok(Message::Event { time, event }) => {
+                if let Err(e) = insert(&db, &robot_id, time, &event) {
+                    warn!("event journal: {e}");
+                }
+            }
+            Ok(_) => {}
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+    }
+}
+
+fn open(path: &Path) -> Result<Connection, String> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| format!("create {}: {e}", dir.display()))?;
+    }
+    let db = Connection::open(path).map_err(|e| format!("open {}: {e}", path.display()))?;
+    // WAL lets sqlite3/reporting tools read while the daemon writes
+    db.pragma_update(None, "journal_mode", "WAL").map_err(|e| format!("{}: {e}", path.display()))?;
+    db.execute_batch(SCHEMA).map_err(|e| format!("{}: {e}", path.display()))?;
+    Ok(db)
+}
+
+/// Times are stored as RFC 3339 UTC text, which sorts chronologically and
+/// works with SQLite's date functions.
+fn timestamp(time: SystemTime) -> String {
+    DateTime::<Utc>::from(time).to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+fn insert(db: &Connection, robot_id: &str, time: SystemTime, event: &Event) -> Result<(), String> {
+    let mut data = serde_json::to_value(event).map_err(|e| e.to_string())?;
+    let kind = data.as_object_mut().and_then(|o| o.shift_remove("type"));
+    let kind = kind.as_ref().and_then(|t| t.as_str()).unwrap_or("unknown").to_string();
+    db.execute(
+        "INSERT INTO events (time, robot_id, type, data) VALUES (?1, ?2, ?3, ?4)",
+        params![timestamp(time), robot_id, kind, data.to_string()],
+    )
+    .map_err(|e| format!("insert: {e}"))?;
+    Ok(())
+}
+
+fn prune(db: &Connection, cfg: &JournalConfig) {
+    let cutoff = timestamp(SystemTime::now() - cfg.retention());
+    let by_age = db.execute("DELETE FROM events WHERE time < ?1", params![cutoff]);
+    let by_count = db.execute(
+        "DELETE FROM events WHERE id <= (SELECT MAX(id) FROM events) - ?1",
+        params![cfg.max_events() as i64],
+    );
+    match (by_age, by_count) {
+        (Ok(a), Ok(c)) if a + c > 0 => debug!("event journal: pruned {} events", a + c),
+        (Ok(_), Ok(_)) => {}
+        (Err(e), _) | (_, Err(e)) => warn!("event journal: prune: {e}"),
+    }
+}
+
+/// Events with `from <= time < to`, oldest first, as (time, type, data).
+pub fn read_events(path: &Path, from: SystemTime, to: SystemTime) -> Result<Vec<(String, String, serde_json::Value)>, String> {
+    let db = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .map_err(|e| format!("open {}: {e}", path.display()))?;
+    // Freshly created by a daemon that hasn't set up the schema yet
+    let has_table = db
+        .query_row("SELECT count(*) FROM sqlite_master WHERE type = 'table' AND name = 'events'", [], |row| row.get::<_, i64>(0))
+        .map_err(|e| format!("query: {e}"))?;
+    if has_table == 0 {
+        return Ok(Vec::new());
+    }
+    let mut stmt = db
+        .prepare("SELECT time, type, data FROM events WHERE time >= ?1 AND time < ?2 ORDER BY time, id")
+        .map_err(|e| format!("query: {e}"))?;
+    let rows = stmt
+        .query_map(params![timestamp(from), timestamp(to)], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, String>(2)?))
+        })
+        .map_err(|e| format!("query: {e}"))?;
+    let mut events = Vec::new();
+    for row in rows {
+        let (time, kind, data) = row.map_err(|e| format!("query: {e}"))?;
+        events.push((time, kind, serde_json::from_str(&data).unwrap_or_default()));
+    }
+    Ok(events)
+}
@@ -0,0 +1,148 @@
+//! Native journald output: each event is sent to the journal socket with
+//! structured fields, so `journalctl -u created ROBOT_ID=... DEVICE=...`
+//! works. Event and span fields are uppercased (`device` -> `DEVICE`,
+//! `oi_mode` -> `OI_MODE`); `ROBOT_ID`, `PRIORITY`, `TARGET`, and
+//! `SYSLOG_IDENTIFIER` are always present.
+
+use std::fmt::{self, Write as _};
+use std::io;
+use std::os::unix::net::UnixDatagram;
+use std::path::Path;
+
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+pub const SOCKET: &str = "/run/systemd/journal/socket";
+
+/// Whether journald should be used when the config doesn't say: stderr is
+/// connected to the journal (systemd sets `JOURNAL_STREAM`) and the native
+/// socket exists.
+pub fn detected() -> bool {
+    std::env::var_os("JOURNAL_STREAM").is_some() && Path::new(SOCKET).exists()
+}
+
+pub struct JournaldLayer {
+    socket: UnixDatagram,
+    robot_id: String,
+}
+
+impl JournaldLayer {
+    pub fn new(robot_id: String) -> io::Result<Self> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(SOCKET)?;
+        Ok(JournaldLayer { socket, robot_id })
+    }
+}
+
+/// Span fields as (JOURNAL_NAME, value), kept in the span's extensions.
+#[derive(Default)]
+struct SpanFields(Vec<(String, String)>);
+
+impl<S> Layer<S> for JournaldLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else { return };
+        let mut fields = SpanFields::default();
+        attrs.record(&mut Collect(&mut fields.0));
+        span.extensions_mut().insert(fields);
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else { return };
+        let mut ext = span.extensions_mut();
+        if let Some(fields) = ext.get_mut::<SpanFields>() {
+            let mut updates = Vec::new();
+            values.record(&mut Collect(&mut updates));
+            for (k, v) in updates {
+                fields.0.retain(|(name, _)| *name != k);
+                fields.0.push((k, v));
+            }
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let meta = event.metadata();
+        let mut fields: Vec<(String, String)> = Vec::new();
+        if let Some(scope) = ctx.event_scope(event) {
+            for span in scope.from_root() {
+                if let Some(sf) = span.extensions().get::<SpanFields>() {
+                    for (k, v) in &sf.0 {
+                        fields.retain(|(name, _)| name != k);
+                        fields.push((k.clone(), v.clone()));
+                    }
+                }
+            }
+        }
+        let mut event_fields = Vec::new();
+        event.record(&mut Collect(&mut event_fields));
+
+        let mut buf = Vec::new();
+        put(&mut buf, "PRIORITY", priority(meta.level()));
+        put(&mut buf, "SYSLOG_IDENTIFIER", "created");
+        put(&mut buf, "TARGET", meta.target());
+        put(&mut buf, "ROBOT_ID", &self.robot_id);
+        for (k, v) in fields.iter().chain(event_fields.iter()) {
+            put(&mut buf, k, v);
+        }
+        if let Err(e) = self.socket.send(&buf) {
+            eprintln!("journald: {e}");
+        }
+    }
+}
+
+fn priority(level: &Level) -> &'static str {
+    match *level {
+        Level::ERROR => "3",
+        Level::WARN => "4",
+        Level::INFO => "6",
+        _ => "7",
+    }
+}
+
+/// Append one field in the journal export format; values containing a
+/// newline use the length-prefixed binary form.
+fn put(buf: &mut Vec<u8>, key: &str, value: &str) {
+    buf.extend_from_slice(key.as_bytes());
+    if value.contains('\n') {
+        buf.push(b'\n');
+        buf.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        buf.push(b'=');
+    }
+    buf.extend_from_slice(value.as_bytes());
+    buf.push(b'\n');
+}
+
+/// Journal field names: uppercase letters, digits, and underscores, not
+/// starting with an underscore or digit. `message` becomes `MESSAGE`.
+fn field_name(name: &str) -> String {
+    let mut out: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+        .collect();
+    if out.starts_with(|c: char| c == '_' || c.is_ascii_digit()) {
+        out.insert(0, 'F');
+    }
+    out.truncate(64);
+    out
+}
+
+struct Collect<'a>(&'a mut Vec<(String, String)>);
+
+impl Visit for Collect<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.push((field_name(field.name()), value.to_string()));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        let mut s = String::new();
+        let _ = write!(s, "{value:?}");
+        self.0.push((field_name(field.name()), s));
+    }
+}
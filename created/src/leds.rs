@@ -0,0 +1,489 @@
+//! LEDs: the power LED as a battery gauge, animations played on the LEDs
+//! by name, and one-off flashes (see `morse`).
+//!
+//! With `leds.battery`, the power LED's color follows the charge from
+//! green (full) to red (empty), and its intensity from
+//! `leds.full_intensity` to `leds.empty_intensity`, so a glance at the
+//! robot tells how charged it is.
+//!
+//! Animations (`leds.patterns`) pulse the power LED, blink any of the
+//! LEDs, or chase segments around the Create 2's digit display. Behaviors,
+//! scripts, and control clients ask for one by name; it plays over the
+//! battery gauge until it has run its `cycles`, is stopped, or another
+//! takes its place, one on the LEDs and one on the digits at a time. The
+//! daemon works out each animation frame from the time it started, every
+//! sensor frame, and sends it when it changes, at most every `FRAME`.
+//!
+//! With `leds.display`, the Create 2's four digits show the clock, the
+//! battery charge, and the last two octets of the host's IP address in
+//! turn, `leds.display_s` each, under any animation on the digits.
+//!
+//! In quiet mode (see `quiet`) only the critical animations play.
+//!
+//! The OI only takes the LEDs commands in Safe and Full mode, so they are
+//! sent again whenever the robot enters one of them.
+
+use std::collections::BTreeMap;
+use std::f64::consts::TAU;
+use std::net::{IpAddr, UdpSocket};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use chrono::{Local, Timelike};
+
+use serde_json::{json, Value};
+use tracing::{debug, info, warn};
+
+use crate::config::{LedAnimation, LedPatternConfig, LedsConfig};
+use crate::motion;
+use crate::oi::{self, SensorFrame};
+use crate::quiet;
+
+const BATTERY_CHARGE: u8 = 25;
+const BATTERY_CAPACITY: u8 = 26;
+/// Power LED color: 0 is green, 255 red
+const RED: f64 = 255.0;
+/// Color steps the charge has to move before the gauge changes, so a
+/// reading wavering by a mAh doesn't resend it every frame.
+const STEP: u8 = 3;
+/// Shortest time between two animation frames sent: the LEDs share the
+/// serial link with everything else.
+const FRAME: Duration = Duration::from_millis(50);
+/// Opcode 163, Create 2: the segments of each digit, leftmost first
+const DIGIT_LEDS_RAW: u8 = 163;
+/// Opcode 162, Create 2: the day and schedule LEDs, the clock's colon among them
+const SCHEDULING_LEDS: u8 = 162;
+const COLON: u8 = 1;
+/// How often the IP address is looked up again
+const IP_EVERY: Duration = Duration::from_secs(30);
+
+/// The LEDs command's LED bits, by name; Create 1 and 2 name some bits differently.
+fn led_bit(name: &str) -> Option<u8> {
+    Some(match name {
+        "debris" => 1,
+        "spot" | "play" => 2,
+        "dock" => 4,
+        "check" | "advance" => 8,
+        _ => return None,
+    })
+}
+
+/// The edge of the four digits, clockwise from the top left: digit and
+/// segment bit (a top, b top right, c bottom right, d bottom, e bottom
+/// left, f top left).
+const RING: [(usize, u8); 12] = [(0, 0), (1, 0), (2, 0), (3, 0), (3, 1), (3, 2), (3, 3), (2, 3), (1, 3), (0, 3), (0, 4), (0, 5)];
+
+/// The LEDs command's data: LED bits, power color, power intensity.
+type Lights = [u8; 3];
+/// Segment bits of each digit, leftmost first.
+type Digits = [u8; 4];
+/// The scheduling LEDs command's data: weekday bits, schedule bits.
+type Schedule = [u8; 2];
+
+/// Seven-segment glyphs, segment a in bit 0 to g in bit 6.
+fn glyph(c: char) -> u8 {
+    match c {
+        '0' => 0x3f,
+        '1' => 0x06,
+        '2' => 0x5b,
+        '3' => 0x4f,
+        '4' => 0x66,
+        '5' => 0x6d,
+        '6' => 0x7d,
+        '7' => 0x07,
+        '8' => 0x7f,
+        '9' => 0x6f,
+        'b' => 0x7c,
+        '-' => 0x40,
+        _ => 0,
+    }
+}
+
+/// The first four characters of `text` as digits, blank past its end.
+fn show(text: &str) -> Digits {
+    let mut digits = [0; 4];
+    for (d, c) in digits.iter_mut().zip(text.chars()) {
+        *d = glyph(c);
+    }
+    digits
+}
+
+/// What the digits can show.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Item {
+    Clock,
+    Battery,
+    Ip,
+}
+
+/// The host's address toward the outside: the source address of a route
+/// out, found by connecting a UDP socket, which sends nothing.
+fn host_ip() -> Option<IpAddr> {
+    let socket = UdpSocket::bind("0.0.0.0:0").ok()?;
+    socket.connect("192.0.2.1:9").ok()?;
+    socket.local_addr().ok().map(|a| a.ip()).filter(|ip| !ip.is_unspecified())
+}
+
+/// The digits' rotation.
+struct Display {
+    items: Vec<Item>,
+    each: Duration,
+    clock_24h: bool,
+    started: Instant,
+    /// Last two octets, and when they were looked up
+    ip: Option<([u8; 2], Instant)>,
+}
+
+impl Display {
+    /// The digits, and whether the clock's colon is lit, at `now`.
+    fn show(&mut self, now: Instant, charge: Option<f64>) -> Option<(Digits, bool)> {
+        let turn = now.duration_since(self.started).as_secs_f64() / self.each.as_secs_f64();
+        let item = *self.items.get(turn as usize % self.items.len().max(1))?;
+        Some(match item {
+            Item::Clock => {
+                let time = Local::now();
+                let hour = if self.clock_24h { time.hour() } else { (time.hour() + 11) % 12 + 1 };
+                let text = if self.clock_24h { format!("{hour:02}{:02}", time.minute()) } else { format!("{hour:>2}{:02}", time.minute()) };
+                (show(&text), true)
+            }
+            Item::Battery => match charge {
+                Some(charge) => (show(&format!("b{:>3}", (charge * 100.0).round() as u32)), false),
+                None => (show("b---"), false),
+            },
+            Item::Ip => {
+                if self.ip.is_none_or(|(_, at)| now.duration_since(at) >= IP_EVERY) {
+                    let octets = match host_ip() {
+                        Some(IpAddr::V4(v4)) => [v4.octets()[2], v4.octets()[3]],
+                        _ => [0, 0],
+                    };
+                    self.ip = Some((octets, now));
+                }
+                let [third, fourth] = self.ip.map(|(o, _)| o).unwrap_or_default();
+                // One octet, after its number, for each half of the turn
+                let text = match ((third, fourth), turn.fract() < 0.5) {
+                    ((0, 0), _) => "----".to_string(),
+                    (_, true) => format!("3{third:>3}"),
+                    (_, false) => format!("4{fourth:>3}"),
+                };
+                (show(&text), false)
+            }
+        })
+    }
+}
+
+/// A pattern, checked.
+struct Pattern {
+    cfg: LedPatternConfig,
+    bits: u8,
+    power: bool,
+}
+
+struct Playing {
+    name: String,
+    pattern: Pattern,
+    /// On and off spans played once, in place of the pattern's timing
+    spans: Option<Vec<(bool, Duration)>>,
+    started: Instant,
+}
+
+/// One kind of LED command, with the animation playing on it and what it
+/// last sent.
+struct Output<T> {
+    playing: Option<Playing>,
+    sent: Option<T>,
+    at: Option<Instant>,
+}
+
+impl<T> Default for Output<T> {
+    fn default() -> Self {
+        Output { playing: None, sent: None, at: None }
+    }
+}
+
+struct Leds {
+    cfg: LedsConfig,
+    /// Battery charge, from 0 to 1
+    charge: Option<f64>,
+    /// Power LED color and intensity for the battery charge
+    gauge: Option<(u8, u8)>,
+    display: Display,
+    lights: Output<Lights>,
+    digits: Output<Digits>,
+    schedule: Output<Schedule>,
+    mode: Option<i32>,
+}
+
+static CONFIG: Mutex<Option<LedsConfig>> = Mutex::new(None);
+static PATTERNS: Mutex<BTreeMap<String, LedPatternConfig>> = Mutex::new(BTreeMap::new());
+static STATE: Mutex<Option<Leds>> = Mutex::new(None);
+
+fn state() -> std::sync::MutexGuard<'static, Option<Leds>> {
+    STATE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn patterns() -> std::sync::MutexGuard<'static, BTreeMap<String, LedPatternConfig>> {
+    PATTERNS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// `cfg` with its LED names checked.
+fn pattern(cfg: &LedPatternConfig) -> Result<Pattern, String> {
+    let (mut bits, mut power) = (0, false);
+    for led in cfg.leds() {
+        match led.as_str() {
+            "power" => power = true,
+            name => bits |= led_bit(name).ok_or_else(|| format!("unknown LED {name:?}"))?,
+        }
+    }
+    Ok(Pattern { cfg: cfg.clone(), bits, power })
+}
+
+/// Check the patterns, leaving out any that can't be played, and the
+/// display's items.
+pub fn init(cfg: LedsConfig) {
+    for item in cfg.display() {
+        if self::item(&item).is_none() {
+            warn!("leds.display: unknown item {item:?}, expected clock, battery, or ip");
+        }
+    }
+    let mut checked = BTreeMap::new();
+    for (name, p) in cfg.patterns() {
+        match pattern(&p) {
+            Ok(_) => {
+                checked.insert(name, p);
+            }
+            Err(e) => warn!("leds.patterns.{name}: {e}"),
+        }
+    }
+    *patterns() = checked;
+    *CONFIG.lock().unwrap_or_else(|e| e.into_inner()) = Some(cfg);
+}
+
+fn item(name: &str) -> Option<Item> {
+    Some(match name {
+        "clock" => Item::Clock,
+        "battery" => Item::Battery,
+        "ip" => Item::Ip,
+        _ => return None,
+    })
+}
+
+pub fn begin() {
+    let cfg = CONFIG.lock().unwrap_or_else(|e| e.into_inner()).clone().unwrap_or_default();
+    let items = cfg.display().iter().filter_map(|i| item(i)).collect();
+    let display = Display { items, each: cfg.display_time(), clock_24h: cfg.clock_24h(), started: Instant::now(), ip: None };
+    *state() = Some(Leds {
+        cfg,
+        charge: None,
+        gauge: None,
+        display,
+        lights: Output::default(),
+        digits: Output::default(),
+        schedule: Output::default(),
+        mode: None,
+    });
+}
+
+pub fn end() {
+    *state() = None;
+}
+
+/// Whether there is a pattern called `name`.
+pub fn known(name: &str) -> bool {
+    patterns().contains_key(name)
+}
+
+/// Start the pattern `name`, in place of the one playing on the same LEDs.
+/// In quiet mode only the critical patterns play; the others are skipped,
+/// so a behavior asking for one carries on.
+pub fn play(name: &str) -> Result<Value, String> {
+    let cfg = patterns().get(name).cloned().ok_or_else(|| format!("no LED pattern {name:?}"))?;
+    let pattern = pattern(&cfg)?;
+    let mut guard = state();
+    let leds = guard.as_mut().ok_or("no robot is connected")?;
+    if quiet::on() && !quiet::critical_led(name) {
+        info!("LED pattern {name} skipped: quiet mode is on");
+        return Ok(json!({ "pattern": name, "skipped": "quiet mode is on" }));
+    }
+    let output = match cfg.kind() {
+        LedAnimation::Chase => &mut leds.digits.playing,
+        LedAnimation::Pulse | LedAnimation::Blink => &mut leds.lights.playing,
+    };
+    let replaced = output.replace(Playing { name: name.to_string(), pattern, spans: None, started: Instant::now() }).map(|p| p.name);
+    info!("playing LED pattern {name}");
+    Ok(json!({ "pattern": name, "replaced": replaced }))
+}
+
+/// Flash the LEDs of `cfg` (its color and intensity) on and off for the
+/// `spans` given, once, in place of the pattern playing on them; refused
+/// in quiet mode.
+pub fn flash(name: &str, cfg: &LedPatternConfig, spans: Vec<(bool, Duration)>) -> Result<Value, String> {
+    if quiet::on() {
+        return Err("quiet mode is on".into());
+    }
+    let pattern = pattern(cfg)?;
+    let mut guard = state();
+    let leds = guard.as_mut().ok_or("no robot is connected")?;
+    let seconds: f64 = spans.iter().map(|(_, d)| d.as_secs_f64()).sum();
+    let replaced = leds.lights.playing.replace(Playing { name: name.to_string(), pattern, spans: Some(spans), started: Instant::now() }).map(|p| p.name);
+    info!("flashing {name}");
+    Ok(json!({ "flashing": name, "seconds": seconds, "replaced": replaced }))
+}
+
+/// Stop the animations, leaving the battery gauge if it is on.
+pub fn stop() -> Result<Value, String> {
+    let mut guard = state();
+    let leds = guard.as_mut().ok_or("no robot is connected")?;
+    let stopped: Vec<String> = [leds.lights.playing.take(), leds.digits.playing.take()].into_iter().flatten().map(|p| p.name).collect();
+    Ok(json!({ "stopped": stopped }))
+}
+
+/// The animations playing and the battery gauge, for `createctl leds`.
+pub fn status() -> Value {
+    let names: Vec<String> = patterns().keys().cloned().collect();
+    let guard = state();
+    let Some(leds) = guard.as_ref() else { return json!({ "playing": [], "patterns": names }) };
+    let playing: Vec<&str> = [&leds.lights.playing, &leds.digits.playing].into_iter().flatten().map(|p| p.name.as_str()).collect();
+    json!({
+        "playing": playing,
+        "patterns": names,
+        "gauge": leds.gauge.map(|(color, intensity)| json!({ "color": color, "intensity": intensity }))
+    })
+}
+
+impl Playing {
+    /// Where in its period the animation is, from 0 to 1; none once it
+    /// has played its cycles.
+    fn phase(&self, now: Instant) -> Option<f64> {
+        let periods = now.duration_since(self.started).as_secs_f64() / self.pattern.cfg.period().as_secs_f64();
+        let cycles = self.pattern.cfg.cycles();
+        (cycles == 0 || periods < cycles as f64).then_some(periods.fract())
+    }
+
+    /// Whether the span playing is on; none after the last.
+    fn lit(&self, spans: &[(bool, Duration)], now: Instant) -> Option<bool> {
+        let elapsed = now.duration_since(self.started);
+        let mut end = Duration::ZERO;
+        spans.iter().find(|(_, d)| {
+            end += *d;
+            elapsed < end
+        }).map(|(on, _)| *on)
+    }
+
+    fn lights(&self, now: Instant, gauge: Option<(u8, u8)>) -> Option<Lights> {
+        let p = &self.pattern;
+        let on = |lit: bool| if lit { (p.bits, p.cfg.intensity() as f64) } else { (0, 0.0) };
+        let (bits, intensity) = match (&self.spans, p.cfg.kind()) {
+            (Some(spans), _) => on(self.lit(spans, now)?),
+            (None, LedAnimation::Pulse) => {
+                let (high, low) = (p.cfg.intensity() as f64, p.cfg.min_intensity() as f64);
+                (p.bits, low + (high - low) * (1.0 - (TAU * self.phase(now)?).cos()) / 2.0)
+            }
+            (None, _) => on(self.phase(now)? < p.cfg.duty()),
+        };
+        let color = p.cfg.color.or(gauge.map(|(c, _)| c)).unwrap_or(0);
+        // A pattern leaving out the power LED leaves it to the gauge
+        let (color, intensity) = if p.power { (color, intensity.round() as u8) } else { gauge.unwrap_or((0, 0)) };
+        Some([bits, color, intensity])
+    }
+
+    fn digits(&self, now: Instant) -> Option<Digits> {
+        let phase = self.phase(now)?;
+        let mut digits = [0; 4];
+        let head = (phase * RING.len() as f64) as usize;
+        for i in 0..self.pattern.cfg.width().min(RING.len()) {
+            let (digit, segment) = RING[(head + i) % RING.len()];
+            digits[digit] |= 1 << segment;
+        }
+        Some(digits)
+    }
+}
+
+impl<T: Copy + PartialEq + Default> Output<T> {
+    /// The animation's frame, else `base`, else all off if anything was
+    /// shown before; none once it ends.
+    fn frame(&mut self, base: Option<T>, render: impl Fn(&Playing) -> Option<T>) -> Option<T> {
+        let rendered = self.playing.as_ref().and_then(render);
+        if rendered.is_none() {
+            if let Some(done) = self.playing.take() {
+                info!("LED pattern {} done", done.name);
+            }
+        }
+        rendered.or(base).or(self.sent.map(|_| T::default()))
+    }
+
+    /// `frame` if it isn't what was sent last, and the last send was long enough ago.
+    fn due(&mut self, frame: Option<T>, now: Instant) -> Option<T> {
+        let frame = frame.filter(|f| self.sent != Some(*f))?;
+        if self.at.is_some_and(|at| now.duration_since(at) < FRAME) {
+            return None;
+        }
+        self.sent = Some(frame);
+        self.at = Some(now);
+        Some(frame)
+    }
+}
+
+impl Leds {
+    /// Power LED color and intensity for a charge from 0 to 1.
+    fn gauge(&self, charge: f64) -> (u8, u8) {
+        let (full, empty) = (self.cfg.full_intensity() as f64, self.cfg.empty_intensity() as f64);
+        let color = (RED * (1.0 - charge)).round() as u8;
+        let intensity = (empty + (full - empty) * charge).round() as u8;
+        (color, intensity)
+    }
+
+    fn update(&mut self, frame: &SensorFrame) {
+        let now = Instant::now();
+        let mode = frame.get(oi::OI_MODE).or(self.mode);
+        // Entering Safe or Full mode: whatever was sent before was ignored
+        if mode != self.mode && matches!(mode, Some(oi::MODE_SAFE | oi::MODE_FULL)) {
+            self.lights.sent = None;
+            self.digits.sent = None;
+            self.schedule.sent = None;
+        }
+        self.mode = mode;
+        if quiet::on() {
+            for output in [&mut self.lights.playing, &mut self.digits.playing] {
+                if let Some(p) = output.take_if(|p| !quiet::critical_led(&p.name)) {
+                    info!("LED pattern {} stopped: quiet mode is on", p.name);
+                }
+            }
+        }
+        if let (Some(charge), Some(capacity)) = (frame.get(BATTERY_CHARGE), frame.get(BATTERY_CAPACITY).filter(|c| *c > 0)) {
+            let charge = (charge as f64 / capacity as f64).clamp(0.0, 1.0);
+            self.charge = Some(charge);
+            if self.cfg.battery() {
+                let (color, intensity) = self.gauge(charge);
+                if self.gauge.is_none_or(|(c, _)| c.abs_diff(color) >= STEP) {
+                    debug!("power LED: {:.0}% charged, color {color}, intensity {intensity}", charge * 100.0);
+                    self.gauge = Some((color, intensity));
+                }
+            }
+        }
+
+        let gauge = self.gauge;
+        let lights = self.lights.frame(gauge.map(|(c, i)| [0, c, i]), |p| p.lights(now, gauge));
+        if let Some(lights) = self.lights.due(lights, now) {
+            motion::queue([vec![oi::LEDS], lights.to_vec()].concat());
+        }
+        let display = self.display.show(now, self.charge);
+        let digits = self.digits.frame(display.map(|(d, _)| d), |p| p.digits(now));
+        if let Some(digits) = self.digits.due(digits, now) {
+            motion::queue([vec![DIGIT_LEDS_RAW], digits.to_vec()].concat());
+        }
+        // The colon only shows with the clock, not under a chase
+        let colon = display.is_some_and(|(_, colon)| colon) && self.digits.playing.is_none();
+        let schedule = self.schedule.frame(colon.then_some([0, COLON]), |_| None);
+        if let Some(schedule) = self.schedule.due(schedule, now) {
+            motion::queue([vec![SCHEDULING_LEDS], schedule.to_vec()].concat());
+        }
+    }
+}
+
+/// Follow the battery charge, turn the display, and play the animations;
+/// call every frame.
+pub fn update(frame: &SensorFrame) {
+    if let Some(leds) = state().as_mut() {
+        leds.update(frame);
+    }
+}
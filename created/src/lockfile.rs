@@ -0,0 +1,89 @@
+//! UUCP-style lock files, `/var/lock/LCK..ttyUSB0`, holding the PID of the
+//! program using the port: the convention minicom, screen, gpsd, and the
+//! like keep to, so the daemon doesn't open a port one of them has, nor
+//! they the robot's. One left by a process that is gone is stale and
+//! taken over. Between programs that don't keep to it, the exclusive mode
+//! serialport opens ports in (TIOCEXCL) keeps a second open out. Windows
+//! has no such convention, opening a COM port for one program at a time
+//! itself, and there ports aren't locked.
+
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+use tracing::{info, warn};
+
+/// A port locked, until dropped.
+pub struct Lock {
+    path: PathBuf,
+}
+
+/// `LCK..` and the device's name under /dev, any `/` in it as `_`.
+fn lock_path(dir: &Path, device: &Path) -> PathBuf {
+    let dev = fs::canonicalize(device).unwrap_or_else(|_| device.to_path_buf());
+    let name = dev.strip_prefix("/dev").unwrap_or(&dev).to_string_lossy().replace('/', "_");
+    dir.join(format!("LCK..{name}"))
+}
+
+/// Whether process `pid` is running.
+#[cfg(unix)]
+fn alive(pid: i32) -> bool {
+    // SAFETY: signal 0 only checks that the process exists
+    let sent = unsafe { libc::kill(pid, 0) } == 0;
+    sent || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// Whether process `pid` is running: taken to be, not being known.
+#[cfg(not(unix))]
+fn alive(_pid: i32) -> bool {
+    true
+}
+
+impl Lock {
+    /// Lock `device` with a file in `dir`; Err if another program holds it.
+    /// Ok(None) if the file can't be made at all (no such directory, no
+    /// permission to write it): the port is used unlocked then.
+    pub fn acquire(dir: &Path, device: &Path) -> Result<Option<Lock>, String> {
+        let path = lock_path(dir, device);
+        match fs::read_to_string(&path) {
+            Ok(content) => match content.trim().parse::<i32>() {
+                Ok(pid) if pid as u32 == std::process::id() => {}
+                Ok(pid) if pid > 0 && alive(pid) => {
+                    return Err(format!("in use by process {pid} (lock file {})", path.display()));
+                }
+                Ok(pid) => {
+                    info!("taking over the stale lock file {} of process {pid}", path.display());
+                    let _ = fs::remove_file(&path);
+                }
+                Err(_) => return Err(format!("locked by {}, which doesn't hold a PID (remove it if nothing uses the port)", path.display())),
+            },
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(format!("can't read the lock file {}: {e}", path.display())),
+        }
+        let mut file = match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                return Err(format!("locked by another program just now (lock file {})", path.display()));
+            }
+            Err(e) => {
+                warn!("can't create the lock file {} ({e}): using {} without one", path.display(), device.display());
+                return Ok(None);
+            }
+        };
+        // The PID as ten characters, right-aligned, then a newline
+        if let Err(e) = writeln!(file, "{:>10}", std::process::id()) {
+            let _ = fs::remove_file(&path);
+            warn!("can't write the lock file {} ({e}): using {} without one", path.display(), device.display());
+            return Ok(None);
+        }
+        Ok(Some(Lock { path }))
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            warn!("can't remove the lock file {}: {e}", self.path.display());
+        }
+    }
+}
@@ -0,0 +1,110 @@
+//! Log file writer with size- and age-based rotation for installs without
+//! journald. `created.log` rotates to `created.log.1` (optionally gzipped to
+//! `created.log.1.gz`), older files shift up, and the oldest are deleted.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+use crate::config::LogFileConfig;
+
+pub struct RotatingFile {
+    path: PathBuf,
+    max_bytes: u64,
+    max_age: Option<Duration>,
+    max_files: usize,
+    compress: bool,
+    file: File,
+    size: u64,
+    opened: Instant,
+}
+
+impl RotatingFile {
+    pub fn open(cfg: &LogFileConfig) -> io::Result<Self> {
+        let path = cfg.path();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(RotatingFile {
+            path,
+            max_bytes: cfg.max_bytes(),
+            max_age: cfg.rotate_after(),
+            max_files: cfg.max_files(),
+            compress: cfg.compress(),
+            file,
+            size,
+            opened: Instant::now(),
+        })
+    }
+
+    fn rotated(&self, n: usize) -> PathBuf {
+        PathBuf::from(format!("{}.{n}", self.path.display()))
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        // Drop the oldest, then shift .N -> .N+1 (compressed or not)
+        for n in (1..=self.max_files).rev() {
+            let plain = self.rotated(n);
+            let gz = gz_path(&plain);
+            if n == self.max_files {
+                let _ = fs::remove_file(&plain);
+                let _ = fs::remove_file(&gz);
+                continue;
+            }
+            let _ = fs::rename(&plain, self.rotated(n + 1));
+            let _ = fs::rename(&gz, gz_path(&self.rotated(n + 1)));
+        }
+        let first = self.rotated(1);
+        fs::rename(&self.path, &first)?;
+        self.file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        self.size = 0;
+        self.opened = Instant::now();
+        if self.compress {
+            // Off the logging path: compressing a full file takes a while on an SBC
+            thread::spawn(move || {
+                if let Err(e) = gzip(&first) {
+                    eprintln!("log rotation: failed to compress {}: {e}", first.display());
+                }
+            });
+        }
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let too_old = self.max_age.is_some_and(|age| self.opened.elapsed() >= age);
+        if self.size > 0 && (self.size + buf.len() as u64 > self.max_bytes || too_old) {
+            if let Err(e) = self.rotate() {
+                // Keep appending to the current file rather than losing lines
+                eprintln!("log rotation of {} failed: {e}", self.path.display());
+            }
+        }
+        let n = self.file.write(buf)?;
+        self.size += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+fn gz_path(p: &Path) -> PathBuf {
+    PathBuf::from(format!("{}.gz", p.display()))
+}
+
+fn gzip(path: &Path) -> io::Result<()> {
+    let mut input = File::open(path)?;
+    let mut enc = GzEncoder::new(File::create(gz_path(path))?, Compression::default());
+    io::copy(&mut input, &mut enc)?;
+    enc.finish()?;
+    fs::remove_file(path)
+}
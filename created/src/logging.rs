@@ -0,0 +1,190 @@
+use std::fmt;
+use std::io::IsTerminal;
+use std::sync::Mutex;
+
+use serde_json::{Map, Value};
+use tracing::field::{Field, Visit};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::fmt::format::{JsonFields, Writer};
+use tracing_subscriber::fmt::time::{FormatTime, SystemTime};
+use tracing_subscriber::fmt::writer::MakeWriter;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields, FormattedFields};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer, Registry};
+
+use crate::config::{Config, LogFormat};
+#[cfg(target_os = "linux")]
+use crate::journald::{self, JournaldLayer};
+use crate::logfile::RotatingFile;
+
+type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
+/// Initialize the global subscriber: native journald (when enabled or, by
+/// default, when started by systemd) or stderr, plus a rotating log file if
+/// configured. stderr and the file use `log_format`.
+///
+/// `RUST_LOG` controls filtering everywhere; the default is `info`.
+/// Records from crates still using `log` are forwarded as well. Returns a
+/// message to log if an output could not be opened.
+pub fn init(config: &Config) -> Option<String> {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let format = config.log_format();
+    let robot_id = config.robot_id();
+    let mut layers: Vec<BoxedLayer> = Vec::new();
+    let mut problem = None;
+
+    let file_cfg = config.log_file.clone().unwrap_or_default();
+    if file_cfg.enabled() {
+        match RotatingFile::open(&file_cfg) {
+            Ok(file) => layers.push(fmt_layer(Mutex::new(file), false, format, &robot_id)),
+            Err(e) => problem = Some(format!("cannot open log file {}: {e}", file_cfg.path().display())),
+        }
+    }
+    let mut to_journal = false;
+    match journal_layer(config.journald, &robot_id) {
+        Ok(Some(layer)) => {
+            layers.push(layer);
+            to_journal = true;
+        }
+        Ok(None) => {}
+        Err(e) => problem = Some(e),
+    }
+    // stderr goes to the journal too under systemd, so skip it to avoid duplicates
+    if !to_journal && (file_cfg.stderr() || layers.is_empty()) {
+        layers.push(fmt_layer(std::io::stderr, std::io::stderr().is_terminal(), format, &robot_id));
+    }
+
+    tracing_subscriber::registry().with(layers).with(filter).init();
+    problem
+}
+
+/// The journald layer, if enabled (`journald`, or by default when started
+/// by systemd).
+#[cfg(target_os = "linux")]
+fn journal_layer(journald: Option<bool>, robot_id: &str) -> Result<Option<BoxedLayer>, String> {
+    if !journald.unwrap_or_else(journald::detected) {
+        return Ok(None);
+    }
+    match JournaldLayer::new(robot_id.to_string()) {
+        Ok(layer) => Ok(Some(layer.boxed())),
+        Err(e) => Err(format!("cannot connect to {}: {e}; logging to stderr", journald::SOCKET)),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn journal_layer(journald: Option<bool>, _robot_id: &str) -> Result<Option<BoxedLayer>, String> {
+    match journald {
+        Some(true) => Err("journald is Linux's; logging to stderr".into()),
+        _ => Ok(None),
+    }
+}
+
+fn fmt_layer<W>(writer: W, ansi: bool, format: LogFormat, robot_id: &str) -> BoxedLayer
+where
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    let layer = tracing_subscriber::fmt::layer().with_writer(writer).with_ansi(ansi);
+    match format {
+        LogFormat::Text => layer.boxed(),
+        LogFormat::Json => layer
+            .fmt_fields(JsonFields::new())
+            .event_format(JsonLine { robot_id: robot_id.to_string() })
+            .boxed(),
+    }
+}
+
+/// One JSON object per line: `timestamp`, `level`, `target`, `robot_id`,
+/// `message`, `fields` (event fields plus those of every enclosing span), and
+/// `spans` (names, outermost first).
+struct JsonLine {
+    robot_id: String,
+}
+
+impl<S, N> FormatEvent<S, N> for JsonLine
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(&self, ctx: &FmtContext<'_, S, N>, mut writer: Writer<'_>, event: &Event<'_>) -> fmt::Result {
+        let mut timestamp = String::new();
+        SystemTime.format_time(&mut Writer::new(&mut timestamp))?;
+
+        let mut fields = Map::new();
+        let mut spans = Vec::new();
+        if let Some(scope) = ctx.event_scope() {
+            for span in scope.from_root() {
+                spans.push(Value::from(span.name()));
+                let ext = span.extensions();
+                if let Some(formatted) = ext.get::<FormattedFields<N>>() {
+                    if let Ok(Value::Object(map)) = serde_json::from_str::<Value>(formatted) {
+                        fields.extend(map);
+                    }
+                }
+            }
+        }
+        let mut visitor = JsonVisitor { message: None, fields: &mut fields };
+        event.record(&mut visitor);
+        let message = visitor.message.unwrap_or_default();
+
+        let meta = event.metadata();
+        let line = serde_json::json!({
+            "timestamp": timestamp,
+            "level": meta.level().as_str(),
+            "target": meta.target(),
+            "robot_id": self.robot_id,
+            "message": message,
+            "fields": fields,
+            "spans": spans,
+        });
+        writeln!(writer, "{line}")
+    }
+}
+
+/// Collects event fields into a JSON object, keeping `message` separate.
+struct JsonVisitor<'a> {
+    message: Option<String>,
+    fields: &'a mut Map<String, Value>,
+}
+
+impl JsonVisitor<'_> {
+    fn insert(&mut self, field: &Field, value: Value) {
+        self.fields.insert(field.name().to_string(), value);
+    }
+}
+
+impl Visit for JsonVisitor<'_> {
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.insert(field, value.into());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.insert(field, value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.insert(field, value.into());
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.insert(field, value.into());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message = Some(value.to_string());
+        } else {
+            self.insert(field, value.into());
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        let s = format!("{value:?}");
+        if field.name() == "message" {
+            self.message = Some(s);
+        } else {
+            self.insert(field, s.into());
+        }
+    }
+}
@@ -0,0 +1,230 @@
+//! Low-voltage cutoff: once the battery voltage (packet 22) has stayed
+//! below `low_voltage.cutoff_mv` for `debounce_ms`, so a sag while the
+//! motors pull hard doesn't count, the robot is stopped at once, the
+//! running behavior cancelled, a warning played, and the robot put in its
+//! least power-hungry state: brushes and vacuum off, LEDs off, Passive
+//! mode, or with `low_voltage.power_off` powered down altogether. Until
+//! the voltage is back to `resume_mv` nothing may drive it or start a
+//! behavior, so nothing runs the battery into deep discharge.
+//!
+//! Unlike the battery supervisor it doesn't try to reach the dock: at
+//! this voltage the trip may not make it, and standing still is safer.
+
+use std::sync::Mutex;
+use std::time::Instant;
+
+use serde_json::{json, Value};
+use tracing::{error, info, warn};
+
+use crate::bus::Event;
+use crate::config::LowVoltageConfig;
+use crate::motion::{self, Wheels};
+use crate::oi::{self, SensorFrame};
+use crate::{audit, behavior, song};
+
+const VOLTAGE: u8 = 22;
+const MOTORS: u8 = 138;
+/// PWM Motors: main brush, side brush, then vacuum duty cycles
+const PWM_MOTORS: u8 = 144;
+/// Falling, twice, 1/64 s units
+const WARNING: [song::Note; 5] = [[76, 12], [72, 12], [76, 12], [72, 12], [64, 40]];
+
+struct Cutoff {
+    cfg: LowVoltageConfig,
+    /// Since when the voltage has been below the cutoff
+    below: Option<Instant>,
+    /// Cut off: when, and at what voltage
+    tripped: Option<(Instant, i32)>,
+    /// Powered down after the warning
+    powered_down: bool,
+}
+
+static CONFIG: Mutex<Option<LowVoltageConfig>> = Mutex::new(None);
+static STATE: Mutex<Option<Cutoff>> = Mutex::new(None);
+
+fn state() -> std::sync::MutexGuard<'static, Option<Cutoff>> {
+    STATE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Does nothing unless `low_voltage.enabled`.
+pub fn init(cfg: LowVoltageConfig) {
+    if cfg.enabled() {
+        *CONFIG.lock().unwrap_or_else(|e| e.into_inner()) = Some(cfg);
+    }
+}
+
+pub fn begin() {
+    let cfg = CONFIG.lock().unwrap_or_else(|e| e.into_inner()).clone();
+    *state() = cfg.map(|cfg| Cutoff { cfg, below: None, tripped: None, powered_down: false });
+}
+
+pub fn end() {
+    *state() = None;
+}
+
+/// Check the voltage; events to publish.
+pub fn update(frame: &SensorFrame) -> Vec<Event> {
+    let mut guard = state();
+    let Some(c) = guard.as_mut() else { return Vec::new() };
+    let Some(voltage_mv) = frame.get(VOLTAGE) else { return Vec::new() };
+    if let Some((at, _)) = c.tripped {
+        if voltage_mv >= c.cfg.resume_mv() {
+            c.tripped = None;
+            c.below = None;
+            c.powered_down = false;
+            drop(guard);
+            info!("battery voltage back to {voltage_mv} mV, motion allowed again");
+            audit::record("low_voltage_recovered", "battery voltage", &format!("{voltage_mv} mV"));
+            return vec![Event::LowVoltageRecovered { voltage_mv }];
+        }
+        if c.cfg.power_off() && !c.powered_down && at.elapsed() >= song::length(&WARNING) {
+            c.powered_down = true;
+            warn!("low-voltage cutoff: powering the robot down");
+            motion::queue(vec![oi::POWER]);
+        }
+        return Vec::new();
+    }
+    if voltage_mv >= c.cfg.cutoff_mv() {
+        c.below = None;
+        return Vec::new();
+    }
+    let since = *c.below.get_or_insert_with(Instant::now);
+    if since.elapsed() < c.cfg.debounce() {
+        return Vec::new();
+    }
+    c.tripped = Some((Instant::now(), voltage_mv));
+    let cutoff_mv = c.cfg.cutoff_mv();
+    drop(guard);
+    error!("battery voltage {voltage_mv} mV below {cutoff_mv} mV: cutting off, the robot stays still until it is charged");
+    audit::record("low_voltage_cutoff", "battery voltage", &format!("{voltage_mv} mV below {cutoff_mv} mV: wheels stopped, behavior cancelled, robot put to a low-power state"));
+    motion::halt();
+    motion::discard();
+    behavior::cancel("low-voltage cutoff");
+    if let Err(e) = song::queue_critical("low-voltage warning", WARNING.to_vec()) {
+        warn!("low-voltage cutoff: can't play the warning: {e}");
+    }
+    // Main brush, side brush, and vacuum, then the LEDs, before Passive
+    // mode, where the robot no longer takes them
+    motion::queue(vec![MOTORS, 0]);
+    motion::queue(vec![oi::LEDS, 0, 0, 0]);
+    motion::queue(vec![oi::START]);
+    vec![Event::LowVoltageCutoff { voltage_mv, cutoff_mv }]
+}
+
+fn tripped() -> bool {
+    state().as_ref().is_some_and(|c| c.tripped.is_some())
+}
+
+/// Err while cut off, for whatever would set the robot moving.
+pub fn check() -> Result<(), String> {
+    if tripped() {
+        return Err("the battery voltage is below the cutoff (low_voltage.cutoff_mv); charge the robot first".into());
+    }
+    Ok(())
+}
+
+/// `wheels`, or a stop while cut off.
+pub fn limit(wheels: Wheels) -> Wheels {
+    if tripped() {
+        Wheels::STOP
+    } else {
+        wheels
+    }
+}
+
+/// Whether the raw OI command `bytes` may be sent now: nothing running a
+/// motor (drive commands but a stop, cleaning, Seek Dock, Play Script,
+/// brushes or vacuum on), nor Safe or Full mode, while cut off.
+pub fn allows(bytes: &[u8]) -> bool {
+    let runs = match bytes {
+        _ if oi::moves(bytes) => true,
+        [oi::SAFE | oi::FULL, ..] => true,
+        [MOTORS | PWM_MOTORS, rest @ ..] => rest.iter().any(|b| *b != 0),
+        _ => false,
+    };
+    !runs || !tripped()
+}
+
+pub fn status() -> Value {
+    let guard = state();
+    let Some(c) = guard.as_ref() else { return json!({ "cut_off": false }) };
+    json!({
+        "cut_off": c.tripped.is_some(),
+        "voltage_mv": c.tripped.map(|(_, v)| v),
+        "since_s": c.tripped.map(|(at, _)| at.elapsed().as_secs_f64()),
+        "powered_down": c.powered_down
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::{Duration, SystemTime};
+
+    use super::*;
+
+    const FORWARD: [u8; 5] = [oi::DRIVE_DIRECT, 0, 200, 0, 200];
+
+    fn voltage(mv: i32) -> SensorFrame {
+        let mut frame = SensorFrame::new(SystemTime::now());
+        frame.set(VOLTAGE, mv);
+        frame
+    }
+
+    fn session() -> std::sync::MutexGuard<'static, ()> {
+        let serial = crate::robot::serial();
+        init(LowVoltageConfig { enabled: Some(true), cutoff_mv: Some(12_000), debounce_ms: Some(30), resume_mv: Some(13_500), power_off: Some(false) });
+        begin();
+        serial
+    }
+
+    fn cut_off() -> Vec<Event> {
+        update(&voltage(11_900));
+        thread::sleep(Duration::from_millis(40));
+        update(&voltage(11_900))
+    }
+
+    #[test]
+    fn cuts_off_below_the_cutoff_and_recovers_at_resume() {
+        let _serial = session();
+        assert!(update(&voltage(11_900)).is_empty());
+        assert!(check().is_ok());
+        thread::sleep(Duration::from_millis(40));
+        assert!(matches!(&update(&voltage(11_900))[..], [Event::LowVoltageCutoff { voltage_mv: 11_900, cutoff_mv: 12_000 }]));
+        assert!(check().is_err());
+        assert_eq!(limit(Wheels::new(100, 100)), Wheels::STOP);
+        // Above the cutoff isn't enough
+        assert!(update(&voltage(13_000)).is_empty());
+        assert!(check().is_err());
+        assert!(matches!(&update(&voltage(13_500))[..], [Event::LowVoltageRecovered { voltage_mv: 13_500 }]));
+        assert!(check().is_ok());
+        assert_eq!(limit(Wheels::new(100, 100)), Wheels::new(100, 100));
+        end();
+    }
+
+    #[test]
+    fn a_sag_shorter_than_the_debounce_doesnt_count() {
+        let _serial = session();
+        update(&voltage(11_500));
+        update(&voltage(12_500));
+        thread::sleep(Duration::from_millis(40));
+        assert!(update(&voltage(11_500)).is_empty());
+        assert!(check().is_ok());
+        end();
+    }
+
+    #[test]
+    fn refuses_motors_and_driving_modes_while_cut_off() {
+        let _serial = session();
+        assert!(allows(&FORWARD) && allows(&[oi::SAFE]));
+        cut_off();
+        for refused in [&FORWARD[..], &[oi::SAFE], &[oi::FULL], &[oi::SEEK_DOCK], &[oi::PLAY_SCRIPT], &[MOTORS, 0b100], &[PWM_MOTORS, 0, 0, 50]] {
+            assert!(!allows(refused), "{refused:?}");
+        }
+        for allowed in [&[oi::DRIVE_DIRECT, 0, 0, 0, 0][..], &[MOTORS, 0], &[PWM_MOTORS, 0, 0, 0], &[oi::START], &[oi::LEDS, 0, 0, 0], &[oi::POWER]] {
+            assert!(allows(allowed), "{allowed:?}");
+        }
+        end();
+        assert!(allows(&FORWARD));
+    }
+}
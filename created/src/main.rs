@@ -1,263 +1,453 @@
+mod aliases;
+mod audit;
+mod battery;
+mod beeps;
+mod behavior;
+mod bluetooth;
+mod bumper;
+mod buttons;
+mod bus;
+mod charging_lockout;
+mod cliff;
+mod command;
+mod config;
+mod control;
+mod detect;
+mod discovery;
+mod estop;
+mod export;
+mod flight;
+mod ftdi;
+#[cfg(target_os = "linux")]
+mod gamepad;
+mod geofence;
+mod governor;
+mod hotplug;
+mod influx;
+mod journal;
+#[cfg(target_os = "linux")]
+mod journald;
+mod leds;
+mod lockfile;
+mod logfile;
+mod logging;
+mod low_voltage;
+mod mcap;
+mod mock;
+mod monitor;
+mod morse;
+mod motion;
+mod oi;
+mod overcurrent;
+mod patrol;
+mod pose;
+mod power;
+mod quiet;
+mod recharge;
+mod recorder;
+mod reflex;
+mod release;
+mod replay;
+mod report;
+mod resume;
+mod ring;
+mod robot;
+mod safety;
+mod slip;
+mod sniffer;
+mod song;
+mod stats;
+mod stuck;
+mod tcp;
+mod telemetry;
+mod thermal;
+#[cfg(unix)]
+mod teleop;
+mod timing;
+mod transport;
+#[cfg(unix)]
+mod tui;
+mod wakeup;
+mod wheel_drop;
+
 use std::env;
-use std::fs;
-use std::io::{Read, Write};
-use std::path::{Path, PathBuf};
-use std::thread;
-use std::time::Duration;
+use std::path::PathBuf;
+use std::process;
+use std::sync::mpsc::RecvTimeoutError;
+use std::thread::{self, JoinHandle};
 
-use log::{error, info, warn};
-use serde::Deserialize;
-use serialport::SerialPort;
+use chrono::NaiveDate;
+use tracing::{error, info, warn, Level};
 
-#[derive(Debug, Deserialize, Default, Clone)]
-struct SerialConfig {
-    /// Serial device path (e.g. /dev/ttyUSB0). If not set, autodetects.
-    path: Option<String>,
-    /// Baud rate (default 57600 for Create 1)
-    baud: Option<u32>,
+/// Why `teleop` and `tui` can't run here
+#[cfg(not(unix))]
+const NO_CONTROL_SOCKET: &str = "the control socket is a Unix socket, not available here";
+
+/// Command-line options. The daemon is normally started without arguments.
+#[derive(Debug, Default)]
+struct Args {
+    /// Named config profile (`[profile.<name>]`), overrides `CREATED_PROFILE`
+    profile: Option<String>,
+    command: Command,
 }
 
-#[derive(Debug, Deserialize, Default)]
-struct Config {
-    /// Log interval in milliseconds
-    interval_ms: Option<u64>,
-    /// Optional message to log instead of default
-    message: Option<String>,
-    /// Serial configuration for iRobot Create
-    serial: Option<SerialConfig>,
+#[derive(Debug, Default)]
+enum Command {
+    /// Run the daemon (default)
+    #[default]
+    Daemon,
+    /// Feed a recording through the session pipeline instead of a robot
+    Replay { file: PathBuf, speed: f64, live: Sinks },
+    /// Convert recordings to day-partitioned Parquet files
+    Export { inputs: Vec<PathBuf>, out: PathBuf },
+    /// Print the daily report for a date (default yesterday)
+    Report { date: Option<NaiveDate> },
+    /// Drive the robot from the keyboard through the daemon
+    Teleop,
+    /// Watch and drive the robot from a terminal dashboard through the daemon
+    Tui,
+    /// Print the sensor packet table as Markdown
+    Packets,
 }
 
-impl Config {
-    fn interval(&self) -> Duration {
-        Duration::from_millis(self.interval_ms.unwrap_or(5_000))
-    }
-
-    fn message(&self) -> &str {
-        self.message.as_deref().unwrap_or("hello world")
-    }
+/// Consumers fed as things happen, which a replay leaves out unless asked with `--with`:
+/// they'd take recorded frames and events for the day's.
+#[derive(Debug, Clone, Copy, Default)]
+struct Sinks {
+    journal: bool,
+    influx: bool,
+    telemetry: bool,
 }
 
-fn main() {
-    // Initialize logger (stdout/stderr -> journald when under systemd)
-    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+impl Sinks {
+    const ALL: Sinks = Sinks { journal: true, influx: true, telemetry: true };
 
-    // Handle graceful shutdown on SIGINT/SIGTERM
-    let (tx_main, rx_main) = std::sync::mpsc::channel::<()>();
-    let (tx_robot, rx_robot) = std::sync::mpsc::channel::<()>();
-    if let Err(e) = ctrlc::set_handler(move || {
-        let _ = tx_main.send(());
-        let _ = tx_robot.send(());
-    }) {
-        warn!("failed to set signal handler: {e}");
+    fn add(&mut self, name: &str) -> Result<(), String> {
+        match name {
+            "journal" => self.journal = true,
+            "influx" => self.influx = true,
+            "telemetry" => self.telemetry = true,
+            _ => return Err(format!("unknown sink {name:?}, expected journal, influx, or telemetry")),
+        }
+        Ok(())
     }
+}
 
-    let config = load_config();
-    info!("starting created daemon");
-    info!("config: interval={:?}, message=\"{}\"", config.interval(), config.message());
+fn usage() -> String {
+    format!(
+        "Usage: created [options] [command]\n\
+         Commands:\n  \
+           (none)              Run the daemon\n  \
+           replay <file>       Replay a .csv or .mcap recording through the session pipeline\n  \
+           export <path>...    Convert .csv/.mcap recordings (files or directories) to Parquet\n  \
+           report [YYYY-MM-DD] Print the daily activity report (default: yesterday)\n  \
+           teleop              Drive the robot with the keyboard (through the running daemon)\n  \
+           tui                 Dashboard of sensors, pose, battery, and events, with teleop\n  \
+           packets             Print the sensor packets the daemon decodes, as a Markdown table\n\
+         Options:\n  \
+           --profile <name>    Apply [profile.<name>] from config.toml (env: {})\n  \
+           --speed <factor>    Replay speed, 0 = as fast as possible (default 1)\n  \
+           --out <dir>         Export destination, partitioned by day (default {})\n  \
+           --with <sink>       Replay into journal, influx, or telemetry too (repeatable)\n  \
+           -h, --help          Show this help",
+        config::PROFILE_ENV,
+        export::DEFAULT_OUT
+    )
+}
 
-    // Spawn background thread to handle iRobot Create over serial (plug-and-play)
-    let robot_cfg = config.serial.clone().unwrap_or_default();
-    thread::spawn(move || {
-        robot_worker(rx_robot, robot_cfg);
-    });
+fn usage_error(msg: &str) -> ! {
+    eprintln!("{msg}\n{}", usage());
+    process::exit(2);
+}
 
-    // Main loop
-    loop {
-        if let Ok(_) = rx_main.try_recv() {
-            info!("shutdown signal received; exiting");
-            break;
+fn parse_args() -> Args {
+    let mut args = Args::default();
+    let mut positional = Vec::new();
+    let mut speed = 1.0;
+    let mut out = PathBuf::from(export::DEFAULT_OUT);
+    let mut live = Sinks::default();
+    let mut it = env::args().skip(1);
+    while let Some(arg) = it.next() {
+        match arg.as_str() {
+            "--profile" => match it.next() {
+                Some(name) => args.profile = Some(name),
+                None => usage_error("--profile requires a name"),
+            },
+            "--speed" => match it.next().and_then(|s| s.parse::<f64>().ok()).filter(|s| *s >= 0.0) {
+                Some(s) => speed = s,
+                None => usage_error("--speed requires a non-negative number"),
+            },
+            "--out" => match it.next() {
+                Some(dir) => out = PathBuf::from(dir),
+                None => usage_error("--out requires a directory"),
+            },
+            "--with" => match it.next() {
+                Some(name) => {
+                    if let Err(e) = live.add(&name) {
+                        usage_error(&e);
+                    }
+                }
+                None => usage_error("--with requires a sink name"),
+            },
+            "-h" | "--help" => {
+                println!("{}", usage());
+                process::exit(0);
+            }
+            other => match other.strip_prefix("--profile=") {
+                Some(name) => args.profile = Some(name.to_string()),
+                None if other.starts_with('-') => usage_error(&format!("Unknown arg: {other}")),
+                None => positional.push(other.to_string()),
+            },
         }
-        info!("{}", config.message());
-        thread::sleep(config.interval());
     }
+    args.command = match positional.as_slice() {
+        [] => Command::Daemon,
+        [cmd, file] if cmd == "replay" => Command::Replay { file: PathBuf::from(file), speed, live },
+        [cmd] if cmd == "replay" => usage_error("replay requires a recording file"),
+        [cmd] if cmd == "export" => usage_error("export requires recordings or a recording directory"),
+        [cmd] if cmd == "teleop" => Command::Teleop,
+        [cmd] if cmd == "tui" => Command::Tui,
+        [cmd] if cmd == "packets" => Command::Packets,
+        [cmd] if cmd == "report" => Command::Report { date: None },
+        [cmd, date] if cmd == "report" => match NaiveDate::parse_from_str(date, "%Y-%m-%d") {
+            Ok(d) => Command::Report { date: Some(d) },
+            Err(_) => usage_error(&format!("invalid date {date:?}, expected YYYY-MM-DD")),
+        },
+        [cmd, inputs @ ..] if cmd == "export" => Command::Export { inputs: inputs.iter().map(PathBuf::from).collect(), out },
+        _ => usage_error(&format!("Unknown command: {}", positional.join(" "))),
+    };
+    if args.profile.is_none() {
+        args.profile = env::var(config::PROFILE_ENV).ok().filter(|p| !p.is_empty());
+    }
+    args
 }
 
-fn load_config() -> Config {
-    match find_config_file() {
-        Some(path) => match read_toml::<Config>(&path) {
-            Ok(cfg) => cfg,
+fn main() {
+    let args = parse_args();
+
+    // Config decides the log format, so read it before initializing the logger
+    let (config, diagnostics) = config::load_config(args.profile.as_deref());
+    let config_invalid = diagnostics.iter().any(|(level, _)| *level == Level::ERROR);
+    let log_problem = logging::init(&config);
+    for (level, msg) in diagnostics {
+        match level {
+            Level::ERROR => error!(target: "created::config", "{msg}"),
+            Level::WARN => warn!(target: "created::config", "{msg}"),
+            _ => info!(target: "created::config", "{msg}"),
+        }
+    }
+    if let Some(msg) = log_problem {
+        warn!("{msg}");
+    }
+
+    if let Command::Export { inputs, out } = &args.command {
+        match export::run(inputs, out) {
+            Ok(n) => info!("wrote {n} Parquet files to {}", out.display()),
             Err(e) => {
-                error!("failed to parse config at {}: {e}", path.display());
-                Config { interval_ms: None, message: None, serial: None }
+                error!("export failed: {e}");
+                process::exit(1);
             }
-        },
-        None => {
-            warn!("no config file found; using defaults");
-            Config { interval_ms: None, message: None, serial: None }
         }
+        return;
     }
-}
-
-fn read_toml<T: for<'de> serde::Deserialize<'de>>(path: &Path) -> Result<T, String> {
-    let mut f = fs::File::open(path).map_err(|e| format!("open: {e}"))?;
-    let mut s = String::new();
-    f.read_to_string(&mut s).map_err(|e| format!("read: {e}"))?;
-    toml::from_str(&s).map_err(|e| format!("toml: {e}"))
-}
 
-fn find_config_file() -> Option<PathBuf> {
-    // 1) Explicit path via env var
-    if let Ok(p) = env::var("CREATED_CONFIG") {
-        let pb = PathBuf::from(p);
-        if pb.is_file() {
-            return Some(pb);
+    if let Command::Teleop = &args.command {
+        #[cfg(unix)]
+        let result = teleop::run(&config.control.clone().unwrap_or_default().socket());
+        #[cfg(not(unix))]
+        let result: Result<(), String> = Err(NO_CONTROL_SOCKET.into());
+        if let Err(e) = result {
+            eprintln!("teleop: {e}");
+            process::exit(1);
         }
+        return;
     }
 
-    // 2) XDG config home
-    if let Ok(xdg) = env::var("XDG_CONFIG_HOME") {
-        let p = Path::new(&xdg).join("created").join("config.toml");
-        if p.is_file() {
-            return Some(p);
+    if let Command::Tui = &args.command {
+        #[cfg(unix)]
+        let result = tui::run(&config.control.clone().unwrap_or_default().socket());
+        #[cfg(not(unix))]
+        let result: Result<(), String> = Err(NO_CONTROL_SOCKET.into());
+        if let Err(e) = result {
+            eprintln!("tui: {e}");
+            process::exit(1);
         }
+        return;
     }
 
-    // 3) ~/.config
-    if let Some(home) = dirs_home() {
-        let p = home.join(".config").join("created").join("config.toml");
-        if p.is_file() {
-            return Some(p);
-        }
+    if let Command::Packets = &args.command {
+        print!("{}", oi::packet_docs());
+        return;
     }
 
-    // 4) /etc/created/config.toml
-    let etc = Path::new("/etc").join("created").join("config.toml");
-    if etc.is_file() {
-        return Some(etc);
+    if let Command::Report { date } = &args.command {
+        let date = date.unwrap_or_else(|| chrono::Local::now().date_naive() - chrono::Days::new(1));
+        match report::generate(&config.journal.clone().unwrap_or_default(), date, &config.robot_id()) {
+            Ok(text) => print!("{text}"),
+            Err(e) => {
+                error!("report failed: {e}");
+                process::exit(1);
+            }
+        }
+        return;
     }
 
-    None
-}
+    // Before any thread is started, for them to take its timer slack
+    power::init(config.power.clone().unwrap_or_default());
+    flight::install(config.flight_recorder.clone().unwrap_or_default());
+    sniffer::init(&config.sniffer.clone().unwrap_or_default());
 
-fn dirs_home() -> Option<PathBuf> {
-    if let Ok(home) = env::var("HOME") {
-        return Some(PathBuf::from(home));
+    // Handle graceful shutdown on SIGINT/SIGTERM
+    let (tx_main, rx_main) = std::sync::mpsc::channel::<()>();
+    let (tx_robot, rx_robot) = std::sync::mpsc::channel::<()>();
+    if let Err(e) = ctrlc::set_handler(move || {
+        let _ = tx_main.send(());
+        let _ = tx_robot.send(());
+        wakeup::notify();
+    }) {
+        warn!("failed to set signal handler: {e}");
     }
-    // Fallback for non-standard envs
-    dirs_fallback_home()
-}
 
-#[cfg(unix)]
-fn dirs_fallback_home() -> Option<PathBuf> { None }
-
-#[cfg(not(unix))]
-fn dirs_fallback_home() -> Option<PathBuf> { None }
+    pose::init(config.pose.clone().unwrap_or_default());
+    slip::init(config.pose.clone().unwrap_or_default().slip());
 
-// ---------------- iRobot Create OI handling ----------------
+    if let Command::Replay { file, speed, live } = &args.command {
+        process::exit(run_replay(&config, file, *speed, *live, rx_robot));
+    }
 
-fn robot_worker(rx: std::sync::mpsc::Receiver<()>, serial_cfg: SerialConfig) {
-    let mut last_handled: Option<PathBuf> = None;
-    loop {
-        // Shutdown check with short timeout to keep loop responsive
-        if let Ok(_) = rx.recv_timeout(Duration::from_millis(200)) {
-            info!("robot worker shutdown");
-            return;
+    info!("starting created daemon");
+    // Not for replays: recorded frames would count against the real battery
+    aliases::init(&config.serial.clone().unwrap_or_default());
+    battery::init(config.battery.clone().unwrap_or_default());
+    motion::init(&config.motion.clone().unwrap_or_default());
+    behavior::init(config.behavior.clone().unwrap_or_default());
+    recharge::init(config.recharge.clone().unwrap_or_default());
+    reflex::init(config.reflexes.clone().unwrap_or_default());
+    geofence::init(config.geofence.clone().unwrap_or_default());
+    patrol::init(config.patrol.clone().unwrap_or_default());
+    bumper::init(config.virtual_bumper.clone().unwrap_or_default());
+    cliff::init(config.cliff.clone().unwrap_or_default());
+    wheel_drop::init(config.wheel_drop.clone().unwrap_or_default());
+    charging_lockout::init(config.charging_lockout.clone().unwrap_or_default());
+    low_voltage::init(config.low_voltage.clone().unwrap_or_default());
+    overcurrent::init(config.overcurrent.clone().unwrap_or_default());
+    stuck::init(config.stuck.clone().unwrap_or_default());
+    governor::init(config.governor.clone().unwrap_or_default());
+    thermal::init(config.battery_temperature.clone().unwrap_or_default());
+    quiet::init(config.quiet.clone().unwrap_or_default());
+    audit::init(config.audit.clone().unwrap_or_default());
+    estop::init(config.estop.clone().unwrap_or_default());
+    safety::init(config.safety.clone().unwrap_or_default());
+    song::init(config.songs.clone().unwrap_or_default());
+    beeps::init(config.beeps.clone().unwrap_or_default());
+    if config_invalid {
+        beeps::config_invalid();
+    }
+    leds::init(config.leds.clone().unwrap_or_default());
+    morse::init(config.morse.clone().unwrap_or_default());
+    buttons::init(config.buttons.clone().unwrap_or_default());
+    control::spawn(&config.control.clone().unwrap_or_default());
+    if let Some(gamepad_cfg) = config.gamepad.clone().filter(|c| c.enabled()) {
+        #[cfg(target_os = "linux")]
+        if let Err(e) = gamepad::spawn(gamepad_cfg) {
+            error!("gamepad: {e}");
         }
+        #[cfg(not(target_os = "linux"))]
+        warn!("gamepad: evdev input devices are Linux's; {} is ignored", gamepad_cfg.device.as_deref().unwrap_or("the gamepad"));
+    }
+    info!("config: interval={:?}, message=\"{}\"", config.interval(), config.message());
 
-        // Reset last_handled if it disappeared
-        if let Some(ref p) = last_handled {
-            if !p.exists() {
-                last_handled = None;
-            }
+    let mut bus = bus::Bus::default();
+    let mut sink_threads = spawn_sinks(&config, Sinks::ALL, &mut bus);
+    // For the control socket's `sensors` and `events`
+    if config.control.clone().unwrap_or_default().enabled() {
+        sink_threads.push(monitor::spawn(&mut bus));
+    }
+    control::attach(Some(bus.clone()));
+    estop::attach(Some(bus.clone()));
+    let journal_cfg = config.journal.clone().unwrap_or_default();
+    if let Some(report_cfg) = config.report.clone().filter(|c| c.enabled()) {
+        if journal_cfg.enabled() {
+            report::spawn(report_cfg, journal_cfg, config.robot_id());
+        } else {
+            warn!("daily reports need the event journal; enable [journal]");
         }
+    }
 
-        match pick_serial_port(&serial_cfg) {
-            Some(port_path) => {
-                // Only act when a new device shows up or if we haven't handled any
-                let should_handle = match &last_handled {
-                    Some(prev) => prev != &port_path,
-                    None => true,
-                };
-                if should_handle {
-                    let baud = serial_cfg.baud.unwrap_or(57_600);
-                    match connect_and_act(&port_path, baud) {
-                        Ok(()) => {
-                            info!("handled robot on {}", port_path.display());
-                            last_handled = Some(port_path);
-                        }
-                        Err(e) => {
-                            warn!("failed to handle robot on {}: {}", port_path.display(), e);
-                        }
-                    }
-                }
-            }
-            None => {
-                // No candidate device found right now
-            }
-        }
+    // Spawn background thread to handle iRobot Create over serial (plug-and-play)
+    let robot_cfg = config.serial.clone().unwrap_or_default();
+    let sensors_cfg = config.sensors.clone().unwrap_or_default();
+    let robot = thread::spawn(move || {
+        robot::robot_worker(rx_robot, robot_cfg, sensors_cfg, bus);
+    });
 
-        // Avoid busy loop
-        thread::sleep(Duration::from_secs(2));
+    // Main loop: the message every interval, until the shutdown signal ends the wait
+    loop {
+        info!("{}", config.message());
+        power::sample();
+        match rx_main.recv_timeout(config.interval()) {
+            Ok(()) => break,
+            Err(RecvTimeoutError::Timeout) => {}
+            // No signal handler to wait for
+            Err(RecvTimeoutError::Disconnected) => thread::sleep(config.interval()),
+        }
+    }
+    info!("shutdown signal received; exiting");
+
+    // Let the robot session wind down, then the consumers drain and flush
+    let _ = robot.join();
+    control::attach(None);
+    estop::attach(None);
+    for handle in sink_threads {
+        let _ = handle.join();
     }
 }
 
-fn pick_serial_port(cfg: &SerialConfig) -> Option<PathBuf> {
-    // 1) Configured path
-    if let Some(ref p) = cfg.path {
-        let pb = PathBuf::from(p);
-        if pb.exists() { return Some(pb); }
+/// Start the configured consumers of sensor frames, commands, and events: the recorders,
+/// and those of `live` that are enabled.
+fn spawn_sinks(config: &config::Config, live: Sinks, bus: &mut bus::Bus) -> Vec<JoinHandle<()>> {
+    let mut threads = Vec::new();
+    let record = config.record.clone().unwrap_or_default();
+    if let Some(csv) = record.csv.filter(|c| c.enabled()) {
+        threads.push(recorder::spawn(csv, bus));
     }
-    // 2) Our udev-provided symlinks
-    if let Ok(entries) = fs::read_dir("/dev/serial") {
-        for e in entries.flatten() {
-            let p = e.path();
-            if let Some(name) = p.file_name().and_then(|s| s.to_str()) {
-                if name.starts_with("by-irobot-") {
-                    if p.exists() { return Some(p); }
-                }
-            }
-        }
+    if let Some(mcap) = record.mcap.filter(|c| c.enabled()) {
+        threads.push(mcap::spawn(mcap, bus));
     }
-    // 3) /dev/serial/by-id/* is the most stable symlink location
-    if let Ok(entries) = fs::read_dir("/dev/serial/by-id") {
-        for e in entries.flatten() {
-            let p = e.path();
-            if p.exists() { return Some(p); }
-        }
+    if let Some(influx) = config.influx.clone().filter(|c| live.influx && c.enabled()) {
+        threads.push(influx::spawn(influx, config.robot_id(), bus));
     }
-    // 4) Fallback to ttyUSB* and ttyACM*
-    let mut candidates: Vec<PathBuf> = Vec::new();
-    if let Ok(entries) = fs::read_dir("/dev") {
-        for e in entries.flatten() {
-            let name = e.file_name();
-            if let Some(s) = name.to_str() {
-                if s.starts_with("ttyUSB") || s.starts_with("ttyACM") {
-                    candidates.push(e.path());
-                }
-            }
-        }
+    if let Some(telemetry) = config.telemetry.clone().filter(|c| live.telemetry && c.enabled()) {
+        threads.extend(telemetry::spawn(telemetry, bus));
     }
-    candidates.sort();
-    candidates.into_iter().next()
-}
-
-fn connect_and_act(port_path: &Path, baud: u32) -> Result<(), String> {
-    info!("connecting to {} at {} baud", port_path.display(), baud);
-    let mut port = serialport::new(port_path.to_string_lossy(), baud)
-        .timeout(Duration::from_millis(500))
-        .open()
-        .map_err(|e| format!("open serial: {e}"))?;
-
-    // iRobot Create OI minimal sequence: Start (128), define song (140), play (141), power (133)
-    // Define a tiny 3-note song (C4, E4, G4)
-    send_bytes(&mut *port, &[128])?; // Start
-    thread::sleep(Duration::from_millis(50));
-
-    // Song definition: [140, song_number, length, note, duration, ...]
-    let song: [u8; 9] = [140, 0, 3, 60, 16, 64, 16, 67, 24];
-    send_bytes(&mut *port, &song)?;
-    thread::sleep(Duration::from_millis(20));
-
-    // Play song 0
-    send_bytes(&mut *port, &[141, 0])?;
-    thread::sleep(Duration::from_millis(1500));
-
-    // Power down (sleep)
-    send_bytes(&mut *port, &[133])?;
-    Ok(())
+    if let Some(journal) = config.journal.clone().filter(|c| live.journal && c.enabled()) {
+        threads.push(journal::spawn(journal, config.robot_id(), bus));
+    }
+    threads
 }
 
-fn send_bytes(port: &mut dyn SerialPort, data: &[u8]) -> Result<(), String> {
-    port.write_all(data).map_err(|e| format!("write: {e}"))?;
-    port.flush().map_err(|e| format!("flush: {e}"))
+/// `created replay`: returns the process exit code.
+fn run_replay(config: &config::Config, file: &std::path::Path, speed: f64, live: Sinks, rx: std::sync::mpsc::Receiver<()>) -> i32 {
+    let frames = match replay::load(file) {
+        Ok(frames) => frames,
+        Err(e) => {
+            error!("cannot replay {}: {e}", file.display());
+            return 1;
+        }
+    };
+    let mut bus = bus::Bus::default();
+    let sink_threads = spawn_sinks(config, live, &mut bus);
+    let stream = config.sensors.clone().unwrap_or_default().stream();
+    let result = replay::run(file, frames, speed, &stream, &bus, &rx);
+    drop(bus);
+    for handle in sink_threads {
+        let _ = handle.join();
+    }
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            error!("replay failed: {e}");
+            1
+        }
+    }
 }
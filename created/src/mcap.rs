@@ -0,0 +1,406 @@
+//! MCAP recorder: sensor frames, commands, daemon events, and pose estimates
+//! as JSON messages on `/sensors`, `/commands`, `/events`, and `/pose`,
+//! readable by Foxglove Studio and the `mcap` CLI.
+//!
+//! Files are unchunked: records are appended as they arrive and a summary
+//! section (schemas, channels, statistics) is written when a file is closed.
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::mpsc::RecvTimeoutError;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use chrono::Utc;
+use serde_json::json;
+use tracing::{info, warn};
+
+use crate::bus::{Bus, Message, Receiver, Sampling};
+use crate::config::McapConfig;
+use crate::oi;
+use crate::recorder;
+
+const MAGIC: &[u8; 8] = b"\x89MCAP0\r\n";
+const QUEUE_MESSAGES: usize = 1024;
+const FILE_PREFIX: &str = "telemetry-";
+
+const OP_HEADER: u8 = 0x01;
+const OP_FOOTER: u8 = 0x02;
+const OP_SCHEMA: u8 = 0x03;
+const OP_CHANNEL: u8 = 0x04;
+const OP_MESSAGE: u8 = 0x05;
+const OP_STATISTICS: u8 = 0x0B;
+const OP_SUMMARY_OFFSET: u8 = 0x0E;
+const OP_DATA_END: u8 = 0x0F;
+
+const CH_SENSORS: u16 = 1;
+const CH_COMMANDS: u16 = 2;
+const CH_EVENTS: u16 = 3;
+const CH_POSE: u16 = 4;
+
+/// Subscribe to the bus and start the recorder thread. It exits (finishing the
+/// current file) once every publisher has been dropped.
+pub fn spawn(cfg: McapConfig, bus: &mut Bus) -> JoinHandle<()> {
+    let sampling = Sampling::new("mcap recorder", cfg.rate_hz, cfg.fields.as_deref());
+    let rx = bus.subscribe("mcap recorder", QUEUE_MESSAGES, sampling);
+    thread::spawn(move || run(cfg, rx))
+}
+
+fn run(cfg: McapConfig, rx: Receiver) {
+    info!("recording MCAP to {}", cfg.dir().display());
+    let mut current: Option<(McapWriter<BufWriter<File>>, Instant)> = None;
+    loop {
+        let msg = match rx.recv_timeout(Duration::from_secs(1)) {
+            Ok(msg) => Some(msg),
+            Err(RecvTimeoutError::Timeout) => None,
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+        if let Some(msg) = msg {
+            if current.as_ref().is_some_and(|(_, opened)| opened.elapsed() >= cfg.rotate_after()) {
+                finish(current.take());
+            }
+            if current.is_none() {
+                match open(&cfg) {
+                    Ok(w) => current = Some((w, Instant::now())),
+                    Err(e) => warn!("mcap recorder: {e}"),
+                }
+            }
+            if let Some((w, _)) = current.as_mut() {
+                if let Err(e) = write_message(w, &msg) {
+                    warn!("mcap recorder: write: {e}");
+                    current = None;
+                }
+            }
+        }
+        if let Some((w, _)) = current.as_mut() {
+            let _ = w.out.flush();
+        }
+    }
+    finish(current.take());
+}
+
+fn open(cfg: &McapConfig) -> Result<McapWriter<BufWriter<File>>, String> {
+    let dir = cfg.dir();
+    fs::create_dir_all(&dir).map_err(|e| format!("create {}: {e}", dir.display()))?;
+    let stamp = Utc::now().format("%Y%m%dT%H%M%S%.3fZ");
+    let path: PathBuf = dir.join(format!("{FILE_PREFIX}{stamp}.mcap"));
+    let file = File::create(&path).map_err(|e| format!("create {}: {e}", path.display()))?;
+    let mut w = McapWriter::new(BufWriter::new(file)).map_err(|e| format!("write {}: {e}", path.display()))?;
+    w.add_channel(CH_SENSORS, "/sensors", "created.SensorFrame", &sensor_schema())
+        .and_then(|()| w.add_channel(CH_COMMANDS, "/commands", "created.Command", &command_schema()))
+        .and_then(|()| w.add_channel(CH_EVENTS, "/events", "created.Event", &event_schema()))
+        .and_then(|()| w.add_channel(CH_POSE, "/pose", "created.Pose", &pose_schema()))
+        .map_err(|e| format!("write {}: {e}", path.display()))?;
+    info!("mcap recorder: writing {}", path.display());
+    recorder::prune(&dir, FILE_PREFIX, ".mcap", cfg.max_files());
+    Ok(w)
+}
+
+fn finish(current: Option<(McapWriter<BufWriter<File>>, Instant)>) {
+    if let Some((w, _)) = current {
+        if let Err(e) = w.finish() {
+            warn!("mcap recorder: failed to finish file: {e}");
+        }
+    }
+}
+
+fn write_message(w: &mut McapWriter<BufWriter<File>>, msg: &Message) -> io::Result<()> {
+    let (channel, time, body) = match msg {
+        Message::Sensors(frame) => (CH_SENSORS, frame.time, serde_json::to_vec(&**frame)?),
+        Message::Command { time, bytes } => {
+            (CH_COMMANDS, *time, serde_json::to_vec(&json!({ "opcode": bytes.first(), "bytes": bytes }))?)
+        }
+        Message::Event { time, event } => (CH_EVENTS, *time, serde_json::to_vec(event)?),
+        Message::Pose(pose) => (CH_POSE, pose.time, serde_json::to_vec(pose)?),
+    };
+    w.message(channel, time, &body)
+}
+
+fn sensor_schema() -> serde_json::Value {
+    let properties: serde_json::Map<String, serde_json::Value> =
+        oi::PACKETS.iter().map(|p| (p.name.to_string(), json!({ "type": "integer" }))).collect();
+    json!({ "type": "object", "properties": properties })
+}
+
+fn command_schema() -> serde_json::Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "opcode": { "type": "integer" },
+            "bytes": { "type": "array", "items": { "type": "integer" } }
+        }
+    })
+}
+
+fn event_schema() -> serde_json::Value {
+    json!({ "type": "object", "properties": { "type": { "type": "string" } } })
+}
+
+fn pose_schema() -> serde_json::Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "x": { "type": "number" },
+            "y": { "type": "number" },
+            "theta": { "type": "number" },
+            "confidence": { "type": "number" }
+        }
+    })
+}
+
+/// Minimal MCAP writer for JSON-encoded channels, each with its own schema.
+struct McapWriter<W: Write> {
+    out: W,
+    pos: u64,
+    /// (channel id, topic, schema name, schema JSON)
+    channels: Vec<(u16, String, String, Vec<u8>)>,
+    message_counts: Vec<(u16, u64)>,
+    sequence: u32,
+    start_time: u64,
+    end_time: u64,
+    /// The CRC of the summary section so far, once it is being written
+    summary_crc: Option<crc32fast::Hasher>,
+}
+
+impl<W: Write> McapWriter<W> {
+    fn new(out: W) -> io::Result<Self> {
+        let mut w = McapWriter {
+            out,
+            pos: 0,
+            channels: Vec::new(),
+            message_counts: Vec::new(),
+            sequence: 0,
+            start_time: 0,
+            end_time: 0,
+            summary_crc: None,
+        };
+        w.raw(MAGIC)?;
+        let mut body = Vec::new();
+        put_str(&mut body, "");
+        put_str(&mut body, concat!("created ", env!("CARGO_PKG_VERSION")));
+        w.record(OP_HEADER, &body)?;
+        Ok(w)
+    }
+
+    /// Register a channel and its schema (the schema shares the channel's id).
+    fn add_channel(&mut self, id: u16, topic: &str, schema_name: &str, schema: &serde_json::Value) -> io::Result<()> {
+        let schema = serde_json::to_vec(schema)?;
+        self.write_schema(id, schema_name, &schema)?;
+        self.write_channel(id, topic)?;
+        self.channels.push((id, topic.to_string(), schema_name.to_string(), schema));
+        self.message_counts.push((id, 0));
+        Ok(())
+    }
+
+    fn write_schema(&mut self, id: u16, schema_name: &str, schema: &[u8]) -> io::Result<()> {
+        let mut body = Vec::new();
+        body.extend_from_slice(&id.to_le_bytes());
+        put_str(&mut body, schema_name);
+        put_str(&mut body, "jsonschema");
+        put_bytes(&mut body, schema);
+        self.record(OP_SCHEMA, &body)
+    }
+
+    fn write_channel(&mut self, id: u16, topic: &str) -> io::Result<()> {
+        let mut body = Vec::new();
+        body.extend_from_slice(&id.to_le_bytes());
+        body.extend_from_slice(&id.to_le_bytes());
+        put_str(&mut body, topic);
+        put_str(&mut body, "json");
+        body.extend_from_slice(&0u32.to_le_bytes()); // empty metadata map
+        self.record(OP_CHANNEL, &body)
+    }
+
+    fn message(&mut self, channel: u16, time: SystemTime, data: &[u8]) -> io::Result<()> {
+        let ns = time.duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or(0);
+        let mut body = Vec::with_capacity(22 + data.len());
+        body.extend_from_slice(&channel.to_le_bytes());
+        body.extend_from_slice(&self.sequence.to_le_bytes());
+        body.extend_from_slice(&ns.to_le_bytes());
+        body.extend_from_slice(&ns.to_le_bytes());
+        body.extend_from_slice(data);
+        self.record(OP_MESSAGE, &body)?;
+
+        self.sequence = self.sequence.wrapping_add(1);
+        if self.start_time == 0 || ns < self.start_time {
+            self.start_time = ns;
+        }
+        self.end_time = self.end_time.max(ns);
+        if let Some((_, n)) = self.message_counts.iter_mut().find(|(id, _)| *id == channel) {
+            *n += 1;
+        }
+        Ok(())
+    }
+
+    /// Write the data end record, the summary section, and the footer.
+    fn finish(mut self) -> io::Result<()> {
+        self.record(OP_DATA_END, &0u32.to_le_bytes())?;
+
+        let summary_start = self.pos;
+        self.summary_crc = Some(crc32fast::Hasher::new());
+        let mut offsets = Vec::new();
+        let channels = std::mem::take(&mut self.channels);
+        let schemas_start = self.pos;
+        for (id, _, name, schema) in &channels {
+            self.write_schema(*id, name, schema)?;
+        }
+        offsets.push((OP_SCHEMA, schemas_start, self.pos - schemas_start));
+        let channels_start = self.pos;
+        for (id, topic, _, _) in &channels {
+            self.write_channel(*id, topic)?;
+        }
+        offsets.push((OP_CHANNEL, channels_start, self.pos - channels_start));
+
+        let stats_start = self.pos;
+        let mut body = Vec::new();
+        let total: u64 = self.message_counts.iter().map(|(_, n)| n).sum();
+        body.extend_from_slice(&total.to_le_bytes());
+        body.extend_from_slice(&(self.message_counts.len() as u16).to_le_bytes());
+        body.extend_from_slice(&(self.message_counts.len() as u32).to_le_bytes());
+        body.extend_from_slice(&0u32.to_le_bytes()); // attachments
+        body.extend_from_slice(&0u32.to_le_bytes()); // metadata
+        body.extend_from_slice(&0u32.to_le_bytes()); // chunks
+        body.extend_from_slice(&self.start_time.to_le_bytes());
+        body.extend_from_slice(&self.end_time.to_le_bytes());
+        body.extend_from_slice(&((self.message_counts.len() * 10) as u32).to_le_bytes());
+        for (id, n) in &self.message_counts {
+            body.extend_from_slice(&id.to_le_bytes());
+            body.extend_from_slice(&n.to_le_bytes());
+        }
+        self.record(OP_STATISTICS, &body)?;
+        offsets.push((OP_STATISTICS, stats_start, self.pos - stats_start));
+
+        let summary_offset_start = self.pos;
+        for (op, start, len) in offsets {
+            let mut body = vec![op];
+            body.extend_from_slice(&start.to_le_bytes());
+            body.extend_from_slice(&len.to_le_bytes());
+            self.record(OP_SUMMARY_OFFSET, &body)?;
+        }
+
+        // The CRC covers the summary through the footer's summary offset
+        // start, so the footer goes out in two parts
+        let mut body = Vec::new();
+        body.extend_from_slice(&summary_start.to_le_bytes());
+        body.extend_from_slice(&summary_offset_start.to_le_bytes());
+        self.raw(&[OP_FOOTER])?;
+        self.raw(&(body.len() as u64 + 4).to_le_bytes())?;
+        self.raw(&body)?;
+        let crc = self.summary_crc.take().map_or(0, |h| h.finalize());
+        self.raw(&crc.to_le_bytes())?;
+        self.raw(MAGIC)?;
+        self.out.flush()
+    }
+
+    fn record(&mut self, op: u8, body: &[u8]) -> io::Result<()> {
+        self.raw(&[op])?;
+        self.raw(&(body.len() as u64).to_le_bytes())?;
+        self.raw(body)
+    }
+
+    fn raw(&mut self, data: &[u8]) -> io::Result<()> {
+        self.out.write_all(data)?;
+        if let Some(crc) = self.summary_crc.as_mut() {
+            crc.update(data);
+        }
+        self.pos += data.len() as u64;
+        Ok(())
+    }
+}
+
+/// Read every `(topic, log_time, data)` message from an MCAP file written by
+/// this module (unchunked). Reading stops at the data end record, or at the
+/// first truncated record of a file that was never finished.
+pub fn read_messages(data: &[u8]) -> Result<Vec<(String, SystemTime, Vec<u8>)>, String> {
+    if data.len() < MAGIC.len() || &data[..MAGIC.len()] != MAGIC {
+        return Err("not an MCAP file".into());
+    }
+    let mut topics: Vec<(u16, String)> = Vec::new();
+    let mut messages = Vec::new();
+    let mut rest = &data[MAGIC.len()..];
+    while rest.len() >= 9 {
+        let op = rest[0];
+        let len = u64::from_le_bytes(rest[1..9].try_into().expect("8 bytes")) as usize;
+        let Some(body) = rest.get(9..9 + len) else { break };
+        rest = &rest[9 + len..];
+        match op {
+            OP_CHANNEL if body.len() >= 8 => {
+                let id = u16::from_le_bytes([body[0], body[1]]);
+                let topic_len = u32::from_le_bytes(body[4..8].try_into().expect("4 bytes")) as usize;
+                let topic = body.get(8..8 + topic_len).ok_or("truncated channel record")?;
+                topics.push((id, String::from_utf8_lossy(topic).into_owned()));
+            }
+            OP_MESSAGE if body.len() >= 22 => {
+                let channel = u16::from_le_bytes([body[0], body[1]]);
+                let ns = u64::from_le_bytes(body[6..14].try_into().expect("8 bytes"));
+                if let Some((_, topic)) = topics.iter().find(|(id, _)| *id == channel) {
+                    messages.push((topic.clone(), UNIX_EPOCH + Duration::from_nanos(ns), body[22..].to_vec()));
+                }
+            }
+            OP_DATA_END | OP_FOOTER => break,
+            _ => {}
+        }
+    }
+    Ok(messages)
+}
+
+fn put_str(buf: &mut Vec<u8>, s: &str) {
+    put_bytes(buf, s.as_bytes());
+}
+
+fn put_bytes(buf: &mut Vec<u8>, b: &[u8]) {
+    buf.extend_from_slice(&(b.len() as u32).to_le_bytes());
+    buf.extend_from_slice(b);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The (opcode, body) records that make up `rest`.
+    fn records(mut rest: &[u8]) -> Vec<(u8, &[u8])> {
+        let mut records = Vec::new();
+        while !rest.is_empty() {
+            let len = u64::from_le_bytes(rest[1..9].try_into().unwrap()) as usize;
+            records.push((rest[0], &rest[9..9 + len]));
+            rest = &rest[9 + len..];
+        }
+        records
+    }
+
+    #[test]
+    fn writes_a_summary_with_a_group_per_record_type() {
+        let mut data = Vec::new();
+        let mut w = McapWriter::new(&mut data).unwrap();
+        w.add_channel(CH_SENSORS, "/sensors", "created.SensorFrame", &sensor_schema()).unwrap();
+        w.add_channel(CH_POSE, "/pose", "created.Pose", &pose_schema()).unwrap();
+        w.message(CH_POSE, UNIX_EPOCH + Duration::from_secs(1), b"{\"x\":1}").unwrap();
+        w.finish().unwrap();
+        let end = data.len() - MAGIC.len();
+        assert_eq!(&data[end..], MAGIC);
+
+        let (op, footer) = *records(&data[MAGIC.len()..end]).last().unwrap();
+        assert_eq!(op, OP_FOOTER);
+        let summary_start = u64::from_le_bytes(footer[0..8].try_into().unwrap()) as usize;
+        let offsets_start = u64::from_le_bytes(footer[8..16].try_into().unwrap()) as usize;
+        let crc = u32::from_le_bytes(footer[16..20].try_into().unwrap());
+        let footer_start = end - 9 - footer.len();
+        assert_eq!(crc, crc32fast::hash(&data[summary_start..footer_start + 9 + 16]));
+
+        let mut groups = Vec::new();
+        for (op, body) in records(&data[offsets_start..end]) {
+            if op != OP_SUMMARY_OFFSET {
+                continue;
+            }
+            let start = u64::from_le_bytes(body[1..9].try_into().unwrap()) as usize;
+            let len = u64::from_le_bytes(body[9..17].try_into().unwrap()) as usize;
+            let ops: Vec<u8> = records(&data[start..start + len]).iter().map(|(op, _)| *op).collect();
+            groups.push((body[0], ops));
+        }
+        assert_eq!(groups, [(OP_SCHEMA, vec![OP_SCHEMA; 2]), (OP_CHANNEL, vec![OP_CHANNEL; 2]), (OP_STATISTICS, vec![OP_STATISTICS])]);
+
+        let messages = read_messages(&data).unwrap();
+        assert_eq!(messages, [("/pose".to_string(), UNIX_EPOCH + Duration::from_secs(1), b"{\"x\":1}".to_vec())]);
+    }
+}
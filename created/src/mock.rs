@@ -0,0 +1,270 @@
+//! A simulated robot, `serial.path = "mock://"`: a Create 2 on an open
+//! floor with its battery half full. It answers the probe and sensor
+//! queries, keeps its OI mode, streams the packets asked for every 15 ms,
+//! and drives as the wheel speeds sent say, its encoders, distance, and
+//! angle following; it never bumps into anything. For trying the daemon, a
+//! behavior, or a client without a robot at hand.
+
+use std::f64::consts::PI;
+use std::io::{self, ErrorKind, Read, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::command;
+use crate::oi;
+use crate::transport::Transport;
+
+/// The scheme of a `serial.path` naming the simulated robot
+pub const SCHEME: &str = "mock://";
+/// How often a Create streams
+const PERIOD: Duration = Duration::from_millis(15);
+/// How long reads wait with nothing to read, as a serial port's do
+const TIMEOUT: Duration = Duration::from_millis(500);
+/// A Create 2's wheels: how far apart, and how far a wheel goes per encoder count
+const WHEEL_BASE_MM: f64 = 235.0;
+const MM_PER_COUNT: f64 = PI * 72.0 / 508.8;
+
+/// The simulated robot, as the port to it.
+pub struct Robot {
+    mode: i32,
+    /// The packets asked to be streamed; kept while the stream is paused
+    stream: Vec<u8>,
+    streaming: bool,
+    /// When the next stream frame is due
+    due: Instant,
+    /// Wheel speeds, mm/s
+    left: i16,
+    right: i16,
+    /// Velocity and radius as last sent with drive, for packets 39 and 40
+    drive: (i16, i16),
+    moved: Instant,
+    /// Encoder counts, left and right
+    counts: [f64; 2],
+    /// mm driven and degrees turned since they were last read
+    distance: f64,
+    angle: f64,
+    /// Bytes written, short of a whole command
+    input: Vec<u8>,
+    /// Bytes for the daemon to read
+    output: Vec<u8>,
+}
+
+impl Robot {
+    pub fn new() -> Robot {
+        let now = Instant::now();
+        Robot {
+            mode: 0,
+            stream: Vec::new(),
+            streaming: false,
+            due: now,
+            left: 0,
+            right: 0,
+            drive: (0, 0),
+            moved: now,
+            counts: [0.0; 2],
+            distance: 0.0,
+            angle: 0.0,
+            input: Vec::new(),
+            output: Vec::new(),
+        }
+    }
+
+    /// Drive on at the wheel speeds until now.
+    fn advance(&mut self) {
+        let dt = self.moved.elapsed().as_secs_f64();
+        self.moved = Instant::now();
+        let (left, right) = (self.left as f64 * dt, self.right as f64 * dt);
+        self.counts[0] += left / MM_PER_COUNT;
+        self.counts[1] += right / MM_PER_COUNT;
+        self.distance += (left + right) / 2.0;
+        self.angle += ((right - left) / WHEEL_BASE_MM).to_degrees();
+    }
+
+    fn set_wheels(&mut self, left: i16, right: i16) {
+        self.advance();
+        self.left = left;
+        self.right = right;
+    }
+
+    /// The value of packet `id`, reading (and so clearing) distance and angle.
+    fn value(&mut self, id: u8) -> i32 {
+        let driving = self.left != 0 || self.right != 0;
+        match id {
+            19 => take(&mut self.distance),
+            20 => take(&mut self.angle),
+            22 => 16_000,
+            23 if driving => -450,
+            23 => -180,
+            24 => 28,
+            25 => 1_350,
+            26 => 2_696,
+            // An open floor under every cliff sensor
+            28..=31 => 2_000,
+            35 => self.mode,
+            38 => self.stream.len() as i32,
+            39 => self.drive.0 as i32,
+            40 => self.drive.1 as i32,
+            41 => self.right as i32,
+            42 => self.left as i32,
+            43 => self.counts[0] as i64 as u16 as i32,
+            44 => self.counts[1] as i64 as u16 as i32,
+            54 | 55 if driving => 60,
+            // Moving forward or turning as told
+            58 if driving => 1,
+            _ => 0,
+        }
+    }
+
+    /// The data of packet `id`, a group's members one after another.
+    fn packet(&mut self, id: u8, out: &mut Vec<u8>) {
+        for p in oi::expand(id).unwrap_or_default() {
+            let value = self.value(p.id);
+            p.format.encode(value, out);
+        }
+    }
+
+    /// The next stream frame, `[19, n, id, data..., checksum]`.
+    fn frame(&mut self) {
+        self.advance();
+        let mut body = Vec::new();
+        for id in self.stream.clone() {
+            if oi::expand(id).is_some() {
+                body.push(id);
+                self.packet(id, &mut body);
+            }
+        }
+        let mut frame = vec![oi::STREAM_HEADER, body.len() as u8];
+        frame.extend_from_slice(&body);
+        let sum = frame.iter().fold(0u8, |acc, &b| acc.wrapping_add(b));
+        frame.push(sum.wrapping_neg());
+        self.output.extend_from_slice(&frame);
+    }
+
+    /// Act on one command as a Create would.
+    fn command(&mut self, command: &[u8]) {
+        let word = |i: usize| i16::from_be_bytes([command[i], command[i + 1]]);
+        match command[0] {
+            oi::START => self.mode = oi::MODE_PASSIVE,
+            130 | oi::SAFE if self.mode != 0 => self.mode = oi::MODE_SAFE,
+            oi::FULL if self.mode != 0 => self.mode = oi::MODE_FULL,
+            oi::POWER | 173 => {
+                self.mode = 0;
+                self.streaming = false;
+            }
+            // Cleaning and docking hand the robot back to passive mode
+            oi::SPOT | oi::CLEAN | oi::MAX | oi::SEEK_DOCK if self.mode != 0 => self.mode = oi::MODE_PASSIVE,
+            oi::DRIVE_DIRECT if self.mode >= oi::MODE_SAFE => {
+                self.drive = (0, 0);
+                self.set_wheels(word(3), word(1));
+            }
+            oi::DRIVE if self.mode >= oi::MODE_SAFE => {
+                let (velocity, radius) = (word(1), word(3));
+                self.drive = (velocity, radius);
+                let (left, right) = wheels(velocity, radius);
+                self.set_wheels(left, right);
+            }
+            oi::SENSORS => self.query(&command[1..2]),
+            oi::QUERY_LIST => self.query(&command[2..]),
+            oi::STREAM => {
+                self.stream = command[2..].to_vec();
+                self.streaming = true;
+                self.due = Instant::now();
+            }
+            oi::PAUSE_RESUME_STREAM => self.streaming = command[1] == 1,
+            _ => {}
+        }
+        // Out of safe and full mode the wheels stop
+        if self.mode < oi::MODE_SAFE {
+            self.set_wheels(0, 0);
+        }
+    }
+
+    fn query(&mut self, ids: &[u8]) {
+        self.advance();
+        let mut reply = Vec::new();
+        for &id in ids {
+            self.packet(id, &mut reply);
+        }
+        self.output.extend_from_slice(&reply);
+    }
+}
+
+/// The wheel speeds of drive's velocity and turn radius, its special radii
+/// for straight ahead and turning in place included.
+fn wheels(velocity: i16, radius: i16) -> (i16, i16) {
+    match radius {
+        i16::MIN | i16::MAX | 0 => (velocity, velocity),
+        1 => (-velocity, velocity),
+        -1 => (velocity, -velocity),
+        r => {
+            let (v, r, half) = (velocity as f64, r as f64, WHEEL_BASE_MM / 2.0);
+            ((v * (r - half) / r) as i16, (v * (r + half) / r) as i16)
+        }
+    }
+}
+
+/// The whole mm or degrees of `so_far`, leaving the fraction for next time.
+fn take(so_far: &mut f64) -> i32 {
+    let whole = so_far.trunc().clamp(i16::MIN as f64, i16::MAX as f64);
+    *so_far -= whole;
+    whole as i32
+}
+
+impl Read for Robot {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let deadline = Instant::now() + TIMEOUT;
+        while self.output.is_empty() {
+            let now = Instant::now();
+            if self.streaming && !self.stream.is_empty() {
+                thread::sleep(self.due.saturating_duration_since(now));
+                self.frame();
+                // Read late, the next frame is a period on rather than at once
+                self.due = (self.due + PERIOD).max(Instant::now());
+            } else if now < deadline {
+                thread::sleep(deadline - now);
+            } else {
+                return Err(io::Error::new(ErrorKind::TimedOut, "simulated robot has nothing to say"));
+            }
+        }
+        let n = buf.len().min(self.output.len());
+        buf[..n].copy_from_slice(&self.output[..n]);
+        self.output.drain(..n);
+        Ok(n)
+    }
+}
+
+impl Write for Robot {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.input.extend_from_slice(buf);
+        loop {
+            match self.input.first() {
+                None => break,
+                // Not a command, as the OI ignores it
+                Some(&code) if !command::is_opcode(code) => {
+                    self.input.remove(0);
+                }
+                Some(_) => {
+                    let Some(n) = command::command_length(&self.input) else { break };
+                    let command: Vec<u8> = self.input.drain(..n).collect();
+                    self.command(&command);
+                }
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Transport for Robot {
+    fn kind(&self) -> &'static str {
+        "mock"
+    }
+
+    /// Nothing between it and the daemon runs at a baud rate.
+    fn set_baud_rate(&mut self, _baud: u32) -> Result<(), String> {
+        Ok(())
+    }
+}
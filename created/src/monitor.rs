@@ -0,0 +1,96 @@
+//! Monitor: the latest sensor frame and the most recent events, kept for
+//! the control socket's `sensors` and `events` commands, so a client such
+//! as `created tui` can follow the robot without a telemetry sink of its
+//! own. Frames are sampled as the bus delivers them, at most ten a second.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde_json::{json, Value};
+
+use crate::bus::{Bus, Event, Message, Receiver, Sampling};
+use crate::oi::{self, SensorFrame};
+
+const QUEUE_MESSAGES: usize = 256;
+const SAMPLE_EVERY: Duration = Duration::from_millis(100);
+/// Events kept for `events`
+const KEEP_EVENTS: usize = 200;
+
+struct Monitor {
+    frame: Option<SensorFrame>,
+    /// Events with their sequence numbers, oldest first
+    events: VecDeque<(u64, Value)>,
+    next_seq: u64,
+}
+
+static STATE: Mutex<Monitor> = Mutex::new(Monitor { frame: None, events: VecDeque::new(), next_seq: 1 });
+
+fn state() -> std::sync::MutexGuard<'static, Monitor> {
+    STATE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+pub fn spawn(bus: &mut Bus) -> JoinHandle<()> {
+    let sampling = Sampling { period: Some(SAMPLE_EVERY), ..Sampling::default() };
+    let rx = bus.subscribe("monitor", QUEUE_MESSAGES, sampling);
+    thread::spawn(move || run(rx))
+}
+
+fn run(rx: Receiver) {
+    for msg in rx {
+        match msg {
+            Message::Sensors(frame) => state().frame = Some(*frame),
+            Message::Event { time, event } => {
+                let disconnected = matches!(event, Event::Disconnected { .. });
+                let mut value = serde_json::to_value(&event).unwrap_or_default();
+                if let Value::Object(fields) = &mut value {
+                    fields.insert("time".into(), DateTime::<Utc>::from(time).to_rfc3339_opts(SecondsFormat::Millis, true).into());
+                }
+                let mut m = state();
+                let seq = m.next_seq;
+                m.next_seq += 1;
+                m.events.push_back((seq, value));
+                if m.events.len() > KEEP_EVENTS {
+                    m.events.pop_front();
+                }
+                // A new session's packets aren't the last one's
+                if disconnected {
+                    m.frame = None;
+                }
+            }
+            Message::Command { .. } | Message::Pose(_) => {}
+        }
+    }
+}
+
+/// The latest sensor frame, by packet name, with its age and OI mode.
+pub fn sensors() -> Result<Value, String> {
+    let frame = state().frame.ok_or("no sensor frames yet")?;
+    let age = SystemTime::now().duration_since(frame.time).unwrap_or_default();
+    Ok(json!({
+        "age_s": age.as_secs_f64(),
+        "mode": frame.get(oi::OI_MODE).map(oi::mode_name),
+        "packets": frame
+    }))
+}
+
+/// The events kept after sequence number `after` (all with 0), and the
+/// number to ask after next time.
+pub fn events(after: u64) -> Value {
+    let m = state();
+    let events: Vec<Value> = m
+        .events
+        .iter()
+        .filter(|(seq, _)| *seq > after)
+        .map(|(seq, event)| {
+            let mut event = event.clone();
+            if let Value::Object(fields) = &mut event {
+                fields.insert("seq".into(), (*seq).into());
+            }
+            event
+        })
+        .collect();
+    json!({ "events": events, "last": m.next_seq - 1 })
+}
@@ -0,0 +1,127 @@
+//! Morse code: short strings, an IP address or an error code, sent as
+//! beeps or LED flashes, for a robot with no network or display to tell
+//! them through. Timing is the standard one, in dots of `morse.wpm`: a
+//! dash is three dots, and the gaps are one dot within a letter, three
+//! between letters, and seven between words.
+
+use std::sync::Mutex;
+
+use serde_json::Value;
+
+use crate::config::{LedPatternConfig, MorseConfig};
+use crate::leds;
+use crate::song::{self, Tone};
+
+/// Letters, digits, and the punctuation with a code.
+fn code(c: char) -> Option<&'static str> {
+    Some(match c.to_ascii_uppercase() {
+        'A' => ".-",
+        'B' => "-...",
+        'C' => "-.-.",
+        'D' => "-..",
+        'E' => ".",
+        'F' => "..-.",
+        'G' => "--.",
+        'H' => "....",
+        'I' => "..",
+        'J' => ".---",
+        'K' => "-.-",
+        'L' => ".-..",
+        'M' => "--",
+        'N' => "-.",
+        'O' => "---",
+        'P' => ".--.",
+        'Q' => "--.-",
+        'R' => ".-.",
+        'S' => "...",
+        'T' => "-",
+        'U' => "..-",
+        'V' => "...-",
+        'W' => ".--",
+        'X' => "-..-",
+        'Y' => "-.--",
+        'Z' => "--..",
+        '0' => "-----",
+        '1' => ".----",
+        '2' => "..---",
+        '3' => "...--",
+        '4' => "....-",
+        '5' => ".....",
+        '6' => "-....",
+        '7' => "--...",
+        '8' => "---..",
+        '9' => "----.",
+        '.' => ".-.-.-",
+        ',' => "--..--",
+        '?' => "..--..",
+        '\'' => ".----.",
+        '!' => "-.-.--",
+        '/' => "-..-.",
+        '(' => "-.--.",
+        ')' => "-.--.-",
+        '&' => ".-...",
+        ':' => "---...",
+        ';' => "-.-.-.",
+        '=' => "-...-",
+        '+' => ".-.-.",
+        '-' => "-....-",
+        '_' => "..--.-",
+        '"' => ".-..-.",
+        '$' => "...-..-",
+        '@' => ".--.-.",
+        _ => return None,
+    })
+}
+
+/// `text` as on and off spans, in dots; the gap after the last letter is
+/// left out.
+pub fn encode(text: &str) -> Result<Vec<(bool, u32)>, String> {
+    let mut spans: Vec<(bool, u32)> = Vec::new();
+    for (i, word) in text.split_whitespace().enumerate() {
+        if i > 0 {
+            spans.push((false, 7));
+        }
+        for (j, c) in word.chars().enumerate() {
+            let code = code(c).ok_or_else(|| format!("{c:?} has no Morse code"))?;
+            if j > 0 {
+                spans.push((false, 3));
+            }
+            for (k, symbol) in code.chars().enumerate() {
+                if k > 0 {
+                    spans.push((false, 1));
+                }
+                spans.push((true, if symbol == '-' { 3 } else { 1 }));
+            }
+        }
+    }
+    if spans.is_empty() {
+        return Err("nothing to send".into());
+    }
+    Ok(spans)
+}
+
+static CONFIG: Mutex<Option<MorseConfig>> = Mutex::new(None);
+
+fn config() -> MorseConfig {
+    CONFIG.lock().unwrap_or_else(|e| e.into_inner()).clone().unwrap_or_default()
+}
+
+pub fn init(cfg: MorseConfig) {
+    *CONFIG.lock().unwrap_or_else(|e| e.into_inner()) = Some(cfg);
+}
+
+/// Queue `text` as beeps of `morse.pitch`, after the songs queued before.
+pub fn beep(text: &str) -> Result<Value, String> {
+    let cfg = config();
+    let dot = cfg.dot().as_secs_f64();
+    let melody: Vec<Tone> = encode(text)?.into_iter().map(|(on, dots)| Tone { pitch: on.then_some(cfg.pitch()), seconds: dots as f64 * dot }).collect();
+    song::queue(&format!("morse {text:?}"), song::encode(&melody)?, false)
+}
+
+/// Flash `text` on `morse.leds`, in place of the LED pattern playing.
+pub fn flash(text: &str) -> Result<Value, String> {
+    let cfg = config();
+    let spans = encode(text)?.into_iter().map(|(on, dots)| (on, cfg.dot() * dots)).collect();
+    let pattern = LedPatternConfig { leds: cfg.leds.clone(), color: cfg.color, ..Default::default() };
+    leds::flash(&format!("morse {text:?}"), &pattern, spans)
+}
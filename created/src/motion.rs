@@ -0,0 +1,397 @@
+//! Requested wheel motion, shared between whoever drives the robot (control
+//! socket clients such as `created teleop`) and the session that sends it.
+//! The session ramps the wheels toward the request within the configured
+//! acceleration limits and sends them with Drive Direct, entering Safe mode
+//! the first time, and every session starts stopped. One-off commands (seek
+//! dock, play a song) are queued and sent once by the next session loop.
+//!
+//! Requests come in layers, subsumption style: safety stops, then escape
+//! maneuvers (the reflexes), then the user's driving and behaviors, then
+//! idle, which stands still. Each layer proposes wheel speeds or nothing,
+//! and the highest layer with a proposal drives the wheels; the ones below
+//! keep theirs for when it lets go.
+//!
+//! Remote drivers and behaviors lease the user layer rather than set it: a
+//! lease has to be renewed within `motion.heartbeat_ms`, or the wheels are
+//! stopped, so a client that goes quiet (a dropped SSH session, a hung
+//! script) can't leave the robot driving.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::config::MotionConfig;
+use crate::power;
+
+/// The OI's wheel speed limit, mm/s.
+pub const MAX_SPEED: i16 = 500;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+pub struct Wheels {
+    /// mm/s, positive forward
+    pub left: i16,
+    pub right: i16,
+}
+
+impl Wheels {
+    pub const STOP: Wheels = Wheels { left: 0, right: 0 };
+
+    pub fn new(left: i32, right: i32) -> Self {
+        let clamp = |v: i32| v.clamp(-(MAX_SPEED as i32), MAX_SPEED as i32) as i16;
+        Wheels { left: clamp(left), right: clamp(right) }
+    }
+
+    /// Wheel speeds for forward speed `v` (mm/s) and turn rate `omega`
+    /// (rad/s, counter-clockwise), with the wheels `wheel_base` metres apart.
+    pub fn arc(v: f64, omega: f64, wheel_base: f64) -> Self {
+        let d = omega * wheel_base * 1000.0 / 2.0;
+        Wheels::new((v - d).round() as i32, (v + d).round() as i32)
+    }
+
+    /// Drive Direct (145): right wheel first, big-endian.
+    pub fn command(&self) -> [u8; 5] {
+        let [rh, rl] = self.right.to_be_bytes();
+        let [lh, ll] = self.left.to_be_bytes();
+        [crate::oi::DRIVE_DIRECT, rh, rl, lh, ll]
+    }
+}
+
+/// Who is asking for the wheels, lowest priority first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Layer {
+    /// Standing still when nothing else proposes
+    Idle,
+    /// Driving clients (teleop, `createctl drive`, the gamepad) and behaviors
+    User,
+    /// Reflexes
+    Escape,
+    /// Stops that nothing may override, taken without ramping down
+    Safety,
+}
+
+const LAYERS: usize = 4;
+
+#[derive(Clone, Copy)]
+struct Request {
+    wheels: Wheels,
+    /// Bumped on every change so the session sends each request once
+    generation: u64,
+    /// Skip the ramp for this request
+    immediate: bool,
+    /// Bumped on every request, changed or not, to time a stream of them
+    count: u64,
+    /// When a lease was last renewed; None for a request that holds until changed
+    renewed: Option<Instant>,
+}
+
+const STILL: Request = Request { wheels: Wheels::STOP, generation: 0, immediate: false, count: 0, renewed: None };
+
+/// Each layer's proposal, by layer. Idle always has one.
+static REQUESTS: Mutex<[Option<Request>; LAYERS]> = Mutex::new([Some(STILL), Some(STILL), None, None]);
+
+fn requests() -> std::sync::MutexGuard<'static, [Option<Request>; LAYERS]> {
+    REQUESTS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// The layer with the highest proposal, and the proposal.
+fn winner(requests: &[Option<Request>; LAYERS]) -> (Layer, Request) {
+    let layers = [Layer::Idle, Layer::User, Layer::Escape, Layer::Safety];
+    layers.into_iter().rev().find_map(|l| requests[l as usize].map(|r| (l, r))).unwrap_or((Layer::Idle, STILL))
+}
+
+/// Propose `wheels` on `layer`, until the next proposal or `release`.
+pub fn propose(layer: Layer, wheels: Wheels) {
+    power::nudge();
+    let mut requests = requests();
+    let r = requests[layer as usize].get_or_insert(STILL);
+    r.count += 1;
+    r.renewed = None;
+    if r.wheels != wheels {
+        r.wheels = wheels;
+        r.generation += 1;
+        r.immediate = false;
+    }
+}
+
+/// Propose `wheels` on the user layer until they are changed, or for
+/// `motion.heartbeat_ms` unless renewed by another request or `renew`.
+pub fn lease(wheels: Wheels) {
+    propose(Layer::User, wheels);
+    if let Some(r) = requests()[Layer::User as usize].as_mut() {
+        r.renewed = Some(Instant::now());
+    }
+}
+
+/// Renew the user layer's lease without changing it; false if it has none.
+pub fn renew() -> bool {
+    let mut requests = requests();
+    let Some(r) = requests[Layer::User as usize].as_mut().filter(|r| r.renewed.is_some()) else { return false };
+    r.count += 1;
+    r.renewed = Some(Instant::now());
+    true
+}
+
+/// Take back `layer`'s proposal, handing the wheels to the layers below.
+pub fn release(layer: Layer) {
+    if layer != Layer::Idle {
+        requests()[layer as usize] = None;
+    }
+}
+
+/// The layer driving the wheels now.
+pub fn layer() -> Layer {
+    winner(&requests()).0
+}
+
+pub fn set(wheels: Wheels) {
+    propose(Layer::User, wheels);
+}
+
+pub fn stop() {
+    set(Wheels::STOP);
+}
+
+/// Stop at once, without ramping down: before handing the wheels to the OI
+/// (Seek Dock, Passive mode), which a ramp still in progress would undo.
+pub fn halt() {
+    power::nudge();
+    let mut requests = requests();
+    let r = requests[Layer::User as usize].get_or_insert(STILL);
+    r.wheels = Wheels::STOP;
+    r.generation += 1;
+    r.immediate = true;
+    r.renewed = None;
+}
+
+/// The speeds asked for by the layer driving the wheels.
+pub fn current() -> Wheels {
+    winner(&requests()).1.wheels
+}
+
+/// The speeds last sent to the wheels, after ramping and limits.
+static SENT: Mutex<Wheels> = Mutex::new(Wheels::STOP);
+
+pub fn sent() -> Wheels {
+    *SENT.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// One-off OI commands, sent in order by the session.
+static ACTIONS: Mutex<Vec<Vec<u8>>> = Mutex::new(Vec::new());
+
+fn actions() -> std::sync::MutexGuard<'static, Vec<Vec<u8>>> {
+    ACTIONS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Queue raw OI bytes for the session to send as they are.
+pub fn queue(command: Vec<u8>) {
+    power::nudge();
+    actions().push(command);
+}
+
+/// Drop the one-off commands not sent yet.
+pub fn discard() {
+    actions().clear();
+}
+
+/// Acceleration limits, mm/s² per wheel; zero means no limit. Requests
+/// closer together than `smooth` are interpolated between.
+#[derive(Clone, Copy)]
+struct Limits {
+    accel: f64,
+    decel: f64,
+    smooth: Duration,
+}
+
+static LIMITS: Mutex<Limits> = Mutex::new(Limits { accel: 0.0, decel: 0.0, smooth: Duration::ZERO });
+
+fn limits() -> Limits {
+    *LIMITS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// How long a lease lasts without being renewed; None for ever.
+static HEARTBEAT: Mutex<Option<Duration>> = Mutex::new(None);
+
+pub fn init(cfg: &MotionConfig) {
+    *LIMITS.lock().unwrap_or_else(|e| e.into_inner()) = Limits { accel: cfg.accel(), decel: cfg.decel(), smooth: cfg.smooth() };
+    *HEARTBEAT.lock().unwrap_or_else(|e| e.into_inner()) = cfg.heartbeat();
+}
+
+impl Limits {
+    /// Move `from` toward `to` by at most what the limits allow in `dt`.
+    /// Both wheels are scaled by the same fraction of their change, so the
+    /// robot keeps the requested curvature while it ramps.
+    fn step(&self, from: [f64; 2], to: [f64; 2], dt: Duration) -> [f64; 2] {
+        let dt = dt.as_secs_f64();
+        let mut fraction: f64 = 1.0;
+        for (a, b) in from.into_iter().zip(to) {
+            let change = (b - a).abs();
+            // Speeding up means moving away from zero in the same direction
+            let speeding_up = b.abs() > a.abs() && a * b >= 0.0;
+            let limit = if speeding_up { self.accel } else { self.decel };
+            if limit > 0.0 && change > 0.0 {
+                fraction = fraction.min(limit * dt / change);
+            }
+        }
+        let fraction = fraction.min(1.0);
+        [from[0] + (to[0] - from[0]) * fraction, from[1] + (to[1] - from[1]) * fraction]
+    }
+}
+
+/// Longest step taken at once, so a slow loop doesn't turn into a jump.
+const MAX_STEP: Duration = Duration::from_millis(100);
+/// Weight of the latest gap in the average interval between requests.
+const INTERVAL_WEIGHT: f64 = 0.3;
+
+/// The target, gliding from one request to the next of a stream: each new
+/// request is reached over the average interval between them, so the
+/// target keeps moving while the next one is on its way instead of
+/// standing still and then jumping. A stop, or a request after a longer
+/// gap, is taken at once.
+struct Glide {
+    from: [f64; 2],
+    to: [f64; 2],
+    since: Instant,
+    span: Duration,
+    arrived: Option<Instant>,
+    /// Average gap between requests of the stream, s
+    interval: Option<f64>,
+}
+
+impl Glide {
+    fn new(now: Instant) -> Self {
+        Glide { from: [0.0; 2], to: [0.0; 2], since: now, span: Duration::ZERO, arrived: None, interval: None }
+    }
+
+    fn at(&self, now: Instant) -> [f64; 2] {
+        let t = match self.span.as_secs_f64() {
+            span if span > 0.0 => (now.duration_since(self.since).as_secs_f64() / span).min(1.0),
+            _ => 1.0,
+        };
+        [self.from[0] + (self.to[0] - self.from[0]) * t, self.from[1] + (self.to[1] - self.from[1]) * t]
+    }
+
+    /// A request came in, changed or not.
+    fn arrive(&mut self, now: Instant, smooth: Duration) {
+        let gap = self.arrived.map(|a| now.duration_since(a));
+        self.arrived = Some(now);
+        self.interval = match gap {
+            Some(gap) if gap <= smooth => Some(self.interval.map_or(gap.as_secs_f64(), |i| i + (gap.as_secs_f64() - i) * INTERVAL_WEIGHT)),
+            _ => None,
+        };
+    }
+
+    /// A changed request came in.
+    fn request(&mut self, wheels: Wheels, now: Instant, smooth: Duration) {
+        self.arrive(now, smooth);
+        self.from = self.at(now);
+        self.to = [wheels.left as f64, wheels.right as f64];
+        self.since = now;
+        self.span = match self.interval {
+            Some(i) if wheels != Wheels::STOP => Duration::from_secs_f64(i),
+            _ => Duration::ZERO,
+        };
+    }
+
+    fn jump(&mut self, wheels: Wheels) {
+        self.from = [wheels.left as f64, wheels.right as f64];
+        self.to = self.from;
+        self.span = Duration::ZERO;
+        self.interval = None;
+    }
+}
+
+/// Tracks the request a session follows and the speeds it last sent.
+pub struct Follower {
+    seen: Option<(Layer, u64)>,
+    counted: u64,
+    glide: Glide,
+    speeds: [f64; 2],
+    sent: Option<Wheels>,
+    stepped: Instant,
+}
+
+impl Follower {
+    /// Start from a stop, so a request left over from an earlier session
+    /// doesn't set a freshly connected robot in motion.
+    pub fn new() -> Self {
+        *requests() = [Some(STILL), Some(STILL), None, None];
+        actions().clear();
+        *SENT.lock().unwrap_or_else(|e| e.into_inner()) = Wheels::STOP;
+        Follower { seen: None, counted: 0, glide: Glide::new(Instant::now()), speeds: [0.0; 2], sent: None, stepped: Instant::now() }
+    }
+
+    /// The wheel speeds to send, if they changed since the last call.
+    /// Call often (every loop): each call takes one ramp step.
+    pub fn poll(&mut self) -> Option<Wheels> {
+        let now = Instant::now();
+        let limits = limits();
+        {
+            let (layer, r) = winner(&requests());
+            // A layer taking over, or letting go, counts as a new request
+            if self.seen != Some((layer, r.generation)) {
+                self.seen = Some((layer, r.generation));
+                if r.immediate || (layer == Layer::Safety && r.wheels == Wheels::STOP) {
+                    self.glide.jump(r.wheels);
+                    self.speeds = [r.wheels.left as f64, r.wheels.right as f64];
+                } else {
+                    self.glide.request(r.wheels, now, limits.smooth);
+                }
+            } else if self.counted != r.count {
+                self.glide.arrive(now, limits.smooth);
+            }
+            self.counted = r.count;
+        }
+        let dt = now.duration_since(self.stepped).min(MAX_STEP);
+        self.stepped = now;
+        let [left, right] = self.glide.at(now);
+        // Checked every step, as the robot moves relative to the fence
+        // and to what the light bumpers see
+        let target = Wheels::new(left.round() as i32, right.round() as i32);
+        let target = crate::bumper::limit(crate::geofence::limit(crate::thermal::limit(crate::governor::limit(target))));
+        let target = [target.left as f64, target.right as f64];
+        self.speeds = if crate::estop::engaged() { [0.0; 2] } else { limits.step(self.speeds, target, dt) };
+        let wheels = Wheels::new(self.speeds[0].round() as i32, self.speeds[1].round() as i32);
+        // Last, on what is sent, so nothing ramps through them
+        let capped = crate::safety::limit(wheels, self.sent.unwrap_or(Wheels::STOP), dt);
+        let limited = crate::overcurrent::limit(crate::wheel_drop::limit(crate::cliff::limit(crate::low_voltage::limit(crate::charging_lockout::limit(capped)))));
+        if limited != wheels {
+            self.speeds = [limited.left as f64, limited.right as f64];
+        }
+        let wheels = limited;
+        if self.sent == Some(wheels) {
+            return None;
+        }
+        self.sent = Some(wheels);
+        *SENT.lock().unwrap_or_else(|e| e.into_inner()) = wheels;
+        Some(wheels)
+    }
+
+    /// Stop the wheels if the lease driving them ran out: the time since it
+    /// was last renewed, then.
+    pub fn watchdog(&mut self) -> Option<Duration> {
+        let heartbeat = (*HEARTBEAT.lock().unwrap_or_else(|e| e.into_inner()))?;
+        let mut requests = requests();
+        // A lease under a reflex or a safety stop isn't being renewed, nor driving
+        if winner(&requests).0 != Layer::User {
+            return None;
+        }
+        let r = requests[Layer::User as usize].as_mut()?;
+        let since = r.renewed?.elapsed();
+        if r.wheels == Wheels::STOP || since <= heartbeat {
+            return None;
+        }
+        // Ramped down as usual: a stop by the decel limit is the safer one
+        r.wheels = Wheels::STOP;
+        r.generation += 1;
+        r.immediate = false;
+        r.renewed = None;
+        Some(since)
+    }
+
+    /// Queued one-off commands, oldest first.
+    pub fn actions(&mut self) -> Vec<Vec<u8>> {
+        std::mem::take(&mut *actions())
+    }
+}
@@ -0,0 +1,610 @@
+//! iRobot Create Open Interface: opcodes, sensor packet table, and the
+//! sensor stream frame parser. The table describes each packet's layout
+//! (`Format`), and decoding, encoding, and the packet docs all follow it,
+//! so a packet added there is handled everywhere.
+
+use std::fmt;
+use std::time::SystemTime;
+
+use nom::error::{ErrorKind, ParseError};
+use nom::combinator::map;
+use nom::multi::many0_count;
+use nom::number::complete::{be_i16, be_i8, be_u16, be_u8};
+use nom::IResult;
+use serde::ser::{Serialize, SerializeMap, Serializer};
+
+pub const START: u8 = 128;
+pub const SAFE: u8 = 131;
+pub const FULL: u8 = 132;
+pub const SPOT: u8 = 134;
+pub const CLEAN: u8 = 135;
+pub const MAX: u8 = 136;
+pub const DRIVE: u8 = 137;
+pub const DRIVE_DIRECT: u8 = 145;
+pub const DRIVE_PWM: u8 = 146;
+pub const SEEK_DOCK: u8 = 143;
+pub const SCRIPT: u8 = 152;
+pub const PLAY_SCRIPT: u8 = 153;
+pub const SONG: u8 = 140;
+pub const PLAY: u8 = 141;
+pub const LEDS: u8 = 139;
+pub const POWER: u8 = 133;
+pub const SENSORS: u8 = 142;
+pub const QUERY_LIST: u8 = 149;
+pub const STREAM: u8 = 148;
+pub const PAUSE_RESUME_STREAM: u8 = 150;
+
+/// Drive, Drive Direct, and Drive PWM: the commands that set the wheels.
+pub const DRIVES: [u8; 3] = [DRIVE, DRIVE_DIRECT, DRIVE_PWM];
+/// Spot, Clean, Max, and Seek Dock, which have the robot drive itself,
+/// and Play Script, which can drive it as a program stored earlier does.
+pub const SELF_DRIVING: [u8; 5] = [SPOT, CLEAN, MAX, SEEK_DOCK, PLAY_SCRIPT];
+
+/// Whether the command `bytes` is a drive command.
+pub fn is_drive(bytes: &[u8]) -> bool {
+    bytes.first().is_some_and(|op| DRIVES.contains(op))
+}
+
+/// Whether the command `bytes` is a drive command stopping the wheels.
+pub fn is_stop(bytes: &[u8]) -> bool {
+    is_drive(bytes) && bytes[1..].iter().all(|b| *b == 0)
+}
+
+/// Whether the command `bytes` can set the robot moving: a drive command
+/// other than a stop, or one that has the robot drive itself.
+pub fn moves(bytes: &[u8]) -> bool {
+    (is_drive(bytes) && !is_stop(bytes)) || bytes.first().is_some_and(|op| SELF_DRIVING.contains(op))
+}
+
+/// First byte of every frame sent by the robot while streaming.
+pub const STREAM_HEADER: u8 = 19;
+
+/// Packet id of the OI mode.
+pub const OI_MODE: u8 = 35;
+
+/// How a packet's value is laid out: one byte or two, big-endian, signed
+/// or not.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    U8,
+    I8,
+    U16,
+    I16,
+}
+
+use Format::{I16, I8, U16, U8};
+
+impl Format {
+    pub const fn size(self) -> usize {
+        match self {
+            U8 | I8 => 1,
+            U16 | I16 => 2,
+        }
+    }
+
+    /// The lowest and highest value it holds.
+    pub fn range(self) -> (i32, i32) {
+        match self {
+            U8 => (0, u8::MAX as i32),
+            I8 => (i8::MIN as i32, i8::MAX as i32),
+            U16 => (0, u16::MAX as i32),
+            I16 => (i16::MIN as i32, i16::MAX as i32),
+        }
+    }
+
+    /// Its value off the front of `input`.
+    fn parse(self, input: &[u8]) -> IResult<&[u8], i32, Fail> {
+        match self {
+            U8 => map(be_u8, i32::from)(input),
+            I8 => map(be_i8, i32::from)(input),
+            U16 => map(be_u16, i32::from)(input),
+            I16 => map(be_i16, i32::from)(input),
+        }
+    }
+
+    /// Append `value` as the robot sends it.
+    pub fn encode(self, value: i32, out: &mut Vec<u8>) {
+        match self.size() {
+            1 => out.push(value as u8),
+            _ => out.extend_from_slice(&(value as u16).to_be_bytes()),
+        }
+    }
+}
+
+/// A single sensor packet as described in the OI specification.
+#[derive(Debug, Clone, Copy)]
+pub struct Packet {
+    pub id: u8,
+    pub name: &'static str,
+    pub format: Format,
+    /// What its value counts in, if it is a quantity (bits for a bit field)
+    pub unit: &'static str,
+}
+
+const fn p(id: u8, name: &'static str, format: Format, unit: &'static str) -> Packet {
+    Packet { id, name, format, unit }
+}
+
+/// Every individual packet, indexed by `id - FIRST_PACKET`: the one
+/// description of the sensor packets, which the frame decoder and encoder
+/// follow and `packet_docs` lists. Packets 7-42 are shared by Create 1 and
+/// Create 2; 43-58 exist on Create 2 only.
+pub const PACKETS: [Packet; 52] = [
+    p(7, "bumps_wheeldrops", U8, "bits"),
+    p(8, "wall", U8, ""),
+    p(9, "cliff_left", U8, ""),
+    p(10, "cliff_front_left", U8, ""),
+    p(11, "cliff_front_right", U8, ""),
+    p(12, "cliff_right", U8, ""),
+    p(13, "virtual_wall", U8, ""),
+    p(14, "overcurrents", U8, "bits"),
+    p(15, "dirt_detect", U8, ""),
+    p(16, "unused_16", U8, ""),
+    p(17, "ir_opcode", U8, "IR byte"),
+    p(18, "buttons", U8, "bits"),
+    p(19, "distance", I16, "mm"),
+    p(20, "angle", I16, "degrees"),
+    p(21, "charging_state", U8, "state"),
+    p(22, "voltage", U16, "mV"),
+    p(23, "current", I16, "mA"),
+    p(24, "temperature", I8, "°C"),
+    p(25, "battery_charge", U16, "mAh"),
+    p(26, "battery_capacity", U16, "mAh"),
+    p(27, "wall_signal", U16, ""),
+    p(28, "cliff_left_signal", U16, ""),
+    p(29, "cliff_front_left_signal", U16, ""),
+    p(30, "cliff_front_right_signal", U16, ""),
+    p(31, "cliff_right_signal", U16, ""),
+    p(32, "cargo_bay_digital_inputs", U8, "bits"),
+    p(33, "cargo_bay_analog_signal", U16, ""),
+    p(34, "charging_sources", U8, "bits"),
+    p(35, "oi_mode", U8, "mode"),
+    p(36, "song_number", U8, ""),
+    p(37, "song_playing", U8, ""),
+    p(38, "stream_packets", U8, ""),
+    p(39, "requested_velocity", I16, "mm/s"),
+    p(40, "requested_radius", I16, "mm"),
+    p(41, "requested_right_velocity", I16, "mm/s"),
+    p(42, "requested_left_velocity", I16, "mm/s"),
+    p(43, "left_encoder_counts", U16, "counts"),
+    p(44, "right_encoder_counts", U16, "counts"),
+    p(45, "light_bumper", U8, "bits"),
+    p(46, "light_bump_left_signal", U16, ""),
+    p(47, "light_bump_front_left_signal", U16, ""),
+    p(48, "light_bump_center_left_signal", U16, ""),
+    p(49, "light_bump_center_right_signal", U16, ""),
+    p(50, "light_bump_front_right_signal", U16, ""),
+    p(51, "light_bump_right_signal", U16, ""),
+    p(52, "ir_opcode_left", U8, "IR byte"),
+    p(53, "ir_opcode_right", U8, "IR byte"),
+    p(54, "left_motor_current", I16, "mA"),
+    p(55, "right_motor_current", I16, "mA"),
+    p(56, "main_brush_current", I16, "mA"),
+    p(57, "side_brush_current", I16, "mA"),
+    p(58, "stasis", U8, "bits"),
+];
+
+pub const FIRST_PACKET: u8 = 7;
+pub const LAST_PACKET: u8 = 58;
+
+/// OI modes as reported by packet 35.
+pub const MODE_PASSIVE: i32 = 1;
+pub const MODE_SAFE: i32 = 2;
+pub const MODE_FULL: i32 = 3;
+
+/// Name of an OI mode as reported by packet 35.
+pub fn mode_name(mode: i32) -> &'static str {
+    match mode {
+        0 => "off",
+        MODE_PASSIVE => "passive",
+        MODE_SAFE => "safe",
+        MODE_FULL => "full",
+        _ => "unknown",
+    }
+}
+
+/// Look up an individual packet by its snake_case name.
+pub fn packet_by_name(name: &str) -> Option<&'static Packet> {
+    PACKETS.iter().find(|p| p.name == name)
+}
+
+/// The group packets, each standing for a range of individual packets.
+pub const GROUPS: [u8; 11] = [0, 1, 2, 3, 4, 5, 6, 100, 101, 106, 107];
+
+/// The individual packets making up a packet id: itself for an individual
+/// packet, or the member range for a group packet.
+pub fn expand(id: u8) -> Option<&'static [Packet]> {
+    let (first, last) = match id {
+        0 => (7, 26),
+        1 => (7, 16),
+        2 => (17, 20),
+        3 => (21, 26),
+        4 => (27, 34),
+        5 => (35, 42),
+        6 => (7, 42),
+        100 => (7, 58),
+        101 => (43, 58),
+        106 => (46, 51),
+        107 => (54, 58),
+        FIRST_PACKET..=LAST_PACKET => (id, id),
+        _ => return None,
+    };
+    Some(&PACKETS[(first - FIRST_PACKET) as usize..=(last - FIRST_PACKET) as usize])
+}
+
+/// Decoded sensor values from one stream frame.
+#[derive(Debug, Clone, Copy)]
+pub struct SensorFrame {
+    pub time: SystemTime,
+    values: [Option<i32>; LAST_PACKET as usize + 1],
+}
+
+impl SensorFrame {
+    pub fn new(time: SystemTime) -> Self {
+        SensorFrame { time, values: [None; LAST_PACKET as usize + 1] }
+    }
+
+    pub fn get(&self, id: u8) -> Option<i32> {
+        self.values.get(id as usize).copied().flatten()
+    }
+
+    pub fn set(&mut self, id: u8, value: i32) {
+        if let Some(slot) = self.values.get_mut(id as usize) {
+            *slot = Some(value);
+        }
+    }
+
+    /// Packets present in this frame, in id order.
+    pub fn iter(&self) -> impl Iterator<Item = (&'static Packet, i32)> + '_ {
+        PACKETS.iter().filter_map(|p| self.get(p.id).map(|v| (p, v)))
+    }
+}
+
+/// Serializes as a map of packet name to value (the timestamp is left to the
+/// container format).
+impl Serialize for SensorFrame {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+        for (p, v) in self.iter() {
+            map.serialize_entry(p.name, &v)?;
+        }
+        map.end()
+    }
+}
+
+/// Why a stream frame was discarded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameError {
+    /// Its bytes don't add up: corrupted, or a data byte taken for a header
+    Checksum,
+    UnknownPacket(u8),
+    Truncated(u8),
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FrameError::Checksum => write!(f, "stream checksum mismatch"),
+            FrameError::UnknownPacket(id) => write!(f, "unknown packet id {id}"),
+            FrameError::Truncated(id) => write!(f, "packet {id} truncated"),
+        }
+    }
+}
+
+/// A frame body's parse error: what was wrong, or None for nom's own, the
+/// body running out where the next packet id would be.
+#[derive(Debug)]
+struct Fail(Option<FrameError>);
+
+impl ParseError<&[u8]> for Fail {
+    fn from_error_kind(_: &[u8], _: ErrorKind) -> Self {
+        Fail(None)
+    }
+
+    fn append(_: &[u8], _: ErrorKind, other: Self) -> Self {
+        other
+    }
+}
+
+/// The members' values, one after the other as the table lays them out,
+/// into `frame`.
+fn values<'f>(members: &'static [Packet], frame: &'f mut SensorFrame) -> impl FnMut(&[u8]) -> IResult<&[u8], (), Fail> + 'f {
+    move |mut input| {
+        for p in members {
+            let (rest, value) = p.format.parse(input).map_err(|_| nom::Err::Failure(Fail(Some(FrameError::Truncated(p.id)))))?;
+            frame.set(p.id, value);
+            input = rest;
+        }
+        Ok((input, ()))
+    }
+}
+
+/// A packet id and the packets it stands for.
+fn members(input: &[u8]) -> IResult<&[u8], &'static [Packet], Fail> {
+    let (rest, id) = be_u8(input)?;
+    let members = expand(id).ok_or(nom::Err::Failure(Fail(Some(FrameError::UnknownPacket(id)))))?;
+    Ok((rest, members))
+}
+
+/// Decode the `[id, data..., id, data...]` body of a stream frame into
+/// `frame`.
+pub fn decode_packets(data: &[u8], frame: &mut SensorFrame) -> Result<(), FrameError> {
+    let entry = |input| {
+        let (rest, members) = members(input)?;
+        values(members, frame)(rest)
+    };
+    match many0_count(entry)(data) {
+        Ok(_) => Ok(()),
+        Err(nom::Err::Failure(Fail(Some(e)))) => Err(e),
+        // A packet's failures are the only ones many0 passes on
+        Err(_) => unreachable!("frame body parse failed without a cause"),
+    }
+}
+
+/// Decode the reply to a Query List or Sensors command for `ids`, their
+/// data without the ids, into `frame`.
+pub fn decode_reply(ids: &[u8], mut data: &[u8], frame: &mut SensorFrame) -> Result<(), FrameError> {
+    for &id in ids {
+        let members = expand(id).ok_or(FrameError::UnknownPacket(id))?;
+        data = match values(members, frame)(data) {
+            Ok((rest, ())) => rest,
+            Err(nom::Err::Failure(Fail(Some(e)))) => return Err(e),
+            Err(_) => unreachable!("reply parse failed without a cause"),
+        };
+    }
+    Ok(())
+}
+
+/// How many bytes the reply to a Query List for `ids` has.
+pub fn reply_size(ids: &[u8]) -> usize {
+    ids.iter().flat_map(|&id| expand(id).unwrap_or_default()).map(|p| p.format.size()).sum()
+}
+
+/// Encode `frame` as the robot would stream it: `[19, n, id, data..., checksum]`
+/// with one entry per present packet.
+pub fn encode_stream_frame(frame: &SensorFrame) -> Vec<u8> {
+    let mut out = vec![STREAM_HEADER, 0];
+    for (p, v) in frame.iter() {
+        out.push(p.id);
+        p.format.encode(v, &mut out);
+    }
+    out[1] = (out.len() - 2) as u8;
+    let sum = out.iter().fold(0u8, |acc, &b| acc.wrapping_add(b));
+    out.push(sum.wrapping_neg());
+    out
+}
+
+/// The packet table as Markdown, for `created packets`: each packet's id,
+/// name, size, range, unit, and the groups it is streamed in.
+pub fn packet_docs() -> String {
+    let mut out = String::from("| id | name | bytes | range | unit | in groups |\n|---:|---|---:|---|---|---|\n");
+    for p in &PACKETS {
+        let (min, max) = p.format.range();
+        let groups: Vec<String> = GROUPS.iter().filter(|&&g| expand(g).is_some_and(|m| m.iter().any(|m| m.id == p.id))).map(u8::to_string).collect();
+        out.push_str(&format!("| {} | `{}` | {} | {min} to {max} | {} | {} |\n", p.id, p.name, p.format.size(), p.unit, groups.join(", ")));
+    }
+    out
+}
+
+/// The longest stream frame: header, length, 255 bytes of body, checksum.
+const MAX_FRAME: usize = 258;
+
+/// Reassembles stream frames (`[19, n, body..., checksum]`) from arbitrary
+/// read chunks, resynchronizing on the next header byte after corruption.
+/// The port is read straight into its buffer (`spare`, then `filled`), and
+/// frames are decoded where they lie, so streaming allocates nothing: the
+/// buffer is fixed, room for a frame waiting on its last bytes and a read
+/// after it, and the bytes left over are moved to its front only when a
+/// read needs the room.
+#[derive(Debug)]
+pub struct StreamParser {
+    buf: [u8; 2 * MAX_FRAME],
+    /// The bytes waiting, `buf[start..end]`
+    start: usize,
+    end: usize,
+}
+
+impl Default for StreamParser {
+    fn default() -> Self {
+        StreamParser { buf: [0; 2 * MAX_FRAME], start: 0, end: 0 }
+    }
+}
+
+impl StreamParser {
+    /// Room to read into, after the bytes waiting: at least `MAX_FRAME`.
+    pub fn spare(&mut self) -> &mut [u8] {
+        if self.buf.len() - self.end < MAX_FRAME {
+            self.buf.copy_within(self.start..self.end, 0);
+            self.end -= self.start;
+            self.start = 0;
+        }
+        &mut self.buf[self.end..]
+    }
+
+    /// `n` bytes were read into `spare()`.
+    pub fn filled(&mut self, n: usize) {
+        self.end = (self.end + n).min(self.buf.len());
+    }
+
+    /// Next complete frame, `Some(Err)` for a frame that failed its checksum
+    /// or could not be decoded, `None` when more bytes are needed.
+    pub fn next_frame(&mut self) -> Option<Result<SensorFrame, FrameError>> {
+        match self.buf[self.start..self.end].iter().position(|&b| b == STREAM_HEADER) {
+            Some(i) => self.start += i,
+            None => {
+                self.start = 0;
+                self.end = 0;
+                return None;
+            }
+        }
+        let waiting = &self.buf[self.start..self.end];
+        if waiting.len() < 2 {
+            return None;
+        }
+        let n = waiting[1] as usize;
+        if waiting.len() < n + 3 {
+            return None;
+        }
+        let sum = waiting[..n + 3].iter().fold(0u8, |acc, &b| acc.wrapping_add(b));
+        if sum != 0 {
+            // Not a real header (or a corrupted frame): skip it and resync
+            self.start += 1;
+            return Some(Err(FrameError::Checksum));
+        }
+        let mut frame = SensorFrame::new(SystemTime::now());
+        let res = decode_packets(&waiting[2..n + 2], &mut frame);
+        self.start += n + 3;
+        Some(res.map(|()| frame))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values_of(frame: &SensorFrame) -> Vec<(u8, i32)> {
+        frame.iter().map(|(p, v)| (p.id, v)).collect()
+    }
+
+    /// Every member of `id` set to a value of its own, within its range.
+    fn sample(id: u8) -> SensorFrame {
+        let mut frame = SensorFrame::new(SystemTime::UNIX_EPOCH);
+        for p in expand(id).unwrap() {
+            let (min, max) = p.format.range();
+            let value = if min < 0 { -(p.id as i32) * 3 } else { p.id as i32 * 5 };
+            frame.set(p.id, value.clamp(min, max));
+        }
+        frame
+    }
+
+    fn feed(parser: &mut StreamParser, bytes: &[u8]) {
+        parser.spare()[..bytes.len()].copy_from_slice(bytes);
+        parser.filled(bytes.len());
+    }
+
+    fn frames(parser: &mut StreamParser) -> Vec<Result<SensorFrame, FrameError>> {
+        std::iter::from_fn(|| parser.next_frame()).collect()
+    }
+
+    #[test]
+    fn decodes_each_format() {
+        let mut frame = SensorFrame::new(SystemTime::UNIX_EPOCH);
+        // bumps (u8), voltage (u16), current (i16), temperature (i8)
+        decode_reply(&[7, 22, 23, 24], &[200, 0x3e, 0x80, 0xff, 0x4c, 0xf6], &mut frame).unwrap();
+        assert_eq!(values_of(&frame), [(7, 200), (22, 16000), (23, -180), (24, -10)]);
+
+        let mut frame = SensorFrame::new(SystemTime::UNIX_EPOCH);
+        // Big-endian extremes: the top bit is a sign only for the signed formats
+        decode_packets(&[43, 0xff, 0xfe, 19, 0x80, 0x00, 35, 3], &mut frame).unwrap();
+        assert_eq!(values_of(&frame), [(19, -32768), (35, 3), (43, 65534)]);
+    }
+
+    #[test]
+    fn encodes_what_it_decodes() {
+        for (format, bytes, value) in [(U8, &[255][..], 255), (I8, &[0x80], -128), (U16, &[0x12, 0x34], 0x1234), (I16, &[0xff, 0xff], -1)] {
+            assert_eq!(format.parse(bytes).unwrap(), (&[][..], value));
+            let mut out = Vec::new();
+            format.encode(value, &mut out);
+            assert_eq!(out, bytes);
+        }
+    }
+
+    #[test]
+    fn decodes_the_group_packets() {
+        // Reply sizes from the OI specification
+        let sizes = [(0, 26), (1, 10), (2, 6), (3, 10), (4, 14), (5, 12), (6, 52), (100, 80), (101, 28), (106, 12), (107, 9)];
+        for (id, size) in sizes {
+            assert_eq!(reply_size(&[id]), size, "group {id}");
+            let expected = sample(id);
+            let mut data = Vec::new();
+            for (p, v) in expected.iter() {
+                p.format.encode(v, &mut data);
+            }
+            assert_eq!(data.len(), size);
+
+            let mut frame = SensorFrame::new(SystemTime::UNIX_EPOCH);
+            decode_reply(&[id], &data, &mut frame).unwrap();
+            assert_eq!(values_of(&frame), values_of(&expected), "reply for group {id}");
+
+            let mut body = vec![id];
+            body.extend_from_slice(&data);
+            let mut frame = SensorFrame::new(SystemTime::UNIX_EPOCH);
+            decode_packets(&body, &mut frame).unwrap();
+            assert_eq!(values_of(&frame), values_of(&expected), "stream body for group {id}");
+        }
+    }
+
+    #[test]
+    fn short_or_unknown_data_is_an_error() {
+        let mut frame = SensorFrame::new(SystemTime::UNIX_EPOCH);
+        assert_eq!(decode_reply(&[22], &[0x3e], &mut frame), Err(FrameError::Truncated(22)));
+        assert_eq!(decode_reply(&[7, 22], &[1], &mut frame), Err(FrameError::Truncated(22)));
+        assert_eq!(decode_reply(&[3], &[0; 9], &mut frame), Err(FrameError::Truncated(26)));
+        assert_eq!(decode_reply(&[99], &[0], &mut frame), Err(FrameError::UnknownPacket(99)));
+        assert_eq!(decode_packets(&[7, 1, 22, 0x3e], &mut frame), Err(FrameError::Truncated(22)));
+        assert_eq!(decode_packets(&[7, 1, 99, 0], &mut frame), Err(FrameError::UnknownPacket(99)));
+        assert_eq!(decode_packets(&[], &mut frame), Ok(()));
+    }
+
+    #[test]
+    fn streams_a_frame_split_anywhere() {
+        let frame = sample(100);
+        let bytes = encode_stream_frame(&frame);
+        for at in 1..bytes.len() {
+            let mut parser = StreamParser::default();
+            feed(&mut parser, &bytes[..at]);
+            assert!(parser.next_frame().is_none(), "complete after {at} bytes");
+            feed(&mut parser, &bytes[at..]);
+            let decoded = parser.next_frame().unwrap().unwrap();
+            assert_eq!(values_of(&decoded), values_of(&frame));
+            assert!(parser.next_frame().is_none());
+        }
+    }
+
+    #[test]
+    fn resyncs_after_a_checksum_error() {
+        let frame = sample(6);
+        let good = encode_stream_frame(&frame);
+        let mut corrupted = good.clone();
+        corrupted[10] ^= 0x40;
+        let mut parser = StreamParser::default();
+        // Noise and a corrupted frame, then good ones: a byte taken for a
+        // header may wait on bytes of the frames after it to be ruled out
+        let mut bytes = vec![0, 19, 7];
+        bytes.extend_from_slice(&corrupted);
+        for _ in 0..3 {
+            bytes.extend_from_slice(&good);
+        }
+        feed(&mut parser, &bytes);
+        let results = frames(&mut parser);
+        assert!(results.iter().any(|r| matches!(r, Err(FrameError::Checksum))));
+        let decoded: Vec<_> = results.iter().filter_map(|r| r.as_ref().ok()).collect();
+        assert!(decoded.len() >= 2, "{} of 3 good frames decoded", decoded.len());
+        assert!(decoded.iter().all(|d| values_of(d) == values_of(&frame)));
+
+        // And goes on with the frames after
+        feed(&mut parser, &good);
+        let decoded = parser.next_frame().unwrap().unwrap();
+        assert_eq!(values_of(&decoded), values_of(&frame));
+    }
+
+    #[test]
+    fn tells_what_moves_the_robot() {
+        let cases: &[(&[u8], bool, bool, bool)] = &[
+            (&[DRIVE_DIRECT, 0, 100, 0, 100], true, false, true),
+            (&[DRIVE_DIRECT, 0, 0, 0, 0], true, true, false),
+            (&[DRIVE, 0, 0, 0x80, 0x00], true, false, true),
+            (&[DRIVE, 0, 0, 0, 0], true, true, false),
+            (&[DRIVE_PWM, 0xff, 0x01, 0, 0], true, false, true),
+            (&[CLEAN], false, false, true),
+            (&[SEEK_DOCK], false, false, true),
+            (&[PLAY_SCRIPT], false, false, true),
+            (&[SCRIPT, 1, DRIVE_DIRECT], false, false, false),
+            (&[LEDS, 0, 0, 0], false, false, false),
+            (&[], false, false, false),
+        ];
+        for (bytes, drive, stop, moving) in cases {
+            assert_eq!((is_drive(bytes), is_stop(bytes), moves(bytes)), (*drive, *stop, *moving), "{bytes:?}");
+        }
+    }
+}
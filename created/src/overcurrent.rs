@@ -0,0 +1,255 @@
+//! Overcurrent protection: the OI flags a wheel or brush motor drawing too
+//! much current (packet 14). A flag held for `overcurrent.persist_ms`, a
+//! jammed wheel or a brush wound up in a cable rather than a moment's
+//! surge, trips that motor: a wheel stops both wheels, cancelling the
+//! running behavior, as driving one would spin the robot on the other, and
+//! a brush is turned off. Either stays cut, whatever asks for it, until
+//! acknowledged on the control socket once the flag has cleared.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+use tracing::{info, warn};
+
+use crate::{audit, behavior};
+use crate::bus::Event;
+use crate::config::OvercurrentConfig;
+use crate::motion::{self, Wheels};
+use crate::oi::{self, SensorFrame};
+
+const OVERCURRENTS: u8 = 14;
+/// Packet 14's bits on the Create 2, and the Motors (138) bits running that
+/// motor (none for the wheels)
+const MOTORS: [(&str, i32, u8); 4] = [("left_wheel", 0b1_0000, 0), ("right_wheel", 0b1000, 0), ("main_brush", 0b100, 0b100), ("side_brush", 0b1, 0b1)];
+/// Motors: brushes and vacuum on or off
+const MOTORS_OPCODE: u8 = 138;
+/// PWM Motors: main brush, side brush, then vacuum duty cycles
+const PWM_MOTORS: u8 = 144;
+
+struct Protection {
+    enabled: bool,
+    persist: Duration,
+    /// When each motor's flag went up, while it stays up
+    flagged: [Option<Instant>; 4],
+    /// Motors cut until acknowledged
+    tripped: [bool; 4],
+    /// The last Motors byte sent, to turn off only the brush that tripped
+    motors: u8,
+}
+
+static STATE: Mutex<Option<Protection>> = Mutex::new(None);
+
+fn state() -> std::sync::MutexGuard<'static, Option<Protection>> {
+    STATE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+pub fn init(cfg: OvercurrentConfig) {
+    *state() = Some(Protection { enabled: cfg.enabled(), persist: cfg.persist(), flagged: [None; 4], tripped: [false; 4], motors: 0 });
+}
+
+/// A new session: flags are timed afresh, but what tripped stays cut.
+pub fn begin() {
+    if let Some(p) = state().as_mut() {
+        p.flagged = [None; 4];
+        p.motors = 0;
+    }
+}
+
+/// Check the overcurrent flags; events to publish.
+pub fn update(frame: &SensorFrame) -> Vec<Event> {
+    let (tripped, persisted, motors) = {
+        let mut guard = state();
+        let Some(p) = guard.as_mut().filter(|p| p.enabled) else { return Vec::new() };
+        let Some(bits) = frame.get(OVERCURRENTS) else { return Vec::new() };
+        let now = Instant::now();
+        let mut tripped = Vec::new();
+        for (i, (name, bit, _)) in MOTORS.iter().enumerate() {
+            if bits & bit == 0 {
+                p.flagged[i] = None;
+                continue;
+            }
+            let since = *p.flagged[i].get_or_insert(now);
+            if !p.tripped[i] && now.duration_since(since) >= p.persist {
+                p.tripped[i] = true;
+                tripped.push(i);
+                let cause = format!("overcurrent on the {} for {:.1} s, cut until acknowledged", name.replace('_', " "), now.duration_since(since).as_secs_f64());
+                warn!("{cause}");
+                audit::record("overcurrent_cut", "overcurrent flags", &cause);
+            }
+        }
+        let persisted = tripped.iter().filter_map(|&i| p.flagged[i]).map(|since| now.duration_since(since)).max().unwrap_or_default();
+        (tripped, persisted, p.motors)
+    };
+    if tripped.is_empty() {
+        return Vec::new();
+    }
+    if tripped.iter().any(|&i| MOTORS[i].2 == 0) {
+        motion::halt();
+        behavior::cancel("overcurrent");
+    }
+    let brushes: u8 = tripped.iter().map(|&i| MOTORS[i].2).fold(0, |a, b| a | b);
+    if brushes != 0 {
+        motion::queue(vec![MOTORS_OPCODE, motors & !brushes]);
+    }
+    vec![Event::Overcurrent { motors: tripped.iter().map(|&i| MOTORS[i].0.to_string()).collect(), for_s: persisted.as_secs_f64() }]
+}
+
+fn wheels_tripped() -> bool {
+    state().as_ref().is_some_and(|p| p.tripped[..2].contains(&true))
+}
+
+/// `wheels`, or a stop while a wheel is tripped.
+pub fn limit(wheels: Wheels) -> Wheels {
+    if wheels_tripped() {
+        Wheels::STOP
+    } else {
+        wheels
+    }
+}
+
+/// Whether the raw OI command `bytes` may be sent now: not one running a
+/// tripped motor.
+pub fn allows(bytes: &[u8]) -> bool {
+    let mut guard = state();
+    let Some(p) = guard.as_mut() else { return true };
+    let cut = |name: &str| MOTORS.iter().position(|m| m.0 == name).is_some_and(|i| p.tripped[i]);
+    match bytes {
+        _ if oi::moves(bytes) => !(cut("left_wheel") || cut("right_wheel")),
+        [MOTORS_OPCODE, bits] => {
+            let running = MOTORS.iter().enumerate().any(|(i, m)| p.tripped[i] && bits & m.2 != 0);
+            if !running {
+                p.motors = *bits;
+            }
+            !running
+        }
+        [PWM_MOTORS, main, side, _] => !((cut("main_brush") && *main != 0) || (cut("side_brush") && *side != 0)),
+        _ => true,
+    }
+}
+
+/// Release the tripped motors whose flags have cleared.
+pub fn acknowledge() -> Result<Value, String> {
+    let released: Vec<&str> = {
+        let mut guard = state();
+        let p = guard.as_mut().ok_or("overcurrent protection isn't running")?;
+        if !p.tripped.contains(&true) {
+            return Err("no motor is cut for overcurrent".into());
+        }
+        let still: Vec<&str> = MOTORS.iter().enumerate().filter(|(i, _)| p.tripped[*i] && p.flagged[*i].is_some()).map(|(_, m)| m.0).collect();
+        if !still.is_empty() {
+            return Err(format!("still over current: {}", still.join(", ")));
+        }
+        let released = MOTORS.iter().enumerate().filter(|(i, _)| p.tripped[*i]).map(|(_, m)| m.0).collect();
+        p.tripped = [false; 4];
+        released
+    };
+    info!("overcurrent acknowledged: {}", released.join(", "));
+    audit::record("overcurrent_acknowledged", "control", &format!("released {}", released.join(", ")));
+    Ok(json!({ "released": released }))
+}
+
+pub fn status() -> Value {
+    let guard = state();
+    let Some(p) = guard.as_ref() else { return json!({ "enabled": false }) };
+    let names = |on: &dyn Fn(usize) -> bool| -> Vec<&str> { MOTORS.iter().enumerate().filter(|(i, _)| on(*i)).map(|(_, m)| m.0).collect() };
+    json!({
+        "enabled": p.enabled,
+        "flagged": names(&|i| p.flagged[i].is_some()),
+        "tripped": names(&|i| p.tripped[i]),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::SystemTime;
+
+    use super::*;
+
+    const FORWARD: [u8; 5] = [oi::DRIVE_DIRECT, 0, 200, 0, 200];
+    const LEFT_WHEEL: i32 = 0b1_0000;
+    const MAIN_BRUSH: i32 = 0b100;
+
+    fn flags(bits: i32) -> SensorFrame {
+        let mut frame = SensorFrame::new(SystemTime::now());
+        frame.set(OVERCURRENTS, bits);
+        frame
+    }
+
+    fn protecting(enabled: bool) -> std::sync::MutexGuard<'static, ()> {
+        let serial = crate::robot::serial();
+        init(OvercurrentConfig { enabled: Some(enabled), persist_ms: Some(30) });
+        begin();
+        serial
+    }
+
+    /// Hold `bits` up past the persist time.
+    fn trip(bits: i32) -> Vec<Event> {
+        update(&flags(bits));
+        thread::sleep(Duration::from_millis(40));
+        update(&flags(bits))
+    }
+
+    #[test]
+    fn trips_only_a_flag_that_persists() {
+        let _serial = protecting(true);
+        assert!(update(&flags(LEFT_WHEEL)).is_empty());
+        update(&flags(0));
+        thread::sleep(Duration::from_millis(40));
+        // A surge that came and went starts afresh
+        assert!(update(&flags(LEFT_WHEEL)).is_empty());
+        assert!(allows(&FORWARD));
+        thread::sleep(Duration::from_millis(40));
+        assert!(matches!(&update(&flags(LEFT_WHEEL))[..], [Event::Overcurrent { motors, .. }] if motors == &["left_wheel"]));
+        // Reported once
+        assert!(update(&flags(LEFT_WHEEL)).is_empty());
+        update(&flags(0));
+        acknowledge().unwrap();
+    }
+
+    #[test]
+    fn a_wheel_cut_stops_both_wheels_until_acknowledged() {
+        let _serial = protecting(true);
+        trip(LEFT_WHEEL);
+        assert_eq!(limit(Wheels::new(200, 200)), Wheels::STOP);
+        for refused in [&FORWARD[..], &[oi::DRIVE, 0, 100, 0, 1], &[oi::SEEK_DOCK]] {
+            assert!(!allows(refused), "{refused:?}");
+        }
+        for allowed in [&[oi::DRIVE_DIRECT, 0, 0, 0, 0][..], &[MOTORS_OPCODE, 0b101], &[oi::LEDS, 0, 0, 0]] {
+            assert!(allows(allowed), "{allowed:?}");
+        }
+        assert!(acknowledge().unwrap_err().contains("still over current"));
+        update(&flags(0));
+        // Not on a new session either
+        begin();
+        assert_eq!(limit(Wheels::new(200, 200)), Wheels::STOP);
+        assert_eq!(acknowledge().unwrap()["released"], json!(["left_wheel"]));
+        assert_eq!(limit(Wheels::new(200, 200)), Wheels::new(200, 200));
+        assert!(allows(&FORWARD));
+        assert!(acknowledge().is_err());
+    }
+
+    #[test]
+    fn a_brush_cut_leaves_the_rest_running() {
+        let _serial = protecting(true);
+        assert!(allows(&[MOTORS_OPCODE, 0b101]));
+        trip(MAIN_BRUSH);
+        assert_eq!(limit(Wheels::new(200, 200)), Wheels::new(200, 200));
+        assert!(allows(&FORWARD));
+        assert!(!allows(&[MOTORS_OPCODE, 0b100]));
+        assert!(allows(&[MOTORS_OPCODE, 0b011]));
+        assert!(!allows(&[PWM_MOTORS, 50, 0, 0]));
+        assert!(allows(&[PWM_MOTORS, 0, 50, 100]));
+        update(&flags(0));
+        acknowledge().unwrap();
+        assert!(allows(&[MOTORS_OPCODE, 0b100]));
+    }
+
+    #[test]
+    fn disabled_it_trips_nothing() {
+        let _serial = protecting(false);
+        assert!(trip(LEFT_WHEEL | MAIN_BRUSH).is_empty());
+        assert!(allows(&FORWARD) && allows(&[MOTORS_OPCODE, 0b100]));
+    }
+}
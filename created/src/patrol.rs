@@ -0,0 +1,261 @@
+//! Scheduled patrols: at each of `patrol.times` the `patrol` behavior is
+//! started on `patrol.route` (it undocks, plays the route, and docks
+//! again), unless something else is running or the battery supervisor
+//! has the robot docking or charging. Every patrol run, scheduled or
+//! started by hand, gets a report: when it ran, how far it went, how each
+//! phase ended, and what it ran into, written as JSON to
+//! `<reports_dir>/patrol-<route>-YYYYMMDD-HHMMSS.json` and summed up in a
+//! `patrol_report` event.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use chrono::{DateTime, Local, NaiveDateTime, NaiveTime};
+use serde_json::{json, Value};
+use tracing::{info, warn};
+
+use crate::behavior::{self, Outcome};
+use crate::bus::Event;
+use crate::config::PatrolConfig;
+use crate::pose::Pose;
+use crate::recharge;
+
+/// A start time is still due this long after it passed, s.
+const DUE_S: i64 = 60;
+
+struct Run {
+    route: Option<String>,
+    scheduled: bool,
+    started: DateTime<Local>,
+    since: Instant,
+    distance: f64,
+    last: Option<Pose>,
+    phases: Vec<Value>,
+    /// Bumps, cliffs, and the like along the way
+    counts: BTreeMap<&'static str, u32>,
+    docked: bool,
+}
+
+struct Scheduler {
+    cfg: PatrolConfig,
+    times: Vec<NaiveTime>,
+    /// The last start time acted on
+    fired: Option<NaiveDateTime>,
+    /// A scheduled start waiting for its `behavior_started`
+    starting: bool,
+    run: Option<Run>,
+}
+
+static STATE: Mutex<Option<Scheduler>> = Mutex::new(None);
+static CONFIG: Mutex<Option<PatrolConfig>> = Mutex::new(None);
+/// The last run's report.
+static LAST: Mutex<Option<Value>> = Mutex::new(None);
+
+fn state() -> std::sync::MutexGuard<'static, Option<Scheduler>> {
+    STATE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Reports are kept for every patrol; runs are only scheduled with
+/// `patrol.enabled`.
+pub fn init(cfg: PatrolConfig) {
+    if cfg.enabled() {
+        match (&cfg.route, cfg.times()) {
+            (None, _) => warn!("patrol: no route set, nothing will be scheduled"),
+            (_, Err(e)) => warn!("{e}; nothing will be scheduled"),
+            (Some(route), Ok(times)) => info!("patrolling route {route} at {}", times.iter().map(|t| t.format("%H:%M").to_string()).collect::<Vec<_>>().join(", ")),
+        }
+    }
+    *CONFIG.lock().unwrap_or_else(|e| e.into_inner()) = Some(cfg);
+}
+
+/// Settings for patrols, `[patrol]` in the config.
+pub fn config() -> PatrolConfig {
+    CONFIG.lock().unwrap_or_else(|e| e.into_inner()).clone().unwrap_or_default()
+}
+
+pub fn begin() {
+    let cfg = config();
+    let times = match (cfg.enabled(), &cfg.route) {
+        (true, Some(_)) => cfg.times().unwrap_or_default(),
+        _ => Vec::new(),
+    };
+    *state() = Some(Scheduler { cfg, times, fired: None, starting: false, run: None });
+}
+
+/// A run in progress is reported as cancelled.
+pub fn end() {
+    let Some(mut s) = state().take() else { return };
+    if let Some(run) = s.run.take() {
+        s.report(run, Outcome::Cancelled, Some("session ended".into()));
+    }
+}
+
+/// The schedule, the run in progress, and the last report.
+pub fn status() -> Value {
+    let cfg = config();
+    let guard = state();
+    let running = guard.as_ref().and_then(|s| s.run.as_ref()).map(|r| {
+        json!({ "route": r.route, "started": r.started.to_rfc3339(), "distance_m": r.distance })
+    });
+    let next = cfg.times().ok().and_then(|times| next(&times, Local::now().naive_local()));
+    json!({
+        "enabled": cfg.enabled(),
+        "route": cfg.route,
+        "times": cfg.times,
+        "next": next.map(|t| t.format("%Y-%m-%d %H:%M").to_string()),
+        "running": running,
+        "last": LAST.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    })
+}
+
+/// The next of `times` after `now`.
+fn next(times: &[NaiveTime], now: NaiveDateTime) -> Option<NaiveDateTime> {
+    let today = now.date();
+    let tomorrow = today.succ_opt()?;
+    times.iter().flat_map(|t| [today.and_time(*t), tomorrow.and_time(*t)]).filter(|t| *t > now).min()
+}
+
+/// Follow the frame's events (including the behaviors'), and start a
+/// patrol when one is due; events to publish.
+pub fn update(pose: Option<Pose>, events: &[Event]) -> Vec<Event> {
+    let mut guard = state();
+    let Some(s) = guard.as_mut() else { return Vec::new() };
+    let mut out = Vec::new();
+    for event in events {
+        match event {
+            Event::BehaviorStarted { name } if name == "patrol" => {
+                let route = behavior::status().and_then(|b| b["progress"]["route"].as_str().map(str::to_string));
+                let scheduled = std::mem::take(&mut s.starting);
+                s.run = Some(Run {
+                    route,
+                    scheduled,
+                    started: Local::now(),
+                    since: Instant::now(),
+                    distance: 0.0,
+                    last: None,
+                    phases: Vec::new(),
+                    counts: BTreeMap::new(),
+                    docked: false,
+                });
+            }
+            Event::BehaviorEnded { name, outcome, reason } if name == "patrol" => {
+                if let Some(run) = s.run.take() {
+                    out.push(s.report(run, *outcome, reason.clone()));
+                }
+            }
+            _ => {
+                if let Some(run) = s.run.as_mut() {
+                    run.observe(event);
+                }
+            }
+        }
+    }
+    if let (Some(run), Some(p)) = (s.run.as_mut(), pose) {
+        if let Some(last) = run.last.replace(p) {
+            run.distance += (p.x - last.x).hypot(p.y - last.y);
+        }
+    }
+    s.schedule();
+    out
+}
+
+impl Run {
+    fn observe(&mut self, event: &Event) {
+        let kind = match event {
+            Event::PatrolPhase { route, phase, outcome, reason } => {
+                self.route.get_or_insert_with(|| route.clone());
+                self.phases.push(json!({ "phase": phase, "outcome": outcome, "reason": reason }));
+                return;
+            }
+            Event::Docked => {
+                self.docked = true;
+                return;
+            }
+            Event::Undocked => {
+                self.docked = false;
+                return;
+            }
+            Event::Bump { .. } => "bump",
+            Event::Cliff { .. } => "cliff",
+            Event::WheelDrop { .. } => "wheel_drop",
+            Event::ReflexStarted { .. } => "reflex",
+            Event::Geofence { zone, .. } if zone != "inside" => "geofence",
+            _ => return,
+        };
+        *self.counts.entry(kind).or_default() += 1;
+    }
+}
+
+impl Scheduler {
+    /// Start the patrol if one of the times just passed.
+    fn schedule(&mut self) {
+        let now = Local::now().naive_local();
+        let due = self.times.iter().map(|t| now.date().and_time(*t)).filter(|t| *t <= now && (now - *t).num_seconds() < DUE_S).max();
+        let Some(due) = due else { return };
+        if self.fired == Some(due) {
+            return;
+        }
+        self.fired = Some(due);
+        let Some(route) = self.cfg.route.clone() else { return };
+        if let Some(b) = behavior::status() {
+            warn!("patrol of {route} at {} skipped: {} is running", due.format("%H:%M"), b["name"].as_str().unwrap_or("a behavior"));
+            return;
+        }
+        if recharge::holding() {
+            warn!("patrol of {route} at {} skipped: the battery is low", due.format("%H:%M"));
+            return;
+        }
+        match behavior::build("patrol", &[&route]).and_then(behavior::start) {
+            Ok(()) => {
+                info!("patrolling {route} ({} schedule)", due.format("%H:%M"));
+                self.starting = true;
+            }
+            Err(e) => warn!("patrol of {route}: {e}"),
+        }
+    }
+
+    /// Write the run's report; the event summing it up.
+    fn report(&self, run: Run, outcome: Outcome, reason: Option<String>) -> Event {
+        let route = run.route.unwrap_or_else(|| "unknown".into());
+        let duration = run.since.elapsed().as_secs_f64();
+        let report = json!({
+            "route": route,
+            "scheduled": run.scheduled,
+            "started": run.started.to_rfc3339(),
+            "ended": Local::now().to_rfc3339(),
+            "duration_s": duration,
+            "distance_m": run.distance,
+            "outcome": outcome,
+            "reason": reason,
+            "docked": run.docked,
+            "phases": run.phases,
+            "events": run.counts
+        });
+        let path = self.cfg.reports_dir().join(format!("patrol-{route}-{}.json", run.started.format("%Y%m%d-%H%M%S")));
+        let text = serde_json::to_string_pretty(&report).unwrap_or_default();
+        let written = fs::create_dir_all(self.cfg.reports_dir()).and_then(|()| fs::write(&path, text));
+        let path = match written {
+            Ok(()) => {
+                info!("patrol of {route} {}: {:.2} m in {duration:.0} s, report in {}", outcome_name(outcome), run.distance, path.display());
+                Some(path.display().to_string())
+            }
+            Err(e) => {
+                warn!("patrol report not written to {}: {e}", path.display());
+                None
+            }
+        };
+        *LAST.lock().unwrap_or_else(|e| e.into_inner()) = Some(report);
+        Event::PatrolReport { route, outcome, reason, duration_s: duration, distance_m: run.distance, docked: run.docked, path }
+    }
+}
+
+fn outcome_name(outcome: Outcome) -> &'static str {
+    match outcome {
+        Outcome::Done => "done",
+        Outcome::Failed => "failed",
+        Outcome::Cancelled => "cancelled",
+        Outcome::TimedOut => "timed out",
+    }
+}
@@ -0,0 +1,150 @@
+//! Dead reckoning: the robot's 2D pose (x, y in metres, theta in radians,
+//! counter-clockwise) relative to where the session started, integrated
+//! from the wheel encoder counts when they are streamed (Create 2) or from
+//! the distance and angle packets otherwise. Published on the bus after
+//! each frame and shown by the `pose` control command.
+
+use std::f64::consts::PI;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use serde::Serialize;
+
+use crate::config::{PoseConfig, PoseSource};
+use crate::oi::SensorFrame;
+
+const DISTANCE: u8 = 19;
+const ANGLE: u8 = 20;
+const LEFT_ENCODER: u8 = 43;
+const RIGHT_ENCODER: u8 = 44;
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Pose {
+    #[serde(skip)]
+    pub time: SystemTime,
+    /// metres
+    pub x: f64,
+    pub y: f64,
+    /// radians, -π..π
+    pub theta: f64,
+    /// How far to trust it, 0 to 1, as wheel slip wears it down
+    pub confidence: f64,
+}
+
+impl Pose {
+    fn origin() -> Self {
+        Pose { time: SystemTime::now(), x: 0.0, y: 0.0, theta: 0.0, confidence: 1.0 }
+    }
+
+    /// Drive `distance` metres while turning by `turn` radians, assuming a
+    /// constant-curvature arc since the last frame.
+    fn advance(&mut self, distance: f64, turn: f64) {
+        let heading = self.theta + turn / 2.0;
+        self.x += distance * heading.cos();
+        self.y += distance * heading.sin();
+        self.theta = normalize(self.theta + turn);
+    }
+}
+
+/// An angle wrapped into -π..π.
+pub fn normalize(angle: f64) -> f64 {
+    let a = (angle + PI).rem_euclid(2.0 * PI) - PI;
+    if a == -PI {
+        PI
+    } else {
+        a
+    }
+}
+
+struct Tracker {
+    cfg: PoseConfig,
+    pose: Pose,
+    /// Encoder counts of the previous frame
+    encoders: Option<(i32, i32)>,
+}
+
+impl Tracker {
+    fn update(&mut self, frame: &SensorFrame) -> Pose {
+        let counts = frame.get(LEFT_ENCODER).zip(frame.get(RIGHT_ENCODER));
+        let use_encoders = match self.cfg.source() {
+            PoseSource::Encoders => true,
+            PoseSource::Odometry => false,
+            PoseSource::Auto => counts.is_some(),
+        };
+        // Slipping wheels turn without taking the robot anywhere
+        let hold = crate::slip::holding();
+        if use_encoders {
+            if let Some((left, right)) = counts {
+                if let Some((last_left, last_right)) = self.encoders {
+                    // The counters are 16-bit and wrap in either direction
+                    let dl = (left.wrapping_sub(last_left) as u16 as i16) as f64 * self.cfg.metres_per_count();
+                    let dr = (right.wrapping_sub(last_right) as u16 as i16) as f64 * self.cfg.metres_per_count();
+                    let distance = if hold { 0.0 } else { (dl + dr) / 2.0 };
+                    self.pose.advance(distance, (dr - dl) / self.cfg.wheel_base());
+                }
+                self.encoders = Some((left, right));
+            }
+        } else {
+            // Both reset every time they are sent, so each frame holds the
+            // motion since the previous one
+            let distance = if hold { 0.0 } else { frame.get(DISTANCE).unwrap_or(0) as f64 / 1000.0 };
+            let angle = (frame.get(ANGLE).unwrap_or(0) as f64).to_radians();
+            self.pose.advance(distance, angle);
+        }
+        self.pose.time = frame.time;
+        self.pose.confidence = crate::slip::confidence();
+        self.pose
+    }
+}
+
+static CONFIG: Mutex<Option<PoseConfig>> = Mutex::new(None);
+static TRACKER: Mutex<Option<Tracker>> = Mutex::new(None);
+
+fn tracker() -> std::sync::MutexGuard<'static, Option<Tracker>> {
+    TRACKER.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Track the pose in every session. Does nothing unless `pose.enabled`.
+pub fn init(cfg: PoseConfig) {
+    if cfg.enabled() {
+        *CONFIG.lock().unwrap_or_else(|e| e.into_inner()) = Some(cfg);
+    }
+}
+
+/// Distance between the wheels in metres, as configured for odometry.
+pub fn wheel_base() -> f64 {
+    CONFIG.lock().unwrap_or_else(|e| e.into_inner()).clone().unwrap_or_default().wheel_base()
+}
+
+pub fn metres_per_count() -> f64 {
+    CONFIG.lock().unwrap_or_else(|e| e.into_inner()).clone().unwrap_or_default().metres_per_count()
+}
+
+/// Start a session at the origin, or at `resumed`, where the session a
+/// lost link ended left off.
+pub fn begin(resumed: Option<Pose>) {
+    let cfg = CONFIG.lock().unwrap_or_else(|e| e.into_inner()).clone();
+    *tracker() = cfg.map(|cfg| Tracker { cfg, pose: resumed.unwrap_or_else(Pose::origin), encoders: None });
+}
+
+pub fn end() {
+    *tracker() = None;
+}
+
+/// Fold in a frame; the updated pose, if tracking.
+pub fn update(frame: &SensorFrame) -> Option<Pose> {
+    tracker().as_mut().map(|t| t.update(frame))
+}
+
+pub fn current() -> Option<Pose> {
+    tracker().as_ref().map(|t| t.pose)
+}
+
+/// Make the current position the origin, facing along x.
+pub fn reset() -> Result<(), String> {
+    let mut t = tracker();
+    let t = t.as_mut().ok_or("pose tracking is disabled or no robot is connected")?;
+    t.pose = Pose::origin();
+    crate::slip::reset();
+    Ok(())
+}
@@ -0,0 +1,209 @@
+//! The power profile, for a host running off a battery, where every time
+//! the daemon wakes the CPU costs runtime. `power.profile = "low"` looks
+//! for a port less often, pauses the robot's stream while it is idle and
+//! reads a frame every `power.idle_poll_ms` instead of 66 a second, and
+//! has the kernel fire the daemon's timers up to `power.timer_slack_ms`
+//! late, so they wake the host together rather than one after another.
+//!
+//! What it saves shows as the daemon's wakeups: how often its threads went
+//! to sleep and were woken again (voluntary context switches, from
+//! /proc/self/task), over 10 s windows, for `createctl power` and the
+//! Influx sink.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use serde_json::{json, Value};
+#[cfg(not(target_os = "linux"))]
+use tracing::debug;
+use tracing::info;
+#[cfg(target_os = "linux")]
+use tracing::warn;
+
+use crate::config::{PowerConfig, PowerProfile};
+use crate::wakeup;
+
+const WINDOW: Duration = Duration::from_secs(10);
+/// The threads `report` lists, busiest first
+const THREADS: usize = 8;
+
+static CONFIG: Mutex<Option<PowerConfig>> = Mutex::new(None);
+/// The session's stream is paused, the robot idle
+static SLOWED: AtomicBool = AtomicBool::new(false);
+
+fn config() -> PowerConfig {
+    CONFIG.lock().unwrap_or_else(|e| e.into_inner()).clone().unwrap_or_default()
+}
+
+/// Before any thread is started, as they take the timer slack of the thread
+/// starting them.
+pub fn init(cfg: PowerConfig) {
+    if let Some(slack) = cfg.timer_slack() {
+        set_timer_slack(slack);
+    }
+    if cfg.profile() == PowerProfile::Low {
+        info!(
+            "low-power profile: looking for a port every {} s, {}",
+            cfg.rescan().as_secs(),
+            cfg.idle_poll().map_or("streaming at full rate".to_string(), |d| format!("a frame every {} ms while idle", d.as_millis()))
+        );
+    }
+    *CONFIG.lock().unwrap_or_else(|e| e.into_inner()) = Some(cfg);
+}
+
+#[cfg(target_os = "linux")]
+fn set_timer_slack(slack: Duration) {
+    // SAFETY: a plain syscall, on the calling thread
+    if unsafe { libc::prctl(libc::PR_SET_TIMERSLACK, slack.as_nanos().max(1) as libc::c_ulong, 0, 0, 0) } != 0 {
+        warn!("can't set the timer slack: {}", std::io::Error::last_os_error());
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_timer_slack(_slack: Duration) {
+    debug!("no timer slack outside Linux: timers fire when due");
+}
+
+/// How often to look for a port when nothing says one came.
+pub fn rescan() -> Duration {
+    config().rescan()
+}
+
+/// Following whether a session's robot is idle, to slow its stream.
+pub struct Idle {
+    poll: Option<Duration>,
+    after: Duration,
+    /// Since the last turn anything happened in
+    since: Instant,
+}
+
+impl Idle {
+    pub fn new() -> Idle {
+        let cfg = config();
+        Idle { poll: cfg.idle_poll(), after: cfg.idle_after(), since: Instant::now() }
+    }
+
+    /// A turn of the session, `active` if anything happened in it; how long
+    /// to pause the stream for, once the robot has been idle long enough.
+    pub fn turn(&mut self, active: bool) -> Option<Duration> {
+        let poll = self.poll?;
+        let now = Instant::now();
+        if active {
+            self.since = now;
+        }
+        let slow = now.duration_since(self.since) >= self.after;
+        if SLOWED.swap(slow, Ordering::Relaxed) != slow {
+            if slow {
+                info!("robot idle for {} s: reading a frame every {} ms", self.after.as_secs(), poll.as_millis());
+            } else {
+                info!("robot active: streaming at full rate");
+            }
+        }
+        slow.then_some(poll)
+    }
+}
+
+impl Drop for Idle {
+    fn drop(&mut self) {
+        SLOWED.store(false, Ordering::Relaxed);
+    }
+}
+
+/// Something wants the robot: wake a session waiting out an idle poll.
+pub fn nudge() {
+    if SLOWED.load(Ordering::Relaxed) {
+        wakeup::notify();
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Thread {
+    pub tid: u32,
+    pub name: String,
+    pub wakeups_per_s: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Report {
+    pub window_s: f64,
+    pub wakeups_per_s: f64,
+    pub threads: Vec<Thread>,
+}
+
+struct Window {
+    started: Instant,
+    /// Each thread's name and count at the start
+    counts: BTreeMap<u32, (String, u64)>,
+}
+
+static WINDOWS: Mutex<(Option<Window>, Option<Report>)> = Mutex::new((None, None));
+
+/// Each thread's name and voluntary context switches so far.
+fn switches() -> BTreeMap<u32, (String, u64)> {
+    let mut out = BTreeMap::new();
+    let Ok(dir) = fs::read_dir("/proc/self/task") else { return out };
+    for entry in dir.flatten() {
+        let Some(tid) = entry.file_name().to_str().and_then(|s| s.parse().ok()) else { continue };
+        let Ok(status) = fs::read_to_string(entry.path().join("status")) else { continue };
+        let field = |key: &str| status.lines().find_map(|l| l.strip_prefix(key)).map(str::trim);
+        let Some(count) = field("voluntary_ctxt_switches:").and_then(|v| v.parse().ok()) else { continue };
+        out.insert(tid, (field("Name:").unwrap_or_default().to_string(), count));
+    }
+    out
+}
+
+/// Count the wakeups since the last call, closing the window once it is
+/// `WINDOW` long. Called from the main loop, which wakes every so often
+/// anyway.
+pub fn sample() {
+    let mut guard = WINDOWS.lock().unwrap_or_else(|e| e.into_inner());
+    let counts = switches();
+    let Some(window) = guard.0.as_ref() else {
+        guard.0 = Some(Window { started: Instant::now(), counts });
+        return;
+    };
+    let elapsed = window.started.elapsed();
+    if elapsed < WINDOW {
+        return;
+    }
+    let secs = elapsed.as_secs_f64();
+    // A thread started within the window counts from 0, one gone isn't counted
+    let mut threads: Vec<Thread> = counts
+        .iter()
+        .map(|(&tid, (name, n))| {
+            let before = window.counts.get(&tid).map_or(0, |(_, n)| *n);
+            Thread { tid, name: name.clone(), wakeups_per_s: n.saturating_sub(before) as f64 / secs }
+        })
+        .collect();
+    threads.sort_by(|a, b| b.wakeups_per_s.total_cmp(&a.wakeups_per_s));
+    let wakeups_per_s = threads.iter().map(|t| t.wakeups_per_s).sum();
+    threads.truncate(THREADS);
+    guard.1 = Some(Report { window_s: secs, wakeups_per_s, threads });
+    guard.0 = Some(Window { started: Instant::now(), counts });
+}
+
+/// The last complete window.
+pub fn report() -> Option<Report> {
+    WINDOWS.lock().unwrap_or_else(|e| e.into_inner()).1.clone()
+}
+
+/// Whether the session has its stream slowed.
+pub fn slowed() -> bool {
+    SLOWED.load(Ordering::Relaxed)
+}
+
+pub fn status() -> Value {
+    let cfg = config();
+    json!({
+        "profile": cfg.profile(),
+        "slowed": slowed(),
+        "rescan_s": cfg.rescan().as_secs(),
+        "idle_poll_ms": cfg.idle_poll().map(|d| d.as_millis() as u64),
+        "timer_slack_ms": cfg.timer_slack().map(|d| d.as_millis() as u64),
+        "wakeups": report(),
+    })
+}
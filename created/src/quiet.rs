@@ -0,0 +1,136 @@
+//! Quiet mode: while it is on, the song queue takes nothing, so no songs,
+//! status beeps, Morse code, or greeting are played, and LED animations
+//! don't start (those playing stop), for a robot that has to keep still
+//! for a while. The beeps in `quiet.critical_beeps` and LED patterns in
+//! `quiet.critical_leds` are exempt, so a robot running out of battery
+//! still says so. A song number a mission step or script plays is played
+//! regardless.
+//!
+//! Quiet mode is on during `quiet.hours`, local time, and turned on or off
+//! from the control socket or a button mapped to it. Turned by hand during
+//! quiet hours or outside them, it stays so until they next begin or end.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use chrono::{Local, NaiveDateTime, NaiveTime};
+use serde_json::{json, Value};
+use tracing::{info, warn};
+
+use crate::config::QuietConfig;
+
+#[derive(Default)]
+struct Quiet {
+    hours: Vec<(NaiveTime, NaiveTime)>,
+    critical_beeps: Vec<String>,
+    critical_leds: Vec<String>,
+    /// Turned on or off by hand, until the hours next begin or end (ever without hours)
+    set: Option<(bool, Option<NaiveDateTime>)>,
+}
+
+static STATE: Mutex<Option<Quiet>> = Mutex::new(None);
+/// Whether it was on when last asked, to log the changes the hours make
+static WAS_ON: AtomicBool = AtomicBool::new(false);
+
+fn state() -> std::sync::MutexGuard<'static, Option<Quiet>> {
+    STATE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+pub fn init(cfg: QuietConfig) {
+    let hours = cfg.hours().unwrap_or_else(|e| {
+        warn!("{e}; no quiet hours");
+        Vec::new()
+    });
+    let quiet = Quiet { hours, critical_beeps: cfg.critical_beeps(), critical_leds: cfg.critical_leds(), set: None };
+    if !quiet.hours.is_empty() {
+        info!("quiet hours {}", quiet.ranges().join(", "));
+    }
+    *state() = Some(quiet);
+}
+
+impl Quiet {
+    fn ranges(&self) -> Vec<String> {
+        self.hours.iter().map(|(start, end)| format!("{}-{}", start.format("%H:%M"), end.format("%H:%M"))).collect()
+    }
+
+    /// Whether `time` falls in the quiet hours; a range may run past midnight.
+    fn in_hours(&self, time: NaiveTime) -> bool {
+        self.hours.iter().any(|&(start, end)| if start <= end { start <= time && time < end } else { time >= start || time < end })
+    }
+
+    /// When the quiet hours next begin or end after `now`.
+    fn next_change(&self, now: NaiveDateTime) -> Option<NaiveDateTime> {
+        let today = now.date();
+        let tomorrow = today.succ_opt()?;
+        let times = self.hours.iter().flat_map(|&(start, end)| [start, end]);
+        times.flat_map(|t| [today.and_time(t), tomorrow.and_time(t)]).filter(|t| *t > now).min()
+    }
+
+    fn on(&mut self, now: NaiveDateTime) -> bool {
+        if self.set.is_some_and(|(_, until)| until.is_some_and(|until| now >= until)) {
+            self.set = None;
+        }
+        match self.set {
+            Some((quiet, _)) => quiet,
+            None => self.in_hours(now.time()),
+        }
+    }
+}
+
+fn now() -> NaiveDateTime {
+    Local::now().naive_local()
+}
+
+pub fn on() -> bool {
+    let on = state().get_or_insert_with(Quiet::default).on(now());
+    if WAS_ON.swap(on, Ordering::Relaxed) != on {
+        info!("quiet mode {}", if on { "on" } else { "off" });
+    }
+    on
+}
+
+/// Whether the beep `name` is to be played in quiet mode too.
+pub fn critical_beep(name: &str) -> bool {
+    state().as_ref().is_some_and(|q| q.critical_beeps.iter().any(|b| b == name))
+}
+
+/// Whether the LED pattern `name` is to be played in quiet mode too.
+pub fn critical_led(name: &str) -> bool {
+    state().as_ref().is_some_and(|q| q.critical_leds.iter().any(|p| p == name))
+}
+
+pub fn set(quiet: bool) -> Value {
+    {
+        let mut guard = state();
+        let q = guard.get_or_insert_with(Quiet::default);
+        q.set = Some((quiet, q.next_change(now())));
+    }
+    on();
+    status()
+}
+
+pub fn toggle() -> Value {
+    set(!on())
+}
+
+/// Back to following the quiet hours.
+pub fn auto() -> Value {
+    if let Some(q) = state().as_mut() {
+        q.set = None;
+    }
+    on();
+    status()
+}
+
+pub fn status() -> Value {
+    let quiet = on();
+    let mut guard = state();
+    let q = guard.get_or_insert_with(Quiet::default);
+    json!({
+        "quiet": quiet,
+        "hours": q.ranges(),
+        "in_hours": q.in_hours(now().time()),
+        "set_by_hand": q.set.is_some(),
+        "until": q.set.and_then(|(_, until)| until).map(|t| t.format("%Y-%m-%d %H:%M").to_string()),
+    })
+}
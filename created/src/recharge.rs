@@ -0,0 +1,228 @@
+//! Battery supervisor: when the charge or the voltage falls to the low
+//! threshold it interrupts the running behavior and sends the robot to its
+//! dock, with the host-side dock behavior or the built-in Seek Dock. It
+//! stays quiet until the battery is back above the resume levels, so a
+//! battery hovering around the threshold doesn't trigger it over and over.
+//! Docking only counts once the robot charges: the dock behavior checks
+//! that itself, and after Seek Dock the supervisor does, backing off and
+//! seeking again (the robot realigns on the base's IR beams) when the
+//! contacts touch without charging, up to `behavior.dock.retries` times.
+//! If docking fails and the battery then reaches the critical level, the
+//! robot is stopped and kept stopped until it is charged.
+
+use std::sync::Mutex;
+use std::time::Instant;
+
+use tracing::{error, info, warn};
+
+use crate::behavior::{self, dock};
+use crate::bus::Event;
+use crate::config::{DockMethod, RechargeConfig};
+use crate::motion::{self, Layer, Wheels};
+use crate::oi::{self, SensorFrame};
+use crate::pose;
+
+const VOLTAGE: u8 = 22;
+const BATTERY_CHARGE: u8 = 25;
+const BATTERY_CAPACITY: u8 = 26;
+const CHARGING_SOURCES: u8 = 34;
+const HOME_BASE: i32 = 0b10;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    /// Watching for the low threshold
+    Armed,
+    /// On the way to the dock since then, on the given attempt
+    Docking { since: Instant, attempt: u32 },
+    /// Seek Dock reached the dock at `on`: waiting for it to charge
+    Verifying { since: Instant, on: Instant, attempt: u32 },
+    /// Backing off the dock since `on`, to seek it again
+    Backing { since: Instant, on: Instant, attempt: u32 },
+    /// Docked, or docking failed: waiting for the battery to recover
+    Waiting,
+    /// Kept stopped until the battery recovers
+    Critical,
+}
+
+struct Supervisor {
+    cfg: RechargeConfig,
+    state: State,
+}
+
+static SUPERVISOR: Mutex<Option<Supervisor>> = Mutex::new(None);
+static CONFIG: Mutex<Option<RechargeConfig>> = Mutex::new(None);
+
+fn supervisor() -> std::sync::MutexGuard<'static, Option<Supervisor>> {
+    SUPERVISOR.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Supervise every session. Does nothing unless `recharge.enabled`.
+pub fn init(cfg: RechargeConfig) {
+    if cfg.enabled() {
+        *CONFIG.lock().unwrap_or_else(|e| e.into_inner()) = Some(cfg);
+    }
+}
+
+pub fn begin() {
+    let cfg = CONFIG.lock().unwrap_or_else(|e| e.into_inner()).clone();
+    *supervisor() = cfg.map(|cfg| Supervisor { cfg, state: State::Armed });
+}
+
+pub fn end() {
+    *supervisor() = None;
+    motion::release(Layer::Safety);
+}
+
+/// Whether the robot is docking or charging for a low battery, so it
+/// shouldn't be sent out.
+pub fn holding() -> bool {
+    supervisor().as_ref().is_some_and(|s| s.state != State::Armed)
+}
+
+/// Check one frame, before the behaviors step; events to publish.
+pub fn update(frame: &SensorFrame, events: &[Event]) -> Vec<Event> {
+    let mut guard = supervisor();
+    let Some(s) = guard.as_mut() else { return Vec::new() };
+    s.update(frame, events)
+}
+
+impl Supervisor {
+    fn update(&mut self, frame: &SensorFrame, events: &[Event]) -> Vec<Event> {
+        let cfg = self.cfg.clone();
+        let mut out = Vec::new();
+        let voltage_mv = frame.get(VOLTAGE);
+        let percent = match (frame.get(BATTERY_CHARGE), frame.get(BATTERY_CAPACITY)) {
+            (Some(charge), Some(capacity)) if capacity > 0 => Some(charge as f64 * 100.0 / capacity as f64),
+            _ => None,
+        };
+        let below = |p: f64, mv: i32| percent.is_some_and(|x| x <= p) || voltage_mv.is_some_and(|v| v <= mv);
+        let low = below(cfg.low_percent(), cfg.low_voltage_mv());
+        let critical = below(cfg.critical_percent(), cfg.critical_voltage_mv());
+        // Both readings have to recover (as far as they are streamed)
+        let recovered = percent.is_none_or(|p| p >= cfg.resume_percent()) && voltage_mv.is_none_or(|v| v >= cfg.resume_voltage_mv()) && (percent.is_some() || voltage_mv.is_some());
+        let docked = frame.get(CHARGING_SOURCES).is_some_and(|s| s & HOME_BASE != 0) || events.iter().any(|e| matches!(e, Event::Docked));
+
+        match self.state {
+            State::Armed if low && !docked => {
+                warn!("battery low ({}), returning to the dock", reading(percent, voltage_mv));
+                out.push(Event::BatteryLow { percent, voltage_mv });
+                behavior::cancel("battery low");
+                match cfg.method() {
+                    DockMethod::Behavior => {
+                        if let Err(e) = behavior::build("dock", &[]).and_then(behavior::start) {
+                            error!("battery low: can't start docking: {e}");
+                        }
+                    }
+                    DockMethod::Seek => {
+                        motion::halt();
+                        motion::queue(vec![oi::SEEK_DOCK]);
+                    }
+                }
+                self.state = State::Docking { since: Instant::now(), attempt: 1 };
+            }
+            State::Docking { .. } | State::Verifying { .. } | State::Backing { .. } => {
+                let Some(docking) = self.docking(frame, docked) else { return out };
+                match docking {
+                    Ok(()) => info!("docked to recharge"),
+                    Err(reason) => {
+                        warn!("battery low and docking failed: {reason}");
+                        // A dock behavior that gave up has published its own
+                        let running = behavior::status().is_some_and(|b| b["name"] == "dock");
+                        if cfg.method() == DockMethod::Seek || running {
+                            out.push(Event::DockingFailed { reason });
+                        }
+                        match cfg.method() {
+                            DockMethod::Behavior => {
+                                behavior::cancel("docking failed");
+                            }
+                            // Leaving Passive mode ends Seek Dock
+                            DockMethod::Seek => motion::queue(vec![oi::SAFE]),
+                        }
+                        motion::halt();
+                    }
+                }
+                self.state = State::Waiting;
+            }
+            _ => {}
+        }
+        if recovered && matches!(self.state, State::Waiting | State::Critical) {
+            info!("battery recovered ({}), supervising again", reading(percent, voltage_mv));
+            self.state = State::Armed;
+            motion::release(Layer::Safety);
+        }
+        if critical && !docked && matches!(self.state, State::Waiting) {
+            error!("battery critical ({}), stopping", reading(percent, voltage_mv));
+            out.push(Event::BatteryCritical { percent, voltage_mv });
+            self.state = State::Critical;
+        }
+        if self.state == State::Critical {
+            behavior::cancel("battery critical");
+            motion::propose(Layer::Safety, Wheels::STOP);
+        }
+        out
+    }
+
+    /// Follow docking; `None` while it is under way, then whether the
+    /// robot docked and charges.
+    fn docking(&mut self, frame: &SensorFrame, docked: bool) -> Option<Result<(), String>> {
+        let (since, attempt) = match self.state {
+            State::Docking { since, attempt } | State::Verifying { since, attempt, .. } | State::Backing { since, attempt, .. } => (since, attempt),
+            _ => return None,
+        };
+        if since.elapsed() > self.cfg.dock_timeout() {
+            return Some(Err(format!("not docked within {:?}", self.cfg.dock_timeout())));
+        }
+        if self.cfg.method() == DockMethod::Behavior {
+            // The dock behavior checks the charging itself, and retries
+            return if behavior::status().is_some_and(|b| b["name"] == "dock") {
+                None
+            } else if docked {
+                Some(Ok(()))
+            } else {
+                // Failed, or cancelled by hand
+                Some(Err("the dock behavior ended without docking".to_string()))
+            };
+        }
+        let cfg = behavior::config().dock();
+        match self.state {
+            State::Docking { .. } if docked => self.state = State::Verifying { since, on: Instant::now(), attempt },
+            // Off the contacts again: Seek Dock is still at it
+            State::Verifying { .. } if !docked => self.state = State::Docking { since, attempt },
+            State::Verifying { on, .. } => {
+                // Without the charging state, being on the dock has to do
+                if dock::charging(frame) != Some(false) {
+                    return Some(Ok(()));
+                }
+                if on.elapsed() < cfg.verify() {
+                    return None;
+                }
+                if attempt > cfg.retries() {
+                    return Some(Err(format!("on the dock but not charging; giving up after {attempt} attempts")));
+                }
+                warn!("on the dock but not charging, backing off to seek it again");
+                // Leaving Passive mode ends Seek Dock
+                motion::queue(vec![oi::SAFE]);
+                motion::set(Wheels::arc(-cfg.slow_speed(), 0.0, pose::wheel_base()));
+                self.state = State::Backing { since, on: Instant::now(), attempt };
+            }
+            State::Backing { on, .. } if on.elapsed().as_secs_f64() >= cfg.backup_m() * 1000.0 / cfg.slow_speed() => {
+                motion::halt();
+                motion::queue(vec![oi::SEEK_DOCK]);
+                self.state = State::Docking { since, attempt: attempt + 1 };
+            }
+            // Off contacts that didn't charge, as undocking
+            State::Backing { .. } => crate::charging_lockout::undocking(),
+            _ => {}
+        }
+        None
+    }
+}
+
+fn reading(percent: Option<f64>, voltage_mv: Option<i32>) -> String {
+    match (percent, voltage_mv) {
+        (Some(p), Some(v)) => format!("{p:.0}%, {v} mV"),
+        (Some(p), None) => format!("{p:.0}%"),
+        (None, Some(v)) => format!("{v} mV"),
+        (None, None) => "no reading".into(),
+    }
+}
@@ -0,0 +1,169 @@
+//! CSV sensor recorder: one row per decoded sensor frame, written to
+//! timestamped files that rotate by age and size.
+
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::RecvTimeoutError;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use tracing::{info, warn};
+
+use crate::bus::{Bus, Message, Receiver, Sampling};
+use crate::config::CsvConfig;
+use crate::oi::{self, SensorFrame};
+
+/// Frames buffered between the serial reader and the recorder thread
+/// (about 15 s at the 15 ms stream rate).
+const QUEUE_FRAMES: usize = 1024;
+const FILE_PREFIX: &str = "sensors-";
+
+/// Subscribe to the bus and start the recorder thread. It exits (flushing the
+/// current file) once every publisher has been dropped.
+pub fn spawn(cfg: CsvConfig, bus: &mut Bus) -> JoinHandle<()> {
+    let sampling = Sampling::new("csv recorder", cfg.rate_hz, cfg.fields.as_deref());
+    let rx = bus.subscribe("csv recorder", QUEUE_FRAMES, sampling);
+    thread::spawn(move || CsvRecorder::new(cfg).run(rx))
+}
+
+struct CsvFile {
+    out: BufWriter<File>,
+    path: PathBuf,
+    columns: Vec<u8>,
+    opened: Instant,
+    bytes: u64,
+}
+
+struct CsvRecorder {
+    cfg: CsvConfig,
+    current: Option<CsvFile>,
+}
+
+impl CsvRecorder {
+    fn new(cfg: CsvConfig) -> Self {
+        CsvRecorder { cfg, current: None }
+    }
+
+    fn run(mut self, rx: Receiver) {
+        info!("recording sensor CSV to {}", self.cfg.dir().display());
+        loop {
+            match rx.recv_timeout(Duration::from_secs(1)) {
+                Ok(Message::Sensors(frame)) => {
+                    if let Err(e) = self.write(&frame) {
+                        warn!("csv recorder: {e}");
+                        // Start over with a fresh file on the next frame
+                        self.current = None;
+                    }
+                }
+                Ok(_) => {}
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+            // Keep at most about a second of rows in memory
+            if let Some(f) = self.current.as_mut() {
+                let _ = f.out.flush();
+            }
+        }
+    }
+
+    fn write(&mut self, frame: &SensorFrame) -> Result<(), String> {
+        let rotate = match &self.current {
+            Some(f) => {
+                f.opened.elapsed() >= self.cfg.rotate_after()
+                    || f.bytes >= self.cfg.max_file_bytes()
+                    || !frame.iter().map(|(p, _)| p.id).eq(f.columns.iter().copied())
+            }
+            None => true,
+        };
+        if rotate {
+            self.rotate(frame)?;
+        }
+        let f = self.current.as_mut().expect("file opened by rotate");
+        let mut line = DateTime::<Utc>::from(frame.time).to_rfc3339_opts(SecondsFormat::Millis, true);
+        for (_, value) in frame.iter() {
+            line.push(',');
+            line.push_str(&value.to_string());
+        }
+        line.push('\n');
+        f.out.write_all(line.as_bytes()).map_err(|e| format!("write {}: {e}", f.path.display()))?;
+        f.bytes += line.len() as u64;
+        Ok(())
+    }
+
+    /// Close the current file, open a new one with a header matching
+    /// `frame`'s packets, and prune old files.
+    fn rotate(&mut self, frame: &SensorFrame) -> Result<(), String> {
+        if let Some(mut old) = self.current.take() {
+            let _ = old.out.flush();
+        }
+        let dir = self.cfg.dir();
+        fs::create_dir_all(&dir).map_err(|e| format!("create {}: {e}", dir.display()))?;
+        let stamp = DateTime::<Utc>::from(frame.time).format("%Y%m%dT%H%M%S%.3fZ");
+        let path = dir.join(format!("{FILE_PREFIX}{stamp}.csv"));
+        let file = File::create(&path).map_err(|e| format!("create {}: {e}", path.display()))?;
+        let mut out = BufWriter::new(file);
+
+        let columns: Vec<u8> = frame.iter().map(|(p, _)| p.id).collect();
+        let mut header = String::from("timestamp");
+        for (p, _) in frame.iter() {
+            header.push(',');
+            header.push_str(p.name);
+        }
+        header.push('\n');
+        out.write_all(header.as_bytes()).map_err(|e| format!("write {}: {e}", path.display()))?;
+        info!("csv recorder: writing {}", path.display());
+
+        self.current = Some(CsvFile { out, path, columns, opened: Instant::now(), bytes: header.len() as u64 });
+        prune(&dir, FILE_PREFIX, ".csv", self.cfg.max_files());
+        Ok(())
+    }
+}
+
+/// Read a recording written by this module back into sensor frames.
+pub fn read_csv(text: &str) -> Result<Vec<SensorFrame>, String> {
+    let mut lines = text.lines();
+    let header = lines.next().ok_or("empty file")?;
+    let mut columns = Vec::new();
+    for name in header.split(',').skip(1) {
+        let p = oi::packet_by_name(name).ok_or_else(|| format!("unknown column {name:?}"))?;
+        columns.push(p.id);
+    }
+    let mut frames = Vec::new();
+    for (n, line) in lines.enumerate().filter(|(_, l)| !l.is_empty()) {
+        let mut cells = line.split(',');
+        let stamp = cells.next().unwrap_or_default();
+        let time = DateTime::parse_from_rfc3339(stamp).map_err(|e| format!("line {}: timestamp: {e}", n + 2))?;
+        let mut frame = SensorFrame::new(time.into());
+        for (id, cell) in columns.iter().zip(cells) {
+            let v = cell.parse().map_err(|e| format!("line {}: {e}", n + 2))?;
+            frame.set(*id, v);
+        }
+        frames.push(frame);
+    }
+    Ok(frames)
+}
+
+/// Delete the oldest `<prefix>*<extension>` recordings in `dir` so that at
+/// most `keep` remain. Names must embed a sortable UTC timestamp.
+pub fn prune(dir: &Path, prefix: &str, extension: &str, keep: usize) {
+    let Ok(entries) = fs::read_dir(dir) else { return };
+    let mut files: Vec<PathBuf> = entries
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| {
+            p.file_name()
+                .and_then(|n| n.to_str())
+                .is_some_and(|n| n.starts_with(prefix) && n.ends_with(extension))
+        })
+        .collect();
+    files.sort();
+    let excess = files.len().saturating_sub(keep);
+    for old in &files[..excess] {
+        match fs::remove_file(old) {
+            Ok(()) => info!("removed old recording {}", old.display()),
+            Err(e) => warn!("failed to remove old recording {}: {e}", old.display()),
+        }
+    }
+}
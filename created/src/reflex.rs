@@ -0,0 +1,228 @@
+//! Reflexes: built-in escape maneuvers on a cliff, a wheel drop, a bump, or
+//! getting stuck, which take the wheels from whatever drives them (a behavior, teleop, or
+//! `createctl drive`) until they are done, on the escape layer of
+//! [`motion`]. The running behavior is
+//! suspended meanwhile, and afterwards resumed or cancelled as the
+//! trigger's `then` says. A more serious trigger (wheel drop, then cliff,
+//! then stuck, then bump) interrupts a reflex in progress; the others wait for it.
+//!
+//! Each maneuver is a short list of motion primitives: `stop` halts and
+//! holds still, `back_off` backs up and turns away from the side that
+//! triggered it, and `wiggle` backs up in steps while turning from side to
+//! side, to work free of something it is caught on, before turning away.
+//! Whatever the maneuver, the wheels are held still for as long as the
+//! trigger lasts (a wheel still dropped, a cliff still seen).
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use tracing::{info, warn};
+
+use crate::behavior::primitive::{Drive, Stop, Turn};
+use crate::behavior::{self, Behavior, Context, Status};
+use crate::bus::Event;
+use crate::config::{ReflexAction, ReflexConfig, ReflexThen, ReflexesConfig};
+use crate::motion::{self, Layer, Wheels};
+use crate::oi::SensorFrame;
+use crate::pose::Pose;
+
+const BUMPS_WHEELDROPS: u8 = 7;
+const CLIFFS: [u8; 4] = [9, 10, 11, 12];
+
+/// In order of priority.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Trigger {
+    Bump,
+    Stuck,
+    Cliff,
+    WheelDrop,
+}
+
+impl Trigger {
+    fn as_str(self) -> &'static str {
+        match self {
+            Trigger::Bump => "bump",
+            Trigger::Stuck => "stuck",
+            Trigger::Cliff => "cliff",
+            Trigger::WheelDrop => "wheel_drop",
+        }
+    }
+
+    /// The trigger and whether it is on the left, the right, or both.
+    fn of(event: &Event) -> Option<(Trigger, bool, bool)> {
+        match event {
+            Event::Bump { left, right } => Some((Trigger::Bump, *left, *right)),
+            Event::WheelDrop { left, right } => Some((Trigger::WheelDrop, *left, *right)),
+            Event::Stuck { .. } => Some((Trigger::Stuck, false, false)),
+            Event::Cliff { sensors } => {
+                let left = sensors.iter().any(|s| s.contains("left"));
+                let right = sensors.iter().any(|s| s.contains("right"));
+                Some((Trigger::Cliff, left, right))
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether it is still there in `frame`.
+    fn lasts(self, frame: &SensorFrame) -> bool {
+        let bits = frame.get(BUMPS_WHEELDROPS).unwrap_or(0);
+        match self {
+            Trigger::Bump => bits & 0b11 != 0,
+            Trigger::WheelDrop => bits & 0b1100 != 0,
+            Trigger::Cliff => CLIFFS.iter().any(|id| frame.get(*id).unwrap_or(0) != 0),
+            // Only the maneuver can tell
+            Trigger::Stuck => false,
+        }
+    }
+}
+
+fn action_name(action: ReflexAction) -> &'static str {
+    match action {
+        ReflexAction::Stop => "stop",
+        ReflexAction::BackOff => "back_off",
+        ReflexAction::Wiggle => "wiggle",
+    }
+}
+
+/// A trigger's config with its own defaults filled in.
+struct Reflex {
+    cfg: ReflexConfig,
+    enabled: bool,
+    action: ReflexAction,
+    then: ReflexThen,
+}
+
+fn reflex(cfg: &ReflexesConfig, trigger: Trigger) -> Reflex {
+    let (c, enabled, action, then) = match trigger {
+        Trigger::Cliff => (&cfg.cliff, true, ReflexAction::BackOff, ReflexThen::Resume),
+        Trigger::WheelDrop => (&cfg.wheel_drop, true, ReflexAction::Stop, ReflexThen::Cancel),
+        Trigger::Bump => (&cfg.bump, false, ReflexAction::Wiggle, ReflexThen::Resume),
+        Trigger::Stuck => (&cfg.stuck, true, ReflexAction::Wiggle, ReflexThen::Cancel),
+    };
+    let c = c.clone().unwrap_or_default();
+    Reflex { enabled: c.enabled.unwrap_or(enabled), action: c.action.unwrap_or(action), then: c.then.unwrap_or(then), cfg: c }
+}
+
+/// The maneuver, turning away from `left` (clockwise) or otherwise `right`.
+fn moves(r: &Reflex, left: bool, right: bool) -> VecDeque<Box<dyn Behavior>> {
+    let cfg = behavior::config();
+    let away = if left && !right { -1.0 } else { 1.0 };
+    let c = &r.cfg;
+    let mut moves: VecDeque<Box<dyn Behavior>> = VecDeque::new();
+    match r.action {
+        ReflexAction::Stop => moves.push_back(Box::new(Stop::new(&cfg))),
+        ReflexAction::BackOff => {
+            moves.push_back(Box::new(Drive::new(-c.backup_m(), Some(c.speed()), &cfg)));
+            moves.push_back(Box::new(Turn::new(away * c.turn(), Some(c.turn_rate()), &cfg)));
+        }
+        ReflexAction::Wiggle => {
+            let swings = c.wiggles().max(1);
+            for i in 0..swings {
+                moves.push_back(Box::new(Drive::new(-c.backup_m() / swings as f64, Some(c.speed()), &cfg)));
+                let side = if i % 2 == 0 { away } else { -away };
+                moves.push_back(Box::new(Turn::new(side * c.wiggle_angle(), Some(c.turn_rate()), &cfg)));
+            }
+            moves.push_back(Box::new(Turn::new(away * c.turn(), Some(c.turn_rate()), &cfg)));
+        }
+    }
+    moves
+}
+
+struct Active {
+    trigger: Trigger,
+    then: ReflexThen,
+    moves: VecDeque<Box<dyn Behavior>>,
+    /// Why a move failed; the rest are skipped
+    failed: Option<String>,
+}
+
+struct Reflexes {
+    cfg: ReflexesConfig,
+    active: Option<Active>,
+}
+
+static STATE: Mutex<Option<Reflexes>> = Mutex::new(None);
+static CONFIG: Mutex<Option<ReflexesConfig>> = Mutex::new(None);
+
+fn state() -> std::sync::MutexGuard<'static, Option<Reflexes>> {
+    STATE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Watch every session. Does nothing if `reflexes.enabled` is off.
+pub fn init(cfg: ReflexesConfig) {
+    if cfg.enabled() {
+        *CONFIG.lock().unwrap_or_else(|e| e.into_inner()) = Some(cfg);
+    }
+}
+
+pub fn begin() {
+    let cfg = CONFIG.lock().unwrap_or_else(|e| e.into_inner()).clone();
+    *state() = cfg.map(|cfg| Reflexes { cfg, active: None });
+}
+
+pub fn end() {
+    *state() = None;
+    motion::release(Layer::Escape);
+}
+
+/// Whether the stuck reflex is on, to try to free the robot before the
+/// behavior is cancelled.
+pub fn unsticks() -> bool {
+    state().as_ref().is_some_and(|s| reflex(&s.cfg, Trigger::Stuck).enabled)
+}
+
+/// Start, step, or finish a reflex for this frame; events to publish.
+pub fn update(frame: &SensorFrame, pose: Option<Pose>, events: &[Event]) -> Vec<Event> {
+    let mut guard = state();
+    let Some(s) = guard.as_mut() else { return Vec::new() };
+    let mut out = Vec::new();
+    let current = s.active.as_ref().map(|a| a.trigger);
+    let triggered = events
+        .iter()
+        .filter_map(Trigger::of)
+        .map(|(trigger, left, right)| (trigger, left, right, reflex(&s.cfg, trigger)))
+        .filter(|(trigger, _, _, r)| r.enabled && current.is_none_or(|c| *trigger > c))
+        .max_by_key(|(trigger, ..)| *trigger);
+    if let Some((trigger, left, right, r)) = triggered {
+        if let Some(old) = s.active.take() {
+            info!("{} reflex interrupted by a {} reflex", old.trigger.as_str(), trigger.as_str());
+        }
+        info!("{} reflex: {}", trigger.as_str(), action_name(r.action));
+        out.push(Event::ReflexStarted { trigger: trigger.as_str().to_string(), action: action_name(r.action).to_string() });
+        s.active = Some(Active { trigger, then: r.then, moves: moves(&r, left, right), failed: None });
+    }
+    let Some(active) = s.active.as_mut() else { return out };
+    let mut ctx = Context { frame, pose, events, now: Instant::now(), emit: Vec::new() };
+    while let Some(m) = active.moves.front_mut() {
+        match m.step(&mut ctx) {
+            Status::Running(wheels) => {
+                motion::propose(Layer::Escape, wheels);
+                return out;
+            }
+            Status::Done => {
+                active.moves.pop_front();
+            }
+            Status::Failed(reason) => {
+                warn!("{} reflex: {} failed: {reason}", active.trigger.as_str(), m.name());
+                active.failed = Some(format!("{}: {reason}", m.name()));
+                active.moves.clear();
+            }
+        }
+    }
+    if active.trigger.lasts(frame) {
+        motion::propose(Layer::Escape, Wheels::STOP);
+        return out;
+    }
+    // Whatever drove the wheels before starts again from a stop
+    motion::release(Layer::Escape);
+    motion::stop();
+    let Some(done) = s.active.take() else { return out };
+    drop(guard);
+    let cancelled = match done.then {
+        ReflexThen::Cancel => behavior::cancel(&format!("{} reflex", done.trigger.as_str())).is_some(),
+        ReflexThen::Resume => false,
+    };
+    out.push(Event::ReflexEnded { trigger: done.trigger.as_str().to_string(), cancelled, reason: done.failed });
+    out
+}
@@ -0,0 +1,119 @@
+//! Handing the port over for a while: `createctl release` ends the session
+//! with the wheels stopped and the robot in Passive mode, keeping its OI
+//! mode, pose, and running behavior as a lost link would, and closes the
+//! port, its lock file and latency timer put back. The daemon then leaves
+//! the port alone, so a firmware updater, a terminal, or a test program can
+//! have it without the service being stopped. `createctl reclaim`, or with
+//! `release <seconds>` that long passing, takes it back: the daemon looks
+//! for the robot at once, and the session on it picks up where the
+//! released one was, however long it was away.
+
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use chrono::{SecondsFormat, Utc};
+use serde_json::{json, Value};
+use tracing::info;
+
+use crate::wakeup;
+
+/// Why a released session ended, as its `disconnected` event says
+pub const REASON: &str = "port released";
+/// How long `release` waits for the session to let go of the port
+#[cfg_attr(not(unix), allow(dead_code))]
+const WAIT: Duration = Duration::from_secs(5);
+
+struct Release {
+    since: Instant,
+    at: String,
+    /// Reclaimed by itself after this long
+    until: Option<Duration>,
+    /// The worker has let go of the port
+    done: bool,
+    /// The port let go of, if a session had it
+    device: Option<String>,
+}
+
+static STATE: Mutex<Option<Release>> = Mutex::new(None);
+/// Notified once the worker has let go
+static LET_GO: Condvar = Condvar::new();
+
+fn state() -> std::sync::MutexGuard<'static, Option<Release>> {
+    STATE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Release the port, for `until` or until reclaimed.
+pub fn release(until: Option<Duration>) -> Value {
+    let mut guard = state();
+    match guard.as_mut() {
+        Some(r) => r.until = until.map(|d| r.since.elapsed() + d),
+        None => {
+            info!("releasing the port{}", until.map_or(String::new(), |d| format!(" for {} s", d.as_secs())));
+            *guard = Some(Release { since: Instant::now(), at: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true), until, done: false, device: None });
+        }
+    }
+    wakeup::notify();
+    json!({ "released": true })
+}
+
+/// Wait for the worker to let go of the released port, for up to `WAIT`.
+#[cfg_attr(not(unix), allow(dead_code))]
+pub fn wait() -> Result<Value, String> {
+    let guard = LET_GO.wait_timeout_while(state(), WAIT, |s| s.as_ref().is_some_and(|r| !r.done)).unwrap_or_else(|e| e.into_inner()).0;
+    match guard.as_ref() {
+        Some(r) if r.done => Ok(json!({ "released": true, "device": r.device, "reclaim_in_s": reclaim_in(r) })),
+        Some(_) => Err(format!("the session hasn't let go of the port after {} s; check `createctl release status` before using it", WAIT.as_secs())),
+        None => Err("reclaimed before it was let go of".into()),
+    }
+}
+
+fn reclaim_in(r: &Release) -> Option<f64> {
+    r.until.map(|d| d.saturating_sub(r.since.elapsed()).as_secs_f64())
+}
+
+/// When a timed release runs out.
+pub fn deadline() -> Option<Instant> {
+    let guard = state();
+    let r = guard.as_ref()?;
+    Some(r.since + r.until?)
+}
+
+/// Whether the port is released: the session is to let go of it, and the
+/// worker to leave it alone.
+pub fn held() -> bool {
+    let mut guard = state();
+    let Some(r) = guard.as_ref() else { return false };
+    if r.until.is_some_and(|d| r.since.elapsed() >= d) {
+        info!("reclaiming the port, released {:.0} s ago", r.since.elapsed().as_secs_f64());
+        *guard = None;
+        return false;
+    }
+    true
+}
+
+/// The worker has let go of the port, `device` if a session had it.
+pub fn let_go(device: Option<&str>) {
+    let mut guard = state();
+    let Some(r) = guard.as_mut().filter(|r| !r.done) else { return };
+    r.done = true;
+    r.device = device.map(str::to_string);
+    match device {
+        Some(device) => info!("{device} is released: `createctl reclaim` to take it back"),
+        None => info!("port released, with no robot connected: not looking for one until `createctl reclaim`"),
+    }
+    LET_GO.notify_all();
+}
+
+/// Take the port back.
+pub fn reclaim() -> Result<Value, String> {
+    let r = state().take().ok_or("the port isn't released")?;
+    wakeup::notify();
+    info!("reclaiming the port, released {:.0} s ago", r.since.elapsed().as_secs_f64());
+    Ok(json!({ "reclaimed": true, "device": r.device, "released_s": r.since.elapsed().as_secs_f64() }))
+}
+
+pub fn status() -> Value {
+    let guard = state();
+    let Some(r) = guard.as_ref() else { return json!({ "released": false }) };
+    json!({ "released": true, "since": r.at, "let_go": r.done, "device": r.device, "reclaim_in_s": reclaim_in(r) })
+}
@@ -0,0 +1,148 @@
+//! `created replay <file>`: feed a CSV or MCAP recording through the normal
+//! session (stream parser, bus, recorders) over a virtual port, so a run
+//! observed in the field can be reproduced on a desk.
+
+use std::fs;
+use std::io::{self, ErrorKind, Read, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Receiver;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tracing::field::Empty;
+use tracing::{info, info_span};
+
+use crate::bus::Bus;
+use crate::oi::{self, SensorFrame};
+use crate::transport::Transport;
+use crate::{mcap, recorder, robot};
+
+/// Longest pause honored between two recorded frames; longer gaps (e.g. a
+/// disconnect during the run) are shortened so the session does not stall.
+const MAX_GAP: Duration = Duration::from_secs(1);
+/// Like a serial port's read timeout, so the session can check for shutdown.
+const READ_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Load the sensor frames of a recording, choosing the format by extension.
+pub fn load(path: &Path) -> Result<Vec<SensorFrame>, String> {
+    let ext = path.extension().and_then(|e| e.to_str()).unwrap_or_default();
+    match ext {
+        "csv" => {
+            let text = fs::read_to_string(path).map_err(|e| format!("read {}: {e}", path.display()))?;
+            recorder::read_csv(&text)
+        }
+        "mcap" => {
+            let data = fs::read(path).map_err(|e| format!("read {}: {e}", path.display()))?;
+            let mut frames = Vec::new();
+            for (topic, time, body) in mcap::read_messages(&data)? {
+                if topic != "/sensors" {
+                    continue;
+                }
+                let values: serde_json::Map<String, serde_json::Value> =
+                    serde_json::from_slice(&body).map_err(|e| format!("sensor message: {e}"))?;
+                let mut frame = SensorFrame::new(time);
+                for (name, v) in values {
+                    if let (Some(p), Some(v)) = (oi::packet_by_name(&name), v.as_i64()) {
+                        frame.set(p.id, v as i32);
+                    }
+                }
+                frames.push(frame);
+            }
+            Ok(frames)
+        }
+        _ => Err(format!("unsupported recording type {:?} (expected .csv or .mcap)", ext)),
+    }
+}
+
+/// Replay `frames` at `speed` times real time (0 = as fast as possible)
+/// through a regular session. Returns once the recording is exhausted or on
+/// shutdown.
+pub fn run(path: &Path, frames: Vec<SensorFrame>, speed: f64, stream: &[u8], bus: &Bus, rx: &Receiver<()>) -> Result<(), String> {
+    let device = format!("replay:{}", path.display());
+    let _session = info_span!("session", device = %device, baud = 0, oi_mode = Empty).entered();
+    info!("replaying {} sensor frames from {} at {}x", frames.len(), path.display(), speed);
+    let finished = Arc::new(AtomicBool::new(false));
+    let port = ReplayPort::new(frames, speed, finished.clone());
+    match robot::run_port(Box::new(port), &device, 0, stream, bus, rx, None) {
+        Err(_) if finished.load(Ordering::Relaxed) => {
+            info!("end of recording");
+            Ok(())
+        }
+        other => other,
+    }
+}
+
+/// Virtual port that streams recorded frames with their original spacing and
+/// swallows everything written to it.
+struct ReplayPort {
+    frames: Vec<SensorFrame>,
+    next: usize,
+    speed: f64,
+    /// Wall-clock instant at which frame `next` is due
+    due: Instant,
+    pending: Vec<u8>,
+    finished: Arc<AtomicBool>,
+}
+
+impl ReplayPort {
+    fn new(frames: Vec<SensorFrame>, speed: f64, finished: Arc<AtomicBool>) -> Self {
+        ReplayPort { frames, next: 0, speed, due: Instant::now(), pending: Vec::new(), finished }
+    }
+
+    fn gap_after(&self, i: usize) -> Duration {
+        if self.speed <= 0.0 {
+            return Duration::ZERO;
+        }
+        let (Some(a), Some(b)) = (self.frames.get(i), self.frames.get(i + 1)) else {
+            return Duration::ZERO;
+        };
+        let gap = b.time.duration_since(a.time).unwrap_or_default().min(MAX_GAP);
+        gap.div_f64(self.speed)
+    }
+}
+
+impl Read for ReplayPort {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pending.is_empty() {
+            let Some(frame) = self.frames.get(self.next) else {
+                self.finished.store(true, Ordering::Relaxed);
+                return Err(io::Error::new(ErrorKind::UnexpectedEof, "end of recording"));
+            };
+            let wait = self.due.saturating_duration_since(Instant::now());
+            if wait > READ_TIMEOUT {
+                thread::sleep(READ_TIMEOUT);
+                return Err(io::Error::new(ErrorKind::TimedOut, "replay: next frame not due"));
+            }
+            thread::sleep(wait);
+            self.pending = oi::encode_stream_frame(frame);
+            self.due += self.gap_after(self.next);
+            self.next += 1;
+        }
+        let n = buf.len().min(self.pending.len());
+        buf[..n].copy_from_slice(&self.pending[..n]);
+        self.pending.drain(..n);
+        Ok(n)
+    }
+}
+
+impl Write for ReplayPort {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Transport for ReplayPort {
+    fn kind(&self) -> &'static str {
+        "replay"
+    }
+
+    fn set_baud_rate(&mut self, _baud: u32) -> Result<(), String> {
+        Ok(())
+    }
+}
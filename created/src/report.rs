@@ -0,0 +1,171 @@
+//! Daily activity report: runtime, distance, dockings, and notable events of
+//! one local day, built from the event journal and written as Markdown to
+//! `<dir>/report-YYYY-MM-DD.md`. `report.command`, if set, receives each new
+//! report on stdin (e.g. `mosquitto_pub -t robots/create-1/report -s` or a
+//! chat webhook via `curl --data-binary @-`).
+
+use std::fmt::Write as _;
+use std::fs;
+use std::io::Write as _;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use chrono::{DateTime, Days, Local, NaiveDate, TimeZone};
+use serde_json::Value;
+use tracing::{info, warn};
+
+use crate::config::{JournalConfig, ReportConfig};
+use crate::journal;
+
+/// Notable events listed per report, beyond which only a count is given.
+const MAX_NOTABLE: usize = 50;
+
+pub fn spawn(cfg: ReportConfig, journal_cfg: JournalConfig, robot_id: String) {
+    thread::spawn(move || run(cfg, journal_cfg, robot_id));
+}
+
+fn run(cfg: ReportConfig, journal_cfg: JournalConfig, robot_id: String) {
+    info!("writing daily reports to {} at {:02}:00", cfg.dir().display(), cfg.hour());
+    loop {
+        // Catches up on yesterday's report after a restart, too
+        let yesterday = Local::now().date_naive() - Days::new(1);
+        let path = report_path(&cfg, yesterday);
+        if !path.exists() {
+            match generate(&journal_cfg, yesterday, &robot_id) {
+                Ok(text) => {
+                    if let Err(e) = write(&cfg, &path, &text) {
+                        warn!("daily report: {e}");
+                    }
+                }
+                Err(e) => warn!("daily report for {yesterday}: {e}"),
+            }
+        }
+        thread::sleep(until_next(cfg.hour()));
+    }
+}
+
+fn report_path(cfg: &ReportConfig, date: NaiveDate) -> PathBuf {
+    cfg.dir().join(format!("report-{date}.md"))
+}
+
+fn write(cfg: &ReportConfig, path: &PathBuf, text: &str) -> Result<(), String> {
+    fs::create_dir_all(cfg.dir()).map_err(|e| format!("create {}: {e}", cfg.dir().display()))?;
+    fs::write(path, text).map_err(|e| format!("write {}: {e}", path.display()))?;
+    info!("wrote daily report {}", path.display());
+    if let Some(cmd) = cfg.command.as_deref() {
+        publish(cmd, text)?;
+    }
+    Ok(())
+}
+
+fn publish(cmd: &str, text: &str) -> Result<(), String> {
+    let mut child = Command::new("sh")
+        .args(["-c", cmd])
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("run {cmd:?}: {e}"))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        let _ = stdin.write_all(text.as_bytes());
+    }
+    match child.wait() {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => Err(format!("{cmd:?} exited with {status}")),
+        Err(e) => Err(format!("{cmd:?}: {e}")),
+    }
+}
+
+/// Time until `hour`:00 local time, today or tomorrow.
+fn until_next(hour: u32) -> Duration {
+    let now = Local::now();
+    let today = now.date_naive().and_hms_opt(hour, 0, 0).and_then(|t| Local.from_local_datetime(&t).earliest());
+    let next = match today {
+        Some(t) if t > now => t,
+        _ => {
+            let tomorrow = now.date_naive() + Days::new(1);
+            tomorrow.and_hms_opt(hour, 0, 0).and_then(|t| Local.from_local_datetime(&t).earliest()).unwrap_or(now + chrono::Duration::hours(24))
+        }
+    };
+    (next - now).to_std().unwrap_or(Duration::from_secs(3600)).max(Duration::from_secs(60))
+}
+
+fn local_midnight(date: NaiveDate) -> SystemTime {
+    let start = date.and_hms_opt(0, 0, 0).and_then(|t| Local.from_local_datetime(&t).earliest());
+    start.map(SystemTime::from).unwrap_or(SystemTime::UNIX_EPOCH)
+}
+
+/// The report for `date` (local time) as Markdown.
+pub fn generate(journal_cfg: &JournalConfig, date: NaiveDate, robot_id: &str) -> Result<String, String> {
+    let path = journal_cfg.path();
+    if !path.exists() {
+        return Err(format!("no event journal at {} (enable [journal])", path.display()));
+    }
+    let events = journal::read_events(&path, local_midnight(date), local_midnight(date + Days::new(1)))?;
+
+    let mut sessions = 0;
+    let (mut runtime, mut driving, mut idle, mut distance, mut energy) = (0.0, 0.0, 0.0, 0.0, 0.0);
+    let (mut dockings, mut undockings, mut bumps, mut cliffs, mut drops) = (0, 0, 0, 0, 0);
+    let mut notable = Vec::new();
+    for (time, kind, data) in &events {
+        let at = DateTime::parse_from_rfc3339(time).map(|t| t.with_timezone(&Local).format("%H:%M:%S").to_string()).unwrap_or_default();
+        let num = |key: &str| data.get(key).and_then(Value::as_f64).unwrap_or(0.0);
+        let text = |key: &str| data.get(key).and_then(Value::as_str).unwrap_or_default().to_string();
+        match kind.as_str() {
+            "session_summary" => sessions += 1,
+            "activity" => {
+                runtime += num("duration_s");
+                driving += num("driving_s");
+                idle += num("idle_s");
+                distance += num("distance_m");
+                energy += num("energy_wh");
+            }
+            "docked" => dockings += 1,
+            "undocked" => undockings += 1,
+            "bump" => bumps += 1,
+            "cliff" => {
+                cliffs += 1;
+                notable.push(format!("{at} cliff: {}", data.get("sensors").map(|s| s.to_string()).unwrap_or_default()));
+            }
+            "wheel_drop" => {
+                drops += 1;
+                notable.push(format!("{at} wheel drop"));
+            }
+            "disconnected" if text("reason") != "shutdown" => {
+                notable.push(format!("{at} session on {} ended: {}", text("device"), text("reason")));
+            }
+            "battery_degraded" => {
+                notable.push(format!("{at} battery health {:.0}% ({} mAh)", num("health_percent"), num("capacity_mah")));
+            }
+            _ => {}
+        }
+    }
+
+    let mut out = String::new();
+    let _ = writeln!(out, "# {robot_id}: {date}\n");
+    if events.is_empty() {
+        let _ = writeln!(out, "No activity recorded.");
+        return Ok(out);
+    }
+    let _ = writeln!(out, "- Sessions ended: {sessions}");
+    let _ = writeln!(out, "- Runtime: {} (driving {}, idle {})", hm(runtime), hm(driving), hm(idle));
+    let _ = writeln!(out, "- Distance: {distance:.1} m");
+    let _ = writeln!(out, "- Energy: {energy:.1} Wh");
+    let _ = writeln!(out, "- Dockings: {dockings} (undocked {undockings} times)");
+    let _ = writeln!(out, "- Bumps: {bumps}, cliffs: {cliffs}, wheel drops: {drops}");
+    if !notable.is_empty() {
+        let _ = writeln!(out, "\n## Notable events\n");
+        for line in notable.iter().take(MAX_NOTABLE) {
+            let _ = writeln!(out, "- {line}");
+        }
+        if notable.len() > MAX_NOTABLE {
+            let _ = writeln!(out, "- ... and {} more", notable.len() - MAX_NOTABLE);
+        }
+    }
+    Ok(out)
+}
+
+fn hm(secs: f64) -> String {
+    let mins = (secs / 60.0).round() as u64;
+    format!("{}h {:02}m", mins / 60, mins % 60)
+}
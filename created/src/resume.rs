@@ -0,0 +1,60 @@
+//! Picking up after a lost link. A session that ends because the port
+//! failed or the stream stalled (a USB cable pulled or knocked loose, a
+//! flaky adapter, the OI restarting) leaves what it was doing here: the OI
+//! mode, the pose estimate, and the running behavior. When the same robot,
+//! by USB serial number where there is one, is connected again within
+//! `serial.resume_s`, or after the port was released to another program
+//! however long that took, the new session starts from them instead of
+//! afresh; after that, or on another robot, they are dropped.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use tracing::info;
+
+use crate::behavior::Behavior;
+use crate::pose::Pose;
+
+/// What a lost link left off.
+pub struct Saved {
+    device: String,
+    at: Instant,
+    pub mode: Option<i32>,
+    pub pose: Option<Pose>,
+    pub behavior: Option<Box<dyn Behavior>>,
+}
+
+impl Saved {
+    pub fn after(&self) -> Duration {
+        self.at.elapsed()
+    }
+}
+
+/// A session's part in it: the robot it is on, and what to start from.
+pub struct Session {
+    pub device: String,
+    pub saved: Option<Saved>,
+}
+
+static SAVED: Mutex<Option<Saved>> = Mutex::new(None);
+
+/// Keep what the session on `device` left off as its link was lost.
+pub fn save(device: &str, mode: Option<i32>, pose: Option<Pose>, behavior: Option<Box<dyn Behavior>>) {
+    let saved = Saved { device: device.to_string(), at: Instant::now(), mode, pose, behavior };
+    *SAVED.lock().unwrap_or_else(|e| e.into_inner()) = Some(saved);
+}
+
+/// A session starting on `device`: what its last one left off, if that was
+/// on this robot within `within`.
+pub fn start(device: &str, within: Duration) -> Session {
+    let saved = SAVED.lock().unwrap_or_else(|e| e.into_inner()).take();
+    let saved = saved.filter(|s| {
+        let resumes = s.device == device && s.at.elapsed() <= within;
+        // Dropped as a matter of course with resuming off
+        if !resumes && s.behavior.is_some() && !within.is_zero() {
+            info!("not resuming behavior {} on {device}: lost on {} {:.0} s ago", s.behavior.as_ref().map_or("", |b| b.name()), s.device, s.at.elapsed().as_secs_f64());
+        }
+        resumes
+    });
+    Session { device: device.to_string(), saved }
+}
@@ -0,0 +1,194 @@
+//! A lock-free ring buffer for one producer and one consumer that never
+//! has the producer wait: once it is full, a push overwrites the oldest
+//! value not yet popped. The session thread hands sensor frames to each
+//! telemetry sink through one (see `bus`), so a sink that stalls (an MQTT
+//! hiccup, a slow disk) loses its oldest frames instead of holding the
+//! serial reader up, and catches up on the newest.
+//!
+//! Each slot holds a boxed value with its sequence number, swapped in and
+//! out whole, so the two ends never share a value: the producer drops what
+//! it swaps out (a value overwritten unread), and the consumer only hands
+//! values on in sequence, skipping those it was lapped past.
+
+use std::marker::PhantomData;
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, AtomicU64, Ordering};
+
+struct Slot<T> {
+    seq: u64,
+    value: T,
+}
+
+pub struct Ring<T> {
+    slots: Box<[AtomicPtr<Slot<T>>]>,
+    /// The sequence number of the next value pushed
+    head: AtomicU64,
+    _values: PhantomData<*mut T>,
+}
+
+// SAFETY: values are moved through the ring, from the thread pushing them
+// to the one popping (or overwriting) them, and never shared, as with a
+// channel
+unsafe impl<T: Send> Send for Ring<T> {}
+unsafe impl<T: Send> Sync for Ring<T> {}
+
+impl<T> Ring<T> {
+    pub fn new(capacity: usize) -> Self {
+        let slots = (0..capacity.max(1)).map(|_| AtomicPtr::new(ptr::null_mut())).collect();
+        Ring { slots, head: AtomicU64::new(0), _values: PhantomData }
+    }
+
+    fn slot(&self, seq: u64) -> &AtomicPtr<Slot<T>> {
+        &self.slots[(seq % self.slots.len() as u64) as usize]
+    }
+
+    /// Push `value`, from one thread at a time; whether it overwrote the
+    /// oldest value, the ring being full.
+    pub fn push(&self, value: T) -> bool {
+        let seq = self.head.load(Ordering::Relaxed);
+        let new = Box::into_raw(Box::new(Slot { seq, value }));
+        let old = self.slot(seq).swap(new, Ordering::AcqRel);
+        self.head.store(seq + 1, Ordering::Release);
+        if old.is_null() {
+            return false;
+        }
+        // SAFETY: swapped out of its slot, so no one else has it
+        drop(unsafe { Box::from_raw(old) });
+        true
+    }
+
+    /// Pop the oldest value, from one thread at a time; `next` is that
+    /// thread's place in the ring, from 0.
+    pub fn pop(&self, next: &mut u64) -> Option<T> {
+        loop {
+            let head = self.head.load(Ordering::Acquire);
+            // Whatever it was lapped past has been overwritten
+            *next = (*next).max(head.saturating_sub(self.slots.len() as u64));
+            if *next >= head {
+                return None;
+            }
+            let taken = self.slot(*next).swap(ptr::null_mut(), Ordering::AcqRel);
+            if taken.is_null() {
+                *next += 1;
+                continue;
+            }
+            // SAFETY: as in `push`
+            let slot = unsafe { Box::from_raw(taken) };
+            if slot.seq < *next {
+                continue;
+            }
+            // Ahead of `next` if it was lapped since `head` was read; the
+            // older values still in the ring are skipped
+            *next = slot.seq + 1;
+            return Some(slot.value);
+        }
+    }
+}
+
+impl<T> Drop for Ring<T> {
+    fn drop(&mut self) {
+        for slot in self.slots.iter_mut() {
+            let p = *slot.get_mut();
+            if !p.is_null() {
+                // SAFETY: the ring is going, and with it the last reference
+                drop(unsafe { Box::from_raw(p) });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    fn drain(ring: &Ring<u32>, next: &mut u64) -> Vec<u32> {
+        std::iter::from_fn(|| ring.pop(next)).collect()
+    }
+
+    #[test]
+    fn pops_in_order() {
+        let ring = Ring::new(4);
+        let mut next = 0;
+        assert_eq!(ring.pop(&mut next), None);
+        for v in 1..=3 {
+            assert!(!ring.push(v));
+        }
+        assert_eq!(drain(&ring, &mut next), [1, 2, 3]);
+        ring.push(4);
+        assert_eq!(drain(&ring, &mut next), [4]);
+    }
+
+    #[test]
+    fn overwrites_the_oldest_when_full() {
+        let ring = Ring::new(3);
+        let mut next = 0;
+        let overwrote: Vec<bool> = (1..=5).map(|v| ring.push(v)).collect();
+        assert_eq!(overwrote, [false, false, false, true, true]);
+        assert_eq!(drain(&ring, &mut next), [3, 4, 5]);
+    }
+
+    #[test]
+    fn skips_what_a_lapped_reader_missed() {
+        let ring = Ring::new(4);
+        let mut next = 0;
+        ring.push(1);
+        ring.push(2);
+        assert_eq!(ring.pop(&mut next), Some(1));
+        for v in 3..=10 {
+            ring.push(v);
+        }
+        assert_eq!(drain(&ring, &mut next), [7, 8, 9, 10]);
+        assert_eq!(next, 10);
+    }
+
+    #[test]
+    fn drops_every_value_once() {
+        let token = Arc::new(());
+        let ring = Ring::new(3);
+        let mut next = 0;
+        for _ in 0..5 {
+            ring.push(token.clone());
+        }
+        // Two overwritten
+        assert_eq!(Arc::strong_count(&token), 4);
+        drop(ring.pop(&mut next));
+        assert_eq!(Arc::strong_count(&token), 3);
+        drop(ring);
+        assert_eq!(Arc::strong_count(&token), 1);
+    }
+
+    #[test]
+    fn one_thread_pushing_and_one_popping() {
+        let count: u32 = if cfg!(miri) { 500 } else { 200_000 };
+        let ring = Arc::new(Ring::new(16));
+        let producer = {
+            let ring = ring.clone();
+            thread::spawn(move || {
+                for v in 0..count {
+                    ring.push(v);
+                    if v % 64 == 0 {
+                        thread::yield_now();
+                    }
+                }
+            })
+        };
+        let mut next = 0;
+        let mut last = None;
+        let mut received = 0;
+        while last != Some(count - 1) {
+            match ring.pop(&mut next) {
+                Some(v) => {
+                    assert!(last.is_none_or(|l| v > l), "{v} after {last:?}");
+                    last = Some(v);
+                    received += 1;
+                }
+                None => thread::yield_now(),
+            }
+        }
+        producer.join().unwrap();
+        assert_eq!(ring.pop(&mut next), None);
+        assert!(received <= count);
+    }
+}
@@ -0,0 +1,944 @@
+use std::io::{ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use tracing::field::Empty;
+use tracing::{debug, debug_span, info, info_span, warn, Span};
+
+use crate::bus::{Bus, Event, Message};
+use crate::command;
+use crate::config::{SensorsConfig, SerialConfig, WakeLine};
+use crate::detect::EventDetector;
+use crate::discovery;
+use crate::flight;
+use crate::oi::{self, SensorFrame, StreamParser};
+use crate::motion::{self, Wheels};
+use crate::{aliases, audit, battery, beeps, behavior, bluetooth, bumper, buttons, charging_lockout, cliff, estop, ftdi, geofence, governor, hotplug, leds, lockfile, low_voltage, mock, overcurrent, patrol, pose, power, quiet, recharge, reflex, release, resume, safety, slip, sniffer, song, stats, stuck, tcp, thermal, timing, transport, wakeup, wheel_drop};
+use crate::transport::Transport;
+
+/// How long the sensor stream may stay silent before the session is dropped.
+const STREAM_STALL: Duration = Duration::from_secs(5);
+/// How long a reply to a sensor query may take.
+const REPLY_WAIT: Duration = Duration::from_millis(500);
+/// The longest wait between tries to connect to a Bluetooth adapter.
+const BLUETOOTH_BACKOFF: Duration = Duration::from_secs(30);
+/// How long the worker sleeps when it can't wait for events instead.
+const TICK: Duration = Duration::from_millis(200);
+
+// ---------------- iRobot Create OI handling ----------------
+
+pub fn robot_worker(rx: Receiver<()>, serial_cfg: SerialConfig, sensors_cfg: SensorsConfig, bus: Bus) {
+    let stream = sensors_cfg.stream();
+    let mut monitor = open_hotplug(&serial_cfg);
+    let mut filter = discovery::Filter::new(&serial_cfg);
+    // When to look for a port: at once, on a device plugged in, or again
+    // after a while when a session failed or, without hotplug events, no
+    // port was found
+    let mut scan = Some(Instant::now());
+    // Looks in a row that found nothing to claim
+    let mut failures = 0;
+    // The port is released to another program, or was and no session has
+    // followed yet
+    let mut released = false;
+    let mut after_release = false;
+    loop {
+        if rx.try_recv().is_ok() {
+            info!("robot worker shutdown");
+            return;
+        }
+
+        if release::held() {
+            // Nothing to let go of while no session has the port
+            release::let_go(None);
+            released = true;
+        } else if released {
+            released = false;
+            after_release = true;
+            scan = Some(Instant::now());
+        }
+
+        if !released && scan.is_some_and(|at| at <= Instant::now()) {
+            scan = None;
+            let ports = discovery::candidates(&serial_cfg, &filter);
+            let (claimed, retry) = claim(&ports, &serial_cfg, &mut filter);
+            // Released while it was being probed: dropped, the port closes
+            if let Some(Claimed { path: port_path, baud, port }) = claimed.filter(|_| !release::held()) {
+                let robot = aliases::begin(&aliases::key(&port_path));
+                // Everything logged while handling this device carries its path, name, transport, and baud
+                let _session = info_span!("session", device = %port_path.display(), robot = robot.as_deref().unwrap_or("unnamed"), transport = port.kind(), baud, oi_mode = Empty).entered();
+                // After a release, however long it was: the robot was handed over, not lost
+                let within = if after_release { Duration::MAX } else { serial_cfg.resume() };
+                after_release = false;
+                let resume = Some(resume::start(&discovery::identity(&port_path), within));
+                let result = run_port(port, &port_path.display().to_string(), baud, &stream, &bus, &rx, resume);
+                aliases::end();
+                let reason = match &result {
+                    Ok(()) => "shutdown".to_string(),
+                    Err(e) => e.clone(),
+                };
+                let disconnected = Event::Disconnected { device: port_path.display().to_string(), reason };
+                // Handed over, not lost: nothing to beep about
+                if !release::held() {
+                    beeps::react(&disconnected);
+                }
+                bus.event(disconnected);
+                match result {
+                    Ok(()) => {
+                        info!("robot worker shutdown");
+                        return;
+                    }
+                    Err(_) if release::held() => release::let_go(Some(&port_path.display().to_string())),
+                    Err(e) => {
+                        warn!("session on {} ended: {}", port_path.display(), e);
+                        flight::dump(&format!("session on {} ended: {e}", port_path.display()));
+                    }
+                }
+                // The device may still be there with the robot off: no event will say it is back on
+                scan = Some(Instant::now() + power::rescan());
+                failures = 0;
+            } else if retry || monitor.is_none() {
+                failures += 1;
+                scan = Some(Instant::now() + retry_after(&serial_cfg, failures));
+            }
+        }
+
+        // Asleep until there is something to do: a device plugged in, shutdown,
+        // a release or reclaim, the next look, or a timed release running out
+        let until = if released { release::deadline() } else { scan };
+        let timeout = until.map(|at| at.saturating_duration_since(Instant::now()));
+        match wakeup::wait(monitor.as_ref(), timeout) {
+            Ok(false) => {}
+            Ok(true) => match monitor.as_ref().map(hotplug::Monitor::changes) {
+                Some(Ok(changes)) => {
+                    for change in changes {
+                        match change {
+                            hotplug::Change::Added(path) => {
+                                info!("serial device {path} plugged in");
+                                filter.plugged(Path::new(&path));
+                                scan = Some(Instant::now());
+                            }
+                            hotplug::Change::Removed(path) => debug!("serial device {path} removed"),
+                            hotplug::Change::Lost => {
+                                debug!("hotplug events lost, rescanning");
+                                scan = Some(Instant::now());
+                            }
+                        }
+                    }
+                }
+                Some(Err(e)) => {
+                    warn!("hotplug events failed ({e}), rescanning for serial devices every {} s", power::rescan().as_secs());
+                    monitor = None;
+                    scan = Some(Instant::now());
+                }
+                None => {}
+            },
+            Err(e) => {
+                // Not to spin on it
+                debug!("can't wait for events ({e}): sleeping instead");
+                thread::sleep(TICK);
+            }
+        }
+    }
+}
+
+/// How long to wait before looking again after `failures` looks in a row
+/// found nothing: `power::rescan`, or for a Bluetooth adapter, which each try
+/// pages for as long as `bluetooth::CONNECT_TIMEOUT` while it is off or out
+/// of range, twice as long every time up to `BLUETOOTH_BACKOFF`.
+fn retry_after(cfg: &SerialConfig, failures: u32) -> Duration {
+    let rescan = power::rescan();
+    if !cfg.path.as_deref().is_some_and(|p| p.starts_with(bluetooth::SCHEME)) {
+        return rescan;
+    }
+    rescan.saturating_mul(1 << failures.saturating_sub(1).min(8)).min(BLUETOOTH_BACKOFF)
+}
+
+/// The hotplug monitor, unless `serial.hotplug = false` or it can't be had.
+fn open_hotplug(cfg: &SerialConfig) -> Option<hotplug::Monitor> {
+    if !cfg.hotplug.unwrap_or(true) {
+        return None;
+    }
+    match hotplug::Monitor::open() {
+        Ok(m) => {
+            info!("watching for serial devices through {} hotplug events", m.source());
+            Some(m)
+        }
+        Err(e) => {
+            warn!("no hotplug events ({e}), rescanning for serial devices every {} s", power::rescan().as_secs());
+            None
+        }
+    }
+}
+
+/// What a failed read or write says about the link: the errors a tty
+/// gives once its device is unplugged, or a socket once its connection
+/// drops, named as such.
+fn lost(what: &str, e: &std::io::Error) -> String {
+    let gone = matches!(e.raw_os_error(), Some(libc::EIO | libc::ENXIO | libc::ENODEV))
+        || matches!(e.kind(), ErrorKind::BrokenPipe | ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted);
+    if gone {
+        format!("{what}: device gone ({e})")
+    } else {
+        format!("{what}: {e}")
+    }
+}
+
+/// An open port plus the bus every command sent is published on.
+struct Link<'a> {
+    port: Box<dyn Transport>,
+    bus: &'a Bus,
+    /// Commands queued to go out together, and where each ends
+    batch: Vec<u8>,
+    ends: Vec<usize>,
+    /// Commands written
+    sent: u64,
+}
+
+impl Link<'_> {
+    fn new(port: Box<dyn Transport>, bus: &Bus) -> Link<'_> {
+        Link { port, bus, batch: Vec::new(), ends: Vec::new(), sent: 0 }
+    }
+
+    /// Send the command `data` now, after any queued.
+    fn send(&mut self, data: &[u8]) -> Result<(), String> {
+        self.queue(data)?;
+        self.flush()
+    }
+
+    /// Queue the command `data` to go out with the others of this turn of
+    /// the session, in one write and flush: one syscall and one USB
+    /// transfer for a burst rather than one each. A command switching the
+    /// OI's mode goes out at once, those before it with it, so the robot
+    /// has switched before it reads what comes after, as when every
+    /// command was written on its own.
+    fn queue(&mut self, data: &[u8]) -> Result<(), String> {
+        let _command = debug_span!("command", opcode = data.first().copied().unwrap_or_default()).entered();
+        debug!("tx {:?}", data);
+        self.batch.extend_from_slice(data);
+        self.ends.push(self.batch.len());
+        match data.first() {
+            Some(&code) if command::switches_mode(code) => self.flush(),
+            _ => Ok(()),
+        }
+    }
+
+    /// Write out the queued commands.
+    fn flush(&mut self) -> Result<(), String> {
+        if self.batch.is_empty() {
+            return Ok(());
+        }
+        let start = Instant::now();
+        let written = self.port.write_all(&self.batch).map_err(|e| lost("write", &e)).and_then(|()| self.port.flush().map_err(|e| lost("flush", &e)));
+        let mut from = 0;
+        for end in self.ends.drain(..) {
+            let bytes = &self.batch[from..end];
+            from = end;
+            sniffer::tx(bytes);
+            if written.is_ok() {
+                self.sent += 1;
+                self.bus.publish(Message::Command { time: SystemTime::now(), bytes: bytes.to_vec() });
+            }
+        }
+        self.batch.clear();
+        written?;
+        timing::write(start.elapsed());
+        Ok(())
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.port.read(buf)?;
+        flight::rx(&buf[..n]);
+        sniffer::rx(&buf[..n]);
+        Ok(n)
+    }
+}
+
+/// Why a port found wasn't claimed.
+#[derive(Debug)]
+enum Unclaimed {
+    /// Something answered the probe, but not the OI
+    NotRobot(String),
+    /// Nothing answered the probe
+    Silent,
+    Failed(String),
+}
+
+/// A port claimed: the path it was found at, and the baud rate it answered at
+struct Claimed {
+    path: PathBuf,
+    baud: u32,
+    port: Box<dyn Transport>,
+}
+
+/// Open and probe `ports` all at once, for the first of them, in order, to
+/// answer as a robot, open: as soon as it has, and those before it haven't,
+/// without waiting on the probes of those after it, whose ports are closed
+/// again as they end. Those found not to be a robot are passed over from
+/// now on. Without `serial.probe` the first is taken as it is. Also whether
+/// to look again later: a port couldn't be opened or didn't answer.
+fn claim(ports: &[PathBuf], cfg: &SerialConfig, filter: &mut discovery::Filter) -> (Option<Claimed>, bool) {
+    let ports = if cfg.probe() { ports } else { &ports[..ports.len().min(1)] };
+    let (tx, results) = mpsc::channel();
+    for (i, port_path) in ports.iter().enumerate() {
+        let (tx, port_path, cfg) = (tx.clone(), port_path.clone(), cfg.clone());
+        thread::spawn(move || {
+            // Claimed already, if the worker has gone: dropped, the port closes
+            let _ = tx.send((i, open_port(&port_path, &cfg)));
+        });
+    }
+    drop(tx);
+    let mut settled: Vec<Option<_>> = ports.iter().map(|_| None).collect();
+    let mut retry = false;
+    // The first port not yet given up on
+    let mut next = 0;
+    while next < ports.len() {
+        let Ok((i, result)) = results.recv() else {
+            // A probe panicked
+            return (None, true);
+        };
+        settled[i] = Some(result);
+        while let Some(result) = settled.get_mut(next).and_then(Option::take) {
+            let port_path = &ports[next];
+            next += 1;
+            match result {
+                Ok((baud, port)) => return (Some(Claimed { path: port_path.clone(), baud, port }), retry),
+                Err(Unclaimed::NotRobot(why)) => {
+                    warn!("{} isn't a robot ({why}): leaving it alone", port_path.display());
+                    filter.reject(port_path);
+                }
+                Err(Unclaimed::Silent) => {
+                    warn!("can't connect to {}: no reply to the OI probe at {:?} baud (the robot may be off)", port_path.display(), cfg.bauds());
+                    retry = true;
+                }
+                Err(Unclaimed::Failed(e)) => {
+                    warn!("can't connect to {}: {e}", port_path.display());
+                    retry = true;
+                }
+            }
+        }
+    }
+    (None, retry)
+}
+
+/// Lock and open `port_path` and, with `serial.probe`, make sure it is a
+/// robot before claiming it, trying each of `serial.bauds()` in turn until
+/// it answers at one; that baud rate, and the port.
+fn open_port(port_path: &Path, cfg: &SerialConfig) -> Result<(u32, Box<dyn Transport>), Unclaimed> {
+    let bauds = cfg.bauds();
+    if let Some(address) = port_path.to_str().filter(|p| p.starts_with(mock::SCHEME)) {
+        // Nothing between it and the daemon to run at a baud rate
+        info!("connecting to the simulated robot {address}");
+        let mut robot = mock::Robot::new();
+        if cfg.probe() {
+            let firmware = probe_oi(&mut robot)?;
+            debug!("{address} answers as a robot: {firmware}");
+        }
+        return Ok((0, Box::new(robot)));
+    }
+    if let Some(address) = port_path.to_str().filter(|p| p.starts_with(bluetooth::SCHEME)) {
+        // The adapter's baud rate to the robot is its own; no lock file for a socket
+        info!("connecting to {address} over RFCOMM");
+        let mut link = bluetooth::Rfcomm::connect(address).map_err(Unclaimed::Failed)?;
+        if cfg.probe() {
+            let firmware = probe_oi(&mut link)?;
+            debug!("{address} answers as a robot: {firmware}");
+        }
+        return Ok((0, Box::new(link)));
+    }
+    if let Some(address) = port_path.to_str().filter(|p| tcp::is_network(p)) {
+        let mut port = tcp::NetPort::connect(address).map_err(Unclaimed::Failed)?;
+        if address.starts_with(tcp::RAW) {
+            // The bridge's baud rate to the robot is its own; no lock file for a socket
+            info!("connected to {address}");
+            if cfg.probe() {
+                let firmware = probe_oi(&mut port)?;
+                debug!("{address} answers as a robot: {firmware}");
+            }
+            return Ok((0, Box::new(port)));
+        }
+        info!("connected to {address}, at {} baud", bauds[0]);
+        port.set_baud_rate(bauds[0]).map_err(|e| Unclaimed::Failed(format!("set baud rate {}: {e}", bauds[0])))?;
+        if !cfg.probe() {
+            return Ok((bauds[0], Box::new(port)));
+        }
+        let baud = probe_bauds(address, &mut port, &bauds)?;
+        return Ok((baud, Box::new(port)));
+    }
+    info!("connecting to {} at {} baud", port_path.display(), bauds[0]);
+    let lock = match cfg.lock_dir() {
+        Some(dir) => lockfile::Lock::acquire(&dir, port_path).map_err(Unclaimed::Failed)?,
+        None => None,
+    };
+    // Before the probe, which waits on replies too
+    let latency = cfg.latency_timer_ms.map(|ms| ms.max(1)).and_then(|ms| ftdi::set(port_path, ms));
+    let mut port = transport::Serial::open(port_path, bauds[0], lock, latency).map_err(Unclaimed::Failed)?;
+    if !cfg.probe() {
+        return Ok((bauds[0], Box::new(port)));
+    }
+    let name = port_path.display().to_string();
+    let baud = match probe_bauds(&name, &mut port, &bauds) {
+        // A Create 2 asleep (on the dock, or idle for 5 minutes) doesn't answer until woken
+        Err(Unclaimed::Silent) if cfg.wake.unwrap_or_default() != WakeLine::Off => {
+            info!("{name} doesn't answer: pulsing BRC to wake the robot");
+            port.set_baud_rate(bauds[0]).map_err(|e| Unclaimed::Failed(format!("set baud rate {}: {e}", bauds[0])))?;
+            wake(&mut port, cfg.wake.unwrap_or_default()).map_err(|e| Unclaimed::Failed(format!("wake: can't drive the modem lines ({e})")))?;
+            probe_bauds(&name, &mut port, &bauds)?
+        }
+        result => result?,
+    };
+    Ok((baud, Box::new(port)))
+}
+
+/// How long the BRC pin is held low to wake a Create 2, and how long its
+/// OI takes to come up after
+const WAKE_PULSE: Duration = Duration::from_millis(500);
+const WAKE_SETTLE: Duration = Duration::from_secs(1);
+
+/// Pulse the BRC pin low through `line`: a sleeping Create 2 wakes on the
+/// pulse, one awake or a Create 1, without the pin, is none the worse.
+fn wake(port: &mut transport::Serial, line: WakeLine) -> Result<(), String> {
+    let set = |port: &mut transport::Serial, asserted: bool| -> Result<(), String> {
+        if matches!(line, WakeLine::Rts | WakeLine::Both) {
+            port.request_to_send(asserted)?;
+        }
+        if matches!(line, WakeLine::Dtr | WakeLine::Both) {
+            port.data_terminal_ready(asserted)?;
+        }
+        Ok(())
+    };
+    // Asserted means low, as opening the port leaves it: high first, for an edge
+    set(port, false)?;
+    thread::sleep(Duration::from_millis(100));
+    set(port, true)?;
+    thread::sleep(WAKE_PULSE);
+    set(port, false)?;
+    thread::sleep(WAKE_SETTLE);
+    Ok(())
+}
+
+/// Probe the port `name` at each of `bauds` in turn until it answers as a
+/// robot at one; that baud rate.
+fn probe_bauds(name: &str, port: &mut dyn Transport, bauds: &[u32]) -> Result<u32, Unclaimed> {
+    // Garbage at a wrong baud rate says nothing about the device yet
+    let mut garbled = None;
+    for &baud in bauds {
+        if baud != bauds[0] {
+            debug!("trying {name} at {baud} baud");
+            port.set_baud_rate(baud).map_err(|e| Unclaimed::Failed(format!("set baud rate {baud}: {e}")))?;
+        }
+        match probe_oi(port) {
+            Ok(firmware) => {
+                debug!("{name} answers as a robot at {baud} baud: {firmware}");
+                if baud != bauds[0] {
+                    info!("{name} answers at {baud} baud, not {}: set serial.baud = {baud} to connect at once", bauds[0]);
+                }
+                return Ok(baud);
+            }
+            Err(Unclaimed::NotRobot(why)) => garbled = Some(format!("{why} at {baud} baud")),
+            Err(Unclaimed::Silent) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Err(garbled.map_or(Unclaimed::Silent, Unclaimed::NotRobot))
+}
+
+/// The OI mode, battery voltage, and charging state: what the probe asks for
+const PROBE: [u8; 3] = [oi::OI_MODE, 22, 21];
+
+/// Wake the OI and ask for its mode, the battery voltage, and the charging
+/// state in one query: a robot answers four bytes, each in range; another
+/// device stays silent or answers something else, and is left with nothing
+/// but these few bytes sent to it.
+fn probe_oi(port: &mut dyn Transport) -> Result<String, Unclaimed> {
+    let failed = |e: std::io::Error| Unclaimed::Failed(format!("probe: {e}"));
+    // A stream left running by an earlier session would be taken for the reply
+    port.write_all(&[oi::START, oi::PAUSE_RESUME_STREAM, 0]).map_err(failed)?;
+    port.flush().map_err(failed)?;
+    thread::sleep(Duration::from_millis(50));
+    let mut buf = [0u8; 256];
+    let deadline = Instant::now() + REPLY_WAIT;
+    while Instant::now() < deadline {
+        match port.read(&mut buf) {
+            Ok(n) if n > 0 => {}
+            _ => break,
+        }
+    }
+    let mut query = vec![oi::QUERY_LIST, PROBE.len() as u8];
+    query.extend_from_slice(&PROBE);
+    port.write_all(&query).map_err(failed)?;
+    port.flush().map_err(failed)?;
+    let mut reply = Vec::new();
+    let size = oi::reply_size(&PROBE);
+    let deadline = Instant::now() + REPLY_WAIT;
+    while reply.len() < size && Instant::now() < deadline {
+        match port.read(&mut buf[..size - reply.len()]) {
+            Ok(n) => reply.extend_from_slice(&buf[..n]),
+            Err(e) if e.kind() == ErrorKind::TimedOut => {}
+            Err(e) => return Err(failed(e)),
+        }
+    }
+    let mut answer = SensorFrame::new(SystemTime::now());
+    if reply.len() < size || oi::decode_reply(&PROBE, &reply, &mut answer).is_err() {
+        return Err(match reply.len() {
+            0 => Unclaimed::Silent,
+            n => Unclaimed::NotRobot(format!("{n} bytes in reply to the OI probe, {reply:?}")),
+        });
+    }
+    let [mode, voltage, charging] = PROBE.map(|id| answer.get(id).unwrap_or_default());
+    if mode > oi::MODE_FULL || charging > 5 || voltage > 25_000 {
+        return Err(Unclaimed::NotRobot(format!("mode {mode}, {voltage} mV, charging state {charging} in reply to the OI probe")));
+    }
+    Ok(format!("OI in {} mode, {voltage} mV", oi::mode_name(mode)))
+}
+
+/// Run a session on `port` until shutdown (Ok) or the link is lost. With
+/// `resume`, the session starts from what a lost link to the same robot
+/// left off, and leaves what it was doing for the next if its own is lost.
+pub fn run_port(port: Box<dyn Transport>, device: &str, baud: u32, stream: &[u8], bus: &Bus, rx: &Receiver<()>, mut resume: Option<resume::Session>) -> Result<(), String> {
+    let mut link = Link::new(port, bus);
+    let connected = Event::Connected { device: device.to_string(), robot: aliases::current(), baud };
+    bus.event(connected.clone());
+    let mut resumed = resume.as_mut().and_then(|r| r.saved.take());
+    if let Some(saved) = &resumed {
+        let mode = saved.mode.map(|m| oi::mode_name(m).to_string());
+        let behavior = saved.behavior.as_ref().map(|b| b.name().to_string());
+        info!("{device} is back after {:.1} s, resuming", saved.after().as_secs_f64());
+        bus.event(Event::Resumed { device: device.to_string(), after_s: saved.after().as_secs_f64(), mode, behavior });
+    }
+    stats::begin(device);
+    battery::begin();
+    pose::begin(resumed.as_ref().and_then(|s| s.pose));
+    slip::begin();
+    behavior::begin(resumed.as_mut().and_then(|s| s.behavior.take()));
+    estop::begin();
+    recharge::begin();
+    reflex::begin();
+    geofence::begin();
+    patrol::begin();
+    bumper::begin();
+    cliff::begin();
+    wheel_drop::begin();
+    charging_lockout::begin();
+    low_voltage::begin();
+    overcurrent::begin();
+    stuck::begin();
+    governor::begin();
+    thermal::begin();
+    leds::begin();
+    buttons::begin();
+    let greeting = song::begin();
+    beeps::react(&connected);
+
+    let mut mode = None;
+    let result = start_session(&mut link, stream, greeting).and_then(|()| {
+        if let Some(m @ (oi::MODE_SAFE | oi::MODE_FULL)) = resumed.as_ref().and_then(|s| s.mode) {
+            match safety::set_mode(oi::mode_name(m)) {
+                Ok(_) => info!("resuming {} mode", oi::mode_name(m)),
+                Err(e) => warn!("not resuming {} mode: {e}", oi::mode_name(m)),
+            }
+        }
+        stream_sensors(&mut link, rx, &mut mode)
+    });
+
+    // Best effort: the port may already be gone
+    let _ = link.send(&[oi::PAUSE_RESUME_STREAM, 0]);
+    if result.is_ok() {
+        // Not on a lost link: a robot powered off can't come back
+        let _ = link.send(&[oi::POWER]);
+    }
+    match link.port.close() {
+        // A lost link fails to close as a matter of course
+        Err(e) if result.is_ok() => warn!("closing {device}: {e}"),
+        Err(e) => debug!("closing {device}: {e}"),
+        Ok(()) => {}
+    }
+    battery::save();
+    let pose = pose::current();
+    pose::end();
+    slip::end();
+    let behavior = behavior::end();
+    if let (Err(_), Some(r)) = (&result, &resume) {
+        resume::save(&r.device, mode, pose, behavior);
+    }
+    recharge::end();
+    reflex::end();
+    geofence::end();
+    patrol::end();
+    bumper::end();
+    cliff::end();
+    wheel_drop::end();
+    charging_lockout::end();
+    low_voltage::end();
+    stuck::end();
+    governor::end();
+    thermal::end();
+    leds::end();
+    buttons::end();
+    song::end();
+    if let Some((summary, today)) = stats::end() {
+        info!(
+            "session summary: {:.0}s, {:.2} m driven, {:.0}° turned, {} bumps, {} cliffs, {:.0}s driving / {:.0}s idle, {:.2} Wh",
+            summary.duration_s,
+            summary.distance_m,
+            summary.turned_deg,
+            summary.bumps,
+            summary.cliffs,
+            summary.driving_s,
+            summary.idle_s,
+            summary.energy_wh
+        );
+        bus.event(Event::SessionSummary(summary));
+        if let Some(today) = today {
+            bus.event(Event::Activity(today));
+        }
+    }
+    result
+}
+
+/// Wake the OI, greet, identify it for the self-test, and start the stream.
+fn start_session(link: &mut Link, stream: &[u8], greeting: bool) -> Result<(), String> {
+    link.send(&[oi::START])?;
+    thread::sleep(Duration::from_millis(50));
+    if !greeting && !quiet::on() {
+        greet(link)?;
+    }
+    if behavior::config().self_test().on_connect() {
+        let firmware = identify(link)?;
+        behavior::selftest::identified(firmware);
+    }
+    start_stream(link, stream)
+}
+
+fn greet(link: &mut Link) -> Result<(), String> {
+    let _behavior = info_span!("behavior", name = "greeting").entered();
+    // Song definition: [140, song_number, length, note, duration, ...]
+    // Define a tiny 3-note song (C4, E4, G4)
+    let song: [u8; 9] = [oi::SONG, 0, 3, 60, 16, 64, 16, 67, 24];
+    link.send(&song)?;
+    thread::sleep(Duration::from_millis(20));
+
+    // Play song 0
+    link.send(&[oi::PLAY, 0])
+}
+
+/// Ask the OI for its mode, and for a packet only the Create 2's knows, for
+/// the self-test: before the stream starts, so the replies can't be mixed
+/// up with it. Err only if the port fails.
+fn identify(link: &mut Link) -> Result<Result<String, String>, String> {
+    // A stream left running by an earlier session would be taken for replies
+    pause(link)?;
+    link.send(&[oi::SENSORS, oi::OI_MODE])?;
+    let Some(mode) = reply(link)? else { return Ok(Err("no reply to a query for the OI mode".into())) };
+    if mode > oi::MODE_FULL as u8 {
+        return Ok(Err(format!("unexpected reply {mode} to a query for the OI mode")));
+    }
+    link.send(&[oi::SENSORS, oi::LAST_PACKET])?;
+    let oi = match reply(link)? {
+        Some(_) => format!("packets to {} (Create 2)", oi::LAST_PACKET),
+        None => format!("no packet {} (the original Create)", oi::LAST_PACKET),
+    };
+    Ok(Ok(format!("OI in {} mode, {oi}", oi::mode_name(mode as i32))))
+}
+
+/// Pause the stream, and drop what was on its way.
+fn pause(link: &mut Link) -> Result<(), String> {
+    link.send(&[oi::PAUSE_RESUME_STREAM, 0])?;
+    thread::sleep(Duration::from_millis(50));
+    let mut buf = [0u8; 256];
+    let deadline = Instant::now() + REPLY_WAIT;
+    while Instant::now() < deadline {
+        match link.read(&mut buf) {
+            Ok(n) if n > 0 => {}
+            _ => break,
+        }
+    }
+    Ok(())
+}
+
+/// Ask for the packets `ids`, which the stream doesn't carry, in one query
+/// between pausing and resuming it. None if the reply doesn't come within
+/// `REPLY_WAIT` or doesn't decode; Err only if the port fails.
+fn query(link: &mut Link, ids: &[u8]) -> Result<Option<SensorFrame>, String> {
+    pause(link)?;
+    let mut cmd = vec![oi::QUERY_LIST, ids.len() as u8];
+    cmd.extend_from_slice(ids);
+    link.send(&cmd)?;
+    let size = oi::reply_size(ids);
+    let mut buf = [0u8; 64];
+    let mut reply = Vec::new();
+    let deadline = Instant::now() + REPLY_WAIT;
+    while reply.len() < size && Instant::now() < deadline {
+        match link.read(&mut buf[..size - reply.len()]) {
+            Ok(n) => reply.extend_from_slice(&buf[..n]),
+            Err(e) if e.kind() == ErrorKind::TimedOut => {}
+            Err(e) => return Err(lost("read", &e)),
+        }
+    }
+    link.send(&[oi::PAUSE_RESUME_STREAM, 1])?;
+    let mut answer = SensorFrame::new(SystemTime::now());
+    Ok((reply.len() == size && oi::decode_reply(ids, &reply, &mut answer).is_ok()).then_some(answer))
+}
+
+/// A one-byte reply, if it comes within `REPLY_WAIT`.
+fn reply(link: &mut Link) -> Result<Option<u8>, String> {
+    let deadline = Instant::now() + REPLY_WAIT;
+    let mut byte = [0u8; 1];
+    while Instant::now() < deadline {
+        match link.read(&mut byte) {
+            Ok(1) => return Ok(Some(byte[0])),
+            Ok(_) => {}
+            Err(e) if e.kind() == ErrorKind::TimedOut => {}
+            Err(e) => return Err(format!("read: {e}")),
+        }
+    }
+    Ok(None)
+}
+
+fn start_stream(link: &mut Link, stream: &[u8]) -> Result<(), String> {
+    let ids: Vec<u8> = stream
+        .iter()
+        .copied()
+        .filter(|&id| {
+            let known = oi::expand(id).is_some();
+            if !known {
+                warn!("ignoring unknown sensor packet id {id} in sensors.stream");
+            }
+            known
+        })
+        .collect();
+    if ids.is_empty() {
+        return Err("no valid sensor packets to stream".into());
+    }
+    info!("streaming sensor packets {:?}", ids);
+    let mut cmd = vec![oi::STREAM, ids.len() as u8];
+    cmd.extend_from_slice(&ids);
+    link.send(&cmd)?;
+    link.bus.event(Event::StreamStarted { packets: ids });
+    Ok(())
+}
+
+/// The session loop, until shutdown (Ok) or the link is lost; `mode` the
+/// OI mode, as the stream shows it and as the session sets it.
+fn stream_sensors(link: &mut Link, rx: &Receiver<()>, mode: &mut Option<i32>) -> Result<(), String> {
+    let mut parser = StreamParser::default();
+    let mut last_frame = Instant::now();
+    let mut detector = EventDetector::default();
+    let mut follower = motion::Follower::new();
+    let mut idle = power::Idle::new();
+    timing::reset();
+    loop {
+        if rx.try_recv().is_ok() {
+            return Ok(());
+        }
+        let sent = link.sent;
+        let mut happened = false;
+        // Straight into the parser's buffer, where the frames are decoded
+        match link.read(parser.spare()) {
+            Ok(n) => parser.filled(n),
+            Err(e) if e.kind() == ErrorKind::TimedOut => {}
+            Err(e) => return Err(lost("read", &e)),
+        }
+        let read_at = Instant::now();
+        while let Some(res) = parser.next_frame() {
+            match res {
+                Ok(frame) => {
+                    last_frame = Instant::now();
+                    timing::frame(read_at);
+                    *mode = frame.get(oi::OI_MODE).or(*mode);
+                    let mut events = detector.update(&frame);
+                    events.extend(cliff::update(&frame));
+                    events.extend(wheel_drop::update(&frame));
+                    events.extend(overcurrent::update(&frame));
+                    charging_lockout::update(&frame);
+                    events.extend(battery::update(&frame));
+                    events.extend(low_voltage::update(&frame));
+                    let finished_day = stats::update(&frame, &events);
+                    events.extend(finished_day);
+                    events.extend(slip::update(&frame));
+                    let pose = pose::update(&frame);
+                    events.extend(geofence::update(pose));
+                    events.extend(bumper::update(&frame));
+                    events.extend(governor::update(&frame));
+                    events.extend(thermal::update(&frame));
+                    let supervised = recharge::update(&frame, &events);
+                    events.extend(supervised);
+                    events.extend(stuck::update(&frame));
+                    let reflexes = reflex::update(&frame, pose, &events);
+                    events.extend(reflexes);
+                    let progress = behavior::tick(&frame, pose, &events);
+                    events.extend(progress);
+                    let patrolled = patrol::update(pose, &events);
+                    events.extend(patrolled);
+                    events.extend(buttons::update(&frame));
+                    happened |= !events.is_empty();
+                    song::update(&frame);
+                    leds::update(&frame);
+                    link.bus.publish(Message::Sensors(Arc::new(frame)));
+                    if let Some(pose) = pose {
+                        link.bus.publish(Message::Pose(pose));
+                    }
+                    for event in events {
+                        beeps::react(&event);
+                        if let Event::ModeChanged { to, .. } = &event {
+                            // Shows up as OI_MODE in the journal and in JSON log fields
+                            Span::current().record("oi_mode", to.as_str());
+                        }
+                        link.bus.event(event);
+                    }
+                }
+                Err(e) => {
+                    debug!("discarding sensor frame: {e}");
+                    behavior::selftest::discarded();
+                }
+            }
+        }
+        if behavior::selftest::wants_encoders() {
+            // Packets 43 and 44 aren't streamed, and a wheel check counts them
+            let answer = query(link, &[43, 44])?;
+            behavior::selftest::queried(answer.and_then(|a| Some([a.get(43)?, a.get(44)?])));
+            timing::gap();
+            last_frame = Instant::now();
+        }
+        if release::held() {
+            // Left in Passive mode, still for whatever takes the port over
+            link.send(&Wheels::STOP.command())?;
+            link.send(&[oi::START])?;
+            return Err(release::REASON.into());
+        }
+        if estop::stop_now() {
+            // Passive mode, where the robot can't be driven until it is
+            // cleared and something asks for Safe mode again
+            link.send(&Wheels::STOP.command())?;
+            link.send(&[oi::START])?;
+            *mode = Some(oi::MODE_PASSIVE);
+        }
+        if let Some(since) = follower.watchdog() {
+            warn!("drive request not renewed for {:.1} s, stopping the wheels", since.as_secs_f64());
+            audit::record("heartbeat_stop", "heartbeat watchdog", &format!("drive request not renewed for {:.1} s", since.as_secs_f64()));
+            link.bus.event(Event::HeartbeatLost { after_s: since.as_secs_f64() });
+        }
+        if let Some(wheels) = follower.poll() {
+            // Passive mode ignores Drive; the robot also falls back to it on
+            // a cliff or wheel drop
+            if wheels != Wheels::STOP && !matches!(*mode, Some(oi::MODE_SAFE | oi::MODE_FULL)) {
+                link.queue(&[oi::SAFE])?;
+                *mode = Some(oi::MODE_SAFE);
+            }
+            link.queue(&wheels.command())?;
+        }
+        for action in follower.actions() {
+            if !safety::allows(&action) {
+                continue;
+            }
+            let action = match safety::limit_command(&action, motion::sent()) {
+                Ok(action) => action,
+                Err(e) => {
+                    warn!("safety: not sending {action:?}: {e}");
+                    continue;
+                }
+            };
+            if !estop::allows(&action) {
+                debug!("emergency stop engaged, not sending {action:?}");
+                continue;
+            }
+            if !cliff::allows(&action) {
+                warn!("cliff interlock: not sending {action:?}, which drives forward");
+                audit::record("command_dropped", "cliff interlock", &format!("{action:?} drives forward"));
+                continue;
+            }
+            if !wheel_drop::allows(&action) {
+                warn!("wheel drop: not sending drive command {action:?}");
+                audit::record("command_dropped", "wheel drop stop", &format!("drive command {action:?}"));
+                continue;
+            }
+            if !low_voltage::allows(&action) {
+                warn!("low-voltage cutoff: not sending {action:?}");
+                audit::record("command_dropped", "low-voltage cutoff", &format!("{action:?} while cut off"));
+                continue;
+            }
+            if !charging_lockout::allows(&action) {
+                warn!("charging lockout: not sending {action:?}, which moves the robot, on the dock");
+                audit::record("command_dropped", "charging lockout", &format!("{action:?} moves the robot on the dock"));
+                continue;
+            }
+            if !overcurrent::allows(&action) {
+                warn!("not sending {action:?}: it runs a motor cut for overcurrent");
+                audit::record("command_dropped", "overcurrent protection", &format!("{action:?} runs a motor cut for overcurrent"));
+                continue;
+            }
+            link.queue(&action)?;
+        }
+        // The turn's drive command and actions, together
+        link.flush()?;
+        timing::busy(read_at.elapsed());
+        if last_frame.elapsed() > STREAM_STALL {
+            return Err(format!("no sensor data for {:?}", STREAM_STALL));
+        }
+        let active = happened || link.sent != sent || behavior::status().is_some() || motion::sent() != Wheels::STOP;
+        if let Some(poll) = idle.turn(active) {
+            // Paused until the next frame is due, or something wants the
+            // robot sooner
+            link.send(&[oi::PAUSE_RESUME_STREAM, 0])?;
+            if let Err(e) = wakeup::wait(None, Some(poll)) {
+                debug!("can't wait for events ({e}): sleeping instead");
+                thread::sleep(poll);
+            }
+            link.send(&[oi::PAUSE_RESUME_STREAM, 1])?;
+            timing::gap();
+            last_frame = Instant::now();
+        }
+    }
+}
+
+/// The session's interlocks and supervisors keep their state in statics:
+/// their tests, and a session's, run one at a time.
+#[cfg(test)]
+pub(crate) fn serial() -> std::sync::MutexGuard<'static, ()> {
+    static SERIAL: std::sync::Mutex<()> = std::sync::Mutex::new(());
+    SERIAL.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::Sampling;
+
+    #[test]
+    fn probes_the_simulated_robot() {
+        let mut robot = mock::Robot::new();
+        let answer = probe_oi(&mut robot).unwrap();
+        assert!(answer.starts_with("OI in passive mode"), "{answer}");
+    }
+
+    #[test]
+    fn runs_a_session_on_the_simulated_robot() {
+        let _serial = serial();
+        let mut bus = Bus::default();
+        let messages = bus.subscribe("test", 256, Sampling::new("test", None, None));
+        let (stop, rx) = mpsc::channel();
+        let session = {
+            let bus = bus.clone();
+            thread::spawn(move || run_port(Box::new(mock::Robot::new()), mock::SCHEME, 0, &[100], &bus, &rx, None))
+        };
+        let (mut commands, mut frames) = (Vec::new(), Vec::new());
+        while frames.len() < 5 {
+            match messages.recv_timeout(Duration::from_secs(5)).expect("the session to stream") {
+                Message::Command { bytes, .. } => commands.push(bytes[0]),
+                Message::Sensors(frame) => frames.push(frame),
+                _ => {}
+            }
+        }
+        stop.send(()).unwrap();
+        assert_eq!(session.join().unwrap(), Ok(()));
+        assert_eq!(commands.first(), Some(&oi::START));
+        assert!(commands.contains(&oi::STREAM), "{commands:?}");
+        for frame in &frames {
+            // Group 100: every packet
+            assert_eq!(frame.iter().count(), oi::PACKETS.len());
+            assert!(frame.get(22).is_some_and(|mv| mv > 10_000), "voltage {:?}", frame.get(22));
+        }
+    }
+}
@@ -0,0 +1,303 @@
+//! Safety policy: what the daemon may do to the robot at all, whoever asks.
+//! Full mode turns off the robot's own cliff, wheel drop, and charger
+//! safeties, so unless `safety.allow_full_mode` it is never entered: asking
+//! for it on the control socket or from a script is refused, and a Full
+//! command is dropped before it reaches the port, whatever queued it.
+//!
+//! The caps on forward speed, turn rate, and acceleration apply to every
+//! speed the session sends, after arbitration, ramping, and the other
+//! limits, so no driver, behavior, or setting (`motion.accel = 0`, a stop
+//! taken at once) goes past them. Slowing down is never capped. Raw drive
+//! commands from missions and scripts are held to them too: clamped to the
+//! speed and turn rate caps, or dropped where they can't be, as the robot
+//! takes them at once, with no ramp to stretch to the acceleration cap.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use serde_json::{json, Value};
+use tracing::{debug, info, warn};
+
+use crate::config::SafetyConfig;
+use crate::motion::{self, Wheels};
+use crate::{audit, oi, pose};
+
+static ALLOW_FULL: AtomicBool = AtomicBool::new(false);
+
+/// Forward speed, mm/s, turn rate, rad/s, and acceleration, mm/s²; None for no cap
+#[derive(Clone, Copy)]
+struct Caps {
+    speed: Option<f64>,
+    turn_rate: Option<f64>,
+    accel: Option<f64>,
+}
+
+static CAPS: Mutex<Caps> = Mutex::new(Caps { speed: None, turn_rate: None, accel: None });
+/// Which caps clamped the last speeds sent: speed, turn rate, acceleration
+static CLAMPING: Mutex<[bool; 3]> = Mutex::new([false; 3]);
+
+fn caps() -> Caps {
+    *CAPS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+pub fn init(cfg: SafetyConfig) {
+    ALLOW_FULL.store(cfg.allow_full_mode(), Ordering::Relaxed);
+    if cfg.allow_full_mode() {
+        info!("Full mode allowed: the robot won't stop itself for cliffs or wheel drops in it");
+    }
+    let caps = Caps { speed: cfg.max_speed(), turn_rate: cfg.max_turn_rate().map(f64::to_radians), accel: cfg.max_accel() };
+    *CAPS.lock().unwrap_or_else(|e| e.into_inner()) = caps;
+}
+
+/// Log the caps that start or stop clamping.
+fn clamping(now: [bool; 3]) {
+    const WHAT: [&str; 3] = ["forward speed", "turn rate", "acceleration"];
+    const CAP: [&str; 3] = ["speed", "turn_rate", "accel"];
+    let mut was = CLAMPING.lock().unwrap_or_else(|e| e.into_inner());
+    for i in 0..3 {
+        match (was[i], now[i]) {
+            (false, true) => {
+                info!("safety: clamping the {} to safety.max_{}", WHAT[i], CAP[i]);
+                audit::record("clamp", &format!("safety.max_{}", CAP[i]), &format!("clamping the {}", WHAT[i]));
+            }
+            (true, false) => {
+                debug!("safety: no longer clamping the {}", WHAT[i]);
+                audit::record("clamp_ended", &format!("safety.max_{}", CAP[i]), &format!("no longer clamping the {}", WHAT[i]));
+            }
+            _ => {}
+        }
+    }
+    *was = now;
+}
+
+/// `wheels` within the speed and turn rate caps, and within the
+/// acceleration cap from `sent` over `dt`.
+pub fn limit(wheels: Wheels, sent: Wheels, dt: Duration) -> Wheels {
+    let (out, now) = cap(wheels, sent, dt, caps());
+    clamping(now);
+    out
+}
+
+/// `wheels` within `caps`, and which of them clamped it.
+fn cap(wheels: Wheels, sent: Wheels, dt: Duration, caps: Caps) -> (Wheels, [bool; 3]) {
+    let wheel_base = pose::wheel_base() * 1000.0;
+    let mut v = (wheels.left as f64 + wheels.right as f64) / 2.0;
+    let mut omega = (wheels.right as f64 - wheels.left as f64) / wheel_base;
+    let mut now = [false; 3];
+    if let Some(max) = caps.speed.filter(|max| v.abs() > *max) {
+        v = max.copysign(v);
+        now[0] = true;
+    }
+    if let Some(max) = caps.turn_rate.filter(|max| omega.abs() > *max) {
+        omega = max.copysign(omega);
+        now[1] = true;
+    }
+    let mut out = [v - omega * wheel_base / 2.0, v + omega * wheel_base / 2.0];
+    if let Some(accel) = caps.accel {
+        // Both wheels by the same fraction, keeping the curve
+        let from = [sent.left as f64, sent.right as f64];
+        let mut fraction: f64 = 1.0;
+        for (a, b) in from.into_iter().zip(out) {
+            let speeding_up = b.abs() > a.abs() && a * b >= 0.0;
+            let change = (b - a).abs();
+            if speeding_up && change > 0.0 {
+                fraction = fraction.min(accel * dt.as_secs_f64() / change);
+            }
+        }
+        if fraction < 1.0 {
+            out = [from[0] + (out[0] - from[0]) * fraction, from[1] + (out[1] - from[1]) * fraction];
+            now[2] = true;
+        }
+    }
+    if now == [false; 3] {
+        return (wheels, now);
+    }
+    (Wheels::new(out[0].round() as i32, out[1].round() as i32), now)
+}
+
+/// The raw OI command `bytes` as the caps let it through, with the wheels
+/// last sent at `sent`: a drive command clamped to the speed and turn rate
+/// caps (as Drive Direct), Err for one they can't hold, and anything else
+/// as it is.
+pub fn limit_command(bytes: &[u8], sent: Wheels) -> Result<Vec<u8>, String> {
+    let limited = command_within(bytes, sent, caps());
+    match &limited {
+        Ok(out) if out != bytes => {
+            info!("safety: clamping raw drive command {bytes:?} to {out:?}");
+            audit::record("clamp", "safety caps", &format!("raw drive command {bytes:?} sent as {out:?}"));
+        }
+        Err(e) => audit::record("command_dropped", "safety caps", &format!("{bytes:?}: {e}")),
+        _ => {}
+    }
+    limited
+}
+
+fn command_within(bytes: &[u8], sent: Wheels, caps: Caps) -> Result<Vec<u8>, String> {
+    if !oi::is_drive(bytes) || oi::is_stop(bytes) || bytes.len() < 5 {
+        return Ok(bytes.to_vec());
+    }
+    let word = |i: usize| i16::from_be_bytes([bytes[i], bytes[i + 1]]) as f64;
+    let wheels = match bytes[0] {
+        oi::DRIVE_DIRECT => Wheels::new(word(3) as i32, word(1) as i32),
+        oi::DRIVE => {
+            // Velocity and radius: straight (no arc has radius 0 either),
+            // on the spot (1 counter-clockwise, -1 clockwise), or along the arc
+            let (v, r) = (word(1), word(3));
+            let half = pose::wheel_base() * 1000.0 / 2.0;
+            let (left, right) = match r as i32 {
+                0 | 32767 | -32768 => (v, v),
+                1 => (-v, v),
+                -1 => (v, -v),
+                _ => (v * (r - half) / r, v * (r + half) / r),
+            };
+            Wheels::new(left.round() as i32, right.round() as i32)
+        }
+        _ if caps.speed.is_some() || caps.turn_rate.is_some() || caps.accel.is_some() => {
+            return Err("Drive PWM sets no speeds the safety caps can hold".into())
+        }
+        _ => return Ok(bytes.to_vec()),
+    };
+    // Taken at once: any speeding up at all is past an acceleration cap
+    let (capped, now) = cap(wheels, sent, Duration::ZERO, caps);
+    if now[2] {
+        return Err("it speeds up at once, past safety.max_accel (drive with ramped requests instead)".into());
+    }
+    if now == [false; 3] {
+        return Ok(bytes.to_vec());
+    }
+    Ok(capped.command().to_vec())
+}
+
+/// Put the robot in `mode`: passive, safe, or full.
+pub fn set_mode(mode: &str) -> Result<Value, String> {
+    let opcode = match mode {
+        "passive" => oi::START,
+        "safe" => oi::SAFE,
+        "full" if !ALLOW_FULL.load(Ordering::Relaxed) => {
+            audit::record("full_mode_refused", "mode request", "Full mode is forbidden (safety.allow_full_mode)");
+            return Err("Full mode is forbidden (safety.allow_full_mode)".into());
+        }
+        "full" => oi::FULL,
+        _ => return Err(format!("unknown mode {mode:?} (passive, safe, full)")),
+    };
+    if opcode == oi::START {
+        // Passive mode ignores Drive: a request left standing would be undone at its next change
+        motion::halt();
+    } else {
+        crate::estop::check()?;
+        crate::low_voltage::check()?;
+    }
+    motion::queue(vec![opcode]);
+    Ok(json!({ "mode": mode }))
+}
+
+/// Whether the raw OI command `bytes` may be sent now.
+pub fn allows(bytes: &[u8]) -> bool {
+    if bytes.first() == Some(&oi::FULL) && !ALLOW_FULL.load(Ordering::Relaxed) {
+        warn!("not entering Full mode: it is forbidden (safety.allow_full_mode)");
+        audit::record("full_mode_refused", "raw command", "Full mode is forbidden (safety.allow_full_mode)");
+        return false;
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NONE: Caps = Caps { speed: None, turn_rate: None, accel: None };
+    const TICK: Duration = Duration::from_millis(100);
+
+    fn turn_rate(w: Wheels) -> f64 {
+        (w.right as f64 - w.left as f64) / (pose::wheel_base() * 1000.0)
+    }
+
+    #[test]
+    fn forbids_full_mode_unless_allowed() {
+        let _serial = crate::robot::serial();
+        init(SafetyConfig::default());
+        assert!(!allows(&[oi::FULL]));
+        assert!(set_mode("full").unwrap_err().contains("forbidden"));
+        assert!(set_mode("turbo").is_err());
+        for other in [&[oi::SAFE][..], &[oi::START], &[oi::DRIVE_DIRECT, 0, 100, 0, 100], &[oi::LEDS, 0, 0, 0], &[]] {
+            assert!(allows(other), "{other:?}");
+        }
+        init(SafetyConfig { allow_full_mode: Some(true), ..Default::default() });
+        assert!(allows(&[oi::FULL]));
+        init(SafetyConfig::default());
+    }
+
+    #[test]
+    fn passes_anything_without_caps() {
+        for wheels in [Wheels::new(500, 500), Wheels::new(-500, 500), Wheels::STOP] {
+            assert_eq!(cap(wheels, Wheels::STOP, TICK, NONE), (wheels, [false; 3]));
+        }
+    }
+
+    #[test]
+    fn caps_the_speed_keeping_the_turn() {
+        let caps = Caps { speed: Some(200.0), ..NONE };
+        let (out, now) = cap(Wheels::new(400, 500), Wheels::STOP, TICK, caps);
+        assert_eq!(now, [true, false, false]);
+        assert_eq!(out, Wheels::new(150, 250));
+        let (out, _) = cap(Wheels::new(-300, -300), Wheels::STOP, TICK, caps);
+        assert_eq!(out, Wheels::new(-200, -200));
+        assert_eq!(cap(Wheels::new(100, 300), Wheels::STOP, TICK, caps).1, [false; 3]);
+    }
+
+    #[test]
+    fn caps_the_turn_rate_keeping_the_speed() {
+        let caps = Caps { turn_rate: Some(1.0), ..NONE };
+        let (out, now) = cap(Wheels::new(-300, 500), Wheels::STOP, TICK, caps);
+        assert_eq!(now, [false, true, false]);
+        assert_eq!(out.left as i32 + out.right as i32, 200);
+        assert!((turn_rate(out) - 1.0).abs() < 0.01, "{out:?}");
+    }
+
+    #[test]
+    fn spreads_speeding_up_over_both_wheels() {
+        let caps = Caps { accel: Some(500.0), ..NONE };
+        let (out, now) = cap(Wheels::new(200, 400), Wheels::STOP, TICK, caps);
+        assert_eq!(now, [false, false, true]);
+        // 50 mm/s for the faster wheel in 0.1 s, the other by the same fraction
+        assert_eq!(out, Wheels::new(25, 50));
+        let (out, _) = cap(Wheels::new(300, 300), Wheels::new(250, 250), TICK, caps);
+        assert_eq!(out, Wheels::new(300, 300));
+    }
+
+    #[test]
+    fn never_holds_back_slowing_down() {
+        let caps = Caps { speed: Some(300.0), turn_rate: Some(1.0), accel: Some(100.0) };
+        for (wheels, sent) in [(Wheels::STOP, Wheels::new(300, 300)), (Wheels::new(100, 100), Wheels::new(300, 300)), (Wheels::new(-100, -100), Wheels::new(-300, -300))] {
+            assert_eq!(cap(wheels, sent, TICK, caps), (wheels, [false; 3]), "{wheels:?} from {sent:?}");
+        }
+    }
+
+    #[test]
+    fn clamps_raw_drive_commands() {
+        let caps = Caps { speed: Some(200.0), ..NONE };
+        let direct = Wheels::new(500, 500).command().to_vec();
+        assert_eq!(command_within(&direct, Wheels::STOP, caps).unwrap(), Wheels::new(200, 200).command());
+        // Drive straight, velocity 500
+        assert_eq!(command_within(&[oi::DRIVE, 0x01, 0xf4, 0x7f, 0xff], Wheels::STOP, caps).unwrap(), Wheels::new(200, 200).command());
+        // On the spot, counter-clockwise
+        assert_eq!(command_within(&[oi::DRIVE, 0x01, 0x2c, 0, 1], Wheels::STOP, caps).unwrap(), [oi::DRIVE, 0x01, 0x2c, 0, 1]);
+        let within = Wheels::new(100, 150).command().to_vec();
+        assert_eq!(command_within(&within, Wheels::STOP, caps).unwrap(), within);
+        for other in [vec![oi::LEDS, 0, 0, 0], vec![oi::DRIVE_DIRECT, 0, 0, 0, 0], vec![oi::DRIVE_PWM, 0, 0, 0, 0]] {
+            assert_eq!(command_within(&other, Wheels::STOP, caps).unwrap(), other);
+        }
+    }
+
+    #[test]
+    fn refuses_raw_drive_commands_it_cant_cap() {
+        let pwm = [oi::DRIVE_PWM, 0, 100, 0, 100];
+        assert!(command_within(&pwm, Wheels::STOP, Caps { speed: Some(200.0), ..NONE }).is_err());
+        assert_eq!(command_within(&pwm, Wheels::STOP, NONE).unwrap(), pwm);
+        let accel = Caps { accel: Some(500.0), ..NONE };
+        assert!(command_within(&Wheels::new(100, 100).command(), Wheels::STOP, accel).is_err());
+        let slower = Wheels::new(100, 100).command().to_vec();
+        assert_eq!(command_within(&slower, Wheels::new(200, 200), accel).unwrap(), slower);
+    }
+}
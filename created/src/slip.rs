@@ -0,0 +1,169 @@
+//! Wheel slip: the Create 2's stasis sensor (packet 58), a caster at the
+//! back that toggles while the robot really moves forward, checked against
+//! the wheel encoders. Wheels turning forward without the stasis sensor
+//! toggling are slipping (a carpet edge, a wheel off the ground, pushing
+//! against something); the stasis sensor toggling with the wheels still
+//! means the robot is being carried or pushed. Either has to last
+//! `after_s` to count, and publishes `slip_started` and `slip_ended`
+//! events.
+//!
+//! While it lasts the pose estimate is worth less: its `confidence`
+//! starts at 1 and falls with the distance the wheels slip, or the time
+//! the robot is carried, until `createctl pose reset`. With `hold`, the
+//! pose doesn't count distance driven on slipping wheels.
+
+use std::sync::Mutex;
+use std::time::Instant;
+
+use tracing::{info, warn};
+
+use crate::bus::Event;
+use crate::config::SlipConfig;
+use crate::oi::SensorFrame;
+use crate::pose;
+
+const DISTANCE: u8 = 19;
+const LEFT_ENCODER: u8 = 43;
+const RIGHT_ENCODER: u8 = 44;
+const STASIS: u8 = 58;
+const TOGGLING: i32 = 0b01;
+const DISABLED: i32 = 0b10;
+/// Slower than this, mm/s, the wheels count as still.
+const STILL: f64 = 5.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Wheels,
+    Carried,
+}
+
+impl Kind {
+    fn as_str(self) -> &'static str {
+        match self {
+            Kind::Wheels => "wheels",
+            Kind::Carried => "carried",
+        }
+    }
+}
+
+struct Watch {
+    cfg: SlipConfig,
+    encoders: Option<(i32, i32)>,
+    last: Option<Instant>,
+    /// What the sensors disagree on, and since when
+    disagree: Option<(Kind, Instant)>,
+    /// Slipping for `after_s` or more
+    slipping: Option<(Kind, Instant)>,
+    confidence: f64,
+}
+
+static STATE: Mutex<Option<Watch>> = Mutex::new(None);
+static CONFIG: Mutex<Option<SlipConfig>> = Mutex::new(None);
+
+fn state() -> std::sync::MutexGuard<'static, Option<Watch>> {
+    STATE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Watch every session. Does nothing unless `pose.slip.enabled`.
+pub fn init(cfg: SlipConfig) {
+    if cfg.enabled() {
+        *CONFIG.lock().unwrap_or_else(|e| e.into_inner()) = Some(cfg);
+    }
+}
+
+pub fn begin() {
+    let cfg = CONFIG.lock().unwrap_or_else(|e| e.into_inner()).clone();
+    *state() = cfg.map(|cfg| Watch { cfg, encoders: None, last: None, disagree: None, slipping: None, confidence: 1.0 });
+}
+
+pub fn end() {
+    *state() = None;
+}
+
+/// How much the pose estimate is still worth, 0 to 1 (1 without slip
+/// detection).
+pub fn confidence() -> f64 {
+    state().as_ref().map_or(1.0, |w| w.confidence)
+}
+
+/// Whether the wheels are slipping, or the robot being carried, now.
+pub fn slipping() -> bool {
+    state().as_ref().is_some_and(|w| w.slipping.is_some())
+}
+
+/// Whether the pose should leave out the distance driven now.
+pub fn holding() -> bool {
+    state().as_ref().is_some_and(|w| w.cfg.hold() && w.slipping.is_some_and(|(k, _)| k == Kind::Wheels))
+}
+
+/// Full confidence again, with the pose reset.
+pub fn reset() {
+    if let Some(w) = state().as_mut() {
+        w.confidence = 1.0;
+    }
+}
+
+impl Watch {
+    /// Forward speed on the encoders since the last frame, mm/s.
+    fn speed(&mut self, frame: &SensorFrame, dt: f64) -> Option<f64> {
+        if let Some((left, right)) = frame.get(LEFT_ENCODER).zip(frame.get(RIGHT_ENCODER)) {
+            let last = self.encoders.replace((left, right))?;
+            let dl = (left.wrapping_sub(last.0) as u16 as i16) as f64;
+            let dr = (right.wrapping_sub(last.1) as u16 as i16) as f64;
+            return (dt > 0.0).then(|| (dl + dr) / 2.0 * pose::metres_per_count() * 1000.0 / dt);
+        }
+        let distance = frame.get(DISTANCE)? as f64;
+        (dt > 0.0).then(|| distance / dt)
+    }
+
+    fn update(&mut self, frame: &SensorFrame) -> Vec<Event> {
+        let now = Instant::now();
+        let dt = self.last.map_or(0.0, |l| now.duration_since(l).as_secs_f64()).min(0.1);
+        self.last = Some(now);
+        let speed = self.speed(frame, dt);
+        let seen = match (frame.get(STASIS), speed) {
+            (Some(stasis), Some(v)) if stasis & DISABLED == 0 => {
+                let toggling = stasis & TOGGLING != 0;
+                if v >= self.cfg.min_speed() && !toggling {
+                    Some(Kind::Wheels)
+                } else if v.abs() < STILL && toggling {
+                    Some(Kind::Carried)
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        };
+        let mut out = Vec::new();
+        if self.disagree.map(|(k, _)| k) != seen {
+            self.disagree = seen.map(|k| (k, now));
+        }
+        if let Some((kind, since)) = self.slipping {
+            if seen != Some(kind) {
+                let duration_s = now.duration_since(since).as_secs_f64();
+                info!("slip ended ({}, {duration_s:.1} s): pose confidence {:.2}", kind.as_str(), self.confidence);
+                out.push(Event::SlipEnded { kind: kind.as_str().to_string(), duration_s, confidence: self.confidence });
+                self.slipping = None;
+            }
+        }
+        if let Some((kind, since)) = self.disagree {
+            if self.slipping.is_none() && now.duration_since(since) >= self.cfg.after() {
+                warn!("slip: {}", if kind == Kind::Wheels { "the wheels are turning without the robot moving" } else { "the robot is moving without the wheels turning" });
+                out.push(Event::SlipStarted { kind: kind.as_str().to_string() });
+                self.slipping = Some((kind, since));
+            }
+        }
+        let lost = match self.slipping {
+            Some((Kind::Wheels, _)) => speed.unwrap_or(0.0).abs() * dt / 1000.0 / self.cfg.tolerance_m(),
+            Some((Kind::Carried, _)) => dt / self.cfg.carried_s(),
+            None => 0.0,
+        };
+        self.confidence = (self.confidence - lost).max(0.0);
+        out
+    }
+}
+
+/// Check one frame, before the pose is updated; events to publish.
+pub fn update(frame: &SensorFrame) -> Vec<Event> {
+    state().as_mut().map_or_else(Vec::new, |w| w.update(frame))
+}
@@ -0,0 +1,100 @@
+//! Raw serial sniffer: every byte sent to or received from the robot, as a
+//! timestamped hexdump in its own file, independent of normal logging.
+//! Enabled from config or toggled at runtime over the control socket.
+
+use std::fmt::Write as _;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+
+use chrono::{SecondsFormat, Utc};
+use tracing::{info, warn};
+
+use crate::config::SnifferConfig;
+
+static SNIFFER: OnceLock<Mutex<Sniffer>> = OnceLock::new();
+
+struct Sniffer {
+    path: PathBuf,
+    file: Option<File>,
+}
+
+fn sniffer() -> &'static Mutex<Sniffer> {
+    SNIFFER.get_or_init(|| Mutex::new(Sniffer { path: SnifferConfig::default().path(), file: None }))
+}
+
+/// Apply the configured output path, and start sniffing if enabled.
+pub fn init(cfg: &SnifferConfig) {
+    sniffer().lock().unwrap_or_else(|e| e.into_inner()).path = cfg.path();
+    if cfg.enabled() {
+        if let Err(e) = set_enabled(true) {
+            warn!("serial sniffer: {e}");
+        }
+    }
+}
+
+/// Start or stop sniffing. Returns the output path.
+pub fn set_enabled(on: bool) -> Result<PathBuf, String> {
+    let mut s = sniffer().lock().unwrap_or_else(|e| e.into_inner());
+    if on && s.file.is_none() {
+        if let Some(dir) = s.path.parent() {
+            fs::create_dir_all(dir).map_err(|e| format!("create {}: {e}", dir.display()))?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&s.path)
+            .map_err(|e| format!("open {}: {e}", s.path.display()))?;
+        s.file = Some(file);
+        info!("serial sniffer writing to {}", s.path.display());
+    } else if !on && s.file.take().is_some() {
+        info!("serial sniffer stopped");
+    }
+    Ok(s.path.clone())
+}
+
+pub fn enabled() -> bool {
+    sniffer().lock().unwrap_or_else(|e| e.into_inner()).file.is_some()
+}
+
+pub fn rx(data: &[u8]) {
+    record("RX", data);
+}
+
+pub fn tx(data: &[u8]) {
+    record("TX", data);
+}
+
+fn record(dir: &str, data: &[u8]) {
+    let Some(lock) = SNIFFER.get() else { return };
+    let mut s = lock.lock().unwrap_or_else(|e| e.into_inner());
+    let Some(file) = s.file.as_mut() else { return };
+    let text = hexdump(&Utc::now().to_rfc3339_opts(SecondsFormat::Micros, true), dir, data);
+    // Unbuffered on purpose: the point is to see the last bytes before a hang
+    if let Err(e) = file.write_all(text.as_bytes()) {
+        warn!("serial sniffer: write failed, stopping: {e}");
+        s.file = None;
+    }
+}
+
+/// `<time> <dir> <len> bytes` followed by `offset: hex |ascii|` rows of 16.
+fn hexdump(time: &str, dir: &str, data: &[u8]) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "{time} {dir} {} bytes", data.len());
+    for (row, chunk) in data.chunks(16).enumerate() {
+        let _ = write!(out, "  {:04x}: ", row * 16);
+        for i in 0..16 {
+            match chunk.get(i) {
+                Some(b) => {
+                    let _ = write!(out, "{b:02x} ");
+                }
+                None => out.push_str("   "),
+            }
+        }
+        out.push('|');
+        out.extend(chunk.iter().map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' }));
+        out.push_str("|\n");
+    }
+    out
+}
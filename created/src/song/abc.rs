@@ -0,0 +1,393 @@
+//! ABC notation, the plain-text tune format, in its simple single-voice
+//! form:
+//!
+//! ```text
+//! X:1
+//! T:Scale
+//! M:4/4
+//! L:1/8
+//! Q:1/4=120
+//! K:G
+//! GABc dedB | d2 g2 !fermata!g4 |]
+//! ```
+//!
+//! The header sets the title (`T:`), meter (`M:`), unit note length
+//! (`L:`), tempo (`Q:`), and key (`K:`, with modes); they may change in
+//! the tune too, on their own line or inline as `[K:D]`. Notes are `C` to
+//! `B` from middle C up, `c` to `b` an octave higher, with `,` and `'`
+//! for octaves down and up, `^`, `_`, and `=` accidentals (kept to the end
+//! of the bar), and lengths as multiples and fractions of the unit
+//! (`A2`, `A/2`, `A/`, `A3/2`); `z` is a rest. Ties (`-`), broken rhythm
+//! (`>`, `<`), and tuplets (`(3`) are followed. Chords play their first
+//! note; decorations, chord symbols, grace notes, and slurs are left out,
+//! and repeats are played once. Only the first tune of a file is read.
+
+use std::collections::HashMap;
+
+use super::Tone;
+
+/// Semitones above C of C, D, E, F, G, A, B.
+const SCALE: [i32; 7] = [0, 2, 4, 5, 7, 9, 11];
+/// Letters sharpened, in order, by sharp key signatures, and flattened by flat ones.
+const SHARPS: [usize; 7] = [3, 0, 4, 1, 5, 2, 6];
+const FLATS: [usize; 7] = [6, 2, 5, 1, 4, 0, 3];
+/// Middle C, written `C`.
+const MIDDLE_C: i32 = 60;
+/// Left out wherever they are: decorations and spacing.
+const IGNORED: &str = " \t`~.HLMOPSTuv)";
+/// The longest a note's length (in units) or a multi-bar rest (in bars) may be.
+const LONGEST: f64 = 256.0;
+
+struct Tune {
+    /// Unit note length, in whole notes
+    unit: Option<f64>,
+    /// Meter, in whole notes per bar
+    bar: f64,
+    /// Tempo: beats of `beat` whole notes per minute
+    beat: f64,
+    bpm: f64,
+    /// Semitones the key signature adds to each letter
+    key: [i32; 7],
+    title: Option<String>,
+}
+
+fn fraction(s: &str) -> Option<f64> {
+    let (n, d) = s.trim().split_once('/')?;
+    let (n, d): (f64, f64) = (n.trim().parse().ok()?, d.trim().parse().ok()?);
+    (n > 0.0 && d > 0.0).then_some(n / d)
+}
+
+/// Sharps (positive) or flats (negative) of a key like `G`, `F#m`, or `Bbdor`.
+fn signature(key: &str) -> Result<i32, String> {
+    let key = key.split_whitespace().next().unwrap_or("");
+    if key.is_empty() || key.eq_ignore_ascii_case("none") || key == "HP" || key == "Hp" {
+        return Ok(0);
+    }
+    let mut chars = key.chars();
+    let tonic = chars.next().unwrap_or('C').to_ascii_uppercase();
+    // Fifths above C of the major keys on each natural
+    let mut fifths = match tonic {
+        'C' => 0,
+        'G' => 1,
+        'D' => 2,
+        'A' => 3,
+        'E' => 4,
+        'B' => 5,
+        'F' => -1,
+        _ => return Err(format!("bad key {key:?}")),
+    };
+    let mut rest = chars.as_str();
+    if let Some(r) = rest.strip_prefix('#') {
+        fifths += 7;
+        rest = r;
+    } else if let Some(r) = rest.strip_prefix('b') {
+        fifths -= 7;
+        rest = r;
+    }
+    let mode = rest.to_ascii_lowercase();
+    let mode: String = mode.chars().take(3).collect();
+    fifths += match mode.as_str() {
+        "" | "maj" | "ion" => 0,
+        "m" | "min" | "aeo" => -3,
+        "mix" => -1,
+        "dor" => -2,
+        "phr" => -4,
+        "lyd" => 1,
+        "loc" => -5,
+        _ => return Err(format!("bad key {key:?}")),
+    };
+    if !(-7..=7).contains(&fifths) {
+        return Err(format!("bad key {key:?}"));
+    }
+    Ok(fifths)
+}
+
+impl Tune {
+    fn new() -> Self {
+        Tune { unit: None, bar: 1.0, beat: 0.25, bpm: 120.0, key: [0; 7], title: None }
+    }
+
+    /// The unit length: `L:`, else 1/16 in meters under 3/4 and 1/8 otherwise.
+    fn unit(&self) -> f64 {
+        self.unit.unwrap_or(if self.bar < 0.75 { 1.0 / 16.0 } else { 1.0 / 8.0 })
+    }
+
+    fn seconds(&self, whole_notes: f64) -> f64 {
+        whole_notes / self.beat * 60.0 / self.bpm
+    }
+
+    fn field(&mut self, name: char, value: &str) -> Result<(), String> {
+        let value = value.trim();
+        let bad = || format!("bad {name}: field {value:?}");
+        match name {
+            'T' => {
+                self.title.get_or_insert_with(|| value.to_string());
+            }
+            'M' => {
+                self.bar = match value {
+                    "C" | "C|" | "none" | "" => 1.0,
+                    _ => fraction(value).ok_or_else(bad)?,
+                }
+            }
+            'L' => self.unit = Some(fraction(value).ok_or_else(bad)?),
+            'Q' => {
+                // `1/4=120`, possibly with several beats and text around it,
+                // or an old `120` in units
+                let tempo = value.split('"').enumerate().filter(|(i, _)| i % 2 == 0).map(|(_, s)| s).collect::<String>();
+                match tempo.split_once('=') {
+                    Some((beats, bpm)) => {
+                        self.beat = beats.split_whitespace().map(fraction).sum::<Option<f64>>().ok_or_else(bad)?;
+                        self.bpm = bpm.trim().parse().ok().filter(|b: &f64| *b > 0.0).ok_or_else(bad)?;
+                    }
+                    None => {
+                        self.beat = self.unit();
+                        self.bpm = tempo.trim().parse().ok().filter(|b: &f64| *b > 0.0).ok_or_else(bad)?;
+                    }
+                }
+            }
+            'K' => {
+                let fifths = signature(value)?;
+                self.key = [0; 7];
+                let (order, shift) = if fifths >= 0 { (SHARPS, 1) } else { (FLATS, -1) };
+                for letter in order.iter().take(fifths.unsigned_abs() as usize) {
+                    self.key[*letter] = shift;
+                }
+            }
+            // Reference number, composer, notes, voices, and the like
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/// Reads the notes of a line of music into the melody.
+struct Music<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+}
+
+impl Music<'_> {
+    fn number(&mut self) -> Option<u32> {
+        let digits: String = std::iter::from_fn(|| self.chars.next_if(char::is_ascii_digit)).collect();
+        digits.parse().ok()
+    }
+
+    /// A length after a note: `2`, `/2`, `/`, `//`, `3/2`.
+    fn length(&mut self) -> Result<f64, String> {
+        let mut length = self.number().unwrap_or(1) as f64;
+        while self.chars.next_if_eq(&'/').is_some() {
+            match self.number().unwrap_or(2) {
+                0 => return Err("a note length divided by 0".into()),
+                d => length /= d as f64,
+            }
+        }
+        if length <= 0.0 || length > LONGEST {
+            return Err(format!("a note length of {length} units (0 to {LONGEST})"));
+        }
+        Ok(length)
+    }
+
+    fn skip_past(&mut self, end: char) {
+        for c in self.chars.by_ref() {
+            if c == end {
+                break;
+            }
+        }
+    }
+}
+
+/// Playing state carried across lines.
+#[derive(Default)]
+struct Player {
+    /// Accidentals written in this bar: (letter, octave) → semitones
+    accidentals: HashMap<(usize, i32), i32>,
+    tie: bool,
+    /// Length factor for the next note, from broken rhythm
+    broken: Option<f64>,
+    /// Notes left in a tuplet, and their length factor
+    tuplet: Option<(u32, f64)>,
+    /// Inside `[...]`: whether the chord's note was played
+    chord: Option<bool>,
+}
+
+impl Player {
+    fn line(&mut self, tune: &mut Tune, text: &str, melody: &mut Vec<Tone>) -> Result<(), String> {
+        let mut m = Music { chars: text.chars().peekable() };
+        while let Some(c) = m.chars.next() {
+            match c {
+                '%' => break,
+                c if IGNORED.contains(c) => {}
+                '"' => m.skip_past('"'),
+                '!' => m.skip_past('!'),
+                '+' => m.skip_past('+'),
+                '{' => m.skip_past('}'),
+                '|' | ':' => {
+                    self.accidentals.clear();
+                    // Repeat endings, `|1` and `:|2`
+                    m.number();
+                }
+                '[' if m.chars.peek().is_some_and(char::is_ascii_digit) => {
+                    m.number();
+                }
+                '[' if m.chars.clone().nth(1) == Some(':') => {
+                    let name = m.chars.next().unwrap_or(' ');
+                    m.chars.next();
+                    let value: String = std::iter::from_fn(|| m.chars.next_if(|c| *c != ']')).collect();
+                    m.chars.next();
+                    tune.field(name, &value)?;
+                }
+                '[' => self.chord = Some(false),
+                ']' => {
+                    if self.chord.take().is_none() {
+                        // The end of a `[|` or `|]` bar line
+                        continue;
+                    }
+                    let length = m.length()?;
+                    if let Some(last) = melody.last_mut() {
+                        last.seconds *= length;
+                    }
+                }
+                '(' => {
+                    if let Some(p) = m.number().filter(|p| *p >= 2) {
+                        let q = match p {
+                            3 | 6 => 2,
+                            2 | 4 | 8 => 3,
+                            _ => 2,
+                        };
+                        self.tuplet = Some((p, q as f64 / p as f64));
+                    }
+                }
+                '-' => self.tie = true,
+                '>' | '<' => {
+                    let mut dots = 1;
+                    while m.chars.next_if_eq(&c).is_some() {
+                        dots += 1;
+                    }
+                    let short = 0.5f64.powi(dots);
+                    let (this, next) = if c == '>' { (2.0 - short, short) } else { (short, 2.0 - short) };
+                    if let Some(last) = melody.last_mut() {
+                        last.seconds *= this;
+                    }
+                    self.broken = Some(next);
+                }
+                '^' | '_' | '=' | 'A'..='G' | 'a'..='g' | 'z' | 'x' | 'Z' | 'X' => self.note(tune, c, &mut m, melody)?,
+                _ => return Err(format!("unexpected {c:?} in {text:?}")),
+            }
+        }
+        Ok(())
+    }
+
+    fn note(&mut self, tune: &Tune, first: char, m: &mut Music, melody: &mut Vec<Tone>) -> Result<(), String> {
+        let mut accidental = None;
+        let mut c = first;
+        while let Some(shift) = match c {
+            '^' => Some(1),
+            '_' => Some(-1),
+            '=' => Some(0),
+            _ => None,
+        } {
+            accidental = Some(accidental.unwrap_or(0) + shift);
+            c = m.chars.next().ok_or("accidental without a note")?;
+        }
+        let pitch = match c {
+            'z' | 'x' => None,
+            'Z' | 'X' => {
+                // Whole bars of rest
+                let bars = m.number().unwrap_or(1) as f64;
+                if !(1.0..=LONGEST).contains(&bars) {
+                    return Err(format!("a rest of {bars} bars (1 to {LONGEST})"));
+                }
+                melody.push(Tone { pitch: None, seconds: tune.seconds(bars * tune.bar) });
+                return Ok(());
+            }
+            'A'..='G' | 'a'..='g' => {
+                let letter = "CDEFGAB".find(c.to_ascii_uppercase()).unwrap_or(0);
+                let mut octave = if c.is_ascii_lowercase() { 1 } else { 0 };
+                while let Some(mark) = m.chars.next_if(|c| *c == ',' || *c == '\'') {
+                    octave += if mark == '\'' { 1 } else { -1 };
+                }
+                let shift = match accidental {
+                    Some(shift) => {
+                        self.accidentals.insert((letter, octave), shift);
+                        shift
+                    }
+                    None => self.accidentals.get(&(letter, octave)).copied().unwrap_or(tune.key[letter]),
+                };
+                let midi = MIDDLE_C + 12 * octave + SCALE[letter] + shift;
+                Some(u8::try_from(midi).ok().filter(|m| *m <= 127).ok_or_else(|| format!("note {c:?} out of range"))?)
+            }
+            _ => return Err(format!("accidental before {c:?}")),
+        };
+        let mut length = m.length()? * tune.unit();
+        if let Some(factor) = self.broken.take() {
+            length *= factor;
+        }
+        if let Some((left, factor)) = self.tuplet {
+            length *= factor;
+            self.tuplet = (left > 1).then_some((left - 1, factor));
+        }
+        match self.chord {
+            Some(true) => return Ok(()),
+            Some(false) => self.chord = Some(true),
+            None => {}
+        }
+        let seconds = tune.seconds(length);
+        match melody.last_mut() {
+            Some(last) if std::mem::take(&mut self.tie) && last.pitch == pitch => last.seconds += seconds,
+            _ => melody.push(Tone { pitch, seconds }),
+        }
+        Ok(())
+    }
+}
+
+/// The title and melody of the first tune in `text`.
+pub fn parse(text: &str) -> Result<(String, Vec<Tone>), String> {
+    let mut tune = Tune::new();
+    let mut player = Player::default();
+    let mut melody = Vec::new();
+    let mut body = false;
+    for line in text.lines() {
+        let line = line.trim();
+        let mut chars = line.chars();
+        // Fields are capitals, but for lyrics and remarks: `c:` would be music
+        if let (Some(name @ ('A'..='Z' | 'w' | 'r')), Some(':')) = (chars.next(), chars.next()) {
+            if name == 'X' && body {
+                break;
+            }
+            body |= name == 'K';
+            tune.field(name, chars.as_str())?;
+            continue;
+        }
+        if line.is_empty() && body && !melody.is_empty() {
+            // A blank line ends the tune
+            break;
+        }
+        body = true;
+        player.line(&mut tune, line, &mut melody)?;
+    }
+    if melody.is_empty() {
+        return Err("no notes".into());
+    }
+    Ok((tune.title.unwrap_or_else(|| "abc".into()), melody))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn seconds(music: &str) -> Result<Vec<f64>, String> {
+        let (_, melody) = parse(&format!("X:1\nL:1/4\nQ:1/4=60\nK:C\n{music}\n"))?;
+        Ok(melody.iter().map(|t| t.seconds).collect())
+    }
+
+    #[test]
+    fn reads_note_lengths() {
+        assert_eq!(seconds("C C2 C/2 C/ C// C3/2 Z2").unwrap(), [1.0, 2.0, 0.5, 0.5, 0.25, 1.5, 8.0]);
+    }
+
+    #[test]
+    fn refuses_lengths_that_are_zero_or_huge() {
+        for music in ["C/0", "C3/0", "C0", "C0/2", "C4294967295", "C257", "[CE]/0", "Z0", "Z100000"] {
+            assert!(seconds(music).is_err(), "{music}");
+        }
+    }
+}
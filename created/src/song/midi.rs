@@ -0,0 +1,228 @@
+//! Standard MIDI files (formats 0 and 1), read down to one melody: the
+//! notes of one track, one at a time. A note starting while another still
+//! sounds cuts it short, so chords come out as their last note struck.
+//! Tempo changes are followed wherever they are (format 1 files keep them
+//! in the first track); SMPTE time division isn't supported.
+
+use std::fs;
+use std::path::Path;
+
+use super::Tone;
+
+/// Tempo before any tempo event: 120 beats per minute, µs per quarter note.
+const DEFAULT_TEMPO: f64 = 500_000.0;
+/// Channel 10, percussion: notes that are drums, not pitches.
+const DRUMS: u8 = 9;
+
+struct Reader<'a> {
+    data: &'a [u8],
+    at: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        let bytes = self.data.get(self.at..self.at + n).ok_or("unexpected end of file")?;
+        self.at += n;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        let b = self.take(4)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    /// A variable-length quantity: 7 bits a byte, high bit set on all but the last.
+    fn vlq(&mut self) -> Result<u32, String> {
+        let mut value = 0u32;
+        for _ in 0..4 {
+            let b = self.byte()?;
+            value = (value << 7) | (b & 0x7f) as u32;
+            if b & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err("variable-length quantity longer than 4 bytes".into())
+    }
+
+    fn done(&self) -> bool {
+        self.at >= self.data.len()
+    }
+}
+
+/// A note on or off, at an absolute tick.
+struct NoteEvent {
+    tick: u64,
+    channel: u8,
+    note: u8,
+    on: bool,
+}
+
+#[derive(Default)]
+struct Track {
+    notes: Vec<NoteEvent>,
+    /// (tick, µs per quarter note)
+    tempos: Vec<(u64, f64)>,
+}
+
+fn track(data: &[u8]) -> Result<Track, String> {
+    let mut r = Reader { data, at: 0 };
+    let mut out = Track::default();
+    let mut tick = 0u64;
+    let mut running: Option<u8> = None;
+    while !r.done() {
+        tick += r.vlq()? as u64;
+        let mut status = r.byte()?;
+        match status {
+            0xff => {
+                let kind = r.byte()?;
+                let len = r.vlq()? as usize;
+                let body = r.take(len)?;
+                match (kind, body) {
+                    (0x51, [a, b, c]) => out.tempos.push((tick, u32::from_be_bytes([0, *a, *b, *c]) as f64)),
+                    (0x2f, _) => break,
+                    _ => {}
+                }
+                continue;
+            }
+            0xf0 | 0xf7 => {
+                let len = r.vlq()? as usize;
+                r.take(len)?;
+                continue;
+            }
+            _ => {}
+        }
+        // Running status: a data byte reuses the last status byte
+        let first = if status & 0x80 == 0 {
+            let data = status;
+            status = running.ok_or("data byte without a status byte")?;
+            data
+        } else {
+            running = Some(status);
+            r.byte()?
+        };
+        let channel = status & 0x0f;
+        match status & 0xf0 {
+            0x80 => {
+                r.byte()?;
+                out.notes.push(NoteEvent { tick, channel, note: first, on: false });
+            }
+            0x90 => {
+                let velocity = r.byte()?;
+                out.notes.push(NoteEvent { tick, channel, note: first, on: velocity > 0 });
+            }
+            0xa0 | 0xb0 | 0xe0 => {
+                r.byte()?;
+            }
+            0xc0 | 0xd0 => {}
+            _ => return Err(format!("unknown MIDI status byte {status:#04x}")),
+        }
+    }
+    Ok(out)
+}
+
+/// Seconds at each tick, by the tempo map.
+struct Clock {
+    /// Ticks per quarter note
+    division: f64,
+    /// (tick, µs per quarter note), by tick
+    tempos: Vec<(u64, f64)>,
+}
+
+impl Clock {
+    fn seconds(&self, tick: u64) -> f64 {
+        let mut seconds = 0.0;
+        let (mut at, mut tempo) = (0u64, DEFAULT_TEMPO);
+        for &(t, next) in self.tempos.iter().take_while(|(t, _)| *t < tick) {
+            seconds += (t - at) as f64 * tempo / self.division / 1e6;
+            (at, tempo) = (t, next);
+        }
+        seconds + (tick - at) as f64 * tempo / self.division / 1e6
+    }
+}
+
+/// The melody of `data`'s track `track` (from 0), or of the first track
+/// with notes off the drum channel.
+pub fn parse(data: &[u8], track_index: Option<usize>) -> Result<Vec<Tone>, String> {
+    let mut r = Reader { data, at: 0 };
+    if r.take(4)? != b"MThd" {
+        return Err("not a MIDI file".into());
+    }
+    let len = r.u32()? as usize;
+    let header = r.take(len)?;
+    let &[format_hi, format_lo, _, _, division_hi, division_lo, ..] = header else {
+        return Err("short MIDI header".into());
+    };
+    let format = u16::from_be_bytes([format_hi, format_lo]);
+    if format > 1 {
+        return Err(format!("MIDI format {format} isn't supported, only 0 and 1"));
+    }
+    let division = u16::from_be_bytes([division_hi, division_lo]);
+    if division & 0x8000 != 0 || division == 0 {
+        return Err("SMPTE time division isn't supported".into());
+    }
+    let mut tracks = Vec::new();
+    while !r.done() {
+        let kind = r.take(4)?;
+        let len = r.u32()? as usize;
+        let body = r.take(len)?;
+        // Unknown chunks are to be skipped
+        if kind == b"MTrk" {
+            tracks.push(track(body)?);
+        }
+    }
+    let mut tempos: Vec<(u64, f64)> = tracks.iter().flat_map(|t| t.tempos.iter().copied()).collect();
+    tempos.sort_by_key(|(tick, _)| *tick);
+    let clock = Clock { division: division as f64, tempos };
+
+    let mut notes: Vec<&NoteEvent> = match track_index {
+        Some(i) => tracks.get(i).ok_or_else(|| format!("no track {i}: the file has {}", tracks.len()))?.notes.iter().collect(),
+        None => tracks
+            .iter()
+            .map(|t| t.notes.iter().filter(|n| n.channel != DRUMS).collect::<Vec<_>>())
+            .find(|n| n.iter().any(|n| n.on))
+            .ok_or("no notes in the file")?,
+    };
+    // Offs before ons at the same tick, so a repeated note isn't cut off at once
+    notes.sort_by_key(|n| (n.tick, n.on));
+
+    let mut melody = Vec::new();
+    let mut sounding: Option<(u8, u64)> = None;
+    let mut last = None;
+    for n in notes {
+        // Other notes' offs don't matter: only the sounding one's, and any on
+        let matters = match sounding {
+            Some((pitch, _)) => n.on || n.note == pitch,
+            None => n.on,
+        };
+        if !matters {
+            continue;
+        }
+        let at = clock.seconds(n.tick);
+        match (sounding.take(), last) {
+            (Some((pitch, start)), _) => melody.push(Tone { pitch: Some(pitch), seconds: at - clock.seconds(start) }),
+            (None, Some(silent_since)) => melody.push(Tone { pitch: None, seconds: at - silent_since }),
+            (None, None) => {}
+        }
+        last = Some(at);
+        if n.on {
+            sounding = Some((n.note, n.tick));
+        }
+    }
+    melody.retain(|t| t.seconds > 0.0);
+    if melody.is_empty() {
+        return Err(match track_index {
+            Some(i) => format!("no notes in track {i}"),
+            None => "no notes in the file".into(),
+        });
+    }
+    Ok(melody)
+}
+
+pub fn read(path: &Path, track: Option<usize>) -> Result<Vec<Tone>, String> {
+    let data = fs::read(path).map_err(|e| format!("read {}: {e}", path.display()))?;
+    parse(&data, track).map_err(|e| format!("{}: {e}", path.display()))
+}
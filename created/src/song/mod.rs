@@ -0,0 +1,378 @@
+//! Songs: melodies read from MIDI files, RTTTL ringtones, or ABC tunes,
+//! encoded as OI song notes, and played by the session. The OI keeps 16 songs of at most 16 notes, each note a
+//! MIDI note number from 31 to 127 (anything else is a rest) held for a
+//! number of 1/64 s. Melodies reaching outside that range are first
+//! fitted into it (`songs.fit`): transposed by octaves, or with the notes
+//! outside folded an octave at a time. Longer melodies are split into
+//! 16-note chunks, stored in turn in the slots from `songs.first_slot` on.
+//!
+//! Songs queue up and play one after another. The OI ignores Play while a
+//! song is playing, and storing a song over one that plays garbles it, so
+//! each chunk waits for the song-playing packet (37) to clear: first for
+//! whatever else is playing, then for the chunk before it. Without packet
+//! 37 in the stream, chunks are timed by their length instead.
+
+pub mod abc;
+pub mod midi;
+pub mod rtttl;
+
+use std::collections::VecDeque;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+use tracing::{info, warn};
+
+use crate::config::{SongFit, SongsConfig};
+use crate::motion;
+use crate::oi::{self, SensorFrame};
+use crate::quiet;
+
+/// One note or rest of a melody, before encoding.
+#[derive(Debug, Clone, Copy)]
+pub struct Tone {
+    /// MIDI note number; none for a rest
+    pub pitch: Option<u8>,
+    pub seconds: f64,
+}
+
+/// An OI song note: MIDI note number, and duration in 1/64 s.
+pub type Note = [u8; 2];
+
+pub const MAX_NOTES: usize = 16;
+const LOWEST: u8 = 31;
+const HIGHEST: u8 = 127;
+/// Outside the playable range, so the OI rests.
+const REST: u8 = 0;
+/// The longest a melody may be, encoded: its notes are queued up front.
+const LONGEST: Duration = Duration::from_secs(600);
+const SONG_PLAYING: u8 = 37;
+/// Timed without packet 37: left between one chunk's end and the next
+/// chunk's Play, which the OI would ignore if the first were still playing.
+const GAP: Duration = Duration::from_millis(30);
+/// A chunk packet 37 doesn't show playing within this was never played.
+const STARTS_WITHIN: Duration = Duration::from_millis(200);
+/// A chunk still showing playing this long past its length is taken as over.
+const OVERDUE: Duration = Duration::from_secs(1);
+
+/// `pitch` moved by octaves into 31-127.
+fn fold(mut pitch: i32) -> u8 {
+    while pitch < LOWEST as i32 {
+        pitch += 12;
+    }
+    while pitch > HIGHEST as i32 {
+        pitch -= 12;
+    }
+    pitch as u8
+}
+
+/// `melody` with its pitches fitted into the OI's range as `how` says; the
+/// octaves it was transposed by and the notes changed on their own.
+pub fn fit(melody: &[Tone], how: SongFit) -> (Vec<Tone>, i32, usize) {
+    let outside = |shift: i32| melody.iter().filter_map(|t| t.pitch).filter(|p| !(LOWEST as i32..=HIGHEST as i32).contains(&(*p as i32 + shift))).count();
+    // The shift leaving the fewest notes outside, the smallest of those
+    let octaves = match how {
+        SongFit::Transpose => (-10..=10).min_by_key(|k: &i32| (outside(12 * k), k.abs())).unwrap_or(0),
+        SongFit::Fold | SongFit::Clamp => 0,
+    };
+    let changed = outside(12 * octaves);
+    let fitted = melody
+        .iter()
+        .map(|t| {
+            let pitch = t.pitch.map(|p| p as i32 + 12 * octaves).map(|p| match how {
+                SongFit::Clamp => p.clamp(LOWEST as i32, HIGHEST as i32) as u8,
+                SongFit::Transpose | SongFit::Fold => fold(p),
+            });
+            Tone { pitch, ..*t }
+        })
+        .collect();
+    (fitted, octaves, changed)
+}
+
+/// `melody` in OI notes: pitches clamped to 31-127, durations rounded to
+/// 1/64 s without the rounding adding up over the melody, and anything
+/// longer than the OI's longest note (255/64 s) continued as rests. Err
+/// for a melody longer than `LONGEST`, or with a length that isn't finite.
+pub fn encode(melody: &[Tone]) -> Result<Vec<Note>, String> {
+    if melody.iter().any(|t| !t.seconds.is_finite()) {
+        return Err("a note's length isn't a number".into());
+    }
+    let total: f64 = melody.iter().map(|t| t.seconds.max(0.0)).sum();
+    if total > LONGEST.as_secs_f64() {
+        return Err(format!("{total:.0} s long, longer than a song may be ({} s)", LONGEST.as_secs()));
+    }
+    let mut notes = Vec::new();
+    let (mut t, mut at) = (0.0, 0u64);
+    for tone in melody {
+        t += tone.seconds.max(0.0);
+        let end = (t * 64.0).round() as u64;
+        let mut pitch = tone.pitch.map_or(REST, |p| p.clamp(LOWEST, HIGHEST));
+        while at < end {
+            let duration = (end - at).min(u8::MAX as u64);
+            notes.push([pitch, duration as u8]);
+            at += duration;
+            pitch = REST;
+        }
+    }
+    Ok(notes)
+}
+
+/// How long `notes` take to play.
+pub fn length(notes: &[Note]) -> Duration {
+    Duration::from_secs_f64(notes.iter().map(|[_, d]| *d as f64).sum::<f64>() / 64.0)
+}
+
+/// A chunk sent to the robot.
+struct Sent {
+    at: Instant,
+    length: Duration,
+    /// Packet 37 has shown it playing
+    started: bool,
+}
+
+struct Song {
+    name: String,
+    chunks: Vec<Vec<Note>>,
+    /// The next chunk to play
+    next: usize,
+    /// The chunk sent last, until it is over
+    sent: Option<Sent>,
+    /// When its first chunk was sent
+    started: Option<Instant>,
+    /// Queued to cut in after the chunk playing
+    cut_in: bool,
+}
+
+/// The connected robot's songs: the one playing first.
+struct Player {
+    queue: VecDeque<Song>,
+}
+
+static CONFIG: Mutex<Option<SongsConfig>> = Mutex::new(None);
+static STATE: Mutex<Option<Player>> = Mutex::new(None);
+
+fn config() -> SongsConfig {
+    CONFIG.lock().unwrap_or_else(|e| e.into_inner()).clone().unwrap_or_default()
+}
+
+fn state() -> std::sync::MutexGuard<'static, Option<Player>> {
+    STATE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+pub fn init(cfg: SongsConfig) {
+    *CONFIG.lock().unwrap_or_else(|e| e.into_inner()) = Some(cfg);
+}
+
+/// Ready to play for a new session: true if the configured greeting is
+/// queued, so the built-in one isn't needed.
+pub fn begin() -> bool {
+    *state() = Some(Player { queue: VecDeque::new() });
+    let Some(greeting) = config().greeting else { return false };
+    match load(&greeting, None).and_then(|notes| queue(&greeting, notes, false)) {
+        Ok(_) => true,
+        Err(e) => {
+            warn!("greeting: {e}");
+            false
+        }
+    }
+}
+
+pub fn end() {
+    *state() = None;
+}
+
+/// A path as it is, else `name`, `name.mid`, `name.midi`, or `name.abc` in `songs.dir`.
+fn locate(name: &str, dir: &Path) -> Result<PathBuf, String> {
+    let path = Path::new(name);
+    if path.components().count() > 1 || path.exists() {
+        return Ok(path.to_path_buf());
+    }
+    ["", ".mid", ".midi", ".abc"]
+        .iter()
+        .map(|ext| dir.join(format!("{name}{ext}")))
+        .find(|p| p.is_file())
+        .ok_or_else(|| format!("no song {name:?} in {}", dir.display()))
+}
+
+/// `melody` fitted into the OI's range (`songs.fit`) and in OI notes,
+/// unless nothing is left of it.
+pub fn encoded(what: &str, melody: &[Tone]) -> Result<Vec<Note>, String> {
+    let how = config().fit();
+    let (melody, octaves, changed) = fit(melody, how);
+    if octaves != 0 {
+        let plural = if octaves.abs() == 1 { "" } else { "s" };
+        info!("{what}: transposed {} {} octave{plural} to fit the OI's range", if octaves > 0 { "up" } else { "down" }, octaves.abs());
+    }
+    if changed > 0 {
+        let what_happened = if how == SongFit::Clamp { "clamped" } else { "folded by octaves" };
+        warn!("{what}: {changed} of its notes outside the OI's range (MIDI 31-127) {what_happened}");
+    }
+    let notes = encode(&melody).map_err(|e| format!("{what}: {e}"))?;
+    if notes.is_empty() {
+        return Err(format!("{what}: every note is too short to play"));
+    }
+    Ok(notes)
+}
+
+/// The melody of the ringtone `name` in `songs.rtttl` or the tune in
+/// `songs.abc`, else of an ABC file or a MIDI file, from `track` (default
+/// `songs.track`), in OI notes.
+pub fn load(name: &str, track: Option<usize>) -> Result<Vec<Note>, String> {
+    let cfg = config();
+    if let Some(text) = cfg.rtttl.as_ref().and_then(|r| r.get(name)) {
+        let (_, melody) = rtttl::parse(text).map_err(|e| format!("songs.rtttl.{name}: {e}"))?;
+        return encoded(name, &melody);
+    }
+    if let Some(text) = cfg.abc.as_ref().and_then(|a| a.get(name)) {
+        let (_, melody) = abc::parse(text).map_err(|e| format!("songs.abc.{name}: {e}"))?;
+        return encoded(name, &melody);
+    }
+    let path = locate(name, &cfg.dir())?;
+    if path.extension().is_some_and(|e| e == "abc") {
+        let text = fs::read_to_string(&path).map_err(|e| format!("read {}: {e}", path.display()))?;
+        let (_, melody) = abc::parse(&text).map_err(|e| format!("{}: {e}", path.display()))?;
+        return encoded(&path.display().to_string(), &melody);
+    }
+    encoded(&path.display().to_string(), &midi::read(&path, track.or(cfg.track))?)
+}
+
+/// Queue `notes` to play on the connected robot after the songs queued
+/// before, or with `next`, right after the chunk playing now; refused in
+/// quiet mode.
+pub fn queue(name: &str, notes: Vec<Note>, next: bool) -> Result<Value, String> {
+    if quiet::on() {
+        return Err("quiet mode is on".into());
+    }
+    enqueue(name, notes, next)
+}
+
+/// Queue `notes` right after the chunk playing now, even in quiet mode:
+/// for critical beeps.
+pub fn queue_critical(name: &str, notes: Vec<Note>) -> Result<Value, String> {
+    enqueue(name, notes, true)
+}
+
+fn enqueue(name: &str, notes: Vec<Note>, next: bool) -> Result<Value, String> {
+    let mut guard = state();
+    let player = guard.as_mut().ok_or("no robot is connected")?;
+    let chunks: Vec<Vec<Note>> = notes.chunks(MAX_NOTES).map(<[Note]>::to_vec).collect();
+    let seconds = length(&notes).as_secs_f64();
+    // Cutting in goes after the others waiting to, but ahead of the rest
+    let ahead = if next { player.queue.iter().take_while(|s| s.cut_in && s.next == 0).count() } else { player.queue.len() };
+    info!("queued {name}: {} notes in {} chunks, {seconds:.1} s, after {ahead} songs", notes.len(), chunks.len());
+    let reply = json!({ "song": name, "notes": notes.len(), "chunks": chunks.len(), "seconds": seconds, "ahead": ahead });
+    // First in line, it waits for the chunk playing now in its place
+    let sent = if next && ahead == 0 { player.queue.front_mut().and_then(|s| s.sent.take()) } else { None };
+    player.queue.insert(ahead, Song { name: name.to_string(), chunks, next: 0, sent, started: None, cut_in: next });
+    Ok(reply)
+}
+
+/// Play an RTTTL ringtone given as it is.
+pub fn play_rtttl(text: &str) -> Result<Value, String> {
+    let (name, melody) = rtttl::parse(text)?;
+    let name = if name.is_empty() { "rtttl".to_string() } else { name };
+    queue(&name, encoded(&name, &melody)?, false)
+}
+
+/// Play a song from `songs.rtttl` or `songs.abc`, or a file from `songs.dir` or a path.
+pub fn play(name: &str, track: Option<usize>) -> Result<Value, String> {
+    queue(name, load(name, track)?, false)
+}
+
+/// Empty the queue, stopping the song playing after its current chunk:
+/// the OI can't cut a song short.
+pub fn stop() -> Result<Value, String> {
+    let mut guard = state();
+    let player = guard.as_mut().ok_or("no robot is connected")?;
+    let stopped: Vec<String> = player.queue.drain(..).map(|s| s.name).collect();
+    Ok(json!({ "stopped": stopped }))
+}
+
+/// The song playing, how far along it is, and the songs queued after it.
+pub fn status() -> Value {
+    let guard = state();
+    let Some(player) = guard.as_ref() else { return json!({ "playing": null, "queued": [] }) };
+    let queued: Vec<&str> = player.queue.iter().skip(1).map(|s| s.name.as_str()).collect();
+    let Some(s) = player.queue.front() else { return json!({ "playing": null, "queued": queued }) };
+    json!({
+        "playing": s.name,
+        "chunk": s.next,
+        "chunks": s.chunks.len(),
+        "elapsed_s": s.started.map(|t| t.elapsed().as_secs_f64()),
+        "queued": queued
+    })
+}
+
+impl Sent {
+    /// Whether the chunk is done playing, by packet 37 if streamed.
+    fn over(&mut self, playing: Option<bool>, now: Instant) -> bool {
+        let elapsed = now.duration_since(self.at);
+        match playing {
+            Some(true) => {
+                self.started = true;
+                elapsed > self.length + OVERDUE
+            }
+            Some(false) => self.started || elapsed > STARTS_WITHIN,
+            None => elapsed >= self.length + GAP,
+        }
+    }
+}
+
+/// Store and play the next chunk once the robot has finished the last
+/// one; call every frame.
+pub fn update(frame: &SensorFrame) {
+    let mut guard = state();
+    let Some(player) = guard.as_mut() else { return };
+    let Some(song) = player.queue.front_mut() else { return };
+    let now = Instant::now();
+    let playing = frame.get(SONG_PLAYING).map(|p| p != 0);
+    if let Some(sent) = song.sent.as_mut() {
+        if !sent.over(playing, now) {
+            return;
+        }
+        song.sent = None;
+    }
+    let Some(chunk) = song.chunks.get(song.next) else {
+        info!("finished playing {}", song.name);
+        player.queue.pop_front();
+        return;
+    };
+    // Someone else's song: a mission step, the gamepad, the greeting
+    if playing == Some(true) {
+        return;
+    }
+    let cfg = config();
+    let number = cfg.first_slot() + (song.next % cfg.slots() as usize) as u8;
+    let mut command = vec![oi::SONG, number, chunk.len() as u8];
+    command.extend(chunk.iter().flatten());
+    motion::queue(command);
+    motion::queue(vec![oi::PLAY, number]);
+    if song.next == 0 {
+        info!("playing {}", song.name);
+        song.started = Some(now);
+    }
+    song.sent = Some(Sent { at: now, length: length(chunk), started: false });
+    song.next += 1;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_long_notes_as_rests_after() {
+        let melody = [Tone { pitch: Some(60), seconds: 5.0 }, Tone { pitch: None, seconds: 0.5 }];
+        assert_eq!(encode(&melody).unwrap(), [[60, 255], [REST, 65], [REST, 32]]);
+    }
+
+    #[test]
+    fn refuses_endless_melodies() {
+        for seconds in [f64::INFINITY, f64::NAN, 601.0] {
+            assert!(encode(&[Tone { pitch: Some(60), seconds }]).is_err(), "{seconds}");
+        }
+        let long = vec![Tone { pitch: Some(60), seconds: 1.0 }; 601];
+        assert!(encode(&long).is_err());
+    }
+}
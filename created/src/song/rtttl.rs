@@ -0,0 +1,108 @@
+//! RTTTL, the ringtone format: `name:d=4,o=5,b=100:8e6,8d#6,p,4c.`. A
+//! name, then defaults for duration (as a fraction of a whole note),
+//! octave, and beats (quarter notes) per minute, then the notes, each an
+//! optional duration, a letter (`p` a pause), an optional `#`, an
+//! optional `.` lengthening it by half, and an optional octave (0 to 9).
+//! `a` of octave 4 is 440 Hz.
+
+use super::Tone;
+
+/// Semitones above C of the note letters.
+fn semitone(letter: char) -> Option<u8> {
+    Some(match letter {
+        'c' => 0,
+        'd' => 2,
+        'e' => 4,
+        'f' => 5,
+        'g' => 7,
+        'a' => 9,
+        // Some ringtones write b as h, the German way
+        'b' | 'h' => 11,
+        _ => return None,
+    })
+}
+
+const DURATIONS: [u32; 6] = [1, 2, 4, 8, 16, 32];
+
+fn duration(d: u32) -> Result<u32, String> {
+    DURATIONS.contains(&d).then_some(d).ok_or_else(|| format!("bad duration {d}: expected 1, 2, 4, 8, 16, or 32"))
+}
+
+/// Octaves 0 to 9 reach every MIDI note up to 127.
+fn octave(o: u32) -> Result<u32, String> {
+    (o <= 9).then_some(o).ok_or_else(|| format!("bad octave {o}: expected 0 to 9"))
+}
+
+/// The name and melody of an RTTTL ringtone.
+pub fn parse(text: &str) -> Result<(String, Vec<Tone>), String> {
+    let mut sections = text.trim().splitn(3, ':');
+    let (Some(name), Some(defaults), Some(notes)) = (sections.next(), sections.next(), sections.next()) else {
+        return Err("expected name:defaults:notes".into());
+    };
+    let (mut d, mut o, mut b) = (4, 6, 63);
+    for setting in defaults.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        let (key, value) = setting.split_once('=').ok_or_else(|| format!("bad default {setting:?}"))?;
+        let value: u32 = value.trim().parse().map_err(|_| format!("bad default {setting:?}"))?;
+        match key.trim() {
+            "d" => d = duration(value)?,
+            "o" => o = octave(value)?,
+            "b" if value > 0 => b = value,
+            _ => return Err(format!("bad default {setting:?}")),
+        }
+    }
+    let whole = 240.0 / b as f64;
+    let mut melody = Vec::new();
+    for note in notes.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        let bad = || format!("bad note {note:?}");
+        let lower = note.to_ascii_lowercase();
+        let letter_at = lower.find(|c: char| c.is_ascii_alphabetic()).ok_or_else(bad)?;
+        let (length, rest) = lower.split_at(letter_at);
+        let length = if length.is_empty() { d } else { duration(length.parse().map_err(|_| bad())?)? };
+        let mut chars = rest.chars().peekable();
+        let letter = chars.next().ok_or_else(bad)?;
+        let sharp = chars.next_if_eq(&'#').is_some();
+        let mut dotted = chars.next_if_eq(&'.').is_some();
+        let digits: String = std::iter::from_fn(|| chars.next_if(char::is_ascii_digit)).collect();
+        dotted |= chars.next_if_eq(&'.').is_some();
+        if chars.next().is_some() {
+            return Err(bad());
+        }
+        let octave = if digits.is_empty() { o } else { digits.parse().ok().and_then(|o| octave(o).ok()).ok_or_else(bad)? };
+        let pitch = match letter {
+            'p' => None,
+            _ => {
+                let step = semitone(letter).ok_or_else(bad)? + sharp as u8;
+                let midi = (octave + 1).checked_mul(12).and_then(|m| m.checked_add(step as u32));
+                Some(midi.and_then(|m| u8::try_from(m).ok()).filter(|m| *m <= 127).ok_or_else(bad)?)
+            }
+        };
+        let seconds = whole / length as f64 * if dotted { 1.5 } else { 1.0 };
+        melody.push(Tone { pitch, seconds });
+    }
+    if melody.is_empty() {
+        return Err("no notes".into());
+    }
+    Ok((name.trim().to_string(), melody))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_pitches_and_lengths() {
+        let (name, melody) = parse("x:d=4,o=5,b=60:a4,8c#6,p,2g.,c9").unwrap();
+        assert_eq!(name, "x");
+        let pitches: Vec<_> = melody.iter().map(|t| t.pitch).collect();
+        assert_eq!(pitches, [Some(69), Some(85), None, Some(79), Some(120)]);
+        let seconds: Vec<_> = melody.iter().map(|t| t.seconds).collect();
+        assert_eq!(seconds, [1.0, 0.5, 1.0, 3.0, 1.0]);
+    }
+
+    #[test]
+    fn refuses_octaves_out_of_range() {
+        for text in ["x:d=4,o=5,b=9:c4294967295", "x:d=4,o=5,b=9:c10", "x:d=4,o=5,b=9:b9", "x:d=4,o=4294967295,b=9:c", "x:d=4,o=10,b=9:c"] {
+            assert!(parse(text).is_err(), "{text}");
+        }
+    }
+}
@@ -0,0 +1,191 @@
+//! Per-session statistics: distance, rotation, bumps, cliffs, time spent
+//! driving vs idle, and energy drawn from the battery. The running session's
+//! figures and the last finished session's summary back the `status` control
+//! command; the summary is also logged and published as a `session_summary`
+//! event when the session ends.
+//!
+//! The same figures are also kept per local day: an `activity` event covers
+//! the part of a session that fell on one day, published at local midnight
+//! and when the session ends, so daily reports can add them up.
+
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use chrono::{DateTime, Local, NaiveDate, SecondsFormat, Utc};
+use serde::Serialize;
+
+use crate::bus::Event;
+use crate::oi::SensorFrame;
+
+const DISTANCE: u8 = 19;
+const ANGLE: u8 = 20;
+const VOLTAGE: u8 = 22;
+const CURRENT: u8 = 23;
+/// Frame gaps longer than this (a stalled stream) are not counted as time
+/// driving or idle.
+const MAX_GAP: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionStats {
+    pub device: String,
+    pub started: String,
+    pub ended: Option<String>,
+    pub duration_s: f64,
+    pub frames: u64,
+    /// Sum of wheel travel in both directions
+    pub distance_m: f64,
+    /// Sum of rotation in both directions
+    pub turned_deg: f64,
+    pub bumps: u64,
+    pub wheel_drops: u64,
+    pub cliffs: u64,
+    pub driving_s: f64,
+    pub idle_s: f64,
+    /// Energy drawn while discharging (voltage × current over time)
+    pub energy_wh: f64,
+    #[serde(skip)]
+    start: SystemTime,
+    #[serde(skip)]
+    last_frame: Option<SystemTime>,
+}
+
+impl SessionStats {
+    fn new(device: &str) -> Self {
+        let start = SystemTime::now();
+        SessionStats {
+            device: device.to_string(),
+            started: timestamp(start),
+            ended: None,
+            duration_s: 0.0,
+            frames: 0,
+            distance_m: 0.0,
+            turned_deg: 0.0,
+            bumps: 0,
+            wheel_drops: 0,
+            cliffs: 0,
+            driving_s: 0.0,
+            idle_s: 0.0,
+            energy_wh: 0.0,
+            start,
+            last_frame: None,
+        }
+    }
+
+    fn frame(&mut self, frame: &SensorFrame) {
+        self.frames += 1;
+        let distance = frame.get(DISTANCE).unwrap_or(0);
+        let angle = frame.get(ANGLE).unwrap_or(0);
+        self.distance_m += distance.unsigned_abs() as f64 / 1000.0;
+        self.turned_deg += angle.unsigned_abs() as f64;
+
+        let dt = self.last_frame.and_then(|t| frame.time.duration_since(t).ok()).filter(|dt| *dt <= MAX_GAP);
+        self.last_frame = Some(frame.time);
+        let Some(dt) = dt.map(|d| d.as_secs_f64()) else { return };
+        if distance != 0 || angle != 0 {
+            self.driving_s += dt;
+        } else {
+            self.idle_s += dt;
+        }
+        if let (Some(mv), Some(ma)) = (frame.get(VOLTAGE), frame.get(CURRENT)) {
+            // Negative current is discharge
+            if ma < 0 {
+                self.energy_wh += mv as f64 * -ma as f64 / 1e6 * dt / 3600.0;
+            }
+        }
+    }
+
+    fn event(&mut self, event: &Event) {
+        match event {
+            Event::Bump { .. } => self.bumps += 1,
+            Event::WheelDrop { .. } => self.wheel_drops += 1,
+            Event::Cliff { .. } => self.cliffs += 1,
+            _ => {}
+        }
+    }
+
+    fn refresh(&mut self) {
+        self.duration_s = self.start.elapsed().unwrap_or_default().as_secs_f64();
+    }
+
+    fn close(&mut self, at: SystemTime) {
+        self.duration_s = at.duration_since(self.start).unwrap_or_default().as_secs_f64();
+        self.ended = Some(timestamp(at));
+    }
+
+    fn day(&self) -> NaiveDate {
+        DateTime::<Local>::from(self.start).date_naive()
+    }
+}
+
+struct State {
+    current: Option<SessionStats>,
+    /// The running session's share of the current local day
+    today: Option<SessionStats>,
+    last: Option<SessionStats>,
+}
+
+static STATE: Mutex<State> = Mutex::new(State { current: None, today: None, last: None });
+
+fn state() -> std::sync::MutexGuard<'static, State> {
+    STATE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn timestamp(time: SystemTime) -> String {
+    DateTime::<Utc>::from(time).to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+/// Start counting for a new session on `device`.
+pub fn begin(device: &str) {
+    let mut state = state();
+    state.current = Some(SessionStats::new(device));
+    state.today = Some(SessionStats::new(device));
+}
+
+/// Account for one sensor frame and the events detected in it. Returns an
+/// `activity` event for the previous day once the local date changes.
+pub fn update(frame: &SensorFrame, events: &[Event]) -> Option<Event> {
+    let mut state = state();
+    let state = &mut *state;
+    let mut finished_day = None;
+    if let Some(today) = state.today.as_mut() {
+        if DateTime::<Local>::from(frame.time).date_naive() != today.day() {
+            let mut next = SessionStats::new(&today.device);
+            next.start = frame.time;
+            next.started = timestamp(frame.time);
+            let mut done = std::mem::replace(today, next);
+            done.close(frame.time);
+            finished_day = Some(Event::Activity(done));
+        }
+    }
+    for stats in [state.current.as_mut(), state.today.as_mut()].into_iter().flatten() {
+        stats.frame(frame);
+        for event in events {
+            stats.event(event);
+        }
+    }
+    finished_day
+}
+
+/// Close the running session; returns its summary and the `activity` for
+/// its last day.
+pub fn end() -> Option<(SessionStats, Option<SessionStats>)> {
+    let mut state = state();
+    let now = SystemTime::now();
+    let mut stats = state.current.take()?;
+    stats.close(now);
+    let today = state.today.take().map(|mut t| {
+        t.close(now);
+        t
+    });
+    state.last = Some(stats.clone());
+    Some((stats, today))
+}
+
+/// The running session (if any) and the last finished one.
+pub fn snapshot() -> (Option<SessionStats>, Option<SessionStats>) {
+    let mut state = state();
+    if let Some(current) = state.current.as_mut() {
+        current.refresh();
+    }
+    (state.current.clone(), state.last.clone())
+}
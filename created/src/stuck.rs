@@ -0,0 +1,160 @@
+//! Stuck detection: the wheels are driven but the robot gets nowhere
+//! (wedged under furniture, pushing against something the bumper misses,
+//! high-centred on a threshold), or a wheel motor keeps reporting
+//! overcurrent. Either publishes a `stuck` event, once until the wheels
+//! are stopped or move again. The stuck reflex then tries to wiggle the
+//! robot free before cancelling the running behavior; with that reflex
+//! off, the behavior is cancelled and the wheels stopped right away.
+//!
+//! Progress is measured on the wheel encoders (the distance and angle
+//! packets, or the encoder counts on a Create 2) against the speeds
+//! actually sent, after ramping, the geofence, and the virtual bumper, so
+//! a robot held back on purpose isn't taken for stuck.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use tracing::warn;
+
+use crate::bus::Event;
+use crate::config::StuckConfig;
+use crate::oi::SensorFrame;
+use crate::{behavior, motion, pose, reflex};
+
+const OVERCURRENTS: u8 = 14;
+/// Left and right wheel bits
+const WHEEL_OVERCURRENT: i32 = 0b11000;
+const DISTANCE: u8 = 19;
+const ANGLE: u8 = 20;
+const LEFT_ENCODER: u8 = 43;
+const RIGHT_ENCODER: u8 = 44;
+
+/// One frame's wheel travel, mm: as driven, and as measured.
+struct Sample {
+    at: Instant,
+    driven: f64,
+    measured: f64,
+}
+
+struct Watch {
+    cfg: StuckConfig,
+    /// Frames since the wheels started being driven, oldest first
+    samples: VecDeque<Sample>,
+    driven_since: Option<Instant>,
+    overcurrent_since: Option<Instant>,
+    encoders: Option<(i32, i32)>,
+    last: Option<Instant>,
+    /// A stuck event is out, until the wheels stop or get going
+    raised: bool,
+}
+
+static STATE: Mutex<Option<Watch>> = Mutex::new(None);
+static CONFIG: Mutex<Option<StuckConfig>> = Mutex::new(None);
+
+fn state() -> std::sync::MutexGuard<'static, Option<Watch>> {
+    STATE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Watch every session. Does nothing unless `stuck.enabled`.
+pub fn init(cfg: StuckConfig) {
+    if cfg.enabled() {
+        *CONFIG.lock().unwrap_or_else(|e| e.into_inner()) = Some(cfg);
+    }
+}
+
+pub fn begin() {
+    let cfg = CONFIG.lock().unwrap_or_else(|e| e.into_inner()).clone();
+    *state() = cfg.map(|cfg| Watch {
+        cfg,
+        samples: VecDeque::new(),
+        driven_since: None,
+        overcurrent_since: None,
+        encoders: None,
+        last: None,
+        raised: false,
+    });
+}
+
+pub fn end() {
+    *state() = None;
+}
+
+impl Watch {
+    /// Wheel travel since the last frame, mm, summed over both wheels.
+    fn measured(&mut self, frame: &SensorFrame) -> f64 {
+        if let Some((left, right)) = frame.get(LEFT_ENCODER).zip(frame.get(RIGHT_ENCODER)) {
+            let mm = pose::metres_per_count() * 1000.0;
+            let travel = self.encoders.map(|(l, r)| {
+                let dl = left.wrapping_sub(l) as u16 as i16;
+                let dr = right.wrapping_sub(r) as u16 as i16;
+                (dl.unsigned_abs() as f64 + dr.unsigned_abs() as f64) * mm
+            });
+            self.encoders = Some((left, right));
+            return travel.unwrap_or(0.0);
+        }
+        let distance = frame.get(DISTANCE).unwrap_or(0) as f64;
+        let angle = (frame.get(ANGLE).unwrap_or(0) as f64).to_radians();
+        // Either wheel's share of a turn on the spot adds up to the angle
+        2.0 * distance.abs() + angle.abs() * pose::wheel_base() * 1000.0
+    }
+
+    fn update(&mut self, frame: &SensorFrame) -> Option<(String, f64)> {
+        let now = Instant::now();
+        let dt = self.last.map_or(0.0, |l| now.duration_since(l).as_secs_f64()).min(0.1);
+        self.last = Some(now);
+        let measured = self.measured(frame);
+        let sent = motion::sent();
+        let (left, right) = (sent.left as f64, sent.right as f64);
+        let driven = left.abs().max(right.abs()) >= self.cfg.min_speed();
+
+        let overcurrent = frame.get(OVERCURRENTS).is_some_and(|o| o & WHEEL_OVERCURRENT != 0);
+        self.overcurrent_since = if overcurrent { Some(self.overcurrent_since.unwrap_or(now)) } else { None };
+        if !driven {
+            self.samples.clear();
+            self.driven_since = None;
+            self.raised = false;
+            return None;
+        }
+        let since = *self.driven_since.get_or_insert(now);
+        self.samples.push_back(Sample { at: now, driven: (left.abs() + right.abs()) * dt, measured });
+        let window = self.cfg.after();
+        while self.samples.front().is_some_and(|s| now.duration_since(s.at) > window) {
+            self.samples.pop_front();
+        }
+        let driven_mm: f64 = self.samples.iter().map(|s| s.driven).sum();
+        let measured_mm: f64 = self.samples.iter().map(|s| s.measured).sum();
+        let moving = measured_mm >= driven_mm * self.cfg.progress();
+        let for_s = now.duration_since(since).as_secs_f64();
+        if self.raised {
+            self.raised = !moving;
+            return None;
+        }
+        let stalled = self.overcurrent_since.zip(self.cfg.overcurrent()).is_some_and(|(s, d)| now.duration_since(s) >= d);
+        let reason = if stalled {
+            "wheel overcurrent".to_string()
+        } else if now.duration_since(since) >= window && !moving {
+            format!("{measured_mm:.0} mm of {driven_mm:.0} mm driven in {:.1} s", window.as_secs_f64())
+        } else {
+            return None;
+        };
+        self.raised = true;
+        Some((reason, for_s))
+    }
+}
+
+/// Check one frame, before the reflexes; events to publish.
+pub fn update(frame: &SensorFrame) -> Vec<Event> {
+    let stuck = {
+        let mut guard = state();
+        let Some(w) = guard.as_mut() else { return Vec::new() };
+        w.update(frame)
+    };
+    let Some((reason, for_s)) = stuck else { return Vec::new() };
+    warn!("stuck: {reason}");
+    if !reflex::unsticks() {
+        behavior::cancel("stuck");
+        motion::stop();
+    }
+    vec![Event::Stuck { reason, for_s }]
+}
@@ -0,0 +1,250 @@
+//! Serial ports over the network: a robot whose port is shared by ser2net,
+//! an ESP-Link bridge, or the like. `serial.path = "tcp://host:port"`
+//! sends the OI bytes as they are, the bridge having the serial settings;
+//! `rfc2217://host:port` speaks telnet with the COM-PORT-OPTION (RFC 2217),
+//! so the baud rate is set from here and can be probed for as on a local
+//! port. Either behaves like a port: reads time out after `TIMEOUT` with
+//! nothing to read, and a connection dropped fails the next read or write,
+//! ending the session for the worker to connect again.
+
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{Shutdown, TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use crate::transport::Transport;
+
+/// The schemes of a `serial.path` naming a port on the network
+pub const RAW: &str = "tcp://";
+pub const RFC2217: &str = "rfc2217://";
+/// How long reads and writes wait, as for serial ports
+const TIMEOUT: Duration = Duration::from_millis(500);
+/// How long connecting to each of the host's addresses may take
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Telnet commands and options
+const IAC: u8 = 255;
+const DONT: u8 = 254;
+const DO: u8 = 253;
+const WONT: u8 = 252;
+const WILL: u8 = 251;
+const SB: u8 = 250;
+const SE: u8 = 240;
+const BINARY: u8 = 0;
+const SUPPRESS_GO_AHEAD: u8 = 3;
+const COM_PORT_OPTION: u8 = 44;
+// COM-PORT-OPTION commands, client to server
+const SET_BAUDRATE: u8 = 1;
+const SET_DATASIZE: u8 = 2;
+const SET_PARITY: u8 = 3;
+const SET_STOPSIZE: u8 = 4;
+const SET_CONTROL: u8 = 5;
+
+/// Whether `path` names a port on the network.
+pub fn is_network(path: &str) -> bool {
+    path.starts_with(RAW) || path.starts_with(RFC2217)
+}
+
+/// Where the telnet decoder is in the bytes from the server.
+#[derive(Clone, Copy)]
+enum Telnet {
+    Data,
+    Iac,
+    /// WILL, WONT, DO, or DONT, its option to come
+    Negotiate(u8),
+    Subnegotiation,
+    SubnegotiationIac,
+}
+
+/// A connection to a networked port.
+pub struct NetPort {
+    stream: TcpStream,
+    /// The decoder's state when speaking RFC 2217; None for raw TCP
+    telnet: Option<Telnet>,
+    /// Replies to the server's negotiation, sent with the next write or read
+    replies: Vec<u8>,
+    /// The options answered so far, so a server repeating itself isn't answered forever
+    answered: Vec<(u8, u8)>,
+}
+
+impl NetPort {
+    /// Connect to the bridge at `path`, a `tcp://` or `rfc2217://` address.
+    pub fn connect(path: &str) -> Result<NetPort, String> {
+        let (address, telnet) = match (path.strip_prefix(RAW), path.strip_prefix(RFC2217)) {
+            (Some(address), _) => (address, false),
+            (_, Some(address)) => (address, true),
+            _ => return Err(format!("{path}: not a {RAW} or {RFC2217} address")),
+        };
+        let address = address.trim_end_matches('/');
+        let addrs = address.to_socket_addrs().map_err(|e| format!("{path}: can't resolve {address:?} (expected host:port): {e}"))?;
+        let mut last = None;
+        let stream = addrs.into_iter().find_map(|addr| match TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT) {
+            Ok(stream) => Some(stream),
+            Err(e) => {
+                last = Some(format!("connect to {addr}: {e}"));
+                None
+            }
+        });
+        let stream = stream.ok_or_else(|| last.unwrap_or_else(|| format!("{path}: no address for {address:?}")))?;
+        let socket = |e: io::Error| format!("socket: {e}");
+        // Commands are a few bytes each: don't hold them back to fill a segment
+        stream.set_nodelay(true).map_err(socket)?;
+        stream.set_read_timeout(Some(TIMEOUT)).map_err(socket)?;
+        stream.set_write_timeout(Some(TIMEOUT)).map_err(socket)?;
+        let mut port = NetPort { stream, telnet: None, replies: Vec::new(), answered: Vec::new() };
+        if telnet {
+            port.telnet = Some(Telnet::Data);
+            // 8-bit clean both ways, and serial settings from here
+            port.replies.extend_from_slice(&[IAC, WILL, BINARY, IAC, DO, BINARY, IAC, WILL, COM_PORT_OPTION, IAC, DO, SUPPRESS_GO_AHEAD]);
+            for (command, value) in [(SET_DATASIZE, 8), (SET_PARITY, 1), (SET_STOPSIZE, 1), (SET_CONTROL, 1)] {
+                port.command(command, &[value]);
+            }
+            port.send_replies().map_err(|e| format!("telnet negotiation: {e}"))?;
+        }
+        Ok(port)
+    }
+
+    /// Queue a COM-PORT-OPTION subnegotiation.
+    fn command(&mut self, command: u8, value: &[u8]) {
+        self.replies.extend_from_slice(&[IAC, SB, COM_PORT_OPTION, command]);
+        escape(value, &mut self.replies);
+        self.replies.extend_from_slice(&[IAC, SE]);
+    }
+
+    fn send_replies(&mut self) -> io::Result<()> {
+        if !self.replies.is_empty() {
+            let replies = std::mem::take(&mut self.replies);
+            self.stream.write_all(&replies)?;
+        }
+        Ok(())
+    }
+
+    /// Agree to the options RFC 2217 needs and refuse the rest, once each.
+    fn negotiate(&mut self, verb: u8, option: u8) {
+        if self.answered.contains(&(verb, option)) {
+            return;
+        }
+        self.answered.push((verb, option));
+        let wanted = matches!(option, BINARY | SUPPRESS_GO_AHEAD | COM_PORT_OPTION);
+        let reply = match verb {
+            DO if wanted => WILL,
+            DO | DONT => WONT,
+            WILL if wanted => DO,
+            _ => DONT,
+        };
+        self.replies.extend_from_slice(&[IAC, reply, option]);
+    }
+
+    /// The data in `buf[..n]`, received from a telnet server, moved to
+    /// the front with the telnet commands taken out; how much is left.
+    fn decode(&mut self, buf: &mut [u8], n: usize) -> usize {
+        let Some(mut state) = self.telnet else { return n };
+        let mut out = 0;
+        for i in 0..n {
+            let b = buf[i];
+            state = match (state, b) {
+                (Telnet::Data, IAC) => Telnet::Iac,
+                (Telnet::Data, _) | (Telnet::Iac, IAC) => {
+                    buf[out] = b;
+                    out += 1;
+                    Telnet::Data
+                }
+                (Telnet::Iac, WILL | WONT | DO | DONT) => Telnet::Negotiate(b),
+                (Telnet::Iac, SB) => Telnet::Subnegotiation,
+                // NOP, GA, and the like
+                (Telnet::Iac, _) => Telnet::Data,
+                (Telnet::Negotiate(verb), option) => {
+                    self.negotiate(verb, option);
+                    Telnet::Data
+                }
+                // The server's answers to the settings sent: nothing to do with them
+                (Telnet::Subnegotiation, IAC) => Telnet::SubnegotiationIac,
+                (Telnet::Subnegotiation, _) => Telnet::Subnegotiation,
+                (Telnet::SubnegotiationIac, SE) => Telnet::Data,
+                (Telnet::SubnegotiationIac, _) => Telnet::Subnegotiation,
+            };
+        }
+        self.telnet = Some(state);
+        out
+    }
+}
+
+/// `data` with IAC doubled, as telnet carries it.
+fn escape(data: &[u8], out: &mut Vec<u8>) {
+    for &b in data {
+        out.push(b);
+        if b == IAC {
+            out.push(IAC);
+        }
+    }
+}
+
+/// A read or write timing out: TimedOut, as a serial port reports it,
+/// rather than the WouldBlock sockets give.
+fn timed_out(e: io::Error) -> io::Error {
+    if e.kind() == ErrorKind::WouldBlock {
+        io::Error::new(ErrorKind::TimedOut, "network port timed out")
+    } else {
+        e
+    }
+}
+
+impl Read for NetPort {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            let n = self.stream.read(buf).map_err(timed_out)?;
+            if n == 0 {
+                return Err(io::Error::new(ErrorKind::BrokenPipe, "connection closed by the bridge"));
+            }
+            let n = self.decode(buf, n);
+            self.send_replies().map_err(timed_out)?;
+            // Nothing but telnet commands: wait for data as a port would
+            if n > 0 {
+                return Ok(n);
+            }
+        }
+    }
+}
+
+impl Write for NetPort {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.send_replies().map_err(timed_out)?;
+        if self.telnet.is_none() {
+            return self.stream.write(buf).map_err(timed_out);
+        }
+        let mut escaped = Vec::with_capacity(buf.len());
+        escape(buf, &mut escaped);
+        self.stream.write_all(&escaped).map_err(timed_out)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl Transport for NetPort {
+    fn kind(&self) -> &'static str {
+        if self.telnet.is_some() { "rfc2217" } else { "tcp" }
+    }
+
+    /// Set the bridge's baud rate to the robot; an error over raw TCP,
+    /// where the bridge's own setting is all there is.
+    fn set_baud_rate(&mut self, baud: u32) -> Result<(), String> {
+        if self.telnet.is_none() {
+            return Err(format!("the baud rate is set on the bridge for {RAW}; use {RFC2217} to set it from here"));
+        }
+        self.command(SET_BAUDRATE, &baud.to_be_bytes());
+        self.send_replies().map_err(|e| e.to_string())
+    }
+
+    /// Hang up, so the bridge frees its port for the next client at once.
+    fn close(self: Box<Self>) -> Result<(), String> {
+        match self.stream.shutdown(Shutdown::Both) {
+            Err(e) if e.kind() != ErrorKind::NotConnected => Err(format!("shutdown: {e}")),
+            _ => Ok(()),
+        }
+    }
+}
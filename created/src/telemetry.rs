@@ -0,0 +1,398 @@
+//! Live telemetry streams: every message on the bus (sensor frames, commands,
+//! events) sent to TCP clients and UDP subscribers, encoded per client as
+//! JSON (one object per line, the default) or CBOR (RFC 8949, as a CBOR
+//! sequence on TCP and one item per datagram on UDP).
+//!
+//! Each message is `{"topic": "sensors"|"commands"|"events", "time_ns": ...,
+//! "data": ...}`, with `data` as on the MCAP channels of the same name.
+//!
+//! TCP clients may send `cbor` or `json` as their first line within 500 ms
+//! of connecting. UDP clients send a `subscribe` or `subscribe cbor`
+//! datagram and repeat it at least every 60 s to keep receiving.
+//!
+//! Either may add `delta` (`cbor delta`, `subscribe delta`) to be sent
+//! sensor frames as deltas: a whole frame, marked `"delta": false`, every
+//! `telemetry.snapshot_s`, and in between only the fields that changed,
+//! marked `"delta": true`. Over TCP a delta is against the frame before it;
+//! over UDP against the last whole frame, so a lost datagram loses only
+//! itself. A delta client joining, or the frame's packets changing, sends
+//! every delta client a whole frame next. Other topics are sent as they are.
+
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+use serde::Serialize;
+use tracing::{debug, info, warn};
+
+use crate::bus::{Bus, Event, Message, Receiver, Sampling};
+use crate::config::TelemetryConfig;
+use crate::oi::SensorFrame;
+use crate::pose::Pose;
+
+const QUEUE_MESSAGES: usize = 1024;
+const NEGOTIATE_TIMEOUT: Duration = Duration::from_millis(500);
+/// A client that can't take a message within this long is dropped.
+const WRITE_TIMEOUT: Duration = Duration::from_millis(200);
+const UDP_SUBSCRIPTION: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+enum Encoding {
+    #[default]
+    Json,
+    Cbor,
+}
+
+impl Encoding {
+    fn parse(word: &str) -> Option<Self> {
+        match word {
+            "json" => Some(Encoding::Json),
+            "cbor" => Some(Encoding::Cbor),
+            _ => None,
+        }
+    }
+}
+
+/// What a client asked for, in any order: `json` or `cbor`, and `delta`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+struct Options {
+    encoding: Encoding,
+    delta: bool,
+}
+
+impl Options {
+    fn parse<'a>(words: impl Iterator<Item = &'a str>) -> Option<Self> {
+        let mut options = Options::default();
+        for word in words {
+            match word {
+                "delta" => options.delta = true,
+                _ => options.encoding = Encoding::parse(word)?,
+            }
+        }
+        Some(options)
+    }
+}
+
+#[derive(Serialize)]
+struct Envelope<'a> {
+    topic: &'static str,
+    time_ns: u64,
+    /// Only for delta clients' sensor frames
+    #[serde(skip_serializing_if = "Option::is_none")]
+    delta: Option<bool>,
+    data: Payload<'a>,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum Payload<'a> {
+    Sensors(&'a SensorFrame),
+    Command { opcode: u8, bytes: &'a [u8] },
+    Event(&'a Event),
+    Pose(&'a Pose),
+}
+
+fn envelope(msg: &Message) -> Envelope<'_> {
+    let (topic, time, data) = match msg {
+        Message::Sensors(frame) => ("sensors", frame.time, Payload::Sensors(frame)),
+        Message::Command { time, bytes } => {
+            ("commands", *time, Payload::Command { opcode: bytes.first().copied().unwrap_or_default(), bytes })
+        }
+        Message::Event { time, event } => ("events", *time, Payload::Event(event)),
+        Message::Pose(pose) => ("pose", pose.time, Payload::Pose(pose)),
+    };
+    let time_ns = time.duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or(0);
+    Envelope { topic, time_ns, delta: None, data }
+}
+
+/// How a sensor frame goes to delta clients.
+enum Frame {
+    Whole,
+    Delta {
+        /// The fields changed since the frame before, for TCP
+        previous: Box<SensorFrame>,
+        /// And since the last whole frame, for UDP
+        snapshot: Box<SensorFrame>,
+    },
+}
+
+/// The frames deltas are taken against.
+#[derive(Default)]
+struct Bases {
+    previous: Option<Arc<SensorFrame>>,
+    snapshot: Option<(Instant, Arc<SensorFrame>)>,
+}
+
+impl Bases {
+    /// `frame`, sent whole if `resync` (a delta client hasn't had a whole
+    /// frame yet), the last whole one is `every` old, or its packets differ,
+    /// which a delta can't say.
+    fn next(&mut self, frame: &Arc<SensorFrame>, every: Duration, resync: bool) -> Frame {
+        let previous = self.previous.replace(frame.clone());
+        let out = match (&self.snapshot, previous) {
+            (Some((at, snapshot)), Some(previous)) if !resync && at.elapsed() < every && same_packets(snapshot, frame) => {
+                Frame::Delta { previous: Box::new(changes(&previous, frame)), snapshot: Box::new(changes(snapshot, frame)) }
+            }
+            _ => Frame::Whole,
+        };
+        if matches!(out, Frame::Whole) {
+            self.snapshot = Some((Instant::now(), frame.clone()));
+        }
+        out
+    }
+}
+
+fn same_packets(a: &SensorFrame, b: &SensorFrame) -> bool {
+    a.iter().map(|(p, _)| p.id).eq(b.iter().map(|(p, _)| p.id))
+}
+
+/// The fields of `frame` that differ from `base`, given the same packets.
+fn changes(base: &SensorFrame, frame: &SensorFrame) -> SensorFrame {
+    let mut out = SensorFrame::new(frame.time);
+    for (p, v) in frame.iter().filter(|&(p, v)| base.get(p.id) != Some(v)) {
+        out.set(p.id, v);
+    }
+    out
+}
+
+/// Which version of a message a client is sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Form {
+    /// As it is, for clients without deltas and topics other than sensors
+    Plain,
+    Whole,
+    SincePrevious,
+    SinceSnapshot,
+}
+
+/// Encoded once per message, encoding, and form, however many clients use it.
+struct Encoded<'a> {
+    msg: &'a Message,
+    /// For a sensor frame
+    frame: Option<Frame>,
+    cache: HashMap<(Encoding, Form), Vec<u8>>,
+}
+
+impl<'a> Encoded<'a> {
+    fn new(msg: &'a Message, frame: Option<Frame>) -> Self {
+        Encoded { msg, frame, cache: HashMap::new() }
+    }
+
+    /// The form for a client, `since` being its kind of delta.
+    fn form(&self, delta: bool, since: Form) -> Form {
+        match &self.frame {
+            Some(Frame::Whole) if delta => Form::Whole,
+            Some(Frame::Delta { .. }) if delta => since,
+            _ => Form::Plain,
+        }
+    }
+
+    fn get(&mut self, encoding: Encoding, form: Form) -> &[u8] {
+        let (msg, frame) = (self.msg, &self.frame);
+        self.cache.entry((encoding, form)).or_insert_with(|| {
+            let mut envelope = envelope(msg);
+            match (form, frame) {
+                (Form::Whole, _) => envelope.delta = Some(false),
+                (Form::SincePrevious, Some(Frame::Delta { previous: changed, .. }))
+                | (Form::SinceSnapshot, Some(Frame::Delta { snapshot: changed, .. })) => {
+                    envelope.delta = Some(true);
+                    envelope.data = Payload::Sensors(changed);
+                }
+                _ => {}
+            }
+            match encoding {
+                Encoding::Json => {
+                    let mut out = serde_json::to_vec(&envelope).unwrap_or_default();
+                    out.push(b'\n');
+                    out
+                }
+                Encoding::Cbor => {
+                    let mut out = Vec::new();
+                    let _ = ciborium::into_writer(&envelope, &mut out);
+                    out
+                }
+            }
+        })
+    }
+}
+
+struct TcpClient {
+    peer: SocketAddr,
+    stream: TcpStream,
+    options: Options,
+    /// Has had a whole frame, if a delta client
+    synced: bool,
+}
+
+struct UdpSubscriber {
+    options: Options,
+    synced: bool,
+    renewed: Instant,
+}
+
+#[derive(Default)]
+struct Clients {
+    tcp: Vec<TcpClient>,
+    udp: HashMap<SocketAddr, UdpSubscriber>,
+}
+
+/// Bind the configured listeners and start the sink thread. Returns None if
+/// neither listener could be bound.
+pub fn spawn(cfg: TelemetryConfig, bus: &mut Bus) -> Option<JoinHandle<()>> {
+    let clients = Arc::new(Mutex::new(Clients::default()));
+    let mut udp = None;
+    let mut any = false;
+    if let Some(addr) = cfg.tcp.as_deref() {
+        match TcpListener::bind(addr) {
+            Ok(listener) => {
+                info!("telemetry stream on tcp://{addr}");
+                let clients = clients.clone();
+                thread::spawn(move || accept(listener, clients));
+                any = true;
+            }
+            Err(e) => warn!("telemetry: bind tcp {addr}: {e}"),
+        }
+    }
+    if let Some(addr) = cfg.udp.as_deref() {
+        match UdpSocket::bind(addr) {
+            Ok(socket) => {
+                info!("telemetry stream on udp://{addr}");
+                if let Ok(rx_socket) = socket.try_clone() {
+                    let clients = clients.clone();
+                    thread::spawn(move || subscriptions(rx_socket, clients));
+                }
+                udp = Some(socket);
+                any = true;
+            }
+            Err(e) => warn!("telemetry: bind udp {addr}: {e}"),
+        }
+    }
+    if !any {
+        return None;
+    }
+    let sampling = Sampling::new("telemetry stream", cfg.rate_hz, cfg.fields.as_deref());
+    let rx = bus.subscribe("telemetry stream", QUEUE_MESSAGES, sampling);
+    let every = cfg.snapshot();
+    Some(thread::spawn(move || run(rx, clients, udp, every)))
+}
+
+fn accept(listener: TcpListener, clients: Arc<Mutex<Clients>>) {
+    for conn in listener.incoming() {
+        let stream = match conn {
+            Ok(s) => s,
+            Err(e) => {
+                warn!("telemetry accept: {e}");
+                continue;
+            }
+        };
+        let clients = clients.clone();
+        // Negotiation waits for the client, so don't hold up other accepts
+        thread::spawn(move || {
+            let Ok(peer) = stream.peer_addr() else { return };
+            let options = negotiate(&stream);
+            let _ = stream.set_write_timeout(Some(WRITE_TIMEOUT));
+            let _ = stream.set_nodelay(true);
+            debug!("telemetry client {peer} connected ({options:?})");
+            lock(&clients).tcp.push(TcpClient { peer, stream, options, synced: false });
+        });
+    }
+}
+
+fn negotiate(stream: &TcpStream) -> Options {
+    let _ = stream.set_read_timeout(Some(NEGOTIATE_TIMEOUT));
+    let mut line = String::new();
+    let requested = stream
+        .try_clone()
+        .ok()
+        .and_then(|s| BufReader::new(s).read_line(&mut line).ok())
+        .and_then(|_| Options::parse(line.split_whitespace()));
+    requested.unwrap_or_default()
+}
+
+fn subscriptions(socket: UdpSocket, clients: Arc<Mutex<Clients>>) {
+    let mut buf = [0u8; 64];
+    loop {
+        let Ok((n, peer)) = socket.recv_from(&mut buf) else { continue };
+        let text = String::from_utf8_lossy(&buf[..n]);
+        let mut words = text.split_whitespace();
+        match words.next() {
+            Some("subscribe") => match Options::parse(words) {
+                Some(options) => subscribe(&clients, peer, options),
+                None => debug!("telemetry: ignoring datagram from {peer}"),
+            },
+            Some("unsubscribe") => {
+                lock(&clients).udp.remove(&peer);
+            }
+            _ => debug!("telemetry: ignoring datagram from {peer}"),
+        }
+    }
+}
+
+fn subscribe(clients: &Mutex<Clients>, peer: SocketAddr, options: Options) {
+    let mut clients = lock(clients);
+    match clients.udp.get_mut(&peer) {
+        // Renewed, its deltas carrying on
+        Some(sub) if sub.options == options => sub.renewed = Instant::now(),
+        previous => {
+            if previous.is_none() {
+                debug!("telemetry subscriber {peer} ({options:?})");
+            }
+            clients.udp.insert(peer, UdpSubscriber { options, synced: false, renewed: Instant::now() });
+        }
+    }
+}
+
+fn lock(clients: &Mutex<Clients>) -> std::sync::MutexGuard<'_, Clients> {
+    clients.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn run(rx: Receiver, clients: Arc<Mutex<Clients>>, udp: Option<UdpSocket>, every: Duration) {
+    let mut bases = Bases::default();
+    loop {
+        let msg = match rx.recv_timeout(Duration::from_secs(1)) {
+            Ok(msg) => msg,
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => return,
+        };
+        let mut clients = lock(&clients);
+        let frame = match &msg {
+            Message::Sensors(frame) => {
+                let resync = clients.tcp.iter().any(|c| c.options.delta && !c.synced)
+                    || clients.udp.values().any(|s| s.options.delta && !s.synced);
+                Some(bases.next(frame, every, resync))
+            }
+            _ => None,
+        };
+        let whole = matches!(frame, Some(Frame::Whole));
+        let mut encoded = Encoded::new(&msg, frame);
+        clients.tcp.retain_mut(|c| {
+            let form = encoded.form(c.options.delta, Form::SincePrevious);
+            match c.stream.write_all(encoded.get(c.options.encoding, form)) {
+                Ok(()) => {
+                    c.synced |= whole;
+                    true
+                }
+                Err(e) => {
+                    debug!("telemetry client {} dropped: {e}", c.peer);
+                    false
+                }
+            }
+        });
+        clients.udp.retain(|peer, sub| sub.renewed.elapsed() < UDP_SUBSCRIPTION || {
+            debug!("telemetry subscriber {peer} expired");
+            false
+        });
+        if let Some(socket) = udp.as_ref() {
+            for (peer, sub) in clients.udp.iter_mut() {
+                let form = encoded.form(sub.options.delta, Form::SinceSnapshot);
+                // Datagrams over the path MTU are dropped by the kernel, not us
+                let _ = socket.send_to(encoded.get(sub.options.encoding, form), peer);
+                sub.synced |= whole;
+            }
+        }
+    }
+}
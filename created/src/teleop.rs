@@ -0,0 +1,284 @@
+//! `created teleop`: drive the robot from a terminal (e.g. over SSH) through
+//! the running daemon's control socket. WASD or the arrow keys change speed
+//! and turn rate in steps, space stops, q or Esc quits (and stops).
+//!
+//! The keys are a deadman switch: the robot drives only while one is held,
+//! and stops when they are let go. Terminals that report key releases (the
+//! kitty keyboard protocol) stop it at once; elsewhere a held key is seen
+//! by its autorepeat, and none for `RELEASE` counts as let go. A key steps
+//! the speed or turn rate as it goes down, then every `HELD_STEP` while it
+//! is held, however fast the terminal repeats it.
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags, PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags};
+use crossterm::{execute, terminal};
+
+use crate::motion::MAX_SPEED;
+
+const SPEED_STEP: i32 = 50;
+const TURN_STEP: i32 = 25;
+/// The current command is repeated this often even without key presses.
+pub(crate) const REPEAT: Duration = Duration::from_millis(250);
+/// No autorepeat of a driving key for this long, and it has been let go;
+/// longer than the usual delay before a held key starts repeating.
+const RELEASE: Duration = Duration::from_millis(700);
+/// A held key steps again this often.
+const HELD_STEP: Duration = Duration::from_millis(250);
+
+/// A connection to the daemon's control socket.
+pub(crate) struct Client {
+    stream: UnixStream,
+    replies: BufReader<UnixStream>,
+}
+
+impl Client {
+    pub(crate) fn connect(socket: &Path) -> Result<Self, String> {
+        let stream = UnixStream::connect(socket).map_err(|e| format!("cannot connect to {}: {e}", socket.display()))?;
+        let replies = BufReader::new(stream.try_clone().map_err(|e| e.to_string())?);
+        Ok(Client { stream, replies })
+    }
+
+    /// The reply to `command`, whether or not the daemon refused it; Err
+    /// if the connection is gone.
+    pub(crate) fn request(&mut self, command: &str) -> Result<String, String> {
+        writeln!(self.stream, "{command}").map_err(|e| format!("send: {e}"))?;
+        let mut reply = String::new();
+        match self.replies.read_line(&mut reply) {
+            Ok(0) => Err("the daemon closed the connection".into()),
+            Ok(_) => Ok(reply),
+            Err(e) => Err(format!("receive: {e}")),
+        }
+    }
+
+    pub(crate) fn send(&mut self, command: &str) -> Result<String, String> {
+        let reply = self.request(command)?;
+        if reply.contains("\"ok\":true") {
+            Ok(reply)
+        } else {
+            Err(reply.trim().to_string())
+        }
+    }
+}
+
+/// Restores the terminal however teleop exits.
+pub(crate) struct RawMode;
+
+impl RawMode {
+    pub(crate) fn enable() -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        Ok(RawMode)
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// Which driving keys are held, from their releases where the terminal
+/// reports them, or else from their autorepeat.
+pub(crate) struct Deadman {
+    releases: bool,
+    /// The keys held, and when each last stepped
+    held: Vec<(KeyCode, Instant)>,
+    /// The last press or repeat, while a key is held
+    last: Option<Instant>,
+}
+
+impl Deadman {
+    /// Ask the terminal for key releases; call in raw mode.
+    pub(crate) fn enable() -> Self {
+        let releases = terminal::supports_keyboard_enhancement().unwrap_or(false)
+            && execute!(io::stdout(), PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES)).is_ok();
+        Deadman { releases, held: Vec::new(), last: None }
+    }
+
+    /// Note a driving key going down, repeating (as a press, without key
+    /// releases), or coming up; whether it steps, going down or held for
+    /// `HELD_STEP` since it last did.
+    pub(crate) fn key(&mut self, code: KeyCode, kind: KeyEventKind) -> bool {
+        if kind == KeyEventKind::Release {
+            self.held.retain(|(held, _)| *held != code);
+            return false;
+        }
+        let now = Instant::now();
+        self.last = Some(now);
+        match self.held.iter_mut().find(|(held, _)| *held == code) {
+            Some((_, stepped)) if now.duration_since(*stepped) < HELD_STEP => false,
+            Some((_, stepped)) => {
+                *stepped = now;
+                true
+            }
+            None => {
+                self.held.push((code, now));
+                true
+            }
+        }
+    }
+
+    /// Whether the driving keys have just been let go; true once each time.
+    pub(crate) fn released(&mut self) -> bool {
+        let Some(last) = self.last else { return false };
+        let gone = if self.releases { self.held.is_empty() } else { last.elapsed() >= RELEASE };
+        if gone {
+            self.held.clear();
+            self.last = None;
+        }
+        gone
+    }
+}
+
+impl Drop for Deadman {
+    fn drop(&mut self) {
+        if self.releases {
+            let _ = execute!(io::stdout(), PopKeyboardEnhancementFlags);
+        }
+    }
+}
+
+/// Whether `code` drives the robot.
+pub(crate) fn is_driving(code: KeyCode) -> bool {
+    matches!(code, KeyCode::Char('w' | 's' | 'a' | 'd' | ' ') | KeyCode::Up | KeyCode::Down | KeyCode::Left | KeyCode::Right)
+}
+
+/// Change `speed` and `turn` for a driving key; false for any other key.
+pub(crate) fn steer(code: KeyCode, speed: &mut i32, turn: &mut i32) -> bool {
+    match code {
+        KeyCode::Char('w') | KeyCode::Up => *speed += SPEED_STEP,
+        KeyCode::Char('s') | KeyCode::Down => *speed -= SPEED_STEP,
+        KeyCode::Char('a') | KeyCode::Left => *turn += TURN_STEP,
+        KeyCode::Char('d') | KeyCode::Right => *turn -= TURN_STEP,
+        KeyCode::Char(' ') => (*speed, *turn) = (0, 0),
+        _ => return false,
+    }
+    let max = MAX_SPEED as i32;
+    *speed = (*speed).clamp(-max, max);
+    *turn = (*turn).clamp(-max, max);
+    true
+}
+
+pub fn run(socket: &Path) -> Result<(), String> {
+    let mut client = Client::connect(socket)?;
+    // The daemon stops the robot if this connection drops
+    client.send("teleop")?;
+    client.send("stop")?;
+    let raw = RawMode::enable().map_err(|e| format!("raw mode: {e}"))?;
+    let mut deadman = Deadman::enable();
+    print!("created teleop: hold W/S or Up/Down speed, A/D or Left/Right turn; space stop, q quit\r\n");
+
+    let (mut speed, mut turn) = (0, 0);
+    let mut last_sent = Instant::now() - REPEAT;
+    let mut changed = true;
+    let result = loop {
+        if event::poll(Duration::from_millis(50)).map_err(|e| e.to_string())? {
+            if let Event::Key(KeyEvent { code, modifiers, kind, .. }) = event::read().map_err(|e| e.to_string())? {
+                match code {
+                    KeyCode::Char('q') | KeyCode::Esc if kind == KeyEventKind::Press => break Ok(()),
+                    KeyCode::Char('c') if modifiers.contains(KeyModifiers::CONTROL) => break Ok(()),
+                    _ if is_driving(code) => {
+                        changed |= deadman.key(code, kind) && steer(code, &mut speed, &mut turn);
+                    }
+                    _ => {}
+                }
+            }
+        }
+        if deadman.released() && (speed, turn) != (0, 0) {
+            (speed, turn) = (0, 0);
+            changed = true;
+        }
+        if changed || last_sent.elapsed() >= REPEAT {
+            // Positive turn is counter-clockwise: the right wheel runs faster
+            if let Err(e) = client.send(&format!("drive {} {}", speed - turn, speed + turn)) {
+                break Err(e);
+            }
+            if changed {
+                print!("\r\x1b[Kspeed {speed:+4} mm/s  turn {turn:+4} mm/s");
+                let _ = io::stdout().flush();
+            }
+            last_sent = Instant::now();
+            changed = false;
+        }
+    };
+    let _ = client.send("stop");
+    drop(deadman);
+    drop(raw);
+    println!();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::mem::ManuallyDrop;
+
+    use super::*;
+
+    /// Not dropped, which would pop keyboard flags never pushed.
+    fn deadman(releases: bool) -> ManuallyDrop<Deadman> {
+        ManuallyDrop::new(Deadman { releases, held: Vec::new(), last: None })
+    }
+
+    /// As if `code` last stepped `ago`.
+    fn stepped(d: &mut Deadman, code: KeyCode, ago: Duration) {
+        d.held.iter_mut().filter(|(held, _)| *held == code).for_each(|(_, t)| *t = Instant::now() - ago);
+    }
+
+    #[test]
+    fn steps_on_a_press_and_then_while_held() {
+        let w = KeyCode::Char('w');
+        for releases in [true, false] {
+            let mut d = deadman(releases);
+            let repeat = if releases { KeyEventKind::Repeat } else { KeyEventKind::Press };
+            assert!(d.key(w, KeyEventKind::Press));
+            // Autorepeat, however fast, doesn't step until HELD_STEP
+            assert!(!d.key(w, repeat));
+            assert!(!d.key(w, repeat));
+            stepped(&mut d, w, HELD_STEP);
+            assert!(d.key(w, repeat));
+            assert!(!d.key(w, repeat));
+            // Another key steps at once
+            assert!(d.key(KeyCode::Char('a'), KeyEventKind::Press));
+            assert!(!d.released());
+        }
+    }
+
+    #[test]
+    fn speeds_up_to_the_limit_while_held() {
+        let mut d = deadman(true);
+        let (mut speed, mut turn) = (0, 0);
+        let w = KeyCode::Up;
+        let mut kind = KeyEventKind::Press;
+        for _ in 0..20 {
+            if d.key(w, kind) {
+                steer(w, &mut speed, &mut turn);
+            }
+            stepped(&mut d, w, HELD_STEP);
+            kind = KeyEventKind::Repeat;
+        }
+        assert_eq!((speed, turn), (MAX_SPEED as i32, 0));
+    }
+
+    #[test]
+    fn lets_go_on_release_or_silence() {
+        let w = KeyCode::Char('w');
+        let mut d = deadman(true);
+        d.key(w, KeyEventKind::Press);
+        assert!(!d.released());
+        assert!(!d.key(w, KeyEventKind::Release));
+        assert!(d.released());
+        assert!(!d.released());
+        // A press after letting go steps again
+        assert!(d.key(w, KeyEventKind::Press));
+
+        let mut d = deadman(false);
+        d.key(w, KeyEventKind::Press);
+        assert!(!d.released());
+        d.last = Some(Instant::now() - RELEASE);
+        assert!(d.released());
+        assert!(d.key(w, KeyEventKind::Press));
+    }
+}
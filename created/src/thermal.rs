@@ -0,0 +1,240 @@
+//! Battery temperature: packet 24, in °C. From `warning_c` the robot is
+//! held to `warning_speed`, as the wheels' current heats the battery, and
+//! charging is paused by taking the robot out of Passive mode, where the
+//! Create charges; from `critical_c` the wheels stop and the running
+//! behavior is cancelled. Each level holds until the temperature is
+//! `hysteresis_c` below where it began, and charging resumes once it is
+//! back to normal.
+
+use std::sync::Mutex;
+
+use serde_json::{json, Value};
+use tracing::{info, warn};
+
+use crate::{audit, behavior};
+use crate::bus::Event;
+use crate::config::BatteryTemperatureConfig;
+use crate::motion::{self, Wheels};
+use crate::oi::{self, SensorFrame};
+
+const TEMPERATURE: u8 = 24;
+const CHARGING_STATE: u8 = 21;
+const CHARGING_SOURCES: u8 = 34;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Level {
+    Normal,
+    Warning,
+    Critical,
+}
+
+impl Level {
+    fn name(self) -> &'static str {
+        match self {
+            Level::Normal => "normal",
+            Level::Warning => "warning",
+            Level::Critical => "critical",
+        }
+    }
+}
+
+struct Thermal {
+    cfg: BatteryTemperatureConfig,
+    level: Level,
+    celsius: Option<i32>,
+    /// Charging was paused
+    paused: bool,
+}
+
+static CONFIG: Mutex<Option<BatteryTemperatureConfig>> = Mutex::new(None);
+static STATE: Mutex<Option<Thermal>> = Mutex::new(None);
+
+fn state() -> std::sync::MutexGuard<'static, Option<Thermal>> {
+    STATE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+pub fn init(cfg: BatteryTemperatureConfig) {
+    *CONFIG.lock().unwrap_or_else(|e| e.into_inner()) = Some(cfg);
+}
+
+pub fn begin() {
+    let cfg = CONFIG.lock().unwrap_or_else(|e| e.into_inner()).clone().unwrap_or_default();
+    *state() = Some(Thermal { cfg, level: Level::Normal, celsius: None, paused: false }).filter(|t| t.cfg.enabled());
+}
+
+pub fn end() {
+    *state() = None;
+}
+
+impl Thermal {
+    /// The level for `celsius`, staying at the current one within the hysteresis.
+    fn level_for(&self, celsius: f64) -> Level {
+        let (warning, critical, margin) = (self.cfg.warning_c(), self.cfg.critical_c(), self.cfg.hysteresis_c());
+        let rising = if celsius >= critical {
+            Level::Critical
+        } else if celsius >= warning {
+            Level::Warning
+        } else {
+            Level::Normal
+        };
+        let holding = match self.level {
+            Level::Critical if celsius > critical - margin => Level::Critical,
+            Level::Critical | Level::Warning if celsius > warning - margin => Level::Warning,
+            _ => Level::Normal,
+        };
+        rising.max(holding)
+    }
+}
+
+/// Check the battery temperature; events to publish.
+pub fn update(frame: &SensorFrame) -> Vec<Event> {
+    let mut guard = state();
+    let Some(t) = guard.as_mut() else { return Vec::new() };
+    let Some(celsius) = frame.get(TEMPERATURE) else { return Vec::new() };
+    t.celsius = Some(celsius);
+    let charging = matches!(frame.get(CHARGING_STATE), Some(1..=3));
+    let passive = frame.get(oi::OI_MODE) == Some(oi::MODE_PASSIVE);
+    let level = t.level_for(celsius as f64);
+    if t.cfg.pause_charging() {
+        if level >= Level::Warning && charging && passive && !t.paused {
+            warn!("battery at {celsius} °C: pausing charging");
+            audit::record("charging_paused", "battery temperature", &format!("battery at {celsius} °C"));
+            t.paused = true;
+            motion::queue(vec![oi::SAFE]);
+        } else if level == Level::Normal && t.paused {
+            t.paused = false;
+            // Back in Passive mode on the dock, it charges again
+            if frame.get(CHARGING_SOURCES).is_some_and(|s| s != 0) {
+                info!("battery at {celsius} °C: resuming charging");
+                motion::queue(vec![oi::START]);
+            }
+        }
+    }
+    if level == t.level {
+        return Vec::new();
+    }
+    let was = t.level;
+    t.level = level;
+    match level {
+        Level::Critical => warn!("battery at {celsius} °C, past {} °C: stopping", t.cfg.critical_c()),
+        Level::Warning if was == Level::Normal => warn!("battery at {celsius} °C, past {} °C: slowing down to {} mm/s", t.cfg.warning_c(), t.cfg.warning_speed()),
+        _ => info!("battery temperature {} at {celsius} °C", level.name()),
+    }
+    audit::record("battery_temperature", "battery temperature", &format!("{} at {celsius} °C, from {}", level.name(), was.name()));
+    drop(guard);
+    if level == Level::Critical {
+        behavior::cancel("battery temperature critical");
+        motion::stop();
+    }
+    vec![Event::BatteryTemperature { level: level.name().to_string(), celsius }]
+}
+
+/// `wheels` held to the warning speed, or stopped when critical.
+pub fn limit(wheels: Wheels) -> Wheels {
+    let guard = state();
+    let Some(t) = guard.as_ref() else { return wheels };
+    let max_speed = match t.level {
+        Level::Normal => return wheels,
+        Level::Warning => t.cfg.warning_speed(),
+        Level::Critical => return Wheels::STOP,
+    };
+    let fastest = (wheels.left as f64).abs().max((wheels.right as f64).abs());
+    if fastest <= max_speed {
+        return wheels;
+    }
+    let k = max_speed / fastest;
+    Wheels::new((wheels.left as f64 * k).round() as i32, (wheels.right as f64 * k).round() as i32)
+}
+
+/// The temperature and level, for `createctl status`; null when disabled
+/// or not connected.
+pub fn status() -> Value {
+    let guard = state();
+    let Some(t) = guard.as_ref() else { return Value::Null };
+    json!({ "celsius": t.celsius, "level": t.level.name(), "charging_paused": t.paused })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+
+    use super::*;
+
+    fn reading(celsius: i32, charging: bool) -> SensorFrame {
+        let mut frame = SensorFrame::new(SystemTime::now());
+        frame.set(TEMPERATURE, celsius);
+        frame.set(CHARGING_STATE, if charging { 2 } else { 0 });
+        frame.set(CHARGING_SOURCES, if charging { 0b10 } else { 0 });
+        frame.set(oi::OI_MODE, oi::MODE_PASSIVE);
+        frame
+    }
+
+    fn level(events: &[Event]) -> Option<&str> {
+        match events {
+            [Event::BatteryTemperature { level, .. }] => Some(level),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn levels_hold_within_the_hysteresis() {
+        // 45 and 55 °C, 3 °C of hysteresis
+        let cases = [
+            (Level::Normal, 44.9, Level::Normal),
+            (Level::Normal, 45.0, Level::Warning),
+            (Level::Normal, 60.0, Level::Critical),
+            (Level::Warning, 42.1, Level::Warning),
+            (Level::Warning, 42.0, Level::Normal),
+            (Level::Warning, 55.0, Level::Critical),
+            (Level::Critical, 52.1, Level::Critical),
+            (Level::Critical, 52.0, Level::Warning),
+            (Level::Critical, 42.1, Level::Warning),
+            (Level::Critical, 30.0, Level::Normal),
+        ];
+        for (from, celsius, to) in cases {
+            let t = Thermal { cfg: BatteryTemperatureConfig::default(), level: from, celsius: None, paused: false };
+            assert_eq!(t.level_for(celsius), to, "{celsius} °C from {from:?}");
+        }
+    }
+
+    #[test]
+    fn slows_then_stops_with_the_temperature() {
+        let _serial = crate::robot::serial();
+        init(BatteryTemperatureConfig::default());
+        begin();
+        assert!(update(&reading(40, false)).is_empty());
+        assert_eq!(limit(Wheels::new(400, 200)), Wheels::new(400, 200));
+        assert_eq!(level(&update(&reading(47, false))), Some("warning"));
+        // Held to 200 mm/s, keeping the curve
+        assert_eq!(limit(Wheels::new(400, 200)), Wheels::new(200, 100));
+        assert_eq!(limit(Wheels::new(-150, 150)), Wheels::new(-150, 150));
+        assert_eq!(level(&update(&reading(56, false))), Some("critical"));
+        assert_eq!(limit(Wheels::new(100, 100)), Wheels::STOP);
+        assert!(update(&reading(53, false)).is_empty());
+        assert_eq!(level(&update(&reading(51, false))), Some("warning"));
+        assert_eq!(level(&update(&reading(41, false))), Some("normal"));
+        assert_eq!(limit(Wheels::new(400, 200)), Wheels::new(400, 200));
+        end();
+    }
+
+    #[test]
+    fn pauses_charging_while_warm() {
+        let _serial = crate::robot::serial();
+        init(BatteryTemperatureConfig::default());
+        begin();
+        update(&reading(44, true));
+        assert_eq!(status()["charging_paused"], false);
+        update(&reading(46, true));
+        assert_eq!(status()["charging_paused"], true);
+        update(&reading(43, true));
+        assert_eq!(status()["charging_paused"], true);
+        update(&reading(41, true));
+        assert_eq!(status()["charging_paused"], false);
+        end();
+        init(BatteryTemperatureConfig { pause_charging: Some(false), ..Default::default() });
+        begin();
+        update(&reading(46, true));
+        assert_eq!(status()["charging_paused"], false);
+        end();
+    }
+}
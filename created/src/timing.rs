@@ -0,0 +1,172 @@
+//! Control-loop timing: the interval between sensor frames (the robot streams
+//! every 15 ms), how long each serial write takes, and how long the stream
+//! loop spends handling what it read. Figures cover 10 s windows; the last
+//! complete window is what `createctl timing` and the Influx sink report.
+//!
+//! The OI has no acknowledgements, so a command's round trip can't be seen
+//! from the host; write latency (write plus flush, until the bytes are handed
+//! to the serial driver) is the closest measure.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use tracing::{info, warn};
+
+/// The robot's streaming period.
+const STREAM_PERIOD: Duration = Duration::from_millis(15);
+/// A frame arriving this much later than the previous one missed its slot.
+const LATE: Duration = Duration::from_micros(STREAM_PERIOD.as_micros() as u64 * 3 / 2);
+const WINDOW: Duration = Duration::from_secs(10);
+/// Warn when more than this share of a window's frames were late.
+const LATE_WARN_RATIO: f64 = 0.05;
+
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct Summary {
+    pub count: u64,
+    pub mean_ms: f64,
+    pub max_ms: f64,
+    /// Standard deviation
+    pub jitter_ms: f64,
+}
+
+#[derive(Default)]
+struct Accumulator {
+    count: u64,
+    sum: f64,
+    sum_sq: f64,
+    max: f64,
+}
+
+impl Accumulator {
+    fn add(&mut self, d: Duration) {
+        let ms = d.as_secs_f64() * 1000.0;
+        self.count += 1;
+        self.sum += ms;
+        self.sum_sq += ms * ms;
+        self.max = self.max.max(ms);
+    }
+
+    fn summary(&self) -> Summary {
+        if self.count == 0 {
+            return Summary::default();
+        }
+        let n = self.count as f64;
+        let mean = self.sum / n;
+        Summary { count: self.count, mean_ms: mean, max_ms: self.max, jitter_ms: (self.sum_sq / n - mean * mean).max(0.0).sqrt() }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct Report {
+    pub window_s: f64,
+    pub frame_interval: Summary,
+    /// Frames more than 22.5 ms after the previous one
+    pub late_frames: u64,
+    pub write_latency: Summary,
+    /// Time spent parsing and publishing per read
+    pub loop_busy: Summary,
+}
+
+struct Timing {
+    started: Instant,
+    last_frame: Option<Instant>,
+    frames: Accumulator,
+    late: u64,
+    writes: Accumulator,
+    busy: Accumulator,
+    last: Option<Report>,
+    warned: bool,
+}
+
+static TIMING: Mutex<Option<Timing>> = Mutex::new(None);
+
+fn with<R>(f: impl FnOnce(&mut Timing) -> R) -> R {
+    let mut guard = TIMING.lock().unwrap_or_else(|e| e.into_inner());
+    let t = guard.get_or_insert_with(|| Timing {
+        started: Instant::now(),
+        last_frame: None,
+        frames: Accumulator::default(),
+        late: 0,
+        writes: Accumulator::default(),
+        busy: Accumulator::default(),
+        last: None,
+        warned: false,
+    });
+    f(t)
+}
+
+/// Start over, e.g. for a new session, so reconnect gaps don't count.
+pub fn reset() {
+    with(|t| {
+        t.started = Instant::now();
+        t.last_frame = None;
+        t.frames = Accumulator::default();
+        t.late = 0;
+        t.writes = Accumulator::default();
+        t.busy = Accumulator::default();
+    });
+}
+
+/// The stream was paused: the interval up to the next frame isn't one.
+pub fn gap() {
+    with(|t| t.last_frame = None);
+}
+
+pub fn frame(at: Instant) {
+    with(|t| {
+        if let Some(prev) = t.last_frame {
+            let interval = at.duration_since(prev);
+            t.frames.add(interval);
+            if interval > LATE {
+                t.late += 1;
+            }
+        }
+        t.last_frame = Some(at);
+        roll(t);
+    });
+}
+
+pub fn write(took: Duration) {
+    with(|t| t.writes.add(took));
+}
+
+pub fn busy(took: Duration) {
+    with(|t| t.busy.add(took));
+}
+
+fn roll(t: &mut Timing) {
+    let elapsed = t.started.elapsed();
+    if elapsed < WINDOW {
+        return;
+    }
+    let report = Report {
+        window_s: elapsed.as_secs_f64(),
+        frame_interval: t.frames.summary(),
+        late_frames: t.late,
+        write_latency: t.writes.summary(),
+        loop_busy: t.busy.summary(),
+    };
+    let late_ratio = if t.frames.count > 0 { t.late as f64 / t.frames.count as f64 } else { 0.0 };
+    if late_ratio > LATE_WARN_RATIO && !t.warned {
+        warn!(
+            "missing streaming deadlines: {} of {} frames late, interval mean {:.1} ms, max {:.1} ms, loop busy max {:.1} ms",
+            t.late, t.frames.count, report.frame_interval.mean_ms, report.frame_interval.max_ms, report.loop_busy.max_ms
+        );
+        t.warned = true;
+    } else if late_ratio <= LATE_WARN_RATIO && t.warned {
+        info!("streaming deadlines met again");
+        t.warned = false;
+    }
+    t.last = Some(report);
+    t.started = Instant::now();
+    t.frames = Accumulator::default();
+    t.late = 0;
+    t.writes = Accumulator::default();
+    t.busy = Accumulator::default();
+}
+
+/// The last complete window.
+pub fn report() -> Option<Report> {
+    with(|t| t.last)
+}
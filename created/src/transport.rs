@@ -0,0 +1,92 @@
+//! What a session speaks OI over. A transport is a byte pipe to the robot
+//! whose reads time out with nothing to read, as a serial port's do, so the
+//! session can check for shutdown, and whose first failure ends the
+//! session: a local serial port (`Serial`, which a pty from socat or a
+//! simulator opens as too), a networked port (`tcp`), a Bluetooth link
+//! (`bluetooth`), the simulated robot (`mock`), or a recording played back
+//! (`replay`). The session, the probe, and the protocol above them don't
+//! know which.
+
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use crate::{ftdi, lockfile};
+
+/// How long reads and writes on a serial port wait
+const TIMEOUT: Duration = Duration::from_millis(500);
+
+/// A link to a robot.
+pub trait Transport: Read + Write + Send {
+    /// What it is, for the logs
+    fn kind(&self) -> &'static str;
+
+    /// Talk to the robot at `baud` from now on; an error where the rate is
+    /// set at the other end (a bridge, a Bluetooth adapter).
+    fn set_baud_rate(&mut self, baud: u32) -> Result<(), String>;
+
+    /// Close the link at the end of a session, any last bytes sent first;
+    /// what went wrong, which dropping it would keep quiet.
+    fn close(self: Box<Self>) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// A local serial port, with the lock file keeping other programs off it
+/// for as long as it is open, and its adapter's latency timer, put back
+/// once it is closed.
+pub struct Serial {
+    port: Box<dyn serialport::SerialPort>,
+    _lock: Option<lockfile::Lock>,
+    _latency: Option<ftdi::Latency>,
+}
+
+impl Serial {
+    /// Open `path` at `baud`, holding `lock` and `latency` until it is closed.
+    pub fn open(path: &Path, baud: u32, lock: Option<lockfile::Lock>, latency: Option<ftdi::Latency>) -> Result<Serial, String> {
+        let port = serialport::new(path.to_string_lossy(), baud).timeout(TIMEOUT).open().map_err(|e| format!("open serial: {e}"))?;
+        Ok(Serial { port, _lock: lock, _latency: latency })
+    }
+
+    /// Assert (drive low) or release the RTS line.
+    pub fn request_to_send(&mut self, asserted: bool) -> Result<(), String> {
+        self.port.write_request_to_send(asserted).map_err(|e| e.to_string())
+    }
+
+    /// Assert (drive low) or release the DTR line.
+    pub fn data_terminal_ready(&mut self, asserted: bool) -> Result<(), String> {
+        self.port.write_data_terminal_ready(asserted).map_err(|e| e.to_string())
+    }
+}
+
+impl Read for Serial {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.port.read(buf)
+    }
+}
+
+impl Write for Serial {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.port.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.port.flush()
+    }
+}
+
+impl Transport for Serial {
+    fn kind(&self) -> &'static str {
+        "serial"
+    }
+
+    fn set_baud_rate(&mut self, baud: u32) -> Result<(), String> {
+        self.port.set_baud_rate(baud).map_err(|e| e.to_string())
+    }
+
+    fn close(mut self: Box<Self>) -> Result<(), String> {
+        // Waits for the output to drain: the last command is the robot's
+        // power off, which closing right away could cut short
+        self.port.flush().map_err(|e| format!("drain: {e}"))
+    }
+}
@@ -0,0 +1,364 @@
+//! `created tui`: a cockpit in the terminal, over the running daemon's
+//! control socket. It shows the connection, OI mode, and who has the
+//! wheels; the battery; cliff, wall, and light bump signals as gauges; the
+//! pose, wheel speeds, and contacts; and the latest events, refreshed four
+//! times a second from `status`, `behavior`, `sensors`, `pose`, and
+//! `events`. The keys of `created teleop` drive the robot from it, held as a
+//! deadman switch as there; once they have, quitting (or losing the
+//! connection) stops it.
+
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::style::{Attribute, Print, SetAttribute};
+use crossterm::terminal::{self, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
+use serde_json::Value;
+
+use crate::teleop::{self, Client, Deadman, RawMode, REPEAT};
+
+const REFRESH: Duration = Duration::from_millis(250);
+/// Events kept for the log, more than a tall terminal shows
+const LOG_LINES: usize = 100;
+/// Cliff, wall, and light bump signals go up to 4095
+const SIGNAL_MAX: f64 = 4095.0;
+const GAUGE_WIDTH: usize = 20;
+/// Packet 21, from 0
+const CHARGING_STATES: [&str; 6] = ["not charging", "reconditioning", "charging", "trickle charging", "waiting", "charging fault"];
+const CLIFFS: [(&str, &str); 4] =
+    [("left", "cliff_left_signal"), ("front left", "cliff_front_left_signal"), ("front right", "cliff_front_right_signal"), ("right", "cliff_right_signal")];
+const LIGHT_BUMPS: [(&str, &str); 6] = [
+    ("left", "light_bump_left_signal"),
+    ("front left", "light_bump_front_left_signal"),
+    ("center left", "light_bump_center_left_signal"),
+    ("center right", "light_bump_center_right_signal"),
+    ("front right", "light_bump_front_right_signal"),
+    ("right", "light_bump_right_signal"),
+];
+
+/// The alternate screen in raw mode, restored however the TUI exits.
+struct Screen {
+    _raw: RawMode,
+}
+
+impl Screen {
+    fn enter() -> io::Result<Self> {
+        let raw = RawMode::enable()?;
+        execute!(io::stdout(), EnterAlternateScreen, Hide)?;
+        Ok(Screen { _raw: raw })
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        let _ = execute!(io::stdout(), Show, LeaveAlternateScreen);
+    }
+}
+
+#[derive(Default)]
+struct Cockpit {
+    status: Value,
+    behavior: Value,
+    /// The latest sensor reply, none before the first frame
+    sensors: Option<Value>,
+    pose: Option<Value>,
+    /// Event times and lines, newest last, with how many times in a row
+    /// each came (teleop repeats its `drive`)
+    log: VecDeque<(String, String, usize)>,
+    last_event: u64,
+    speed: i32,
+    turn: i32,
+}
+
+/// The reply to `command` if the daemon took it.
+fn query(client: &mut Client, command: &str) -> Result<Option<Value>, String> {
+    let reply: Value = serde_json::from_str(&client.request(command)?).map_err(|e| format!("{command}: bad reply: {e}"))?;
+    Ok((reply["ok"] == true).then_some(reply))
+}
+
+/// A JSON value as it reads in the log: strings without quotes.
+fn plain(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// An event's local time, and its type and other fields.
+fn event_line(event: &Value) -> (String, String) {
+    let time = event["time"].as_str().and_then(|t| chrono::DateTime::parse_from_rfc3339(t).ok());
+    let time = time.map_or_else(|| "--:--:--".to_string(), |t| t.with_timezone(&chrono::Local).format("%H:%M:%S").to_string());
+    let mut line = plain(&event["type"]);
+    for (key, value) in event.as_object().into_iter().flatten() {
+        if !matches!(key.as_str(), "time" | "type" | "seq") && !value.is_null() {
+            line.push_str(&format!(" {key}={}", plain(value)));
+        }
+    }
+    (time, line)
+}
+
+/// `label`, a bar filled to `fraction`, and `text`.
+fn gauge(label: &str, fraction: Option<f64>, text: &str) -> String {
+    let bar = match fraction {
+        Some(f) => {
+            let filled = (f.clamp(0.0, 1.0) * GAUGE_WIDTH as f64).round() as usize;
+            format!("{}{}", "█".repeat(filled), "░".repeat(GAUGE_WIDTH - filled))
+        }
+        None => " ".repeat(GAUGE_WIDTH),
+    };
+    format!("  {label:<13}{bar} {text}")
+}
+
+fn lamp(on: bool) -> &'static str {
+    if on {
+        "●"
+    } else {
+        "·"
+    }
+}
+
+/// `line` cut or padded to `width` characters.
+fn fit(line: &str, width: usize) -> String {
+    let mut line: String = line.chars().take(width).collect();
+    let len = line.chars().count();
+    line.extend(std::iter::repeat_n(' ', width - len));
+    line
+}
+
+impl Cockpit {
+    /// Ask the daemon for everything shown.
+    fn poll(&mut self, client: &mut Client) -> Result<(), String> {
+        self.status = query(client, "status")?.unwrap_or_default();
+        self.behavior = query(client, "behavior")?.map(|b| b["behavior"].clone()).unwrap_or_default();
+        self.sensors = query(client, "sensors")?;
+        self.pose = query(client, "pose")?;
+        let Some(events) = query(client, &format!("events {}", self.last_event))? else { return Ok(()) };
+        let last = events["last"].as_u64().unwrap_or(0);
+        // The daemon restarted: its numbers start over
+        if last < self.last_event {
+            self.last_event = 0;
+            return Ok(());
+        }
+        for event in events["events"].as_array().into_iter().flatten() {
+            let (time, line) = event_line(event);
+            match self.log.back_mut() {
+                Some((last_time, last, count)) if *last == line => {
+                    *last_time = time;
+                    *count += 1;
+                }
+                _ => self.log.push_back((time, line, 1)),
+            }
+            if self.log.len() > LOG_LINES {
+                self.log.pop_front();
+            }
+        }
+        self.last_event = last;
+        Ok(())
+    }
+
+    fn packet(&self, name: &str) -> Option<i64> {
+        self.sensors.as_ref()?["packets"][name].as_i64()
+    }
+
+    fn title(&self) -> String {
+        let mut parts = vec!["created tui".to_string()];
+        match self.status["session"]["device"].as_str() {
+            Some(device) if self.status["connected"] == true => parts.push(format!("connected to {device}")),
+            _ => parts.push("no robot connected".into()),
+        }
+        if let Some(mode) = self.sensors.as_ref().and_then(|s| s["mode"].as_str()) {
+            parts.push(format!("mode {mode}"));
+        }
+        if let Some(layer) = self.status["layer"].as_str() {
+            parts.push(format!("wheels: {layer}"));
+        }
+        if let Some(name) = self.behavior["name"].as_str() {
+            let elapsed = self.behavior["elapsed_s"].as_f64().map(|s| format!(" {s:.0} s")).unwrap_or_default();
+            parts.push(format!("behavior {name}{elapsed}"));
+        }
+        format!(" {}", parts.join("  │  "))
+    }
+
+    /// Battery and signal gauges.
+    fn left(&self) -> Vec<String> {
+        let mut lines = vec!["Battery".to_string()];
+        let (charge, capacity) = (self.packet("battery_charge"), self.packet("battery_capacity"));
+        let fraction = charge.zip(capacity.filter(|c| *c > 0)).map(|(c, cap)| c as f64 / cap as f64);
+        let text = match (fraction, charge, capacity) {
+            (Some(f), Some(c), Some(cap)) => format!("{:.0} %  {c}/{cap} mAh", f * 100.0),
+            _ => "-".into(),
+        };
+        lines.push(gauge("charge", fraction, &text));
+        let mut readings = Vec::new();
+        if let Some(mv) = self.packet("voltage") {
+            readings.push(format!("{:.2} V", mv as f64 / 1000.0));
+        }
+        if let Some(ma) = self.packet("current") {
+            readings.push(format!("{ma:+} mA"));
+        }
+        if let Some(c) = self.packet("temperature") {
+            readings.push(format!("{c} °C"));
+        }
+        if let Some(state) = self.packet("charging_state").and_then(|s| CHARGING_STATES.get(s as usize)) {
+            readings.push(state.to_string());
+        }
+        lines.push(format!("  {}", readings.join("  ")));
+        lines.push(String::new());
+        lines.push("Cliff and wall signals".into());
+        for (label, name) in CLIFFS.iter().chain(&[("wall", "wall_signal")]) {
+            lines.push(self.signal(label, name));
+        }
+        lines.push(String::new());
+        lines.push("Light bumps".into());
+        for (label, name) in LIGHT_BUMPS {
+            lines.push(self.signal(label, name));
+        }
+        lines
+    }
+
+    fn signal(&self, label: &str, name: &str) -> String {
+        let value = self.packet(name);
+        gauge(label, value.map(|v| v as f64 / SIGNAL_MAX), &value.map_or_else(|| "-".into(), |v| v.to_string()))
+    }
+
+    /// Pose, wheels, contacts, and teleop.
+    fn right(&self) -> Vec<String> {
+        let mut lines = vec!["Pose".to_string()];
+        match &self.pose {
+            Some(p) => {
+                let f = |key: &str| p[key].as_f64().unwrap_or(0.0);
+                lines.push(format!("  x {:+.2} m   y {:+.2} m", f("x"), f("y")));
+                lines.push(format!("  heading {:+.1}°   confidence {:.2}", f("theta").to_degrees(), f("confidence")));
+            }
+            None => lines.extend(["  -".to_string(), String::new()]),
+        }
+        lines.push(String::new());
+        lines.push("Wheels (left, right)".into());
+        let drive = &self.status["drive"];
+        lines.push(format!("  commanded   {:+5} {:+5} mm/s", drive["left"].as_i64().unwrap_or(0), drive["right"].as_i64().unwrap_or(0)));
+        let pair = |left: &str, right: &str, unit: &str| match (self.packet(left), self.packet(right)) {
+            (Some(l), Some(r)) => format!("{l:+5} {r:+5} {unit}"),
+            _ => "-".into(),
+        };
+        lines.push(format!("  requested   {}", pair("requested_left_velocity", "requested_right_velocity", "mm/s")));
+        lines.push(format!("  motors      {}", pair("left_motor_current", "right_motor_current", "mA")));
+        lines.push(String::new());
+        lines.push("Contacts, left to right".into());
+        let bits = self.packet("bumps_wheeldrops").unwrap_or(0);
+        let bit = |b: i64| lamp(bits & (1 << b) != 0);
+        lines.push(format!("  bump {} {}   wheel drop {} {}", bit(1), bit(0), bit(3), bit(2)));
+        let on = |name: &str| lamp(self.packet(name).is_some_and(|v| v != 0));
+        let cliffs = ["cliff_left", "cliff_front_left", "cliff_front_right", "cliff_right"].map(on).join(" ");
+        lines.push(format!("  cliff {cliffs}   wall {}   virtual wall {}", on("wall"), on("virtual_wall")));
+        let light = self.packet("light_bumper").unwrap_or(0);
+        let light: Vec<&str> = (0..6).map(|b| lamp(light & (1 << b) != 0)).collect();
+        lines.push(format!("  light bumper {}", light.join(" ")));
+        lines.push(String::new());
+        lines.push("Teleop".into());
+        lines.push(format!("  speed {:+4} mm/s   turn {:+4} mm/s", self.speed, self.turn));
+        lines
+    }
+
+    fn draw(&self) -> io::Result<()> {
+        let (width, height) = terminal::size()?;
+        let (width, height) = (width as usize, height as usize);
+        let mut lines = vec![String::new()];
+        let (left, right) = (self.left(), self.right());
+        let half = width / 2;
+        for i in 0..left.len().max(right.len()) {
+            let l = left.get(i).map_or("", String::as_str);
+            let r = right.get(i).map_or("", String::as_str);
+            lines.push(format!("{}{r}", fit(l, half)));
+        }
+        lines.push(String::new());
+        lines.push("Events".into());
+        // Whatever room is left, above the key help
+        let room = height.saturating_sub(lines.len() + 2);
+        let skip = self.log.len().saturating_sub(room);
+        lines.extend(self.log.iter().skip(skip).map(|(time, line, count)| match count {
+            1 => format!("  {time} {line}"),
+            n => format!("  {time} {line} (×{n})"),
+        }));
+        lines.resize(height.saturating_sub(1).max(1), String::new());
+
+        let mut out = io::stdout();
+        queue!(out, MoveTo(0, 0), SetAttribute(Attribute::Reverse), Print(fit(&self.title(), width)), SetAttribute(Attribute::Reset))?;
+        for (row, line) in lines.iter().enumerate().skip(1) {
+            queue!(out, MoveTo(0, row as u16), Print(fit(line, width)))?;
+        }
+        let help = " q quit   hold W/S or Up/Down speed   A/D or Left/Right turn   space stop";
+        queue!(out, MoveTo(0, height.saturating_sub(1) as u16), SetAttribute(Attribute::Dim), Print(fit(help, width)), SetAttribute(Attribute::Reset))?;
+        out.flush()
+    }
+}
+
+pub fn run(socket: &Path) -> Result<(), String> {
+    let mut client = Client::connect(socket)?;
+    let screen = Screen::enter().map_err(|e| format!("terminal: {e}"))?;
+    let mut deadman = Deadman::enable();
+    let mut cockpit = Cockpit::default();
+    let mut last_poll = Instant::now() - REFRESH;
+    let mut last_sent = Instant::now();
+    // Set once the keys have driven the robot, when it is ours to stop
+    let mut driving = false;
+    let result = loop {
+        let mut redraw = false;
+        if last_poll.elapsed() >= REFRESH {
+            if let Err(e) = cockpit.poll(&mut client) {
+                break Err(e);
+            }
+            last_poll = Instant::now();
+            redraw = true;
+        }
+        let mut steered = false;
+        if event::poll(Duration::from_millis(50)).map_err(|e| e.to_string())? {
+            match event::read().map_err(|e| e.to_string())? {
+                Event::Key(KeyEvent { code, modifiers, kind, .. }) => match code {
+                    KeyCode::Char('q') | KeyCode::Esc if kind == KeyEventKind::Press => break Ok(()),
+                    KeyCode::Char('c') if modifiers.contains(KeyModifiers::CONTROL) => break Ok(()),
+                    _ if teleop::is_driving(code) => {
+                        steered = deadman.key(code, kind) && teleop::steer(code, &mut cockpit.speed, &mut cockpit.turn);
+                    }
+                    _ => {}
+                },
+                Event::Resize(..) => redraw = true,
+                _ => {}
+            }
+        }
+        if deadman.released() && (cockpit.speed, cockpit.turn) != (0, 0) {
+            (cockpit.speed, cockpit.turn) = (0, 0);
+            steered = true;
+        }
+        let moving = (cockpit.speed, cockpit.turn) != (0, 0);
+        if steered || (moving && last_sent.elapsed() >= REPEAT) {
+            // From now on the daemon stops the robot if this connection drops
+            if !driving {
+                if let Err(e) = client.send("teleop") {
+                    break Err(e);
+                }
+                driving = true;
+            }
+            // Positive turn is counter-clockwise: the right wheel runs faster
+            if let Err(e) = client.send(&format!("drive {} {}", cockpit.speed - cockpit.turn, cockpit.speed + cockpit.turn)) {
+                break Err(e);
+            }
+            last_sent = Instant::now();
+            redraw |= steered;
+        }
+        if redraw {
+            if let Err(e) = cockpit.draw() {
+                break Err(format!("draw: {e}"));
+            }
+        }
+    };
+    if driving {
+        let _ = client.send("stop");
+    }
+    drop(deadman);
+    drop(screen);
+    result
+}
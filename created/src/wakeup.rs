@@ -0,0 +1,89 @@
+//! Waking the robot worker between sessions. Instead of looking every so
+//! often for whatever may have changed, it sleeps in `wait` until there is
+//! something to do: a device event on the hotplug socket, its next look at
+//! the ports coming due, or a `notify` from another thread (the signal
+//! handler on shutdown, the control socket on a release or reclaim).
+//! Without Unix sockets to poll, `wait` waits on a condition variable, with
+//! no device events to wake it.
+
+#[cfg(unix)]
+use std::io::{self, Read, Write};
+#[cfg(unix)]
+use std::os::fd::AsRawFd;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+#[cfg(unix)]
+use std::sync::OnceLock;
+#[cfg(not(unix))]
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
+
+use crate::hotplug;
+
+/// The ends `notify` writes to and `wait` polls
+#[cfg(unix)]
+static PAIR: OnceLock<Option<(UnixStream, UnixStream)>> = OnceLock::new();
+
+#[cfg(unix)]
+fn pair() -> Option<&'static (UnixStream, UnixStream)> {
+    PAIR.get_or_init(|| {
+        let (tx, rx) = UnixStream::pair().ok()?;
+        tx.set_nonblocking(true).ok()?;
+        rx.set_nonblocking(true).ok()?;
+        Some((tx, rx))
+    })
+    .as_ref()
+}
+
+/// Wake the worker from `wait`, or have its next `wait` return at once.
+#[cfg(unix)]
+pub fn notify() {
+    if let Some((tx, _)) = pair() {
+        // A full socket already has the worker woken
+        let _ = (&*tx).write(&[1]);
+    }
+}
+
+/// Sleep until `monitor` has device events, `notify` is called, or
+/// `timeout` passes (None: for as long as it takes); whether `monitor` has
+/// events.
+#[cfg(unix)]
+pub fn wait(monitor: Option<&hotplug::Monitor>, timeout: Option<Duration>) -> Result<bool, String> {
+    let (_, rx) = pair().ok_or("no wakeup socket")?;
+    let fd = monitor.map_or(-1, |m| m.as_raw_fd());
+    let mut pfds = [libc::pollfd { fd: rx.as_raw_fd(), events: libc::POLLIN, revents: 0 }, libc::pollfd { fd, events: libc::POLLIN, revents: 0 }];
+    let ms = timeout.map_or(-1, |t| t.as_millis().min(i32::MAX as u128) as i32);
+    // SAFETY: a plain syscall on descriptors held open by the caller; poll skips -1
+    if unsafe { libc::poll(pfds.as_mut_ptr(), pfds.len() as libc::nfds_t, ms) } < 0 {
+        let e = io::Error::last_os_error();
+        return if e.kind() == io::ErrorKind::Interrupted { Ok(false) } else { Err(format!("poll: {e}")) };
+    }
+    if pfds[0].revents != 0 {
+        let mut buf = [0u8; 64];
+        while matches!((&*rx).read(&mut buf), Ok(n) if n > 0) {}
+    }
+    Ok(pfds[1].revents != 0)
+}
+
+/// Whether `notify` has been called since the last `wait` returned
+#[cfg(not(unix))]
+static NOTIFIED: Mutex<bool> = Mutex::new(false);
+#[cfg(not(unix))]
+static WOKEN: Condvar = Condvar::new();
+
+#[cfg(not(unix))]
+pub fn notify() {
+    *NOTIFIED.lock().unwrap_or_else(|e| e.into_inner()) = true;
+    WOKEN.notify_all();
+}
+
+#[cfg(not(unix))]
+pub fn wait(_monitor: Option<&hotplug::Monitor>, timeout: Option<Duration>) -> Result<bool, String> {
+    let notified = NOTIFIED.lock().unwrap_or_else(|e| e.into_inner());
+    let mut notified = match timeout {
+        Some(timeout) => WOKEN.wait_timeout_while(notified, timeout, |n| !*n).unwrap_or_else(|e| e.into_inner()).0,
+        None => WOKEN.wait_while(notified, |n| !*n).unwrap_or_else(|e| e.into_inner()),
+    };
+    *notified = false;
+    Ok(false)
+}
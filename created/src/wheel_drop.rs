@@ -0,0 +1,176 @@
+//! Wheel drop stop: a dropped wheel means the robot was picked up or went
+//! over a step, so it stops at once, without ramping down, whatever drives
+//! it, and the running behavior (a mission or patrol included) is
+//! cancelled. The wheels stay stopped until both have been down for
+//! `wheel_drop.debounce_ms`, so a robot set down again, or rocking on an
+//! edge, doesn't drive off the moment a wheel touches.
+//!
+//! Like the cliff interlock it is applied to the speeds the session sends
+//! and to raw commands (drive commands, and those having the robot drive
+//! itself, cleaning or seeking the dock), and it isn't configurable off; the wheel drop
+//! reflex choosing a maneuver can only add to it.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+use tracing::{info, warn};
+
+use crate::{audit, behavior};
+use crate::bus::Event;
+use crate::config::WheelDropConfig;
+use crate::motion::{self, Wheels};
+use crate::oi::{self, SensorFrame};
+
+const BUMPS_WHEELDROPS: u8 = 7;
+const WHEEL_DROPS: i32 = 0b1100;
+
+struct Stop {
+    debounce: Duration,
+    /// Stopped: since when both wheels are down, if they are
+    engaged: Option<Option<Instant>>,
+}
+
+static DEBOUNCE: Mutex<Duration> = Mutex::new(Duration::ZERO);
+static STATE: Mutex<Option<Stop>> = Mutex::new(None);
+
+fn state() -> std::sync::MutexGuard<'static, Option<Stop>> {
+    STATE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+pub fn init(cfg: WheelDropConfig) {
+    *DEBOUNCE.lock().unwrap_or_else(|e| e.into_inner()) = cfg.debounce();
+}
+
+pub fn begin() {
+    let debounce = *DEBOUNCE.lock().unwrap_or_else(|e| e.into_inner());
+    *state() = Some(Stop { debounce, engaged: None });
+}
+
+pub fn end() {
+    *state() = None;
+}
+
+/// Check the wheel drops; events to publish.
+pub fn update(frame: &SensorFrame) -> Vec<Event> {
+    let mut guard = state();
+    let Some(s) = guard.as_mut() else { return Vec::new() };
+    let Some(bits) = frame.get(BUMPS_WHEELDROPS) else { return Vec::new() };
+    if bits & WHEEL_DROPS != 0 {
+        let first = s.engaged.replace(None).is_none();
+        let debounce = s.debounce;
+        drop(guard);
+        // Whatever asks for the wheels meanwhile is dropped, not kept for later
+        motion::halt();
+        if first {
+            warn!("wheel drop: stopping until the wheels are down for {debounce:?}");
+            audit::record("wheel_drop_stop", "wheel drop sensors", "wheel dropped: wheels stopped, behavior cancelled");
+            behavior::cancel("wheel drop");
+        }
+        return Vec::new();
+    }
+    let Some(since) = s.engaged.as_mut() else { return Vec::new() };
+    let since = *since.get_or_insert_with(Instant::now);
+    if since.elapsed() < s.debounce {
+        return Vec::new();
+    }
+    s.engaged = None;
+    info!("wheels down for {:?}, motion allowed again", s.debounce);
+    audit::record("wheels_down", "wheel drop sensors", &format!("both wheels down for {:?}", s.debounce));
+    vec![Event::WheelsDown]
+}
+
+fn engaged() -> bool {
+    state().as_ref().is_some_and(|s| s.engaged.is_some())
+}
+
+/// `wheels`, or a stop while a wheel is dropped or just came down.
+pub fn limit(wheels: Wheels) -> Wheels {
+    if engaged() {
+        Wheels::STOP
+    } else {
+        wheels
+    }
+}
+
+/// Whether the raw OI command `bytes` may be sent now: nothing that sets
+/// the robot moving while engaged.
+pub fn allows(bytes: &[u8]) -> bool {
+    !oi::moves(bytes) || !engaged()
+}
+
+pub fn status() -> Value {
+    let guard = state();
+    let Some(s) = guard.as_ref() else { return json!({ "stopped": false }) };
+    json!({ "stopped": s.engaged.is_some(), "wheels_down_s": s.engaged.flatten().map(|t| t.elapsed().as_secs_f64()) })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::SystemTime;
+
+    use super::*;
+
+    const FORWARD: [u8; 5] = [oi::DRIVE_DIRECT, 0, 200, 0, 200];
+
+    fn drops(bits: i32) -> SensorFrame {
+        let mut frame = SensorFrame::new(SystemTime::now());
+        frame.set(BUMPS_WHEELDROPS, bits);
+        frame
+    }
+
+    fn session(debounce_ms: u64) -> std::sync::MutexGuard<'static, ()> {
+        let serial = crate::robot::serial();
+        init(WheelDropConfig { debounce_ms: Some(debounce_ms) });
+        begin();
+        serial
+    }
+
+    #[test]
+    fn stops_until_both_wheels_are_down_for_the_debounce() {
+        let _serial = session(30);
+        assert!(update(&drops(0)).is_empty());
+        assert_eq!(limit(Wheels::new(200, 200)), Wheels::new(200, 200));
+        // A bump alone isn't a wheel drop
+        update(&drops(0b11));
+        assert!(allows(&FORWARD));
+        update(&drops(0b100));
+        assert_eq!(limit(Wheels::new(200, 200)), Wheels::STOP);
+        assert_eq!(limit(Wheels::new(-200, -200)), Wheels::STOP);
+        assert!(!allows(&FORWARD));
+        assert!(update(&drops(0)).is_empty());
+        assert!(!allows(&FORWARD));
+        thread::sleep(Duration::from_millis(40));
+        assert!(matches!(&update(&drops(0))[..], [Event::WheelsDown]));
+        assert!(allows(&FORWARD));
+        end();
+    }
+
+    #[test]
+    fn a_wheel_dropping_again_restarts_the_debounce() {
+        let _serial = session(30);
+        update(&drops(0b1000));
+        update(&drops(0));
+        thread::sleep(Duration::from_millis(20));
+        update(&drops(0b1100));
+        update(&drops(0));
+        thread::sleep(Duration::from_millis(20));
+        assert!(update(&drops(0)).is_empty());
+        assert_eq!(limit(Wheels::new(100, 100)), Wheels::STOP);
+        end();
+    }
+
+    #[test]
+    fn refuses_only_what_moves_the_robot() {
+        let _serial = session(1000);
+        update(&drops(0b100));
+        for refused in [&FORWARD[..], &[oi::DRIVE, 0xff, 0x38, 0, 1], &[oi::DRIVE_PWM, 0, 50, 0, 50], &[oi::SEEK_DOCK], &[oi::SPOT]] {
+            assert!(!allows(refused), "{refused:?}");
+        }
+        for allowed in [&[oi::DRIVE_DIRECT, 0, 0, 0, 0][..], &[oi::LEDS, 0, 0, 0], &[oi::PLAY, 0], &[oi::START]] {
+            assert!(allows(allowed), "{allowed:?}");
+        }
+        end();
+    }
+}